@@ -3,12 +3,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
+use crate::dag::{EvidenceNodes, EvidenceRecord, SkipRound};
 use aptos_consensus_types::{
     block::block_test_utils::certificate_for_genesis,
     common::{Author, Payload},
 };
 use aptos_temppath::TempPath;
-use aptos_types::aggregate_signature::AggregateSignature;
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, validator_signer::ValidatorSigner,
+};
+use std::collections::BTreeMap;
 
 #[test]
 fn test_put_get() {
@@ -81,7 +85,15 @@ fn test_dag() {
     let db = ConsensusDB::new(&tmp_dir);
     assert_eq!(db.get_certified_nodes().unwrap().len(), 0);
 
-    let node = Node::new(1, 1, Author::random(), 123, Payload::empty(false), vec![]);
+    let node = Node::new(
+        ChainId::test(),
+        1,
+        1,
+        Author::random(),
+        123,
+        Payload::empty(false),
+        vec![],
+    );
 
     db.save_node(&node).unwrap();
 
@@ -96,4 +108,289 @@ fn test_dag() {
     let certified_node_from_db = from_db.remove(certified_node.metadata().digest()).unwrap();
 
     assert_eq!(certified_node, certified_node_from_db);
+
+    db.save_pending_node(&certified_node).unwrap();
+    assert_eq!(db.get_pending_nodes().unwrap().len(), 1);
+    db.delete_pending_node(&certified_node.digest()).unwrap();
+    assert_eq!(db.get_pending_nodes().unwrap().len(), 0);
+
+    let deletions = HashMap::from([(certified_node.digest(), 1)]);
+    db.save_pending_deletions(&deletions).unwrap();
+    assert_eq!(db.get_pending_deletions().unwrap(), deletions);
+    db.delete_pending_deletions(&[certified_node.digest()])
+        .unwrap();
+    assert!(db.get_pending_deletions().unwrap().is_empty());
+
+    let ordered_anchor = OrderedAnchor::new(certified_node.metadata().clone(), vec![]);
+    db.save_ordered_anchor(&ordered_anchor).unwrap();
+    assert_eq!(
+        db.get_ordered_anchors().unwrap(),
+        HashMap::from([(certified_node.digest(), ordered_anchor)])
+    );
+    db.delete_ordered_anchors(vec![certified_node.digest()])
+        .unwrap();
+    assert!(db.get_ordered_anchors().unwrap().is_empty());
+
+    let signer = ValidatorSigner::random(None);
+    let vote = SkipVote::new(SkipRound::new(1, 2, Author::random()), &signer).unwrap();
+    db.save_skip_vote(&vote).unwrap();
+    assert_eq!(
+        db.get_skip_votes().unwrap(),
+        HashMap::from([((2, signer.author()), vote)])
+    );
+    db.delete_skip_votes(vec![(2, signer.author())]).unwrap();
+    assert!(db.get_skip_votes().unwrap().is_empty());
+
+    let reserved = Node::new(
+        ChainId::test(),
+        1,
+        3,
+        Author::random(),
+        0,
+        Payload::empty(false),
+        vec![],
+    );
+    db.save_dag_self_reservation(1, 3, &reserved).unwrap();
+    assert_eq!(
+        db.get_dag_self_reservations().unwrap(),
+        HashMap::from([((1, 3), reserved)])
+    );
+    db.delete_dag_self_reservations(vec![(1, 3)]).unwrap();
+    assert!(db.get_dag_self_reservations().unwrap().is_empty());
+
+    let digest = HashValue::random();
+    let mut acked = BitVec::with_num_bits(4);
+    acked.set(2);
+    db.save_dag_broadcast_progress(1, 3, &digest, &BitVec::with_num_bits(4))
+        .unwrap();
+    db.save_dag_broadcast_progress(1, 3, &digest, &acked)
+        .unwrap();
+    assert_eq!(
+        db.get_dag_broadcast_progress().unwrap(),
+        HashMap::from([((1, 3), BroadcastProgress::new(digest, acked))])
+    );
+    db.delete_dag_broadcast_progress(vec![(1, 3)]).unwrap();
+    assert!(db.get_dag_broadcast_progress().unwrap().is_empty());
+
+    let author = Author::random();
+    db.save_dag_equivocator(1, &author, 4).unwrap();
+    db.save_dag_equivocator(2, &author, 2).unwrap();
+    assert_eq!(
+        db.get_dag_equivocators().unwrap(),
+        HashMap::from([((1, author), 4), ((2, author), 2)])
+    );
+    db.delete_dag_equivocators(vec![(1, author)]).unwrap();
+    assert_eq!(
+        db.get_dag_equivocators().unwrap(),
+        HashMap::from([((2, author), 2)])
+    );
+
+    let other = Author::random();
+    db.save_dag_denied_authors(1, &[(author, 3), (other, 3)])
+        .unwrap();
+    db.save_dag_denied_authors(2, &[(author, 6)]).unwrap();
+    assert_eq!(
+        db.get_dag_denied_authors().unwrap(),
+        HashMap::from([((1, author), 3), ((1, other), 3), ((2, author), 6)])
+    );
+    db.delete_dag_denied_authors(vec![(1, author), (1, other)])
+        .unwrap();
+    assert_eq!(
+        db.get_dag_denied_authors().unwrap(),
+        HashMap::from([((2, author), 6)])
+    );
+
+    let kept = new_certified_node(1, 4, author);
+    let conflicting = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            4,
+            author,
+            124,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    let mut evidence = EvidenceRecord::new(1, EvidenceNodes::Full {
+        kept: Box::new(kept),
+        conflicting: Box::new(conflicting.clone()),
+    });
+    db.save_dag_equivocation_evidence(&evidence).unwrap();
+    evidence.set_pending_report(true);
+    db.save_dag_equivocation_evidence(&evidence).unwrap();
+    assert_eq!(
+        db.get_dag_equivocation_evidence().unwrap(),
+        HashMap::from([(conflicting.digest(), evidence)])
+    );
+    db.delete_dag_equivocation_evidence(vec![conflicting.digest()])
+        .unwrap();
+    assert!(db.get_dag_equivocation_evidence().unwrap().is_empty());
+
+    assert_eq!(db.get_dag_epoch_start_round().unwrap(), None);
+    db.save_dag_epoch_start_round(1, 5).unwrap();
+    db.save_dag_epoch_start_round(2, 7).unwrap();
+    assert_eq!(db.get_dag_epoch_start_round().unwrap(), Some((2, 7)));
+
+    assert_eq!(db.get_dag_last_committed_anchor().unwrap(), None);
+    let anchor = conflicting.metadata().clone();
+    db.save_dag_last_committed_anchor(&anchor).unwrap();
+    assert_eq!(db.get_dag_last_committed_anchor().unwrap(), Some(anchor));
+    db.delete_dag_last_committed_anchor().unwrap();
+    assert_eq!(db.get_dag_last_committed_anchor().unwrap(), None);
+
+    let summary = |epoch| DagEpochSummary {
+        epoch,
+        num_nodes: 3,
+        num_rounds: 2,
+        nodes_by_author: BTreeMap::from([(signer.author(), 2)]),
+        missed_rounds_by_author: BTreeMap::from([(signer.author(), 0)]),
+        num_equivocations: 1,
+        num_ordered_anchors: 1,
+        num_skipped_anchors: 0,
+        total_commit_latency_rounds: 1,
+        pruned_bytes: 100,
+    };
+    db.save_dag_epoch_summary(&summary(2)).unwrap();
+    db.save_dag_epoch_summary(&summary(1)).unwrap();
+    assert_eq!(db.get_dag_epoch_summaries().unwrap(), vec![
+        summary(1),
+        summary(2)
+    ]);
+
+    let remnant = |epoch, rounds: &[Round]| {
+        let nodes: Vec<_> = rounds
+            .iter()
+            .map(|round| new_certified_node(epoch, *round, signer.author()))
+            .collect();
+        let anchor = OrderedAnchor::new(nodes.last().unwrap().metadata().clone(), vec![]);
+        EpochRemnant::new(epoch, vec![anchor], nodes)
+    };
+    db.save_dag_epoch_remnant(&remnant(2, &[3, 4])).unwrap();
+    db.save_dag_epoch_remnant(&remnant(1, &[1])).unwrap();
+    db.save_dag_epoch_remnant(&remnant(1, &[1, 2])).unwrap();
+    assert_eq!(db.get_dag_epoch_remnants().unwrap(), vec![
+        remnant(1, &[1, 2]),
+        remnant(2, &[3, 4])
+    ]);
+    db.delete_dag_epoch_remnant(1).unwrap();
+    assert_eq!(db.get_dag_epoch_remnants().unwrap(), vec![remnant(2, &[
+        3, 4
+    ])]);
+}
+
+fn new_certified_node(epoch: u64, round: Round, author: Author) -> CertifiedNode {
+    let node = Node::new(
+        ChainId::test(),
+        epoch,
+        round,
+        author,
+        123,
+        Payload::empty(false),
+        vec![],
+    );
+    CertifiedNode::new(node, AggregateSignature::empty())
+}
+
+#[test]
+fn test_dag_legacy_certified_node_migration() {
+    let tmp_dir = TempPath::new();
+    let nodes: Vec<_> = (1..4)
+        .map(|round| new_certified_node(1, round, Author::random()))
+        .collect();
+    {
+        let db = ConsensusDB::new(&tmp_dir);
+        let batch = SchemaBatch::new();
+        for node in &nodes {
+            batch
+                .put::<LegacyCertifiedNodeSchema>(&node.digest(), node)
+                .unwrap();
+        }
+        db.commit(batch).unwrap();
+    }
+
+    let db = ConsensusDB::new(&tmp_dir);
+    let from_db = db.get_certified_nodes().unwrap();
+    assert_eq!(from_db.len(), nodes.len());
+    for node in &nodes {
+        assert_eq!(from_db.get(&node.digest()), Some(node));
+    }
+    let mut iter = db
+        .db
+        .iter::<LegacyCertifiedNodeSchema>(ReadOptions::default())
+        .unwrap();
+    iter.seek_to_first();
+    assert!(iter.next().is_none());
+
+    // the migrated nodes have their index entries
+    db.delete_certified_nodes(vec![nodes[0].digest()]).unwrap();
+    assert_eq!(db.get_certified_nodes().unwrap().len(), nodes.len() - 1);
+}
+
+#[test]
+fn test_dag_delete_rounds_below() {
+    let tmp_dir = TempPath::new();
+    let authors = [Author::ZERO, Author::random(), Author::random()];
+    let from_db = {
+        let db = ConsensusDB::new(&tmp_dir);
+        let mut nodes = vec![];
+        for epoch in 1..3 {
+            for round in 1..5 {
+                for author in authors {
+                    let node = new_certified_node(epoch, round, author);
+                    db.save_certified_node(&node).unwrap();
+                    nodes.push(node);
+                }
+            }
+        }
+
+        db.delete_dag_rounds_below(2, 3).unwrap();
+        let from_db = db.get_certified_nodes().unwrap();
+        for node in &nodes {
+            let metadata = node.metadata();
+            let kept = (metadata.epoch(), metadata.round()) >= (2, 3);
+            assert_eq!(from_db.contains_key(&node.digest()), kept);
+            assert_eq!(
+                db.db
+                    .get::<CertifiedNodeIndexSchema>(&node.digest())
+                    .unwrap()
+                    .is_some(),
+                kept
+            );
+        }
+        assert_eq!(from_db.len(), 2 * authors.len());
+        from_db
+    };
+
+    // the same nodes are recovered after reopening
+    let db = ConsensusDB::new(&tmp_dir);
+    assert_eq!(db.get_certified_nodes().unwrap(), from_db);
+}
+
+#[test]
+fn test_open_readonly() {
+    let tmp_dir = TempPath::new();
+    let nodes: Vec<_> = (1..4)
+        .map(|round| new_certified_node(1, round, Author::random()))
+        .collect();
+    {
+        let db = ConsensusDB::new(&tmp_dir);
+        for node in &nodes {
+            db.save_certified_node(node).unwrap();
+        }
+        db.save_dag_epoch_start_round(1, 1).unwrap();
+    }
+
+    let db = ConsensusDB::open_readonly(&tmp_dir).unwrap();
+    let from_db = db.get_certified_nodes().unwrap();
+    assert_eq!(from_db.len(), nodes.len());
+    for node in &nodes {
+        assert_eq!(from_db.get(&node.digest()), Some(node));
+    }
+    assert_eq!(db.get_dag_epoch_start_round().unwrap(), Some((1, 1)));
+    assert!(db.save_certified_node(&nodes[0]).is_err());
+
+    // nothing to open
+    assert!(ConsensusDB::open_readonly(TempPath::new()).is_err());
 }
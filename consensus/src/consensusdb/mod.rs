@@ -7,20 +7,41 @@ mod consensusdb_test;
 mod schema;
 
 use crate::{
-    dag::{CertifiedNode, Node},
+    dag::{
+        BroadcastProgress, CertifiedNode, DagEpochSummary, EpochRemnant, EvidenceRecord, Node,
+        NodeMetadata, OrderedAnchor, SkipVote,
+    },
     error::DbError,
 };
 use anyhow::Result;
-use aptos_consensus_types::{block::Block, quorum_cert::QuorumCert};
+use aptos_bitvec::BitVec;
+use aptos_consensus_types::{
+    block::Block,
+    common::{Author, Round},
+    quorum_cert::QuorumCert,
+};
 use aptos_crypto::HashValue;
 use aptos_logger::prelude::*;
-use aptos_schemadb::{Options, ReadOptions, SchemaBatch, DB, DEFAULT_COLUMN_FAMILY_NAME};
+use aptos_schemadb::{
+    schema::KeyCodec, ColumnFamilyName, Options, ReadOptions, SchemaBatch, DB,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
 use schema::{
     block::BlockSchema,
-    dag::{CertifiedNodeSchema, NodeSchema},
+    dag::{
+        BroadcastProgressSchema, CertifiedNodeIndexSchema, CertifiedNodeSchema,
+        DagEpochSummarySchema, DeniedAuthorSchema, EpochRemnantSchema, EquivocationEvidenceSchema,
+        EquivocatorSchema, LegacyCertifiedNodeSchema, NodeKey, NodeSchema, OrderedAnchorSchema,
+        PendingDeletionSchema, PendingNodeSchema, SelfReservationSchema, SkipVoteSchema,
+    },
     quorum_certificate::QCSchema,
     single_entry::{SingleEntryKey, SingleEntrySchema},
-    BLOCK_CF_NAME, CERTIFIED_NODE_CF_NAME, NODE_CF_NAME, QC_CF_NAME, SINGLE_ENTRY_CF_NAME,
+    BLOCK_CF_NAME, BROADCAST_PROGRESS_CF_NAME, CERTIFIED_NODE_CF_NAME,
+    CERTIFIED_NODE_INDEX_CF_NAME, DAG_EPOCH_SUMMARY_CF_NAME, DENIED_AUTHOR_CF_NAME,
+    EPOCH_REMNANT_CF_NAME, EQUIVOCATION_EVIDENCE_CF_NAME, EQUIVOCATOR_CF_NAME,
+    LEGACY_CERTIFIED_NODE_CF_NAME, NODE_CF_NAME, ORDERED_ANCHOR_CF_NAME, PENDING_DELETION_CF_NAME,
+    PENDING_NODE_CF_NAME, QC_CF_NAME, SELF_RESERVATION_CF_NAME, SINGLE_ENTRY_CF_NAME,
+    SKIP_VOTE_CF_NAME,
 };
 use std::{collections::HashMap, iter::Iterator, path::Path, time::Instant};
 
@@ -48,15 +69,32 @@ pub struct ConsensusDB {
 }
 
 impl ConsensusDB {
-    pub fn new<P: AsRef<Path> + Clone>(db_root_path: P) -> Self {
-        let column_families = vec![
+    fn column_families() -> Vec<ColumnFamilyName> {
+        vec![
             /* UNUSED CF = */ DEFAULT_COLUMN_FAMILY_NAME,
             BLOCK_CF_NAME,
             QC_CF_NAME,
             SINGLE_ENTRY_CF_NAME,
             NODE_CF_NAME,
             CERTIFIED_NODE_CF_NAME,
-        ];
+            CERTIFIED_NODE_INDEX_CF_NAME,
+            LEGACY_CERTIFIED_NODE_CF_NAME,
+            PENDING_NODE_CF_NAME,
+            PENDING_DELETION_CF_NAME,
+            ORDERED_ANCHOR_CF_NAME,
+            SKIP_VOTE_CF_NAME,
+            DAG_EPOCH_SUMMARY_CF_NAME,
+            SELF_RESERVATION_CF_NAME,
+            BROADCAST_PROGRESS_CF_NAME,
+            EQUIVOCATOR_CF_NAME,
+            EQUIVOCATION_EVIDENCE_CF_NAME,
+            DENIED_AUTHOR_CF_NAME,
+            EPOCH_REMNANT_CF_NAME,
+        ]
+    }
+
+    pub fn new<P: AsRef<Path> + Clone>(db_root_path: P) -> Self {
+        let column_families = Self::column_families();
 
         let path = db_root_path.as_ref().join(CONSENSUS_DB_NAME);
         let instant = Instant::now();
@@ -72,7 +110,49 @@ impl ConsensusDB {
             instant.elapsed().as_millis()
         );
 
-        Self { db }
+        let consensus_db = Self { db };
+        consensus_db
+            .migrate_legacy_certified_nodes()
+            .expect("ConsensusDB certified node migration failed; unable to continue");
+        consensus_db
+    }
+
+    /// Opens an existing DB without ever writing to it, for offline inspection. The certified
+    /// nodes of the legacy schema are not migrated.
+    #[cfg(any(test, feature = "dag-debugger"))]
+    pub fn open_readonly<P: AsRef<Path>>(db_root_path: P) -> Result<Self> {
+        let path = db_root_path.as_ref().join(CONSENSUS_DB_NAME);
+        let db = DB::open_cf_readonly(
+            &Options::default(),
+            path,
+            "consensus",
+            Self::column_families(),
+        )?;
+        Ok(Self { db })
+    }
+
+    /// Rewrites the certified nodes stored by digest to the (epoch, round, author) schema, in a
+    /// single batch so an interrupted migration is simply redone on the next open.
+    fn migrate_legacy_certified_nodes(&self) -> Result<(), DbError> {
+        let mut iter = self
+            .db
+            .iter::<LegacyCertifiedNodeSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        let legacy_nodes = iter.collect::<Result<Vec<(HashValue, CertifiedNode)>>>()?;
+        if legacy_nodes.is_empty() {
+            return Ok(());
+        }
+        let batch = SchemaBatch::new();
+        for (digest, node) in &legacy_nodes {
+            Self::put_certified_node(&batch, node)?;
+            batch.delete::<LegacyCertifiedNodeSchema>(digest)?;
+        }
+        self.commit(batch)?;
+        info!(
+            "Migrated {} certified nodes to the (epoch, round, author) schema",
+            legacy_nodes.len()
+        );
+        Ok(())
     }
 
     pub fn get_data(
@@ -199,9 +279,16 @@ impl ConsensusDB {
         Ok(())
     }
 
+    fn put_certified_node(batch: &SchemaBatch, node: &CertifiedNode) -> Result<()> {
+        let metadata = node.metadata();
+        let key = (metadata.epoch(), metadata.round(), *metadata.author());
+        batch.put::<CertifiedNodeSchema>(&key, node)?;
+        batch.put::<CertifiedNodeIndexSchema>(&node.digest(), &key)
+    }
+
     pub fn save_certified_node(&self, node: &CertifiedNode) -> Result<(), DbError> {
         let batch = SchemaBatch::new();
-        batch.put::<CertifiedNodeSchema>(&node.digest(), node)?;
+        Self::put_certified_node(&batch, node)?;
         self.commit(batch)?;
         Ok(())
     }
@@ -211,14 +298,337 @@ impl ConsensusDB {
             .db
             .iter::<CertifiedNodeSchema>(ReadOptions::default())?;
         iter.seek_to_first();
-        Ok(iter.collect::<Result<HashMap<HashValue, CertifiedNode>>>()?)
+        Ok(iter
+            .map(|entry| entry.map(|(_, node)| (node.digest(), node)))
+            .collect::<Result<HashMap<HashValue, CertifiedNode>>>()?)
     }
 
     pub fn delete_certified_nodes(&self, digests: Vec<HashValue>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        for digest in &digests {
+            if let Some(key) = self.db.get::<CertifiedNodeIndexSchema>(digest)? {
+                batch.delete::<CertifiedNodeSchema>(&key)?;
+            }
+            batch.delete::<CertifiedNodeIndexSchema>(digest)?;
+        }
+        self.commit(batch)
+    }
+
+    /// Deletes the certified nodes of the epochs below `epoch` and of the rounds of `epoch` below
+    /// `round` with a single range deletion, only their index entries are deleted one by one. The
+    /// keys start with the epoch and the round, so only the deleted range is read.
+    pub fn delete_dag_rounds_below(&self, epoch: u64, round: Round) -> Result<(), DbError> {
+        let begin: NodeKey = (0, 0, Author::ZERO);
+        let end: NodeKey = (epoch, round, Author::ZERO);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(<NodeKey as KeyCodec<CertifiedNodeSchema>>::encode_key(
+            &end,
+        )?);
+        let mut iter = self.db.iter::<CertifiedNodeSchema>(opts)?;
+        iter.seek(&begin)?;
+        let batch = SchemaBatch::new();
+        for entry in iter {
+            let (_, node) = entry?;
+            batch.delete::<CertifiedNodeIndexSchema>(&node.digest())?;
+        }
+        batch.delete_range::<CertifiedNodeSchema>(&begin, &end)?;
+        self.commit(batch)
+    }
+
+    pub fn save_pending_node(&self, node: &CertifiedNode) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<PendingNodeSchema>(&node.digest(), node)?;
+        self.commit(batch)?;
+        Ok(())
+    }
+
+    pub fn delete_pending_node(&self, digest: &HashValue) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.delete::<PendingNodeSchema>(digest)?;
+        self.commit(batch)
+    }
+
+    pub fn get_pending_nodes(&self) -> Result<HashMap<HashValue, CertifiedNode>, DbError> {
+        let mut iter = self.db.iter::<PendingNodeSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<HashValue, CertifiedNode>>>()?)
+    }
+
+    pub fn save_ordered_anchor(&self, ordered_anchor: &OrderedAnchor) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<OrderedAnchorSchema>(ordered_anchor.anchor().digest(), ordered_anchor)?;
+        self.commit(batch)
+    }
+
+    pub fn get_ordered_anchors(&self) -> Result<HashMap<HashValue, OrderedAnchor>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<OrderedAnchorSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<HashValue, OrderedAnchor>>>()?)
+    }
+
+    pub fn delete_ordered_anchors(&self, digests: Vec<HashValue>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        digests
+            .iter()
+            .try_for_each(|digest| batch.delete::<OrderedAnchorSchema>(digest))?;
+        self.commit(batch)
+    }
+
+    pub fn save_pending_deletions(
+        &self,
+        deletions: &HashMap<HashValue, u32>,
+    ) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        deletions.iter().try_for_each(|(digest, attempts)| {
+            batch.put::<PendingDeletionSchema>(digest, attempts)
+        })?;
+        self.commit(batch)
+    }
+
+    pub fn delete_pending_deletions(&self, digests: &[HashValue]) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        digests
+            .iter()
+            .try_for_each(|digest| batch.delete::<PendingDeletionSchema>(digest))?;
+        self.commit(batch)
+    }
+
+    pub fn get_pending_deletions(&self) -> Result<HashMap<HashValue, u32>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<PendingDeletionSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<HashValue, u32>>>()?)
+    }
+
+    pub fn save_skip_vote(&self, vote: &SkipVote) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<SkipVoteSchema>(&(vote.skip().round(), *vote.author()), vote)?;
+        self.commit(batch)
+    }
+
+    pub fn get_skip_votes(&self) -> Result<HashMap<(Round, Author), SkipVote>, DbError> {
+        let mut iter = self.db.iter::<SkipVoteSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<(Round, Author), SkipVote>>>()?)
+    }
+
+    pub fn delete_skip_votes(&self, keys: Vec<(Round, Author)>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<SkipVoteSchema>(key))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_self_reservation(
+        &self,
+        epoch: u64,
+        round: Round,
+        node: &Node,
+    ) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<SelfReservationSchema>(&(epoch, round), node)?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_self_reservations(&self) -> Result<HashMap<(u64, Round), Node>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<SelfReservationSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<(u64, Round), Node>>>()?)
+    }
+
+    pub fn delete_dag_self_reservations(&self, keys: Vec<(u64, Round)>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<SelfReservationSchema>(key))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_broadcast_progress(
+        &self,
+        epoch: u64,
+        round: Round,
+        digest: &HashValue,
+        acked: &BitVec,
+    ) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<BroadcastProgressSchema>(
+            &(epoch, round),
+            &BroadcastProgress::new(*digest, acked.clone()),
+        )?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_broadcast_progress(
+        &self,
+    ) -> Result<HashMap<(u64, Round), BroadcastProgress>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<BroadcastProgressSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<(u64, Round), BroadcastProgress>>>()?)
+    }
+
+    pub fn delete_dag_broadcast_progress(&self, keys: Vec<(u64, Round)>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<BroadcastProgressSchema>(key))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_equivocator(
+        &self,
+        epoch: u64,
+        author: &Author,
+        round: Round,
+    ) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<EquivocatorSchema>(&(epoch, *author), &round)?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_equivocators(&self) -> Result<HashMap<(u64, Author), Round>, DbError> {
+        let mut iter = self.db.iter::<EquivocatorSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<(u64, Author), Round>>>()?)
+    }
+
+    pub fn delete_dag_equivocators(&self, keys: Vec<(u64, Author)>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<EquivocatorSchema>(key))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_equivocation_evidence(&self, record: &EvidenceRecord) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<EquivocationEvidenceSchema>(record.conflicting().digest(), record)?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_equivocation_evidence(
+        &self,
+    ) -> Result<HashMap<HashValue, EvidenceRecord>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<EquivocationEvidenceSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<HashValue, EvidenceRecord>>>()?)
+    }
+
+    pub fn delete_dag_equivocation_evidence(&self, digests: Vec<HashValue>) -> Result<(), DbError> {
         let batch = SchemaBatch::new();
         digests
             .iter()
-            .try_for_each(|hash| batch.delete::<CertifiedNodeSchema>(hash))?;
+            .try_for_each(|digest| batch.delete::<EquivocationEvidenceSchema>(digest))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_denied_authors(
+        &self,
+        epoch: u64,
+        authors: &[(Author, Round)],
+    ) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        authors.iter().try_for_each(|(author, round)| {
+            batch.put::<DeniedAuthorSchema>(&(epoch, *author), round)
+        })?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_denied_authors(&self) -> Result<HashMap<(u64, Author), Round>, DbError> {
+        let mut iter = self.db.iter::<DeniedAuthorSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<(u64, Author), Round>>>()?)
+    }
+
+    pub fn delete_dag_denied_authors(&self, keys: Vec<(u64, Author)>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<DeniedAuthorSchema>(key))?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_epoch_start_round(&self, epoch: u64, round: Round) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<SingleEntrySchema>(
+            &SingleEntryKey::DagEpochStartRound,
+            &bcs::to_bytes(&(epoch, round)).map_err(anyhow::Error::from)?,
+        )?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_epoch_start_round(&self) -> Result<Option<(u64, Round)>, DbError> {
+        Ok(self
+            .db
+            .get::<SingleEntrySchema>(&SingleEntryKey::DagEpochStartRound)?
+            .map(|bytes| bcs::from_bytes(&bytes))
+            .transpose()
+            .map_err(anyhow::Error::from)?)
+    }
+
+    pub fn save_dag_last_committed_anchor(&self, anchor: &NodeMetadata) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<SingleEntrySchema>(
+            &SingleEntryKey::DagLastCommittedAnchor,
+            &bcs::to_bytes(anchor).map_err(anyhow::Error::from)?,
+        )?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_last_committed_anchor(&self) -> Result<Option<NodeMetadata>, DbError> {
+        Ok(self
+            .db
+            .get::<SingleEntrySchema>(&SingleEntryKey::DagLastCommittedAnchor)?
+            .map(|bytes| bcs::from_bytes(&bytes))
+            .transpose()
+            .map_err(anyhow::Error::from)?)
+    }
+
+    pub fn delete_dag_last_committed_anchor(&self) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.delete::<SingleEntrySchema>(&SingleEntryKey::DagLastCommittedAnchor)?;
+        self.commit(batch)
+    }
+
+    pub fn save_dag_epoch_summary(&self, summary: &DagEpochSummary) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<DagEpochSummarySchema>(&summary.epoch, summary)?;
+        self.commit(batch)
+    }
+
+    #[cfg(test)]
+    pub fn get_dag_epoch_summaries(&self) -> Result<Vec<DagEpochSummary>, DbError> {
+        let mut iter = self
+            .db
+            .iter::<DagEpochSummarySchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter
+            .map(|result| result.map(|(_, summary)| summary))
+            .collect::<Result<Vec<DagEpochSummary>>>()?)
+    }
+
+    pub fn save_dag_epoch_remnant(&self, remnant: &EpochRemnant) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<EpochRemnantSchema>(&remnant.epoch(), remnant)?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_epoch_remnants(&self) -> Result<Vec<EpochRemnant>, DbError> {
+        let mut iter = self.db.iter::<EpochRemnantSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter
+            .map(|result| result.map(|(_, remnant)| remnant))
+            .collect::<Result<Vec<EpochRemnant>>>()?)
+    }
+
+    pub fn delete_dag_epoch_remnant(&self, epoch: u64) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.delete::<EpochRemnantSchema>(&epoch)?;
         self.commit(batch)
     }
 }
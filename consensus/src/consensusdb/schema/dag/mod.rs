@@ -6,17 +6,112 @@
 //! Serialized bytes identified by node digest.
 //! ```text
 //! |<---key---->|<---value--->|
-//! |   digest   |   node/certified node    |
+//! |   digest   |   node/pending node    |
+//! ```
+//!
+//! Certified nodes identified by epoch, round and author, so the rounds below a round are a single
+//! key range. The digest is part of the node.
+//! ```text
+//! |<----------key---------->|<------value----->|
+//! | epoch | round | author  |  certified node  |
+//! ```
+//!
+//! The key of each certified node by digest.
+//! ```text
+//! |<---key---->|<----------value---------->|
+//! |   digest   |  epoch | round | author   |
+//! ```
+//!
+//! Certified nodes identified by digest, the previous format, rewritten to the schema above when
+//! the DB is opened.
+//! ```text
+//! |<---key---->|<-----value----->|
+//! |   digest   |  certified node |
+//! ```
+//!
+//! Ordered anchors identified by the anchor digest.
+//! ```text
+//! |<---key---->|<---value--->|
+//! |   digest   |   anchor and batch sources    |
+//! ```
+//!
+//! Certified nodes that failed to be deleted, with the number of attempts.
+//! ```text
+//! |<---key---->|<---value--->|
+//! |   digest   |   attempts  |
+//! ```
+//!
+//! Skip votes identified by round and voter.
+//! ```text
+//! |<------key------>|<---value--->|
+//! |  round | author |  skip vote  |
+//! ```
+//!
+//! Summaries of the ended epochs identified by epoch.
+//! ```text
+//! |<---key--->|<---value--->|
+//! |   epoch   |   summary   |
+//! ```
+//!
+//! Slots reserved by the local validator before broadcasting its node, identified by epoch and
+//! round.
+//! ```text
+//! |<-----key----->|<---value--->|
+//! | epoch | round |     node    |
+//! ```
+//!
+//! The validators that acked the certified node broadcast by the local validator, identified by
+//! epoch and round.
+//! ```text
+//! |<-----key----->|<--------value-------->|
+//! | epoch | round |  digest | acked bitvec |
+//! ```
+//!
+//! The authors excluded for equivocating in an epoch, with the round of their first equivocation.
+//! ```text
+//! |<-----key------>|<-value->|
+//! | epoch | author |  round  |
+//! ```
+//!
+//! Equivocations identified by the digest of the conflicting node, with both nodes or only their
+//! metadata.
+//! ```text
+//! |<---key---->|<------value------>|
+//! |   digest   |  evidence record  |
+//! ```
+//!
+//! The authors denied by governance in an epoch, with the round of the governance event denying
+//! them.
+//! ```text
+//! |<-----key------>|<-value->|
+//! | epoch | author |  round  |
+//! ```
+//!
+//! The ordered and uncommitted history of an ended epoch, identified by epoch, until it's
+//! committed.
+//! ```text
+//! |<---key--->|<-------------value------------->|
+//! |   epoch   |   anchors and their nodes       |
 //! ```
 
-use crate::dag::{CertifiedNode, Node};
+use super::ensure_slice_len_eq;
+use crate::dag::{
+    BroadcastProgress, CertifiedNode, DagEpochSummary, EpochRemnant, EvidenceRecord, Node,
+    OrderedAnchor, SkipVote,
+};
 use anyhow::Result;
+use aptos_consensus_types::common::{Author, Round};
 use aptos_crypto::HashValue;
 use aptos_schemadb::{
     define_schema,
     schema::{KeyCodec, ValueCodec},
     ColumnFamilyName,
 };
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::mem::size_of;
+
+/// Epoch, round and author of a certified node.
+pub type NodeKey = (u64, Round, Author);
 
 pub const NODE_CF_NAME: ColumnFamilyName = "node";
 
@@ -42,16 +137,53 @@ impl ValueCodec<NodeSchema> for Node {
     }
 }
 
-pub const CERTIFIED_NODE_CF_NAME: ColumnFamilyName = "certified_node";
+pub const CERTIFIED_NODE_CF_NAME: ColumnFamilyName = "certified_node_by_round";
 
 define_schema!(
     CertifiedNodeSchema,
-    HashValue,
+    NodeKey,
     CertifiedNode,
     CERTIFIED_NODE_CF_NAME
 );
 
-impl KeyCodec<CertifiedNodeSchema> for HashValue {
+impl KeyCodec<CertifiedNodeSchema> for NodeKey {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let (epoch, round, author) = self;
+        let mut encoded = Vec::with_capacity(2 * size_of::<u64>() + Author::LENGTH);
+        encoded.write_u64::<BigEndian>(*epoch)?;
+        encoded.write_u64::<BigEndian>(*round)?;
+        encoded.extend_from_slice(author.as_ref());
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, 2 * size_of::<u64>() + Author::LENGTH)?;
+        let epoch = data.read_u64::<BigEndian>()?;
+        let round = data.read_u64::<BigEndian>()?;
+        Ok((epoch, round, Author::from_bytes(data)?))
+    }
+}
+
+impl ValueCodec<CertifiedNodeSchema> for CertifiedNode {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const CERTIFIED_NODE_INDEX_CF_NAME: ColumnFamilyName = "certified_node_index";
+
+define_schema!(
+    CertifiedNodeIndexSchema,
+    HashValue,
+    NodeKey,
+    CERTIFIED_NODE_INDEX_CF_NAME
+);
+
+impl KeyCodec<CertifiedNodeIndexSchema> for HashValue {
     fn encode_key(&self) -> Result<Vec<u8>> {
         Ok(self.to_vec())
     }
@@ -61,7 +193,368 @@ impl KeyCodec<CertifiedNodeSchema> for HashValue {
     }
 }
 
-impl ValueCodec<CertifiedNodeSchema> for CertifiedNode {
+impl ValueCodec<CertifiedNodeIndexSchema> for NodeKey {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        <NodeKey as KeyCodec<CertifiedNodeSchema>>::encode_key(self)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        <NodeKey as KeyCodec<CertifiedNodeSchema>>::decode_key(data)
+    }
+}
+
+pub const LEGACY_CERTIFIED_NODE_CF_NAME: ColumnFamilyName = "certified_node";
+
+define_schema!(
+    LegacyCertifiedNodeSchema,
+    HashValue,
+    CertifiedNode,
+    LEGACY_CERTIFIED_NODE_CF_NAME
+);
+
+impl KeyCodec<LegacyCertifiedNodeSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<LegacyCertifiedNodeSchema> for CertifiedNode {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const PENDING_NODE_CF_NAME: ColumnFamilyName = "pending_node";
+
+define_schema!(
+    PendingNodeSchema,
+    HashValue,
+    CertifiedNode,
+    PENDING_NODE_CF_NAME
+);
+
+impl KeyCodec<PendingNodeSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<PendingNodeSchema> for CertifiedNode {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const PENDING_DELETION_CF_NAME: ColumnFamilyName = "pending_deletion";
+
+define_schema!(
+    PendingDeletionSchema,
+    HashValue,
+    u32,
+    PENDING_DELETION_CF_NAME
+);
+
+impl KeyCodec<PendingDeletionSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<PendingDeletionSchema> for u32 {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const ORDERED_ANCHOR_CF_NAME: ColumnFamilyName = "ordered_anchor";
+
+define_schema!(
+    OrderedAnchorSchema,
+    HashValue,
+    OrderedAnchor,
+    ORDERED_ANCHOR_CF_NAME
+);
+
+impl KeyCodec<OrderedAnchorSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<OrderedAnchorSchema> for OrderedAnchor {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const SKIP_VOTE_CF_NAME: ColumnFamilyName = "skip_vote";
+
+define_schema!(SkipVoteSchema, (Round, Author), SkipVote, SKIP_VOTE_CF_NAME);
+
+impl KeyCodec<SkipVoteSchema> for (Round, Author) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+impl ValueCodec<SkipVoteSchema> for SkipVote {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const DAG_EPOCH_SUMMARY_CF_NAME: ColumnFamilyName = "dag_epoch_summary";
+
+define_schema!(
+    DagEpochSummarySchema,
+    u64,
+    DagEpochSummary,
+    DAG_EPOCH_SUMMARY_CF_NAME
+);
+
+impl KeyCodec<DagEpochSummarySchema> for u64 {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(size_of::<u64>());
+        encoded.write_u64::<BigEndian>(*self)?;
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, size_of::<u64>())?;
+        Ok(data.read_u64::<BigEndian>()?)
+    }
+}
+
+impl ValueCodec<DagEpochSummarySchema> for DagEpochSummary {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const SELF_RESERVATION_CF_NAME: ColumnFamilyName = "dag_self_reservation";
+
+define_schema!(
+    SelfReservationSchema,
+    (u64, Round),
+    Node,
+    SELF_RESERVATION_CF_NAME
+);
+
+impl KeyCodec<SelfReservationSchema> for (u64, Round) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(2 * size_of::<u64>());
+        encoded.write_u64::<BigEndian>(self.0)?;
+        encoded.write_u64::<BigEndian>(self.1)?;
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, 2 * size_of::<u64>())?;
+        let epoch = data.read_u64::<BigEndian>()?;
+        let round = data.read_u64::<BigEndian>()?;
+        Ok((epoch, round))
+    }
+}
+
+impl ValueCodec<SelfReservationSchema> for Node {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const BROADCAST_PROGRESS_CF_NAME: ColumnFamilyName = "dag_broadcast_progress";
+
+define_schema!(
+    BroadcastProgressSchema,
+    (u64, Round),
+    BroadcastProgress,
+    BROADCAST_PROGRESS_CF_NAME
+);
+
+impl KeyCodec<BroadcastProgressSchema> for (u64, Round) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(2 * size_of::<u64>());
+        encoded.write_u64::<BigEndian>(self.0)?;
+        encoded.write_u64::<BigEndian>(self.1)?;
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, 2 * size_of::<u64>())?;
+        let epoch = data.read_u64::<BigEndian>()?;
+        let round = data.read_u64::<BigEndian>()?;
+        Ok((epoch, round))
+    }
+}
+
+impl ValueCodec<BroadcastProgressSchema> for BroadcastProgress {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const EQUIVOCATOR_CF_NAME: ColumnFamilyName = "dag_equivocator";
+
+define_schema!(EquivocatorSchema, (u64, Author), Round, EQUIVOCATOR_CF_NAME);
+
+impl KeyCodec<EquivocatorSchema> for (u64, Author) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(size_of::<u64>() + Author::LENGTH);
+        encoded.write_u64::<BigEndian>(self.0)?;
+        encoded.extend_from_slice(self.1.as_ref());
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, size_of::<u64>() + Author::LENGTH)?;
+        let epoch = data.read_u64::<BigEndian>()?;
+        Ok((epoch, Author::from_bytes(data)?))
+    }
+}
+
+impl ValueCodec<EquivocatorSchema> for Round {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const EQUIVOCATION_EVIDENCE_CF_NAME: ColumnFamilyName = "dag_equivocation_evidence";
+
+define_schema!(
+    EquivocationEvidenceSchema,
+    HashValue,
+    EvidenceRecord,
+    EQUIVOCATION_EVIDENCE_CF_NAME
+);
+
+impl KeyCodec<EquivocationEvidenceSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<EquivocationEvidenceSchema> for EvidenceRecord {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const DENIED_AUTHOR_CF_NAME: ColumnFamilyName = "dag_denied_author";
+
+define_schema!(
+    DeniedAuthorSchema,
+    (u64, Author),
+    Round,
+    DENIED_AUTHOR_CF_NAME
+);
+
+impl KeyCodec<DeniedAuthorSchema> for (u64, Author) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(size_of::<u64>() + Author::LENGTH);
+        encoded.write_u64::<BigEndian>(self.0)?;
+        encoded.extend_from_slice(self.1.as_ref());
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, size_of::<u64>() + Author::LENGTH)?;
+        let epoch = data.read_u64::<BigEndian>()?;
+        Ok((epoch, Author::from_bytes(data)?))
+    }
+}
+
+impl ValueCodec<DeniedAuthorSchema> for Round {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+pub const EPOCH_REMNANT_CF_NAME: ColumnFamilyName = "dag_epoch_remnant";
+
+define_schema!(EpochRemnantSchema, u64, EpochRemnant, EPOCH_REMNANT_CF_NAME);
+
+impl KeyCodec<EpochRemnantSchema> for u64 {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(size_of::<u64>());
+        encoded.write_u64::<BigEndian>(*self)?;
+        Ok(encoded)
+    }
+
+    fn decode_key(mut data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, size_of::<u64>())?;
+        Ok(data.read_u64::<BigEndian>()?)
+    }
+}
+
+impl ValueCodec<EpochRemnantSchema> for EpochRemnant {
     fn encode_value(&self) -> Result<Vec<u8>> {
         Ok(bcs::to_bytes(&self)?)
     }
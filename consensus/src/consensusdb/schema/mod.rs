@@ -20,6 +20,12 @@ pub(crate) fn ensure_slice_len_eq(data: &[u8], len: usize) -> Result<()> {
 }
 
 pub use block::BLOCK_CF_NAME;
-pub use dag::{CERTIFIED_NODE_CF_NAME, NODE_CF_NAME};
+pub use dag::{
+    BROADCAST_PROGRESS_CF_NAME, CERTIFIED_NODE_CF_NAME, CERTIFIED_NODE_INDEX_CF_NAME,
+    DAG_EPOCH_SUMMARY_CF_NAME, DENIED_AUTHOR_CF_NAME, EPOCH_REMNANT_CF_NAME,
+    EQUIVOCATION_EVIDENCE_CF_NAME, EQUIVOCATOR_CF_NAME, LEGACY_CERTIFIED_NODE_CF_NAME,
+    NODE_CF_NAME, ORDERED_ANCHOR_CF_NAME, PENDING_DELETION_CF_NAME, PENDING_NODE_CF_NAME,
+    SELF_RESERVATION_CF_NAME, SKIP_VOTE_CF_NAME,
+};
 pub use quorum_certificate::QC_CF_NAME;
 pub use single_entry::SINGLE_ENTRY_CF_NAME;
@@ -41,6 +41,10 @@ pub enum SingleEntryKey {
     LastVote = 0,
     // Two chain timeout cert
     Highest2ChainTimeoutCert = 1,
+    // Epoch and round of the parentless DAG nodes
+    DagEpochStartRound = 2,
+    // Metadata of the last committed DAG anchor
+    DagLastCommittedAnchor = 3,
 }
 
 impl KeyCodec<SingleEntrySchema> for SingleEntryKey {
@@ -0,0 +1,39 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::round_schedule::RoundSchedule;
+use aptos_consensus_types::common::{Author, Round};
+
+/// Decides which validator's node is the anchor of a round, every validator must use the
+/// same election to produce the same ordering.
+pub trait AnchorElection: Send + Sync {
+    fn get_anchor(&self, round: Round) -> Author;
+}
+
+/// Takes turns in validator order, one validator per anchor round. A vote round gets the anchor
+/// of the next anchor round.
+pub struct RoundRobinAnchorElection {
+    validators: Vec<Author>,
+    round_schedule: RoundSchedule,
+}
+
+impl RoundRobinAnchorElection {
+    /// Anchors of the default round schedule.
+    pub fn new(validators: Vec<Author>) -> Self {
+        Self::with_schedule(validators, RoundSchedule::default())
+    }
+
+    pub fn with_schedule(validators: Vec<Author>, round_schedule: RoundSchedule) -> Self {
+        Self {
+            validators,
+            round_schedule,
+        }
+    }
+}
+
+impl AnchorElection for RoundRobinAnchorElection {
+    fn get_anchor(&self, round: Round) -> Author {
+        let position = self.round_schedule.anchor_position(round);
+        self.validators[position as usize % self.validators.len()]
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verifies the certificates of a fetch response at once. The aggregate signatures of the
+//! certificates are scaled by random coefficients and checked against one aggregated key per
+//! certificate in a single multi-pairing, instead of two pairings per certificate. A failed batch
+//! only tells that some certificate is invalid, they are then verified one by one to tell which.
+
+use crate::dag::{
+    counters,
+    types::{CertifiedNode, NodeCertificate, NodeDigest},
+};
+use anyhow::anyhow;
+use aptos_bitvec::BitVec;
+use aptos_crypto::{bls12381, HashValue};
+use aptos_types::validator_verifier::{ValidatorVerifier, VerifyError};
+use thiserror::Error as ThisError;
+
+/// A certificate that failed verification, by its position in the batch.
+#[derive(Debug, ThisError)]
+#[error("certificate {index} of digest {digest} is invalid: {error}")]
+pub struct InvalidCertificate {
+    pub index: usize,
+    pub digest: HashValue,
+    pub error: VerifyError,
+}
+
+/// The certificates of a batch that failed verification, in the order of the batch.
+#[derive(Debug, ThisError)]
+#[error("{} of {} certificates are invalid", .invalid.len(), .num_certificates)]
+pub struct BatchVerifyError {
+    num_certificates: usize,
+    invalid: Vec<InvalidCertificate>,
+}
+
+impl BatchVerifyError {
+    pub fn invalid(&self) -> &[InvalidCertificate] {
+        &self.invalid
+    }
+
+    pub fn into_invalid(self) -> Vec<InvalidCertificate> {
+        self.invalid
+    }
+}
+
+/// The two ways of verifying the certificates, `ValidatorVerifier` implements them with the
+/// signatures and tests with a verifier observing which one runs.
+pub trait CertificateVerifier {
+    /// Whether every certificate is valid, without telling which ones aren't.
+    fn verify_batch(&self, certs: &[(HashValue, &NodeCertificate)]) -> Result<(), VerifyError>;
+
+    fn verify_certificate(
+        &self,
+        digest: HashValue,
+        cert: &NodeCertificate,
+    ) -> Result<(), VerifyError>;
+}
+
+impl CertificateVerifier for ValidatorVerifier {
+    /// Checks the signers of every certificate like `verify_multi_signatures`, then their
+    /// signatures over the digests with `Signature::verify_multiple`. The random coefficients
+    /// keep signature material from being moved between certificates, so a batch passing proves
+    /// each certificate would verify alone.
+    fn verify_batch(&self, certs: &[(HashValue, &NodeCertificate)]) -> Result<(), VerifyError> {
+        let validators = self.get_ordered_account_addresses();
+        let mut messages = Vec::with_capacity(certs.len());
+        let mut public_keys = Vec::with_capacity(certs.len());
+        let mut signatures: Vec<&bls12381::Signature> = Vec::with_capacity(certs.len());
+        for (digest, cert) in certs {
+            let bitvec = cert.signatures().get_signers_bitvec();
+            if bitvec.num_buckets() != BitVec::required_buckets(validators.len() as u16)
+                || bitvec
+                    .last_set_bit()
+                    .map_or(false, |bit| bit as usize >= validators.len())
+            {
+                return Err(VerifyError::InvalidBitVec);
+            }
+            let signers = cert.signatures().get_signers_addresses(&validators);
+            self.check_voting_power(signers.iter())?;
+            let signature = cert
+                .signatures()
+                .sig()
+                .as_ref()
+                .ok_or(VerifyError::EmptySignature)?;
+            let keys = signers
+                .iter()
+                .map(|signer| {
+                    self.get_public_key(signer)
+                        .ok_or(VerifyError::UnknownAuthor)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let aggregated_key = bls12381::PublicKey::aggregate(keys.iter().collect())
+                .map_err(|_| VerifyError::FailedToAggregatePubKey)?;
+            messages.push(NodeDigest::new(*digest));
+            public_keys.push(aggregated_key);
+            signatures.push(signature);
+        }
+        bls12381::Signature::verify_multiple(
+            &messages.iter().collect::<Vec<_>>(),
+            &public_keys.iter().collect::<Vec<_>>(),
+            &signatures,
+        )
+        .map_err(|_| VerifyError::InvalidAggregatedSignature)
+    }
+
+    fn verify_certificate(
+        &self,
+        digest: HashValue,
+        cert: &NodeCertificate,
+    ) -> Result<(), VerifyError> {
+        self.verify_multi_signatures(&NodeDigest::new(digest), cert.signatures())
+    }
+}
+
+/// Verifies that every certificate signs its digest, at once when there are several of them.
+/// Fails with every invalid certificate, the batch is verified one by one to find them if the
+/// batch check fails.
+pub fn verify_certificates_batched(
+    certs: &[(HashValue, &NodeCertificate)],
+    verifier: &ValidatorVerifier,
+) -> Result<(), BatchVerifyError> {
+    verify_certificates_with(certs, verifier)
+}
+
+/// `verify_certificates_batched` with any `CertificateVerifier`.
+pub fn verify_certificates_with(
+    certs: &[(HashValue, &NodeCertificate)],
+    verifier: &dyn CertificateVerifier,
+) -> Result<(), BatchVerifyError> {
+    // a single certificate costs the same either way
+    if certs.len() > 1 {
+        if verifier.verify_batch(certs).is_ok() {
+            counters::DAG_CERTIFICATE_BATCH_VERIFY_COUNT
+                .with_label_values(&["batch"])
+                .inc();
+            return Ok(());
+        }
+        counters::DAG_CERTIFICATE_BATCH_VERIFY_COUNT
+            .with_label_values(&["fallback"])
+            .inc();
+    }
+    let invalid: Vec<_> = certs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (digest, cert))| {
+            verifier
+                .verify_certificate(*digest, cert)
+                .err()
+                .map(|error| InvalidCertificate {
+                    index,
+                    digest: *digest,
+                    error,
+                })
+        })
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    Err(BatchVerifyError {
+        num_certificates: certs.len(),
+        invalid,
+    })
+}
+
+/// Verifies the nodes like `CertifiedNode::verify`, their certificates at once. The result of
+/// each node is at its position.
+pub fn verify_nodes_batched(
+    nodes: &[&CertifiedNode],
+    verifier: &ValidatorVerifier,
+) -> Vec<anyhow::Result<()>> {
+    let mut results: Vec<_> = nodes
+        .iter()
+        .map(|node| match node.has_valid_digest() {
+            true => Ok(()),
+            false => Err(anyhow!("invalid digest")),
+        })
+        .collect();
+    let (positions, certificates): (Vec<_>, Vec<_>) = nodes
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| results[*position].is_ok())
+        .map(|(position, node)| (position, (node.digest(), node.certificate())))
+        .unzip();
+    let certs: Vec<_> = certificates
+        .iter()
+        .map(|(digest, certificate)| (*digest, certificate))
+        .collect();
+    if let Err(e) = verify_certificates_batched(&certs, verifier) {
+        for invalid in e.into_invalid() {
+            results[positions[invalid.index]] = Err(anyhow!("unable to verify: {}", invalid.error));
+        }
+    }
+    results
+}
@@ -0,0 +1,112 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::Dag,
+    reliable_broadcast::BroadcastStatus,
+    types::{CertifiedAck, CertifiedNode},
+    validator_index::ValidatorIndex,
+};
+use anyhow::anyhow;
+use aptos_bitvec::BitVec;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::RwLock;
+use aptos_logger::warn;
+use std::sync::Arc;
+
+/// New acks coalesced into a single write of the progress.
+pub const DEFAULT_FLUSH_INTERVAL: usize = 4;
+
+/// Collects the acks of the certified node broadcast by the local validator like
+/// `CertificateAckState`, and persists them every `flush_interval` new acks and once every
+/// validator acked. A restart loses at most the acks of the last interval.
+pub struct BroadcastProgressTracker {
+    dag: Arc<RwLock<Dag>>,
+    validator_index: Arc<ValidatorIndex>,
+    round: Round,
+    digest: HashValue,
+    acked: BitVec,
+    num_acked: usize,
+    /// New acks since the last write
+    num_unsaved: usize,
+    flush_interval: usize,
+}
+
+impl BroadcastProgressTracker {
+    /// Starts from the persisted progress of the node, if any.
+    pub fn new(dag: Arc<RwLock<Dag>>, node: &CertifiedNode, flush_interval: usize) -> Self {
+        let round = node.metadata().round();
+        let digest = node.digest();
+        let (validator_index, progress) = {
+            let mut dag_writer = dag.write();
+            (
+                dag_writer.validator_index().clone(),
+                dag_writer.broadcast_progress(round, &digest),
+            )
+        };
+        let acked = progress.unwrap_or_else(|| BitVec::with_num_bits(validator_index.len() as u16));
+        let num_acked = (0..validator_index.len())
+            .filter(|index| acked.is_set(*index as u16))
+            .count();
+        Self {
+            dag,
+            validator_index,
+            round,
+            digest,
+            acked,
+            num_acked,
+            num_unsaved: 0,
+            flush_interval: flush_interval.max(1),
+        }
+    }
+
+    /// The validators that didn't ack yet, the only ones the node still has to be sent to.
+    pub fn remaining(&self) -> Vec<Author> {
+        self.validator_index
+            .authors()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.acked.is_set(*index as u16))
+            .map(|(_, author)| *author)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.num_acked == self.validator_index.len()
+    }
+
+    fn flush(&mut self) {
+        let result =
+            Dag::save_broadcast_progress(&self.dag, self.round, self.digest, self.acked.clone());
+        if let Err(e) = result {
+            warn!(
+                "Failed to save the broadcast progress of round {}: {:?}",
+                self.round, e
+            );
+        }
+        self.num_unsaved = 0;
+    }
+}
+
+impl BroadcastStatus for BroadcastProgressTracker {
+    type Ack = CertifiedAck;
+    type Aggregated = ();
+    type Message = CertifiedNode;
+
+    fn add(&mut self, peer: Author, _ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
+        let index = self
+            .validator_index
+            .index_of(&peer)
+            .ok_or_else(|| anyhow!("ack from unknown peer {}", peer))?;
+        if !self.acked.is_set(index as u16) {
+            self.acked.set(index as u16);
+            self.num_acked += 1;
+            self.num_unsaved += 1;
+            if self.is_complete() || self.num_unsaved >= self.flush_interval {
+                self.flush();
+            }
+        }
+        Ok(self.is_complete().then_some(()))
+    }
+}
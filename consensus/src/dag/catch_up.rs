@@ -0,0 +1,442 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        counters,
+        dag_fetcher::{check_fetched_nodes, filter_fetched_response, CertifiedNodeVerifier},
+        dag_network::DAGNetworkSender,
+        dag_store::{CatchUpProgress, Dag, FetchPlan, InsertOutcome},
+        fetch_budget::{Admission, FetchBudget},
+        peer_tracker::DagPeerTracker,
+        reliable_broadcast::CatchUpRequest,
+        types::{AuthorFetchRequest, CertifiedNode, DAGMessage, FetchResponse},
+    },
+    network::TConsensusMsg,
+    util::time_service::TimeService,
+};
+use anyhow::ensure;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::{debug, info, warn};
+use aptos_types::epoch_state::EpochState;
+use futures::future::join_all;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Settings of `DagCatchUp`.
+#[derive(Clone, Copy, Debug)]
+pub struct DagCatchUpConfig {
+    /// How often the DAG is checked for holes when no rejection wakes the task
+    pub interval: Duration,
+    pub request_timeout: Duration,
+    /// Rounds of an author one request covers at most
+    pub max_rounds_per_request: Round,
+    /// Wait after a wave that inserted nothing, doubled by every such wave up to `max_backoff`
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for DagCatchUpConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(1),
+            max_rounds_per_request: 10,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CatchUpOutcome {
+    /// Nothing the peers claim is missing anymore and the target, if any, was inserted
+    CaughtUp {
+        num_inserted: usize,
+    },
+    EpochEnded,
+}
+
+/// Closes the gap of a DAG that fell behind. It's woken by the `CatchUpRequest`s of the nodes
+/// rejected for being too far ahead, and checks for the holes the peers can fill every
+/// `interval`. The missing slots are fetched in waves of author requests admitted by the fetch
+/// budget, each sent to the peers ranked by the peer tracker, and the nodes of a wave are added
+/// together so they can connect to each other.
+pub struct DagCatchUp {
+    epoch_state: Arc<EpochState>,
+    dag: Arc<RwLock<Dag>>,
+    network: Arc<dyn DAGNetworkSender>,
+    verifier: Arc<dyn CertifiedNodeVerifier>,
+    peer_tracker: Arc<Mutex<DagPeerTracker>>,
+    budget: FetchBudget,
+    time_service: Arc<dyn TimeService>,
+    config: DagCatchUpConfig,
+    request_rx: Receiver<CatchUpRequest>,
+    /// The highest node rejected for being too far ahead, inserted once its parents are
+    target: Option<CertifiedNode>,
+}
+
+impl DagCatchUp {
+    pub fn new(
+        dag: Arc<RwLock<Dag>>,
+        network: Arc<dyn DAGNetworkSender>,
+        verifier: Arc<dyn CertifiedNodeVerifier>,
+        peer_tracker: Arc<Mutex<DagPeerTracker>>,
+        budget: FetchBudget,
+        time_service: Arc<dyn TimeService>,
+        config: DagCatchUpConfig,
+    ) -> (Self, Sender<CatchUpRequest>) {
+        let (request_tx, request_rx) = tokio::sync::mpsc::channel(16);
+        let epoch_state = dag.read().epoch_state().clone();
+        (
+            Self {
+                epoch_state,
+                dag,
+                network,
+                verifier,
+                peer_tracker,
+                budget,
+                time_service,
+                config,
+                request_rx,
+                target: None,
+            },
+            request_tx,
+        )
+    }
+
+    /// Catches up on every request and every `interval`, until the epoch ends or the handler
+    /// sending the requests is dropped.
+    pub async fn run(mut self) {
+        loop {
+            match tokio::time::timeout(self.config.interval, self.request_rx.recv()).await {
+                Ok(Some(request)) => self.on_request(request),
+                Ok(None) => return,
+                Err(_) => {},
+            }
+            if self.catch_up().await == CatchUpOutcome::EpochEnded {
+                return;
+            }
+        }
+    }
+
+    /// Fetches wave after wave until nothing the peers claim is missing and the target is
+    /// inserted, or until the epoch ends. A wave that inserts nothing, because no peer could serve
+    /// the plan, is followed by an exponential backoff.
+    pub async fn catch_up(&mut self) -> CatchUpOutcome {
+        let started_at = self.time_service.get_current_timestamp();
+        let mut num_inserted = 0;
+        let mut backoff = self.config.min_backoff;
+        loop {
+            while let Ok(request) = self.request_rx.try_recv() {
+                self.on_request(request);
+            }
+            if self.dag.read().is_ended() {
+                self.report_progress(None);
+                return CatchUpOutcome::EpochEnded;
+            }
+            let plan = self.plan();
+            if plan.missing_slots.is_empty() {
+                if let Some(target) = self.target.take() {
+                    if let InsertOutcome::Rejected(e) = self.dag.write().insert_node(target) {
+                        warn!("Failed to insert the node caught up on: {}", e);
+                    }
+                }
+                if num_inserted > 0 {
+                    info!(
+                        "Caught up with {} nodes in {:?}",
+                        num_inserted,
+                        self.time_service
+                            .get_current_timestamp()
+                            .saturating_sub(started_at)
+                    );
+                }
+                self.report_progress(None);
+                return CatchUpOutcome::CaughtUp { num_inserted };
+            }
+            let elapsed = self
+                .time_service
+                .get_current_timestamp()
+                .saturating_sub(started_at);
+            let eta = (num_inserted > 0)
+                .then(|| elapsed.mul_f64(plan.estimated_nodes as f64 / num_inserted as f64));
+            self.report_progress(Some(CatchUpProgress {
+                slots_remaining: plan.estimated_nodes,
+                eta,
+            }));
+            match self.fetch_wave(&plan).await {
+                0 => {
+                    warn!(
+                        "No peer served any of the {} missing slots, retrying in {:?}",
+                        plan.estimated_nodes, backoff
+                    );
+                    self.time_service.sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                },
+                num_wave_inserted => {
+                    num_inserted += num_wave_inserted;
+                    backoff = self.config.min_backoff;
+                },
+            }
+        }
+    }
+
+    /// Keeps the highest target, the history of the lower ones is part of it.
+    fn on_request(&mut self, request: CatchUpRequest) {
+        let round = request.target.metadata().round();
+        if self
+            .target
+            .as_ref()
+            .map_or(true, |target| target.metadata().round() < round)
+        {
+            self.target = Some(request.target);
+        }
+    }
+
+    /// The slots to fetch: those of the fetch plan of the target that a peer claims or the
+    /// target links to, and the holes of every author a peer claims below the highest round. The
+    /// rounds above it are only included once a peer claims more than the park gap beyond it,
+    /// closer ones are still being broadcast.
+    fn plan(&mut self) -> FetchPlan {
+        let dag_reader = self.dag.read();
+        if self
+            .target
+            .as_ref()
+            .map_or(false, |target| dag_reader.exists(&target.digest()))
+        {
+            self.target = None;
+        }
+        let peer_tracker = self.peer_tracker.lock();
+        let validator_index = dag_reader.validator_index();
+        let mut missing_indices: BTreeMap<Round, BTreeSet<usize>> = BTreeMap::new();
+        let mut add = |round: Round, author: &Author| {
+            if let Some(index) = validator_index.index_of(author) {
+                missing_indices.entry(round).or_default().insert(index);
+            }
+        };
+        if let Some(target) = &self.target {
+            let parents: HashSet<_> = target
+                .parents()
+                .iter()
+                .map(|parent| (parent.metadata().round(), *parent.metadata().author()))
+                .collect();
+            for (round, authors) in dag_reader.fetch_plan_for(target).missing_slots {
+                for author in &authors {
+                    if parents.contains(&(round, *author)) || peer_tracker.is_claimed(round, author)
+                    {
+                        add(round, author);
+                    }
+                }
+            }
+        }
+        let highest_round = dag_reader.highest_round();
+        let ahead_rounds: Vec<_> = match peer_tracker.highest_claimed_round() {
+            Some(round) if round > highest_round + dag_reader.park_gap() => {
+                (highest_round + 1..=round).collect()
+            },
+            _ => vec![],
+        };
+        for author in validator_index.authors() {
+            let holes = dag_reader
+                .missing_slots_for_author(author)
+                .into_iter()
+                .filter(|round| *round < highest_round)
+                .chain(ahead_rounds.iter().copied());
+            for round in holes {
+                if peer_tracker.is_claimed(round, author) {
+                    add(round, author);
+                }
+            }
+        }
+        let validators = validator_index.authors();
+        let missing_slots: BTreeMap<_, Vec<_>> = missing_indices
+            .into_iter()
+            .map(|(round, indices)| {
+                (
+                    round,
+                    indices.into_iter().map(|index| validators[index]).collect(),
+                )
+            })
+            .collect();
+        FetchPlan {
+            estimated_nodes: missing_slots.values().map(Vec::len).sum(),
+            missing_slots,
+        }
+    }
+
+    /// Sends the author requests of the lowest rounds of the plan the budget admits, and adds the
+    /// nodes of all the responses at once. Returns the number of nodes added.
+    async fn fetch_wave(&mut self, plan: &FetchPlan) -> usize {
+        let mut requests =
+            plan.author_requests(self.epoch_state.epoch, self.config.max_rounds_per_request);
+        requests.sort_by_key(|request| *request.rounds().start());
+        let mut admitted = vec![];
+        for request in requests {
+            let request_plan = request_plan(plan, &request);
+            match self.budget.admit(&request_plan) {
+                Admission::Admitted(ticket) => admitted.push((ticket, request, request_plan)),
+                Admission::Deferred => break,
+            }
+        }
+        // collected first, a lazy iterator of the futures makes `run` fail to prove it's `Send`
+        let requests: Vec<_> = admitted
+            .iter()
+            .map(|(_, request, request_plan)| self.fetch_request(request, request_plan))
+            .collect();
+        let responses = join_all(requests).await;
+
+        let mut digests = HashSet::new();
+        let mut nodes: Vec<_> = responses
+            .into_iter()
+            .flatten()
+            .filter(|node| digests.insert(node.digest()))
+            .collect();
+        let mut num_inserted = 0;
+        {
+            let mut dag_writer = self.dag.write();
+            nodes.retain(|node| !dag_writer.exists(&node.digest()));
+            let metadatas: Vec<_> = nodes.iter().map(|node| node.metadata().clone()).collect();
+            for (metadata, result) in metadatas.iter().zip(dag_writer.add_nodes(nodes)) {
+                match result {
+                    Ok(()) => {
+                        self.budget.on_insert(metadata, &InsertOutcome::Inserted);
+                        num_inserted += 1;
+                    },
+                    Err(e) => debug!(
+                        "Failed to add node {} caught up on: {}",
+                        metadata.digest(),
+                        e
+                    ),
+                }
+            }
+        }
+        for (ticket, _, _) in admitted {
+            self.budget.release(ticket);
+        }
+        num_inserted
+    }
+
+    /// Asks the peers ranked for the slots of the request in turn, the signers of the target if no
+    /// peer claims them, until one delivers some of the nodes. The peer tracker credits or demotes
+    /// every peer asked.
+    async fn fetch_request(
+        &self,
+        request: &AuthorFetchRequest,
+        plan: &FetchPlan,
+    ) -> Vec<CertifiedNode> {
+        let mut peers = self.peer_tracker.lock().rank_for(plan);
+        if peers.is_empty() {
+            peers = self.target.as_ref().map_or(vec![], |target| {
+                target.signatures().get_signers_addresses(
+                    &self.epoch_state.verifier.get_ordered_account_addresses(),
+                )
+            });
+        }
+        if peers.is_empty() {
+            counters::DAG_CATCH_UP_REQUEST_COUNT
+                .with_label_values(&["unserved"])
+                .inc();
+            return vec![];
+        }
+        for peer in peers {
+            let nodes = match self.send_request(peer, request).await {
+                Ok(nodes) => nodes,
+                Err(e) => {
+                    debug!("Catch-up request to {} failed: {}", peer, e);
+                    vec![]
+                },
+            };
+            self.peer_tracker
+                .lock()
+                .record_response(&peer, plan, &nodes);
+            let outcome = if nodes.is_empty() { "failed" } else { "served" };
+            counters::DAG_CATCH_UP_REQUEST_COUNT
+                .with_label_values(&[outcome])
+                .inc();
+            if !nodes.is_empty() {
+                return nodes;
+            }
+        }
+        vec![]
+    }
+
+    /// Sends the request to `peer` and returns the nodes of its response that are still relevant
+    /// and pass `check_fetched_nodes`. A response with a node the request didn't ask for is
+    /// rejected whole.
+    async fn send_request(
+        &self,
+        peer: Author,
+        request: &AuthorFetchRequest,
+    ) -> anyhow::Result<Vec<CertifiedNode>> {
+        let message = DAGMessage::from(request.clone()).into_network_message();
+        let response = self
+            .network
+            .send_rpc(peer, message, self.config.request_timeout)
+            .await?;
+        let response = FetchResponse::try_from(DAGMessage::try_from(response)?)?;
+        ensure!(response.epoch() == self.epoch_state.epoch, "epoch mismatch");
+        let (nodes, held) = {
+            let dag_reader = self.dag.read();
+            let nodes: Vec<_> = filter_fetched_response(&dag_reader, response)
+                .certified_nodes()
+                .into_iter()
+                .flatten()
+                .collect();
+            for node in &nodes {
+                ensure!(node.author() == request.author(), "unexpected author");
+                ensure!(
+                    request.rounds().contains(&node.metadata().round()),
+                    "unexpected round"
+                );
+            }
+            let mut held: HashMap<_, _> = nodes
+                .iter()
+                .filter_map(|node| {
+                    let metadata = node.metadata();
+                    dag_reader
+                        .expected_digest(metadata)
+                        .map(|digest| ((metadata.round(), *metadata.author()), digest))
+                })
+                .collect();
+            for parent in self.target.iter().flat_map(|target| target.parents()) {
+                let metadata = parent.metadata();
+                held.entry((metadata.round(), *metadata.author()))
+                    .or_insert(*metadata.digest());
+            }
+            (nodes, held)
+        };
+        let (accepted, rejected) = check_fetched_nodes(held, peer, nodes, self.verifier.as_ref());
+        for e in &rejected {
+            warn!("Rejected node fetched to catch up: {}", e);
+        }
+        Ok(accepted)
+    }
+
+    fn report_progress(&self, progress: Option<CatchUpProgress>) {
+        counters::DAG_CATCH_UP_SLOTS_REMAINING
+            .set(progress.map_or(0, |progress| progress.slots_remaining as i64));
+        counters::DAG_CATCH_UP_ETA_SECONDS.set(progress.map_or(0, |progress| {
+            progress.eta.map_or(-1, |eta| eta.as_secs() as i64)
+        }));
+        self.dag.write().set_catch_up_progress(progress);
+    }
+}
+
+/// The slots of `plan` the request covers.
+fn request_plan(plan: &FetchPlan, request: &AuthorFetchRequest) -> FetchPlan {
+    let missing_slots: BTreeMap<_, _> = plan
+        .missing_slots
+        .range(request.rounds())
+        .filter(|(_, authors)| authors.contains(request.author()))
+        .map(|(round, _)| (*round, vec![*request.author()]))
+        .collect();
+    FetchPlan {
+        estimated_nodes: missing_slots.len(),
+        missing_slots,
+    }
+}
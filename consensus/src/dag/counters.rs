@@ -0,0 +1,273 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_metrics_core::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Histogram, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use once_cell::sync::Lazy;
+
+/// Count of anchor orderings aborted because the causal history exceeded the traversal budget.
+pub static TRAVERSAL_BUDGET_EXCEEDED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_dag_traversal_budget_exceeded_count",
+        "Count of anchor orderings that exceeded the traversal budget."
+    )
+    .unwrap()
+});
+
+/// Count of anchor orderings refused because the anchor doesn't reach the last committed anchor.
+pub static ANCHOR_NOT_CONNECTED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_dag_anchor_not_connected_count",
+        "Count of anchors refused for not reaching the last committed anchor."
+    )
+    .unwrap()
+});
+
+/// Count of storage failures tolerated in best effort mode, by operation.
+pub static STORAGE_ERROR_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_storage_error_count",
+        "Count of the DAG storage errors tolerated in best effort mode.",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// Count of queued node deletions retried.
+pub static DELETION_RETRIED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_dag_deletion_retried_count",
+        "Count of the queued node deletions retried."
+    )
+    .unwrap()
+});
+
+/// Count of queued node deletions given up after too many failed attempts.
+pub static DELETION_FAILED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_dag_deletion_failed_count",
+        "Count of the queued node deletions given up after too many attempts."
+    )
+    .unwrap()
+});
+
+/// Count of fetched nodes dropped before verification, by reason.
+pub static FETCHED_NODES_DROPPED_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_fetched_nodes_dropped_count",
+        "Count of the fetched nodes dropped before verification because they are no longer relevant.",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Count of fetched nodes checked before being added, by outcome.
+pub static FETCHED_NODES_CHECKED_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_fetched_nodes_checked_count",
+        "Count of the fetched nodes matched against a held certificate, verified by their signatures or rejected.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Count of DAG resets forced by an operator.
+pub static FORCE_RESET_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_dag_force_reset_count",
+        "Count of the DAG resets forced through the admin interface."
+    )
+    .unwrap()
+});
+
+/// Count of the nodes handed to the DAG, by insert outcome.
+pub static INSERT_OUTCOME_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_insert_outcome_count",
+        "Count of the nodes handed to the DAG by what happened to them.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Count of the equivocation evidence recorded, by whether both nodes are kept.
+pub static EQUIVOCATION_EVIDENCE_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_equivocation_evidence_count",
+        "Count of the equivocations recorded with both nodes or only their metadata.",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Count of the pending nodes by how they left the buffer, and of the nodes parked again.
+pub static PENDING_RESOLUTION_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_pending_resolution_count",
+        "Count of the pending nodes inserted, dropped as duplicates or equivocations, or rejected.",
+        &["resolution"]
+    )
+    .unwrap()
+});
+
+/// Highest round of each author minus the median highest round, by author.
+pub static AUTHOR_SKEW: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_consensus_dag_author_skew",
+        "Rounds each author is ahead of the median author, negative when behind.",
+        &["author"]
+    )
+    .unwrap()
+});
+
+/// Largest gap between an incoming certified node and the highest round since the DAG last
+/// advanced.
+pub static DAG_BEHIND_ROUNDS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_behind_rounds",
+        "Largest number of rounds an incoming certified node was above the highest round of the DAG since it last advanced."
+    )
+    .unwrap()
+});
+
+/// Share of the fetch budget in use, by resource.
+pub static FETCH_BUDGET_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_consensus_dag_fetch_budget_in_flight",
+        "Fetch requests and nodes in flight under the fetch budget.",
+        &["resource"]
+    )
+    .unwrap()
+});
+
+/// Limits of the fetch budget, by resource.
+pub static FETCH_BUDGET_LIMIT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_consensus_dag_fetch_budget_limit",
+        "Fetch requests and nodes allowed in flight by the fetch budget.",
+        &["resource"]
+    )
+    .unwrap()
+});
+
+/// Time from the insertion of a node in the DAG to its ordering.
+pub static NODE_INSERT_TO_ORDER_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "aptos_consensus_dag_node_insert_to_order_seconds",
+        "The time in seconds from the insertion of a node in the DAG to its ordering.",
+        exponential_buckets(/*start=*/ 0.01, /*factor=*/ 1.5, /*count=*/ 25).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Time from the ordering of a node to the commit of its anchor.
+pub static NODE_ORDER_TO_COMMIT_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "aptos_consensus_dag_node_order_to_commit_seconds",
+        "The time in seconds from the ordering of a node to the commit of its anchor.",
+        exponential_buckets(/*start=*/ 0.01, /*factor=*/ 1.5, /*count=*/ 25).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Rounds from an anchor to the round whose links committed it, by whether it's the first attempt
+/// or a fallback after a skipped anchor.
+pub static ANCHOR_COMMIT_LATENCY_ROUNDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aptos_consensus_dag_anchor_commit_latency_rounds",
+        "The rounds from an anchor to the round whose links committed it.",
+        &["attempt"],
+        exponential_buckets(/*start=*/ 1.0, /*factor=*/ 2.0, /*count=*/ 8).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Verdict of the DAG health check: 0 healthy, 1 degraded, 2 stalled.
+pub static DAG_HEALTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_health",
+        "The verdict of the DAG health check, 0 healthy, 1 degraded and 2 stalled."
+    )
+    .unwrap()
+});
+
+/// Count of changes of the DAG health verdict, by new verdict.
+pub static DAG_HEALTH_TRANSITION_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_health_transition_count",
+        "Count of the changes of the DAG health verdict, by new verdict.",
+        &["health"]
+    )
+    .unwrap()
+});
+
+/// Count of the node writes retried by the write retry queue, by outcome.
+pub static WRITE_RETRY_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_write_retry_count",
+        "Count of the node writes retried after a storage failure, by outcome.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Count of the entries of the replay log, written or dropped and why.
+pub static REPLAY_LOG_ENTRY_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_replay_log_entry_count",
+        "Count of the entries of the DAG replay log, written or dropped and why.",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Count of the redeliveries of pruned nodes dropped by the network handler, by message.
+pub static PRUNED_REDELIVERY_DROP_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_pruned_redelivery_drop_count",
+        "Count of the redeliveries of pruned nodes dropped before verification, by message.",
+        &["message"]
+    )
+    .unwrap()
+});
+
+/// Slots the catch-up still has to fetch, 0 once the gap is closed.
+pub static DAG_CATCH_UP_SLOTS_REMAINING: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_catch_up_slots_remaining",
+        "Number of the slots the catch-up of the DAG still has to fetch."
+    )
+    .unwrap()
+});
+
+/// Estimated time until the catch-up closes the gap, -1 while unknown and 0 once it's closed.
+pub static DAG_CATCH_UP_ETA_SECONDS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_catch_up_eta_seconds",
+        "The estimated seconds until the catch-up of the DAG closes the gap, -1 while unknown and 0 once closed."
+    )
+    .unwrap()
+});
+
+/// Count of the catch-up fetch requests, by outcome.
+pub static DAG_CATCH_UP_REQUEST_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_catch_up_request_count",
+        "Count of the fetch requests of the catch-up of the DAG, by outcome.",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Count of the certificate batches verified, by the path that settled them.
+pub static DAG_CERTIFICATE_BATCH_VERIFY_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_certificate_batch_verify_count",
+        "Count of the batches of node certificates verified at once, or one by one after the batch check failed.",
+        &["path"]
+    )
+    .unwrap()
+});
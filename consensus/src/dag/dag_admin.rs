@@ -0,0 +1,76 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    counters,
+    dag_store::{Dag, NodeLatencySample, ResetReport},
+};
+use anyhow::ensure;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::RwLock;
+use aptos_logger::warn;
+use aptos_types::ledger_info::LedgerInfo;
+use std::sync::Arc;
+
+/// Operator-only operations on the DAG, served by the admin interface of the node. They're not
+/// DAG messages so peers can't trigger them, and every call must present the passcode the admin
+/// interface is configured with.
+pub struct DagAdmin {
+    dag: Arc<RwLock<Dag>>,
+    passcode_sha3_256: HashValue,
+}
+
+impl DagAdmin {
+    pub fn new(dag: Arc<RwLock<Dag>>, passcode_sha3_256: HashValue) -> Self {
+        Self {
+            dag,
+            passcode_sha3_256,
+        }
+    }
+
+    /// Resets the DAG with `Dag::force_reset`, `latest_ledger_info` is read from the local ledger.
+    pub fn force_reset(
+        &self,
+        passcode: &str,
+        to_committed_round: Round,
+        latest_ledger_info: &LedgerInfo,
+    ) -> anyhow::Result<ResetReport> {
+        self.check_passcode(passcode)?;
+        let report = self
+            .dag
+            .write()
+            .force_reset(to_committed_round, latest_ledger_info)?;
+        counters::FORCE_RESET_COUNT.inc();
+        warn!(
+            "DAG force reset to round {}: {:?}",
+            to_committed_round, report
+        );
+        Ok(report)
+    }
+
+    /// Re-admits `author` with `Dag::clear_equivocator`, returns whether it was excluded.
+    pub fn clear_equivocator(&self, passcode: &str, author: &Author) -> anyhow::Result<bool> {
+        self.check_passcode(passcode)?;
+        Ok(self.dag.write().clear_equivocator(author)?)
+    }
+
+    /// The latencies of the last committed nodes, to debug the outliers of the latency histograms.
+    pub fn latency_samples(&self, passcode: &str) -> anyhow::Result<Vec<NodeLatencySample>> {
+        self.check_passcode(passcode)?;
+        Ok(self.dag.read().latency_samples())
+    }
+
+    /// Compares every byte of the digests so the time taken doesn't tell how much of a guess
+    /// matches.
+    fn check_passcode(&self, passcode: &str) -> anyhow::Result<()> {
+        let digest = HashValue::sha3_256_of(passcode.as_bytes());
+        let difference = digest
+            .as_ref()
+            .iter()
+            .zip(self.passcode_sha3_256.as_ref())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b));
+        ensure!(difference == 0, "invalid admin passcode");
+        Ok(())
+    }
+}
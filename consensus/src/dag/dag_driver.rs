@@ -3,40 +3,50 @@
 
 use crate::{
     dag::{
-        dag_store::Dag,
+        anchor_election::AnchorElection,
+        broadcast_progress::{BroadcastProgressTracker, DEFAULT_FLUSH_INTERVAL},
+        dag_store::{Dag, Frontier},
         reliable_broadcast::ReliableBroadcast,
-        types::{CertificateAckState, CertifiedNode, Node, NodeCertificate, SignatureBuilder},
+        types::{CertifiedNode, Node, NodeCertificate, SignatureBuilder},
     },
     state_replication::PayloadClient,
     util::time_service::TimeService,
 };
 use aptos_consensus_types::common::{Author, Payload};
 use aptos_infallible::RwLock;
+use aptos_logger::{error, info, warn};
 use aptos_types::{block_info::Round, epoch_state::EpochState};
 use futures::{
     future::{AbortHandle, Abortable},
-    FutureExt,
+    Future, FutureExt,
 };
 use std::sync::Arc;
 
+/// Bytes of strong link certificates in a proposed node, well below the network message limit so
+/// the payload still fits.
+pub const MAX_STRONG_LINKS_BYTES: usize = 512 * 1024;
+
 pub(crate) struct DagDriver {
     author: Author,
     epoch_state: Arc<EpochState>,
     dag: Arc<RwLock<Dag>>,
     payload_client: Arc<dyn PayloadClient>,
     reliable_broadcast: Arc<ReliableBroadcast>,
+    anchor_election: Arc<dyn AnchorElection>,
     current_round: Round,
     time_service: Arc<dyn TimeService>,
     rb_abort_handle: Option<AbortHandle>,
 }
 
 impl DagDriver {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         author: Author,
         epoch_state: Arc<EpochState>,
         dag: Arc<RwLock<Dag>>,
         payload_client: Arc<dyn PayloadClient>,
         reliable_broadcast: Arc<ReliableBroadcast>,
+        anchor_election: Arc<dyn AnchorElection>,
         current_round: Round,
         time_service: Arc<dyn TimeService>,
     ) -> Self {
@@ -46,6 +56,7 @@ impl DagDriver {
             dag,
             payload_client,
             reliable_broadcast,
+            anchor_election,
             current_round,
             time_service,
             rb_abort_handle: None,
@@ -53,16 +64,21 @@ impl DagDriver {
     }
 
     pub fn add_node(&mut self, node: CertifiedNode) -> anyhow::Result<()> {
-        let mut dag_writer = self.dag.write();
         let round = node.metadata().round();
-        if dag_writer.all_exists(node.parents()) {
-            dag_writer.add_node(node)?;
+        if self.dag.read().all_exists(node.parents()) {
+            // the storage write happens without holding the lock
+            let digest = Dag::insert_shared(&self.dag, node)?.digest();
+            if let Some(e) = self.dag.write().link_inserted().remove(&digest) {
+                return Err(e.into());
+            }
             if self.current_round == round {
-                let maybe_strong_links = dag_writer
-                    .get_strong_links_for_round(self.current_round, &self.epoch_state.verifier);
-                drop(dag_writer);
-                if let Some(strong_links) = maybe_strong_links {
-                    self.enter_new_round(strong_links);
+                let maybe_frontier = self
+                    .dag
+                    .read()
+                    .frontier()
+                    .filter(|frontier| frontier.round == self.current_round);
+                if let Some(frontier) = maybe_frontier {
+                    self.enter_new_round(frontier);
                 }
             }
         }
@@ -70,36 +86,149 @@ impl DagDriver {
         Ok(())
     }
 
-    pub fn enter_new_round(&mut self, strong_links: Vec<NodeCertificate>) {
+    pub fn enter_new_round(&mut self, frontier: Frontier) {
+        // the new node must not get ahead of the nodes still waiting to be persisted
+        if let Err(e) = Dag::flush_writes(&self.dag) {
+            error!("Not proposing in round {}: {}", self.current_round + 1, e);
+            return;
+        }
         // TODO: support pulling payload
         let payload = Payload::empty(false);
-        // TODO: need to wait to pass median of parents timestamp
-        let timestamp = self.time_service.get_current_timestamp();
         self.current_round += 1;
+        let reservation = self
+            .dag
+            .read()
+            .self_reservation(self.current_round)
+            .cloned();
+        if let Some(node) = reservation {
+            // broadcast before a restart, a new node would equivocate
+            self.resume_broadcast(node);
+            return;
+        }
+        let (chain_id, (strong_links, weak_links)) = {
+            let dag_reader = self.dag.read();
+            let num_bytes: usize = frontier
+                .strong_links
+                .iter()
+                .map(NodeCertificate::serialized_size)
+                .sum();
+            let links = if num_bytes <= MAX_STRONG_LINKS_BYTES {
+                (frontier.strong_links, frontier.weak_links)
+            } else {
+                // the weak links of the frontier are against all of its strong links
+                let anchor = self.anchor_election.get_anchor(frontier.round);
+                match dag_reader.get_strong_links_bounded(
+                    frontier.round,
+                    MAX_STRONG_LINKS_BYTES,
+                    &anchor,
+                ) {
+                    Ok(strong_links) => {
+                        let weak_links = dag_reader.weak_links_for(frontier.round, &strong_links);
+                        (strong_links, weak_links)
+                    },
+                    Err(e) => {
+                        warn!(
+                            "Linking all the nodes of round {} in round {}: {}",
+                            frontier.round, self.current_round, e
+                        );
+                        (frontier.strong_links, frontier.weak_links)
+                    },
+                }
+            };
+            (dag_reader.chain_id(), links)
+        };
+        let parents: Vec<_> = strong_links.into_iter().chain(weak_links).collect();
+        // later than every parent even if our clock is behind theirs
+        let timestamp = parents
+            .iter()
+            .map(|parent| parent.metadata().timestamp().saturating_add(1))
+            .fold(
+                self.time_service.get_current_timestamp().as_micros() as u64,
+                u64::max,
+            );
         let new_node = Node::new(
+            chain_id,
             self.epoch_state.epoch,
             self.current_round,
             self.author,
-            timestamp.as_micros() as u64,
+            timestamp,
             payload,
-            strong_links,
+            parents,
         );
         self.broadcast_node(new_node);
     }
 
+    /// Reserves the slot of the node before broadcasting it, a node conflicting with a reservation
+    /// is not broadcast.
     pub fn broadcast_node(&mut self, node: Node) {
+        if let Err(e) = Dag::reserve_self_slot(&self.dag, &node) {
+            error!(
+                "Failed to reserve the slot of node {}: {}",
+                node.digest(),
+                e
+            );
+            return;
+        }
         let rb = self.reliable_broadcast.clone();
-        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let dag = self.dag.clone();
         let signature_builder =
             SignatureBuilder::new(node.metadata().clone(), self.epoch_state.clone());
-        let cert_ack_set = CertificateAckState::new(self.epoch_state.verifier.len());
         let task = self
             .reliable_broadcast
             .broadcast(node.clone(), signature_builder)
             .then(move |certificate| {
-                let certified_node = CertifiedNode::new(node, certificate.signatures().to_owned());
-                rb.broadcast(certified_node, cert_ack_set)
+                let certified_node = CertifiedNode::from_certificate(node, certificate)
+                    .expect("certificate is built for the node");
+                let progress =
+                    BroadcastProgressTracker::new(dag, &certified_node, DEFAULT_FLUSH_INTERVAL);
+                rb.broadcast(certified_node, progress)
             });
+        self.spawn_broadcast(task);
+    }
+
+    /// Resumes the broadcast of the node of the round reserved before a restart. Its certified node
+    /// is sent to the validators that didn't ack it yet, or the node is broadcast again to be
+    /// certified if it wasn't persisted.
+    fn resume_broadcast(&mut self, node: Node) {
+        let digest = node.digest();
+        let existing = self.dag.read().get_node(&digest);
+        let certified_node = match existing {
+            Some(certified_node) => certified_node,
+            None => {
+                info!(
+                    "Broadcasting node {} reserved in round {} again",
+                    digest, self.current_round
+                );
+                self.broadcast_node(node);
+                return;
+            },
+        };
+        let progress = BroadcastProgressTracker::new(
+            self.dag.clone(),
+            &certified_node,
+            DEFAULT_FLUSH_INTERVAL,
+        );
+        let remaining = progress.remaining();
+        if remaining.is_empty() {
+            return;
+        }
+        info!(
+            "Resuming the broadcast of node {} in round {} to {} validators",
+            digest,
+            self.current_round,
+            remaining.len()
+        );
+        let task = self.reliable_broadcast.broadcast_to(
+            certified_node.as_ref().clone(),
+            progress,
+            remaining,
+        );
+        self.spawn_broadcast(task);
+    }
+
+    /// Runs the broadcast in the background, aborting the previous one.
+    fn spawn_broadcast(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
         tokio::spawn(Abortable::new(task, abort_registration));
         if let Some(prev_handle) = self.rb_abort_handle.replace(abort_handle) {
             prev_handle.abort();
@@ -3,25 +3,38 @@
 
 use crate::{
     dag::{
-        dag_network::DAGNetworkSender,
-        dag_store::Dag,
-        types::{CertifiedNode, DAGMessage, FetchResponse, Node, RemoteFetchRequest},
+        batch_verify::verify_nodes_batched,
+        counters,
+        dag_network::{DAGNetworkSender, RpcHandler},
+        dag_store::{Dag, DagStoreError, FilteredStats},
+        types::{
+            AuthorFetchRequest, CertifiedNode, DAGMessage, FetchResponse, Node, RemoteFetchRequest,
+            TDAGMessage,
+        },
     },
     network::TConsensusMsg,
 };
-use aptos_consensus_types::common::Author;
+use anyhow::ensure;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
 use aptos_infallible::RwLock;
-use aptos_logger::error;
-use aptos_types::epoch_state::EpochState;
-use std::{sync::Arc, time::Duration};
+use aptos_logger::{debug, error, warn};
+use aptos_types::{epoch_state::EpochState, validator_verifier::ValidatorVerifier};
+use std::{cmp::Reverse, collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
 use tokio::sync::{
     mpsc::{Receiver, Sender},
     oneshot,
 };
 
+/// A request for the missing parents of a node, answered with the node once they are added or
+/// with the fetched nodes that were rejected.
 pub enum LocalFetchRequest {
-    Node(Node, oneshot::Sender<Node>),
-    CertifiedNode(CertifiedNode, oneshot::Sender<CertifiedNode>),
+    Node(Node, oneshot::Sender<Result<Node, FetchRejected>>),
+    CertifiedNode(
+        CertifiedNode,
+        oneshot::Sender<Result<CertifiedNode, FetchRejected>>,
+    ),
 }
 
 impl LocalFetchRequest {
@@ -36,8 +49,8 @@ impl LocalFetchRequest {
 
     pub fn notify(self) {
         if match self {
-            LocalFetchRequest::Node(node, sender) => sender.send(node).map_err(|_| ()),
-            LocalFetchRequest::CertifiedNode(node, sender) => sender.send(node).map_err(|_| ()),
+            LocalFetchRequest::Node(node, sender) => sender.send(Ok(node)).map_err(|_| ()),
+            LocalFetchRequest::CertifiedNode(node, sender) => sender.send(Ok(node)).map_err(|_| ()),
         }
         .is_err()
         {
@@ -45,6 +58,22 @@ impl LocalFetchRequest {
         }
     }
 
+    /// Tells the requester the parents weren't all added, so it can fetch them again from
+    /// another peer than the ones that sent the rejected nodes.
+    pub fn reject(self, rejected: Vec<FetchedNodeError>) {
+        let rejected = FetchRejected { rejected };
+        if match self {
+            LocalFetchRequest::Node(_, sender) => sender.send(Err(rejected)).map_err(|_| ()),
+            LocalFetchRequest::CertifiedNode(_, sender) => {
+                sender.send(Err(rejected)).map_err(|_| ())
+            },
+        }
+        .is_err()
+        {
+            error!("Failed to send the rejected nodes back");
+        }
+    }
+
     pub fn node(&self) -> &Node {
         match self {
             LocalFetchRequest::Node(node, _) => node,
@@ -53,6 +82,219 @@ impl LocalFetchRequest {
     }
 }
 
+/// Verifies the signatures of the fetched nodes no held certificate vouches for.
+pub trait CertifiedNodeVerifier: Send + Sync {
+    fn verify_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()>;
+
+    /// Verifies the nodes together, the result of each node at its position. One by one unless
+    /// the verifier has a cheaper way.
+    fn verify_certified_nodes(&self, nodes: &[&CertifiedNode]) -> Vec<anyhow::Result<()>> {
+        nodes
+            .iter()
+            .map(|node| self.verify_certified_node(node))
+            .collect()
+    }
+}
+
+impl CertifiedNodeVerifier for ValidatorVerifier {
+    fn verify_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        node.verify(self)
+    }
+
+    fn verify_certified_nodes(&self, nodes: &[&CertifiedNode]) -> Vec<anyhow::Result<()>> {
+        verify_nodes_batched(nodes, self)
+    }
+}
+
+/// Why a fetched node was rejected, with the peer that sent it.
+#[derive(Debug, ThisError)]
+pub enum FetchedNodeError {
+    #[error(
+        "node {digest} of {author} at round {round} from {peer} doesn't match the certified digest {expected}"
+    )]
+    DigestMismatch {
+        peer: Author,
+        round: Round,
+        author: Author,
+        digest: HashValue,
+        expected: HashValue,
+    },
+    #[error("node {digest} from {peer} failed verification: {error}")]
+    Unverified {
+        peer: Author,
+        digest: HashValue,
+        error: String,
+    },
+}
+
+impl FetchedNodeError {
+    pub fn peer(&self) -> &Author {
+        match self {
+            FetchedNodeError::DigestMismatch { peer, .. } => peer,
+            FetchedNodeError::Unverified { peer, .. } => peer,
+        }
+    }
+}
+
+/// The fetched nodes rejected while fetching the parents of a `LocalFetchRequest`.
+#[derive(Debug, ThisError)]
+#[error("{} fetched nodes were rejected", .rejected.len())]
+pub struct FetchRejected {
+    rejected: Vec<FetchedNodeError>,
+}
+
+impl FetchRejected {
+    pub fn rejected(&self) -> &[FetchedNodeError] {
+        &self.rejected
+    }
+}
+
+/// The digests certified for the slots of `nodes` by the certificates the DAG holds, and for the
+/// parents of the node of `request` if it is a certified node. A certified node is verified
+/// before it is fetched for, the parents of a node are only claimed by its author and vouch for
+/// nothing.
+pub fn held_digests(
+    dag: &Dag,
+    request: &LocalFetchRequest,
+    nodes: &[CertifiedNode],
+) -> HashMap<(Round, Author), HashValue> {
+    let mut held: HashMap<_, _> = nodes
+        .iter()
+        .filter_map(|node| {
+            let metadata = node.metadata();
+            dag.expected_digest(metadata)
+                .map(|digest| ((metadata.round(), *metadata.author()), digest))
+        })
+        .collect();
+    if let LocalFetchRequest::CertifiedNode(node, _) = request {
+        for parent in node.parents() {
+            let metadata = parent.metadata();
+            held.entry((metadata.round(), *metadata.author()))
+                .or_insert(*metadata.digest());
+        }
+    }
+    held
+}
+
+/// Checks the nodes fetched from `peer`. A node for a slot in `held`, the digests certified by the
+/// certificates we hold by round and author, is accepted without verifying its signatures if its
+/// recomputed digest is the certified one, and rejected otherwise. The other nodes must pass
+/// `verifier`. Nodes are checked from the highest round down, so an accepted node vouches for
+/// its parents in the response. The nodes left to verify if every node is accepted are verified
+/// together upfront, a node only left by a rejection is verified alone. Returns the accepted
+/// nodes in ascending round order.
+pub fn check_fetched_nodes(
+    mut held: HashMap<(Round, Author), HashValue>,
+    peer: Author,
+    mut nodes: Vec<CertifiedNode>,
+    verifier: &dyn CertifiedNodeVerifier,
+) -> (Vec<CertifiedNode>, Vec<FetchedNodeError>) {
+    nodes.sort_by_key(|node| Reverse(node.metadata().round()));
+    let mut vouched = held.clone();
+    let mut unvouched = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let metadata = node.metadata();
+        match vouched.get(&(metadata.round(), *metadata.author())) {
+            Some(expected) if node.has_valid_digest() && node.digest() == *expected => {},
+            Some(_) => continue,
+            None => unvouched.push(index),
+        }
+        for parent in node.parents() {
+            let metadata = parent.metadata();
+            vouched
+                .entry((metadata.round(), *metadata.author()))
+                .or_insert(*metadata.digest());
+        }
+    }
+    let batch: Vec<_> = unvouched.iter().map(|index| &nodes[*index]).collect();
+    let mut verified: HashMap<_, _> = unvouched
+        .iter()
+        .copied()
+        .zip(verifier.verify_certified_nodes(&batch))
+        .collect();
+
+    let mut accepted = vec![];
+    let mut rejected = vec![];
+    for (index, node) in nodes.into_iter().enumerate() {
+        let metadata = node.metadata();
+        let outcome = match held.get(&(metadata.round(), *metadata.author())) {
+            Some(expected) if node.has_valid_digest() && node.digest() == *expected => {
+                Ok("matched")
+            },
+            Some(expected) => Err(FetchedNodeError::DigestMismatch {
+                peer,
+                round: metadata.round(),
+                author: *metadata.author(),
+                digest: node.digest(),
+                expected: *expected,
+            }),
+            None => verified
+                .remove(&index)
+                .unwrap_or_else(|| verifier.verify_certified_node(&node))
+                .map(|_| "verified")
+                .map_err(|e| FetchedNodeError::Unverified {
+                    peer,
+                    digest: node.digest(),
+                    error: e.to_string(),
+                }),
+        };
+        match outcome {
+            Ok(outcome) => {
+                counters::FETCHED_NODES_CHECKED_COUNT
+                    .with_label_values(&[outcome])
+                    .inc();
+                for parent in node.parents() {
+                    let metadata = parent.metadata();
+                    held.entry((metadata.round(), *metadata.author()))
+                        .or_insert(*metadata.digest());
+                }
+                accepted.push(node);
+            },
+            Err(e) => {
+                counters::FETCHED_NODES_CHECKED_COUNT
+                    .with_label_values(&["rejected"])
+                    .inc();
+                rejected.push(e);
+            },
+        }
+    }
+    accepted.reverse();
+    (accepted, rejected)
+}
+
+/// Drops the nodes of the response that are stale by the time it arrives, before verifying the
+/// signatures of the others.
+pub fn filter_fetched_response(dag: &Dag, response: FetchResponse) -> FetchResponse {
+    let epoch = response.epoch();
+    let mut stats = FilteredStats::default();
+    let nodes = response
+        .certified_nodes()
+        .into_iter()
+        .map(|round_nodes| {
+            let (relevant, round_stats) = dag.filter_relevant(round_nodes);
+            stats.merge(&round_stats);
+            relevant
+        })
+        .collect();
+    if stats.num_dropped() > 0 {
+        debug!(
+            "Dropped {} stale fetched nodes: {:?}",
+            stats.num_dropped(),
+            stats
+        );
+        for (reason, count) in [
+            ("wrong_epoch", stats.wrong_epoch),
+            ("below_floor", stats.below_floor),
+            ("duplicate", stats.duplicate),
+        ] {
+            counters::FETCHED_NODES_DROPPED_COUNT
+                .with_label_values(&[reason])
+                .inc_by(count as u64);
+        }
+    }
+    FetchResponse::new(epoch, nodes)
+}
+
 struct DagFetcher {
     epoch_state: Arc<EpochState>,
     network: Arc<dyn DAGNetworkSender>,
@@ -90,30 +332,234 @@ impl DagFetcher {
                 }
                 RemoteFetchRequest::new(
                     local_request.node().metadata().clone(),
-                    dag_reader.lowest_round(),
-                    dag_reader.bitmask(),
+                    dag_reader.exists_mask(),
+                    dag_reader.pending_mask(),
                 )
+                .with_omit_known_parent_certs(true)
             };
-            let network_request = DAGMessage::from(remote_request.clone()).into_network_message();
-            if let Ok(response) = self
-                .network
-                .send_rpc_with_fallbacks(responders, network_request, Duration::from_secs(1))
+            if let Ok((peer, response)) = self
+                .send_remote_request(&remote_request, responders)
                 .await
-                .and_then(DAGMessage::try_from)
-                .and_then(FetchResponse::try_from)
-                .and_then(|response| response.verify(&remote_request, &self.epoch_state.verifier))
+                .and_then(|(peer, response)| {
+                    ensure!(response.epoch() == self.epoch_state.epoch, "epoch mismatch");
+                    Ok((peer, self.filter_response(response)))
+                })
             {
                 // TODO: support chunk response or fallback to state sync
+                let nodes: Vec<_> = response.certified_nodes().into_iter().flatten().collect();
+                let held = held_digests(&self.dag.read(), &local_request, &nodes);
+                let (accepted, rejected) =
+                    check_fetched_nodes(held, peer, nodes, &self.epoch_state.verifier);
+                for e in &rejected {
+                    warn!("Rejected fetched node: {}", e);
+                }
                 let mut dag_writer = self.dag.write();
-                for rounds in response.certified_nodes() {
-                    for node in rounds {
-                        if let Err(e) = dag_writer.add_node(node) {
-                            error!("Failed to add node {}", e);
-                        }
+                for node in accepted {
+                    match dag_writer.add_validated_node(node) {
+                        Err(e @ DagStoreError::ParentMetadataMismatch { .. }) => {
+                            warn!("Rejected node fetched from {}: {}", peer, e)
+                        },
+                        Err(e) => error!("Failed to add node {}", e),
+                        Ok(()) => {},
                     }
                 }
-                local_request.notify();
+                drop(dag_writer);
+                if rejected.is_empty() {
+                    local_request.notify();
+                } else {
+                    local_request.reject(rejected);
+                }
+            }
+        }
+    }
+
+    /// Sends the request and expands a compact response. If the compact nodes can't be rebuilt
+    /// from the local DAG, the request is sent again with the parent certificates. Returns the
+    /// peer that answered with its response.
+    async fn send_remote_request(
+        &self,
+        request: &RemoteFetchRequest,
+        responders: Vec<Author>,
+    ) -> anyhow::Result<(Author, FetchResponse)> {
+        let (peer, response) = self.send_rpc(request, responders.clone()).await?;
+        if !response.is_compact() {
+            return Ok((peer, response));
+        }
+        let expanded = {
+            let dag_reader = self.dag.read();
+            let reader = dag_reader.read();
+            response.expand(|digest| {
+                reader
+                    .get_node_by_digest(digest)
+                    .map(CertifiedNode::certificate)
+            })
+        };
+        match expanded {
+            Ok(response) => Ok((peer, response)),
+            Err(e) => {
+                debug!(
+                    "Failed to expand the compact fetch response: {}, fetching the parents",
+                    e
+                );
+                let request = request.clone().with_omit_known_parent_certs(false);
+                self.send_rpc(&request, responders).await
+            },
+        }
+    }
+
+    async fn send_rpc(
+        &self,
+        request: &RemoteFetchRequest,
+        responders: Vec<Author>,
+    ) -> anyhow::Result<(Author, FetchResponse)> {
+        let network_request = DAGMessage::from(request.clone()).into_network_message();
+        let (peer, response) = self
+            .network
+            .send_rpc_with_fallbacks(responders, network_request, Duration::from_secs(1))
+            .await?;
+        let response = FetchResponse::try_from(DAGMessage::try_from(response)?)?;
+        Ok((peer, response))
+    }
+
+    /// Fetches the nodes of `author` missing from the window from the other validators.
+    pub async fn fetch_author_nodes(&self, author: Author) -> anyhow::Result<()> {
+        let request = {
+            let missing_rounds = self.dag.read().missing_slots_for_author(&author);
+            match (missing_rounds.first(), missing_rounds.last()) {
+                (Some(start_round), Some(end_round)) => AuthorFetchRequest::new(
+                    self.epoch_state.epoch,
+                    author,
+                    *start_round,
+                    *end_round,
+                ),
+                _ => return Ok(()),
+            }
+        };
+        let responders = self
+            .epoch_state
+            .verifier
+            .get_ordered_account_addresses()
+            .into_iter()
+            .filter(|validator| *validator != author)
+            .collect();
+        let nodes = self.send_author_request(request, responders).await?;
+        self.add_fetched_nodes(nodes);
+        Ok(())
+    }
+
+    /// Fetches what's missing to connect `target` following its fetch plan, with requests of at
+    /// most `max_rounds` rounds. Requests go to the target's signers, and the nodes are added
+    /// once all the requests are answered so they can connect to each other.
+    pub async fn fetch_for_target(
+        &self,
+        target: &CertifiedNode,
+        max_rounds: Round,
+    ) -> anyhow::Result<()> {
+        let plan = self.dag.read().fetch_plan_for(target);
+        let responders = target
+            .signatures()
+            .get_signers_addresses(&self.epoch_state.verifier.get_ordered_account_addresses());
+        let mut nodes = vec![];
+        for request in plan.author_requests(self.epoch_state.epoch, max_rounds) {
+            nodes.extend(
+                self.send_author_request(request, responders.clone())
+                    .await?,
+            );
+        }
+        self.add_fetched_nodes(nodes);
+        Ok(())
+    }
+
+    async fn send_author_request(
+        &self,
+        request: AuthorFetchRequest,
+        responders: Vec<Author>,
+    ) -> anyhow::Result<Vec<CertifiedNode>> {
+        let network_request = DAGMessage::from(request.clone()).into_network_message();
+        let response = self
+            .network
+            .send_rpc_with_fallbacks(responders, network_request, Duration::from_secs(1))
+            .await
+            .and_then(|(_, response)| DAGMessage::try_from(response))
+            .and_then(FetchResponse::try_from)
+            .map(|response| self.filter_response(response))
+            .and_then(|response| {
+                response.verify_author_nodes(&request, &self.epoch_state.verifier)
+            })?;
+        Ok(response.certified_nodes().into_iter().flatten().collect())
+    }
+
+    fn filter_response(&self, response: FetchResponse) -> FetchResponse {
+        filter_fetched_response(&self.dag.read(), response)
+    }
+
+    fn add_fetched_nodes(&self, nodes: Vec<CertifiedNode>) {
+        let mut dag_writer = self.dag.write();
+        let nodes = nodes
+            .into_iter()
+            .filter(|node| !dag_writer.exists(&node.digest()))
+            .collect();
+        for result in dag_writer.add_nodes(nodes) {
+            if let Err(e) = result {
+                error!("Failed to add node {}", e);
             }
         }
     }
 }
+
+/// Serves `RemoteFetchRequest`s from the local DAG.
+pub struct RemoteFetchHandler {
+    dag: Arc<RwLock<Dag>>,
+    epoch: u64,
+}
+
+impl RemoteFetchHandler {
+    pub fn new(dag: Arc<RwLock<Dag>>, epoch: u64) -> Self {
+        Self { dag, epoch }
+    }
+}
+
+impl RpcHandler for RemoteFetchHandler {
+    type Request = RemoteFetchRequest;
+    type Response = FetchResponse;
+
+    fn process(&mut self, request: Self::Request) -> anyhow::Result<Self::Response> {
+        let dag_reader = self.dag.read();
+        if request.omit_known_parent_certs() {
+            let nodes = dag_reader.get_missing_compact_nodes(&request);
+            return Ok(FetchResponse::new_compact(self.epoch, nodes));
+        }
+        Ok(FetchResponse::new(
+            self.epoch,
+            dag_reader.get_missing_nodes(&request),
+        ))
+    }
+}
+
+/// Serves `AuthorFetchRequest`s from the local DAG.
+pub struct AuthorFetchHandler {
+    dag: Arc<RwLock<Dag>>,
+    epoch: u64,
+}
+
+impl AuthorFetchHandler {
+    pub fn new(dag: Arc<RwLock<Dag>>, epoch: u64) -> Self {
+        Self { dag, epoch }
+    }
+}
+
+impl RpcHandler for AuthorFetchHandler {
+    type Request = AuthorFetchRequest;
+    type Response = FetchResponse;
+
+    fn process(&mut self, request: Self::Request) -> anyhow::Result<Self::Response> {
+        let nodes = self
+            .dag
+            .read()
+            .get_author_nodes_in_range(request.author(), request.rounds())
+            .into_iter()
+            .map(|node| vec![node.as_ref().clone()])
+            .collect();
+        Ok(FetchResponse::new(self.epoch, nodes))
+    }
+}
@@ -1,28 +1,50 @@
 // Copyright © Aptos Foundation
 
-use super::{reliable_broadcast::CertifiedNodeHandler, types::TDAGMessage};
+use super::{
+    reliable_broadcast::{CertifiedNodeHandler, CertifiedNodeResponse},
+    types::TDAGMessage,
+};
 use crate::{
     dag::{
-        dag_network::RpcHandler, dag_store::Dag, reliable_broadcast::NodeBroadcastHandler,
+        catch_up::{DagCatchUp, DagCatchUpConfig},
+        counters,
+        dag_fetcher::{AuthorFetchHandler, RemoteFetchHandler},
+        dag_network::{DAGNetworkSender, RpcHandler},
+        dag_store::Dag,
+        fetch_budget::{FetchBudget, FetchBudgetConfig},
+        peer_tracker::{DagPeerTracker, DEFAULT_BITMASK_MAX_AGE},
+        reliable_broadcast::NodeBroadcastHandler,
         types::DAGMessage,
+        write_retry::DEFAULT_WRITE_RETRY_BACKOFF,
     },
     network::{IncomingDAGRequest, TConsensusMsg},
+    util::time_service::TimeService,
 };
 use anyhow::bail;
 use aptos_channels::aptos_channel;
 use aptos_consensus_types::common::Author;
-use aptos_infallible::RwLock;
+use aptos_infallible::{Mutex, RwLock};
 use aptos_logger::{error, warn};
 use aptos_network::protocols::network::RpcError;
 use aptos_types::{epoch_state::EpochState, validator_signer::ValidatorSigner};
 use bytes::Bytes;
-use futures::StreamExt;
+use futures::{
+    future::{AbortHandle, Abortable},
+    StreamExt,
+};
 use std::sync::Arc;
+use tracing::debug_span;
 
 struct NetworkHandler {
+    dag: Arc<RwLock<Dag>>,
     dag_rpc_rx: aptos_channel::Receiver<Author, IncomingDAGRequest>,
     node_receiver: NodeBroadcastHandler,
     certified_node_receiver: CertifiedNodeHandler,
+    author_fetch_receiver: AuthorFetchHandler,
+    remote_fetch_receiver: RemoteFetchHandler,
+    /// Fetches for the nodes the certified node handler rejects for being too far ahead, taken
+    /// by `start`
+    catch_up: Option<DagCatchUp>,
     epoch_state: Arc<EpochState>,
 }
 
@@ -32,35 +54,104 @@ impl NetworkHandler {
         dag_rpc_rx: aptos_channel::Receiver<Author, IncomingDAGRequest>,
         signer: ValidatorSigner,
         epoch_state: Arc<EpochState>,
+        network: Arc<dyn DAGNetworkSender>,
+        time_service: Arc<dyn TimeService>,
     ) -> Self {
+        let (validator_index, budget) = {
+            let dag_reader = dag.read();
+            (
+                dag_reader.validator_index().clone(),
+                FetchBudget::from_dag(&dag_reader, FetchBudgetConfig::default()),
+            )
+        };
+        let peer_tracker = DagPeerTracker::new(
+            validator_index,
+            time_service.clone(),
+            DEFAULT_BITMASK_MAX_AGE,
+        );
+        let (catch_up, catch_up_tx) = DagCatchUp::new(
+            dag.clone(),
+            network,
+            Arc::new(epoch_state.verifier.clone()),
+            Arc::new(Mutex::new(peer_tracker)),
+            budget,
+            time_service,
+            DagCatchUpConfig::default(),
+        );
         Self {
+            dag: dag.clone(),
             dag_rpc_rx,
             node_receiver: NodeBroadcastHandler::new(
                 dag.clone(),
                 signer,
                 epoch_state.verifier.clone(),
             ),
-            certified_node_receiver: CertifiedNodeHandler::new(dag),
+            certified_node_receiver: CertifiedNodeHandler::new(dag.clone())
+                .with_deferred_acks()
+                .with_catch_up(catch_up_tx),
+            author_fetch_receiver: AuthorFetchHandler::new(dag.clone(), epoch_state.epoch),
+            remote_fetch_receiver: RemoteFetchHandler::new(dag, epoch_state.epoch),
+            catch_up: Some(catch_up),
             epoch_state,
         }
     }
 
     async fn start(mut self) {
+        // the node writes queued by the inserts are retried in the background while it runs, and
+        // the gap left by the nodes too far ahead is fetched
+        let (write_retries, abort_registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(
+            Dag::run_write_retries(self.dag.clone(), DEFAULT_WRITE_RETRY_BACKOFF),
+            abort_registration,
+        ));
+        let (catch_up, abort_registration) = AbortHandle::new_pair();
+        if let Some(task) = self.catch_up.take() {
+            tokio::spawn(Abortable::new(task.run(), abort_registration));
+        }
         while let Some(msg) = self.dag_rpc_rx.next().await {
             if let Err(e) = self.process_rpc(msg).await {
                 warn!(error = ?e, "error processing rpc");
             }
         }
+        write_retries.abort();
+        catch_up.abort();
     }
 
     async fn process_rpc(&mut self, rpc_request: IncomingDAGRequest) -> anyhow::Result<()> {
         let dag_message: DAGMessage = rpc_request.req.try_into()?;
 
-        let author = dag_message
-            .author()
-            .map_err(|_| anyhow::anyhow!("unexpected rpc message {:?}", dag_message))?;
-        if author != rpc_request.sender {
-            bail!("message author and network author mismatch");
+        // slow peers redeliver the nodes of pruned rounds, they're rejected before any verification
+        let pruned = match &dag_message {
+            DAGMessage::NodeMsg(node) => Some(("node", node.metadata())),
+            DAGMessage::CertifiedNodeMsg(node) => Some(("certified_node", node.metadata())),
+            _ => None,
+        }
+        .filter(|(_, metadata)| self.dag.read().is_pruned_redelivery(metadata));
+        if let Some((message, metadata)) = pruned {
+            counters::PRUNED_REDELIVERY_DROP_COUNT
+                .with_label_values(&[message])
+                .inc();
+            return rpc_request
+                .response_sender
+                .send(Err(RpcError::ApplicationError(anyhow::anyhow!(
+                    "node {} of round {} is pruned",
+                    metadata.digest(),
+                    metadata.round()
+                ))))
+                .map_err(|_| anyhow::anyhow!("unable to respond to rpc"));
+        }
+
+        // fetch requests are served to any validator, the other messages come from their author
+        if !matches!(
+            dag_message,
+            DAGMessage::AuthorFetchRequest(_) | DAGMessage::FetchRequest(_)
+        ) {
+            let author = dag_message
+                .author()
+                .map_err(|_| anyhow::anyhow!("unexpected rpc message {:?}", dag_message))?;
+            if author != rpc_request.sender {
+                bail!("message author and network author mismatch");
+            }
         }
 
         // TODO: verify epoch number and author
@@ -70,9 +161,49 @@ impl NetworkHandler {
                 .verify(&self.epoch_state.verifier)
                 .and_then(|_| self.node_receiver.process(node))
                 .map(|r| r.into()),
-            DAGMessage::CertifiedNodeMsg(node) => node
+            DAGMessage::CertifiedNodeMsg(node) => match self
+                .certified_node_receiver
+                .pre_validate(&node)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    debug_span!(
+                        "dag::verify_certified_node",
+                        round = node.metadata().round()
+                    )
+                    .in_scope(|| node.verify(&self.epoch_state.verifier))
+                })
+                .and_then(|_| self.certified_node_receiver.process_deferred(node))
+            {
+                Ok(CertifiedNodeResponse::Ack(ack)) => Ok(ack.into()),
+                Ok(CertifiedNodeResponse::Deferred(ack_rx)) => {
+                    // answered once the parents arrive, the next messages don't wait for it
+                    let protocol = rpc_request.protocol;
+                    let response_sender = rpc_request.response_sender;
+                    tokio::spawn(async move {
+                        let response = match ack_rx.await {
+                            Ok(ack) => protocol
+                                .to_bytes(&DAGMessage::from(ack).into_network_message())
+                                .map(Bytes::from)
+                                .map_err(RpcError::ApplicationError),
+                            Err(_) => Err(RpcError::ApplicationError(anyhow::anyhow!(
+                                "node evicted before its parents arrived"
+                            ))),
+                        };
+                        if response_sender.send(response).is_err() {
+                            warn!("unable to respond to rpc");
+                        }
+                    });
+                    return Ok(());
+                },
+                Err(e) => Err(e),
+            },
+            DAGMessage::AuthorFetchRequest(request) => request
+                .verify(&self.epoch_state.verifier)
+                .and_then(|_| self.author_fetch_receiver.process(request))
+                .map(|r| r.into()),
+            DAGMessage::FetchRequest(request) => request
                 .verify(&self.epoch_state.verifier)
-                .and_then(|_| self.certified_node_receiver.process(node))
+                .and_then(|_| self.remote_fetch_receiver.process(request))
                 .map(|r| r.into()),
             _ => {
                 error!("unknown rpc message {:?}", dag_message);
@@ -0,0 +1,114 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        counters,
+        dag_store::{DagHealth, DagHealthConfig},
+        epoch_dag_manager::EpochDagManager,
+    },
+    util::time_service::TimeService,
+};
+use aptos_logger::{info, warn};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// A change of the verdict, as logged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DagHealthTransition {
+    pub epoch: u64,
+    pub from: DagHealth,
+    pub to: DagHealth,
+    pub at: Duration,
+}
+
+/// Checks the health of the DAG of the current epoch and publishes the verdict to the readiness
+/// probe of the node, which subscribes instead of interpreting the DAG gauges. Every change of the
+/// verdict is logged and counted.
+pub struct DagHealthMonitor {
+    manager: Arc<EpochDagManager>,
+    time_service: Arc<dyn TimeService>,
+    config: DagHealthConfig,
+    verdict: watch::Sender<DagHealth>,
+}
+
+impl DagHealthMonitor {
+    pub fn new(
+        manager: Arc<EpochDagManager>,
+        time_service: Arc<dyn TimeService>,
+        config: DagHealthConfig,
+    ) -> Self {
+        counters::DAG_HEALTH.set(0);
+        Self {
+            manager,
+            time_service,
+            config,
+            verdict: watch::channel(DagHealth::Healthy).0,
+        }
+    }
+
+    /// The verdict of the last check, healthy before the first one.
+    pub fn verdict(&self) -> DagHealth {
+        self.verdict.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<DagHealth> {
+        self.verdict.subscribe()
+    }
+
+    /// Checks the DAG again and returns the transition if the verdict or the kinds of its issues
+    /// changed. Otherwise only the details of the published verdict are refreshed, without
+    /// notifying the subscribers.
+    pub fn check(&self) -> Option<DagHealthTransition> {
+        let now = self.time_service.get_current_timestamp();
+        let (epoch, verdict) = {
+            let dag = self.manager.current();
+            let dag_reader = dag.read();
+            (
+                dag_reader.epoch_state().epoch,
+                dag_reader.health(now, &self.config),
+            )
+        };
+        if self.verdict.borrow().same_kind(&verdict) {
+            self.verdict.send_if_modified(|current| {
+                *current = verdict;
+                false
+            });
+            return None;
+        }
+        counters::DAG_HEALTH.set(match verdict {
+            DagHealth::Healthy => 0,
+            DagHealth::Degraded(_) => 1,
+            DagHealth::Stalled(_) => 2,
+        });
+        counters::DAG_HEALTH_TRANSITION_COUNT
+            .with_label_values(&[verdict.name()])
+            .inc();
+        let transition = DagHealthTransition {
+            epoch,
+            from: self.verdict.send_replace(verdict.clone()),
+            to: verdict,
+            at: now,
+        };
+        if transition.to.is_healthy() {
+            info!(
+                "DAG of epoch {} is healthy again, was {:?}",
+                epoch, transition.from
+            );
+        } else {
+            warn!(
+                "DAG health of epoch {} changed from {:?} to {:?}",
+                epoch, transition.from, transition.to
+            );
+        }
+        Some(transition)
+    }
+
+    /// Checks the DAG every `interval` until the monitor is dropped.
+    pub async fn run(self, interval: Duration) {
+        loop {
+            self.check();
+            self.time_service.sleep(interval).await;
+        }
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{DagStoreError, DEFAULT_EPOCH_START_ROUND},
+    storage::DAGStorage,
+    types::{CertifiedNode, OrderedAnchor},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_types::chain_id::ChainId;
+use serde::Serialize;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+/// Where a stored node stands, from the records of the store alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoredNodeStatus {
+    Unordered,
+    /// In the batch of an ordered anchor
+    Ordered,
+    /// Waiting for its parents
+    Pending,
+    /// Pruned, its deletion is to be retried
+    QueuedForDeletion,
+}
+
+impl fmt::Display for StoredNodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            StoredNodeStatus::Unordered => "unordered",
+            StoredNodeStatus::Ordered => "ordered",
+            StoredNodeStatus::Pending => "pending",
+            StoredNodeStatus::QueuedForDeletion => "queued for deletion",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// The certified nodes of an epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct EpochSpan {
+    pub epoch: u64,
+    pub lowest_round: Round,
+    pub highest_round: Round,
+    pub num_nodes: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct EpochStartRound {
+    pub epoch: u64,
+    pub round: Round,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagStoreSummary {
+    /// In ascending epoch order
+    pub epochs: Vec<EpochSpan>,
+    pub num_pending_nodes: usize,
+    pub num_ordered_anchors: usize,
+    pub num_queued_deletions: usize,
+    /// The round the latest epoch started at
+    pub epoch_start_round: Option<EpochStartRound>,
+}
+
+impl fmt::Display for DagStoreSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.epochs.is_empty() {
+            writeln!(f, "no certified nodes")?;
+        }
+        for span in &self.epochs {
+            writeln!(
+                f,
+                "epoch {}: rounds {} to {}, {} nodes",
+                span.epoch, span.lowest_round, span.highest_round, span.num_nodes
+            )?;
+        }
+        writeln!(f, "pending nodes: {}", self.num_pending_nodes)?;
+        writeln!(f, "ordered anchors: {}", self.num_ordered_anchors)?;
+        writeln!(f, "queued deletions: {}", self.num_queued_deletions)?;
+        match &self.epoch_start_round {
+            Some(start) => writeln!(
+                f,
+                "epoch start round: {} in epoch {}",
+                start.round, start.epoch
+            ),
+            None => writeln!(f, "epoch start round: none"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RoundEntry {
+    pub author: Author,
+    pub digest: HashValue,
+    pub status: StoredNodeStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagRoundReport {
+    pub epoch: u64,
+    pub round: Round,
+    /// By author, equivocating nodes by digest
+    pub nodes: Vec<RoundEntry>,
+}
+
+impl fmt::Display for DagRoundReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "epoch {}, round {}: {} nodes",
+            self.epoch,
+            self.round,
+            self.nodes.len()
+        )?;
+        for entry in &self.nodes {
+            writeln!(f, "{} {:x} {}", entry.author, entry.digest, entry.status)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ParentEntry {
+    pub round: Round,
+    pub author: Author,
+    pub digest: HashValue,
+    /// Whether the store has the certified parent
+    pub in_store: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagNodeReport {
+    pub digest: HashValue,
+    pub chain_id: ChainId,
+    pub epoch: u64,
+    pub round: Round,
+    pub author: Author,
+    pub timestamp: u64,
+    pub status: StoredNodeStatus,
+    pub num_transactions: usize,
+    pub payload_bytes: usize,
+    pub num_signers: usize,
+    /// In the order of the node
+    pub parents: Vec<ParentEntry>,
+}
+
+impl fmt::Display for DagNodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "node {:x}", self.digest)?;
+        writeln!(f, "chain id: {}", self.chain_id)?;
+        writeln!(f, "epoch: {}", self.epoch)?;
+        writeln!(f, "round: {}", self.round)?;
+        writeln!(f, "author: {}", self.author)?;
+        writeln!(f, "timestamp: {}", self.timestamp)?;
+        writeln!(f, "status: {}", self.status)?;
+        writeln!(
+            f,
+            "payload: {} transactions, {} bytes",
+            self.num_transactions, self.payload_bytes
+        )?;
+        writeln!(f, "signers: {}", self.num_signers)?;
+        writeln!(f, "parents: {}", self.parents.len())?;
+        for parent in &self.parents {
+            write!(
+                f,
+                "  round {} {} {:x}",
+                parent.round, parent.author, parent.digest
+            )?;
+            if parent.in_store {
+                writeln!(f)?;
+            } else {
+                writeln!(f, " (not in the store)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A record of the store failing the validation, with the slot and digest of the record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagProblem {
+    pub epoch: u64,
+    pub round: Round,
+    pub author: Author,
+    pub digest: HashValue,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagCheckReport {
+    /// Certified and pending nodes
+    pub num_checked_nodes: usize,
+    pub problems: Vec<DagProblem>,
+}
+
+impl DagCheckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for DagCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_consistent() {
+            return writeln!(f, "checked {} nodes, no problems", self.num_checked_nodes);
+        }
+        writeln!(
+            f,
+            "checked {} nodes, {} problems",
+            self.num_checked_nodes,
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            writeln!(
+                f,
+                "epoch {} round {} {} {:x}: {}",
+                problem.epoch, problem.round, problem.author, problem.digest, problem.description
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of the DAG records of a store, for offline debugging. Unlike the recovery of
+/// `Dag` it keeps every record as is, whatever its epoch or consistency, and never writes.
+pub struct DagInspector {
+    certified_nodes: HashMap<HashValue, CertifiedNode>,
+    pending_nodes: HashMap<HashValue, CertifiedNode>,
+    ordered_anchors: HashMap<HashValue, OrderedAnchor>,
+    pending_deletions: HashMap<HashValue, u32>,
+    epoch_start_round: Option<(u64, Round)>,
+    /// Digests of the anchors and the nodes of their batches
+    ordered: HashSet<HashValue>,
+}
+
+impl DagInspector {
+    /// Reads the DAG records of the store, only the getters of `storage` are called.
+    pub fn load(storage: &dyn DAGStorage) -> anyhow::Result<Self> {
+        let ordered_anchors = storage.get_ordered_anchors()?;
+        let ordered = ordered_anchors
+            .iter()
+            .flat_map(|(digest, ordered_anchor)| {
+                ordered_anchor
+                    .sources()
+                    .iter()
+                    .map(|source| *source.digest())
+                    .chain(std::iter::once(*digest))
+            })
+            .collect();
+        Ok(Self {
+            certified_nodes: storage.get_certified_nodes()?,
+            pending_nodes: storage.get_pending_nodes()?,
+            ordered_anchors,
+            pending_deletions: storage.get_pending_deletions()?,
+            epoch_start_round: storage.get_epoch_start_round()?,
+            ordered,
+        })
+    }
+
+    pub fn summary(&self) -> DagStoreSummary {
+        let mut epochs: BTreeMap<u64, EpochSpan> = BTreeMap::new();
+        for node in self.certified_nodes.values() {
+            let metadata = node.metadata();
+            let round = metadata.round();
+            let span = epochs.entry(metadata.epoch()).or_insert(EpochSpan {
+                epoch: metadata.epoch(),
+                lowest_round: round,
+                highest_round: round,
+                num_nodes: 0,
+            });
+            span.lowest_round = span.lowest_round.min(round);
+            span.highest_round = span.highest_round.max(round);
+            span.num_nodes += 1;
+        }
+        DagStoreSummary {
+            epochs: epochs.into_values().collect(),
+            num_pending_nodes: self.pending_nodes.len(),
+            num_ordered_anchors: self.ordered_anchors.len(),
+            num_queued_deletions: self.pending_deletions.len(),
+            epoch_start_round: self
+                .epoch_start_round
+                .map(|(epoch, round)| EpochStartRound { epoch, round }),
+        }
+    }
+
+    /// The highest epoch of the stored nodes.
+    pub fn latest_epoch(&self) -> Option<u64> {
+        self.certified_nodes
+            .values()
+            .chain(self.pending_nodes.values())
+            .map(|node| node.metadata().epoch())
+            .max()
+    }
+
+    /// The certified and pending nodes of `round` in `epoch`.
+    pub fn round(&self, epoch: u64, round: Round) -> DagRoundReport {
+        let nodes = self
+            .stored_nodes()
+            .into_iter()
+            .filter(|(_, node, _)| {
+                node.metadata().epoch() == epoch && node.metadata().round() == round
+            })
+            .map(|(_, node, status)| RoundEntry {
+                author: *node.author(),
+                digest: node.digest(),
+                status,
+            })
+            .collect();
+        DagRoundReport {
+            epoch,
+            round,
+            nodes,
+        }
+    }
+
+    /// The stored node with `digest`, certified or pending.
+    pub fn node(&self, digest: &HashValue) -> Option<DagNodeReport> {
+        let (node, status) = match self.certified_nodes.get(digest) {
+            Some(node) => (node, self.status(digest)),
+            None => (self.pending_nodes.get(digest)?, StoredNodeStatus::Pending),
+        };
+        let metadata = node.metadata();
+        let parents = node
+            .parents()
+            .iter()
+            .map(|parent| ParentEntry {
+                round: parent.metadata().round(),
+                author: *parent.metadata().author(),
+                digest: *parent.metadata().digest(),
+                in_store: self
+                    .certified_nodes
+                    .contains_key(parent.metadata().digest()),
+            })
+            .collect();
+        Some(DagNodeReport {
+            digest: node.digest(),
+            chain_id: metadata.chain_id(),
+            epoch: metadata.epoch(),
+            round: metadata.round(),
+            author: *metadata.author(),
+            timestamp: metadata.timestamp(),
+            status,
+            num_transactions: node.payload().len(),
+            payload_bytes: node.payload().size(),
+            num_signers: node.signatures().get_num_voters(),
+            parents,
+        })
+    }
+
+    /// Runs the digest and linking validation of the DAG on every record. Of two nodes in the same
+    /// slot, the one with the bigger digest is reported, as recovery would drop it. Parents below
+    /// the lowest stored round of their epoch are pruned, not missing.
+    pub fn check(&self) -> DagCheckReport {
+        let mut problems = vec![];
+        let mut lowest_rounds: HashMap<u64, Round> = HashMap::new();
+        for node in self.certified_nodes.values() {
+            let metadata = node.metadata();
+            let lowest_round = lowest_rounds
+                .entry(metadata.epoch())
+                .or_insert(metadata.round());
+            *lowest_round = (*lowest_round).min(metadata.round());
+        }
+        let mut slots = HashMap::new();
+        for (key, node) in sorted_by_slot(&self.certified_nodes) {
+            let metadata = node.metadata();
+            let (epoch, round) = (metadata.epoch(), metadata.round());
+            let mut report = |description: String| {
+                problems.push(DagProblem {
+                    epoch,
+                    round,
+                    author: *metadata.author(),
+                    digest: *key,
+                    description,
+                })
+            };
+            check_digest(key, node, &mut report);
+            match slots.entry((epoch, round, *metadata.author())) {
+                Entry::Occupied(kept) => report(format!("equivocates with {}", kept.get())),
+                Entry::Vacant(slot) => {
+                    slot.insert(*key);
+                },
+            }
+            if node.parents().is_empty() {
+                if let Some(start_round) = self.start_round(epoch) {
+                    if round != start_round {
+                        report(
+                            DagStoreError::EmptyParentsNotAllowed { round, start_round }
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+            for parent in node.parents() {
+                let parent = parent.metadata();
+                let error = if parent.digest() == key {
+                    DagStoreError::SelfParent
+                } else if parent.round() >= round {
+                    DagStoreError::InvalidParentRound {
+                        round,
+                        parent_round: parent.round(),
+                    }
+                } else if parent.epoch() != epoch {
+                    DagStoreError::ParentEpochMismatch {
+                        epoch,
+                        parent_epoch: parent.epoch(),
+                    }
+                } else if !self.certified_nodes.contains_key(parent.digest())
+                    && parent.round() >= lowest_rounds[&epoch]
+                {
+                    DagStoreError::MissingParent(*parent.digest())
+                } else {
+                    continue;
+                };
+                report(error.to_string());
+            }
+        }
+        for (key, node) in sorted_by_slot(&self.pending_nodes) {
+            let metadata = node.metadata();
+            check_digest(key, node, &mut |description| {
+                problems.push(DagProblem {
+                    epoch: metadata.epoch(),
+                    round: metadata.round(),
+                    author: *metadata.author(),
+                    digest: *key,
+                    description,
+                })
+            });
+        }
+        let mut ordered_anchors: Vec<_> = self.ordered_anchors.iter().collect();
+        ordered_anchors.sort_by_key(|(key, ordered_anchor)| {
+            let anchor = ordered_anchor.anchor();
+            (anchor.epoch(), anchor.round(), *anchor.author(), **key)
+        });
+        for (key, ordered_anchor) in ordered_anchors {
+            let anchor = ordered_anchor.anchor();
+            let error = if anchor.digest() != key {
+                DagStoreError::DigestMismatch {
+                    key: *key,
+                    digest: *anchor.digest(),
+                }
+            } else if !self.certified_nodes.contains_key(key) {
+                DagStoreError::MissingAnchor(*key)
+            } else {
+                continue;
+            };
+            problems.push(DagProblem {
+                epoch: anchor.epoch(),
+                round: anchor.round(),
+                author: *anchor.author(),
+                digest: *key,
+                description: error.to_string(),
+            });
+        }
+        DagCheckReport {
+            num_checked_nodes: self.certified_nodes.len() + self.pending_nodes.len(),
+            problems,
+        }
+    }
+
+    /// The certified nodes then the pending ones, each by slot.
+    fn stored_nodes(&self) -> Vec<(&HashValue, &CertifiedNode, StoredNodeStatus)> {
+        sorted_by_slot(&self.certified_nodes)
+            .into_iter()
+            .map(|(key, node)| (key, node, self.status(key)))
+            .chain(
+                sorted_by_slot(&self.pending_nodes)
+                    .into_iter()
+                    .map(|(key, node)| (key, node, StoredNodeStatus::Pending)),
+            )
+            .collect()
+    }
+
+    fn status(&self, digest: &HashValue) -> StoredNodeStatus {
+        if self.pending_deletions.contains_key(digest) {
+            StoredNodeStatus::QueuedForDeletion
+        } else if self.ordered.contains(digest) {
+            StoredNodeStatus::Ordered
+        } else {
+            StoredNodeStatus::Unordered
+        }
+    }
+
+    /// The only round of `epoch` where nodes have no parents, unknown for the epochs before the
+    /// latest one.
+    fn start_round(&self, epoch: u64) -> Option<Round> {
+        match self.epoch_start_round {
+            Some((start_epoch, round)) if start_epoch == epoch => Some(round),
+            Some(_) => None,
+            None => Some(DEFAULT_EPOCH_START_ROUND),
+        }
+    }
+}
+
+fn check_digest(key: &HashValue, node: &CertifiedNode, report: &mut impl FnMut(String)) {
+    if *key != node.digest() {
+        report(
+            DagStoreError::DigestMismatch {
+                key: *key,
+                digest: node.digest(),
+            }
+            .to_string(),
+        );
+    }
+    if !node.has_valid_digest() {
+        report("invalid digest".to_string());
+    }
+}
+
+/// The nodes by epoch, round, author and digest.
+fn sorted_by_slot(nodes: &HashMap<HashValue, CertifiedNode>) -> Vec<(&HashValue, &CertifiedNode)> {
+    let mut nodes: Vec<_> = nodes.iter().collect();
+    nodes.sort_by_key(|(key, node)| {
+        let metadata = node.metadata();
+        (
+            metadata.epoch(),
+            metadata.round(),
+            *metadata.author(),
+            **key,
+        )
+    });
+    nodes
+}
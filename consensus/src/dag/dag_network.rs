@@ -22,11 +22,11 @@ pub trait DAGNetworkSender: Send + Sync {
     ) -> anyhow::Result<ConsensusMsg>;
 
     /// Given a list of potential responders, sending rpc to get response from any of them and could
-    /// fallback to more in case of failures.
+    /// fallback to more in case of failures. Returns the responder with its response.
     async fn send_rpc_with_fallbacks(
         &self,
         responders: Vec<Author>,
         message: ConsensusMsg,
         timeout: Duration,
-    ) -> anyhow::Result<ConsensusMsg>;
+    ) -> anyhow::Result<(Author, ConsensusMsg)>;
 }
@@ -1,146 +1,6361 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::dag::{
-    storage::DAGStorage,
-    types::{CertifiedNode, NodeCertificate},
+//! Trace spans, at debug level so their fields are only evaluated when enabled:
+//! - `dag::add_node` with `round`, `author_index`, `parents` and `payload_bytes`, and its
+//!   children `dag::validate` and `dag::save_certified_node`
+//! - `dag::verify_certified_node` with `round`, emitted by the network handler before insertion
+//! - `dag::order_anchor` with `round` and `num_nodes`
+//! - `dag::prune` with `round` and `num_nodes`
+
+use crate::{
+    dag::{
+        counters,
+        pruned_filter::PrunedDigestFilter,
+        pruning_policy::DagPruningPolicy,
+        replay::{DagReplayLog, ReplayHeader, ReplayLogConfig, ReplayLogStats, REPLAY_LOG_VERSION},
+        slot_id::{SlotId, SlotIdError, ValidatorIndexU32},
+        storage::DAGStorage,
+        store_config::{DagStoreConfig, DagStoreConfigError},
+        types::{
+            AuthorFetchRequest, BatchSourceInfo, BroadcastProgress, CertifiedNode,
+            CompactCertifiedNode, DagSnapshotBitmask, EpochRemnant, EvidenceNodes, EvidenceRecord,
+            Node, NodeCertificate, NodeMetadata, OrderedAnchor, RemoteFetchRequest,
+            SkipCertificate,
+        },
+        validator_index::ValidatorIndex,
+        wire_limits::MAX_FETCH_ROUNDS,
+        write_retry::{QueuedWrite, WriteRetryQueue},
+    },
+    util::time_service::{ScheduledTask, TimeService},
 };
-use anyhow::{anyhow, ensure};
+use anyhow::ensure;
+use aptos_bitvec::BitVec;
 use aptos_consensus_types::common::{Author, Round};
-use aptos_crypto::HashValue;
-use aptos_logger::error;
-use aptos_types::{epoch_state::EpochState, validator_verifier::ValidatorVerifier};
+use aptos_crypto::{hash::CryptoHasher, HashValue};
+use aptos_crypto_derive::CryptoHasher;
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::{error, info, sample, sample::SampleRate, warn};
+use aptos_types::{
+    chain_id::ChainId,
+    epoch_state::EpochState,
+    ledger_info::{LedgerInfo, LedgerInfoWithSignatures},
+    validator_verifier::ValidatorVerifier,
+};
+use async_trait::async_trait;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::future::{AbortHandle, Abortable};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    io,
+    mem::{size_of, size_of_val},
+    ops::{ControlFlow, RangeInclusive},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
+use thiserror::Error as ThisError;
+use tokio::sync::watch;
+use tracing::{debug_span, field};
+
+/// Number of rounds reported when the DAG goes over its memory budget.
+const NUM_TOP_MEMORY_ROUNDS: usize = 3;
+
+/// Number of rounds the causal history of an anchor is expected to span.
+pub const DEFAULT_WINDOW_SIZE: Round = 10;
+
+/// Round of the parentless nodes of an epoch unless set with `Dag::set_epoch_start_round`.
+pub const DEFAULT_EPOCH_START_ROUND: Round = 1;
+
+/// Number of digests deleted together when retrying the queued deletions.
+pub const DELETION_RETRY_CHUNK_SIZE: usize = 100;
+
+/// Number of rounds above the lowest round an observer accepts nodes in by default.
+pub const DEFAULT_OBSERVER_ROUND_SPAN: Round = 10 * DEFAULT_WINDOW_SIZE;
+
+/// Rounds an author can be ahead or behind the median author before it's flagged, unless set with
+/// `Dag::set_skew_threshold`.
+pub const DEFAULT_SKEW_THRESHOLD: Round = DEFAULT_WINDOW_SIZE;
+
+/// Rounds above the highest round within which `insert_node` parks a node until the DAG catches
+/// up, unless set with `Dag::set_park_gap`. Nodes further ahead are rejected for `RoundTooHigh`.
+pub const DEFAULT_PARK_GAP: Round = 2;
+
+/// How much earlier than its latest parent a node can be, for the clock skew between validators,
+/// unless set with `Dag::set_timestamp_skew_tolerance`.
+pub const DEFAULT_TIMESTAMP_SKEW_TOLERANCE: Duration = Duration::from_millis(100);
+
+/// How far ahead of local time the timestamp of a node can be, unless set with
+/// `Dag::set_max_clock_drift`.
+pub const DEFAULT_MAX_CLOCK_DRIFT: Duration = Duration::from_secs(60);
+
+/// Number of failed deletions after which a digest is dropped from the retry queue.
+const MAX_DELETION_ATTEMPTS: u32 = 5;
+
+/// Number of committed nodes whose latencies are kept for `Dag::latency_samples`.
+pub const MAX_LATENCY_SAMPLES: usize = 100;
+
+/// Number of committed anchors in the rolling average of the commit latency.
+pub const COMMIT_LATENCY_WINDOW: usize = 20;
+
+/// `Dag::round_generation` of the pruned rounds, above the generation of any mutation.
+pub const PRUNED_GENERATION: u64 = u64::MAX;
+
+#[derive(Clone)]
+pub enum NodeStatus {
+    Unordered(Arc<CertifiedNode>),
+    Ordered(Arc<CertifiedNode>),
+    /// Ordered and confirmed committed by the ledger, see `Dag::notify_commit_confirmed`
+    Committed(Arc<CertifiedNode>),
+}
+
+impl NodeStatus {
+    pub fn as_node(&self) -> &Arc<CertifiedNode> {
+        match self {
+            NodeStatus::Unordered(node)
+            | NodeStatus::Ordered(node)
+            | NodeStatus::Committed(node) => node,
+        }
+    }
+
+    /// A committed node is ordered too.
+    pub fn is_ordered(&self) -> bool {
+        matches!(self, NodeStatus::Ordered(_) | NodeStatus::Committed(_))
+    }
+
+    pub fn is_committed(&self) -> bool {
+        matches!(self, NodeStatus::Committed(_))
+    }
+
+    pub fn kind(&self) -> NodeStatusKind {
+        match self {
+            NodeStatus::Unordered(_) => NodeStatusKind::Unordered,
+            NodeStatus::Ordered(_) => NodeStatusKind::Ordered,
+            NodeStatus::Committed(_) => NodeStatusKind::Committed,
+        }
+    }
+}
+
+/// `NodeStatus` without the node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeStatusKind {
+    Unordered,
+    Ordered,
+    Committed,
+}
+
+/// What a `Dag::notify_commit_confirmed` changed, all zero for a confirmation already seen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitConfirmation {
+    /// The anchor and the older ones it confirmed with it
+    pub num_anchors: usize,
+    /// Nodes moved from `Ordered` to `Committed`
+    pub num_committed: usize,
+    pub num_pruned: usize,
+}
+
+/// The causal history of an anchor that was not ordered before, in the order it's committed.
+pub struct OrderedBatch {
+    anchor: NodeMetadata,
+    nodes: Vec<Arc<CertifiedNode>>,
+    /// One entry per node, in the same order
+    sources: Vec<BatchSourceInfo>,
+}
+
+impl OrderedBatch {
+    pub fn anchor(&self) -> &NodeMetadata {
+        &self.anchor
+    }
+
+    pub fn nodes(&self) -> &[Arc<CertifiedNode>] {
+        &self.nodes
+    }
+
+    pub fn sources(&self) -> &[BatchSourceInfo] {
+        &self.sources
+    }
+
+    pub fn into_nodes(self) -> Vec<Arc<CertifiedNode>> {
+        self.nodes
+    }
+
+    /// The time of the batch in microseconds, the timestamp of its anchor, so every validator
+    /// gives the batch the same time. Increases along the anchors as long as the skew tolerance
+    /// is smaller than the time between them.
+    pub fn timestamp(&self) -> u64 {
+        self.anchor.timestamp()
+    }
+}
+
+/// A slice of the causal history of an anchor emitted by `Dag::order_anchor_streamed`, the
+/// chunks of an anchor concatenate to its `OrderedBatch`.
+pub struct OrderedChunk {
+    /// Position of the first node in the history of the anchor
+    start: u64,
+    nodes: Vec<Arc<CertifiedNode>>,
+    /// One entry per node, in the same order
+    sources: Vec<BatchSourceInfo>,
+    /// Only set on the last chunk
+    completion: Option<AnchorCompletion>,
+}
+
+impl OrderedChunk {
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn nodes(&self) -> &[Arc<CertifiedNode>] {
+        &self.nodes
+    }
+
+    pub fn sources(&self) -> &[BatchSourceInfo] {
+        &self.sources
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.completion.is_some()
+    }
+
+    pub fn completion(&self) -> Option<&AnchorCompletion> {
+        self.completion.as_ref()
+    }
+
+    pub fn into_nodes(self) -> Vec<Arc<CertifiedNode>> {
+        self.nodes
+    }
+}
+
+/// Carried by the last chunk of an anchor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnchorCompletion {
+    anchor: NodeMetadata,
+    /// The skipped anchors since the previous ordered anchor, by round
+    failed_authors: Vec<(Round, Author)>,
+}
+
+impl AnchorCompletion {
+    pub fn anchor(&self) -> &NodeMetadata {
+        &self.anchor
+    }
+
+    pub fn failed_authors(&self) -> &[(Round, Author)] {
+        &self.failed_authors
+    }
+}
+
+/// What `Dag::retry_writes` did with the queued writes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteRetryReport {
+    /// Persisted and linked in
+    pub finalized: Vec<HashValue>,
+    /// Out of attempts, or no longer valid once persisted, their slots are free
+    pub rejected: Vec<HashValue>,
+    /// Still waiting for a retry
+    pub num_queued: usize,
+}
+
+/// Fetched nodes dropped by `Dag::filter_relevant`, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FilteredStats {
+    pub wrong_epoch: usize,
+    pub below_floor: usize,
+    pub duplicate: usize,
+}
+
+impl FilteredStats {
+    pub fn num_dropped(&self) -> usize {
+        self.wrong_epoch + self.below_floor + self.duplicate
+    }
+
+    pub fn merge(&mut self, other: &FilteredStats) {
+        self.wrong_epoch += other.wrong_epoch;
+        self.below_floor += other.below_floor;
+        self.duplicate += other.duplicate;
+    }
+}
+
+/// A slot of the DAG as hashed by `Dag::content_digest`.
+#[derive(Serialize, Deserialize, CryptoHasher)]
+struct DagSlot {
+    round: Round,
+    author_index: u64,
+    digest: HashValue,
+    /// 0 for unordered, 1 for ordered, 2 for committed
+    status: u8,
+}
+
+/// The slots of a round as hashed by `Dag::round_digest`, `None` marks an empty slot.
+#[derive(Serialize, Deserialize, CryptoHasher)]
+struct RoundSlots {
+    round: Round,
+    digests: Vec<Option<HashValue>>,
+}
+
+/// The first slot, in round and author order, where two DAGs hold different nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DagDiff {
+    Identical,
+    Divergent {
+        round: Round,
+        author_index: usize,
+        local: Option<HashValue>,
+        other: Option<HashValue>,
+    },
+}
+
+/// The content of a DAG stripped of the bookkeeping, two DAGs holding the same nodes in the same
+/// states compare equal however they were built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DagContentView {
+    pub floor: Round,
+    pub slots: BTreeMap<(Round, Author), (HashValue, NodeStatusKind)>,
+    pub pending: BTreeSet<HashValue>,
+    /// Authors excluded for equivocating
+    pub equivocators: BTreeSet<Author>,
+    pub last_committed_anchor: Option<HashValue>,
+}
+
+/// Approximate memory held by the DAG, maintained incrementally as nodes are added and removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DagMemoryUsage {
+    pub num_nodes: usize,
+    pub node_bytes: usize,
+    pub num_pending_nodes: usize,
+    pub pending_bytes: usize,
+}
+
+impl DagMemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.node_bytes + self.pending_bytes
+    }
+}
+
+/// A point-in-time summary of the DAG state for metrics and inspection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DagStateSummary {
+    pub lowest_round: Round,
+    pub highest_round: Round,
+    pub memory_usage: DagMemoryUsage,
+    pub over_memory_budget: bool,
+    pub commit_latency: CommitLatencyAverage,
+}
+
+/// Whether the anchors skipped since the last committed one left the commit to a later anchor,
+/// the label of the commit latency metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnchorAttempt {
+    /// No round was skipped since the last committed anchor
+    First,
+    /// A round since the last committed anchor was skipped
+    Fallback,
+}
+
+impl AnchorAttempt {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnchorAttempt::First => "first",
+            AnchorAttempt::Fallback => "fallback",
+        }
+    }
+}
+
+/// Rounds from an anchor to the round whose links committed it, recorded when the anchor is
+/// ordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnchorCommitLatency {
+    pub anchor_round: Round,
+    pub committing_round: Round,
+    pub attempt: AnchorAttempt,
+}
+
+impl AnchorCommitLatency {
+    pub fn rounds(&self) -> Round {
+        self.committing_round - self.anchor_round
+    }
+}
+
+/// Commit latency of the last `COMMIT_LATENCY_WINDOW` anchors at most. Kept as a sum so the
+/// summaries compare exactly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitLatencyAverage {
+    pub total_rounds: u64,
+    pub num_anchors: usize,
+}
+
+impl CommitLatencyAverage {
+    pub fn average(&self) -> Option<f64> {
+        match self.num_anchors {
+            0 => None,
+            num_anchors => Some(self.total_rounds as f64 / num_anchors as f64),
+        }
+    }
+}
+
+/// Thresholds of `Dag::health`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DagHealthConfig {
+    /// Degraded once the highest quorum round didn't advance for that long
+    pub quorum_slow_after: Duration,
+    /// Stalled once the highest quorum round didn't advance for that long
+    pub quorum_stalled_after: Duration,
+    /// Degraded with at least that many pending nodes
+    pub max_pending_nodes: usize,
+    /// An author is absent once its highest round is more than that below the highest quorum round
+    pub absent_rounds: Round,
+    /// Degraded with more absent authors
+    pub max_absent_authors: usize,
+    /// Degraded once the average commit latency of a full window of anchors is above that
+    pub max_commit_latency_rounds: Round,
+}
+
+impl Default for DagHealthConfig {
+    fn default() -> Self {
+        Self {
+            quorum_slow_after: Duration::from_secs(5),
+            quorum_stalled_after: Duration::from_secs(30),
+            max_pending_nodes: 1000,
+            absent_rounds: 10,
+            max_absent_authors: 0,
+            max_commit_latency_rounds: 4,
+        }
+    }
+}
+
+/// What `Dag::health` found wrong with the DAG.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DagHealthIssue {
+    /// The highest quorum round, 0 before the first one, and the local time it was reached, the
+    /// creation of the DAG before it
+    QuorumRoundStuck {
+        round: Round,
+        since: Duration,
+    },
+    Backpressure,
+    /// Only reported without backpressure, which observers never turn on
+    OverMemoryBudget {
+        total_bytes: usize,
+        memory_budget: usize,
+    },
+    PendingBufferFull {
+        num_pending: usize,
+    },
+    AbsentAuthors {
+        authors: Vec<Author>,
+    },
+    CommitLatencyHigh {
+        latency: CommitLatencyAverage,
+    },
+    /// The progress is left to `Dag::catch_up_progress`, it changes with every wave
+    CatchingUp,
+}
+
+impl DagHealthIssue {
+    /// The issue without its details, which change with every check.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DagHealthIssue::QuorumRoundStuck { .. } => "quorum_round_stuck",
+            DagHealthIssue::Backpressure => "backpressure",
+            DagHealthIssue::OverMemoryBudget { .. } => "over_memory_budget",
+            DagHealthIssue::PendingBufferFull { .. } => "pending_buffer_full",
+            DagHealthIssue::AbsentAuthors { .. } => "absent_authors",
+            DagHealthIssue::CommitLatencyHigh { .. } => "commit_latency_high",
+            DagHealthIssue::CatchingUp => "catching_up",
+        }
+    }
+}
+
+/// How far the catch-up is from closing the gap of the DAG, see `Dag::set_catch_up_progress`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CatchUpProgress {
+    pub slots_remaining: usize,
+    /// From the rate the slots were filled at so far, unknown before the first ones
+    pub eta: Option<Duration>,
+}
+
+/// The verdict of `Dag::health` for the readiness probe of the node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DagHealth {
+    Healthy,
+    Degraded(Vec<DagHealthIssue>),
+    /// The DAG doesn't make progress, whatever else is wrong
+    Stalled(DagHealthIssue),
+}
+
+impl DagHealth {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, DagHealth::Healthy)
+    }
+
+    /// The name of the verdict, also the label of the metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DagHealth::Healthy => "healthy",
+            DagHealth::Degraded(_) => "degraded",
+            DagHealth::Stalled(_) => "stalled",
+        }
+    }
+
+    /// Whether both verdicts are the same with the same kinds of issues, whatever their details.
+    pub fn same_kind(&self, other: &DagHealth) -> bool {
+        let kinds = |health: &DagHealth| -> Vec<&'static str> {
+            match health {
+                DagHealth::Healthy => vec![],
+                DagHealth::Degraded(issues) => issues.iter().map(DagHealthIssue::kind).collect(),
+                DagHealth::Stalled(issue) => vec![issue.kind()],
+            }
+        };
+        self.name() == other.name() && kinds(self) == kinds(other)
+    }
+}
+
+/// Aggregate facts about the DAG of an epoch, produced when the epoch ends for post-mortem
+/// analysis. The totals cover the epoch since the DAG was last recovered, the nodes found in
+/// storage included.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DagEpochSummary {
+    pub epoch: u64,
+    pub num_nodes: u64,
+    /// Rounds from the start round of the epoch to the highest round
+    pub num_rounds: u64,
+    pub nodes_by_author: BTreeMap<Author, u64>,
+    /// Rounds without a node of the author
+    pub missed_rounds_by_author: BTreeMap<Author, u64>,
+    /// Equivocating nodes rejected, or dropped at recovery
+    pub num_equivocations: u64,
+    pub num_ordered_anchors: u64,
+    pub num_skipped_anchors: u64,
+    /// Sum over the ordered anchors of the rounds between the anchor and the highest round of the
+    /// DAG when it was ordered
+    pub total_commit_latency_rounds: u64,
+    pub pruned_bytes: u64,
+}
+
+impl DagEpochSummary {
+    /// Average commit latency of the ordered anchors in rounds. Kept out of the fields since BCS
+    /// doesn't serialize floats.
+    pub fn average_commit_latency(&self) -> Option<f64> {
+        match self.num_ordered_anchors {
+            0 => None,
+            num_ordered_anchors => {
+                Some(self.total_commit_latency_rounds as f64 / num_ordered_anchors as f64)
+            },
+        }
+    }
+}
+
+/// Running totals behind the `DagEpochSummary`, unlike the DAG itself they survive pruning.
+struct EpochTotals {
+    /// Nodes added by validator index
+    nodes_by_author: Vec<u64>,
+    /// Digests of the equivocating nodes rejected by the validation
+    equivocations: Mutex<HashSet<HashValue>>,
+    num_ordered_anchors: u64,
+    num_skipped_anchors: u64,
+    total_commit_latency_rounds: u64,
+    pruned_bytes: u64,
+}
+
+impl EpochTotals {
+    fn new(num_validators: usize) -> Self {
+        Self {
+            nodes_by_author: vec![0; num_validators],
+            equivocations: Mutex::new(HashSet::new()),
+            num_ordered_anchors: 0,
+            num_skipped_anchors: 0,
+            total_commit_latency_rounds: 0,
+            pruned_bytes: 0,
+        }
+    }
+}
+
+/// Real clock used by the DAG when no time service is injected. Unlike `ClockTimeService` it
+/// doesn't need an executor handle at construction, tasks are spawned on the current runtime.
+struct WallClock;
+
+#[async_trait]
+impl TimeService for WallClock {
+    fn run_after(&self, timeout: Duration, mut task: Box<dyn ScheduledTask>) -> AbortHandle {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(
+            async move {
+                tokio::time::sleep(timeout).await;
+                task.run().await;
+            },
+            abort_registration,
+        ));
+        abort_handle
+    }
+
+    fn get_current_timestamp(&self) -> Duration {
+        aptos_infallible::duration_since_epoch()
+    }
+
+    async fn sleep(&self, t: Duration) {
+        tokio::time::sleep(t).await
+    }
+}
+
+/// Snapshot of the DAG to build the next node from, taken at once so it's internally consistent.
+#[derive(Clone, Debug)]
+pub struct Frontier {
+    /// The highest round with enough voting power
+    pub round: Round,
+    /// All the certificates of `round`
+    pub strong_links: Vec<NodeCertificate>,
+    /// The latest certificates of the authors missing from `round` that the strong links don't
+    /// already reference
+    pub weak_links: Vec<NodeCertificate>,
+    pub backpressure: bool,
+}
+
+/// Read access to a set of rounds of the DAG, see `Dag::with_rounds`. The accessors return
+/// nothing for the rounds outside of the view.
+pub struct RoundView<'a> {
+    dag: &'a Dag,
+    /// Ascending, without duplicates
+    rounds: Vec<Round>,
+}
+
+impl<'a> RoundView<'a> {
+    pub fn rounds(&self) -> &[Round] {
+        &self.rounds
+    }
+
+    pub fn contains(&self, round: Round) -> bool {
+        self.rounds.binary_search(&round).is_ok()
+    }
+
+    /// The statuses of the nodes of the round by validator index.
+    pub fn slots(&self, round: Round) -> Option<&'a [Option<NodeStatus>]> {
+        if !self.contains(round) {
+            return None;
+        }
+        self.dag.nodes_by_round.get(&round).map(Vec::as_slice)
+    }
+
+    pub fn get_node_status(&self, round: Round, author: &Author) -> Option<&'a NodeStatus> {
+        if !self.contains(round) {
+            return None;
+        }
+        self.dag.get_node_status(round, author)
+    }
+
+    pub fn get_node(&self, round: Round, author: &Author) -> Option<&'a Arc<CertifiedNode>> {
+        self.get_node_status(round, author).map(NodeStatus::as_node)
+    }
+
+    /// Like `Dag::strong_links_for_round`.
+    pub fn strong_links(&self, round: Round) -> Option<Vec<NodeCertificate>> {
+        if !self.contains(round) {
+            return None;
+        }
+        self.dag.strong_links_for_round(round)
+    }
+
+    /// The round of `Dag::bitmask`, empty slots only for a round the DAG doesn't hold.
+    pub fn bitmask(&self, round: Round) -> Option<Vec<bool>> {
+        self.contains(round)
+            .then(|| self.dag.read().round_bitmask(round))
+    }
+}
+
+/// Borrowed reads of the DAG, see `Dag::read`. The nodes are handed out as references living no
+/// longer than the guard, which borrows the DAG and so the lock it's read through, reading them
+/// never touches their reference count. Callers that keep a node past the lock, to broadcast or
+/// order it, take the `Arc` from `Dag::get_node` instead.
+pub struct DagReadGuard<'a> {
+    dag: &'a Dag,
+}
+
+impl<'a> DagReadGuard<'a> {
+    /// The node in the slot of the metadata, `None` if the slot holds another node.
+    pub fn get_node_ref(&self, metadata: &NodeMetadata) -> Option<&CertifiedNode> {
+        self.dag
+            .get_node_by_round_author(metadata.round(), metadata.author())
+            .map(Arc::as_ref)
+            .filter(|node| node.metadata().digest() == metadata.digest())
+    }
+
+    pub fn get_node_by_digest(&self, digest: &HashValue) -> Option<&CertifiedNode> {
+        self.dag.nodes_by_digest.get(digest).map(Arc::as_ref)
+    }
+
+    /// The nodes of the round in validator index order.
+    pub fn round_nodes(&self, round: Round) -> impl Iterator<Item = &CertifiedNode> {
+        self.dag
+            .nodes_by_round
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|status| status.as_node().as_ref())
+    }
+
+    /// Like `Dag::resolve_parent`.
+    pub fn resolve_parent(
+        &self,
+        certificate: &NodeCertificate,
+    ) -> Result<Option<&CertifiedNode>, DagStoreError> {
+        Ok(self.dag.resolve_parent(certificate)?.map(Arc::as_ref))
+    }
+
+    /// Whether every parent is in the DAG and matches its certificate.
+    pub fn all_parents_exist(&self, parents: &[NodeCertificate]) -> bool {
+        parents
+            .iter()
+            .all(|parent| matches!(self.resolve_parent(parent), Ok(Some(_))))
+    }
+
+    /// The voting power of the nodes of the round, the excluded authors don't count.
+    pub fn round_power(&self, round: Round) -> u128 {
+        self.round_nodes(round)
+            .map(|node| self.dag.counted_power(node.metadata().author()))
+            .sum()
+    }
+
+    pub fn has_quorum(&self, round: Round) -> bool {
+        self.round_power(round) >= self.dag.epoch_state.verifier.quorum_voting_power()
+    }
+
+    /// The voting power of the authors of the parents, each author counted once.
+    pub fn parents_power(&self, parents: &[NodeCertificate]) -> u128 {
+        let authors: HashSet<_> = parents
+            .iter()
+            .map(|parent| parent.metadata().author())
+            .collect();
+        authors
+            .into_iter()
+            .map(|author| self.dag.counted_power(author))
+            .sum()
+    }
+
+    /// The round of `Dag::bitmask`.
+    pub fn round_bitmask(&self, round: Round) -> Vec<bool> {
+        let dag = self.dag;
+        let skipped = dag
+            .skipped_anchor(round)
+            .and_then(|anchor| dag.validator_index.index_of(anchor));
+        let slots = dag.nodes_by_round.get(&round);
+        (0..dag.validator_index.len())
+            .map(|index| {
+                skipped == Some(index)
+                    || slots
+                        .and_then(|slots| slots.get(index))
+                        .and_then(Option::as_ref)
+                        .map_or(false, |status| {
+                            !dag.is_equivocator(status.as_node().metadata().author())
+                        })
+            })
+            .collect()
+    }
+}
+
+/// The highest round of the DAG reaching quorum voting power, and the local time it did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoundAdvance {
+    pub round: Round,
+    pub reached_at: Duration,
+}
+
+/// Tells the round timeout logic when the frontier of the DAG advances. It's only signaled when
+/// the highest round with quorum voting power goes up, nodes in rounds below it or in a round
+/// that already has quorum don't signal.
+#[derive(Clone)]
+pub struct RoundAdvanceSignal {
+    receiver: watch::Receiver<Option<RoundAdvance>>,
+}
+
+impl RoundAdvanceSignal {
+    /// The latest advance, signaled or not.
+    pub fn latest(&self) -> Option<RoundAdvance> {
+        *self.receiver.borrow()
+    }
+
+    /// The advance signaled since the last call, if any.
+    pub fn try_next(&mut self) -> Option<RoundAdvance> {
+        match self.receiver.has_changed() {
+            Ok(true) => *self.receiver.borrow_and_update(),
+            _ => None,
+        }
+    }
+
+    /// Waits for the next advance, `None` once the DAG is dropped.
+    pub async fn next(&mut self) -> Option<RoundAdvance> {
+        self.receiver.changed().await.ok()?;
+        *self.receiver.borrow_and_update()
+    }
+}
+
+/// Local times of the transitions of a node in the DAG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct NodeTimings {
+    inserted_at: Duration,
+    /// `None` until ordered, or if ordered before a recovery
+    ordered_at: Option<Duration>,
+}
+
+/// A slot claimed by `Dag::insert` for its node, the node is persisted once `written` is set.
+struct SlotClaim {
+    node: Arc<CertifiedNode>,
+    written: bool,
+}
+
+/// The slot claimed by `Dag::claim_slot`, or the copy of the node already inserted.
+enum Claim {
+    Claimed(SlotId),
+    Inserted(Arc<CertifiedNode>),
+}
+
+/// The latencies of a committed node, from the local times of its transitions in the DAG.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct NodeLatencySample {
+    pub digest: HashValue,
+    pub round: Round,
+    pub author: Author,
+    /// Added to the DAG or recovered from storage
+    pub inserted_at: Duration,
+    /// `None` if ordered before a recovery
+    pub ordered_at: Option<Duration>,
+    pub committed_at: Duration,
+}
+
+impl NodeLatencySample {
+    pub fn insert_to_order(&self) -> Option<Duration> {
+        self.ordered_at
+            .map(|ordered_at| ordered_at.saturating_sub(self.inserted_at))
+    }
+
+    pub fn order_to_commit(&self) -> Option<Duration> {
+        self.ordered_at
+            .map(|ordered_at| self.committed_at.saturating_sub(ordered_at))
+    }
+}
+
+/// Reads the DAG one round per call, so an export of many rounds doesn't hold the lock for
+/// long, while keeping the rounds read consistent with each other. Created by
+/// `Dag::export_cursor` with the generation of every round, a round mutated since it was read
+/// makes the cursor rewind to it. Once the cursor is done, the rounds read are the ones of the
+/// DAG at the last call.
+#[derive(Clone, Debug)]
+pub struct ExportCursor {
+    floor: Round,
+    /// Exclusive
+    end: Round,
+    generations: BTreeMap<Round, u64>,
+    next: Round,
+}
+
+/// What `ExportCursor::next_round` read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportStep {
+    /// The nodes of the round, by validator index
+    Nodes(Round, Vec<Arc<CertifiedNode>>),
+    /// The rounds from this one on changed since they were read, they must be dropped along with
+    /// the rounds below the floor of the cursor, which were pruned. Reading resumes at this round.
+    Retry(Round),
+    Done,
+}
+
+impl ExportCursor {
+    /// The lowest round to export.
+    pub fn floor(&self) -> Round {
+        self.floor
+    }
+
+    pub fn next_round(&mut self, dag: &Dag) -> ExportStep {
+        let read_end = self.next.min(self.end.saturating_sub(1));
+        let changed = (self.floor..=read_end)
+            .find(|round| self.generations.get(round) != Some(&dag.round_generation(*round)));
+        if let Some(round) = changed {
+            let cursor = dag.export_cursor();
+            let next = round.clamp(cursor.floor, cursor.end);
+            *self = ExportCursor { next, ..cursor };
+            return ExportStep::Retry(next);
+        }
+        if self.next >= self.end {
+            return ExportStep::Done;
+        }
+        let round = self.next;
+        self.next += 1;
+        let nodes = dag
+            .nodes_by_round
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|status| status.as_node().clone())
+            .collect();
+        ExportStep::Nodes(round, nodes)
+    }
+}
+
+/// The next round nodes keeping an anchor from being committed, computed by
+/// `Dag::anchor_blockers`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorBlockReport {
+    /// Authors with a node in the next round that doesn't link to the anchor, or whose link
+    /// doesn't count because they're excluded as equivocators or denied authors
+    pub not_linking: Vec<Author>,
+    /// Authors without a node in the next round
+    pub missing: Vec<Author>,
+    /// Voting power of the next round nodes that link to the anchor, as counted for the round
+    pub linking_power: u128,
+    /// Voting power that still has to link to the anchor, 0 once it can be committed
+    pub shortfall: u128,
+}
+
+/// What `Dag::insert_node` did with a node.
+#[derive(Debug)]
+pub enum InsertOutcome {
+    Inserted,
+    /// Already in the DAG, e.g. delivered again by a broadcast retry
+    AlreadyPresent,
+    /// Kept in the pending buffer until its parents arrive
+    ParkedPendingParents,
+    /// Validated but its write failed, kept in the write retry queue until it's persisted
+    WriteRetrying,
+    Rejected(DagStoreError),
+}
+
+impl InsertOutcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            InsertOutcome::Inserted => "inserted",
+            InsertOutcome::AlreadyPresent => "already_present",
+            InsertOutcome::ParkedPendingParents => "parked_pending_parents",
+            InsertOutcome::WriteRetrying => "write_retrying",
+            InsertOutcome::Rejected(_) => "rejected",
+        }
+    }
+
+    pub fn ack_decision(&self) -> AckDecision {
+        match self {
+            InsertOutcome::Inserted | InsertOutcome::AlreadyPresent => AckDecision::AckNow,
+            InsertOutcome::ParkedPendingParents | InsertOutcome::WriteRetrying => {
+                AckDecision::AckLater
+            },
+            InsertOutcome::Rejected(e) => e.ack_decision(),
+        }
+    }
+}
+
+/// Whether the sender of a node should get an ack for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AckDecision {
+    /// The node is in the DAG
+    AckNow,
+    /// The node can still get in, either from the pending buffer once its parents arrive or when
+    /// the sender retries after a transient rejection
+    AckLater,
+    /// The node will never get in, retrying it is pointless
+    NeverAck,
+}
+
+/// Opaque handle of a deferred ack, given back to the `DeferredAckHandler` when the parked node
+/// leaves the pending buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AckToken(pub u64);
+
+/// Notified when a parked node with deferred acks leaves the pending buffer.
+pub trait DeferredAckHandler: Send + Sync {
+    /// The node was promoted into the DAG, its ack can be sent.
+    fn on_promoted(&self, token: AckToken, metadata: &NodeMetadata);
+
+    /// The node was dropped without getting in, it must not be acked.
+    fn on_evicted(&self, _token: AckToken) {}
+}
+
+/// How much equivocation evidence the DAG keeps, set with `Dag::set_evidence_retention`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvidenceRetention {
+    /// Equivocations of an author recorded with both nodes, the later ones only keep their
+    /// metadata
+    pub max_full_records_per_author: usize,
+    /// Rounds below the pruned round whose evidence is kept, the evidence pending report is
+    /// always kept
+    pub window: Round,
+}
+
+impl Default for EvidenceRetention {
+    fn default() -> Self {
+        Self {
+            max_full_records_per_author: 4,
+            window: 10 * DEFAULT_WINDOW_SIZE,
+        }
+    }
+}
+
+/// The equivocation evidence retained in memory and in storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvidenceUsage {
+    pub num_records: usize,
+    pub num_full_records: usize,
+    pub num_pending_report: usize,
+    /// Approximate bytes of the records
+    pub bytes: usize,
+}
+
+/// What `Dag::force_reset` destroyed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResetReport {
+    pub epoch: u64,
+    pub lowest_round: Round,
+    pub highest_round: Round,
+    /// Nodes dropped from memory
+    pub num_nodes: usize,
+    pub num_pending_nodes: usize,
+    /// Records of the epoch deleted from storage
+    pub num_deleted_nodes: usize,
+    pub num_deleted_pending_nodes: usize,
+    pub num_deleted_ordered_anchors: usize,
+    pub num_deleted_evidence_records: usize,
+    /// The round the DAG restarts from
+    pub start_round: Round,
+    /// The confirmed round before the reset, it restarts at the committed round
+    pub confirmed_round: Round,
+    /// The pruning floor before the reset, it restarts at `start_round`
+    pub pruned_below: Round,
+}
+
+/// How the DAG reacts to storage failures. Validators run `Strict` to stop rather than continue
+/// with a truncated or corrupted DAG, `BestEffort` logs and counts the failure and moves on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DagStoreMode {
+    Strict,
+    BestEffort,
+}
+
+impl DagStoreMode {
+    /// Returns the error in `Strict` mode, logs and counts it in `BestEffort` mode.
+    fn tolerate(self, error: DagStoreError, operation: &'static str) -> Result<(), DagStoreError> {
+        match self {
+            DagStoreMode::Strict => Err(error),
+            DagStoreMode::BestEffort => {
+                counters::STORAGE_ERROR_COUNT
+                    .with_label_values(&[operation])
+                    .inc();
+                error!("Error in {}: {:?}", operation, error);
+                Ok(())
+            },
+        }
+    }
+
+    /// Applies the policy to the result of a storage operation, a tolerated failure resolves to
+    /// the default value.
+    fn handle<T: Default>(
+        self,
+        result: anyhow::Result<T>,
+        operation: &'static str,
+    ) -> Result<T, DagStoreError> {
+        result.or_else(|e| {
+            self.tolerate(DagStoreError::Storage(e.context(operation)), operation)
+                .map(|_| T::default())
+        })
+    }
+}
+
+/// Makes the DAG a mirror for nodes that don't vote, like fullnodes serving fetches. An observer
+/// receives nodes in bursts, so it accepts any round up to `max_round_span` above the lowest round
+/// and buffers the nodes until their parents arrive. It never reports backpressure and keeps no
+/// ordering state, the nodes are still checked against the validator set and for equivocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObserverMode {
+    pub max_round_span: Round,
+}
+
+impl Default for ObserverMode {
+    fn default() -> Self {
+        Self {
+            max_round_span: DEFAULT_OBSERVER_ROUND_SPAN,
+        }
+    }
+}
+
+/// What became of a node parked in the pending buffer, or parked again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PendingResolution {
+    Inserted,
+    /// The same node entered the DAG without going through the buffer
+    AlreadyInserted,
+    AlreadyParked,
+    /// Another node of the author took the slot
+    Equivocation,
+    Rejected,
+}
+
+impl PendingResolution {
+    fn label(&self) -> &'static str {
+        match self {
+            PendingResolution::Inserted => "inserted",
+            PendingResolution::AlreadyInserted => "already_inserted",
+            PendingResolution::AlreadyParked => "already_parked",
+            PendingResolution::Equivocation => "equivocation",
+            PendingResolution::Rejected => "rejected",
+        }
+    }
+}
+
+fn record_pending_resolution(resolution: PendingResolution) {
+    counters::PENDING_RESOLUTION_COUNT
+        .with_label_values(&[resolution.label()])
+        .inc();
+}
+
+fn record_insert_outcome(outcome: &InsertOutcome) {
+    counters::INSERT_OUTCOME_COUNT
+        .with_label_values(&[outcome.label()])
+        .inc();
+    if let InsertOutcome::Rejected(e) = outcome {
+        warn!("Rejected node: {:?}", e);
+    }
+}
+
+/// Deletes the certified nodes, or queues them to be retried by `Dag::retry_pending_deletions`
+/// if the deletion fails. The policy only applies when the queue can't be persisted either.
+fn delete_or_queue(
+    storage: &dyn DAGStorage,
+    mode: DagStoreMode,
+    digests: Vec<HashValue>,
+    operation: &'static str,
+) -> Result<(), DagStoreError> {
+    if digests.is_empty() {
+        return Ok(());
+    }
+    let deletions: HashMap<_, _> = digests.iter().map(|digest| (*digest, 0)).collect();
+    if let Err(e) = storage.delete_certified_nodes(digests) {
+        warn!(
+            "Error in {}: {:?}, queueing {} nodes for deletion",
+            operation,
+            e,
+            deletions.len()
+        );
+        mode.handle(
+            storage
+                .save_pending_deletions(&deletions)
+                .map_err(|queue_error| queue_error.context(e)),
+            operation,
+        )?;
+    }
+    Ok(())
+}
+
+/// Repairs made by `Dag::audit_against_storage`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// In-memory nodes that were missing from storage and saved again
+    pub num_resaved: usize,
+    /// Persisted nodes that were missing from memory and added back
+    pub num_loaded: usize,
+    /// Persisted nodes that can't be reconciled, stored under another digest or rejected by the
+    /// DAG, e.g. because the in-memory slot holds a different node
+    pub unrecoverable: Vec<HashValue>,
+}
+
+/// Upper bound of the causal history visited when ordering a single anchor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraversalBudget {
+    pub max_nodes: usize,
+    pub max_rounds: Round,
+}
+
+impl TraversalBudget {
+    /// A correct DAG has at most one node per validator in every round of the window, the budget
+    /// allows twice that so only pathological shapes hit it.
+    pub fn from_window(window_size: Round, num_validators: usize) -> Self {
+        Self {
+            max_nodes: 2 * window_size as usize * num_validators,
+            max_rounds: 2 * window_size,
+        }
+    }
+}
+
+/// The slots to obtain before a node can be added, see `Dag::fetch_plan_for`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchPlan {
+    /// The missing authors of each round, in validator index order
+    pub missing_slots: BTreeMap<Round, Vec<Author>>,
+    pub estimated_nodes: usize,
+}
+
+impl FetchPlan {
+    /// Pages the plan into author requests, each covering at most `max_rounds` rounds.
+    pub fn author_requests(&self, epoch: u64, max_rounds: Round) -> Vec<AuthorFetchRequest> {
+        let mut rounds_by_author: BTreeMap<Author, Vec<Round>> = BTreeMap::new();
+        for (round, authors) in &self.missing_slots {
+            for author in authors {
+                rounds_by_author.entry(*author).or_default().push(*round);
+            }
+        }
+        let mut requests = vec![];
+        for (author, rounds) in rounds_by_author {
+            let mut rounds = rounds.into_iter();
+            let Some(mut start_round) = rounds.next() else {
+                continue;
+            };
+            let mut end_round = start_round;
+            for round in rounds {
+                if round - start_round >= max_rounds {
+                    requests.push(AuthorFetchRequest::new(
+                        epoch,
+                        author,
+                        start_round,
+                        end_round,
+                    ));
+                    start_round = round;
+                }
+                end_round = round;
+            }
+            requests.push(AuthorFetchRequest::new(
+                epoch,
+                author,
+                start_round,
+                end_round,
+            ));
+        }
+        requests
+    }
+}
+
+/// Approximate footprint of a node: its metadata, payload bytes and embedded parent certificates.
+fn estimate_node_size(node: &CertifiedNode) -> usize {
+    size_of::<NodeMetadata>() + node.payload().size() + size_of_val(node.parents())
+}
+
+/// The nodes that were in their slot first when an equivocation was recorded.
+fn first_arrivals<'a>(records: impl Iterator<Item = &'a EvidenceRecord>) -> HashSet<HashValue> {
+    records.map(|record| *record.kept().digest()).collect()
+}
+
+/// Orders the nodes persisted for the same slot, the first one keeps it. That's the node the live
+/// path gave the slot to, which rejected the others: the evidence of the equivocation records it
+/// as kept. Without a record the smaller digest goes first, so every validator keeps the same node
+/// whatever the storage iteration order.
+fn slot_precedence(first_arrivals: &HashSet<HashValue>, digest: &HashValue) -> (bool, HashValue) {
+    (!first_arrivals.contains(digest), *digest)
+}
+
+/// The indexes derived from the nodes of the DAG, rebuilt at recovery once the nodes are loaded.
+/// Each one is a pure function of `nodes_by_round`, so they're rebuilt concurrently.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RecoveredIndexes {
+    nodes_by_digest: HashMap<HashValue, Arc<CertifiedNode>>,
+    node_timings: HashMap<HashValue, NodeTimings>,
+    referenced_digests: BTreeMap<Round, HashMap<Author, HashValue>>,
+    children: HashMap<HashValue, BTreeSet<SlotId>>,
+    /// The voting power of all the authors, the excluded ones are only recovered later
+    power_by_round: BTreeMap<Round, u128>,
+    highest_round_by_author: Vec<Round>,
+    nodes_by_author: Vec<u64>,
+    memory_usage: DagMemoryUsage,
+    bytes_by_round: BTreeMap<Round, usize>,
+}
+
+pub(crate) type NodesByRound = BTreeMap<Round, Vec<Option<NodeStatus>>>;
+
+impl RecoveredIndexes {
+    /// Rebuilds the indexes one after the other.
+    pub(crate) fn rebuild(
+        nodes_by_round: &NodesByRound,
+        verifier: &ValidatorVerifier,
+        num_validators: usize,
+        recovered_at: Duration,
+    ) -> Self {
+        let (highest_round_by_author, nodes_by_author) =
+            rebuild_author_rounds(nodes_by_round, num_validators);
+        let (memory_usage, bytes_by_round) = rebuild_memory_usage(nodes_by_round);
+        Self {
+            nodes_by_digest: rebuild_digest_index(nodes_by_round),
+            node_timings: rebuild_node_timings(nodes_by_round, recovered_at),
+            referenced_digests: rebuild_references(nodes_by_round),
+            children: rebuild_children(nodes_by_round),
+            power_by_round: rebuild_round_power(nodes_by_round, verifier),
+            highest_round_by_author,
+            nodes_by_author,
+            memory_usage,
+            bytes_by_round,
+        }
+    }
+
+    /// Rebuilds the indexes concurrently, they only share the nodes for reading.
+    pub(crate) fn rebuild_parallel(
+        nodes_by_round: &NodesByRound,
+        verifier: &ValidatorVerifier,
+        num_validators: usize,
+        recovered_at: Duration,
+    ) -> Self {
+        let mut nodes_by_digest = HashMap::new();
+        let mut node_timings = HashMap::new();
+        let mut referenced_digests = BTreeMap::new();
+        let mut children = HashMap::new();
+        let mut power_by_round = BTreeMap::new();
+        let mut author_rounds = (vec![], vec![]);
+        let mut memory_usage = (DagMemoryUsage::default(), BTreeMap::new());
+        rayon::scope(|scope| {
+            scope.spawn(|_| nodes_by_digest = rebuild_digest_index(nodes_by_round));
+            scope.spawn(|_| node_timings = rebuild_node_timings(nodes_by_round, recovered_at));
+            scope.spawn(|_| referenced_digests = rebuild_references(nodes_by_round));
+            scope.spawn(|_| children = rebuild_children(nodes_by_round));
+            scope.spawn(|_| power_by_round = rebuild_round_power(nodes_by_round, verifier));
+            scope.spawn(|_| author_rounds = rebuild_author_rounds(nodes_by_round, num_validators));
+            scope.spawn(|_| memory_usage = rebuild_memory_usage(nodes_by_round));
+        });
+        Self {
+            nodes_by_digest,
+            node_timings,
+            referenced_digests,
+            children,
+            power_by_round,
+            highest_round_by_author: author_rounds.0,
+            nodes_by_author: author_rounds.1,
+            memory_usage: memory_usage.0,
+            bytes_by_round: memory_usage.1,
+        }
+    }
+
+    pub(crate) fn num_nodes(&self) -> usize {
+        self.nodes_by_digest.len()
+    }
+}
+
+fn recovered_nodes(nodes_by_round: &NodesByRound) -> impl Iterator<Item = &Arc<CertifiedNode>> {
+    nodes_by_round
+        .values()
+        .flatten()
+        .flatten()
+        .map(|status| status.as_node())
+}
+
+fn rebuild_digest_index(nodes_by_round: &NodesByRound) -> HashMap<HashValue, Arc<CertifiedNode>> {
+    recovered_nodes(nodes_by_round)
+        .map(|node| (node.digest(), node.clone()))
+        .collect()
+}
+
+fn rebuild_node_timings(
+    nodes_by_round: &NodesByRound,
+    recovered_at: Duration,
+) -> HashMap<HashValue, NodeTimings> {
+    recovered_nodes(nodes_by_round)
+        .map(|node| {
+            (node.digest(), NodeTimings {
+                inserted_at: recovered_at,
+                ordered_at: None,
+            })
+        })
+        .collect()
+}
+
+/// The first node by round and validator index linking to a slot gives its digest.
+fn rebuild_references(
+    nodes_by_round: &NodesByRound,
+) -> BTreeMap<Round, HashMap<Author, HashValue>> {
+    let mut referenced_digests = BTreeMap::new();
+    for node in recovered_nodes(nodes_by_round) {
+        index_references(&mut referenced_digests, node);
+    }
+    referenced_digests
+}
+
+/// The slots of the nodes linking to each node of the DAG.
+fn rebuild_children(nodes_by_round: &NodesByRound) -> HashMap<HashValue, BTreeSet<SlotId>> {
+    let digests: HashSet<_> = recovered_nodes(nodes_by_round)
+        .map(|node| node.digest())
+        .collect();
+    let mut children = HashMap::new();
+    for (round, slots) in nodes_by_round {
+        for (index, status) in slots.iter().enumerate() {
+            if let Some(status) = status {
+                let slot = SlotId::new(*round, index as ValidatorIndexU32);
+                index_children(&mut children, slot, status.as_node(), |digest| {
+                    digests.contains(digest)
+                });
+            }
+        }
+    }
+    children
+}
+
+fn rebuild_round_power(
+    nodes_by_round: &NodesByRound,
+    verifier: &ValidatorVerifier,
+) -> BTreeMap<Round, u128> {
+    let mut power_by_round = BTreeMap::new();
+    for node in recovered_nodes(nodes_by_round) {
+        let power = verifier
+            .get_voting_power(node.metadata().author())
+            .unwrap_or_default() as u128;
+        index_round_power(&mut power_by_round, node.metadata().round(), power);
+    }
+    power_by_round
+}
+
+/// The highest round and the number of nodes of every validator, by validator index.
+fn rebuild_author_rounds(
+    nodes_by_round: &NodesByRound,
+    num_validators: usize,
+) -> (Vec<Round>, Vec<u64>) {
+    let mut highest_round_by_author = vec![0; num_validators];
+    let mut nodes_by_author = vec![0; num_validators];
+    for (round, slots) in nodes_by_round {
+        for (index, _) in slots.iter().enumerate().filter(|(_, slot)| slot.is_some()) {
+            index_author_round(
+                &mut highest_round_by_author,
+                &mut nodes_by_author,
+                index,
+                *round,
+            );
+        }
+    }
+    (highest_round_by_author, nodes_by_author)
+}
+
+fn rebuild_memory_usage(nodes_by_round: &NodesByRound) -> (DagMemoryUsage, BTreeMap<Round, usize>) {
+    let mut memory_usage = DagMemoryUsage::default();
+    let mut bytes_by_round = BTreeMap::new();
+    for node in recovered_nodes(nodes_by_round) {
+        index_node_bytes(&mut memory_usage, &mut bytes_by_round, node);
+    }
+    (memory_usage, bytes_by_round)
+}
+
+// The indexing of a single node, shared by the rebuild at recovery and `Dag::account_node_added`
+// so they can't drift apart.
+
+fn index_references(
+    referenced_digests: &mut BTreeMap<Round, HashMap<Author, HashValue>>,
+    node: &CertifiedNode,
+) {
+    for parent in node.parents() {
+        let metadata = parent.metadata();
+        referenced_digests
+            .entry(metadata.round())
+            .or_default()
+            .entry(*metadata.author())
+            .or_insert(*metadata.digest());
+    }
+}
+
+/// Only the parents still in the DAG, `contains` tells which, get an entry.
+fn index_children(
+    children: &mut HashMap<HashValue, BTreeSet<SlotId>>,
+    slot: SlotId,
+    node: &CertifiedNode,
+    contains: impl Fn(&HashValue) -> bool,
+) {
+    for parent in node.parents() {
+        let digest = parent.metadata().digest();
+        if contains(digest) {
+            children.entry(*digest).or_default().insert(slot);
+        }
+    }
+}
+
+/// Returns the voting power of the round with the node.
+fn index_round_power(
+    power_by_round: &mut BTreeMap<Round, u128>,
+    round: Round,
+    power: u128,
+) -> u128 {
+    let round_power = power_by_round.entry(round).or_default();
+    *round_power += power;
+    *round_power
+}
+
+fn index_author_round(
+    highest_round_by_author: &mut [Round],
+    nodes_by_author: &mut [u64],
+    index: usize,
+    round: Round,
+) {
+    highest_round_by_author[index] = highest_round_by_author[index].max(round);
+    nodes_by_author[index] += 1;
+}
+
+fn index_node_bytes(
+    memory_usage: &mut DagMemoryUsage,
+    bytes_by_round: &mut BTreeMap<Round, usize>,
+    node: &CertifiedNode,
+) {
+    let bytes = estimate_node_size(node);
+    memory_usage.num_nodes += 1;
+    memory_usage.node_bytes += bytes;
+    *bytes_by_round.entry(node.metadata().round()).or_default() += bytes;
+}
+
+#[derive(Debug, ThisError)]
+pub enum DagStoreError {
+    #[error("unknown author {0}")]
+    UnknownAuthor(Author),
+    #[error("author {author} is denied after round {denied_after}, node is round {round}")]
+    AuthorDenied {
+        author: Author,
+        round: Round,
+        denied_after: Round,
+    },
+    #[error("node epoch {epoch} doesn't match the DAG epoch {expected}")]
+    EpochMismatch { epoch: u64, expected: u64 },
+    #[error("node chain id {chain_id} doesn't match the DAG chain id {expected}")]
+    ChainIdMismatch {
+        chain_id: ChainId,
+        expected: ChainId,
+    },
+    #[error("epoch {0} ended")]
+    EpochEnded(u64),
+    #[error("validator set changed within the epoch")]
+    ValidatorSetChanged,
+    #[error("node {digest} is stored under {key}")]
+    DigestMismatch { key: HashValue, digest: HashValue },
+    #[error("round {round} is lower than the lowest round {lowest_round}")]
+    RoundTooLow { round: Round, lowest_round: Round },
+    #[error("round {round} is {gap} rounds above the highest round {highest_round}")]
+    RoundTooHigh {
+        round: Round,
+        highest_round: Round,
+        gap: Round,
+    },
+    #[error(
+        "round {round} is more than {max_round_span} rounds above the lowest round {lowest_round}"
+    )]
+    RoundBeyondSpan {
+        round: Round,
+        lowest_round: Round,
+        max_round_span: Round,
+    },
+    #[error("node links to itself")]
+    SelfParent,
+    #[error("node links to parent {0} more than once")]
+    DuplicateParent(HashValue),
+    #[error("node links to more than one node of {author} in round {round}")]
+    DuplicateParentSlot { round: Round, author: Author },
+    #[error("parent round {parent_round} is not lower than node round {round}")]
+    InvalidParentRound { round: Round, parent_round: Round },
+    #[error("parent epoch {parent_epoch} doesn't match node epoch {epoch}")]
+    ParentEpochMismatch { epoch: u64, parent_epoch: u64 },
+    #[error("parent {0} not exist")]
+    MissingParent(HashValue),
+    #[error(
+        "parent {digest} claims round {claimed_round} of {claimed_author}, the node is round {round} of {author}"
+    )]
+    ParentMetadataMismatch {
+        digest: HashValue,
+        claimed_round: Round,
+        claimed_author: Author,
+        round: Round,
+        author: Author,
+    },
+    #[error("node without parents in round {round}, only allowed in start round {start_round}")]
+    EmptyParentsNotAllowed { round: Round, start_round: Round },
+    #[error(
+        "timestamp {timestamp} is not after the parent timestamp {parent_timestamp}, {tolerance:?} of skew tolerated"
+    )]
+    NonMonotonicTimestamp {
+        timestamp: u64,
+        parent_timestamp: u64,
+        tolerance: Duration,
+    },
+    #[error("timestamp {timestamp} is more than {max_drift:?} ahead of local time {local_time}")]
+    TimestampAhead {
+        timestamp: u64,
+        local_time: u64,
+        max_drift: Duration,
+    },
+    #[error("duplicate node")]
+    DuplicateNode,
+    #[error("equivocate node")]
+    EquivocateNode,
+    #[error("own slot in round {round} is reserved for node {reserved}")]
+    SelfSlotReserved { round: Round, reserved: HashValue },
+    #[error("anchor {0} not exist")]
+    MissingAnchor(HashValue),
+    #[error("anchor {0} already ordered")]
+    AnchorAlreadyOrdered(HashValue),
+    #[error("anchor of round {0} is skipped")]
+    AnchorSkipped(Round),
+    #[error("no anchor of round {0} is ordered")]
+    AnchorNotOrdered(Round),
+    #[error(
+        "ledger info of epoch {epoch} round {round} doesn't cover the anchor of epoch {anchor_epoch} round {anchor_round}"
+    )]
+    CommitConfirmationMismatch {
+        epoch: u64,
+        round: Round,
+        anchor_epoch: u64,
+        anchor_round: Round,
+    },
+    #[error("anchor {anchor} doesn't reach the last committed anchor {previous}")]
+    AnchorNotConnected {
+        anchor: HashValue,
+        previous: HashValue,
+    },
+    #[error("invalid skip certificate for round {0}")]
+    InvalidSkipCertificate(Round),
+    #[error("observers don't order the DAG")]
+    OrderingDisabled,
+    #[error(
+        "traversal budget {budget:?} exceeded after visiting {visited_nodes} nodes down to round {lowest_round}"
+    )]
+    BudgetExceeded {
+        budget: TraversalBudget,
+        visited_nodes: usize,
+        lowest_round: Round,
+    },
+    #[error("write of node {0} failed, it's retried")]
+    WriteRetrying(HashValue),
+    #[error("{num_anchors} anchors of epoch {epoch} are ordered and not committed")]
+    RemnantNotTaken { epoch: u64, num_anchors: usize },
+    #[error("invalid config: {0}")]
+    InvalidConfig(#[from] DagStoreConfigError),
+    #[error("invalid slot: {0}")]
+    InvalidSlot(#[from] SlotIdError),
+    #[error(transparent)]
+    Storage(#[from] anyhow::Error),
+}
+
+impl DagStoreError {
+    /// Rejections that can resolve by themselves are `AckLater`, the node is dropped but a retry
+    /// of the sender can succeed once the DAG catches up or storage recovers.
+    pub fn ack_decision(&self) -> AckDecision {
+        match self {
+            DagStoreError::DuplicateNode => AckDecision::AckNow,
+            DagStoreError::MissingParent(_)
+            | DagStoreError::RoundTooHigh { .. }
+            | DagStoreError::RoundBeyondSpan { .. }
+            | DagStoreError::TimestampAhead { .. }
+            | DagStoreError::BudgetExceeded { .. }
+            | DagStoreError::WriteRetrying(_)
+            | DagStoreError::Storage(_) => AckDecision::AckLater,
+            DagStoreError::UnknownAuthor(_)
+            | DagStoreError::AuthorDenied { .. }
+            | DagStoreError::EpochMismatch { .. }
+            | DagStoreError::ChainIdMismatch { .. }
+            | DagStoreError::EpochEnded(_)
+            | DagStoreError::ValidatorSetChanged
+            | DagStoreError::DigestMismatch { .. }
+            | DagStoreError::RoundTooLow { .. }
+            | DagStoreError::SelfParent
+            | DagStoreError::DuplicateParent(_)
+            | DagStoreError::DuplicateParentSlot { .. }
+            | DagStoreError::InvalidParentRound { .. }
+            | DagStoreError::ParentEpochMismatch { .. }
+            | DagStoreError::ParentMetadataMismatch { .. }
+            | DagStoreError::EmptyParentsNotAllowed { .. }
+            | DagStoreError::NonMonotonicTimestamp { .. }
+            | DagStoreError::EquivocateNode
+            | DagStoreError::SelfSlotReserved { .. }
+            | DagStoreError::MissingAnchor(_)
+            | DagStoreError::AnchorAlreadyOrdered(_)
+            | DagStoreError::AnchorSkipped(_)
+            | DagStoreError::AnchorNotOrdered(_)
+            | DagStoreError::CommitConfirmationMismatch { .. }
+            | DagStoreError::AnchorNotConnected { .. }
+            | DagStoreError::InvalidSkipCertificate(_)
+            | DagStoreError::OrderingDisabled
+            | DagStoreError::RemnantNotTaken { .. }
+            | DagStoreError::InvalidConfig(_)
+            | DagStoreError::InvalidSlot(_) => AckDecision::NeverAck,
+        }
+    }
+}
+
+/// Why a round can't provide strong links, `InsufficientPower` is the only one that can resolve by
+/// waiting for more nodes.
+#[derive(Debug, PartialEq, Eq, ThisError)]
+pub enum StrongLinksError {
+    #[error("round {round} is pruned, the lowest round is {lowest_round}")]
+    RoundPruned { round: Round, lowest_round: Round },
+    #[error("round {round} is above the highest round {highest_round}")]
+    RoundUnknown { round: Round, highest_round: Round },
+    #[error("round has {present} voting power, {required} required")]
+    InsufficientPower { present: u128, required: u128 },
+    #[error(
+        "{max_bytes} bytes of strong links only fit {fitting} voting power, {required} required"
+    )]
+    OverSizeBudget {
+        fitting: u128,
+        required: u128,
+        max_bytes: usize,
+    },
+}
+
+/// Data structure that stores the DAG representation, it maintains both hash based index and
+/// round based index.
+pub struct Dag {
+    /// Every voting power computation goes through it so they all agree with each other
+    epoch_state: Arc<EpochState>,
+    /// Nodes of other chains are rejected and dropped at recovery
+    chain_id: ChainId,
+    nodes_by_digest: HashMap<HashValue, Arc<CertifiedNode>>,
+    nodes_by_round: BTreeMap<Round, Vec<Option<NodeStatus>>>,
+    /// Map between peer id to vector index, shared with the other components of the epoch
+    validator_index: Arc<ValidatorIndex>,
+    storage: Arc<dyn DAGStorage>,
+    mode: DagStoreMode,
+    /// Set when the DAG only mirrors the validators
+    observer: Option<ObserverMode>,
+    /// Nodes received before their parents, indexed by round and digest so a node is parked once
+    pending_nodes: BTreeMap<Round, BTreeMap<HashValue, CertifiedNode>>,
+    /// Deferred acks of the pending nodes, by digest, fired when the node leaves the buffer
+    pending_acks: HashMap<HashValue, Vec<AckToken>>,
+    deferred_ack_handler: Option<Arc<dyn DeferredAckHandler>>,
+    memory_usage: DagMemoryUsage,
+    /// Approximate bytes of the nodes in each round, to report the biggest contributors
+    bytes_by_round: BTreeMap<Round, usize>,
+    /// Soft limit of the total bytes, exceeding it turns on backpressure
+    memory_budget: usize,
+    over_memory_budget: bool,
+    /// All time reads of the DAG go through this, so tests can control the clock
+    time_service: Arc<dyn TimeService>,
+    /// Local times of the transitions of each node, dropped with the node
+    node_timings: HashMap<HashValue, NodeTimings>,
+    /// Digests of the nodes ordered and not committed yet, by round of their anchor
+    awaiting_commit: BTreeMap<Round, Vec<HashValue>>,
+    /// Highest round committed through `commit_callback`, 0 after a restart
+    committed_round: Round,
+    /// Highest anchor round confirmed by `notify_commit_confirmed`, 0 after a restart
+    confirmed_round: Round,
+    /// Set once `finalize_epoch` handed out the ordered anchors that aren't committed
+    remnant_taken: bool,
+    /// The latencies of the last committed nodes, oldest first
+    latency_samples: VecDeque<NodeLatencySample>,
+    /// The commit latencies of the last anchors, oldest first
+    commit_latencies: VecDeque<AnchorCommitLatency>,
+    /// Set once the epoch is over, the DAG stays readable but rejects new nodes
+    ended: bool,
+    /// The only round where nodes have no parents
+    epoch_start_round: Round,
+    /// Rounds abandoned because their anchor didn't show up
+    skipped_rounds: BTreeMap<Round, SkipCertificate>,
+    pruning_policy: Arc<dyn DagPruningPolicy>,
+    /// The anchors ordered in the DAG with their batch sources, by anchor digest
+    ordered_anchors: HashMap<HashValue, OrderedAnchor>,
+    /// The last anchor ordered, persisted so every later anchor is checked against it even after
+    /// a restart or once it's pruned
+    last_committed_anchor: Option<NodeMetadata>,
+    /// Slots claimed by `insert`, by round and validator index, until `link_inserted` links their
+    /// node in. Readers see them as empty, every insertion as occupied.
+    reserved_slots: DashMap<SlotId, SlotClaim>,
+    /// Highest round of the claimed nodes already written, their children can be inserted before
+    /// they're linked in
+    highest_written_round: AtomicU64,
+    /// Equivocating nodes rejected by `insert`, their evidence is recorded by `link_inserted`
+    rejected_equivocations: Mutex<Vec<Arc<CertifiedNode>>>,
+    /// Nodes whose write failed in `insert`, their slots stay reserved until they're persisted
+    write_retries: Mutex<WriteRetryQueue>,
+    /// Fingerprints of the nodes of the last pruned rounds
+    pruned_digests: PrunedDigestFilter,
+    /// Digests computed by `round_digest`, with the generation of the round they were computed at
+    round_digests: Mutex<BTreeMap<Round, (u64, HashValue)>>,
+    /// Highest round of the nodes of each author, by validator index, 0 before its first node.
+    /// Pruning doesn't lower it.
+    highest_round_by_author: Vec<Round>,
+    /// Skew beyond which `check_author_skew` flags an author
+    skew_threshold: Round,
+    /// Gap above the highest round within which `insert_node` parks nodes
+    park_gap: Round,
+    /// How much earlier than its latest parent a node can be
+    timestamp_skew_tolerance: Duration,
+    /// How far ahead of local time a node can be
+    max_clock_drift: Duration,
+    /// Equivocations of the epoch in the order they were found, persisted
+    equivocation_evidence: Vec<EvidenceRecord>,
+    evidence_retention: EvidenceRetention,
+    /// Voting power of the nodes of each round
+    power_by_round: BTreeMap<Round, u128>,
+    /// Highest round whose nodes have quorum voting power, 0 before the first one
+    highest_quorum_round: Round,
+    round_advance: watch::Sender<Option<RoundAdvance>>,
+    /// Local time the DAG was created or recovered
+    started_at: Duration,
+    epoch_totals: EpochTotals,
+    /// Digest certified for each slot by the parents of the nodes in the DAG or in the pending
+    /// buffer, by round and author, to check the fetched nodes against
+    referenced_digests: BTreeMap<Round, HashMap<Author, HashValue>>,
+    /// The slots of the nodes linking to each node of the DAG, a node without any has no entry
+    children: HashMap<HashValue, BTreeSet<SlotId>>,
+    /// Generation of the last mutation of each round held or reset, see `round_generation`
+    round_generations: BTreeMap<Round, u64>,
+    /// Generation of the last mutation of any round
+    last_generation: u64,
+    /// Rounds below it were pruned, they're at `PRUNED_GENERATION`
+    pruned_below: Round,
+    /// The node the local validator broadcast in each round, persisted before the broadcast
+    self_reservations: BTreeMap<Round, Node>,
+    /// The acks of the certified nodes broadcast by the local validator, by round
+    broadcast_progress: BTreeMap<Round, BroadcastProgress>,
+    /// Authors excluded for equivocating in the epoch, with the round of their first
+    /// equivocation. Their nodes stay in the DAG, but they don't count towards the voting power
+    /// of their round, aren't links and are reported missing by `bitmask`.
+    equivocators: BTreeMap<Author, Round>,
+    /// Authors denied by governance in the epoch, with the highest round when the denial was
+    /// observed. Their nodes above it are rejected, the ones up to it stay valid.
+    denied_authors: BTreeMap<Author, Round>,
+    /// Whether the other validators reach the quorum without the denied authors, only then they
+    /// don't count towards the voting power of their round and aren't links.
+    exclude_denied: bool,
+    /// The last progress reported by the catch-up, until it closes the gap
+    catch_up_progress: Option<CatchUpProgress>,
+    /// Set by `DagStoreConfig::replay_log`, records the nodes received
+    replay_log: Option<DagReplayLog>,
+}
+
+impl Dag {
+    /// A DAG of the test chain.
+    pub fn new(epoch_state: Arc<EpochState>, storage: Arc<dyn DAGStorage>) -> Self {
+        Self::new_with_time_service(epoch_state, storage, Arc::new(WallClock))
+    }
+
+    /// A DAG of the test chain.
+    pub fn new_with_time_service(
+        epoch_state: Arc<EpochState>,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+    ) -> Self {
+        Self::new_with_mode(
+            epoch_state,
+            ChainId::test(),
+            storage,
+            time_service,
+            DagStoreMode::BestEffort,
+        )
+        .expect("Best effort recovery should not fail")
+    }
+
+    /// Recovers the DAG from storage with the default config, see `try_new`.
+    pub fn new_with_mode(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+    ) -> Result<Self, DagStoreError> {
+        Self::try_new(
+            epoch_state,
+            chain_id,
+            storage,
+            time_service,
+            mode,
+            DagStoreConfig::default(),
+        )
+    }
+
+    /// Recovers the DAG from storage, in `Strict` mode any storage failure or inconsistency
+    /// fails the recovery. Persisted nodes of another epoch or chain are deleted. Fails with
+    /// `InvalidConfig` before reading storage if `config` doesn't validate for the validator set
+    /// of the epoch.
+    pub fn try_new(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        Self::recover(
+            epoch_state,
+            chain_id,
+            storage,
+            time_service,
+            mode,
+            None,
+            config,
+        )
+    }
+
+    /// Recovers the DAG of an observer from storage, the ordered anchors are not recovered.
+    /// Validates `config` like `try_new`.
+    pub fn new_observer(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        observer: ObserverMode,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        Self::recover(
+            epoch_state,
+            chain_id,
+            storage,
+            time_service,
+            mode,
+            Some(observer),
+            config,
+        )
+    }
+
+    fn recover(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        observer: Option<ObserverMode>,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        let epoch = epoch_state.epoch;
+        let validator_index = Arc::new(ValidatorIndex::new(&epoch_state));
+        let num_validators = validator_index.len();
+        config.validate(num_validators)?;
+        let all_nodes = mode.handle(storage.get_certified_nodes(), "recover_nodes")?;
+        let queued_deletions =
+            mode.handle(storage.get_pending_deletions(), "get_pending_deletions")?;
+        let evidence = mode.handle(
+            storage.get_equivocation_evidence(),
+            "get_equivocation_evidence",
+        )?;
+        let first_arrivals =
+            first_arrivals(evidence.values().filter(|record| record.epoch() == epoch));
+        let loading_started = Instant::now();
+        let mut expired = vec![];
+        let mut equivocations = vec![];
+        let mut nodes_by_round: BTreeMap<Round, Vec<Option<NodeStatus>>> = BTreeMap::new();
+        for (digest, certified_node) in all_nodes {
+            if queued_deletions.contains_key(&digest) {
+                // pruned before, deleted by the retry below
+                continue;
+            } else if digest != certified_node.digest() {
+                let mismatch = DagStoreError::DigestMismatch {
+                    key: digest,
+                    digest: certified_node.digest(),
+                };
+                mode.tolerate(mismatch, "recover_nodes")?;
+            } else if certified_node.metadata().epoch() == epoch
+                && certified_node.metadata().chain_id() == chain_id
+            {
+                let arc_node = Arc::new(certified_node);
+                let author = *arc_node.metadata().author();
+                let index = validator_index
+                    .index_of(&author)
+                    .expect("Author from certified node should exist");
+                let round = arc_node.metadata().round();
+                let slot = &mut nodes_by_round
+                    .entry(round)
+                    .or_insert_with(|| vec![None; num_validators])[index];
+                // the live path never persists a second node for a slot, this is a write that
+                // landed anyway or a build without the equivocation check
+                let arc_node = match slot.take() {
+                    Some(existing) => {
+                        let existing = existing.as_node().clone();
+                        let (kept, dropped) =
+                            if slot_precedence(&first_arrivals, &existing.digest())
+                                < slot_precedence(&first_arrivals, &digest)
+                            {
+                                (existing, arc_node)
+                            } else {
+                                (arc_node, existing)
+                            };
+                        warn!(
+                            "Equivocating nodes of {} in round {} in storage, keeping {} and deleting {}",
+                            author,
+                            round,
+                            kept.digest(),
+                            dropped.digest()
+                        );
+                        expired.push(dropped.digest());
+                        equivocations.push((kept.clone(), dropped));
+                        kept
+                    },
+                    None => arc_node,
+                };
+                *slot = Some(NodeStatus::Unordered(arc_node));
+            } else {
+                expired.push(digest);
+            }
+        }
+        delete_or_queue(storage.as_ref(), mode, expired, "delete_expired_nodes")?;
+        let epoch_start_round = mode
+            .handle(storage.get_epoch_start_round(), "get_epoch_start_round")?
+            .filter(|(start_epoch, _)| *start_epoch == epoch)
+            .map_or(DEFAULT_EPOCH_START_ROUND, |(_, round)| round);
+        let last_committed_anchor = mode
+            .handle(
+                storage.get_last_committed_anchor(),
+                "get_last_committed_anchor",
+            )?
+            .filter(|anchor| anchor.epoch() == epoch);
+        let started_at = time_service.get_current_timestamp();
+        let loading_time = loading_started.elapsed();
+        let indexing_started = Instant::now();
+        let indexes = RecoveredIndexes::rebuild_parallel(
+            &nodes_by_round,
+            &epoch_state.verifier,
+            num_validators,
+            started_at,
+        );
+        let indexing_time = indexing_started.elapsed();
+        let mut dag = Self {
+            epoch_state,
+            chain_id,
+            nodes_by_digest: HashMap::new(),
+            nodes_by_round: BTreeMap::new(),
+            validator_index,
+            storage,
+            mode,
+            observer,
+            pending_nodes: BTreeMap::new(),
+            pending_acks: HashMap::new(),
+            deferred_ack_handler: None,
+            memory_usage: DagMemoryUsage::default(),
+            bytes_by_round: BTreeMap::new(),
+            memory_budget: config.memory_budget,
+            over_memory_budget: false,
+            time_service,
+            node_timings: HashMap::new(),
+            awaiting_commit: BTreeMap::new(),
+            committed_round: 0,
+            confirmed_round: 0,
+            remnant_taken: false,
+            latency_samples: VecDeque::new(),
+            commit_latencies: VecDeque::new(),
+            ended: false,
+            epoch_start_round,
+            skipped_rounds: BTreeMap::new(),
+            pruning_policy: config.pruning_policy(),
+            ordered_anchors: HashMap::new(),
+            last_committed_anchor,
+            reserved_slots: DashMap::new(),
+            highest_written_round: AtomicU64::new(0),
+            rejected_equivocations: Mutex::new(vec![]),
+            write_retries: Mutex::new(config.write_retry_queue()),
+            pruned_digests: PrunedDigestFilter::new(config.pruned_filter_rounds),
+            round_digests: Mutex::new(BTreeMap::new()),
+            highest_round_by_author: vec![0; num_validators],
+            skew_threshold: config.skew_threshold,
+            park_gap: config.park_gap,
+            timestamp_skew_tolerance: config.timestamp_skew_tolerance,
+            max_clock_drift: config.max_clock_drift,
+            equivocation_evidence: vec![],
+            evidence_retention: config.evidence_retention,
+            power_by_round: BTreeMap::new(),
+            highest_quorum_round: 0,
+            round_advance: watch::channel(None).0,
+            started_at,
+            epoch_totals: EpochTotals::new(num_validators),
+            referenced_digests: BTreeMap::new(),
+            children: HashMap::new(),
+            round_generations: BTreeMap::new(),
+            last_generation: 0,
+            pruned_below: 0,
+            self_reservations: BTreeMap::new(),
+            broadcast_progress: BTreeMap::new(),
+            equivocators: BTreeMap::new(),
+            denied_authors: BTreeMap::new(),
+            exclude_denied: false,
+            catch_up_progress: None,
+            replay_log: None,
+        };
+        dag.nodes_by_round = nodes_by_round;
+        dag.install_indexes(indexes);
+        dag.update_memory_budget_flag();
+        let records_started = Instant::now();
+        if observer.is_none() {
+            dag.recover_ordered_anchors(epoch)?;
+        }
+        dag.recover_pending_nodes(epoch)?;
+        dag.recover_self_reservations(epoch)?;
+        dag.recover_broadcast_progress(epoch)?;
+        let found_equivocations =
+            dag.recover_equivocation_evidence(epoch, evidence, equivocations)?;
+        dag.recover_equivocators(epoch, found_equivocations)?;
+        dag.recover_denied_authors(epoch)?;
+        dag.retry_pending_deletions(DELETION_RETRY_CHUNK_SIZE)?;
+        if let Some(replay_log) = &config.replay_log {
+            let store_config = DagStoreConfig {
+                replay_log: None,
+                ..config.clone()
+            };
+            dag.open_replay_log(replay_log, store_config);
+        }
+        info!(
+            "Recovered the DAG of epoch {} with {} nodes in {:?}: loading {:?}, indexes {:?}, other records {:?}",
+            epoch,
+            dag.memory_usage.num_nodes,
+            loading_started.elapsed(),
+            loading_time,
+            indexing_time,
+            records_started.elapsed()
+        );
+        Ok(dag)
+    }
+
+    /// The indexes as the inserts maintain them, to compare with a rebuild.
+    #[cfg(test)]
+    pub(crate) fn indexes(&self) -> RecoveredIndexes {
+        RecoveredIndexes {
+            nodes_by_digest: self.nodes_by_digest.clone(),
+            node_timings: self.node_timings.clone(),
+            referenced_digests: self.referenced_digests.clone(),
+            children: self.children.clone(),
+            power_by_round: self.power_by_round.clone(),
+            highest_round_by_author: self.highest_round_by_author.clone(),
+            nodes_by_author: self.epoch_totals.nodes_by_author.clone(),
+            memory_usage: self.memory_usage,
+            bytes_by_round: self.bytes_by_round.clone(),
+        }
+    }
+
+    /// Takes the indexes rebuilt from `nodes_by_round` at recovery.
+    fn install_indexes(&mut self, indexes: RecoveredIndexes) {
+        self.nodes_by_digest = indexes.nodes_by_digest;
+        self.node_timings = indexes.node_timings;
+        self.referenced_digests = indexes.referenced_digests;
+        self.children = indexes.children;
+        self.power_by_round = indexes.power_by_round;
+        self.highest_round_by_author = indexes.highest_round_by_author;
+        self.epoch_totals.nodes_by_author = indexes.nodes_by_author;
+        self.memory_usage = indexes.memory_usage;
+        self.bytes_by_round = indexes.bytes_by_round;
+        let quorum = self.epoch_state.verifier.quorum_voting_power();
+        self.highest_quorum_round = self
+            .power_by_round
+            .iter()
+            .rev()
+            .find(|(_, power)| **power >= quorum)
+            .map_or(0, |(round, _)| *round);
+        if self.highest_quorum_round > 0 {
+            self.round_advance.send_replace(Some(RoundAdvance {
+                round: self.highest_quorum_round,
+                reached_at: self.started_at,
+            }));
+        }
+        self.update_memory_budget_flag();
+    }
+
+    /// Retries the queued deletions in chunks of `chunk_size` digests, a digest failing
+    /// `MAX_DELETION_ATTEMPTS` times is dropped from the queue. Returns the number of digests
+    /// still queued.
+    pub fn retry_pending_deletions(&self, chunk_size: usize) -> Result<usize, DagStoreError> {
+        let mut pending: Vec<_> = self
+            .mode
+            .handle(
+                self.storage.get_pending_deletions(),
+                "get_pending_deletions",
+            )?
+            .into_iter()
+            .collect();
+        pending.sort();
+        let mut deleted = vec![];
+        let mut failed = HashMap::new();
+        let mut dropped = vec![];
+        for chunk in pending.chunks(chunk_size.max(1)) {
+            let digests: Vec<_> = chunk.iter().map(|(digest, _)| *digest).collect();
+            counters::DELETION_RETRIED_COUNT.inc_by(digests.len() as u64);
+            match self.storage.delete_certified_nodes(digests.clone()) {
+                Ok(()) => deleted.extend(digests),
+                Err(e) => {
+                    warn!(
+                        "Failed to retry deletion of {} nodes: {:?}",
+                        digests.len(),
+                        e
+                    );
+                    for (digest, attempts) in chunk {
+                        if attempts + 1 >= MAX_DELETION_ATTEMPTS {
+                            dropped.push(*digest);
+                        } else {
+                            failed.insert(*digest, attempts + 1);
+                        }
+                    }
+                },
+            }
+        }
+        if !dropped.is_empty() {
+            counters::DELETION_FAILED_COUNT.inc_by(dropped.len() as u64);
+            error!(
+                "Giving up deleting {} nodes after {} attempts",
+                dropped.len(),
+                MAX_DELETION_ATTEMPTS
+            );
+            deleted.extend(dropped);
+        }
+        if !failed.is_empty() {
+            self.mode.handle(
+                self.storage.save_pending_deletions(&failed),
+                "save_pending_deletions",
+            )?;
+        }
+        if !deleted.is_empty() {
+            self.mode.handle(
+                self.storage.delete_pending_deletions(&deleted),
+                "delete_pending_deletions",
+            )?;
+        }
+        Ok(failed.len())
+    }
+
+    /// Marks the batches of the persisted ordered anchors as ordered again, the anchors from other
+    /// epochs or already pruned are deleted.
+    fn recover_ordered_anchors(&mut self, epoch: u64) -> Result<(), DagStoreError> {
+        let ordered_anchors = self.mode.handle(
+            self.storage.get_ordered_anchors(),
+            "recover_ordered_anchors",
+        )?;
+        let mut expired = vec![];
+        for (digest, ordered_anchor) in ordered_anchors {
+            if ordered_anchor.anchor().epoch() != epoch || !self.exists(&digest) {
+                expired.push(digest);
+                continue;
+            }
+            for source in ordered_anchor.sources() {
+                let index = self.author_index(source.author());
+                if let Some(slot) = self
+                    .nodes_by_round
+                    .get_mut(&source.round())
+                    .and_then(|slots| slots.get_mut(index))
+                    .and_then(Option::as_mut)
+                    .filter(|slot| !slot.is_ordered())
+                {
+                    *slot = NodeStatus::Ordered(slot.as_node().clone());
+                    self.bump_generation(source.round());
+                }
+            }
+            self.ordered_anchors.insert(digest, ordered_anchor);
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_ordered_anchors(expired),
+                "delete_ordered_anchors",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Parks the persisted pending nodes again once the DAG is reconstructed, the ones from other
+    /// epochs or below the lowest round can never be added and are deleted.
+    fn recover_pending_nodes(&mut self, epoch: u64) -> Result<(), DagStoreError> {
+        let lowest_round = self.lowest_round();
+        let pending_nodes = self
+            .mode
+            .handle(self.storage.get_pending_nodes(), "recover_pending_nodes")?;
+        for (digest, node) in pending_nodes {
+            if node.metadata().epoch() == epoch
+                && node.metadata().chain_id() == self.chain_id
+                && node.metadata().round() >= lowest_round
+            {
+                self.park_node(node, false)?;
+            } else {
+                self.mode.handle(
+                    self.storage.delete_pending_node(&digest),
+                    "delete_pending_node",
+                )?;
+            }
+        }
+        self.promote_pending_nodes()
+    }
+
+    /// Loads the reservations of the local validator, the ones of other epochs or below the lowest
+    /// round are deleted.
+    fn recover_self_reservations(&mut self, epoch: u64) -> Result<(), DagStoreError> {
+        let lowest_round = self.lowest_round();
+        let reservations = self.mode.handle(
+            self.storage.get_self_reservations(),
+            "get_self_reservations",
+        )?;
+        let mut expired = vec![];
+        for ((reservation_epoch, round), node) in reservations {
+            if reservation_epoch == epoch && round >= lowest_round {
+                self.self_reservations.insert(round, node);
+            } else {
+                expired.push((reservation_epoch, round));
+            }
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_self_reservations(expired),
+                "delete_self_reservations",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads the broadcast progress of the local validator, the records of other epochs or below
+    /// the lowest round are deleted.
+    fn recover_broadcast_progress(&mut self, epoch: u64) -> Result<(), DagStoreError> {
+        let lowest_round = self.lowest_round();
+        let records = self.mode.handle(
+            self.storage.get_broadcast_progress(),
+            "get_broadcast_progress",
+        )?;
+        let mut expired = vec![];
+        for ((progress_epoch, round), progress) in records {
+            if progress_epoch == epoch && round >= lowest_round {
+                self.broadcast_progress.insert(round, progress);
+            } else {
+                expired.push((progress_epoch, round));
+            }
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_broadcast_progress(expired),
+                "delete_broadcast_progress",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads the evidence of the epoch, the records of other epochs are deleted, and records the
+    /// equivocating nodes found in storage. Returns the author and round of those nodes.
+    fn recover_equivocation_evidence(
+        &mut self,
+        epoch: u64,
+        records: HashMap<HashValue, EvidenceRecord>,
+        equivocations: Vec<(Arc<CertifiedNode>, Arc<CertifiedNode>)>,
+    ) -> Result<Vec<(Author, Round)>, DagStoreError> {
+        let mut expired = vec![];
+        for (digest, record) in records {
+            if record.epoch() == epoch {
+                self.equivocation_evidence.push(record);
+            } else {
+                expired.push(digest);
+            }
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_equivocation_evidence(expired),
+                "delete_equivocation_evidence",
+            )?;
+        }
+        self.equivocation_evidence
+            .sort_by_key(|record| (record.round(), *record.conflicting().digest()));
+        let mut found = vec![];
+        for (kept, conflicting) in equivocations {
+            self.record_evidence(&kept, &conflicting)?;
+            found.push((
+                *conflicting.metadata().author(),
+                conflicting.metadata().round(),
+            ));
+        }
+        Ok(found)
+    }
+
+    /// Loads the equivocators of the epoch and excludes the authors of the equivocations found in
+    /// storage, the records of other epochs are deleted. The evidence recorded before the restart
+    /// doesn't exclude its author again, the author may have been cleared since.
+    fn recover_equivocators(
+        &mut self,
+        epoch: u64,
+        found_equivocations: Vec<(Author, Round)>,
+    ) -> Result<(), DagStoreError> {
+        let records = self
+            .mode
+            .handle(self.storage.get_equivocators(), "get_equivocators")?;
+        let mut expired = vec![];
+        for ((equivocator_epoch, author), round) in records {
+            if equivocator_epoch == epoch {
+                self.equivocators.insert(author, round);
+            } else {
+                expired.push((equivocator_epoch, author));
+            }
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_equivocators(expired),
+                "delete_equivocators",
+            )?;
+        }
+        for (author, round) in found_equivocations {
+            if let btree_map::Entry::Vacant(entry) = self.equivocators.entry(author) {
+                self.mode.handle(
+                    self.storage.save_equivocator(epoch, &author, round),
+                    "save_equivocator",
+                )?;
+                entry.insert(round);
+            }
+        }
+        if !self.equivocators.is_empty() {
+            self.recount_round_power();
+        }
+        Ok(())
+    }
+
+    /// Loads the authors denied in the epoch, the records of other epochs are deleted.
+    fn recover_denied_authors(&mut self, epoch: u64) -> Result<(), DagStoreError> {
+        let records = self
+            .mode
+            .handle(self.storage.get_denied_authors(), "get_denied_authors")?;
+        let mut expired = vec![];
+        for ((denied_epoch, author), round) in records {
+            if denied_epoch == epoch {
+                self.denied_authors.insert(author, round);
+            } else {
+                expired.push((denied_epoch, author));
+            }
+        }
+        if !expired.is_empty() {
+            self.mode.handle(
+                self.storage.delete_denied_authors(expired),
+                "delete_denied_authors",
+            )?;
+        }
+        if !self.denied_authors.is_empty() {
+            self.recount_round_power();
+        }
+        Ok(())
+    }
+
+    pub fn epoch_state(&self) -> &Arc<EpochState> {
+        &self.epoch_state
+    }
+
+    /// The authors excluded for equivocating, with the round of their first equivocation.
+    pub fn equivocators(&self) -> &BTreeMap<Author, Round> {
+        &self.equivocators
+    }
+
+    pub fn is_equivocator(&self, author: &Author) -> bool {
+        self.equivocators.contains_key(author)
+    }
+
+    /// Excludes `author` for the rest of the epoch after it equivocated in `round`. The record is
+    /// persisted first so a restart keeps excluding it. Returns whether it wasn't excluded yet.
+    pub fn exclude_equivocator(
+        &mut self,
+        author: &Author,
+        round: Round,
+    ) -> Result<bool, DagStoreError> {
+        if self.is_equivocator(author) {
+            return Ok(false);
+        }
+        self.mode.handle(
+            self.storage
+                .save_equivocator(self.epoch_state.epoch, author, round),
+            "save_equivocator",
+        )?;
+        warn!(
+            "Excluding {} from the DAG of epoch {} for equivocating in round {}",
+            author, self.epoch_state.epoch, round
+        );
+        self.equivocators.insert(*author, round);
+        self.recount_round_power();
+        Ok(true)
+    }
+
+    /// Re-admits `author`, once its equivocation has been dealt with on chain. Every call is
+    /// logged for auditing. Returns whether it was excluded.
+    pub fn clear_equivocator(&mut self, author: &Author) -> Result<bool, DagStoreError> {
+        let Some(round) = self.equivocators.get(author).copied() else {
+            warn!(
+                "Clearing {} from the equivocators of epoch {}: not excluded",
+                author, self.epoch_state.epoch
+            );
+            return Ok(false);
+        };
+        self.mode.handle(
+            self.storage
+                .delete_equivocators(vec![(self.epoch_state.epoch, *author)]),
+            "delete_equivocators",
+        )?;
+        self.equivocators.remove(author);
+        self.recount_round_power();
+        warn!(
+            "Cleared {} from the equivocators of epoch {}, excluded since round {}, highest quorum round now {}",
+            author, self.epoch_state.epoch, round, self.highest_quorum_round
+        );
+        Ok(true)
+    }
+
+    /// The authors denied by governance, with the round of the governance event denying them.
+    pub fn denied_authors(&self) -> &BTreeMap<Author, Round> {
+        &self.denied_authors
+    }
+
+    pub fn is_denied(&self, author: &Author) -> bool {
+        self.denied_authors.contains_key(author)
+    }
+
+    /// Replaces the authors denied by governance, as observed on chain. The new nodes of a newly
+    /// denied author are rejected from the round after `denied_after`, the round the governance
+    /// event takes effect at, its nodes up to it stay valid. The round comes from the event rather
+    /// than the local DAG so every validator draws the same boundary. An author that stays denied
+    /// keeps its round, a removed one is accepted again. The set is persisted first so a restart
+    /// keeps enforcing it.
+    pub fn set_denied_authors(
+        &mut self,
+        authors: HashSet<Author>,
+        denied_after: Round,
+    ) -> Result<(), DagStoreError> {
+        let denied_authors: BTreeMap<_, _> = authors
+            .into_iter()
+            .map(|author| {
+                let round = self
+                    .denied_authors
+                    .get(&author)
+                    .copied()
+                    .unwrap_or(denied_after);
+                (author, round)
+            })
+            .collect();
+        if denied_authors == self.denied_authors {
+            return Ok(());
+        }
+        let epoch = self.epoch_state.epoch;
+        let added: Vec<_> = denied_authors
+            .iter()
+            .filter(|(author, _)| !self.is_denied(author))
+            .map(|(author, round)| (*author, *round))
+            .collect();
+        let removed: Vec<_> = self
+            .denied_authors
+            .keys()
+            .filter(|author| !denied_authors.contains_key(author))
+            .map(|author| (epoch, *author))
+            .collect();
+        // a crash in between keeps a removed author denied rather than accepting a new one
+        if !added.is_empty() {
+            self.mode.handle(
+                self.storage.save_denied_authors(epoch, &added),
+                "save_denied_authors",
+            )?;
+        }
+        if !removed.is_empty() {
+            self.mode.handle(
+                self.storage.delete_denied_authors(removed.clone()),
+                "delete_denied_authors",
+            )?;
+        }
+        self.denied_authors = denied_authors;
+        self.recount_round_power();
+        warn!(
+            "Denied authors of epoch {} changed, denied from the round after {:?}, no longer denied {:?}, excluded from the quorum: {}",
+            epoch,
+            added,
+            removed.iter().map(|(_, author)| author).collect::<Vec<_>>(),
+            self.exclude_denied
+        );
+        Ok(())
+    }
+
+    /// Whether the author's nodes are left out of the voting power and the links, for
+    /// equivocating or for being denied while the quorum is reachable without it.
+    fn is_excluded(&self, author: &Author) -> bool {
+        self.is_equivocator(author) || (self.exclude_denied && self.is_denied(author))
+    }
+
+    /// Records the evidence of a node rejected for equivocating and excludes its author, a
+    /// failure to persist either is only logged as the node is rejected either way.
+    fn record_equivocation(&mut self, node: &CertifiedNode) {
+        let metadata = node.metadata();
+        let kept = self
+            .get_node_by_round_author(metadata.round(), metadata.author())
+            .cloned()
+            .or_else(|| self.reserved_node(node))
+            .filter(|kept| kept.digest() != node.digest());
+        if let Some(kept) = kept {
+            if let Err(e) = self.record_evidence(&kept, node) {
+                warn!(
+                    "Failed to record the equivocation of {} in round {}: {:?}",
+                    metadata.author(),
+                    metadata.round(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = self.exclude_equivocator(metadata.author(), metadata.round()) {
+            warn!(
+                "Failed to exclude equivocator {}: {:?}",
+                metadata.author(),
+                e
+            );
+        }
+    }
+
+    /// Persists the evidence of `conflicting`, with both nodes unless the author already has
+    /// `max_full_records_per_author` full records. A node already recorded is skipped.
+    fn record_evidence(
+        &mut self,
+        kept: &CertifiedNode,
+        conflicting: &CertifiedNode,
+    ) -> Result<(), DagStoreError> {
+        let author = conflicting.metadata().author();
+        let mut num_full_records = 0;
+        for record in &self.equivocation_evidence {
+            if *record.conflicting().digest() == conflicting.digest() {
+                return Ok(());
+            }
+            if record.author() == author && record.is_full() {
+                num_full_records += 1;
+            }
+        }
+        let nodes = if num_full_records < self.evidence_retention.max_full_records_per_author {
+            EvidenceNodes::Full {
+                kept: Box::new(kept.clone()),
+                conflicting: Box::new(conflicting.clone()),
+            }
+        } else {
+            EvidenceNodes::Metadata {
+                kept: kept.metadata().clone(),
+                conflicting: conflicting.metadata().clone(),
+            }
+        };
+        let record = EvidenceRecord::new(self.epoch_state.epoch, nodes);
+        self.mode.handle(
+            self.storage.save_equivocation_evidence(&record),
+            "save_equivocation_evidence",
+        )?;
+        counters::EQUIVOCATION_EVIDENCE_COUNT
+            .with_label_values(&[if record.is_full() { "full" } else { "metadata" }])
+            .inc();
+        self.equivocation_evidence.push(record);
+        Ok(())
+    }
+
+    /// The voting power of the node's author that counts towards its round, none for an excluded
+    /// author.
+    fn counted_power(&self, author: &Author) -> u128 {
+        if self.is_excluded(author) {
+            return 0;
+        }
+        self.epoch_state
+            .verifier
+            .get_voting_power(author)
+            .unwrap_or_default() as u128
+    }
+
+    /// Recomputes the voting power of every round and the highest quorum round after the
+    /// equivocators, the denied authors or the voting powers changed. The denied authors are only
+    /// excluded if the validators that are neither denied nor equivocators have a quorum.
+    fn recount_round_power(&mut self) {
+        let verifier = &self.epoch_state.verifier;
+        let denied_power: u128 = self
+            .denied_authors
+            .keys()
+            .filter(|author| !self.is_equivocator(author))
+            .filter_map(|author| verifier.get_voting_power(author))
+            .map(u128::from)
+            .sum();
+        let equivocator_power: u128 = self
+            .equivocators
+            .keys()
+            .filter_map(|author| verifier.get_voting_power(author))
+            .map(u128::from)
+            .sum();
+        self.exclude_denied = verifier
+            .total_voting_power()
+            .saturating_sub(equivocator_power + denied_power)
+            >= verifier.quorum_voting_power();
+        let (power_by_round, highest_quorum_round) = self.derive_round_power();
+        if highest_quorum_round > self.highest_quorum_round {
+            self.round_advance.send_replace(Some(RoundAdvance {
+                round: highest_quorum_round,
+                reached_at: self.time_service.get_current_timestamp(),
+            }));
+        }
+        let recounted: BTreeSet<_> = power_by_round
+            .keys()
+            .chain(self.power_by_round.keys())
+            .copied()
+            .filter(|round| {
+                let counted =
+                    |power: &BTreeMap<Round, u128>| power.get(round).copied().unwrap_or(0);
+                counted(&power_by_round) != counted(&self.power_by_round)
+            })
+            .collect();
+        for round in recounted {
+            self.bump_generation(round);
+        }
+        self.power_by_round = power_by_round;
+        self.highest_quorum_round = highest_quorum_round;
+    }
+
+    /// The voting power of every round and the highest quorum round, from the nodes.
+    fn derive_round_power(&self) -> (BTreeMap<Round, u128>, Round) {
+        let power_by_round: BTreeMap<_, _> = self
+            .nodes_by_round
+            .iter()
+            .map(|(round, slots)| {
+                let power: u128 = slots
+                    .iter()
+                    .flatten()
+                    .map(|status| self.counted_power(status.as_node().metadata().author()))
+                    .sum();
+                (*round, power)
+            })
+            .filter(|(_, power)| *power > 0)
+            .collect();
+        let quorum = self.epoch_state.verifier.quorum_voting_power();
+        let highest_quorum_round = power_by_round
+            .iter()
+            .rev()
+            .find(|(_, power)| **power >= quorum)
+            .map_or(0, |(round, _)| *round);
+        (power_by_round, highest_quorum_round)
+    }
+
+    pub fn validator_index(&self) -> &Arc<ValidatorIndex> {
+        &self.validator_index
+    }
+
+    /// Index of an author of a node that passed validation.
+    fn author_index(&self, author: &Author) -> usize {
+        self.validator_index
+            .index_of(author)
+            .expect("Author should be in the validator set")
+    }
+
+    pub fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
+
+    pub fn is_observer(&self) -> bool {
+        self.observer.is_some()
+    }
+
+    /// Swaps in new voting powers within the epoch, the validators and their indices must stay
+    /// the same as the nodes are indexed by them. The power of every round is recounted with the
+    /// new weights, a round that reaches a quorum with them advances the round.
+    pub fn replace_epoch_state(
+        &mut self,
+        epoch_state: Arc<EpochState>,
+    ) -> Result<(), DagStoreError> {
+        if epoch_state.epoch != self.epoch_state.epoch {
+            return Err(DagStoreError::EpochMismatch {
+                epoch: epoch_state.epoch,
+                expected: self.epoch_state.epoch,
+            });
+        }
+        if !self.validator_index.matches(&epoch_state.verifier) {
+            return Err(DagStoreError::ValidatorSetChanged);
+        }
+        self.epoch_state = epoch_state;
+        self.recount_round_power();
+        Ok(())
+    }
+
+    /// Records the progress of the catch-up, reported by `health` until it's cleared with `None`.
+    pub fn set_catch_up_progress(&mut self, progress: Option<CatchUpProgress>) {
+        self.catch_up_progress = progress;
+    }
+
+    pub fn catch_up_progress(&self) -> Option<CatchUpProgress> {
+        self.catch_up_progress
+    }
+
+    /// Sets the soft memory limit in bytes, going over it turns on backpressure.
+    pub fn set_memory_budget(&mut self, memory_budget: usize) {
+        self.memory_budget = memory_budget;
+        self.update_memory_budget_flag();
+    }
+
+    /// The retained evidence of the equivocations of `author`, in the order they were found.
+    pub fn equivocation_evidence(&self, author: &Author) -> Vec<EvidenceRecord> {
+        self.equivocation_evidence
+            .iter()
+            .filter(|record| record.author() == author)
+            .cloned()
+            .collect()
+    }
+
+    /// Flags the evidence of the conflicting node with `digest` as pending report, so pruning
+    /// keeps it, or clears the flag once reported. Returns whether the evidence is retained.
+    pub fn set_evidence_pending_report(
+        &mut self,
+        digest: &HashValue,
+        pending_report: bool,
+    ) -> Result<bool, DagStoreError> {
+        let Some(record) = self
+            .equivocation_evidence
+            .iter_mut()
+            .find(|record| record.conflicting().digest() == digest)
+        else {
+            return Ok(false);
+        };
+        if record.pending_report() != pending_report {
+            // the flag only changes once persisted
+            let mut updated = record.clone();
+            updated.set_pending_report(pending_report);
+            self.mode.handle(
+                self.storage.save_equivocation_evidence(&updated),
+                "save_equivocation_evidence",
+            )?;
+            *record = updated;
+        }
+        Ok(true)
+    }
+
+    /// Sets how much evidence is kept, the records already retained are left as they are.
+    pub fn set_evidence_retention(&mut self, evidence_retention: EvidenceRetention) {
+        self.evidence_retention = evidence_retention;
+    }
+
+    pub fn evidence_retention(&self) -> EvidenceRetention {
+        self.evidence_retention
+    }
+
+    pub fn evidence_usage(&self) -> EvidenceUsage {
+        let mut usage = EvidenceUsage {
+            num_records: self.equivocation_evidence.len(),
+            ..EvidenceUsage::default()
+        };
+        for record in &self.equivocation_evidence {
+            usage.num_pending_report += usize::from(record.pending_report());
+            usage.bytes += match record.nodes() {
+                EvidenceNodes::Full { kept, conflicting } => {
+                    usage.num_full_records += 1;
+                    estimate_node_size(kept) + estimate_node_size(conflicting)
+                },
+                EvidenceNodes::Metadata { .. } => 2 * std::mem::size_of::<NodeMetadata>(),
+            };
+        }
+        usage
+    }
+
+    /// Sets how many rounds an author can be ahead or behind the median author before
+    /// `check_author_skew` flags it.
+    pub fn set_skew_threshold(&mut self, skew_threshold: Round) {
+        self.skew_threshold = skew_threshold;
+    }
+
+    /// Sets how many rounds above the highest round a node can be and still be parked by
+    /// `insert_node`. At least 1, the gap of the next round.
+    pub fn set_park_gap(&mut self, park_gap: Round) {
+        self.park_gap = park_gap.max(1);
+    }
+
+    pub fn park_gap(&self) -> Round {
+        self.park_gap
+    }
+
+    /// Sets how much earlier than its latest parent a node can be and still be added, 0 requires
+    /// every node to be strictly later than its parents.
+    pub fn set_timestamp_skew_tolerance(&mut self, tolerance: Duration) {
+        self.timestamp_skew_tolerance = tolerance;
+    }
+
+    /// Sets how far ahead of local time the timestamp of a node can be.
+    pub fn set_max_clock_drift(&mut self, max_drift: Duration) {
+        self.max_clock_drift = max_drift;
+    }
+
+    /// Each author's highest round minus the median highest round across authors, the lower
+    /// median for an even number of validators. Authors without nodes count as round 0. A
+    /// positive skew means we mostly hear from that author, a negative one that we rarely do,
+    /// which usually points at our own connectivity. In validator index order.
+    pub fn author_skew(&self) -> Vec<(Author, i64)> {
+        let mut sorted = self.highest_round_by_author.clone();
+        sorted.sort_unstable();
+        let median = match sorted.len() {
+            0 => return vec![],
+            len => sorted[(len - 1) / 2] as i64,
+        };
+        self.validator_index
+            .authors()
+            .iter()
+            .zip(&self.highest_round_by_author)
+            .map(|(author, round)| (*author, *round as i64 - median))
+            .collect()
+    }
+
+    /// Updates the skew gauge of every author and warns about the authors whose skew is beyond the
+    /// threshold, which are returned.
+    pub fn check_author_skew(&self) -> Vec<(Author, i64)> {
+        let threshold = self.skew_threshold as i64;
+        let mut flagged = vec![];
+        for (author, skew) in self.author_skew() {
+            counters::AUTHOR_SKEW
+                .with_label_values(&[&author.to_string()])
+                .set(skew);
+            // checked on every commit, the gauge has the skew in between the sampled warnings
+            if skew > threshold {
+                sample!(
+                    SampleRate::Duration(Duration::from_secs(10)),
+                    warn!(
+                        "Author {} is {} rounds ahead of the median author, we may only hear from it",
+                        author, skew
+                    )
+                );
+                flagged.push((author, skew));
+            } else if skew < -threshold {
+                sample!(
+                    SampleRate::Duration(Duration::from_secs(10)),
+                    warn!(
+                        "Author {} is {} rounds behind the median author, we may never hear from it",
+                        author, -skew
+                    )
+                );
+                flagged.push((author, skew));
+            }
+        }
+        flagged
+    }
+
+    /// Sets how much history is kept when rounds are committed, by default the window of the
+    /// config, see `DagStoreConfig::pruning_policy`.
+    pub fn set_pruning_policy(&mut self, pruning_policy: Arc<dyn DagPruningPolicy>) {
+        self.pruning_policy = pruning_policy;
+    }
+
+    pub fn memory_usage(&self) -> DagMemoryUsage {
+        self.memory_usage
+    }
+
+    pub fn over_memory_budget(&self) -> bool {
+        self.over_memory_budget
+    }
+
+    /// Whether the node should slow down proposing new nodes, observers don't propose.
+    pub fn backpressure(&self) -> bool {
+        self.observer.is_none() && self.over_memory_budget
+    }
+
+    /// One verdict out of the quorum round progress, the backpressure and memory budget, the
+    /// pending buffer, the catch-up and the absent authors. The verdict only changes when an issue
+    /// does, not as time passes within it.
+    pub fn health(&self, now: Duration, config: &DagHealthConfig) -> DagHealth {
+        let (round, advanced_at) = match *self.round_advance.borrow() {
+            Some(advance) => (advance.round, advance.reached_at),
+            None => (0, self.started_at),
+        };
+        let stuck_for = now.saturating_sub(advanced_at);
+        let stuck = DagHealthIssue::QuorumRoundStuck {
+            round,
+            since: advanced_at,
+        };
+        if stuck_for >= config.quorum_stalled_after {
+            return DagHealth::Stalled(stuck);
+        }
+        let mut issues = vec![];
+        if stuck_for >= config.quorum_slow_after {
+            issues.push(stuck);
+        }
+        if self.backpressure() {
+            issues.push(DagHealthIssue::Backpressure);
+        } else if self.over_memory_budget {
+            issues.push(DagHealthIssue::OverMemoryBudget {
+                total_bytes: self.memory_usage.total_bytes(),
+                memory_budget: self.memory_budget,
+            });
+        }
+        let num_pending = self.pending_nodes_count();
+        if num_pending >= config.max_pending_nodes {
+            issues.push(DagHealthIssue::PendingBufferFull { num_pending });
+        }
+        if self.catch_up_progress.is_some() {
+            issues.push(DagHealthIssue::CatchingUp);
+        }
+        let authors: Vec<_> = self
+            .validator_index
+            .authors()
+            .iter()
+            .zip(&self.highest_round_by_author)
+            .filter(|(_, highest_round)| {
+                **highest_round + config.absent_rounds < self.highest_quorum_round
+            })
+            .map(|(author, _)| *author)
+            .collect();
+        if authors.len() > config.max_absent_authors {
+            issues.push(DagHealthIssue::AbsentAuthors { authors });
+        }
+        // a single slow anchor is no trend, only a full window counts
+        let latency = self.commit_latency();
+        if latency.num_anchors == COMMIT_LATENCY_WINDOW
+            && latency.total_rounds > config.max_commit_latency_rounds * latency.num_anchors as u64
+        {
+            issues.push(DagHealthIssue::CommitLatencyHigh { latency });
+        }
+        if issues.is_empty() {
+            DagHealth::Healthy
+        } else {
+            DagHealth::Degraded(issues)
+        }
+    }
+
+    /// Stops accepting nodes at the end of the epoch and returns the final summary. Nodes are
+    /// persisted as they're added so there is nothing to flush, the storage of the epoch is
+    /// cleaned up by the DAG of the next epoch when it's recovered.
+    pub fn finalize(&mut self) -> DagStateSummary {
+        self.ended = true;
+        self.summary()
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Ends the epoch like `finalize` and hands out the remnant of the epoch, the anchors ordered
+    /// above the committed round with the nodes they ordered. The remnant is persisted before
+    /// it's returned, the caller drives it to commitment, or hands it to state sync, and deletes
+    /// it with `DAGStorage::delete_epoch_remnant`. After a restart every recovered anchor is
+    /// above the committed round until the next `commit_callback`, so the caller skips the
+    /// anchors it already committed. A node already pruned is left out, its source stays in the
+    /// anchor record.
+    pub fn finalize_epoch(&mut self) -> Result<EpochRemnant, DagStoreError> {
+        self.ended = true;
+        let mut anchors: Vec<_> = self.uncommitted_anchors().cloned().collect();
+        anchors.sort_by_key(|ordered_anchor| {
+            let anchor = ordered_anchor.anchor();
+            (
+                anchor.round(),
+                self.validator_index.order_key(anchor.author()),
+                *anchor.digest(),
+            )
+        });
+        let mut nodes = vec![];
+        for source in anchors.iter().flat_map(OrderedAnchor::sources) {
+            match self.nodes_by_digest.get(source.digest()) {
+                Some(node) => nodes.push(node.as_ref().clone()),
+                None => warn!(
+                    "Ordered node {} of round {} is pruned, left out of the epoch remnant",
+                    source.digest(),
+                    source.round()
+                ),
+            }
+        }
+        let remnant = EpochRemnant::new(self.epoch_state.epoch, anchors, nodes);
+        if !remnant.is_empty() {
+            self.mode.handle(
+                self.storage.save_epoch_remnant(&remnant),
+                "save_epoch_remnant",
+            )?;
+        }
+        self.remnant_taken = true;
+        Ok(remnant)
+    }
+
+    /// Fails while anchors are ordered and not committed and `finalize_epoch` hasn't handed them
+    /// out, the DAG of the next epoch must not replace this one until it has.
+    pub fn check_remnant_taken(&self) -> Result<(), DagStoreError> {
+        let num_anchors = self.uncommitted_anchors().count();
+        if self.remnant_taken || num_anchors == 0 {
+            Ok(())
+        } else {
+            Err(DagStoreError::RemnantNotTaken {
+                epoch: self.epoch_state.epoch,
+                num_anchors,
+            })
+        }
+    }
+
+    fn uncommitted_anchors(&self) -> impl Iterator<Item = &OrderedAnchor> {
+        let committed_round = self.committed_round;
+        self.ordered_anchors
+            .values()
+            .filter(move |ordered_anchor| ordered_anchor.anchor().round() > committed_round)
+    }
+
+    /// The totals of the epoch so far, with the equivocations found at recovery.
+    pub fn epoch_summary(&self) -> DagEpochSummary {
+        let highest_round = self.highest_round();
+        let num_rounds = if highest_round >= self.epoch_start_round {
+            highest_round - self.epoch_start_round + 1
+        } else {
+            0
+        };
+        let totals = &self.epoch_totals;
+        let nodes_by_author: BTreeMap<_, _> = self
+            .validator_index
+            .authors()
+            .iter()
+            .zip(&totals.nodes_by_author)
+            .map(|(author, num_nodes)| (*author, *num_nodes))
+            .collect();
+        let missed_rounds_by_author = nodes_by_author
+            .iter()
+            .map(|(author, num_nodes)| (*author, num_rounds.saturating_sub(*num_nodes)))
+            .collect();
+        DagEpochSummary {
+            epoch: self.epoch_state.epoch,
+            num_nodes: totals.nodes_by_author.iter().sum(),
+            num_rounds,
+            nodes_by_author,
+            missed_rounds_by_author,
+            num_equivocations: self.num_equivocations() as u64,
+            num_ordered_anchors: totals.num_ordered_anchors,
+            num_skipped_anchors: totals.num_skipped_anchors,
+            total_commit_latency_rounds: totals.total_commit_latency_rounds,
+            pruned_bytes: totals.pruned_bytes,
+        }
+    }
+
+    /// The conflicting nodes rejected in the epoch or found in the retained evidence.
+    fn num_equivocations(&self) -> usize {
+        let mut digests = self.epoch_totals.equivocations.lock().clone();
+        digests.extend(
+            self.equivocation_evidence
+                .iter()
+                .map(|record| *record.conflicting().digest()),
+        );
+        digests.len()
+    }
+
+    pub fn summary(&self) -> DagStateSummary {
+        DagStateSummary {
+            lowest_round: self.lowest_round(),
+            highest_round: self.highest_round(),
+            memory_usage: self.memory_usage,
+            over_memory_budget: self.over_memory_budget,
+            commit_latency: self.commit_latency(),
+        }
+    }
+
+    fn account_node_added(&mut self, slot: SlotId, node: &CertifiedNode) {
+        self.reference_parents(node);
+        let nodes_by_digest = &self.nodes_by_digest;
+        index_children(&mut self.children, slot, node, |digest| {
+            nodes_by_digest.contains_key(digest)
+        });
+        let metadata = node.metadata();
+        if let Some(index) = self.validator_index.index_of(metadata.author()) {
+            index_author_round(
+                &mut self.highest_round_by_author,
+                &mut self.epoch_totals.nodes_by_author,
+                index,
+                metadata.round(),
+            );
+        }
+        let power = self.counted_power(metadata.author());
+        let quorum = self.epoch_state.verifier.quorum_voting_power();
+        let round_power = index_round_power(&mut self.power_by_round, metadata.round(), power);
+        if round_power >= quorum && metadata.round() > self.highest_quorum_round {
+            self.highest_quorum_round = metadata.round();
+            self.round_advance.send_replace(Some(RoundAdvance {
+                round: metadata.round(),
+                reached_at: self.time_service.get_current_timestamp(),
+            }));
+        }
+        index_node_bytes(&mut self.memory_usage, &mut self.bytes_by_round, node);
+        self.update_memory_budget_flag();
+    }
+
+    fn account_node_removed(&mut self, node: &CertifiedNode) {
+        self.children.remove(&node.digest());
+        if let Ok(slot) = self.slot_of(node.metadata().round(), node.metadata().author()) {
+            for parent in node.parents() {
+                let digest = parent.metadata().digest();
+                if let Some(children) = self.children.get_mut(digest) {
+                    children.remove(&slot);
+                    if children.is_empty() {
+                        self.children.remove(digest);
+                    }
+                }
+            }
+        }
+        let bytes = estimate_node_size(node);
+        self.memory_usage.num_nodes -= 1;
+        self.memory_usage.node_bytes -= bytes;
+        let round = node.metadata().round();
+        if let Some(round_bytes) = self.bytes_by_round.get_mut(&round) {
+            *round_bytes -= bytes;
+            if *round_bytes == 0 {
+                self.bytes_by_round.remove(&round);
+            }
+        }
+        let power = self.counted_power(node.metadata().author());
+        if let Some(round_power) = self.power_by_round.get_mut(&round) {
+            *round_power -= power;
+            if *round_power == 0 {
+                self.power_by_round.remove(&round);
+            }
+        }
+        let quorum = self.epoch_state.verifier.quorum_voting_power();
+        if round == self.highest_quorum_round
+            && self.power_by_round.get(&round).copied().unwrap_or_default() < quorum
+        {
+            self.highest_quorum_round = self
+                .power_by_round
+                .iter()
+                .rev()
+                .find(|(_, power)| **power >= quorum)
+                .map_or(0, |(round, _)| *round);
+        }
+        self.update_memory_budget_flag();
+    }
+
+    fn account_pending_added(&mut self, node: &CertifiedNode) {
+        self.reference_parents(node);
+        self.memory_usage.num_pending_nodes += 1;
+        self.memory_usage.pending_bytes += estimate_node_size(node);
+        self.update_memory_budget_flag();
+    }
+
+    fn account_pending_removed(&mut self, node: &CertifiedNode) {
+        self.memory_usage.num_pending_nodes -= 1;
+        self.memory_usage.pending_bytes -= estimate_node_size(node);
+        self.update_memory_budget_flag();
+    }
+
+    fn update_memory_budget_flag(&mut self) {
+        let over_memory_budget = self.memory_usage.total_bytes() > self.memory_budget;
+        if over_memory_budget && !self.over_memory_budget {
+            let mut top_rounds: Vec<_> = self.bytes_by_round.iter().collect();
+            top_rounds.sort_by(|(_, a), (_, b)| b.cmp(a));
+            top_rounds.truncate(NUM_TOP_MEMORY_ROUNDS);
+            warn!(
+                "DAG is over its memory budget of {} bytes, usage: {:?}, top rounds: {:?}",
+                self.memory_budget, self.memory_usage, top_rounds
+            );
+        }
+        self.over_memory_budget = over_memory_budget;
+    }
+
+    /// Recomputes the memory usage from scratch, to check the incremental accounting.
+    #[cfg(test)]
+    pub(crate) fn recompute_memory_usage(&self) -> DagMemoryUsage {
+        let nodes = self
+            .nodes_by_round
+            .values()
+            .flatten()
+            .flatten()
+            .map(NodeStatus::as_node);
+        let pending = self.pending_nodes.values().flat_map(BTreeMap::values);
+        DagMemoryUsage {
+            num_nodes: nodes.clone().count(),
+            node_bytes: nodes.map(|node| estimate_node_size(node)).sum(),
+            num_pending_nodes: pending.clone().count(),
+            pending_bytes: pending.map(estimate_node_size).sum(),
+        }
+    }
+
+    /// Checks the incrementally maintained indexes against the nodes of the DAG and of the pending
+    /// buffer, the per-round voting power, the references of the parents and the children index
+    /// are derived again from scratch. Returns every violation found.
+    #[cfg(test)]
+    pub(crate) fn verify_consistency(&self) -> Result<(), Vec<String>> {
+        let mut violations = vec![];
+        let lowest_round = self.lowest_round();
+        let mut num_nodes = 0;
+        for (round, slots) in &self.nodes_by_round {
+            for (index, status) in slots.iter().enumerate() {
+                let node = match status {
+                    Some(status) => status.as_node(),
+                    None => continue,
+                };
+                num_nodes += 1;
+                let metadata = node.metadata();
+                let author_index = self.validator_index.index_of(metadata.author());
+                if metadata.round() != *round || author_index != Some(index) {
+                    violations.push(format!(
+                        "node {} of round {} by {} is in slot {} of round {}",
+                        node.digest(),
+                        metadata.round(),
+                        metadata.author(),
+                        index,
+                        round
+                    ));
+                }
+                if !self.nodes_by_digest.contains_key(&node.digest()) {
+                    violations.push(format!(
+                        "node {} of round {} isn't indexed by digest",
+                        node.digest(),
+                        round
+                    ));
+                }
+                if let Some(highest_round) = author_index.map(|i| self.highest_round_by_author[i]) {
+                    if highest_round < metadata.round() {
+                        violations.push(format!(
+                            "highest round of {} is {}, it has a node in round {}",
+                            metadata.author(),
+                            highest_round,
+                            metadata.round()
+                        ));
+                    }
+                }
+                for parent in node.parents() {
+                    let parent = parent.metadata();
+                    if parent.round() >= lowest_round
+                        && !self.nodes_by_digest.contains_key(parent.digest())
+                    {
+                        violations.push(format!(
+                            "parent {} of round {} of node {} is missing",
+                            parent.digest(),
+                            parent.round(),
+                            node.digest()
+                        ));
+                    }
+                }
+            }
+        }
+        if num_nodes != self.nodes_by_digest.len() {
+            violations.push(format!(
+                "{} nodes indexed by digest, {} by round",
+                self.nodes_by_digest.len(),
+                num_nodes
+            ));
+        }
+        let memory_usage = self.recompute_memory_usage();
+        if memory_usage != self.memory_usage {
+            violations.push(format!(
+                "memory usage is {:?}, recomputed {:?}",
+                self.memory_usage, memory_usage
+            ));
+        }
+
+        let (power_by_round, highest_quorum_round) = self.derive_round_power();
+        let rounds: BTreeSet<_> = power_by_round
+            .keys()
+            .chain(self.power_by_round.keys())
+            .collect();
+        for round in rounds {
+            let cached = self.power_by_round.get(round).filter(|power| **power > 0);
+            let derived = power_by_round.get(round);
+            if cached != derived {
+                violations.push(format!(
+                    "voting power of round {} is {:?}, recomputed {:?}",
+                    round, cached, derived
+                ));
+            }
+        }
+        if highest_quorum_round != self.highest_quorum_round {
+            violations.push(format!(
+                "highest quorum round is {}, recomputed {}",
+                self.highest_quorum_round, highest_quorum_round
+            ));
+        }
+
+        // the references below the lowest or the pruned round can outlive the nodes linking to them
+        let floor = lowest_round.max(self.pruned_digests.pruned_below());
+        let mut linked: BTreeMap<(Round, Author), BTreeSet<HashValue>> = BTreeMap::new();
+        let nodes = self
+            .nodes_by_round
+            .values()
+            .flat_map(|slots| slots.iter().flatten().map(|status| &**status.as_node()));
+        for node in nodes.chain(self.pending_nodes.values().flat_map(BTreeMap::values)) {
+            for parent in node.parents() {
+                let parent = parent.metadata();
+                if parent.round() >= floor {
+                    linked
+                        .entry((parent.round(), *parent.author()))
+                        .or_default()
+                        .insert(*parent.digest());
+                }
+            }
+        }
+        for (round, slots) in self.referenced_digests.range(floor..) {
+            let mut slots: Vec<_> = slots.iter().collect();
+            slots.sort_by_key(|(author, _)| self.validator_index.order_key(author));
+            for (author, digest) in slots {
+                match linked.get(&(*round, *author)) {
+                    None => violations.push(format!(
+                        "stale reference to {} for the slot of {} in round {}, no node links to it",
+                        digest, author, round
+                    )),
+                    Some(digests) if !digests.contains(digest) => violations.push(format!(
+                        "reference to {} for the slot of {} in round {}, the nodes link to {:?}",
+                        digest, author, round, digests
+                    )),
+                    Some(_) => {},
+                }
+            }
+        }
+        for ((round, author), digests) in &linked {
+            let referenced = self
+                .referenced_digests
+                .get(round)
+                .map_or(false, |slots| slots.contains_key(author));
+            if !referenced {
+                violations.push(format!(
+                    "missing reference to {:?} for the slot of {} in round {}",
+                    digests, author, round
+                ));
+            }
+        }
+
+        let children = rebuild_children(&self.nodes_by_round);
+        let digests: BTreeSet<_> = children.keys().chain(self.children.keys()).collect();
+        for digest in digests {
+            let indexed = self.children.get(digest).filter(|slots| !slots.is_empty());
+            let derived = children.get(digest);
+            match (indexed, derived) {
+                (Some(indexed), None) => violations.push(format!(
+                    "stale children {:?} of {}, no node of the DAG links to it",
+                    indexed, digest
+                )),
+                (indexed, Some(derived)) if indexed != Some(derived) => violations.push(format!(
+                    "children of {} are {:?}, recomputed {:?}",
+                    digest, indexed, derived
+                )),
+                _ => {},
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Leaves the stale reference of a node that was never in the DAG, to check it's caught.
+    #[cfg(test)]
+    pub(crate) fn insert_stale_reference(&mut self, metadata: &NodeMetadata) {
+        self.referenced_digests
+            .entry(metadata.round())
+            .or_default()
+            .insert(*metadata.author(), *metadata.digest());
+    }
+
+    /// Leaves a stale child of a node, as if the child had been removed without unlinking it.
+    #[cfg(test)]
+    pub(crate) fn insert_stale_child(&mut self, digest: HashValue, slot: SlotId) {
+        self.children.entry(digest).or_default().insert(slot);
+    }
+
+    /// A cursor over the rounds currently in the DAG, see `ExportCursor`.
+    pub fn export_cursor(&self) -> ExportCursor {
+        let floor = self.lowest_round();
+        let end = if self.nodes_by_round.is_empty() {
+            floor
+        } else {
+            self.highest_round() + 1
+        };
+        let generations = (floor..end)
+            .map(|round| (round, self.round_generation(round)))
+            .collect();
+        ExportCursor {
+            floor,
+            end,
+            generations,
+            next: floor,
+        }
+    }
+
+    /// Generation of the last mutation of `round`, it changes if and only if what can be read of
+    /// the round changed: a node inserted, a node ordered, the voting power counted in the
+    /// round, or the round pruned or reset. A rejected or duplicate insert leaves it as is, so a
+    /// cache of anything derived from the round is valid as long as the generation it was
+    /// computed at is. Generations only grow, a round never mutated since the DAG was created or
+    /// recovered is at 0, and a pruned round, which can't change anymore, at `PRUNED_GENERATION`.
+    pub fn round_generation(&self, round: Round) -> u64 {
+        if round < self.pruned_below {
+            return PRUNED_GENERATION;
+        }
+        self.round_generations.get(&round).copied().unwrap_or(0)
+    }
+
+    /// Generation of the last mutation of any round, it changes whenever a `round_generation`
+    /// does.
+    pub fn generation(&self) -> u64 {
+        self.last_generation
+    }
+
+    fn bump_generation(&mut self, round: Round) {
+        self.last_generation += 1;
+        self.round_generations.insert(round, self.last_generation);
+    }
+
+    pub(crate) fn lowest_round(&self) -> Round {
+        *self
+            .nodes_by_round
+            .first_key_value()
+            .map(|(round, _)| round)
+            .unwrap_or(&0)
+    }
+
+    pub fn epoch_start_round(&self) -> Round {
+        self.epoch_start_round
+    }
+
+    /// Pins the round of the parentless nodes of the epoch, persisted so it survives a restart.
+    pub fn set_epoch_start_round(&mut self, round: Round) -> Result<(), DagStoreError> {
+        self.mode.handle(
+            self.storage
+                .save_epoch_start_round(self.epoch_state.epoch, round),
+            "save_epoch_start_round",
+        )?;
+        self.epoch_start_round = round;
+        Ok(())
+    }
+
+    /// Whether the node is one of the parentless nodes the epoch starts with.
+    pub fn is_epoch_root(&self, metadata: &NodeMetadata) -> bool {
+        metadata.epoch() == self.epoch_state.epoch && metadata.round() == self.epoch_start_round
+    }
+
+    /// The highest round whose nodes have quorum voting power, 0 if there is none.
+    pub fn highest_quorum_round(&self) -> Round {
+        self.highest_quorum_round
+    }
+
+    /// Subscribes to the advances of `highest_quorum_round`.
+    pub fn round_advance_signal(&self) -> RoundAdvanceSignal {
+        RoundAdvanceSignal {
+            receiver: self.round_advance.subscribe(),
+        }
+    }
+
+    pub fn highest_round(&self) -> Round {
+        *self
+            .nodes_by_round
+            .last_key_value()
+            .map(|(round, _)| round)
+            .unwrap_or(&0)
+    }
+
+    pub fn add_node(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        let span = debug_span!(
+            "dag::add_node",
+            round = node.metadata().round(),
+            author_index = field::Empty,
+            parents = node.parents().len(),
+            payload_bytes = node.payload().size(),
+        );
+        let _entered = span.enter();
+        let node = Arc::new(node);
+        let slot = match debug_span!("dag::validate").in_scope(|| self.validate_new_node(&node)) {
+            Ok(slot) => slot,
+            Err(DagStoreError::EquivocateNode) => {
+                self.record_equivocation(&node);
+                return Err(DagStoreError::EquivocateNode);
+            },
+            Err(e) => return Err(e),
+        };
+        span.record("author_index", slot.position());
+        debug_span!("dag::save_certified_node")
+            .in_scope(|| self.storage.save_certified_node(&node))?;
+        self.link_node(slot, node);
+        Ok(())
+    }
+
+    /// Inserts the node on behalf of concurrent callers, through a shared reference so the
+    /// inserters of distinct slots proceed in parallel. The slot is claimed atomically in
+    /// `reserved_slots`, so of the callers inserting the same node only one persists it. A written
+    /// node counts as present for the insertions that follow and as a parent of their nodes,
+    /// readers only see it once `link_inserted` links it in. A failed write is queued for
+    /// `retry_writes` with the slot still claimed and fails with `WriteRetrying`, the claim is
+    /// only released once the retries are exhausted. Inserting a node that is already present or
+    /// written succeeds with that node, one being written by another caller fails with
+    /// `WriteRetrying` until the write lands.
+    pub fn insert(&self, node: CertifiedNode) -> Result<Arc<CertifiedNode>, DagStoreError> {
+        let node = Arc::new(node);
+        let slot = match self.claim_slot(&node)? {
+            Claim::Claimed(slot) => slot,
+            Claim::Inserted(existing) => return Ok(existing),
+        };
+        let saved = self.storage.save_certified_node(&node);
+        self.finish_write(node, slot, saved)
+    }
+
+    /// Like `insert` for the DAG behind its lock, which is only held to claim the slot and to
+    /// publish the write. Writers aren't held up by the storage write, a node the DAG no longer
+    /// accepts once written is deleted again.
+    pub fn insert_shared(
+        dag: &RwLock<Self>,
+        node: CertifiedNode,
+    ) -> Result<Arc<CertifiedNode>, DagStoreError> {
+        let node = Arc::new(node);
+        let (slot, storage) = {
+            let dag_reader = dag.read();
+            match dag_reader.claim_slot(&node)? {
+                Claim::Claimed(slot) => (slot, dag_reader.storage.clone()),
+                Claim::Inserted(existing) => return Ok(existing),
+            }
+        };
+        let saved = storage.save_certified_node(&node);
+        dag.read().finish_write(node, slot, saved)
+    }
+
+    /// Validates the node and claims its slot for it, unless it's already inserted.
+    fn claim_slot(&self, node: &Arc<CertifiedNode>) -> Result<Claim, DagStoreError> {
+        let digest = node.digest();
+        if let Some(existing) = self.get_node(&digest) {
+            return Ok(Claim::Inserted(existing));
+        }
+        let slot = match self.validate_new_node(node) {
+            Ok(slot) => slot,
+            Err(DagStoreError::DuplicateNode) => {
+                return self.inserted_copy(node).map(Claim::Inserted)
+            },
+            Err(DagStoreError::EquivocateNode) => {
+                self.rejected_equivocations.lock().push(node.clone());
+                return Err(DagStoreError::EquivocateNode);
+            },
+            Err(e) => return Err(e),
+        };
+        match self.reserved_slots.entry(slot) {
+            Entry::Occupied(claim) if claim.get().node.digest() == digest => {
+                match claim.get().written {
+                    true => Ok(Claim::Inserted(claim.get().node.clone())),
+                    false => Err(DagStoreError::WriteRetrying(digest)),
+                }
+            },
+            Entry::Occupied(claim) => {
+                // claimed by another inserter since the validation
+                drop(claim);
+                self.epoch_totals.equivocations.lock().insert(digest);
+                self.rejected_equivocations.lock().push(node.clone());
+                Err(DagStoreError::EquivocateNode)
+            },
+            Entry::Vacant(claim) => {
+                claim.insert(SlotClaim {
+                    node: node.clone(),
+                    written: false,
+                });
+                Ok(Claim::Claimed(slot))
+            },
+        }
+    }
+
+    /// The node already present or written in the slot of `node`, `WriteRetrying` while it's
+    /// still being written.
+    fn inserted_copy(&self, node: &CertifiedNode) -> Result<Arc<CertifiedNode>, DagStoreError> {
+        if let Some(existing) = self.get_node(&node.digest()) {
+            return Ok(existing);
+        }
+        let slot = self.slot_of(node.metadata().round(), node.metadata().author())?;
+        match self.reserved_slots.get(&slot) {
+            Some(claim) if claim.node.digest() == node.digest() && claim.written => {
+                Ok(claim.node.clone())
+            },
+            Some(claim) if claim.node.digest() == node.digest() => {
+                Err(DagStoreError::WriteRetrying(node.digest()))
+            },
+            _ => Err(DagStoreError::DuplicateNode),
+        }
+    }
+
+    /// Publishes the write of the node claiming the slot, or queues it for a retry if it failed.
+    fn finish_write(
+        &self,
+        node: Arc<CertifiedNode>,
+        slot: SlotId,
+        saved: anyhow::Result<()>,
+    ) -> Result<Arc<CertifiedNode>, DagStoreError> {
+        let digest = node.digest();
+        if let Err(e) = saved {
+            let mut write_retries = self.write_retries.lock();
+            if write_retries.capacity() == 0 {
+                self.reserved_slots.remove(&slot);
+                return Err(e.into());
+            }
+            warn!("Failed to write node {}, queued for retry: {:?}", digest, e);
+            let now = self.time_service.get_current_timestamp();
+            if let Some(evicted) = write_retries.push(node, slot, now) {
+                self.give_up_write(evicted, "evicted");
+            }
+            return Err(DagStoreError::WriteRetrying(digest));
+        }
+        self.publish_write(node, slot)
+    }
+
+    /// Marks the persisted node as written in its slot. The claim is gone if the DAG was reset
+    /// since the node was claimed, then the node is claimed again, or deleted if the DAG no
+    /// longer accepts it.
+    fn publish_write(
+        &self,
+        node: Arc<CertifiedNode>,
+        slot: SlotId,
+    ) -> Result<Arc<CertifiedNode>, DagStoreError> {
+        let claimed = self
+            .reserved_slots
+            .get(&slot)
+            .map_or(false, |claim| claim.node.digest() == node.digest());
+        let slot = match claimed {
+            true => slot,
+            false => match self.claim_slot(&node) {
+                Ok(Claim::Claimed(slot)) => slot,
+                Ok(Claim::Inserted(existing)) => return Ok(existing),
+                Err(e) => {
+                    self.delete_rejected_node(&node);
+                    return Err(e);
+                },
+            },
+        };
+        self.mark_written(slot);
+        Ok(node)
+    }
+
+    fn delete_rejected_node(&self, node: &CertifiedNode) {
+        if let Err(e) = delete_or_queue(
+            self.storage.as_ref(),
+            self.mode,
+            vec![node.digest()],
+            "delete_rejected_node",
+        ) {
+            warn!("Failed to delete rejected node {}: {:?}", node.digest(), e);
+        }
+    }
+
+    /// Marks the node claiming the slot as persisted.
+    fn mark_written(&self, slot: SlotId) {
+        if let Some(mut claim) = self.reserved_slots.get_mut(&slot) {
+            claim.written = true;
+            self.highest_written_round
+                .fetch_max(slot.round(), Ordering::AcqRel);
+        }
+    }
+
+    /// The written node claiming the slot of the parent, for the nodes inserted before their
+    /// parents are linked in.
+    fn written_parent(&self, certificate: &NodeCertificate) -> Option<Arc<CertifiedNode>> {
+        let claimed = certificate.metadata();
+        let slot = self.slot_of(claimed.round(), claimed.author()).ok()?;
+        self.reserved_slots
+            .get(&slot)
+            .filter(|claim| claim.written && claim.node.digest() == *claimed.digest())
+            .map(|claim| claim.node.clone())
+    }
+
+    /// Links in the nodes written by `insert`, `insert_shared` and `retry_writes` since the last
+    /// call, parents before children, and records the evidence of the equivocations they
+    /// rejected. A node the DAG no longer accepts is deleted again, or queued for deletion, and
+    /// returned with the reason.
+    pub fn link_inserted(&mut self) -> HashMap<HashValue, DagStoreError> {
+        let mut written: Vec<_> = self
+            .reserved_slots
+            .iter()
+            .filter(|claim| claim.written)
+            .map(|claim| (*claim.key(), claim.node.clone()))
+            .collect();
+        written.sort_unstable_by_key(|(slot, _)| *slot);
+        for (slot, _) in &written {
+            self.reserved_slots.remove(slot);
+        }
+        *self.highest_written_round.get_mut() = 0;
+        let mut rejected = HashMap::new();
+        for (_, node) in written {
+            if let Err(e) = self.link_written(node.clone()) {
+                warn!("Dropped node {} after writing it: {:?}", node.digest(), e);
+                rejected.insert(node.digest(), e);
+            }
+        }
+        let equivocations = std::mem::take(&mut *self.rejected_equivocations.lock());
+        for node in equivocations {
+            self.record_equivocation(&node);
+        }
+        rejected
+    }
+
+    /// Links a written node whose claim was released. A node the DAG no longer accepts is deleted
+    /// again, or queued for deletion.
+    fn link_written(&mut self, node: Arc<CertifiedNode>) -> Result<(), DagStoreError> {
+        // the DAG may have changed since the validation, e.g. pruned
+        match self.validate_new_node(&node) {
+            Ok(slot) => {
+                self.link_node(slot, node);
+                Ok(())
+            },
+            Err(DagStoreError::DuplicateNode) => Ok(()),
+            Err(e) => {
+                self.delete_rejected_node(&node);
+                Err(e)
+            },
+        }
+    }
+
+    /// Replaces the write retry queue, dropping the queued writes is only safe while it's empty.
+    pub fn set_write_retry_queue(&mut self, write_retries: WriteRetryQueue) {
+        *self.write_retries.lock() = write_retries;
+    }
+
+    pub fn num_queued_writes(&self) -> usize {
+        self.write_retries.lock().len()
+    }
+
+    /// Retries the writes queued by `insert` that are due, or all of them with `flush`. Like
+    /// `insert_shared`, no lock is held during the storage writes. The persisted nodes are linked
+    /// in and the pending nodes waiting for them are promoted, a node failing its last attempt is
+    /// rejected and its slot freed for a redelivery.
+    pub fn retry_writes(dag: &RwLock<Self>, flush: bool) -> WriteRetryReport {
+        let mut report = WriteRetryReport::default();
+        let (due, storage) = {
+            let dag_reader = dag.read();
+            let now = dag_reader.time_service.get_current_timestamp();
+            let mut write_retries = dag_reader.write_retries.lock();
+            // the check before every vote and proposal doesn't contend for the write lock
+            if write_retries.is_empty() {
+                return report;
+            }
+            (
+                write_retries.take_due(now, flush),
+                dag_reader.storage.clone(),
+            )
+        };
+        for write in due {
+            let digest = write.node().digest();
+            let saved = storage.save_certified_node(write.node());
+            let dag_reader = dag.read();
+            match saved {
+                Ok(()) => {
+                    counters::WRITE_RETRY_COUNT
+                        .with_label_values(&["finalized"])
+                        .inc();
+                    match dag_reader.publish_write(write.node().clone(), write.slot()) {
+                        Ok(_) => report.finalized.push(digest),
+                        Err(e) => {
+                            warn!("Dropped node {} after writing it: {:?}", digest, e);
+                            report.rejected.push(digest);
+                        },
+                    }
+                },
+                Err(e) => {
+                    warn!(
+                        "Failed to write node {} after {} attempts: {:?}",
+                        digest,
+                        write.attempts() + 1,
+                        e
+                    );
+                    let now = dag_reader.time_service.get_current_timestamp();
+                    let mut write_retries = dag_reader.write_retries.lock();
+                    if let Some(exhausted) = write_retries.retry_later(write, now) {
+                        dag_reader.give_up_write(exhausted, "exhausted");
+                        report.rejected.push(digest);
+                    } else {
+                        counters::WRITE_RETRY_COUNT
+                            .with_label_values(&["failed"])
+                            .inc();
+                    }
+                },
+            }
+        }
+        let mut dag_writer = dag.write();
+        if !report.finalized.is_empty() {
+            let dropped = dag_writer.link_inserted();
+            let (rejected, finalized): (Vec<_>, Vec<_>) = report
+                .finalized
+                .into_iter()
+                .partition(|digest| dropped.contains_key(digest));
+            report.finalized = finalized;
+            report.rejected.extend(rejected);
+            if let Err(e) = dag_writer.promote_pending_nodes() {
+                warn!(
+                    "Failed to promote pending nodes after retried writes: {:?}",
+                    e
+                );
+            }
+        }
+        report.num_queued = dag_writer.num_queued_writes();
+        report
+    }
+
+    /// Retries every queued write right away, before the node votes or proposes so it never
+    /// acts on a node that isn't persisted. Fails if some writes are still queued.
+    pub fn flush_writes(dag: &RwLock<Self>) -> Result<WriteRetryReport, DagStoreError> {
+        let report = Self::retry_writes(dag, true);
+        match report.num_queued {
+            0 => Ok(report),
+            num_queued => Err(DagStoreError::Storage(anyhow::anyhow!(
+                "{} node writes are still queued",
+                num_queued
+            ))),
+        }
+    }
+
+    /// Retries the due writes every `interval`, the background task of the write retry queue.
+    pub async fn run_write_retries(dag: Arc<RwLock<Self>>, interval: Duration) {
+        let time_service = dag.read().time_service.clone();
+        loop {
+            time_service.sleep(interval).await;
+            Self::retry_writes(&dag, false);
+        }
+    }
+
+    /// Frees the slot of a write that won't be retried anymore.
+    fn give_up_write(&self, write: QueuedWrite, reason: &'static str) {
+        counters::WRITE_RETRY_COUNT
+            .with_label_values(&[reason])
+            .inc();
+        let digest = write.node().digest();
+        warn!(
+            "Giving up writing node {} after {} attempts ({}), it has to be delivered again",
+            digest,
+            write.attempts(),
+            reason
+        );
+        self.reserved_slots.remove(&write.slot());
+        record_insert_outcome(&InsertOutcome::Rejected(DagStoreError::Storage(
+            anyhow::anyhow!("write of node {} failed {} times", digest, write.attempts()),
+        )));
+    }
+
+    /// Whether the node claims its slot, from the start of its write until `link_inserted` links
+    /// it in or the write is given up, also while `retry_writes` took it out of the queue.
+    fn is_being_written(&self, node: &CertifiedNode) -> bool {
+        self.reserved_node(node)
+            .map_or(false, |reserved| reserved.digest() == node.digest())
+    }
+
+    /// The node reserving the slot of `node`, if any.
+    fn reserved_node(&self, node: &CertifiedNode) -> Option<Arc<CertifiedNode>> {
+        let slot = self
+            .slot_of(node.metadata().round(), node.metadata().author())
+            .ok()?;
+        self.reserved_slots
+            .get(&slot)
+            .map(|claim| claim.node.clone())
+    }
+
+    /// Links a validated node into the slot `validate_new_node` returned for it. It can't fail, the
+    /// node is already persisted.
+    fn link_node(&mut self, slot: SlotId, node: Arc<CertifiedNode>) {
+        self.set_slot(slot, NodeStatus::Unordered(node.clone()));
+        self.nodes_by_digest.insert(node.digest(), node.clone());
+        self.bump_generation(node.metadata().round());
+        self.account_node_added(slot, &node);
+        self.node_timings.insert(node.digest(), NodeTimings {
+            inserted_at: self.time_service.get_current_timestamp(),
+            ordered_at: None,
+        });
+    }
+
+    /// Adds the nodes in ascending round order so that nodes can follow their parents within the
+    /// batch, a rejected node doesn't stop the rest. Results are in the same order as the input.
+    pub fn add_nodes(&mut self, nodes: Vec<CertifiedNode>) -> Vec<Result<(), DagStoreError>> {
+        let mut indexed_nodes: Vec<_> = nodes.into_iter().enumerate().collect();
+        indexed_nodes.sort_by_key(|(_, node)| node.metadata().round());
+        let mut results: Vec<_> = indexed_nodes
+            .into_iter()
+            .map(|(position, node)| (position, self.add_node(node)))
+            .collect();
+        results.sort_by_key(|(position, _)| *position);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Adds a node whose certificate the caller validated, by its signatures or against the
+    /// digest `expected_digest` returns for it, then promotes the pending nodes it completes.
+    pub fn add_validated_node(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        self.add_node(node)?;
+        self.promote_pending_nodes()
+    }
+
+    /// The digest of the node certified for the slot of `metadata`, if a node of the DAG or of the
+    /// pending buffer links to it. A fetched node for the slot must hash to it, and the
+    /// certificate makes verifying its signatures unnecessary.
+    pub fn expected_digest(&self, metadata: &NodeMetadata) -> Option<HashValue> {
+        if metadata.epoch() != self.epoch_state.epoch {
+            return None;
+        }
+        self.referenced_digests
+            .get(&metadata.round())?
+            .get(metadata.author())
+            .copied()
+    }
+
+    fn reference_parents(&mut self, node: &CertifiedNode) {
+        index_references(&mut self.referenced_digests, node);
+    }
+
+    /// Drops the references of a node that left the pending buffer without entering the DAG. A
+    /// slot another node still links to keeps a digest it links to.
+    fn unreference_parents(&mut self, parents: &[NodeCertificate]) {
+        for parent in parents {
+            let metadata = parent.metadata();
+            let linked = self.linked_digests(metadata.round(), metadata.author());
+            let slots = match self.referenced_digests.get_mut(&metadata.round()) {
+                Some(slots) => slots,
+                None => continue,
+            };
+            match slots.get(metadata.author()) {
+                Some(digest) if linked.contains(digest) => {},
+                _ => match linked.into_iter().next() {
+                    Some(digest) => {
+                        slots.insert(*metadata.author(), digest);
+                    },
+                    None => {
+                        slots.remove(metadata.author());
+                    },
+                },
+            }
+            if slots.is_empty() {
+                self.referenced_digests.remove(&metadata.round());
+            }
+        }
+    }
+
+    /// The digests the nodes of the DAG and of the pending buffer link to for the slot.
+    fn linked_digests(&self, round: Round, author: &Author) -> HashSet<HashValue> {
+        let nodes = self
+            .nodes_by_round
+            .range(round + 1..)
+            .flat_map(|(_, slots)| slots.iter().flatten().map(|status| &**status.as_node()));
+        let pending = self
+            .pending_nodes
+            .range(round + 1..)
+            .flat_map(|(_, nodes)| nodes.values());
+        nodes
+            .chain(pending)
+            .flat_map(|node| node.parents())
+            .map(|parent| parent.metadata())
+            .filter(|metadata| metadata.round() == round && metadata.author() == author)
+            .map(|metadata| *metadata.digest())
+            .collect()
+    }
+
+    /// Cheap checks that only look at the node itself and the round window, so incoming nodes can
+    /// be dropped before verifying signatures or taking the write lock. It doesn't touch storage
+    /// or mutate anything. `MissingParent`, `DuplicateNode`, `EquivocateNode` and `Storage` can
+    /// only be returned later by `add_node`.
+    pub fn pre_validate(&self, node: &CertifiedNode) -> Result<(), DagStoreError> {
+        self.check_node(node, 1)
+    }
+
+    /// Like `pre_validate`, but lets through the nodes at most `park_gap` rounds above the
+    /// highest round, which `insert_node` parks until the DAG reaches them.
+    pub fn pre_validate_parkable(&self, node: &CertifiedNode) -> Result<(), DagStoreError> {
+        self.check_node(node, self.park_gap)
+    }
+
+    fn check_node(&self, node: &CertifiedNode, max_gap: Round) -> Result<(), DagStoreError> {
+        if self.ended {
+            return Err(DagStoreError::EpochEnded(self.epoch_state.epoch));
+        }
+        let metadata = node.metadata();
+        if self.validator_index.index_of(metadata.author()).is_none() {
+            return Err(DagStoreError::UnknownAuthor(*metadata.author()));
+        }
+        if let Some(denied_after) = self.denied_authors.get(metadata.author()) {
+            if metadata.round() > *denied_after {
+                return Err(DagStoreError::AuthorDenied {
+                    author: *metadata.author(),
+                    round: metadata.round(),
+                    denied_after: *denied_after,
+                });
+            }
+        }
+        if metadata.epoch() != self.epoch_state.epoch {
+            return Err(DagStoreError::EpochMismatch {
+                epoch: metadata.epoch(),
+                expected: self.epoch_state.epoch,
+            });
+        }
+        if metadata.chain_id() != self.chain_id {
+            return Err(DagStoreError::ChainIdMismatch {
+                chain_id: metadata.chain_id(),
+                expected: self.chain_id,
+            });
+        }
+        let round = metadata.round();
+        // a DAG pruned empty has no lowest round left, the pruned rounds stay closed
+        let lowest_round = self.lowest_round().max(self.pruned_below);
+        if round < lowest_round {
+            return Err(DagStoreError::RoundTooLow {
+                round,
+                lowest_round,
+            });
+        }
+        match self.observer {
+            Some(ObserverMode { max_round_span }) if round > lowest_round + max_round_span => {
+                return Err(DagStoreError::RoundBeyondSpan {
+                    round,
+                    lowest_round,
+                    max_round_span,
+                });
+            },
+            Some(_) => {},
+            None => {
+                let highest_round = self
+                    .highest_round()
+                    .max(self.highest_written_round.load(Ordering::Acquire));
+                if round > highest_round + max_gap && round != self.epoch_start_round {
+                    return Err(DagStoreError::RoundTooHigh {
+                        round,
+                        highest_round,
+                        gap: round - highest_round,
+                    });
+                }
+            },
+        }
+        if node.parents().is_empty() && round != self.epoch_start_round {
+            return Err(DagStoreError::EmptyParentsNotAllowed {
+                round,
+                start_round: self.epoch_start_round,
+            });
+        }
+        // bounds the timestamps the descendants of a node must follow, a node from the past passes
+        // since a validator catching up or recovering receives it late
+        let local_time = self.time_service.get_current_timestamp().as_micros() as u64;
+        let max_drift = self.max_clock_drift;
+        if metadata.timestamp() > local_time.saturating_add(max_drift.as_micros() as u64) {
+            return Err(DagStoreError::TimestampAhead {
+                timestamp: metadata.timestamp(),
+                local_time,
+                max_drift,
+            });
+        }
+        let mut parent_digests = HashSet::with_capacity(node.parents().len());
+        let mut parent_slots = HashSet::with_capacity(node.parents().len());
+        for parent in node.parents() {
+            let parent_metadata = parent.metadata();
+            if parent_metadata.round() == round && parent_metadata.author() == metadata.author() {
+                return Err(DagStoreError::SelfParent);
+            }
+            if !parent_digests.insert(parent_metadata.digest()) {
+                return Err(DagStoreError::DuplicateParent(*parent_metadata.digest()));
+            }
+            // two nodes of a slot, at most one of them can be in the DAG
+            if !parent_slots.insert((parent_metadata.round(), *parent_metadata.author())) {
+                return Err(DagStoreError::DuplicateParentSlot {
+                    round: parent_metadata.round(),
+                    author: *parent_metadata.author(),
+                });
+            }
+            if parent_metadata.round() >= round {
+                return Err(DagStoreError::InvalidParentRound {
+                    round,
+                    parent_round: parent_metadata.round(),
+                });
+            }
+            if parent_metadata.epoch() != metadata.epoch() {
+                return Err(DagStoreError::ParentEpochMismatch {
+                    epoch: metadata.epoch(),
+                    parent_epoch: parent_metadata.epoch(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every check of the node before it's persisted, returns its slot.
+    fn validate_new_node(&self, node: &CertifiedNode) -> Result<SlotId, DagStoreError> {
+        self.pre_validate(node)?;
+        let metadata = node.metadata();
+        let slot = self.slot_of(metadata.round(), metadata.author())?;
+        let reader = self.read();
+        let mut parent_timestamp = None;
+        for parent in node.parents() {
+            // the stored parent, the certificate doesn't vouch for the timestamp it claims
+            let timestamp = match reader.resolve_parent(parent)? {
+                Some(stored) => stored.metadata().timestamp(),
+                None => match self.written_parent(parent) {
+                    Some(written) => written.metadata().timestamp(),
+                    None => return Err(DagStoreError::MissingParent(*parent.metadata().digest())),
+                },
+            };
+            parent_timestamp = parent_timestamp.max(Some(timestamp));
+        }
+        if self.exists(metadata.digest()) {
+            return Err(DagStoreError::DuplicateNode);
+        }
+        let equivocates = match self.reserved_slots.get(&slot) {
+            Some(claim) if claim.node.digest() == node.digest() => {
+                return Err(DagStoreError::DuplicateNode)
+            },
+            Some(_) => true,
+            None => self
+                .get_node_status(metadata.round(), metadata.author())
+                .is_some(),
+        };
+        if equivocates {
+            self.epoch_totals.equivocations.lock().insert(node.digest());
+            return Err(DagStoreError::EquivocateNode);
+        }
+        if let Some(parent_timestamp) = parent_timestamp {
+            let tolerance = self.timestamp_skew_tolerance;
+            if metadata
+                .timestamp()
+                .saturating_add(tolerance.as_micros() as u64)
+                <= parent_timestamp
+            {
+                return Err(DagStoreError::NonMonotonicTimestamp {
+                    timestamp: metadata.timestamp(),
+                    parent_timestamp,
+                    tolerance,
+                });
+            }
+        }
+        Ok(slot)
+    }
+
+    /// Drops the fetched nodes the DAG can't use anymore, the ones from another epoch, below the
+    /// lowest round or already present, so they're not verified for nothing.
+    pub fn filter_relevant(
+        &self,
+        nodes: Vec<CertifiedNode>,
+    ) -> (Vec<CertifiedNode>, FilteredStats) {
+        let lowest_round = self.lowest_round();
+        let mut stats = FilteredStats::default();
+        let relevant = nodes
+            .into_iter()
+            .filter(|node| {
+                if node.metadata().epoch() != self.epoch_state.epoch {
+                    stats.wrong_epoch += 1;
+                } else if node.metadata().round() < lowest_round {
+                    stats.below_floor += 1;
+                } else if self.exists(&node.digest()) {
+                    stats.duplicate += 1;
+                } else {
+                    return true;
+                }
+                false
+            })
+            .collect();
+        (relevant, stats)
+    }
+
+    /// Adds the node if it can be connected to the DAG, otherwise keeps it in the pending buffer
+    /// until its parents arrive. Every insertion retries the pending nodes that became ready. An
+    /// observer only buffers the nodes that pass `pre_validate`.
+    pub fn add_node_or_buffer(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        if self.ended {
+            return Err(DagStoreError::EpochEnded(self.epoch_state.epoch));
+        }
+        if self.observer.is_some() {
+            self.pre_validate(&node)?;
+        }
+        if !self.is_ready(&node) {
+            return self.park_node(node, true);
+        }
+        self.add_node(node)?;
+        self.promote_pending_nodes()
+    }
+
+    /// Adds the node like `add_node_or_buffer` after `pre_validate_parkable`, but classifies what
+    /// happened instead of failing on the nodes delivered again. Nodes already in the DAG or in
+    /// the pending buffer are neither validated nor persisted again, only the rejections are
+    /// logged.
+    pub fn insert_node(&mut self, node: CertifiedNode) -> InsertOutcome {
+        let recorded = self.replayed_copy(&node);
+        let outcome = if self.exists(&node.digest()) {
+            InsertOutcome::AlreadyPresent
+        } else if self.is_being_written(&node) {
+            InsertOutcome::WriteRetrying
+        } else if self.is_pending(&node) {
+            InsertOutcome::ParkedPendingParents
+        } else if let Err(e) = self.pre_validate_parkable(&node) {
+            InsertOutcome::Rejected(e)
+        } else if !self.is_ready(&node) {
+            match self.park_node(node, true) {
+                Ok(()) => InsertOutcome::ParkedPendingParents,
+                Err(e) => InsertOutcome::Rejected(e),
+            }
+        } else {
+            match self.add_node(node) {
+                Ok(()) => match self.promote_pending_nodes() {
+                    Ok(()) => InsertOutcome::Inserted,
+                    Err(e) => InsertOutcome::Rejected(e),
+                },
+                Err(DagStoreError::DuplicateNode) => InsertOutcome::AlreadyPresent,
+                Err(e) => InsertOutcome::Rejected(e),
+            }
+        };
+        record_insert_outcome(&outcome);
+        self.record_replay(recorded, &outcome);
+        outcome
+    }
+
+    /// Like `insert_node`, but a node that can be added right away is persisted through
+    /// `insert_shared`, without holding the lock during the storage write. Only parking a node,
+    /// linking it in and promoting the pending nodes take the write lock.
+    pub fn insert_node_shared(dag: &RwLock<Self>, node: CertifiedNode) -> InsertOutcome {
+        let (ready, recorded) = {
+            let dag_reader = dag.read();
+            let ready = !dag_reader.exists(&node.digest())
+                && !dag_reader.is_pending(&node)
+                && dag_reader.pre_validate(&node).is_ok()
+                && dag_reader.is_ready(&node);
+            (
+                ready,
+                ready.then(|| dag_reader.replayed_copy(&node)).flatten(),
+            )
+        };
+        if !ready {
+            return dag.write().insert_node(node);
+        }
+        let inserted = Self::insert_shared(dag, node);
+        let outcome = {
+            let mut dag_writer = dag.write();
+            let mut dropped = dag_writer.link_inserted();
+            match inserted {
+                Ok(node) => match dropped.remove(&node.digest()) {
+                    Some(e) => InsertOutcome::Rejected(e),
+                    None => match dag_writer.promote_pending_nodes() {
+                        Ok(()) => InsertOutcome::Inserted,
+                        Err(e) => InsertOutcome::Rejected(e),
+                    },
+                },
+                Err(DagStoreError::WriteRetrying(_)) => InsertOutcome::WriteRetrying,
+                Err(e) => InsertOutcome::Rejected(e),
+            }
+        };
+        record_insert_outcome(&outcome);
+        dag.read().record_replay(recorded, &outcome);
+        outcome
+    }
+
+    /// The node and the time it was received at, if the nodes received are recorded.
+    fn replayed_copy(&self, node: &CertifiedNode) -> Option<(CertifiedNode, Duration)> {
+        self.replay_log
+            .as_ref()
+            .map(|_| (node.clone(), self.time_service.get_current_timestamp()))
+    }
+
+    fn record_replay(&self, recorded: Option<(CertifiedNode, Duration)>, outcome: &InsertOutcome) {
+        if let (Some(replay_log), Some((node, received_at))) = (&self.replay_log, recorded) {
+            replay_log.record(node, outcome, received_at);
+        }
+    }
+
+    /// Starts recording the nodes received, a failure to create the log is only logged.
+    fn open_replay_log(&mut self, config: &ReplayLogConfig, store_config: DagStoreConfig) {
+        let header = ReplayHeader {
+            version: REPLAY_LOG_VERSION,
+            epoch: self.epoch_state.epoch,
+            chain_id: self.chain_id,
+            config: store_config,
+        };
+        match DagReplayLog::create(config, header, self.started_at) {
+            Ok(replay_log) => {
+                // the recovered nodes are in before the first node recorded
+                replay_log.checkpoint(self.content_digest(None));
+                info!(
+                    "Recording the nodes received to {}",
+                    replay_log.path().display()
+                );
+                self.replay_log = Some(replay_log);
+            },
+            Err(e) => warn!(
+                "Failed to create the replay log in {}: {:?}",
+                config.dir.display(),
+                e
+            ),
+        }
+    }
+
+    pub fn replay_log_path(&self) -> Option<&Path> {
+        self.replay_log.as_ref().map(DagReplayLog::path)
+    }
+
+    /// Stops recording the nodes received, the log ends with a checkpoint of the DAG content.
+    pub fn close_replay_log(&mut self) -> Option<io::Result<ReplayLogStats>> {
+        let replay_log = self.replay_log.take()?;
+        replay_log.checkpoint(self.content_digest(None));
+        Some(replay_log.close())
+    }
+
+    /// Like `insert_node`, but if the node ends up in the pending buffer `token` is handed to the
+    /// `DeferredAckHandler` when it leaves it, promoted or evicted.
+    pub fn insert_node_with_ack(&mut self, node: CertifiedNode, token: AckToken) -> InsertOutcome {
+        let metadata = node.metadata().clone();
+        let outcome = self.insert_node(node);
+        if let InsertOutcome::ParkedPendingParents = outcome {
+            self.defer_ack(&metadata, token);
+        }
+        outcome
+    }
+
+    /// Like `insert_node_shared` with the deferred ack of `insert_node_with_ack`.
+    pub fn insert_node_shared_with_ack(
+        dag: &RwLock<Self>,
+        node: CertifiedNode,
+        token: AckToken,
+    ) -> InsertOutcome {
+        let metadata = node.metadata().clone();
+        let outcome = Self::insert_node_shared(dag, node);
+        if let InsertOutcome::ParkedPendingParents = outcome {
+            dag.write().defer_ack(&metadata, token);
+        }
+        outcome
+    }
+
+    pub fn set_deferred_ack_handler(&mut self, handler: Arc<dyn DeferredAckHandler>) {
+        self.deferred_ack_handler = Some(handler);
+    }
+
+    /// The node may have left the buffer since it was parked when the lock was released in
+    /// between, then the token fires right away.
+    fn defer_ack(&mut self, metadata: &NodeMetadata, token: AckToken) {
+        let handler = match &self.deferred_ack_handler {
+            Some(handler) => handler.clone(),
+            None => return,
+        };
+        if self.exists(metadata.digest()) {
+            handler.on_promoted(token, metadata);
+        } else if self
+            .pending_nodes
+            .get(&metadata.round())
+            .map_or(false, |nodes| nodes.contains_key(metadata.digest()))
+        {
+            self.pending_acks
+                .entry(*metadata.digest())
+                .or_default()
+                .push(token);
+        } else {
+            handler.on_evicted(token);
+        }
+    }
+
+    fn fire_pending_acks(&mut self, metadata: &NodeMetadata, promoted: bool) {
+        let tokens = match self.pending_acks.remove(metadata.digest()) {
+            Some(tokens) => tokens,
+            None => return,
+        };
+        if let Some(handler) = &self.deferred_ack_handler {
+            for token in tokens {
+                if promoted {
+                    handler.on_promoted(token, metadata);
+                } else {
+                    handler.on_evicted(token);
+                }
+            }
+        }
+    }
+
+    fn is_pending(&self, node: &CertifiedNode) -> bool {
+        self.pending_nodes
+            .get(&node.metadata().round())
+            .map_or(false, |nodes| nodes.contains_key(&node.digest()))
+    }
+
+    /// A node parked again, e.g. delivered by another peer before its parents arrived, is kept
+    /// once.
+    fn park_node(&mut self, node: CertifiedNode, persist: bool) -> Result<(), DagStoreError> {
+        if self.is_pending(&node) {
+            record_pending_resolution(PendingResolution::AlreadyParked);
+            return Ok(());
+        }
+        if persist {
+            self.mode
+                .handle(self.storage.save_pending_node(&node), "save_pending_node")?;
+        }
+        self.account_pending_added(&node);
+        self.pending_nodes
+            .entry(node.metadata().round())
+            .or_default()
+            .insert(node.digest(), node);
+        Ok(())
+    }
+
+    fn unpark_node(&mut self, node: &CertifiedNode) -> Result<(), DagStoreError> {
+        self.account_pending_removed(node);
+        self.mode.handle(
+            self.storage.delete_pending_node(&node.digest()),
+            "delete_pending_node",
+        )
+    }
+
+    fn is_ready(&self, node: &CertifiedNode) -> bool {
+        (self.observer.is_some() || node.metadata().round() <= self.highest_round() + 1)
+            && node
+                .parents()
+                .iter()
+                .all(|parent| self.exists(parent.metadata().digest()))
+    }
+
+    /// Parents are always from a lower round, so a single pass in ascending round order promotes
+    /// every pending node whose history is complete.
+    fn promote_pending_nodes(&mut self) -> Result<(), DagStoreError> {
+        let mut dropped = vec![];
+        let result = self.try_promote_pending_nodes(&mut dropped);
+        // who else links to the parents is only known once the buffer is whole again
+        for parents in dropped {
+            self.unreference_parents(&parents);
+        }
+        result
+    }
+
+    /// Collects the parents of the nodes that failed to enter the DAG in `dropped`.
+    fn try_promote_pending_nodes(
+        &mut self,
+        dropped: &mut Vec<Vec<NodeCertificate>>,
+    ) -> Result<(), DagStoreError> {
+        let mut pending_nodes = std::mem::take(&mut self.pending_nodes).into_iter();
+        while let Some((round, nodes)) = pending_nodes.next() {
+            let mut nodes = nodes.into_iter();
+            while let Some((digest, node)) = nodes.next() {
+                if !self.is_ready(&node) {
+                    self.pending_nodes
+                        .entry(round)
+                        .or_default()
+                        .insert(digest, node);
+                    continue;
+                }
+                let metadata = node.metadata().clone();
+                let parents = node.parents().to_vec();
+                let result = self.promote_pending_node(node.clone());
+                let storage_error = match result {
+                    Err(DagStoreError::Storage(e)) if self.mode == DagStoreMode::Strict => {
+                        // the node didn't enter the DAG, it stays parked with its acks
+                        self.pending_nodes
+                            .entry(round)
+                            .or_default()
+                            .insert(digest, node);
+                        Some(DagStoreError::Storage(e))
+                    },
+                    result => {
+                        match &result {
+                            Ok(resolution) => record_pending_resolution(*resolution),
+                            Err(DagStoreError::EquivocateNode) => {
+                                record_pending_resolution(PendingResolution::Equivocation)
+                            },
+                            Err(_) => record_pending_resolution(PendingResolution::Rejected),
+                        }
+                        match &result {
+                            Ok(
+                                PendingResolution::Inserted | PendingResolution::AlreadyInserted,
+                            ) => self.fire_pending_acks(&metadata, true),
+                            _ => {
+                                self.fire_pending_acks(&metadata, false);
+                                dropped.push(parents);
+                            },
+                        }
+                        if let Err(e) = result {
+                            warn!("Failed to add pending node: {:?}", e);
+                        }
+                        // the node is settled in memory, only its parked copy may remain stored
+                        self.unpark_node(&node).err()
+                    },
+                };
+                if let Some(e) = storage_error {
+                    // keep the nodes that were not tried yet so the buffer stays consistent
+                    self.pending_nodes.entry(round).or_default().extend(nodes);
+                    for (round, nodes) in pending_nodes {
+                        self.pending_nodes.entry(round).or_default().extend(nodes);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a ready node out of the pending buffer. The same node may have entered the DAG
+    /// meanwhile without going through the buffer, e.g. in a fetch response along with its
+    /// parents, then the parked copy is dropped. If another node holds its slot the author
+    /// equivocated, the parked node is dropped and recorded as evidence. The caller unparks it.
+    fn promote_pending_node(
+        &mut self,
+        node: CertifiedNode,
+    ) -> Result<PendingResolution, DagStoreError> {
+        if self.exists(&node.digest()) {
+            return Ok(PendingResolution::AlreadyInserted);
+        }
+        let metadata = node.metadata();
+        if let Some(kept) = self.get_node_by_round_author(metadata.round(), metadata.author()) {
+            warn!(
+                "Pending node {} of {} in round {} equivocates with {}",
+                node.digest(),
+                metadata.author(),
+                metadata.round(),
+                kept.digest()
+            );
+            self.record_equivocation(&node);
+            return Ok(PendingResolution::Equivocation);
+        }
+        self.add_node(node)?;
+        Ok(PendingResolution::Inserted)
+    }
+
+    pub fn pending_nodes_count(&self) -> usize {
+        self.memory_usage.num_pending_nodes
+    }
+
+    /// Replaces the filter of the pruned nodes, the nodes pruned so far are forgotten.
+    pub fn set_pruned_digest_filter(&mut self, pruned_digests: PrunedDigestFilter) {
+        self.pruned_digests = pruned_digests;
+    }
+
+    pub fn pruned_digests(&self) -> &PrunedDigestFilter {
+        &self.pruned_digests
+    }
+
+    /// Whether the node is the redelivery of a node pruned in the last rounds, which the network
+    /// handler drops before verifying it. See `PrunedDigestFilter`.
+    pub fn is_pruned_redelivery(&self, metadata: &NodeMetadata) -> bool {
+        self.pruned_digests.is_pruned_redelivery(metadata)
+    }
+
+    /// Removes all the rounds below `round` from memory and storage, including the pending nodes.
+    /// Returns the number of nodes removed from the DAG.
+    pub fn prune_below(&mut self, round: Round) -> Result<usize, DagStoreError> {
+        let span = debug_span!("dag::prune", round, num_nodes = field::Empty);
+        let _entered = span.enter();
+        if round > self.pruned_below {
+            // the pruned rounds move to `PRUNED_GENERATION`
+            self.pruned_below = round;
+            self.last_generation += 1;
+        }
+        let to_keep = self.nodes_by_round.split_off(&round);
+        self.skipped_rounds = self.skipped_rounds.split_off(&round);
+        let digests_to_keep = self.round_digests.lock().split_off(&round);
+        *self.round_digests.lock() = digests_to_keep;
+        let pruned: Vec<_> = std::mem::replace(&mut self.nodes_by_round, to_keep)
+            .into_values()
+            .flatten()
+            .flatten()
+            .map(|status| status.as_node().clone())
+            .collect();
+        self.referenced_digests = self.referenced_digests.split_off(&round);
+        self.round_generations = self.round_generations.split_off(&round);
+        // the nodes ordered by a pruned anchor are pruned too
+        self.awaiting_commit = self.awaiting_commit.split_off(&round);
+        // everything is removed from memory before storage is touched, a storage error is only
+        // reported once the indexes agree again
+        let mut storage_results = vec![];
+        let reservations_to_keep = self.self_reservations.split_off(&round);
+        let pruned_reservations: Vec<_> =
+            std::mem::replace(&mut self.self_reservations, reservations_to_keep)
+                .into_keys()
+                .map(|round| (self.epoch_state.epoch, round))
+                .collect();
+        let progress_to_keep = self.broadcast_progress.split_off(&round);
+        let pruned_progress: Vec<_> =
+            std::mem::replace(&mut self.broadcast_progress, progress_to_keep)
+                .into_keys()
+                .map(|round| (self.epoch_state.epoch, round))
+                .collect();
+        let pending_to_keep = self.pending_nodes.split_off(&round);
+        for node in std::mem::replace(&mut self.pending_nodes, pending_to_keep)
+            .into_values()
+            .flat_map(BTreeMap::into_values)
+        {
+            self.fire_pending_acks(node.metadata(), false);
+            storage_results.push(self.unpark_node(&node));
+        }
+        self.pruned_digests
+            .record(round, pruned.iter().map(|node| node.metadata()));
+        let mut digests = Vec::with_capacity(pruned.len());
+        for node in &pruned {
+            self.nodes_by_digest.remove(&node.digest());
+            self.node_timings.remove(&node.digest());
+            self.epoch_totals.pruned_bytes += estimate_node_size(node) as u64;
+            self.account_node_removed(node);
+            digests.push(node.digest());
+        }
+        span.record("num_nodes", pruned.len());
+        let pruned_anchors: Vec<_> = self
+            .ordered_anchors
+            .iter()
+            .filter(|(_, ordered_anchor)| ordered_anchor.anchor().round() < round)
+            .map(|(digest, _)| *digest)
+            .collect();
+        for digest in &pruned_anchors {
+            self.ordered_anchors.remove(digest);
+        }
+        storage_results
+            .push(self.prune_evidence_below(round.saturating_sub(self.evidence_retention.window)));
+        if !pruned_reservations.is_empty() {
+            storage_results.push(self.mode.handle(
+                self.storage.delete_self_reservations(pruned_reservations),
+                "delete_self_reservations",
+            ));
+        }
+        if !pruned_progress.is_empty() {
+            storage_results.push(self.mode.handle(
+                self.storage.delete_broadcast_progress(pruned_progress),
+                "delete_broadcast_progress",
+            ));
+        }
+        if !pruned_anchors.is_empty() {
+            storage_results.push(self.mode.handle(
+                self.storage.delete_ordered_anchors(pruned_anchors),
+                "delete_ordered_anchors",
+            ));
+        }
+        if !digests.is_empty() {
+            if let Err(e) = self
+                .storage
+                .delete_rounds_below(self.epoch_state.epoch, round)
+            {
+                warn!(
+                    "Error in delete_rounds_below: {:?}, deleting {} pruned nodes by digest",
+                    e,
+                    digests.len()
+                );
+                storage_results.push(delete_or_queue(
+                    self.storage.as_ref(),
+                    self.mode,
+                    digests,
+                    "delete_pruned_nodes",
+                ));
+            }
+        }
+        storage_results.into_iter().collect::<Result<Vec<_>, _>>()?;
+        Ok(pruned.len())
+    }
+
+    /// Drops the evidence of the rounds below `round` that isn't pending report.
+    fn prune_evidence_below(&mut self, round: Round) -> Result<(), DagStoreError> {
+        let expired = self.expired_evidence(round);
+        if expired.is_empty() {
+            return Ok(());
+        }
+        self.drop_evidence(&expired);
+        self.mode.handle(
+            self.storage.delete_equivocation_evidence(expired),
+            "delete_equivocation_evidence",
+        )
+    }
+
+    /// The evidence records below `round` that aren't waiting to be reported, by the digest of
+    /// the conflicting node.
+    fn expired_evidence(&self, round: Round) -> Vec<HashValue> {
+        self.equivocation_evidence
+            .iter()
+            .filter(|record| record.round() < round && !record.pending_report())
+            .map(|record| *record.conflicting().digest())
+            .collect()
+    }
+
+    fn drop_evidence(&mut self, digests: &[HashValue]) {
+        self.equivocation_evidence
+            .retain(|record| !digests.contains(record.conflicting().digest()));
+    }
+
+    /// Destroys the DAG of the epoch so it restarts with parentless nodes in the round after
+    /// `to_committed_round`, for a validator that can't recover otherwise. Everything is dropped
+    /// from memory and the certified nodes, pending nodes and ordered anchors of the epoch are
+    /// deleted from storage. The rounds below the start round count as pruned and committed: the
+    /// confirmed round restarts at `to_committed_round` and the evidence is pruned as by a prune
+    /// to the start round. Refuses to reset below the latest committed ledger info. Only meant to
+    /// be called by operators through `DagAdmin`.
+    pub fn force_reset(
+        &mut self,
+        to_committed_round: Round,
+        latest_ledger_info: &LedgerInfo,
+    ) -> anyhow::Result<ResetReport> {
+        let epoch = self.epoch_state.epoch;
+        ensure!(
+            latest_ledger_info.epoch() == epoch,
+            "ledger info epoch {} doesn't match the DAG epoch {}",
+            latest_ledger_info.epoch(),
+            epoch
+        );
+        ensure!(
+            to_committed_round >= latest_ledger_info.round(),
+            "round {} is below the committed round {}",
+            to_committed_round,
+            latest_ledger_info.round()
+        );
+        // storage goes first so a failure leaves the DAG untouched and the reset can be retried
+        let nodes: Vec<_> = self
+            .storage
+            .get_certified_nodes()?
+            .into_iter()
+            .filter(|(_, node)| node.metadata().epoch() == epoch)
+            .map(|(digest, _)| digest)
+            .collect();
+        let pending_nodes: Vec<_> = self
+            .storage
+            .get_pending_nodes()?
+            .into_iter()
+            .filter(|(_, node)| node.metadata().epoch() == epoch)
+            .map(|(digest, _)| digest)
+            .collect();
+        let ordered_anchors: Vec<_> = self
+            .storage
+            .get_ordered_anchors()?
+            .into_iter()
+            .filter(|(_, ordered_anchor)| ordered_anchor.anchor().epoch() == epoch)
+            .map(|(digest, _)| digest)
+            .collect();
+        let start_round = to_committed_round + 1;
+        let expired_evidence =
+            self.expired_evidence(start_round.saturating_sub(self.evidence_retention.window));
+        let report = ResetReport {
+            epoch,
+            lowest_round: self.lowest_round(),
+            highest_round: self.highest_round(),
+            num_nodes: self.memory_usage.num_nodes,
+            num_pending_nodes: self.memory_usage.num_pending_nodes,
+            num_deleted_nodes: nodes.len(),
+            num_deleted_pending_nodes: pending_nodes.len(),
+            num_deleted_ordered_anchors: ordered_anchors.len(),
+            num_deleted_evidence_records: expired_evidence.len(),
+            start_round,
+            confirmed_round: self.confirmed_round,
+            pruned_below: self.pruned_below,
+        };
+        self.storage.delete_certified_nodes(nodes)?;
+        for digest in &pending_nodes {
+            self.storage.delete_pending_node(digest)?;
+        }
+        self.storage.delete_ordered_anchors(ordered_anchors)?;
+        if !expired_evidence.is_empty() {
+            self.storage
+                .delete_equivocation_evidence(expired_evidence.clone())?;
+        }
+        // the reservations of the rounds after the reset still hold
+        self.storage.delete_self_reservations(
+            self.self_reservations
+                .range(..start_round)
+                .map(|(round, _)| (epoch, *round))
+                .collect(),
+        )?;
+        self.storage.delete_broadcast_progress(
+            self.broadcast_progress
+                .range(..start_round)
+                .map(|(round, _)| (epoch, *round))
+                .collect(),
+        )?;
+        self.storage.save_epoch_start_round(epoch, start_round)?;
+        self.storage.delete_last_committed_anchor()?;
+
+        self.nodes_by_digest.clear();
+        for round in std::mem::take(&mut self.nodes_by_round).into_keys() {
+            self.bump_generation(round);
+        }
+        self.pending_nodes.clear();
+        for token in std::mem::take(&mut self.pending_acks)
+            .into_values()
+            .flatten()
+        {
+            if let Some(handler) = &self.deferred_ack_handler {
+                handler.on_evicted(token);
+            }
+        }
+        self.node_timings.clear();
+        self.awaiting_commit.clear();
+        self.bytes_by_round.clear();
+        self.memory_usage = DagMemoryUsage::default();
+        self.update_memory_budget_flag();
+        self.skipped_rounds.clear();
+        self.ordered_anchors.clear();
+        self.last_committed_anchor = None;
+        self.round_digests.lock().clear();
+        self.highest_round_by_author.fill(0);
+        self.power_by_round.clear();
+        self.referenced_digests.clear();
+        self.children.clear();
+        self.self_reservations = self.self_reservations.split_off(&start_round);
+        self.broadcast_progress = self.broadcast_progress.split_off(&start_round);
+        // the queued, written and in-flight writes are of the old DAG, a write landing after the
+        // reset is claimed again, or deleted if the restarted DAG doesn't accept the node
+        self.write_retries.lock().take_due(Duration::ZERO, true);
+        self.reserved_slots.clear();
+        *self.highest_written_round.get_mut() = 0;
+        self.rejected_equivocations.lock().clear();
+        self.pruned_digests.clear();
+        self.catch_up_progress = None;
+        self.drop_evidence(&expired_evidence);
+        self.committed_round = to_committed_round;
+        self.confirmed_round = to_committed_round;
+        // the dropped rounds move to `PRUNED_GENERATION` like pruned ones, the rounds from the
+        // start round keep their bumped generation
+        self.pruned_below = start_round;
+        self.round_generations = self.round_generations.split_off(&start_round);
+        self.last_generation += 1;
+        self.highest_quorum_round = 0;
+        self.epoch_start_round = start_round;
+        Ok(report)
+    }
+
+    /// Prunes the rounds the pruning policy no longer retains once `committed_round` is committed.
+    /// Returns the number of nodes removed from the DAG.
+    pub fn commit_callback(&mut self, committed_round: Round) -> Result<usize, DagStoreError> {
+        self.record_commit(committed_round);
+        self.check_author_skew();
+        match self
+            .pruning_policy
+            .rounds_to_prune(&self.summary(), committed_round)
+        {
+            Some(floor) => self.prune_below(floor),
+            None => Ok(0),
+        }
+    }
+
+    /// Confirms the ledger committed the anchor ordered in `anchor_round`, `ledger_info` must be
+    /// of the epoch and the round of the anchor. The ledger commits in order, so every anchor
+    /// ordered up to it is confirmed with it: the nodes of their ordered anchor records move from
+    /// `Ordered` to `Committed`, then the rounds the pruning policy no longer retains are pruned
+    /// like with `commit_callback`. A confirmation at or below the highest round confirmed so far,
+    /// e.g. of an older anchor arriving after a newer one, changes nothing.
+    pub fn notify_commit_confirmed(
+        &mut self,
+        anchor_round: Round,
+        ledger_info: &LedgerInfoWithSignatures,
+    ) -> Result<CommitConfirmation, DagStoreError> {
+        let epoch = self.epoch_state.epoch;
+        let ledger_info = ledger_info.ledger_info();
+        if ledger_info.epoch() != epoch || ledger_info.round() != anchor_round {
+            return Err(DagStoreError::CommitConfirmationMismatch {
+                epoch: ledger_info.epoch(),
+                round: ledger_info.round(),
+                anchor_epoch: epoch,
+                anchor_round,
+            });
+        }
+        if anchor_round <= self.confirmed_round {
+            return Ok(CommitConfirmation::default());
+        }
+        if !self
+            .ordered_anchors
+            .values()
+            .any(|ordered_anchor| ordered_anchor.anchor().round() == anchor_round)
+        {
+            return Err(DagStoreError::AnchorNotOrdered(anchor_round));
+        }
+        let confirmed_round = self.confirmed_round;
+        let sources: Vec<_> = self
+            .ordered_anchors
+            .values()
+            .filter(|ordered_anchor| {
+                (confirmed_round + 1..=anchor_round).contains(&ordered_anchor.anchor().round())
+            })
+            .map(|ordered_anchor| ordered_anchor.sources().to_vec())
+            .collect();
+        let mut confirmation = CommitConfirmation {
+            num_anchors: sources.len(),
+            ..CommitConfirmation::default()
+        };
+        for source in sources.iter().flatten() {
+            let Ok(slot) = self.slot_of(source.round(), source.author()) else {
+                continue;
+            };
+            let node = match self.slot_status(slot) {
+                Ok(Some(status))
+                    if !status.is_committed() && status.as_node().digest() == *source.digest() =>
+                {
+                    status.as_node().clone()
+                },
+                _ => continue,
+            };
+            self.set_slot(slot, NodeStatus::Committed(node));
+            self.bump_generation(source.round());
+            confirmation.num_committed += 1;
+        }
+        self.confirmed_round = anchor_round;
+        confirmation.num_pruned = self.commit_callback(anchor_round)?;
+        Ok(confirmation)
+    }
+
+    /// Highest anchor round confirmed by `notify_commit_confirmed`.
+    pub fn confirmed_round(&self) -> Round {
+        self.confirmed_round
+    }
+
+    pub fn exists(&self, digest: &HashValue) -> bool {
+        self.nodes_by_digest.contains_key(digest)
+    }
+
+    /// A certificate whose round or author doesn't match the node stored under its digest doesn't
+    /// count as existing.
+    pub fn all_exists(&self, nodes: &[NodeCertificate]) -> bool {
+        self.read().all_parents_exist(nodes)
+    }
+
+    /// The node a parent certificate refers to, `None` if its digest is unknown. The digest alone
+    /// can't be trusted for the round based logic, a certificate claiming another round or author
+    /// than the stored node is an error.
+    pub fn resolve_parent(
+        &self,
+        certificate: &NodeCertificate,
+    ) -> Result<Option<&Arc<CertifiedNode>>, DagStoreError> {
+        let claimed = certificate.metadata();
+        let node = match self.nodes_by_digest.get(claimed.digest()) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let stored = node.metadata();
+        if stored.round() != claimed.round() || stored.author() != claimed.author() {
+            return Err(DagStoreError::ParentMetadataMismatch {
+                digest: *claimed.digest(),
+                claimed_round: claimed.round(),
+                claimed_author: *claimed.author(),
+                round: stored.round(),
+                author: *stored.author(),
+            });
+        }
+        Ok(Some(node))
+    }
+
+    /// The node with the digest for a caller keeping it, reads go through `read`.
+    pub fn get_node(&self, digest: &HashValue) -> Option<Arc<CertifiedNode>> {
+        self.nodes_by_digest.get(digest).cloned()
+    }
+
+    /// Borrowed reads of the nodes and of the voting power of the rounds, for the callers that
+    /// only inspect them.
+    pub fn read(&self) -> DagReadGuard<'_> {
+        DagReadGuard { dag: self }
+    }
+
+    /// Reserves the slot of the local validator in the round of `node` before the node is
+    /// broadcast, the node is persisted with the reservation so it survives a crash and can be
+    /// broadcast again. Reserving the same node again succeeds, any other node for the round is
+    /// refused so a restarted validator doesn't equivocate against itself. The slot is claimed in
+    /// memory and written without holding the lock, the claim is released if the write fails.
+    pub fn reserve_self_slot(dag: &RwLock<Self>, node: &Node) -> Result<(), DagStoreError> {
+        let round = node.metadata().round();
+        let digest = node.digest();
+        let (epoch, storage) = {
+            let mut dag_writer = dag.write();
+            if dag_writer.ended {
+                return Err(DagStoreError::EpochEnded(dag_writer.epoch_state.epoch));
+            }
+            match dag_writer.self_reservations.get(&round) {
+                Some(reserved) if reserved.digest() == digest => return Ok(()),
+                Some(reserved) => {
+                    return Err(DagStoreError::SelfSlotReserved {
+                        round,
+                        reserved: reserved.digest(),
+                    })
+                },
+                None => {},
+            }
+            let lowest_round = dag_writer.lowest_round();
+            if round < lowest_round {
+                return Err(DagStoreError::RoundTooLow {
+                    round,
+                    lowest_round,
+                });
+            }
+            dag_writer.self_reservations.insert(round, node.clone());
+            (dag_writer.epoch_state.epoch, dag_writer.storage.clone())
+        };
+        if let Err(e) = storage.save_self_reservation(epoch, round, node) {
+            let mut dag_writer = dag.write();
+            if dag_writer
+                .self_reservations
+                .get(&round)
+                .map_or(false, |reserved| reserved.digest() == digest)
+            {
+                dag_writer.self_reservations.remove(&round);
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// The node the local validator reserved its slot in `round` for, also after a restart. It
+    /// must be broadcast again, or its certified node resumed, instead of building a new one.
+    pub fn self_reservation(&self, round: Round) -> Option<&Node> {
+        self.self_reservations.get(&round)
+    }
+
+    /// Persists which validators acked the certified node with `digest` the local validator
+    /// broadcast in `round`. The progress is recorded under the lock and written after releasing
+    /// it, a record written after its round is pruned is deleted at the next recovery.
+    pub fn save_broadcast_progress(
+        dag: &RwLock<Self>,
+        round: Round,
+        digest: HashValue,
+        acked: BitVec,
+    ) -> Result<(), DagStoreError> {
+        let (epoch, storage) = {
+            let mut dag_writer = dag.write();
+            let lowest_round = dag_writer.lowest_round();
+            if round < lowest_round {
+                return Err(DagStoreError::RoundTooLow {
+                    round,
+                    lowest_round,
+                });
+            }
+            dag_writer
+                .broadcast_progress
+                .insert(round, BroadcastProgress::new(digest, acked.clone()));
+            (dag_writer.epoch_state.epoch, dag_writer.storage.clone())
+        };
+        storage.save_broadcast_progress(epoch, round, &digest, &acked)?;
+        Ok(())
+    }
 
-/// Data structure that stores the DAG representation, it maintains both hash based index and
-/// round based index.
-pub struct Dag {
-    nodes_by_digest: HashMap<HashValue, Arc<CertifiedNode>>,
-    nodes_by_round: BTreeMap<Round, Vec<Option<Arc<CertifiedNode>>>>,
-    /// Map between peer id to vector index
-    author_to_index: HashMap<Author, usize>,
-    storage: Arc<dyn DAGStorage>,
-}
+    /// The validators that acked the certified node with `digest` broadcast in `round`, also after
+    /// a restart. A record of another node is stale, it's deleted instead of being trusted.
+    pub fn broadcast_progress(&mut self, round: Round, digest: &HashValue) -> Option<BitVec> {
+        match self.broadcast_progress.get(&round) {
+            Some(progress) if progress.digest() == digest => Some(progress.acked().clone()),
+            Some(progress) => {
+                warn!(
+                    "Broadcast progress of round {} is for node {}, not {}, dropping it",
+                    round,
+                    progress.digest(),
+                    digest
+                );
+                self.broadcast_progress.remove(&round);
+                if let Err(e) = self
+                    .storage
+                    .delete_broadcast_progress(vec![(self.epoch_state.epoch, round)])
+                {
+                    warn!("Failed to delete the broadcast progress: {:?}", e);
+                }
+                None
+            },
+            None => None,
+        }
+    }
 
-impl Dag {
-    pub fn new(epoch_state: Arc<EpochState>, storage: Arc<dyn DAGStorage>) -> Self {
-        let epoch = epoch_state.epoch;
-        let author_to_index = epoch_state.verifier.address_to_validator_index().clone();
-        let num_validators = author_to_index.len();
-        let all_nodes = storage.get_certified_nodes().unwrap_or_default();
-        let mut expired = vec![];
-        let mut nodes_by_digest = HashMap::new();
-        let mut nodes_by_round = BTreeMap::new();
-        for (digest, certified_node) in all_nodes {
-            if certified_node.metadata().epoch() == epoch {
-                let arc_node = Arc::new(certified_node);
-                nodes_by_digest.insert(digest, arc_node.clone());
-                let index = *author_to_index
-                    .get(arc_node.metadata().author())
-                    .expect("Author from certified node should exist");
-                let round = arc_node.metadata().round();
-                nodes_by_round
-                    .entry(round)
-                    .or_insert_with(|| vec![None; num_validators])[index] = Some(arc_node);
-            } else {
-                expired.push(digest);
+    /// Local time when the node was added to the DAG, or recovered from storage.
+    pub fn reception_time(&self, digest: &HashValue) -> Option<Duration> {
+        self.node_timings
+            .get(digest)
+            .map(|timings| timings.inserted_at)
+    }
+
+    /// The latencies of the last `MAX_LATENCY_SAMPLES` committed nodes, oldest first.
+    pub fn latency_samples(&self) -> Vec<NodeLatencySample> {
+        self.latency_samples.iter().cloned().collect()
+    }
+
+    /// Records the commit latency of the anchor being ordered. The round whose links committed it
+    /// is the highest round of the DAG, the next round at least: the anchor the commit rule
+    /// committed directly is ordered as soon as the links of its next round arrive, and the ones
+    /// ordered in its history before it. The attempt is a fallback if a round above the previous
+    /// anchor recorded is skipped.
+    fn record_commit_latency(&mut self, anchor: &NodeMetadata) -> AnchorCommitLatency {
+        let previous_round = self
+            .commit_latencies
+            .back()
+            .map_or(0, |latency| latency.anchor_round);
+        let attempt = if self
+            .skipped_rounds
+            .range(previous_round + 1..anchor.round())
+            .next()
+            .is_some()
+        {
+            AnchorAttempt::Fallback
+        } else {
+            AnchorAttempt::First
+        };
+        let latency = AnchorCommitLatency {
+            anchor_round: anchor.round(),
+            committing_round: self.highest_round().max(anchor.round() + 1),
+            attempt,
+        };
+        counters::ANCHOR_COMMIT_LATENCY_ROUNDS
+            .with_label_values(&[attempt.name()])
+            .observe(latency.rounds() as f64);
+        if self.commit_latencies.len() == COMMIT_LATENCY_WINDOW {
+            self.commit_latencies.pop_front();
+        }
+        self.commit_latencies.push_back(latency);
+        latency
+    }
+
+    /// The latencies of the last `COMMIT_LATENCY_WINDOW` anchors recorded, oldest first.
+    pub fn commit_latencies(&self) -> Vec<AnchorCommitLatency> {
+        self.commit_latencies.iter().copied().collect()
+    }
+
+    pub fn commit_latency(&self) -> CommitLatencyAverage {
+        CommitLatencyAverage {
+            total_rounds: self
+                .commit_latencies
+                .iter()
+                .map(|latency| latency.rounds())
+                .sum(),
+            num_anchors: self.commit_latencies.len(),
+        }
+    }
+
+    /// The nodes ordered by the anchors up to `committed_round` are committed.
+    fn record_commit(&mut self, committed_round: Round) {
+        self.committed_round = self.committed_round.max(committed_round);
+        let now = self.time_service.get_current_timestamp();
+        let awaiting = self.awaiting_commit.split_off(&(committed_round + 1));
+        let committed = std::mem::replace(&mut self.awaiting_commit, awaiting);
+        for digest in committed.into_values().flatten() {
+            let (node, timings) = match (
+                self.nodes_by_digest.get(&digest),
+                self.node_timings.get(&digest),
+            ) {
+                (Some(node), Some(timings)) => (node, timings),
+                _ => continue,
+            };
+            let sample = NodeLatencySample {
+                digest,
+                round: node.metadata().round(),
+                author: *node.metadata().author(),
+                inserted_at: timings.inserted_at,
+                ordered_at: timings.ordered_at,
+                committed_at: now,
+            };
+            if let Some(latency) = sample.order_to_commit() {
+                counters::NODE_ORDER_TO_COMMIT_SECONDS.observe(latency.as_secs_f64());
             }
+            if self.latency_samples.len() == MAX_LATENCY_SAMPLES {
+                self.latency_samples.pop_front();
+            }
+            self.latency_samples.push_back(sample);
+        }
+    }
+
+    pub fn get_node_by_round_author(
+        &self,
+        round: Round,
+        author: &Author,
+    ) -> Option<&Arc<CertifiedNode>> {
+        self.get_node_status(round, author).map(NodeStatus::as_node)
+    }
+
+    pub fn get_node_status(&self, round: Round, author: &Author) -> Option<&NodeStatus> {
+        let slot = self.slot_of(round, author).ok()?;
+        self.slot_status(slot).ok()?
+    }
+
+    /// The slot of `author` in `round`.
+    pub fn slot_of(&self, round: Round, author: &Author) -> Result<SlotId, SlotIdError> {
+        SlotId::of_author(round, author, &self.validator_index)
+    }
+
+    /// The status of the node in `slot`, `None` if the slot is empty or its round isn't held.
+    pub fn slot_status(&self, slot: SlotId) -> Result<Option<&NodeStatus>, SlotIdError> {
+        slot.author(&self.validator_index)?;
+        Ok(self
+            .nodes_by_round
+            .get(&slot.round())
+            .and_then(|slots| slots.get(slot.position()))
+            .and_then(Option::as_ref))
+    }
+
+    /// Sets the status of the node in `slot`, adding its round if needed. The slot is resolved
+    /// by `slot_of` against the validator index, which doesn't change within the epoch, so it's
+    /// in range.
+    fn set_slot(&mut self, slot: SlotId, status: NodeStatus) {
+        let num_validators = self.validator_index.len();
+        self.nodes_by_round
+            .entry(slot.round())
+            .or_insert_with(|| vec![None; num_validators])[slot.position()] = Some(status);
+    }
+
+    /// The status of each node in the same order, `None` for the nodes not in the DAG, either
+    /// missing or pruned.
+    pub fn statuses(&self, metadatas: &[NodeMetadata]) -> Vec<Option<NodeStatusKind>> {
+        metadatas
+            .iter()
+            .map(|metadata| {
+                self.get_node_status(metadata.round(), metadata.author())
+                    .filter(|status| status.as_node().digest() == *metadata.digest())
+                    .map(NodeStatus::kind)
+            })
+            .collect()
+    }
+
+    /// Whether all the nodes are ordered, an ordered node counts as committed before the ledger
+    /// confirms it. The nodes no longer in the DAG count as not ordered.
+    pub fn all_committed(&self, metadatas: &[NodeMetadata]) -> bool {
+        self.statuses(metadatas).into_iter().all(|status| {
+            matches!(
+                status,
+                Some(NodeStatusKind::Ordered | NodeStatusKind::Committed)
+            )
+        })
+    }
+
+    /// Computes the slots to fetch before `target` can be added without inserting anything. The
+    /// missing parents are known from their certificates, and as their own history is unknown
+    /// every hole from the lowest round up to them is included. Parents below the lowest round
+    /// are considered satisfied. A target with a parent that doesn't match the stored node can
+    /// never be added, nothing is fetched for it.
+    pub fn fetch_plan_for(&self, target: &CertifiedNode) -> FetchPlan {
+        if target
+            .parents()
+            .iter()
+            .any(|parent| self.resolve_parent(parent).is_err())
+        {
+            return FetchPlan::default();
         }
-        if let Err(e) = storage.delete_certified_nodes(expired) {
-            error!("Error deleting expired nodes: {:?}", e);
+        let lowest_round = self.lowest_round();
+        let missing_parents: Vec<_> = target
+            .parents()
+            .iter()
+            .map(NodeCertificate::metadata)
+            .filter(|metadata| metadata.round() >= lowest_round && !self.exists(metadata.digest()))
+            .collect();
+        let Some(top_round) = missing_parents
+            .iter()
+            .map(|metadata| metadata.round())
+            .max()
+        else {
+            return FetchPlan::default();
+        };
+        let mut missing_indices: BTreeMap<Round, BTreeSet<usize>> = BTreeMap::new();
+        for round in lowest_round..top_round {
+            let holes: BTreeSet<_> = (0..self.validator_index.len())
+                .filter(|index| {
+                    self.nodes_by_round
+                        .get(&round)
+                        .map_or(true, |slots| slots[*index].is_none())
+                })
+                .collect();
+            if !holes.is_empty() {
+                missing_indices.insert(round, holes);
+            }
         }
-        Self {
-            nodes_by_digest,
-            nodes_by_round,
-            author_to_index,
-            storage,
+        for metadata in missing_parents {
+            if let Some(index) = self.validator_index.index_of(metadata.author()) {
+                missing_indices
+                    .entry(metadata.round())
+                    .or_default()
+                    .insert(index);
+            }
+        }
+
+        let validators = self.validator_index.authors();
+        let missing_slots: BTreeMap<_, Vec<_>> = missing_indices
+            .into_iter()
+            .map(|(round, indices)| {
+                (
+                    round,
+                    indices.into_iter().map(|index| validators[index]).collect(),
+                )
+            })
+            .collect();
+        FetchPlan {
+            estimated_nodes: missing_slots.values().map(Vec::len).sum(),
+            missing_slots,
         }
     }
 
-    pub(crate) fn lowest_round(&self) -> Round {
-        *self
+    /// The nodes of `author` in `rounds` that are within the window, in ascending round order.
+    pub fn get_author_nodes_in_range(
+        &self,
+        author: &Author,
+        rounds: RangeInclusive<Round>,
+    ) -> Vec<Arc<CertifiedNode>> {
+        let index = match self.validator_index.index_of(author) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        let start = (*rounds.start()).max(self.lowest_round());
+        let end = (*rounds.end()).min(self.highest_round());
+        if start > end {
+            return vec![];
+        }
+        let rounds: Vec<_> = self
             .nodes_by_round
-            .first_key_value()
-            .map(|(round, _)| round)
-            .unwrap_or(&0)
+            .range(start..=end)
+            .map(|(round, _)| *round)
+            .collect();
+        self.with_rounds(&rounds, |view| {
+            view.rounds()
+                .iter()
+                .filter_map(|round| view.slots(*round)?[index].as_ref())
+                .map(|status| status.as_node().clone())
+                .collect()
+        })
     }
 
-    pub fn highest_round(&self) -> Round {
-        *self
+    /// The nodes of `get_missing_nodes`, compacted for a requester omitting the parent
+    /// certificates it has: the parents in its exists bitmask are sent as digests.
+    pub fn get_missing_compact_nodes(
+        &self,
+        request: &RemoteFetchRequest,
+    ) -> Vec<Vec<CompactCertifiedNode>> {
+        let exists = request.exists_bitmask();
+        let is_known = |parent: &NodeMetadata| {
+            self.validator_index
+                .index_of(parent.author())
+                .map_or(false, |index| exists.has(parent.round(), index))
+        };
+        self.get_missing_nodes(request)
+            .iter()
+            .map(|round_nodes| {
+                round_nodes
+                    .iter()
+                    .map(|node| CompactCertifiedNode::new(node, is_known))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The nodes in the causal history of the request target that the requester has neither in
+    /// its DAG nor in its pending buffer, by ascending round. The history of the pending nodes is
+    /// still traversed as their parents may be missing too. Nothing is returned if the target is
+    /// unknown.
+    pub fn get_missing_nodes(&self, request: &RemoteFetchRequest) -> Vec<Vec<CertifiedNode>> {
+        let reader = self.read();
+        let target = match reader.get_node_by_digest(request.target().digest()) {
+            Some(target) => target,
+            None => return vec![],
+        };
+        let exists = request.exists_bitmask();
+        let pending = request.pending_bitmask();
+        let start_round = request.start_round().max(self.lowest_round());
+        if start_round >= target.metadata().round() {
+            return vec![];
+        }
+        let rounds: Vec<_> = self
             .nodes_by_round
-            .last_key_value()
-            .map(|(round, _)| round)
-            .unwrap_or(&0)
+            .range(start_round..target.metadata().round())
+            .map(|(round, _)| *round)
+            .collect();
+        let mut to_visit: HashSet<_> = target
+            .parents()
+            .iter()
+            .map(|parent| *parent.metadata().digest())
+            .collect();
+        let mut missing = self.with_rounds(&rounds, |view| {
+            let mut missing = vec![];
+            for round in view.rounds().iter().rev() {
+                let mut round_nodes = vec![];
+                for (index, status) in view.slots(*round).unwrap_or_default().iter().enumerate() {
+                    let node = match status {
+                        Some(status) if to_visit.contains(&status.as_node().digest()) => {
+                            status.as_node()
+                        },
+                        _ => continue,
+                    };
+                    // the requester only has nodes whose history is complete
+                    if exists.has(*round, index) {
+                        continue;
+                    }
+                    to_visit.extend(
+                        node.parents()
+                            .iter()
+                            .map(|parent| *parent.metadata().digest()),
+                    );
+                    if !pending.has(*round, index) {
+                        round_nodes.push(node.as_ref().clone());
+                    }
+                }
+                if !round_nodes.is_empty() {
+                    missing.push(round_nodes);
+                }
+            }
+            missing
+        });
+        missing.reverse();
+        missing
     }
 
-    pub fn add_node(&mut self, node: CertifiedNode) -> anyhow::Result<()> {
-        let node = Arc::new(node);
-        let index = *self
-            .author_to_index
-            .get(node.metadata().author())
-            .ok_or_else(|| anyhow!("unknown author"))?;
-        let round = node.metadata().round();
-        ensure!(round >= self.lowest_round(), "round too low");
-        ensure!(round <= self.highest_round() + 1, "round too high");
-        for parent in node.parents() {
-            ensure!(self.exists(parent.metadata().digest()), "parent not exist");
+    /// The rounds within the window that have no node from `author`, in ascending order.
+    pub fn missing_slots_for_author(&self, author: &Author) -> Vec<Round> {
+        let index = match self.validator_index.index_of(author) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        if self.nodes_by_round.is_empty() {
+            return vec![];
         }
-        self.storage.save_certified_node(&node)?;
-        ensure!(
-            self.nodes_by_digest
-                .insert(node.digest(), node.clone())
-                .is_none(),
-            "duplicate node"
+        (self.lowest_round()..=self.highest_round())
+            .filter(|round| {
+                self.nodes_by_round
+                    .get(round)
+                    .map_or(true, |slots| slots[index].is_none())
+            })
+            .collect()
+    }
+
+    #[deprecated(note = "use `strong_links_for_round`, the DAG checks against its own epoch state")]
+    pub fn get_strong_links_for_round(
+        &self,
+        round: Round,
+        _validator_verifier: &ValidatorVerifier,
+    ) -> Option<Vec<NodeCertificate>> {
+        self.strong_links_for_round(round)
+    }
+
+    pub fn strong_links_for_round(&self, round: Round) -> Option<Vec<NodeCertificate>> {
+        self.try_strong_links_for_round(round).ok()
+    }
+
+    /// All the certificates of `round` if they have enough voting power, otherwise whether the
+    /// round is out of the window or only lacks voting power for now.
+    pub fn try_strong_links_for_round(
+        &self,
+        round: Round,
+    ) -> Result<Vec<NodeCertificate>, StrongLinksError> {
+        let lowest_round = self.lowest_round();
+        if round < lowest_round {
+            return Err(StrongLinksError::RoundPruned {
+                round,
+                lowest_round,
+            });
+        }
+        let highest_round = self.highest_round();
+        if round > highest_round {
+            return Err(StrongLinksError::RoundUnknown {
+                round,
+                highest_round,
+            });
+        }
+        let present = self.read().round_power(round);
+        let required = self.epoch_state.verifier.quorum_voting_power();
+        if present < required {
+            return Err(StrongLinksError::InsufficientPower { present, required });
+        }
+        Ok(self
+            .get_certificates_for_round(round)
+            .into_iter()
+            .filter(|certificate| !self.is_excluded(certificate.metadata().author()))
+            .collect())
+    }
+
+    /// Strong links of `round` whose certificates take at most `max_bytes`, so the node linking
+    /// them stays under the network message limit. All the certificates of the round if they fit,
+    /// otherwise a quorum of voting power: the certificate of `anchor`, the anchor of the round,
+    /// first so its commit doesn't lose the link, then the others by decreasing voting power and
+    /// by arrival, until the quorum is reached or the next one doesn't fit. They're returned in
+    /// validator index order.
+    pub fn get_strong_links_bounded(
+        &self,
+        round: Round,
+        max_bytes: usize,
+        anchor: &Author,
+    ) -> Result<Vec<NodeCertificate>, StrongLinksError> {
+        let strong_links = self.try_strong_links_for_round(round)?;
+        let num_bytes: usize = strong_links
+            .iter()
+            .map(NodeCertificate::serialized_size)
+            .sum();
+        if num_bytes <= max_bytes {
+            return Ok(strong_links);
+        }
+        let mut candidates: Vec<_> = self
+            .nodes_by_round
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(index, status)| {
+                let node = status.as_ref()?.as_node();
+                let power = self.counted_power(node.metadata().author());
+                let inserted_at = self
+                    .node_timings
+                    .get(&node.digest())
+                    .map(|timings| timings.inserted_at);
+                (power > 0).then_some((index, node, power, inserted_at))
+            })
+            .collect();
+        candidates.sort_by_key(|(index, node, power, inserted_at)| {
+            (
+                node.metadata().author() != anchor,
+                std::cmp::Reverse(*power),
+                *inserted_at,
+                *index,
+            )
+        });
+        let required = self.epoch_state.verifier.quorum_voting_power();
+        let mut selected = vec![];
+        let mut fitting = 0;
+        let mut num_bytes = 0;
+        for (index, node, power, _) in candidates {
+            if fitting >= required || num_bytes + node.certificate_size() > max_bytes {
+                break;
+            }
+            num_bytes += node.certificate_size();
+            fitting += power;
+            selected.push((index, node.certificate()));
+        }
+        if fitting < required {
+            return Err(StrongLinksError::OverSizeBudget {
+                fitting,
+                required,
+                max_bytes,
+            });
+        }
+        selected.sort_by_key(|(index, _)| *index);
+        Ok(selected
+            .into_iter()
+            .map(|(_, certificate)| certificate)
+            .collect())
+    }
+
+    /// Who keeps `anchor` from being committed. An anchor is committed once the nodes of the next
+    /// round linking to it have more than a third of the voting power, so at least one of them is
+    /// honest. The links come from the children index and the power is counted as for the round,
+    /// so it takes one pass over the validators.
+    pub fn anchor_blockers(&self, anchor: &NodeMetadata) -> AnchorBlockReport {
+        let verifier = &self.epoch_state.verifier;
+        let round = anchor.round() + 1;
+        let slots = self.nodes_by_round.get(&round);
+        let mut linking = vec![false; self.validator_index.len()];
+        for slot in self.children_of(anchor.digest()) {
+            if slot.round() == round {
+                linking[slot.position()] = true;
+            }
+        }
+        let mut report = AnchorBlockReport {
+            not_linking: vec![],
+            missing: vec![],
+            linking_power: 0,
+            shortfall: 0,
+        };
+        for (index, author) in self.validator_index.authors().iter().enumerate() {
+            let power = self.counted_power(author);
+            match slots.and_then(|slots| slots[index].as_ref()) {
+                Some(_) if linking[index] && power > 0 => report.linking_power += power,
+                Some(_) => report.not_linking.push(*author),
+                None => report.missing.push(*author),
+            }
+        }
+        let required = verifier.total_voting_power() - verifier.quorum_voting_power() + 1;
+        report.shortfall = required.saturating_sub(report.linking_power);
+        report
+    }
+
+    /// The slots of the nodes of the DAG linking to the node with `digest`, in round and validator
+    /// index order.
+    pub fn children_of(&self, digest: &HashValue) -> impl Iterator<Item = SlotId> + '_ {
+        self.children.get(digest).into_iter().flatten().copied()
+    }
+
+    /// All the certificates of `round` in validator index order, whether or not the round has
+    /// enough voting power.
+    pub fn get_certificates_for_round(&self, round: Round) -> Vec<NodeCertificate> {
+        self.nodes_by_round
+            .get(&round)
+            .map_or_else(Vec::new, |slots| {
+                slots
+                    .iter()
+                    .flatten()
+                    .map(|status| status.as_node().certificate())
+                    .collect()
+            })
+    }
+
+    /// The authors of `round` in validator index order.
+    pub fn round_authors(&self, round: Round) -> Vec<Author> {
+        self.nodes_by_round
+            .get(&round)
+            .map_or_else(Vec::new, |slots| {
+                slots
+                    .iter()
+                    .flatten()
+                    .map(|status| *status.as_node().metadata().author())
+                    .collect()
+            })
+    }
+
+    /// Fingerprint of the nodes and their statuses up to `up_to_round` included, two DAGs with the
+    /// same content have the same digest regardless of the insertion order.
+    pub fn content_digest(&self, up_to_round: Option<Round>) -> HashValue {
+        let mut hasher = DagSlotHasher::default();
+        for (round, slots) in self
+            .nodes_by_round
+            .range(..=up_to_round.unwrap_or(Round::MAX))
+        {
+            for (author_index, status) in slots.iter().enumerate() {
+                if let Some(status) = status {
+                    let slot = DagSlot {
+                        round: *round,
+                        author_index: author_index as u64,
+                        digest: status.as_node().digest(),
+                        status: match status {
+                            NodeStatus::Unordered(_) => 0,
+                            NodeStatus::Ordered(_) => 1,
+                            NodeStatus::Committed(_) => 2,
+                        },
+                    };
+                    hasher.update(&bcs::to_bytes(&slot).expect("Unable to serialize slot"));
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    pub fn content_view(&self) -> DagContentView {
+        let slots = self
+            .nodes_by_round
+            .iter()
+            .flat_map(|(round, slots)| {
+                slots.iter().flatten().map(move |status| {
+                    let node = status.as_node();
+                    (
+                        (*round, *node.metadata().author()),
+                        (node.digest(), status.kind()),
+                    )
+                })
+            })
+            .collect();
+        DagContentView {
+            floor: self.lowest_round(),
+            slots,
+            pending: self
+                .pending_nodes
+                .values()
+                .flat_map(|nodes| nodes.keys().copied())
+                .collect(),
+            equivocators: self.equivocators.keys().copied().collect(),
+            last_committed_anchor: self
+                .last_committed_anchor
+                .as_ref()
+                .map(|anchor| *anchor.digest()),
+        }
+    }
+
+    /// The node digests of every round, indexed by validator, to be compared with `diff`.
+    pub fn slot_digests(&self) -> BTreeMap<Round, Vec<Option<HashValue>>> {
+        self.nodes_by_round
+            .iter()
+            .map(|(round, slots)| {
+                let digests = slots
+                    .iter()
+                    .map(|status| status.as_ref().map(|status| status.as_node().digest()))
+                    .collect();
+                (*round, digests)
+            })
+            .collect()
+    }
+
+    /// Fingerprint of the nodes of `round`, two DAGs holding the same nodes in the round have the
+    /// same digest. Cached until the generation of the round changes.
+    pub fn round_digest(&self, round: Round) -> Option<HashValue> {
+        let slots = self.nodes_by_round.get(&round)?;
+        let generation = self.round_generation(round);
+        let mut round_digests = self.round_digests.lock();
+        if let Some((cached_at, digest)) = round_digests.get(&round) {
+            if *cached_at == generation {
+                return Some(*digest);
+            }
+        }
+        let round_slots = RoundSlots {
+            round,
+            digests: slots
+                .iter()
+                .map(|status| status.as_ref().map(|status| status.as_node().digest()))
+                .collect(),
+        };
+        let mut hasher = RoundSlotsHasher::default();
+        hasher.update(&bcs::to_bytes(&round_slots).expect("Unable to serialize round"));
+        let digest = hasher.finish();
+        round_digests.insert(round, (generation, digest));
+        Some(digest)
+    }
+
+    /// The digests of the rounds from `from` on, to find the diverging rounds with a peer before
+    /// comparing their slots with `diff`.
+    pub fn round_digests(&self, from: Round) -> Vec<(Round, HashValue)> {
+        self.nodes_by_round
+            .range(from..)
+            .filter_map(|(round, _)| self.round_digest(*round).map(|digest| (*round, digest)))
+            .collect()
+    }
+
+    /// Finds the first slot that differs from the `slot_digests` of another DAG, a missing round
+    /// counts as a round with empty slots.
+    pub fn diff(&self, other_digests: &BTreeMap<Round, Vec<Option<HashValue>>>) -> DagDiff {
+        let local_digests = self.slot_digests();
+        let rounds: BTreeSet<_> = local_digests
+            .keys()
+            .chain(other_digests.keys())
+            .copied()
+            .collect();
+        let slot = |digests: &BTreeMap<Round, Vec<Option<HashValue>>>, round, author_index| {
+            digests
+                .get(&round)
+                .and_then(|slots: &Vec<Option<HashValue>>| slots.get(author_index))
+                .copied()
+                .flatten()
+        };
+        for round in rounds {
+            let num_slots = [&local_digests, other_digests]
+                .iter()
+                .filter_map(|digests| digests.get(&round).map(Vec::len))
+                .max()
+                .unwrap_or_default();
+            for author_index in 0..num_slots {
+                let local = slot(&local_digests, round, author_index);
+                let other = slot(other_digests, round, author_index);
+                if local != other {
+                    return DagDiff::Divergent {
+                        round,
+                        author_index,
+                        local,
+                        other,
+                    };
+                }
+            }
+        }
+        DagDiff::Identical
+    }
+
+    /// Checks every node from the lowest round against storage, saving the in-memory nodes that
+    /// are missing from storage and adding back the persisted nodes missing from memory. Storage
+    /// is scanned without the lock and the DAG is only locked to reconcile one round at a time.
+    pub fn audit_against_storage(dag: &RwLock<Self>) -> anyhow::Result<AuditReport> {
+        let (storage, epoch, lowest_round) = {
+            let dag = dag.read();
+            (
+                dag.storage.clone(),
+                dag.epoch_state.epoch,
+                dag.lowest_round(),
+            )
+        };
+        let mut persisted_by_round: BTreeMap<Round, Vec<(HashValue, CertifiedNode)>> =
+            BTreeMap::new();
+        for (digest, node) in storage.get_certified_nodes()? {
+            if node.metadata().epoch() == epoch && node.metadata().round() >= lowest_round {
+                persisted_by_round
+                    .entry(node.metadata().round())
+                    .or_default()
+                    .push((digest, node));
+            }
+        }
+        let mut rounds: BTreeSet<Round> = persisted_by_round.keys().copied().collect();
+        rounds.extend(
+            dag.read()
+                .nodes_by_round
+                .range(lowest_round..)
+                .map(|(round, _)| *round),
         );
-        let round_ref = self
+
+        let mut report = AuditReport::default();
+        for round in rounds {
+            let persisted = persisted_by_round.remove(&round).unwrap_or_default();
+            dag.write().audit_round(round, persisted, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    /// The persisted nodes are reconciled by validator index and `slot_precedence`, so of two
+    /// nodes of the same slot the one added back is the one recovery keeps, and `unrecoverable`
+    /// lists the digests in the same order on every run.
+    fn audit_round(
+        &mut self,
+        round: Round,
+        mut persisted: Vec<(HashValue, CertifiedNode)>,
+        report: &mut AuditReport,
+    ) -> anyhow::Result<()> {
+        let first_arrivals = first_arrivals(self.equivocation_evidence.iter());
+        persisted.sort_by_key(|(digest, node)| {
+            (
+                self.validator_index.order_key(node.metadata().author()),
+                slot_precedence(&first_arrivals, digest),
+            )
+        });
+        let persisted_digests: HashSet<_> = persisted.iter().map(|(digest, _)| *digest).collect();
+        let missing_from_storage: Vec<_> = self
             .nodes_by_round
-            .entry(round)
-            .or_insert_with(|| vec![None; self.author_to_index.len()]);
-        ensure!(round_ref[index].is_none(), "equivocate node");
-        round_ref[index] = Some(node);
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|status| status.as_node().clone())
+            .filter(|node| !persisted_digests.contains(&node.digest()))
+            .collect();
+        for node in missing_from_storage {
+            self.storage.save_certified_node(&node)?;
+            report.num_resaved += 1;
+        }
+        for (digest, node) in persisted {
+            if digest != node.digest() {
+                report.unrecoverable.push(digest);
+                continue;
+            }
+            if self.exists(&digest) {
+                continue;
+            }
+            match self.add_node(node) {
+                Ok(()) => report.num_loaded += 1,
+                Err(DagStoreError::Storage(e)) => return Err(e),
+                Err(_) => report.unrecoverable.push(digest),
+            }
+        }
         Ok(())
     }
 
-    pub fn exists(&self, digest: &HashValue) -> bool {
-        self.nodes_by_digest.contains_key(digest)
+    /// The default traversal budget for the current validator set.
+    pub fn traversal_budget(&self) -> TraversalBudget {
+        TraversalBudget::from_window(DEFAULT_WINDOW_SIZE, self.validator_index.len())
     }
 
-    pub fn all_exists(&self, nodes: &[NodeCertificate]) -> bool {
-        nodes.iter().all(|certificate| {
-            self.nodes_by_digest
-                .contains_key(certificate.metadata().digest())
+    /// Marks every unordered node in the causal history of the anchor as ordered and returns
+    /// them sorted by round and then by validator index, which is the same on every validator.
+    /// Nothing is marked if the history doesn't fit in the budget, or if the anchor doesn't reach
+    /// `previous`, the previously committed anchor, `last_committed_anchor` if `None`. An anchor
+    /// refused with `AnchorNotConnected` is left to the skip path.
+    pub fn order_anchor(
+        &mut self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+        budget: TraversalBudget,
+    ) -> Result<OrderedBatch, DagStoreError> {
+        let span = debug_span!(
+            "dag::order_anchor",
+            round = anchor.round(),
+            num_nodes = field::Empty
+        );
+        let _entered = span.enter();
+        let nodes = self.unordered_history(anchor, previous, budget)?;
+        let sources: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .map(|(position, node)| BatchSourceInfo::new(node, position as u64))
+            .collect();
+        // persisted before marking the nodes so a failure leaves the anchor unordered
+        let ordered_anchor = OrderedAnchor::new(anchor.clone(), sources.clone());
+        self.mode.handle(
+            self.storage.save_ordered_anchor(&ordered_anchor),
+            "save_ordered_anchor",
+        )?;
+        self.save_committed_anchor(anchor)?;
+        self.mark_ordered(anchor, &nodes);
+        self.ordered_anchors
+            .insert(*anchor.digest(), ordered_anchor);
+        self.last_committed_anchor = Some(anchor.clone());
+        self.epoch_totals.num_ordered_anchors += 1;
+        self.epoch_totals.total_commit_latency_rounds +=
+            self.highest_round().saturating_sub(anchor.round());
+        self.record_commit_latency(anchor);
+        span.record("num_nodes", nodes.len());
+        Ok(OrderedBatch {
+            anchor: anchor.clone(),
+            nodes,
+            sources,
         })
     }
 
-    pub fn get_node(&self, digest: &HashValue) -> Option<Arc<CertifiedNode>> {
-        self.nodes_by_digest.get(digest).cloned()
+    /// Orders the causal history of the anchor like `order_anchor` with the default budget, but
+    /// hands it to `sink` in chunks of at most `chunk_size` nodes. Each chunk is persisted and
+    /// marked as ordered before it's emitted, so calling it again after the sink stopped or after
+    /// a restart resumes with the next chunk and never emits a node twice, a crash between the
+    /// write and the sink loses that chunk like it loses a batch of `order_anchor`. The last chunk
+    /// carries the completion of the anchor. Returns `Break` if the sink stopped before it.
+    pub fn order_anchor_streamed(
+        &mut self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+        chunk_size: usize,
+        mut sink: impl FnMut(OrderedChunk) -> ControlFlow<()>,
+    ) -> Result<ControlFlow<()>, DagStoreError> {
+        let span = debug_span!(
+            "dag::order_anchor_streamed",
+            round = anchor.round(),
+            num_nodes = field::Empty
+        );
+        let _entered = span.enter();
+        let nodes = self.unordered_history(anchor, previous, self.traversal_budget())?;
+        span.record("num_nodes", nodes.len());
+        // the sources of the chunks emitted before the sink stopped
+        let mut sources = self
+            .ordered_anchors
+            .get(anchor.digest())
+            .map(|ordered_anchor| ordered_anchor.sources().to_vec())
+            .unwrap_or_default();
+        let chunk_size = chunk_size.max(1);
+        let num_chunks = (nodes.len() + chunk_size - 1) / chunk_size;
+        for (index, chunk) in nodes.chunks(chunk_size).enumerate() {
+            let start = sources.len() as u64;
+            let chunk_sources: Vec<_> = chunk
+                .iter()
+                .enumerate()
+                .map(|(offset, node)| BatchSourceInfo::new(node, start + offset as u64))
+                .collect();
+            sources.extend(chunk_sources.iter().cloned());
+            let ordered_anchor = OrderedAnchor::new(anchor.clone(), sources.clone());
+            self.mode.handle(
+                self.storage.save_ordered_anchor(&ordered_anchor),
+                "save_ordered_anchor",
+            )?;
+            if index + 1 == num_chunks {
+                self.save_committed_anchor(anchor)?;
+            }
+            self.mark_ordered(anchor, chunk);
+            if index + 1 == num_chunks {
+                self.last_committed_anchor = Some(anchor.clone());
+            }
+            let completion = (index + 1 == num_chunks).then(|| {
+                self.epoch_totals.num_ordered_anchors += 1;
+                self.epoch_totals.total_commit_latency_rounds +=
+                    self.highest_round().saturating_sub(anchor.round());
+                self.record_commit_latency(anchor);
+                AnchorCompletion {
+                    anchor: anchor.clone(),
+                    failed_authors: self.failed_authors(anchor.round()),
+                }
+            });
+            self.ordered_anchors
+                .insert(*anchor.digest(), ordered_anchor);
+            let flow = sink(OrderedChunk {
+                start,
+                nodes: chunk.to_vec(),
+                sources: chunk_sources,
+                completion,
+            });
+            if flow.is_break() && index + 1 < num_chunks {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
     }
 
-    pub fn get_strong_links_for_round(
+    /// The unordered causal history of the anchor, in the order it's committed.
+    fn unordered_history(
+        &self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+        budget: TraversalBudget,
+    ) -> Result<Vec<Arc<CertifiedNode>>, DagStoreError> {
+        if self.observer.is_some() {
+            return Err(DagStoreError::OrderingDisabled);
+        }
+        if self.skipped_anchor(anchor.round()) == Some(anchor.author()) {
+            return Err(DagStoreError::AnchorSkipped(anchor.round()));
+        }
+        let reachable = self.reachable(anchor, budget).map_err(|e| {
+            if matches!(e, DagStoreError::BudgetExceeded { .. }) {
+                counters::TRAVERSAL_BUDGET_EXCEEDED_COUNT.inc();
+            }
+            e
+        })?;
+        self.check_continuity(anchor, previous)?;
+        let mut nodes = vec![];
+        for (round, indices) in reachable.iter().rev() {
+            let slots = &self.nodes_by_round[round];
+            for index in indices {
+                nodes.push(
+                    slots[*index]
+                        .as_ref()
+                        .expect("reachable node must exist")
+                        .as_node()
+                        .clone(),
+                );
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Refuses an anchor that doesn't reach the previously committed anchor, unless that one is
+    /// below the lowest round and already pruned. Two validators committing anchors without a path
+    /// between them could order diverging histories.
+    fn check_continuity(
+        &self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+    ) -> Result<(), DagStoreError> {
+        let previous = match previous.or(self.last_committed_anchor.as_ref()) {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+        if previous.round() < self.lowest_round() || self.has_path(anchor, previous) {
+            return Ok(());
+        }
+        counters::ANCHOR_NOT_CONNECTED_COUNT.inc();
+        warn!(
+            "Anchor {} of round {} doesn't reach the last committed anchor {} of round {}",
+            anchor.digest(),
+            anchor.round(),
+            previous.digest(),
+            previous.round()
+        );
+        Err(DagStoreError::AnchorNotConnected {
+            anchor: *anchor.digest(),
+            previous: *previous.digest(),
+        })
+    }
+
+    /// Persists the anchor as the last committed one, after its ordered anchor record and before
+    /// its nodes are marked, so a failure leaves the anchor unordered and the previous one in
+    /// force.
+    fn save_committed_anchor(&self, anchor: &NodeMetadata) -> Result<(), DagStoreError> {
+        self.mode.handle(
+            self.storage.save_last_committed_anchor(anchor),
+            "save_last_committed_anchor",
+        )
+    }
+
+    /// The last anchor ordered in the epoch, also available after recovery and once pruned.
+    pub fn last_committed_anchor(&self) -> Option<&NodeMetadata> {
+        self.last_committed_anchor.as_ref()
+    }
+
+    /// Whether `to` is in the causal history of `from`, following the parents of the nodes in the
+    /// DAG whatever their status. A node counts as reaching itself.
+    pub fn has_path(&self, from: &NodeMetadata, to: &NodeMetadata) -> bool {
+        let mut visited = HashSet::from([*from.digest()]);
+        let mut frontier = vec![*from.digest()];
+        while let Some(digest) = frontier.pop() {
+            if digest == *to.digest() {
+                return true;
+            }
+            if let Some(node) = self.nodes_by_digest.get(&digest) {
+                for parent in node.parents() {
+                    let parent = parent.metadata();
+                    if parent.round() >= to.round() && visited.insert(*parent.digest()) {
+                        frontier.push(*parent.digest());
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Marks the nodes ordered by the anchor once their ordering is persisted.
+    fn mark_ordered(&mut self, anchor: &NodeMetadata, nodes: &[Arc<CertifiedNode>]) {
+        for node in nodes {
+            let round = node.metadata().round();
+            let index = self.author_index(node.author());
+            let slot = self
+                .nodes_by_round
+                .get_mut(&round)
+                .and_then(|slots| slots[index].as_mut())
+                .expect("reachable node must exist");
+            if !slot.is_ordered() {
+                *slot = NodeStatus::Ordered(slot.as_node().clone());
+                self.bump_generation(round);
+            }
+        }
+        let now = self.time_service.get_current_timestamp();
+        for node in nodes {
+            if let Some(timings) = self.node_timings.get_mut(&node.digest()) {
+                timings.ordered_at = Some(now);
+                counters::NODE_INSERT_TO_ORDER_SECONDS
+                    .observe(now.saturating_sub(timings.inserted_at).as_secs_f64());
+            }
+        }
+        self.awaiting_commit
+            .entry(anchor.round())
+            .or_default()
+            .extend(nodes.iter().map(|node| node.digest()));
+    }
+
+    /// The authors of the anchors skipped after the previous ordered anchor and before `round`.
+    fn failed_authors(&self, round: Round) -> Vec<(Round, Author)> {
+        let previous_round = self
+            .ordered_anchors
+            .values()
+            .map(|ordered_anchor| ordered_anchor.anchor().round())
+            .filter(|anchor_round| *anchor_round < round)
+            .max()
+            .unwrap_or(0);
+        self.skipped_rounds
+            .range(previous_round + 1..round)
+            .map(|(skipped_round, certificate)| (*skipped_round, *certificate.skip().anchor()))
+            .collect()
+    }
+
+    /// The batch sources of an ordered anchor, also available after recovery.
+    pub fn ordered_sources(&self, anchor_digest: &HashValue) -> Option<&[BatchSourceInfo]> {
+        self.ordered_anchors
+            .get(anchor_digest)
+            .map(OrderedAnchor::sources)
+    }
+
+    /// Positions of the unordered nodes in the causal history of the anchor, grouped by round in
+    /// descending order.
+    fn reachable(
+        &self,
+        anchor: &NodeMetadata,
+        budget: TraversalBudget,
+    ) -> Result<Vec<(Round, Vec<usize>)>, DagStoreError> {
+        let status = self
+            .get_node_status(anchor.round(), anchor.author())
+            .filter(|status| status.as_node().metadata() == anchor)
+            .ok_or(DagStoreError::MissingAnchor(*anchor.digest()))?;
+        if status.is_ordered() {
+            return Err(DagStoreError::AnchorAlreadyOrdered(*anchor.digest()));
+        }
+
+        let mut reachable = HashSet::from([*anchor.digest()]);
+        let mut visited_nodes = 0;
+        let mut positions = vec![];
+        for (round, slots) in self.nodes_by_round.range(..=anchor.round()).rev() {
+            let mut indices = vec![];
+            for (index, slot) in slots.iter().enumerate() {
+                if let Some(NodeStatus::Unordered(node)) = slot {
+                    if reachable.contains(&node.digest()) {
+                        visited_nodes += 1;
+                        if visited_nodes > budget.max_nodes
+                            || anchor.round() - round >= budget.max_rounds
+                        {
+                            return Err(DagStoreError::BudgetExceeded {
+                                budget,
+                                visited_nodes,
+                                lowest_round: *round,
+                            });
+                        }
+                        reachable.extend(
+                            node.parents()
+                                .iter()
+                                .map(|parent| *parent.metadata().digest()),
+                        );
+                        indices.push(index);
+                    }
+                }
+            }
+            if !indices.is_empty() {
+                positions.push((*round, indices));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Runs `f` on a view of `rounds`, which can't change until it returns. The DAG is only
+    /// mutated through `&mut self`, so the borrow held by the caller, usually through the read
+    /// lock, already keeps every round stable and no lock is taken per round. Composite reads go
+    /// through the view instead of reading the DAG step by step.
+    pub fn with_rounds<R>(&self, rounds: &[Round], f: impl FnOnce(RoundView<'_>) -> R) -> R {
+        let mut rounds = rounds.to_vec();
+        rounds.sort_unstable();
+        rounds.dedup();
+        f(RoundView { dag: self, rounds })
+    }
+
+    pub fn frontier(&self) -> Option<Frontier> {
+        let rounds: Vec<_> = self.nodes_by_round.keys().copied().collect();
+        self.with_rounds(&rounds, |view| {
+            let (round, strong_links) = view.rounds().iter().rev().find_map(|round| {
+                view.strong_links(*round)
+                    .map(|strong_links| (*round, strong_links))
+            })?;
+            let weak_links = self.weak_links(&view, round, &strong_links);
+            Some(Frontier {
+                round,
+                strong_links,
+                weak_links,
+                backpressure: self.backpressure(),
+            })
+        })
+    }
+
+    /// The weak links of a node strongly linking `strong_links` of `round`, like the weak links
+    /// of the `Frontier` for a subset of its strong links.
+    pub fn weak_links_for(
         &self,
         round: Round,
-        validator_verifier: &ValidatorVerifier,
-    ) -> Option<Vec<NodeCertificate>> {
-        let all_nodes_in_round = self.nodes_by_round.get(&round)?.iter().flatten();
-        if validator_verifier
-            .check_voting_power(
-                all_nodes_in_round
-                    .clone()
-                    .map(|node| node.metadata().author()),
-            )
-            .is_ok()
+        strong_links: &[NodeCertificate],
+    ) -> Vec<NodeCertificate> {
+        let rounds: Vec<_> = self
+            .nodes_by_round
+            .range(..=round)
+            .map(|(r, _)| *r)
+            .collect();
+        self.with_rounds(&rounds, |view| self.weak_links(&view, round, strong_links))
+    }
+
+    /// The latest certificates of the authors missing from `strong_links` that they don't already
+    /// reference.
+    fn weak_links(
+        &self,
+        view: &RoundView,
+        round: Round,
+        strong_links: &[NodeCertificate],
+    ) -> Vec<NodeCertificate> {
+        let linked: HashSet<_> = strong_links
+            .iter()
+            .filter_map(|certificate| view.get_node(round, certificate.metadata().author()))
+            .flat_map(|node| {
+                node.parents()
+                    .iter()
+                    .map(|parent| *parent.metadata().digest())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut lagging: HashSet<_> = (0..self.validator_index.len())
+            .filter(|index| {
+                self.validator_index
+                    .author_at(*index)
+                    .map_or(false, |author| !self.is_excluded(author))
+            })
+            .collect();
+        for certificate in strong_links {
+            lagging.remove(&self.author_index(certificate.metadata().author()));
+        }
+        let mut weak_links = vec![];
+        for lower_round in view.rounds().iter().rev().filter(|r| **r < round) {
+            if lagging.is_empty() {
+                break;
+            }
+            let slots = view.slots(*lower_round).unwrap_or_default();
+            for (index, status) in slots.iter().enumerate() {
+                if let Some(status) = status {
+                    if lagging.remove(&index) && !linked.contains(&status.as_node().digest()) {
+                        weak_links.push(status.as_node().certificate());
+                    }
+                }
+            }
+        }
+        weak_links
+    }
+
+    /// Records that `round` is abandoned, its anchor can't be ordered anymore and its slot isn't
+    /// requested from peers. The node of the anchor is still accepted if it arrives later.
+    pub fn mark_round_skipped(
+        &mut self,
+        round: Round,
+        certificate: SkipCertificate,
+    ) -> Result<(), DagStoreError> {
+        let skip = certificate.skip();
+        if skip.epoch() != self.epoch_state.epoch {
+            return Err(DagStoreError::EpochMismatch {
+                epoch: skip.epoch(),
+                expected: self.epoch_state.epoch,
+            });
+        }
+        if skip.round() != round
+            || self.validator_index.index_of(skip.anchor()).is_none()
+            || certificate.verify(&self.epoch_state.verifier).is_err()
         {
-            Some(all_nodes_in_round.map(|node| node.certificate()).collect())
-        } else {
-            None
+            return Err(DagStoreError::InvalidSkipCertificate(round));
         }
+        if self.skipped_rounds.insert(round, certificate).is_none() {
+            self.epoch_totals.num_skipped_anchors += 1;
+        }
+        Ok(())
     }
 
+    pub fn is_round_skipped(&self, round: Round) -> bool {
+        self.skipped_rounds.contains_key(&round)
+    }
+
+    /// The anchor given up on in `round`, if the round is skipped.
+    pub fn skipped_anchor(&self, round: Round) -> Option<&Author> {
+        self.skipped_rounds
+            .get(&round)
+            .map(|certificate| certificate.skip().anchor())
+    }
+
+    /// The slots below the highest round of the pending buffer that hold neither a node nor a
+    /// pending node, what a DAG that fell behind still has to fetch. The slots of the skipped
+    /// anchors are not counted.
+    pub fn fetch_gap(&self) -> usize {
+        let end_round = match self.pending_nodes.last_key_value() {
+            Some((round, _)) => *round,
+            None => return 0,
+        };
+        let start_round = if self.nodes_by_round.is_empty() {
+            self.epoch_start_round
+        } else {
+            self.lowest_round()
+        };
+        (start_round..end_round)
+            .map(|round| {
+                let slots = self.nodes_by_round.get(&round);
+                let num_present = slots.map_or(0, |slots| slots.iter().flatten().count());
+                let num_pending = self.pending_nodes.get(&round).map_or(0, BTreeMap::len);
+                let skipped_missing = self.skipped_anchor(round).map_or(false, |anchor| {
+                    slots
+                        .zip(self.validator_index.index_of(anchor))
+                        .and_then(|(slots, index)| slots.get(index))
+                        .map_or(true, Option::is_none)
+                });
+                self.validator_index
+                    .len()
+                    .saturating_sub(num_present + num_pending + usize::from(skipped_missing))
+            })
+            .sum()
+    }
+
+    /// Which slots hold a node from the lowest round up to the highest round, the slots of the
+    /// skipped anchors are set so they're not requested. The nodes of excluded equivocators are
+    /// reported missing, like they are to the voting power of their round.
     pub fn bitmask(&self) -> Vec<Vec<bool>> {
-        // TODO: extract local bitvec
-        todo!();
+        if self.nodes_by_round.is_empty() {
+            return vec![];
+        }
+        let reader = self.read();
+        (self.lowest_round()..=self.highest_round())
+            .map(|round| reader.round_bitmask(round))
+            .collect()
+    }
+
+    /// The slots of `bitmask` with the lowest round they start at, only the highest
+    /// `MAX_FETCH_ROUNDS` rounds so a peer can decode it.
+    pub fn exists_mask(&self) -> DagSnapshotBitmask {
+        let start_round = self.mask_start_round();
+        let mut bitmask = self.bitmask();
+        let skipped_rounds = start_round.saturating_sub(self.lowest_round()) as usize;
+        bitmask.drain(..skipped_rounds.min(bitmask.len()));
+        DagSnapshotBitmask::new(start_round, bitmask)
+    }
+
+    /// Which slots hold a node in the pending buffer, from the same round as `exists_mask` up to
+    /// the highest pending round, within `MAX_FETCH_ROUNDS` rounds.
+    pub fn pending_mask(&self) -> DagSnapshotBitmask {
+        let start_round = self.mask_start_round();
+        let end_round = match self.pending_nodes.last_key_value() {
+            Some((round, _)) if *round >= start_round => {
+                (*round).min(start_round + MAX_FETCH_ROUNDS as Round - 1)
+            },
+            _ => return DagSnapshotBitmask::new(start_round, vec![]),
+        };
+        let mut bitmask =
+            vec![vec![false; self.validator_index.len()]; (end_round - start_round + 1) as usize];
+        for (round, nodes) in self.pending_nodes.range(start_round..=end_round) {
+            for node in nodes.values() {
+                // a pending node of an author outside the validator set is left out
+                if let Some(slot) = self
+                    .validator_index
+                    .index_of(node.metadata().author())
+                    .and_then(|index| {
+                        bitmask
+                            .get_mut((round - start_round) as usize)?
+                            .get_mut(index)
+                    })
+                {
+                    *slot = true;
+                }
+            }
+        }
+        DagSnapshotBitmask::new(start_round, bitmask)
+    }
+
+    fn mask_start_round(&self) -> Round {
+        (self.highest_round() + 1)
+            .saturating_sub(MAX_FETCH_ROUNDS as Round)
+            .max(self.lowest_round())
     }
 }
@@ -0,0 +1,161 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::{Dag, DagEpochSummary, DagStoreError, DagStoreMode},
+        storage::DAGStorage,
+        store_config::DagStoreConfig,
+        types::EpochRemnant,
+    },
+    util::time_service::TimeService,
+};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::{info, warn};
+use aptos_types::{chain_id::ChainId, epoch_state::EpochState};
+use std::sync::Arc;
+
+/// Owns the DAG of the current epoch and hands it over at epoch boundaries. Components should get
+/// the DAG through `current` instead of keeping the handle across epochs, an insert into the
+/// handle of an ended epoch fails with `DagStoreError::EpochEnded`.
+pub struct EpochDagManager {
+    chain_id: ChainId,
+    storage: Arc<dyn DAGStorage>,
+    time_service: Arc<dyn TimeService>,
+    mode: DagStoreMode,
+    /// Config of the DAG of every epoch
+    config: DagStoreConfig,
+    current: RwLock<Arc<RwLock<Dag>>>,
+    /// The finalized DAG of the previous epoch, kept readable so the history of the epoch ending
+    /// anchor can still be served until the next transition
+    previous: RwLock<Option<Arc<RwLock<Dag>>>>,
+    /// Summary of the previous epoch, produced when it ended
+    previous_summary: RwLock<Option<DagEpochSummary>>,
+    /// Remnants of ended epochs found in storage at startup, left uncommitted by a crash at the
+    /// epoch boundary
+    recovered_remnants: Mutex<Vec<EpochRemnant>>,
+}
+
+impl EpochDagManager {
+    pub fn new(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        let recovered_remnants: Vec<_> = storage
+            .get_epoch_remnants()?
+            .into_iter()
+            .filter(|remnant| remnant.epoch() < epoch_state.epoch)
+            .collect();
+        for remnant in &recovered_remnants {
+            warn!(
+                "Recovered {} uncommitted anchors of epoch {}",
+                remnant.anchors().len(),
+                remnant.epoch()
+            );
+        }
+        let dag = Dag::try_new(
+            epoch_state,
+            chain_id,
+            storage.clone(),
+            time_service.clone(),
+            mode,
+            config.clone(),
+        )?;
+        Ok(Self {
+            chain_id,
+            storage,
+            time_service,
+            mode,
+            config,
+            current: RwLock::new(Arc::new(RwLock::new(dag))),
+            previous: RwLock::new(None),
+            previous_summary: RwLock::new(None),
+            recovered_remnants: Mutex::new(recovered_remnants),
+        })
+    }
+
+    pub fn current(&self) -> Arc<RwLock<Dag>> {
+        self.current.read().clone()
+    }
+
+    pub fn previous(&self) -> Option<Arc<RwLock<Dag>>> {
+        self.previous.read().clone()
+    }
+
+    pub fn previous_summary(&self) -> Option<DagEpochSummary> {
+        self.previous_summary.read().clone()
+    }
+
+    /// Ends the current epoch and hands out its remnant, see `Dag::finalize_epoch`. Called before
+    /// `start_new_epoch` once the epoch ending anchor is ordered.
+    pub fn finalize_epoch(&self) -> Result<EpochRemnant, DagStoreError> {
+        self.current().write().finalize_epoch()
+    }
+
+    /// The remnants found in storage at startup, oldest epoch first. They have to be taken, and
+    /// committed again, before `start_new_epoch`.
+    pub fn take_recovered_remnants(&self) -> Vec<EpochRemnant> {
+        std::mem::take(&mut *self.recovered_remnants.lock())
+    }
+
+    /// Deletes the persisted remnant of `epoch` once its history is committed.
+    pub fn remnant_committed(&self, epoch: u64) -> Result<(), DagStoreError> {
+        Ok(self.storage.delete_epoch_remnant(epoch)?)
+    }
+
+    /// Finalizes the DAG of the current epoch and swaps in the DAG of `epoch_state`. Taking the
+    /// write lock of the old DAG waits for the in-flight inserts, every insert after that is
+    /// rejected. The summary of the old epoch is logged and saved before recovering the new DAG,
+    /// which deletes the nodes of the old epoch from storage, or queues them for deletion. The new
+    /// DAG starts without equivocators, the ones excluded in the old epoch are deleted with it.
+    /// Refuses with `DagStoreError::RemnantNotTaken` while the old DAG has anchors ordered and not
+    /// committed that `finalize_epoch` didn't hand out, or a recovered remnant isn't taken.
+    pub fn start_new_epoch(
+        &self,
+        epoch_state: Arc<EpochState>,
+    ) -> Result<Arc<RwLock<Dag>>, DagStoreError> {
+        let mut current = self.current.write();
+        let old_epoch = current.read().epoch_state().epoch;
+        if epoch_state.epoch <= old_epoch {
+            return Err(DagStoreError::EpochMismatch {
+                epoch: epoch_state.epoch,
+                expected: old_epoch + 1,
+            });
+        }
+        if let Some(remnant) = self.recovered_remnants.lock().first() {
+            return Err(DagStoreError::RemnantNotTaken {
+                epoch: remnant.epoch(),
+                num_anchors: remnant.anchors().len(),
+            });
+        }
+        current.read().check_remnant_taken()?;
+        let summary = current.write().finalize();
+        info!("DAG of epoch {} finalized: {:?}", old_epoch, summary);
+        let epoch_summary = current.read().epoch_summary();
+        info!("Summary of DAG epoch {}: {:?}", old_epoch, epoch_summary);
+        if let Err(e) = self.storage.save_epoch_summary(&epoch_summary) {
+            warn!("Failed to save the summary of epoch {}: {:?}", old_epoch, e);
+        }
+        // every index of the new epoch starts from what its DAG recovers for the epoch, the
+        // nodes, reservations, broadcast progress, evidence, equivocators and denied authors
+        // of the old epoch are deleted from storage by the recovery
+        let dag = Arc::new(RwLock::new(Dag::try_new(
+            epoch_state,
+            self.chain_id,
+            self.storage.clone(),
+            self.time_service.clone(),
+            self.mode,
+            self.config.clone(),
+        )?));
+        // only the old DAG and its summary are carried, replacing the ones of the epoch before,
+        // and not before the new DAG exists so a failed recovery leaves them matching `current`
+        let old_dag = std::mem::replace(&mut *current, dag.clone());
+        *self.previous.write() = Some(old_dag);
+        *self.previous_summary.write() = Some(epoch_summary);
+        Ok(dag)
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    counters,
+    dag_store::{Dag, FetchPlan, InsertOutcome},
+    types::NodeMetadata,
+};
+use aptos_consensus_types::common::{Author, Round};
+use std::collections::HashMap;
+
+/// Bounds of the budget `FetchBudget::from_dag` derives from the gap of the DAG.
+#[derive(Clone, Copy, Debug)]
+pub struct FetchBudgetConfig {
+    /// Missing slots that justify one more concurrent request
+    pub slots_per_request: usize,
+    pub min_requests: usize,
+    pub max_requests: usize,
+    pub min_in_flight_nodes: usize,
+    pub max_in_flight_nodes: usize,
+}
+
+impl Default for FetchBudgetConfig {
+    fn default() -> Self {
+        Self {
+            slots_per_request: 10,
+            min_requests: 1,
+            max_requests: 8,
+            min_in_flight_nodes: 10,
+            max_in_flight_nodes: 100,
+        }
+    }
+}
+
+/// Identifies the slots of an admitted fetch plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FetchTicket(u64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    Admitted(FetchTicket),
+    /// Over the budget, or all the slots of the plan are already being fetched
+    Deferred,
+}
+
+/// Caps the fetch requests and the nodes they bring in flight at once, so a DAG far behind the
+/// others fetches in waves instead of requesting every missing slot at once. A request holds its
+/// share of the budget until the nodes of all its slots are inserted, or until it's released
+/// because it failed.
+pub struct FetchBudget {
+    max_requests: usize,
+    max_in_flight_nodes: usize,
+    /// The ticket fetching each slot in flight
+    in_flight_slots: HashMap<(Round, Author), FetchTicket>,
+    /// Number of slots still in flight by ticket
+    in_flight_requests: HashMap<FetchTicket, usize>,
+    next_ticket: u64,
+}
+
+impl FetchBudget {
+    pub fn new(max_requests: usize, max_in_flight_nodes: usize) -> Self {
+        let budget = Self {
+            max_requests,
+            max_in_flight_nodes,
+            in_flight_slots: HashMap::new(),
+            in_flight_requests: HashMap::new(),
+            next_ticket: 0,
+        };
+        for (resource, limit) in [("requests", max_requests), ("nodes", max_in_flight_nodes)] {
+            counters::FETCH_BUDGET_LIMIT
+                .with_label_values(&[resource])
+                .set(limit as i64);
+        }
+        budget.update_gauges();
+        budget
+    }
+
+    /// Scales the budget with the fetch gap of the DAG: one request per `slots_per_request`
+    /// missing slots and as many nodes in flight as missing slots, within the bounds of `config`.
+    pub fn from_dag(dag: &Dag, config: FetchBudgetConfig) -> Self {
+        let gap = dag.fetch_gap();
+        let slots_per_request = config.slots_per_request.max(1);
+        let max_requests = ((gap + slots_per_request - 1) / slots_per_request)
+            .clamp(config.min_requests, config.max_requests);
+        let max_in_flight_nodes = gap.clamp(config.min_in_flight_nodes, config.max_in_flight_nodes);
+        Self::new(max_requests, max_in_flight_nodes)
+    }
+
+    pub fn max_requests(&self) -> usize {
+        self.max_requests
+    }
+
+    pub fn max_in_flight_nodes(&self) -> usize {
+        self.max_in_flight_nodes
+    }
+
+    pub fn in_flight_requests(&self) -> usize {
+        self.in_flight_requests.len()
+    }
+
+    pub fn in_flight_nodes(&self) -> usize {
+        self.in_flight_slots.len()
+    }
+
+    /// Admits the plan if a request and the nodes of its slots not already in flight fit in the
+    /// budget. A plan bigger than the whole node budget is only admitted when nothing else is in
+    /// flight, so it can't be deferred forever.
+    pub fn admit(&mut self, plan: &FetchPlan) -> Admission {
+        let slots: Vec<_> = plan
+            .missing_slots
+            .iter()
+            .flat_map(|(round, authors)| authors.iter().map(|author| (*round, *author)))
+            .filter(|slot| !self.in_flight_slots.contains_key(slot))
+            .collect();
+        if slots.is_empty() || self.in_flight_requests.len() >= self.max_requests {
+            return Admission::Deferred;
+        }
+        if !self.in_flight_slots.is_empty()
+            && self.in_flight_slots.len() + slots.len() > self.max_in_flight_nodes
+        {
+            return Admission::Deferred;
+        }
+        let ticket = FetchTicket(self.next_ticket);
+        self.next_ticket += 1;
+        self.in_flight_requests.insert(ticket, slots.len());
+        for slot in slots {
+            self.in_flight_slots.insert(slot, ticket);
+        }
+        self.update_gauges();
+        Admission::Admitted(ticket)
+    }
+
+    /// Releases the slot of the node once it's inserted, and the request once all its slots are.
+    pub fn on_insert(&mut self, metadata: &NodeMetadata, outcome: &InsertOutcome) {
+        if !matches!(outcome, InsertOutcome::Inserted) {
+            return;
+        }
+        let ticket = match self
+            .in_flight_slots
+            .remove(&(metadata.round(), *metadata.author()))
+        {
+            Some(ticket) => ticket,
+            None => return,
+        };
+        if let Some(remaining) = self.in_flight_requests.get_mut(&ticket) {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.in_flight_requests.remove(&ticket);
+            }
+        }
+        self.update_gauges();
+    }
+
+    /// Releases what's left of a request that failed or timed out.
+    pub fn release(&mut self, ticket: FetchTicket) {
+        if self.in_flight_requests.remove(&ticket).is_some() {
+            self.in_flight_slots
+                .retain(|_, slot_ticket| *slot_ticket != ticket);
+            self.update_gauges();
+        }
+    }
+
+    fn update_gauges(&self) {
+        counters::FETCH_BUDGET_IN_FLIGHT
+            .with_label_values(&["requests"])
+            .set(self.in_flight_requests() as i64);
+        counters::FETCH_BUDGET_IN_FLIGHT
+            .with_label_values(&["nodes"])
+            .set(self.in_flight_nodes() as i64);
+    }
+}
@@ -2,16 +2,49 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(dead_code)]
 
+mod anchor_election;
+mod batch_verify;
+mod broadcast_progress;
+mod catch_up;
+mod counters;
+mod dag_admin;
 mod dag_driver;
 mod dag_fetcher;
 mod dag_handler;
+mod dag_health;
+mod dag_inspector;
 mod dag_network;
 mod dag_store;
+mod epoch_dag_manager;
+mod fetch_budget;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod node_builder;
+mod peer_tracker;
+mod pruned_filter;
+mod pruning_policy;
 mod reliable_broadcast;
+pub mod replay;
+mod round_schedule;
+#[cfg(test)]
+mod simulation;
+mod skip_round_tracker;
+mod slot_id;
 mod storage;
+mod store_config;
 #[cfg(test)]
 mod tests;
 mod types;
+mod validator_index;
+mod wire_limits;
+mod write_retry;
 
+pub use dag_inspector::DagInspector;
 pub use dag_network::RpcHandler;
-pub use types::{CertifiedNode, DAGNetworkMessage, Node};
+pub use dag_store::{DagEpochSummary, EvidenceRetention};
+#[cfg(any(test, feature = "test-utils"))]
+pub use node_builder::CertifiedNodeBuilder;
+pub use store_config::{DagStoreConfig, DagStoreConfigError};
+pub use types::{
+    BroadcastProgress, CertifiedNode, DAGNetworkMessage, EpochRemnant, EvidenceNodes,
+    EvidenceRecord, Node, NodeMetadata, OrderedAnchor, SkipRound, SkipVote,
+};
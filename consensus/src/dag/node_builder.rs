@@ -0,0 +1,109 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builds certified nodes whose fields break the rules of the DAG but whose digest and signatures
+//! are valid, to drive the validation paths a byzantine peer can reach. Only compiled for tests
+//! and with the `test-utils` feature.
+
+use crate::dag::types::{CertifiedNode, Node, NodeCertificate};
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_types::{
+    aggregate_signature::PartialSignatures, chain_id::ChainId, validator_signer::ValidatorSigner,
+    validator_verifier::ValidatorVerifier,
+};
+
+/// Starts from the fields of a node, any of which can be overridden. `build` computes the digest
+/// of the result and certifies it with the signatures of the given signers.
+#[derive(Clone)]
+pub struct CertifiedNodeBuilder {
+    chain_id: ChainId,
+    epoch: u64,
+    round: Round,
+    author: Author,
+    timestamp: u64,
+    payload: Payload,
+    parents: Vec<NodeCertificate>,
+}
+
+impl CertifiedNodeBuilder {
+    pub fn new(node: &Node) -> Self {
+        let metadata = node.metadata();
+        Self {
+            chain_id: metadata.chain_id(),
+            epoch: metadata.epoch(),
+            round: metadata.round(),
+            author: *metadata.author(),
+            timestamp: metadata.timestamp(),
+            payload: node.payload().clone(),
+            parents: node.parents().to_vec(),
+        }
+    }
+
+    pub fn chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn round(mut self, round: Round) -> Self {
+        self.round = round;
+        self
+    }
+
+    pub fn author(mut self, author: Author) -> Self {
+        self.author = author;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn payload(mut self, payload: Payload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn parents(mut self, parents: Vec<NodeCertificate>) -> Self {
+        self.parents = parents;
+        self
+    }
+
+    pub fn add_parent(mut self, parent: NodeCertificate) -> Self {
+        self.parents.push(parent);
+        self
+    }
+
+    /// The node without signatures.
+    pub fn build_node(self) -> Node {
+        Node::new(
+            self.chain_id,
+            self.epoch,
+            self.round,
+            self.author,
+            self.timestamp,
+            self.payload,
+            self.parents,
+        )
+    }
+
+    /// Signs the digest of the node with every signer and aggregates the signatures, the node
+    /// passes `verify` against `verifier` if the signers have a quorum.
+    pub fn build(self, signers: &[ValidatorSigner], verifier: &ValidatorVerifier) -> CertifiedNode {
+        let node = self.build_node();
+        let mut partial_signatures = PartialSignatures::empty();
+        for signer in signers {
+            let signature = node.sign(signer).expect("Signing the node should succeed");
+            partial_signatures.add_signature(signer.author(), signature);
+        }
+        let signatures = verifier
+            .aggregate_signatures(&partial_signatures)
+            .expect("Signature aggregation should succeed");
+        CertifiedNode::new(node, signatures)
+    }
+}
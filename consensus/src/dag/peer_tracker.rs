@@ -0,0 +1,208 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::FetchPlan, slot_id::SlotId, types::CertifiedNode,
+        validator_index::ValidatorIndex,
+    },
+    util::time_service::TimeService,
+};
+use aptos_consensus_types::common::{Author, Round};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Number of the highest rounds of a bitmask kept per peer.
+const MAX_TRACKED_ROUNDS: usize = 100;
+
+/// Age past which a bitmask is ignored by default.
+pub const DEFAULT_BITMASK_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// What a peer claims to have from its latest bitmask, and how often it failed to deliver it.
+struct PeerCapability {
+    start_round: Round,
+    bitmask: Vec<Vec<bool>>,
+    failures: u32,
+    updated_at: Duration,
+}
+
+impl PeerCapability {
+    fn claims(&self, slot: SlotId) -> bool {
+        slot.round()
+            .checked_sub(self.start_round)
+            .and_then(|offset| self.bitmask.get(offset as usize))
+            .and_then(|slots| slots.get(slot.position()))
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+/// Tracks the nodes the peers claim to have from the bitmasks they send, to pick who to fetch
+/// from. A peer that doesn't deliver what it claims is ranked lower.
+pub struct DagPeerTracker {
+    validator_index: Arc<ValidatorIndex>,
+    time_service: Arc<dyn TimeService>,
+    /// Bitmasks older than this are ignored and dropped
+    max_age: Duration,
+    peers: HashMap<Author, PeerCapability>,
+}
+
+impl DagPeerTracker {
+    pub fn new(
+        validator_index: Arc<ValidatorIndex>,
+        time_service: Arc<dyn TimeService>,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            validator_index,
+            time_service,
+            max_age,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Records the bitmask of `peer` starting at `start_round`, only its highest rounds are kept.
+    /// The slots beyond the validator set are dropped, they can't hold a node.
+    pub fn update(&mut self, peer: Author, start_round: Round, mut bitmask: Vec<Vec<bool>>) {
+        let now = self.time_service.get_current_timestamp();
+        self.expire(now);
+        let dropped = bitmask.len().saturating_sub(MAX_TRACKED_ROUNDS);
+        bitmask.drain(..dropped);
+        for slots in &mut bitmask {
+            slots.truncate(self.validator_index.len());
+        }
+        let failures = self.peers.get(&peer).map_or(0, |peer| peer.failures);
+        self.peers.insert(peer, PeerCapability {
+            start_round: start_round + dropped as Round,
+            bitmask,
+            failures,
+            updated_at: now,
+        });
+    }
+
+    /// The highest round `peer` claims a node in.
+    pub fn highest_round(&self, peer: &Author) -> Option<Round> {
+        let capability = self.peers.get(peer)?;
+        let offset = capability
+            .bitmask
+            .iter()
+            .rposition(|slots| slots.iter().any(|exists| *exists))?;
+        Some(capability.start_round + offset as Round)
+    }
+
+    /// The highest round any peer claims a node in, the expired bitmasks ignored.
+    pub fn highest_claimed_round(&self) -> Option<Round> {
+        let now = self.time_service.get_current_timestamp();
+        self.peers
+            .iter()
+            .filter(|(_, capability)| !self.is_expired(capability, now))
+            .filter_map(|(peer, _)| self.highest_round(peer))
+            .max()
+    }
+
+    /// Whether any peer claims the node of `author` in `round`, the expired bitmasks ignored.
+    pub fn is_claimed(&self, round: Round, author: &Author) -> bool {
+        let slot = match SlotId::of_author(round, author, &self.validator_index) {
+            Ok(slot) => slot,
+            Err(_) => return false,
+        };
+        let now = self.time_service.get_current_timestamp();
+        self.peers
+            .values()
+            .any(|capability| !self.is_expired(capability, now) && capability.claims(slot))
+    }
+
+    /// The fraction of the slots `peer` claims to have.
+    pub fn density(&self, peer: &Author) -> Option<f64> {
+        let capability = self.peers.get(peer)?;
+        let num_slots: usize = capability.bitmask.iter().map(Vec::len).sum();
+        let num_claimed = capability.bitmask.iter().flatten().filter(|e| **e).count();
+        Some(match num_slots {
+            0 => 0.0,
+            _ => num_claimed as f64 / num_slots as f64,
+        })
+    }
+
+    /// The peers claiming some of the plan, the ones covering the most first. The coverage of a
+    /// peer is divided by one plus its number of failed deliveries.
+    pub fn rank_for(&self, plan: &FetchPlan) -> Vec<Author> {
+        let now = self.time_service.get_current_timestamp();
+        let mut ranked: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, capability)| !self.is_expired(capability, now))
+            .filter_map(|(peer, capability)| {
+                let coverage = self.claimed_slots(capability, plan).len();
+                let score = coverage as f64 / (1 + capability.failures) as f64;
+                (coverage > 0).then_some((score, *peer))
+            })
+            .collect();
+        ranked.sort_by(|(a_score, a_peer), (b_score, b_peer)| {
+            b_score.total_cmp(a_score).then(a_peer.cmp(b_peer))
+        });
+        ranked.into_iter().map(|(_, peer)| peer).collect()
+    }
+
+    /// Demotes `peer` if the response to `plan` misses any node it claimed, a complete response
+    /// forgives one failure.
+    pub fn record_response(&mut self, peer: &Author, plan: &FetchPlan, nodes: &[CertifiedNode]) {
+        let capability = match self.peers.get(peer) {
+            Some(capability) => capability,
+            None => return,
+        };
+        let delivered: HashSet<_> = nodes
+            .iter()
+            .filter_map(|node| {
+                SlotId::of_author(
+                    node.metadata().round(),
+                    node.author(),
+                    &self.validator_index,
+                )
+                .ok()
+            })
+            .collect();
+        let complete = self
+            .claimed_slots(capability, plan)
+            .iter()
+            .all(|slot| delivered.contains(slot));
+        let capability = self.peers.get_mut(peer).expect("peer must exist");
+        if complete {
+            capability.failures = capability.failures.saturating_sub(1);
+        } else {
+            capability.failures += 1;
+        }
+    }
+
+    fn claimed_slots(&self, capability: &PeerCapability, plan: &FetchPlan) -> Vec<SlotId> {
+        plan.missing_slots
+            .iter()
+            .flat_map(|(round, authors)| {
+                authors.iter().filter_map(move |author| {
+                    SlotId::of_author(*round, author, &self.validator_index).ok()
+                })
+            })
+            .filter(|slot| capability.claims(*slot))
+            .collect()
+    }
+
+    fn is_expired(&self, capability: &PeerCapability, now: Duration) -> bool {
+        now.saturating_sub(capability.updated_at) > self.max_age
+    }
+
+    fn expire(&mut self, now: Duration) {
+        let max_age = self.max_age;
+        self.peers
+            .retain(|_, capability| now.saturating_sub(capability.updated_at) <= max_age);
+    }
+
+    pub fn validator_index(&self) -> &Arc<ValidatorIndex> {
+        &self.validator_index
+    }
+
+    pub fn num_peers(&self) -> usize {
+        self.peers.len()
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::types::NodeMetadata;
+use aptos_consensus_types::common::Round;
+use aptos_crypto::HashValue;
+use std::collections::{BTreeMap, HashSet};
+
+/// Pruned rounds remembered by default.
+pub const DEFAULT_PRUNED_FILTER_ROUNDS: usize = 10;
+
+/// The 8 first bytes of a digest, what the filter keeps of it.
+pub fn fingerprint(digest: &HashValue) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest.as_ref()[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Fingerprints of the nodes of the last pruned rounds, so the network handler drops the
+/// redeliveries of slow peers before verifying them. Two digests with the same fingerprint
+/// collide, so a hit is only trusted for a node claiming a round below the pruned round, which
+/// `Dag::pre_validate` rejects anyway. A false positive for any other node falls through to the
+/// full validation.
+pub struct PrunedDigestFilter {
+    max_rounds: usize,
+    fingerprints: BTreeMap<Round, HashSet<u64>>,
+    /// Every round below is pruned
+    pruned_below: Round,
+}
+
+impl PrunedDigestFilter {
+    pub fn new(max_rounds: usize) -> Self {
+        Self {
+            max_rounds,
+            fingerprints: BTreeMap::new(),
+            pruned_below: 0,
+        }
+    }
+
+    /// Forgets every pruned node, keeping the number of rounds.
+    pub fn clear(&mut self) {
+        self.fingerprints.clear();
+        self.pruned_below = 0;
+    }
+
+    pub fn pruned_below(&self) -> Round {
+        self.pruned_below
+    }
+
+    /// Number of fingerprints kept.
+    pub fn len(&self) -> usize {
+        self.fingerprints.values().map(HashSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+
+    /// Adds the nodes pruned below `round` and forgets the rounds older than the last
+    /// `max_rounds` pruned ones.
+    pub fn record<'a>(&mut self, round: Round, pruned: impl IntoIterator<Item = &'a NodeMetadata>) {
+        self.pruned_below = self.pruned_below.max(round);
+        if self.max_rounds == 0 {
+            return;
+        }
+        for metadata in pruned {
+            self.fingerprints
+                .entry(metadata.round())
+                .or_default()
+                .insert(fingerprint(metadata.digest()));
+        }
+        while self.fingerprints.len() > self.max_rounds {
+            self.fingerprints.pop_first();
+        }
+    }
+
+    /// Whether a pruned node may have the digest, with a false positive for a digest sharing the
+    /// fingerprint of a pruned one.
+    pub fn may_contain(&self, digest: &HashValue) -> bool {
+        let fingerprint = fingerprint(digest);
+        self.fingerprints
+            .values()
+            .any(|fingerprints| fingerprints.contains(&fingerprint))
+    }
+
+    /// Whether the node is a redelivery of a pruned node that can be dropped right away.
+    pub fn is_pruned_redelivery(&self, metadata: &NodeMetadata) -> bool {
+        metadata.round() < self.pruned_below
+            && self
+                .fingerprints
+                .get(&metadata.round())
+                .map_or(false, |fingerprints| {
+                    fingerprints.contains(&fingerprint(metadata.digest()))
+                })
+    }
+}
+
+impl Default for PrunedDigestFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_PRUNED_FILTER_ROUNDS)
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::dag_store::DagStateSummary;
+use aptos_consensus_types::common::Round;
+
+/// Decides how much history the DAG keeps once a round is committed. Returns the new lowest
+/// round, or `None` to keep everything.
+pub trait DagPruningPolicy: Send + Sync {
+    fn rounds_to_prune(
+        &self,
+        dag_summary: &DagStateSummary,
+        committed_round: Round,
+    ) -> Option<Round>;
+}
+
+/// Keeps the `window` highest rounds, never pruning rounds that are not committed yet.
+pub struct WindowPolicy {
+    pub window: Round,
+}
+
+impl DagPruningPolicy for WindowPolicy {
+    fn rounds_to_prune(
+        &self,
+        dag_summary: &DagStateSummary,
+        committed_round: Round,
+    ) -> Option<Round> {
+        let floor = dag_summary
+            .highest_round
+            .saturating_sub(self.window)
+            .min(committed_round);
+        (floor > dag_summary.lowest_round).then_some(floor)
+    }
+}
+
+/// Keeps `extra_rounds` rounds below the committed round, for peers lagging behind.
+pub struct RetainCommittedPolicy {
+    pub extra_rounds: Round,
+}
+
+impl DagPruningPolicy for RetainCommittedPolicy {
+    fn rounds_to_prune(
+        &self,
+        dag_summary: &DagStateSummary,
+        committed_round: Round,
+    ) -> Option<Round> {
+        let floor = committed_round.saturating_sub(self.extra_rounds);
+        (floor > dag_summary.lowest_round).then_some(floor)
+    }
+}
+
+pub struct NeverPrune;
+
+impl DagPruningPolicy for NeverPrune {
+    fn rounds_to_prune(
+        &self,
+        _dag_summary: &DagStateSummary,
+        _committed_round: Round,
+    ) -> Option<Round> {
+        None
+    }
+}
@@ -4,19 +4,34 @@
 use super::types::{CertifiedAck, CertifiedNode};
 use crate::{
     dag::{
+        counters,
         dag_network::{DAGNetworkSender, RpcHandler},
-        dag_store::Dag,
-        types::{Node, NodeCertificate, NodeDigest, NodeDigestSignature, TDAGMessage},
+        dag_store::{
+            AckDecision, AckToken, Dag, DagStoreError, DeferredAckHandler, FetchPlan, InsertOutcome,
+        },
+        types::{
+            Node, NodeCertificate, NodeDigest, NodeDigestSignature, NodeMetadata, TDAGMessage,
+        },
     },
     network::TConsensusMsg,
 };
 use anyhow::{bail, ensure};
 use aptos_consensus_types::common::{Author, Round};
-use aptos_infallible::RwLock;
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::warn;
 use aptos_types::{validator_signer::ValidatorSigner, validator_verifier::ValidatorVerifier};
 use futures::{stream::FuturesUnordered, StreamExt};
-use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use thiserror::Error as ThisError;
+use tokio::sync::{mpsc::Sender, oneshot};
 
 pub trait BroadcastStatus {
     type Ack: TDAGMessage;
@@ -40,11 +55,21 @@ impl ReliableBroadcast {
     }
 
     pub fn broadcast<S: BroadcastStatus>(
+        &self,
+        message: S::Message,
+        aggregating: S,
+    ) -> impl Future<Output = S::Aggregated> {
+        self.broadcast_to(message, aggregating, self.validators.clone())
+    }
+
+    /// Like `broadcast`, but only sends to `receivers`, e.g. the validators that didn't ack before
+    /// a restart. The acks of the receivers must be enough to aggregate.
+    pub fn broadcast_to<S: BroadcastStatus>(
         &self,
         message: S::Message,
         mut aggregating: S,
+        receivers: Vec<Author>,
     ) -> impl Future<Output = S::Aggregated> {
-        let receivers: Vec<_> = self.validators.clone();
         let network_sender = self.network_sender.clone();
         async move {
             let mut fut = FuturesUnordered::new();
@@ -134,13 +159,14 @@ impl NodeBroadcastHandler {
             NodeBroadcastHandleError::MissingParents
         );
 
-        // check which parents are missing in the DAG
-        let missing_parents: Vec<NodeCertificate> = node
-            .parents()
-            .iter()
-            .filter(|parent| !dag_reader.exists(parent.metadata().digest()))
-            .cloned()
-            .collect();
+        // check which parents are missing in the DAG, the present ones must match their certificate
+        let mut missing_parents: Vec<NodeCertificate> = vec![];
+        let reader = dag_reader.read();
+        for parent in node.parents() {
+            if reader.resolve_parent(parent)?.is_none() {
+                missing_parents.push(parent.clone());
+            }
+        }
         if !missing_parents.is_empty() {
             // For each missing parent, verify their signatures and voting power
             ensure!(
@@ -166,6 +192,8 @@ impl RpcHandler for NodeBroadcastHandler {
 
     fn process(&mut self, node: Self::Request) -> anyhow::Result<Self::Response> {
         self.validate(&node)?;
+        // a vote must not get ahead of the nodes still waiting to be persisted
+        Dag::flush_writes(&self.dag)?;
 
         let signatures_by_peer = self
             .signatures_by_round_peer
@@ -191,39 +219,225 @@ pub enum CertifiedNodeHandleError {
     NodeExists,
     #[error("missing parents")]
     MissingParents,
+    #[error("node write is retried")]
+    WriteRetrying,
+}
+
+/// The acks of the certified nodes parked for their parents, sent once the DAG promotes them. The
+/// ack of an evicted node is dropped, it is never acked.
+pub struct DeferredAcks {
+    next_token: AtomicU64,
+    waiting: Mutex<HashMap<AckToken, oneshot::Sender<CertifiedAck>>>,
+}
+
+impl Default for DeferredAcks {
+    fn default() -> Self {
+        Self {
+            next_token: AtomicU64::new(0),
+            waiting: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl DeferredAcks {
+    fn defer(&self) -> (AckToken, oneshot::Receiver<CertifiedAck>) {
+        let token = AckToken(self.next_token.fetch_add(1, Ordering::Relaxed));
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.waiting.lock().insert(token, ack_tx);
+        (token, ack_rx)
+    }
+
+    fn cancel(&self, token: AckToken) {
+        self.waiting.lock().remove(&token);
+    }
+
+    pub fn num_waiting(&self) -> usize {
+        self.waiting.lock().len()
+    }
+}
+
+impl DeferredAckHandler for DeferredAcks {
+    fn on_promoted(&self, token: AckToken, metadata: &NodeMetadata) {
+        if let Some(ack_tx) = self.waiting.lock().remove(&token) {
+            // the sender may have given up on the rpc
+            let _ = ack_tx.send(CertifiedAck::new(metadata.epoch()));
+        }
+    }
+
+    fn on_evicted(&self, token: AckToken) {
+        self.cancel(token);
+    }
+}
+
+/// The ack of a certified node, right away or once the node leaves the pending buffer. The
+/// deferred ack fails if the node is evicted instead.
+pub enum CertifiedNodeResponse {
+    Ack(CertifiedAck),
+    Deferred(oneshot::Receiver<CertifiedAck>),
+}
+
+/// What to fetch to catch up with a certified node too far ahead of the DAG.
+#[derive(Clone, Debug)]
+pub struct CatchUpRequest {
+    pub target: CertifiedNode,
+    pub plan: FetchPlan,
 }
 
 pub struct CertifiedNodeHandler {
     dag: Arc<RwLock<Dag>>,
+    catch_up_tx: Option<Sender<CatchUpRequest>>,
+    deferred_acks: Option<Arc<DeferredAcks>>,
+    /// The highest round of the DAG and the largest gap seen at that round
+    largest_gap: (Round, Round),
 }
 
 impl CertifiedNodeHandler {
     pub fn new(dag: Arc<RwLock<Dag>>) -> Self {
-        Self { dag }
+        Self {
+            dag,
+            catch_up_tx: None,
+            deferred_acks: None,
+            largest_gap: (0, 0),
+        }
     }
-}
 
-impl RpcHandler for CertifiedNodeHandler {
-    type Request = CertifiedNode;
-    type Response = CertifiedAck;
+    /// Acks the nodes parked for their parents once the DAG promotes them, see `process_deferred`.
+    /// Sets the `DeferredAckHandler` of the DAG.
+    pub fn with_deferred_acks(mut self) -> Self {
+        let deferred_acks = Arc::new(DeferredAcks::default());
+        self.dag
+            .write()
+            .set_deferred_ack_handler(deferred_acks.clone());
+        self.deferred_acks = Some(deferred_acks);
+        self
+    }
 
-    fn process(&mut self, node: Self::Request) -> anyhow::Result<Self::Response> {
+    pub fn deferred_acks(&self) -> Option<&Arc<DeferredAcks>> {
+        self.deferred_acks.as_ref()
+    }
+
+    /// Hands a catch-up request to `catch_up_tx` for every node rejected for being more than the
+    /// park gap of the DAG above its highest round.
+    pub fn with_catch_up(mut self, catch_up_tx: Sender<CatchUpRequest>) -> Self {
+        self.catch_up_tx = Some(catch_up_tx);
+        self
+    }
+
+    /// Nodes too far ahead are let through so their signatures are verified before `process`
+    /// catches up on them.
+    pub fn pre_validate(&self, node: &CertifiedNode) -> Result<(), DagStoreError> {
+        match self.dag.read().pre_validate_parkable(node) {
+            Err(DagStoreError::RoundTooHigh { .. }) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// The largest gap between a node and the highest round of the DAG since it last advanced,
+    /// also reported by the `DAG_BEHIND_ROUNDS` gauge.
+    pub fn largest_gap(&self) -> Round {
+        self.largest_gap.1
+    }
+
+    fn observe_gap(&mut self, highest_round: Round, gap: Round) {
+        let (round, largest) = self.largest_gap;
+        let largest = if round == highest_round {
+            largest.max(gap)
+        } else {
+            gap
+        };
+        self.largest_gap = (highest_round, largest);
+        counters::DAG_BEHIND_ROUNDS.set(largest as i64);
+    }
+
+    fn catch_up(&self, node: &CertifiedNode, highest_round: Round, gap: Round) {
+        let plan = self.dag.read().fetch_plan_for(node);
+        warn!(
+            "Node of round {} from {} is {} rounds above the highest round {}, catching up with {} nodes over {} rounds",
+            node.metadata().round(),
+            node.metadata().author(),
+            gap,
+            highest_round,
+            plan.estimated_nodes,
+            plan.missing_slots.len()
+        );
+        if let Some(catch_up_tx) = &self.catch_up_tx {
+            let request = CatchUpRequest {
+                target: node.clone(),
+                plan,
+            };
+            if let Err(e) = catch_up_tx.try_send(request) {
+                warn!("Failed to request a catch up: {}", e);
+            }
+        }
+    }
+}
+
+impl CertifiedNodeHandler {
+    /// Like `process`, but with `with_deferred_acks` a node parked for its parents gets its ack
+    /// once it is promoted, instead of failing with `MissingParents`.
+    pub fn process_deferred(
+        &mut self,
+        node: CertifiedNode,
+    ) -> anyhow::Result<CertifiedNodeResponse> {
+        let deferred_acks = match &self.deferred_acks {
+            Some(deferred_acks) => deferred_acks.clone(),
+            None => return self.process(node).map(CertifiedNodeResponse::Ack),
+        };
         let epoch = node.metadata().epoch();
-        {
+        self.check_gap(&node)?;
+        // the token is registered before the insert, the node can be promoted before it returns
+        let (token, ack_rx) = deferred_acks.defer();
+        match Dag::insert_node_shared_with_ack(&self.dag, node, token) {
+            InsertOutcome::ParkedPendingParents => Ok(CertifiedNodeResponse::Deferred(ack_rx)),
+            outcome => {
+                deferred_acks.cancel(token);
+                Self::respond(epoch, outcome).map(CertifiedNodeResponse::Ack)
+            },
+        }
+    }
+
+    /// Catches up on a node too far above the highest round of the DAG instead of inserting it.
+    fn check_gap(&mut self, node: &CertifiedNode) -> Result<(), DagStoreError> {
+        let (checked, highest_round) = {
             let dag_reader = self.dag.read();
-            if dag_reader.exists(&node.digest()) {
-                return Ok(CertifiedAck::new(epoch));
+            (
+                dag_reader.pre_validate_parkable(node),
+                dag_reader.highest_round(),
+            )
+        };
+        if let Err(e) = checked {
+            if let DagStoreError::RoundTooHigh { gap, .. } = e {
+                self.observe_gap(highest_round, gap);
+                self.catch_up(node, highest_round, gap);
+                return Err(e);
             }
+        }
+        let gap = node.metadata().round().saturating_sub(highest_round);
+        self.observe_gap(highest_round, if gap > 1 { gap } else { 0 });
+        Ok(())
+    }
 
-            if !dag_reader.all_exists(node.parents()) {
+    fn respond(epoch: u64, outcome: InsertOutcome) -> anyhow::Result<CertifiedAck> {
+        match outcome.ack_decision() {
+            AckDecision::AckNow => Ok(CertifiedAck::new(epoch)),
+            AckDecision::AckLater | AckDecision::NeverAck => match outcome {
+                InsertOutcome::Rejected(e) => Err(e.into()),
+                InsertOutcome::WriteRetrying => bail!(CertifiedNodeHandleError::WriteRetrying),
                 // TODO(ibalajiarun): implement fetching logic.
-                bail!(CertifiedNodeHandleError::MissingParents);
-            }
+                _ => bail!(CertifiedNodeHandleError::MissingParents),
+            },
         }
+    }
+}
 
-        let mut dag_writer = self.dag.write();
-        dag_writer.add_node(node)?;
+impl RpcHandler for CertifiedNodeHandler {
+    type Request = CertifiedNode;
+    type Response = CertifiedAck;
 
-        Ok(CertifiedAck::new(epoch))
+    fn process(&mut self, node: Self::Request) -> anyhow::Result<Self::Response> {
+        let epoch = node.metadata().epoch();
+        self.check_gap(&node)?;
+        // redeliveries are acked again so the broadcast of the sender completes
+        Self::respond(epoch, Dag::insert_node_shared(&self.dag, node))
     }
 }
@@ -0,0 +1,727 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        counters,
+        dag_store::{Dag, DagEpochSummary, DagStoreMode, InsertOutcome},
+        storage::DAGStorage,
+        store_config::DagStoreConfig,
+        types::{
+            BroadcastProgress, CertifiedNode, EpochRemnant, EvidenceRecord, Node, NodeMetadata,
+            OrderedAnchor, SkipVote,
+        },
+    },
+    util::time_service::{ScheduledTask, TimeService},
+};
+use anyhow::{bail, ensure, Context};
+use aptos_bitvec::BitVec;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use aptos_logger::warn;
+use aptos_types::{chain_id::ChainId, epoch_state::EpochState};
+use async_trait::async_trait;
+use futures::future::AbortHandle;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Version of the framing and of the entries, bumped on any change.
+pub const REPLAY_LOG_VERSION: u32 = 1;
+
+pub const DEFAULT_REPLAY_LOG_MAX_BYTES: u64 = 256 << 20;
+
+pub const DEFAULT_REPLAY_LOG_QUEUE_CAPACITY: usize = 1024;
+
+/// Where and how much the DAG records of the nodes it receives, see `DagReplayLog`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayLogConfig {
+    /// Directory of the logs, one file per epoch and start of the DAG
+    pub dir: PathBuf,
+    /// Entries past this size of the file are dropped
+    pub max_bytes: u64,
+    /// Entries waiting for the writer, dropped once it's full
+    pub queue_capacity: usize,
+}
+
+impl Default for ReplayLogConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("dag_replay"),
+            max_bytes: DEFAULT_REPLAY_LOG_MAX_BYTES,
+            queue_capacity: DEFAULT_REPLAY_LOG_QUEUE_CAPACITY,
+        }
+    }
+}
+
+/// First entry of a log, what the DAG replaying it is created with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub epoch: u64,
+    pub chain_id: ChainId,
+    /// Without the replay log
+    pub config: DagStoreConfig,
+}
+
+/// An `InsertOutcome` as recorded, the error of a rejection by its message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedOutcome {
+    pub label: String,
+    pub error: Option<String>,
+}
+
+impl From<&InsertOutcome> for RecordedOutcome {
+    fn from(outcome: &InsertOutcome) -> Self {
+        Self {
+            label: outcome.label().to_string(),
+            error: match outcome {
+                InsertOutcome::Rejected(e) => Some(e.to_string()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for RecordedOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.error {
+            Some(error) => write!(f, "{} ({})", self.label, error),
+            None => write!(f, "{}", self.label),
+        }
+    }
+}
+
+/// An entry of a log, each framed by its BCS length as a little endian u32.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReplayEntry {
+    Header(ReplayHeader),
+    /// A node the DAG received and what it did with it
+    Node {
+        received_at_micros: u64,
+        node: CertifiedNode,
+        outcome: RecordedOutcome,
+    },
+    /// `Dag::content_digest` of the whole DAG when the log was opened or closed
+    Checkpoint(HashValue),
+    /// Entries dropped because the writer was behind, the rest of the log can't be replayed
+    Dropped(u64),
+}
+
+/// Appends the framed entry, returns the bytes written.
+pub fn write_entry(writer: &mut impl Write, entry: &ReplayEntry) -> io::Result<u64> {
+    let bytes = bcs::to_bytes(entry).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let len = u32::try_from(bytes.len()).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    Ok(4 + bytes.len() as u64)
+}
+
+/// The entries of a log in order.
+pub struct RecordedLog {
+    pub entries: Vec<ReplayEntry>,
+    /// The last entry was cut short, e.g. by a crash of the writer, and is left out
+    pub truncated: bool,
+}
+
+pub fn read_log(path: &Path) -> anyhow::Result<RecordedLog> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?,
+    );
+    let mut entries = vec![];
+    loop {
+        let mut len = [0u8; 4];
+        match read_exact_or_eof(&mut reader, &mut len)? {
+            ReadResult::Eof => {
+                return Ok(RecordedLog {
+                    entries,
+                    truncated: false,
+                })
+            },
+            ReadResult::Partial => {
+                return Ok(RecordedLog {
+                    entries,
+                    truncated: true,
+                })
+            },
+            ReadResult::Full => {},
+        }
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        if let ReadResult::Partial | ReadResult::Eof = read_exact_or_eof(&mut reader, &mut bytes)? {
+            return Ok(RecordedLog {
+                entries,
+                truncated: true,
+            });
+        }
+        let entry = bcs::from_bytes(&bytes).with_context(|| {
+            format!("entry {} of {} is corrupted", entries.len(), path.display())
+        })?;
+        entries.push(entry);
+    }
+}
+
+enum ReadResult {
+    Full,
+    Partial,
+    Eof,
+}
+
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<ReadResult> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(ReadResult::Eof),
+            Ok(0) => return Ok(ReadResult::Partial),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(ReadResult::Full)
+}
+
+/// What a `DagReplayLog` wrote once closed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplayLogStats {
+    pub num_entries: u64,
+    pub num_bytes: u64,
+    /// Because the queue was full or the file at its maximum size
+    pub num_dropped: u64,
+}
+
+/// Records the nodes the DAG receives with what it did with them, for `run` to replay them
+/// against another build. The caller only queues the entries, a thread of the log encodes and
+/// writes them through a buffer. Entries are dropped rather than waited for when the queue is
+/// full, then a `Dropped` entry marks the gap. The file stops growing at its maximum size.
+pub struct DagReplayLog {
+    path: PathBuf,
+    /// `SyncSender` isn't `Sync` before Rust 1.72
+    sender: Option<Mutex<SyncSender<ReplayEntry>>>,
+    dropped: Arc<AtomicU64>,
+    writer: Option<JoinHandle<io::Result<ReplayLogStats>>>,
+}
+
+impl DagReplayLog {
+    /// Creates the log of the epoch of `header` in the directory of `config`, the name of the
+    /// file includes `started_at` so a restart doesn't overwrite the log of the previous run.
+    pub fn create(
+        config: &ReplayLogConfig,
+        header: ReplayHeader,
+        started_at: Duration,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let path = config.dir.join(format!(
+            "dag-replay-{}-{}.bcs",
+            header.epoch,
+            started_at.as_micros()
+        ));
+        let file = File::create(&path)?;
+        let (sender, receiver) = mpsc::sync_channel(config.queue_capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let dropped = dropped.clone();
+            let max_bytes = config.max_bytes;
+            thread::Builder::new()
+                .name("dag-replay-log".to_string())
+                .spawn(move || {
+                    write_entries(BufWriter::new(file), header, receiver, &dropped, max_bytes)
+                })?
+        };
+        Ok(Self {
+            path,
+            sender: Some(Mutex::new(sender)),
+            dropped,
+            writer: Some(writer),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, node: CertifiedNode, outcome: &InsertOutcome, received_at: Duration) {
+        self.send(ReplayEntry::Node {
+            received_at_micros: received_at.as_micros() as u64,
+            node,
+            outcome: outcome.into(),
+        });
+    }
+
+    pub fn checkpoint(&self, content_digest: HashValue) {
+        self.send(ReplayEntry::Checkpoint(content_digest));
+    }
+
+    fn send(&self, entry: ReplayEntry) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.lock().try_send(entry) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                counters::REPLAY_LOG_ENTRY_COUNT
+                    .with_label_values(&["queue_full"])
+                    .inc();
+            },
+            // the writer failed, it logged why
+            Err(TrySendError::Disconnected(_)) => {},
+        }
+    }
+
+    /// Writes out the queued entries and closes the file.
+    pub fn close(mut self) -> io::Result<ReplayLogStats> {
+        self.sender = None;
+        let mut stats = self
+            .writer
+            .take()
+            .expect("writer is only taken on close")
+            .join()
+            .map_err(|_| io::Error::new(ErrorKind::Other, "replay log writer panicked"))??;
+        // dropped after the writer wrote its last entry
+        stats.num_dropped += self.dropped.load(Ordering::Relaxed);
+        Ok(stats)
+    }
+}
+
+fn write_entries(
+    mut file: BufWriter<File>,
+    header: ReplayHeader,
+    receiver: Receiver<ReplayEntry>,
+    dropped: &AtomicU64,
+    max_bytes: u64,
+) -> io::Result<ReplayLogStats> {
+    let mut stats = ReplayLogStats::default();
+    let mut full = false;
+    let mut write = |file: &mut BufWriter<File>, entry: &ReplayEntry| -> io::Result<()> {
+        let bytes = bcs::serialized_size(entry)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))? as u64
+            + 4;
+        full |= stats.num_bytes + bytes > max_bytes;
+        if full {
+            stats.num_dropped += 1;
+            counters::REPLAY_LOG_ENTRY_COUNT
+                .with_label_values(&["max_bytes"])
+                .inc();
+            return Ok(());
+        }
+        stats.num_bytes += write_entry(file, entry)?;
+        stats.num_entries += 1;
+        counters::REPLAY_LOG_ENTRY_COUNT
+            .with_label_values(&["written"])
+            .inc();
+        Ok(())
+    };
+    let result = (|| {
+        write(&mut file, &ReplayEntry::Header(header))?;
+        for entry in receiver {
+            let num_dropped = dropped.swap(0, Ordering::Relaxed);
+            if num_dropped > 0 {
+                write(&mut file, &ReplayEntry::Dropped(num_dropped))?;
+            }
+            let checkpoint = matches!(entry, ReplayEntry::Checkpoint(_));
+            write(&mut file, &entry)?;
+            // so a log copied while the DAG runs can be replayed up to its last checkpoint
+            if checkpoint {
+                file.flush()?;
+            }
+        }
+        file.flush()
+    })();
+    if let Err(e) = &result {
+        warn!("Replay log writer stopped: {:?}", e);
+    }
+    result.map(|()| stats)
+}
+
+/// Where a replay first differed from the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayDivergence {
+    Outcome {
+        /// Position of the entry in the log
+        entry: usize,
+        digest: HashValue,
+        round: Round,
+        author: Author,
+        recorded: RecordedOutcome,
+        replayed: RecordedOutcome,
+    },
+    Content {
+        entry: usize,
+        recorded: HashValue,
+        replayed: HashValue,
+    },
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayDivergence::Outcome {
+                entry,
+                digest,
+                round,
+                author,
+                recorded,
+                replayed,
+            } => write!(
+                f,
+                "entry {}: node {:x} of {} in round {} was {}, replayed {}",
+                entry, digest, author, round, recorded, replayed
+            ),
+            ReplayDivergence::Content {
+                entry,
+                recorded,
+                replayed,
+            } => write!(
+                f,
+                "entry {}: DAG content {:x}, replayed {:x}",
+                entry, recorded, replayed
+            ),
+        }
+    }
+}
+
+/// What `run` found.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    pub epoch: u64,
+    pub num_nodes: usize,
+    /// Nodes and checkpoints that differed
+    pub num_divergences: usize,
+    pub first_divergence: Option<ReplayDivergence>,
+    /// Digest of the last checkpoint of the log
+    pub recorded_digest: Option<HashValue>,
+    /// Digest of the DAG at the end of the replay
+    pub replayed_digest: HashValue,
+    /// The log was cut short or has a gap, the replay stopped there
+    pub incomplete: bool,
+}
+
+impl ReplayReport {
+    pub fn is_faithful(&self) -> bool {
+        self.first_divergence.is_none()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "epoch {}: {} nodes replayed, {} divergences",
+            self.epoch, self.num_nodes, self.num_divergences
+        )?;
+        if let Some(divergence) = &self.first_divergence {
+            writeln!(f, "first divergence: {}", divergence)?;
+        }
+        match self.recorded_digest {
+            Some(recorded) => writeln!(
+                f,
+                "content: recorded {:x}, replayed {:x}",
+                recorded, self.replayed_digest
+            )?,
+            None => writeln!(
+                f,
+                "content: replayed {:x}, no checkpoint recorded",
+                self.replayed_digest
+            )?,
+        }
+        if self.incomplete {
+            writeln!(f, "the log is incomplete, the replay stopped at its gap")?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds the nodes of the log at `log_path` through `Dag::insert_node` of a fresh DAG with the
+/// config of the log, at the recorded times, and compares what it does with the log. Storage is
+/// left out, the DAG only lives in memory.
+pub fn run(log_path: &Path, epoch_state: Arc<EpochState>) -> anyhow::Result<ReplayReport> {
+    let log = read_log(log_path)?;
+    let mut entries = log.entries.into_iter().enumerate();
+    let header = match entries.next() {
+        Some((_, ReplayEntry::Header(header))) => header,
+        _ => bail!("{} doesn't start with a header", log_path.display()),
+    };
+    ensure!(
+        header.version == REPLAY_LOG_VERSION,
+        "log version {} isn't supported, expected {}",
+        header.version,
+        REPLAY_LOG_VERSION
+    );
+    ensure!(
+        header.epoch == epoch_state.epoch,
+        "log of epoch {}, the epoch state is of epoch {}",
+        header.epoch,
+        epoch_state.epoch
+    );
+    let clock = Arc::new(ReplayClock::default());
+    let mut dag = Dag::try_new(
+        epoch_state,
+        header.chain_id,
+        Arc::new(ReplayStorage),
+        clock.clone(),
+        DagStoreMode::Strict,
+        header.config,
+    )?;
+    let mut report = ReplayReport {
+        epoch: header.epoch,
+        num_nodes: 0,
+        num_divergences: 0,
+        first_divergence: None,
+        recorded_digest: None,
+        replayed_digest: HashValue::zero(),
+        incomplete: log.truncated,
+    };
+    for (position, entry) in entries {
+        let divergence = match entry {
+            ReplayEntry::Header(_) => bail!("entry {} is a second header", position),
+            ReplayEntry::Node {
+                received_at_micros,
+                node,
+                outcome: recorded,
+            } => {
+                clock.set(Duration::from_micros(received_at_micros));
+                let (digest, round, author) =
+                    (node.digest(), node.metadata().round(), *node.author());
+                let replayed = RecordedOutcome::from(&dag.insert_node(node));
+                report.num_nodes += 1;
+                (replayed != recorded).then_some(ReplayDivergence::Outcome {
+                    entry: position,
+                    digest,
+                    round,
+                    author,
+                    recorded,
+                    replayed,
+                })
+            },
+            ReplayEntry::Checkpoint(recorded) => {
+                report.recorded_digest = Some(recorded);
+                let replayed = dag.content_digest(None);
+                (replayed != recorded).then_some(ReplayDivergence::Content {
+                    entry: position,
+                    recorded,
+                    replayed,
+                })
+            },
+            ReplayEntry::Dropped(_) => {
+                report.incomplete = true;
+                break;
+            },
+        };
+        if let Some(divergence) = divergence {
+            report.num_divergences += 1;
+            report.first_divergence.get_or_insert(divergence);
+        }
+    }
+    report.replayed_digest = dag.content_digest(None);
+    Ok(report)
+}
+
+/// The time of the node being replayed.
+#[derive(Default)]
+struct ReplayClock {
+    now_micros: AtomicU64,
+}
+
+impl ReplayClock {
+    fn set(&self, now: Duration) {
+        self.now_micros
+            .store(now.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl TimeService for ReplayClock {
+    /// Nothing is scheduled during a replay.
+    fn run_after(&self, _timeout: Duration, _task: Box<dyn ScheduledTask>) -> AbortHandle {
+        AbortHandle::new_pair().0
+    }
+
+    fn get_current_timestamp(&self) -> Duration {
+        Duration::from_micros(self.now_micros.load(Ordering::Relaxed))
+    }
+
+    async fn sleep(&self, _t: Duration) {}
+}
+
+/// Storage of a replayed DAG, empty and accepting every write.
+struct ReplayStorage;
+
+impl DAGStorage for ReplayStorage {
+    fn save_node(&self, _node: &Node) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_certified_node(&self, _node: &CertifiedNode) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_certified_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_certified_nodes(&self, _digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_rounds_below(&self, _epoch: u64, _round: Round) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_ordered_anchor(&self, _ordered_anchor: &OrderedAnchor) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_ordered_anchors(&self) -> anyhow::Result<HashMap<HashValue, OrderedAnchor>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_ordered_anchors(&self, _digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_pending_deletions(&self, _deletions: &HashMap<HashValue, u32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_pending_deletions(&self, _digests: &[HashValue]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_pending_deletions(&self) -> anyhow::Result<HashMap<HashValue, u32>> {
+        Ok(HashMap::new())
+    }
+
+    fn save_skip_vote(&self, _vote: &SkipVote) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_skip_votes(&self) -> anyhow::Result<HashMap<(Round, Author), SkipVote>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_skip_votes(&self, _keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_self_reservation(
+        &self,
+        _epoch: u64,
+        _round: Round,
+        _node: &Node,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_self_reservations(&self) -> anyhow::Result<HashMap<(u64, Round), Node>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_self_reservations(&self, _keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_broadcast_progress(
+        &self,
+        _epoch: u64,
+        _round: Round,
+        _digest: &HashValue,
+        _acked: &BitVec,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_broadcast_progress(&self) -> anyhow::Result<HashMap<(u64, Round), BroadcastProgress>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_broadcast_progress(&self, _keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_equivocator(&self, _epoch: u64, _author: &Author, _round: Round) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_equivocators(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_equivocators(&self, _keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_equivocation_evidence(&self, _record: &EvidenceRecord) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_equivocation_evidence(&self) -> anyhow::Result<HashMap<HashValue, EvidenceRecord>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_equivocation_evidence(&self, _digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_denied_authors(&self, _epoch: u64, _authors: &[(Author, Round)]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_denied_authors(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_denied_authors(&self, _keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_epoch_start_round(&self, _epoch: u64, _round: Round) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_epoch_start_round(&self) -> anyhow::Result<Option<(u64, Round)>> {
+        Ok(None)
+    }
+
+    fn save_last_committed_anchor(&self, _anchor: &NodeMetadata) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_last_committed_anchor(&self) -> anyhow::Result<Option<NodeMetadata>> {
+        Ok(None)
+    }
+
+    fn delete_last_committed_anchor(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_epoch_summary(&self, _summary: &DagEpochSummary) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn save_epoch_remnant(&self, _remnant: &EpochRemnant) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_epoch_remnants(&self) -> anyhow::Result<Vec<EpochRemnant>> {
+        Ok(vec![])
+    }
+
+    fn delete_epoch_remnant(&self, _epoch: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
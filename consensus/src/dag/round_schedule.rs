@@ -0,0 +1,103 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::dag_store::DEFAULT_EPOCH_START_ROUND;
+use aptos_consensus_types::common::Round;
+
+/// Which rounds hold the anchors, relative to the start round of the epoch: `Even` anchors the
+/// start round, the one two rounds later and so on, `Odd` the rounds in between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnchorParity {
+    Even,
+    Odd,
+}
+
+/// The anchor rounds of an epoch, every other round the nodes vote for the anchor of the round
+/// before. All the code telling anchor rounds from vote rounds goes through it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoundSchedule {
+    start_round: Round,
+    first_anchor_round: Round,
+    /// Rounds between two anchors, 2 in the protocol
+    cadence: Round,
+}
+
+impl RoundSchedule {
+    pub fn new(start_round: Round, anchor_parity: AnchorParity) -> Self {
+        let first_anchor_round = match anchor_parity {
+            AnchorParity::Even => start_round,
+            AnchorParity::Odd => start_round + 1,
+        };
+        Self {
+            start_round,
+            first_anchor_round,
+            cadence: 2,
+        }
+    }
+
+    /// Spaces the anchors `cadence` rounds apart instead of 2, for protocol experiments.
+    pub fn with_cadence(mut self, cadence: Round) -> Self {
+        assert!(cadence > 0, "the anchor cadence must be positive");
+        self.cadence = cadence;
+        self
+    }
+
+    pub fn start_round(&self) -> Round {
+        self.start_round
+    }
+
+    pub fn is_anchor_round(&self, round: Round) -> bool {
+        round >= self.first_anchor_round && (round - self.first_anchor_round) % self.cadence == 0
+    }
+
+    /// Whether the nodes of `round` vote for the anchor of the round before.
+    pub fn is_vote_round(&self, round: Round) -> bool {
+        round > self.first_anchor_round && self.is_anchor_round(round - 1)
+    }
+
+    /// The first anchor round after `after`.
+    pub fn next_anchor_round(&self, after: Round) -> Round {
+        if after < self.first_anchor_round {
+            return self.first_anchor_round;
+        }
+        self.first_anchor_round
+            + ((after - self.first_anchor_round) / self.cadence + 1) * self.cadence
+    }
+
+    /// The anchor rounds from `low` to `high` included, in ascending order.
+    pub fn anchor_rounds_between(
+        &self,
+        low: Round,
+        high: Round,
+    ) -> impl DoubleEndedIterator<Item = Round> {
+        let first = if low <= self.first_anchor_round {
+            self.first_anchor_round
+        } else {
+            self.next_anchor_round(low - 1)
+        };
+        let count = if first > high {
+            0
+        } else {
+            (high - first) / self.cadence + 1
+        };
+        let cadence = self.cadence;
+        (0..count).map(move |position| first + position * cadence)
+    }
+
+    /// Number of anchor rounds before `round`, which is the position of an anchor round among
+    /// the anchor rounds of the epoch.
+    pub fn anchor_position(&self, round: Round) -> u64 {
+        if round <= self.first_anchor_round {
+            0
+        } else {
+            (round - self.first_anchor_round - 1) / self.cadence + 1
+        }
+    }
+}
+
+impl Default for RoundSchedule {
+    /// Anchors in the rounds of the same parity as the default epoch start round.
+    fn default() -> Self {
+        Self::new(DEFAULT_EPOCH_START_ROUND, AnchorParity::Even)
+    }
+}
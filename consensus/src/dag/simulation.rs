@@ -0,0 +1,472 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deterministic single-process driver of the real `Dag` for ordering experiments. Every
+//! validator has its own DAG, the nodes are certified with `SignatureBuilder` and delivered over a
+//! virtual clock, and the anchors are ordered with `Dag::order_anchor` once enough of the next
+//! round links to them.
+
+use crate::{
+    dag::{
+        anchor_election::{AnchorElection, RoundRobinAnchorElection},
+        dag_store::{AnchorCommitLatency, Dag, DagStoreError},
+        round_schedule::RoundSchedule,
+        tests::dag_test::MockStorage,
+        types::{CertifiedNode, NodeCertificate, SignatureBuilder},
+        Node,
+    },
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
+};
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier},
+};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+/// Unit of the virtual clock.
+pub type Tick = u64;
+
+/// Virtual time of a tick, as seen by the DAG time service.
+const TICK_DURATION: Duration = Duration::from_millis(10);
+
+/// Voting power of the next round linking to an anchor needed to commit it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitThreshold {
+    /// More than a third of the voting power, so at least one honest validator links the anchor
+    Minority,
+    /// A quorum of the voting power
+    Quorum,
+}
+
+impl CommitThreshold {
+    fn required(&self, verifier: &ValidatorVerifier) -> u128 {
+        match self {
+            Self::Minority => verifier.total_voting_power() - verifier.quorum_voting_power() + 1,
+            Self::Quorum => verifier.quorum_voting_power(),
+        }
+    }
+}
+
+/// Who takes part in every round and how long their messages take.
+pub trait ParticipationSchedule {
+    /// Whether the validator at `index` produces and signs nodes in `round`.
+    fn participates(&self, round: Round, index: usize) -> bool;
+
+    /// Ticks for the node of `round` to travel from the validator at `from` to the one at `to`.
+    fn delay(&self, round: Round, from: usize, to: usize) -> Tick;
+}
+
+/// Every validator takes part until it crashes, and every message takes the same delay unless
+/// overridden for a pair of validators.
+pub struct FixedSchedule {
+    delay: Tick,
+    /// First round without the validator, by validator index
+    crashes: BTreeMap<usize, Round>,
+    delays: BTreeMap<(usize, usize), Tick>,
+}
+
+impl FixedSchedule {
+    pub fn new(delay: Tick) -> Self {
+        Self {
+            delay,
+            crashes: BTreeMap::new(),
+            delays: BTreeMap::new(),
+        }
+    }
+
+    /// The validator at `index` stops taking part from `round` on.
+    pub fn crash(mut self, index: usize, round: Round) -> Self {
+        self.crashes.insert(index, round);
+        self
+    }
+
+    pub fn with_delay(mut self, from: usize, to: usize, delay: Tick) -> Self {
+        self.delays.insert((from, to), delay);
+        self
+    }
+}
+
+impl ParticipationSchedule for FixedSchedule {
+    fn participates(&self, round: Round, index: usize) -> bool {
+        self.crashes
+            .get(&index)
+            .map_or(true, |crash_round| round < *crash_round)
+    }
+
+    fn delay(&self, _round: Round, from: usize, to: usize) -> Tick {
+        self.delays.get(&(from, to)).copied().unwrap_or(self.delay)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub num_validators: usize,
+    /// Validators stop producing nodes after this round
+    pub num_rounds: Round,
+    /// Anchor rounds, every other round from round 1 by default
+    pub round_schedule: RoundSchedule,
+    pub commit_threshold: CommitThreshold,
+    /// Ticks a validator with a quorum waits for the anchor of its round before moving on
+    pub anchor_timeout: Tick,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            num_validators: 4,
+            num_rounds: 20,
+            round_schedule: RoundSchedule::default(),
+            commit_threshold: CommitThreshold::Minority,
+            anchor_timeout: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommittedAnchor {
+    pub round: Round,
+    pub author: Author,
+    pub tick: Tick,
+    /// Round of the validator when it ordered the anchor, minus the anchor round
+    pub latency_rounds: Round,
+    /// Rounds from the anchor to the round whose links committed it, the same on every validator
+    pub commit_latency: AnchorCommitLatency,
+    /// Whether the anchor met the threshold itself, rather than being ordered in the history of a
+    /// later one
+    pub direct: bool,
+}
+
+/// What a single validator ordered.
+#[derive(Clone, Debug, Default)]
+pub struct ValidatorReport {
+    /// Digests of the ordered nodes, in order
+    pub ordered: Vec<HashValue>,
+    pub anchors: Vec<CommittedAnchor>,
+    /// Round of the latest node the validator produced or skipped
+    pub round: Round,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    pub validators: Vec<ValidatorReport>,
+    /// Tick of the last event
+    pub num_ticks: Tick,
+}
+
+enum Event {
+    Deliver { to: usize, node: Arc<CertifiedNode> },
+    AnchorTimeout { validator: usize, round: Round },
+}
+
+struct SimulatedValidator {
+    dag: Dag,
+    /// Delivered nodes whose parents are not in the DAG yet
+    buffer: Vec<Arc<CertifiedNode>>,
+    /// Round whose anchor timeout is scheduled, and whether it expired
+    anchor_timer: Option<(Round, bool)>,
+    last_ordered_round: Round,
+    report: ValidatorReport,
+}
+
+pub struct DagSimulation {
+    config: SimulationConfig,
+    schedule: Box<dyn ParticipationSchedule>,
+    signers: Vec<ValidatorSigner>,
+    epoch_state: Arc<EpochState>,
+    anchor_election: Arc<dyn AnchorElection>,
+    time_service: Arc<SimulatedTimeService>,
+    validators: Vec<SimulatedValidator>,
+    events: BTreeMap<(Tick, u64), Event>,
+    next_event_id: u64,
+    tick: Tick,
+}
+
+impl DagSimulation {
+    /// A simulation of validators with equal voting power and round robin anchors of the configured
+    /// round schedule.
+    pub fn new(config: SimulationConfig, schedule: Box<dyn ParticipationSchedule>) -> Self {
+        let (signers, verifier) = random_validator_verifier(config.num_validators, None, false);
+        let epoch_state = Arc::new(EpochState { epoch: 1, verifier });
+        let authors = epoch_state.verifier.get_ordered_account_addresses();
+        let time_service = Arc::new(SimulatedTimeService::new());
+        let validators = (0..config.num_validators)
+            .map(|_| SimulatedValidator {
+                dag: Dag::new_with_time_service(
+                    epoch_state.clone(),
+                    Arc::new(MockStorage::new()),
+                    time_service.clone(),
+                ),
+                buffer: vec![],
+                anchor_timer: None,
+                last_ordered_round: 0,
+                report: ValidatorReport::default(),
+            })
+            .collect();
+        let anchor_election = Arc::new(RoundRobinAnchorElection::with_schedule(
+            authors,
+            config.round_schedule,
+        ));
+        Self {
+            config,
+            schedule,
+            signers,
+            epoch_state,
+            anchor_election,
+            time_service,
+            validators,
+            events: BTreeMap::new(),
+            next_event_id: 0,
+            tick: 0,
+        }
+    }
+
+    /// The authors in validator index order.
+    pub fn authors(&self) -> Vec<Author> {
+        self.epoch_state.verifier.get_ordered_account_addresses()
+    }
+
+    pub fn set_anchor_election(&mut self, anchor_election: Arc<dyn AnchorElection>) {
+        self.anchor_election = anchor_election;
+    }
+
+    /// Runs until no event is left, every validator stops after `num_rounds`.
+    pub fn run(mut self) -> Result<SimulationReport, DagStoreError> {
+        for index in 0..self.validators.len() {
+            self.enter_round(index, 1, vec![]);
+        }
+        while let Some(((tick, _), event)) = self.events.pop_first() {
+            self.time_service
+                .advance(TICK_DURATION * (tick - self.tick) as u32);
+            self.tick = tick;
+            match event {
+                Event::Deliver { to, node } => self.deliver(to, node)?,
+                Event::AnchorTimeout { validator, round } => {
+                    let state = &mut self.validators[validator];
+                    if state.anchor_timer == Some((round, false)) {
+                        state.anchor_timer = Some((round, true));
+                        self.try_advance(validator);
+                    }
+                },
+            }
+        }
+        Ok(SimulationReport {
+            validators: self
+                .validators
+                .into_iter()
+                .map(|validator| validator.report)
+                .collect(),
+            num_ticks: self.tick,
+        })
+    }
+
+    fn schedule(&mut self, tick: Tick, event: Event) {
+        self.events.insert((tick, self.next_event_id), event);
+        self.next_event_id += 1;
+    }
+
+    /// Moves the validator to `round` and, if it takes part, certifies its node with the
+    /// signatures of the validators taking part in the round and sends it to everyone.
+    fn enter_round(&mut self, index: usize, round: Round, parents: Vec<NodeCertificate>) {
+        self.validators[index].report.round = round;
+        if !self.schedule.participates(round, index) {
+            return;
+        }
+        let node = Node::new(
+            self.validators[index].dag.chain_id(),
+            self.epoch_state.epoch,
+            round,
+            self.signers[index].author(),
+            self.time_service.get_current_timestamp().as_micros() as u64,
+            Payload::empty(false),
+            parents,
+        );
+        let mut signature_builder =
+            SignatureBuilder::new(node.metadata().clone(), self.epoch_state.clone());
+        let mut certificate = None;
+        for (signer_index, signer) in self.signers.iter().enumerate() {
+            if !self.schedule.participates(round, signer_index) {
+                continue;
+            }
+            let signature = node.sign(signer).expect("Signing should succeed");
+            if let Some(built) = signature_builder
+                .add_signature(signer.author(), signature)
+                .expect("Signature should be valid")
+            {
+                certificate = Some(built);
+            }
+        }
+        // without a quorum taking part the node is never certified
+        let certificate = match certificate {
+            Some(certificate) => certificate,
+            None => return,
+        };
+        let certified_node = Arc::new(
+            CertifiedNode::from_certificate(node, certificate)
+                .expect("certificate is built for the node"),
+        );
+        for to in 0..self.validators.len() {
+            let delay = self.schedule.delay(round, index, to);
+            self.schedule(self.tick + delay, Event::Deliver {
+                to,
+                node: certified_node.clone(),
+            });
+        }
+    }
+
+    /// Adds the node and the buffered nodes it unblocks, committing the anchors that become
+    /// committable after every insertion, then tries to move to the next round.
+    fn deliver(&mut self, index: usize, node: Arc<CertifiedNode>) -> Result<(), DagStoreError> {
+        self.validators[index].buffer.push(node);
+        loop {
+            let validator = &mut self.validators[index];
+            let position = match validator
+                .buffer
+                .iter()
+                .position(|node| validator.dag.all_exists(node.parents()))
+            {
+                Some(position) => position,
+                None => break,
+            };
+            let node = validator.buffer.swap_remove(position);
+            validator.dag.add_node(node.as_ref().clone())?;
+            self.try_commit(index)?;
+        }
+        self.try_advance(index);
+        Ok(())
+    }
+
+    /// Moves to the next round once the current one has a quorum and, for an anchor round, its
+    /// anchor or the anchor timeout expired.
+    fn try_advance(&mut self, index: usize) {
+        loop {
+            let validator = &self.validators[index];
+            let round = validator.report.round;
+            if round >= self.config.num_rounds {
+                return;
+            }
+            let strong_links = match validator.dag.strong_links_for_round(round) {
+                Some(strong_links) => strong_links,
+                None => return,
+            };
+            let anchor = self.anchor_election.get_anchor(round);
+            if self.config.round_schedule.is_anchor_round(round)
+                && validator
+                    .dag
+                    .get_node_by_round_author(round, &anchor)
+                    .is_none()
+            {
+                match validator.anchor_timer {
+                    Some((timer_round, true)) if timer_round == round => {},
+                    Some((timer_round, false)) if timer_round == round => return,
+                    _ => {
+                        self.validators[index].anchor_timer = Some((round, false));
+                        self.schedule(
+                            self.tick + self.config.anchor_timeout,
+                            Event::AnchorTimeout {
+                                validator: index,
+                                round,
+                            },
+                        );
+                        return;
+                    },
+                }
+            }
+            self.enter_round(index, round + 1, strong_links);
+        }
+    }
+
+    /// Commits the lowest anchor above the last ordered one whose next round links to it with the
+    /// threshold voting power, along with the uncommitted anchors in its causal history, until
+    /// none is left.
+    fn try_commit(&mut self, index: usize) -> Result<(), DagStoreError> {
+        let required = self
+            .config
+            .commit_threshold
+            .required(&self.epoch_state.verifier);
+        loop {
+            let validator = &self.validators[index];
+            let committable = self
+                .config
+                .round_schedule
+                .anchor_rounds_between(
+                    validator.last_ordered_round + 1,
+                    validator.dag.highest_round().saturating_sub(1),
+                )
+                .filter_map(|round| {
+                    validator
+                        .dag
+                        .get_node_by_round_author(round, &self.anchor_election.get_anchor(round))
+                })
+                .find(|anchor| {
+                    validator
+                        .dag
+                        .anchor_blockers(anchor.metadata())
+                        .linking_power
+                        >= required
+                })
+                .cloned();
+            match committable {
+                Some(anchor) => self.commit(index, anchor)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn commit(&mut self, index: usize, anchor: Arc<CertifiedNode>) -> Result<(), DagStoreError> {
+        let validator = &mut self.validators[index];
+        let mut chain = vec![anchor.clone()];
+        let previous_rounds = self
+            .config
+            .round_schedule
+            .anchor_rounds_between(
+                validator.last_ordered_round + 1,
+                anchor.metadata().round() - 1,
+            )
+            .rev();
+        for round in previous_rounds {
+            let previous = validator
+                .dag
+                .get_node_by_round_author(round, &self.anchor_election.get_anchor(round));
+            if let Some(previous) = previous {
+                if validator.dag.has_path(
+                    chain.last().expect("chain is not empty").metadata(),
+                    previous.metadata(),
+                ) {
+                    chain.push(previous.clone());
+                }
+            }
+        }
+        for anchor in chain.iter().rev() {
+            let budget = validator.dag.traversal_budget();
+            let batch = match validator.dag.order_anchor(anchor.metadata(), None, budget) {
+                Ok(batch) => batch,
+                // left to the skip path like an anchor that never shows up
+                Err(DagStoreError::AnchorNotConnected { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            validator
+                .report
+                .ordered
+                .extend(batch.nodes().iter().map(|node| node.digest()));
+            let commit_latency = *validator
+                .dag
+                .commit_latencies()
+                .last()
+                .expect("the ordered anchor records its latency");
+            let round = anchor.metadata().round();
+            validator.report.anchors.push(CommittedAnchor {
+                round,
+                author: *anchor.metadata().author(),
+                tick: self.tick,
+                latency_rounds: validator.report.round - round,
+                commit_latency,
+                direct: anchor.digest() == chain[0].digest(),
+            });
+        }
+        validator.last_ordered_round = anchor.metadata().round();
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    anchor_election::AnchorElection,
+    storage::DAGStorage,
+    types::{SkipCertificate, SkipVote},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_types::{
+    aggregate_signature::PartialSignatures, epoch_state::EpochState,
+    validator_verifier::VerifyError,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use thiserror::Error as ThisError;
+
+/// Number of rounds above the committed round votes are collected for.
+const MAX_SKIP_VOTE_ROUNDS: Round = 100;
+
+#[derive(Debug, ThisError)]
+pub enum SkipVoteError {
+    #[error("vote epoch {epoch} doesn't match the tracker epoch {expected}")]
+    EpochMismatch { epoch: u64, expected: u64 },
+    #[error("vote skips {anchor} but the anchor of round {round} is {expected}")]
+    WrongAnchor {
+        round: Round,
+        anchor: Author,
+        expected: Author,
+    },
+    #[error("vote for round {round} outside of the rounds {lowest_round} to {highest_round}")]
+    RoundOutsideWindow {
+        round: Round,
+        lowest_round: Round,
+        highest_round: Round,
+    },
+    #[error("vote from unknown author {0}")]
+    UnknownAuthor(Author),
+    #[error("invalid signature from {0}")]
+    InvalidSignature(Author),
+    #[error(transparent)]
+    Storage(#[from] anyhow::Error),
+}
+
+struct RoundSkipVotes {
+    partial_signatures: PartialSignatures,
+    voting_power: u128,
+    certificate: Option<SkipCertificate>,
+}
+
+impl RoundSkipVotes {
+    fn new() -> Self {
+        Self {
+            partial_signatures: PartialSignatures::empty(),
+            voting_power: 0,
+            certificate: None,
+        }
+    }
+}
+
+/// Collects the skip votes of the rounds whose anchor didn't show up, until they reach quorum
+/// voting power and the round can be abandoned with a `SkipCertificate`. Every vote is persisted
+/// as it's added so the collected votes survive a restart. Only the rounds from the committed
+/// round up to `MAX_SKIP_VOTE_ROUNDS` above it are collected, so a peer can't grow the tracker
+/// with votes for rounds that are settled or far ahead.
+pub struct SkipRoundTracker {
+    epoch_state: Arc<EpochState>,
+    anchor_election: Arc<dyn AnchorElection>,
+    storage: Arc<dyn DAGStorage>,
+    votes_by_round: BTreeMap<Round, RoundSkipVotes>,
+    committed_round: Round,
+}
+
+impl SkipRoundTracker {
+    /// Recovers the votes of the epoch from storage, the votes of other epochs are deleted.
+    pub fn new(
+        epoch_state: Arc<EpochState>,
+        anchor_election: Arc<dyn AnchorElection>,
+        storage: Arc<dyn DAGStorage>,
+    ) -> Result<Self, SkipVoteError> {
+        let mut tracker = Self {
+            epoch_state,
+            anchor_election,
+            storage,
+            votes_by_round: BTreeMap::new(),
+            committed_round: 0,
+        };
+        let mut expired = vec![];
+        for (key, vote) in tracker.storage.get_skip_votes()? {
+            if vote.skip().epoch() == tracker.epoch_state.epoch {
+                tracker.insert_vote(vote);
+            } else {
+                expired.push(key);
+            }
+        }
+        if !expired.is_empty() {
+            tracker.storage.delete_skip_votes(expired)?;
+        }
+        Ok(tracker)
+    }
+
+    /// Verifies and adds the vote, duplicates are ignored. Returns the certificate when this vote
+    /// brings the voting power of the round to quorum.
+    pub fn add_vote(&mut self, vote: SkipVote) -> Result<Option<SkipCertificate>, SkipVoteError> {
+        let skip = vote.skip();
+        if skip.epoch() != self.epoch_state.epoch {
+            return Err(SkipVoteError::EpochMismatch {
+                epoch: skip.epoch(),
+                expected: self.epoch_state.epoch,
+            });
+        }
+        let highest_round = self.committed_round + MAX_SKIP_VOTE_ROUNDS;
+        if skip.round() < self.committed_round || skip.round() > highest_round {
+            return Err(SkipVoteError::RoundOutsideWindow {
+                round: skip.round(),
+                lowest_round: self.committed_round,
+                highest_round,
+            });
+        }
+        let expected = self.anchor_election.get_anchor(skip.round());
+        if skip.anchor() != &expected {
+            return Err(SkipVoteError::WrongAnchor {
+                round: skip.round(),
+                anchor: *skip.anchor(),
+                expected,
+            });
+        }
+        let author = *vote.author();
+        let verifier = &self.epoch_state.verifier;
+        if verifier.get_voting_power(&author).is_none() {
+            return Err(SkipVoteError::UnknownAuthor(author));
+        }
+        if self
+            .votes_by_round
+            .get(&skip.round())
+            .map_or(false, |votes| {
+                votes.partial_signatures.signatures().contains_key(&author)
+            })
+        {
+            return Ok(None);
+        }
+        verifier
+            .verify(author, skip, vote.signature())
+            .map_err(|e| match e {
+                VerifyError::UnknownAuthor => SkipVoteError::UnknownAuthor(author),
+                _ => SkipVoteError::InvalidSignature(author),
+            })?;
+        self.storage.save_skip_vote(&vote)?;
+        Ok(self.insert_vote(vote))
+    }
+
+    fn insert_vote(&mut self, vote: SkipVote) -> Option<SkipCertificate> {
+        let verifier = &self.epoch_state.verifier;
+        let voting_power = verifier.get_voting_power(vote.author()).unwrap_or_default();
+        let votes = self
+            .votes_by_round
+            .entry(vote.skip().round())
+            .or_insert_with(RoundSkipVotes::new);
+        votes
+            .partial_signatures
+            .add_signature(*vote.author(), vote.signature().clone());
+        votes.voting_power += voting_power as u128;
+        if votes.certificate.is_some() || votes.voting_power < verifier.quorum_voting_power() {
+            return None;
+        }
+        let signatures = verifier
+            .aggregate_signatures(&votes.partial_signatures)
+            .expect("Signature aggregation should succeed");
+        let certificate = SkipCertificate::new(vote.skip().clone(), signatures);
+        votes.certificate = Some(certificate.clone());
+        Some(certificate)
+    }
+
+    pub fn certificate(&self, round: Round) -> Option<&SkipCertificate> {
+        self.votes_by_round.get(&round)?.certificate.as_ref()
+    }
+
+    /// Voting power of the skip votes collected for `round`.
+    pub fn voting_power(&self, round: Round) -> u128 {
+        self.votes_by_round
+            .get(&round)
+            .map_or(0, |votes| votes.voting_power)
+    }
+
+    /// Moves the window of the collected rounds up to `committed_round`, the votes of the rounds
+    /// below it are dropped.
+    pub fn set_committed_round(&mut self, committed_round: Round) -> Result<(), SkipVoteError> {
+        if committed_round <= self.committed_round {
+            return Ok(());
+        }
+        self.committed_round = committed_round;
+        self.prune_below(committed_round)
+    }
+
+    /// Drops the votes of the rounds below `round` from memory and storage.
+    pub fn prune_below(&mut self, round: Round) -> Result<(), SkipVoteError> {
+        let to_keep = self.votes_by_round.split_off(&round);
+        let keys: Vec<_> = std::mem::replace(&mut self.votes_by_round, to_keep)
+            .into_iter()
+            .flat_map(|(round, votes)| {
+                votes
+                    .partial_signatures
+                    .signatures()
+                    .keys()
+                    .map(|author| (round, *author))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if !keys.is_empty() {
+            self.storage.delete_skip_votes(keys)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::validator_index::ValidatorIndex;
+use aptos_consensus_types::common::{Author, Round};
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+/// Position of a validator in the `ValidatorIndex`, 4 bytes on the wire whatever the platform.
+pub type ValidatorIndexU32 = u32;
+
+/// Why a `SlotId` can't be resolved against a `ValidatorIndex`.
+#[derive(Clone, Debug, PartialEq, Eq, ThisError)]
+pub enum SlotIdError {
+    #[error("author {0} is not in the validator set")]
+    UnknownAuthor(Author),
+    #[error("validator index {index} is out of range of {num_validators} validators")]
+    IndexOutOfRange {
+        index: ValidatorIndexU32,
+        num_validators: usize,
+    },
+}
+
+/// The slot of a node, its round and the validator index of its author. Ordered by round then
+/// index, the order of the slots of the DAG. Only meaningful against the `ValidatorIndex` of the
+/// epoch it was built with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SlotId {
+    round: Round,
+    index: ValidatorIndexU32,
+}
+
+impl SlotId {
+    pub fn new(round: Round, index: ValidatorIndexU32) -> Self {
+        Self { round, index }
+    }
+
+    /// The slot of `author` in `round`.
+    pub fn of_author(
+        round: Round,
+        author: &Author,
+        validator_index: &ValidatorIndex,
+    ) -> Result<Self, SlotIdError> {
+        let index = validator_index
+            .index_of(author)
+            .ok_or(SlotIdError::UnknownAuthor(*author))?;
+        Ok(Self::new(round, index as ValidatorIndexU32))
+    }
+
+    /// The slot at `position` of the validators in `round`, checked against the validator set.
+    pub fn at(
+        round: Round,
+        position: usize,
+        validator_index: &ValidatorIndex,
+    ) -> Result<Self, SlotIdError> {
+        let slot = Self::new(round, position as ValidatorIndexU32);
+        if position >= validator_index.len() || slot.position() != position {
+            return Err(SlotIdError::IndexOutOfRange {
+                index: slot.index,
+                num_validators: validator_index.len(),
+            });
+        }
+        Ok(slot)
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn index(&self) -> ValidatorIndexU32 {
+        self.index
+    }
+
+    /// The index as a position in the slots of a round.
+    pub fn position(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The author of the slot.
+    pub fn author<'a>(
+        &self,
+        validator_index: &'a ValidatorIndex,
+    ) -> Result<&'a Author, SlotIdError> {
+        validator_index
+            .author_at(self.position())
+            .ok_or(SlotIdError::IndexOutOfRange {
+                index: self.index,
+                num_validators: validator_index.len(),
+            })
+    }
+
+    /// The round and author of the slot.
+    pub fn to_round_author(
+        self,
+        validator_index: &ValidatorIndex,
+    ) -> Result<(Round, Author), SlotIdError> {
+        Ok((self.round, *self.author(validator_index)?))
+    }
+}
@@ -3,12 +3,18 @@
 
 use crate::{
     consensusdb::ConsensusDB,
-    dag::{CertifiedNode, Node},
+    dag::{
+        BroadcastProgress, CertifiedNode, DagEpochSummary, EpochRemnant, EvidenceRecord, Node,
+        NodeMetadata, OrderedAnchor, SkipVote,
+    },
 };
+use anyhow::bail;
+use aptos_bitvec::BitVec;
+use aptos_consensus_types::common::{Author, Round};
 use aptos_crypto::HashValue;
 use std::collections::HashMap;
 
-pub trait DAGStorage {
+pub trait DAGStorage: Send + Sync {
     fn save_node(&self, node: &Node) -> anyhow::Result<()>;
 
     fn save_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()>;
@@ -16,6 +22,213 @@ pub trait DAGStorage {
     fn get_certified_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>>;
 
     fn delete_certified_nodes(&self, digests: Vec<HashValue>) -> anyhow::Result<()>;
+
+    /// Deletes the certified nodes of the epochs below `epoch` and of the rounds of `epoch` below
+    /// `round`. Stores keyed by round can do it without listing the nodes.
+    fn delete_rounds_below(&self, epoch: u64, round: Round) -> anyhow::Result<()> {
+        let digests: Vec<_> = self
+            .get_certified_nodes()?
+            .into_iter()
+            .filter(|(_, node)| {
+                let metadata = node.metadata();
+                (metadata.epoch(), metadata.round()) < (epoch, round)
+            })
+            .map(|(digest, _)| digest)
+            .collect();
+        if digests.is_empty() {
+            return Ok(());
+        }
+        self.delete_certified_nodes(digests)
+    }
+
+    /// Persisting the nodes waiting for their parents is optional, without it they're fetched
+    /// again after a restart.
+    fn save_pending_node(&self, _node: &CertifiedNode) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_pending_node(&self, _digest: &HashValue) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_pending_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Ok(HashMap::new())
+    }
+
+    /// Persisting the ordered anchors is optional, without it the source nodes of the batches
+    /// ordered before a restart are only known from the nodes marked ordered.
+    fn save_ordered_anchor(&self, _ordered_anchor: &OrderedAnchor) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_ordered_anchors(&self) -> anyhow::Result<HashMap<HashValue, OrderedAnchor>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_ordered_anchors(&self, _digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Queues certified nodes whose deletion failed to be retried later, with the number of
+    /// attempts so far. Optional, without it the nodes stay stored until they expire at the next
+    /// recovery.
+    fn save_pending_deletions(&self, _deletions: &HashMap<HashValue, u32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_pending_deletions(&self, _digests: &[HashValue]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_pending_deletions(&self) -> anyhow::Result<HashMap<HashValue, u32>> {
+        Ok(HashMap::new())
+    }
+
+    /// Persisting the skip votes is optional, without it the votes are collected again after a
+    /// restart.
+    fn save_skip_vote(&self, _vote: &SkipVote) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_skip_votes(&self) -> anyhow::Result<HashMap<(Round, Author), SkipVote>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_skip_votes(&self, _keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Reserves the slot of the local validator in `round` for `node`, before the node is
+    /// broadcast. Must be durable once it returns, it's what keeps the validator from broadcasting
+    /// another node for the round after a crash, and what it broadcasts again instead. Stores that
+    /// can't make it durable keep the default, which refuses the reservation so the node is never
+    /// broadcast unreserved.
+    fn save_self_reservation(
+        &self,
+        _epoch: u64,
+        _round: Round,
+        _node: &Node,
+    ) -> anyhow::Result<()> {
+        bail!("self reservations are not supported by this storage")
+    }
+
+    fn get_self_reservations(&self) -> anyhow::Result<HashMap<(u64, Round), Node>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_self_reservations(&self, _keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Records the validators that acked the certified node with `digest` broadcast by the local
+    /// validator in `round`, replacing the previous record of the round. Optional, without it a
+    /// resumed broadcast is sent to every validator again.
+    fn save_broadcast_progress(
+        &self,
+        _epoch: u64,
+        _round: Round,
+        _digest: &HashValue,
+        _acked: &BitVec,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_broadcast_progress(&self) -> anyhow::Result<HashMap<(u64, Round), BroadcastProgress>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_broadcast_progress(&self, _keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Records that `author` is excluded from the DAG of `epoch` for equivocating, first in
+    /// `round`. Optional, without it an equivocator is only excluded again once it's caught
+    /// again after a restart.
+    fn save_equivocator(&self, _epoch: u64, _author: &Author, _round: Round) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_equivocators(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_equivocators(&self, _keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Records an equivocation by the digest of the conflicting node, replacing the previous
+    /// record of the digest. Optional, without it the evidence not yet reported is lost on a
+    /// restart.
+    fn save_equivocation_evidence(&self, _record: &EvidenceRecord) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_equivocation_evidence(&self) -> anyhow::Result<HashMap<HashValue, EvidenceRecord>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_equivocation_evidence(&self, _digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Records that the authors are denied by governance in `epoch`, each with the round of the
+    /// governance event denying it.
+    /// Optional, without it the denials are only applied again once governance reports them.
+    fn save_denied_authors(&self, _epoch: u64, _authors: &[(Author, Round)]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_denied_authors(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(HashMap::new())
+    }
+
+    fn delete_denied_authors(&self, _keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Only the start round of the latest epoch is kept. Optional, without it an epoch is assumed
+    /// to start in the default round after a restart.
+    fn save_epoch_start_round(&self, _epoch: u64, _round: Round) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_epoch_start_round(&self) -> anyhow::Result<Option<(u64, Round)>> {
+        Ok(None)
+    }
+
+    /// Only the last committed anchor of the latest epoch is kept. Optional, without it the
+    /// ordering is only checked against the anchors committed since a restart.
+    fn save_last_committed_anchor(&self, _anchor: &NodeMetadata) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn get_last_committed_anchor(&self) -> anyhow::Result<Option<NodeMetadata>> {
+        Ok(None)
+    }
+
+    fn delete_last_committed_anchor(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Keeping the summary of an ended epoch is optional, it's logged either way.
+    fn save_epoch_summary(&self, _summary: &DagEpochSummary) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the remnant of its epoch. Must be durable once it returns, the nodes of the
+    /// epoch are deleted by the DAG of the next epoch. Stores that can't keep it leave the
+    /// default, which fails the save like any storage error instead of losing it silently.
+    fn save_epoch_remnant(&self, _remnant: &EpochRemnant) -> anyhow::Result<()> {
+        bail!("epoch remnants are not supported by this storage")
+    }
+
+    fn get_epoch_remnants(&self) -> anyhow::Result<Vec<EpochRemnant>> {
+        Ok(vec![])
+    }
+
+    fn delete_epoch_remnant(&self, _epoch: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 impl DAGStorage for ConsensusDB {
@@ -34,4 +247,158 @@ impl DAGStorage for ConsensusDB {
     fn delete_certified_nodes(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
         Ok(self.delete_certified_nodes(digests)?)
     }
+
+    fn delete_rounds_below(&self, epoch: u64, round: Round) -> anyhow::Result<()> {
+        Ok(self.delete_dag_rounds_below(epoch, round)?)
+    }
+
+    fn save_pending_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        Ok(self.save_pending_node(node)?)
+    }
+
+    fn delete_pending_node(&self, digest: &HashValue) -> anyhow::Result<()> {
+        Ok(self.delete_pending_node(digest)?)
+    }
+
+    fn get_pending_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Ok(self.get_pending_nodes()?)
+    }
+
+    fn save_ordered_anchor(&self, ordered_anchor: &OrderedAnchor) -> anyhow::Result<()> {
+        Ok(self.save_ordered_anchor(ordered_anchor)?)
+    }
+
+    fn get_ordered_anchors(&self) -> anyhow::Result<HashMap<HashValue, OrderedAnchor>> {
+        Ok(self.get_ordered_anchors()?)
+    }
+
+    fn delete_ordered_anchors(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(self.delete_ordered_anchors(digests)?)
+    }
+
+    fn save_pending_deletions(&self, deletions: &HashMap<HashValue, u32>) -> anyhow::Result<()> {
+        Ok(self.save_pending_deletions(deletions)?)
+    }
+
+    fn delete_pending_deletions(&self, digests: &[HashValue]) -> anyhow::Result<()> {
+        Ok(self.delete_pending_deletions(digests)?)
+    }
+
+    fn get_pending_deletions(&self) -> anyhow::Result<HashMap<HashValue, u32>> {
+        Ok(self.get_pending_deletions()?)
+    }
+
+    fn save_skip_vote(&self, vote: &SkipVote) -> anyhow::Result<()> {
+        Ok(self.save_skip_vote(vote)?)
+    }
+
+    fn get_skip_votes(&self) -> anyhow::Result<HashMap<(Round, Author), SkipVote>> {
+        Ok(self.get_skip_votes()?)
+    }
+
+    fn delete_skip_votes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        Ok(self.delete_skip_votes(keys)?)
+    }
+
+    fn save_self_reservation(&self, epoch: u64, round: Round, node: &Node) -> anyhow::Result<()> {
+        Ok(self.save_dag_self_reservation(epoch, round, node)?)
+    }
+
+    fn get_self_reservations(&self) -> anyhow::Result<HashMap<(u64, Round), Node>> {
+        Ok(self.get_dag_self_reservations()?)
+    }
+
+    fn delete_self_reservations(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_self_reservations(keys)?)
+    }
+
+    fn save_broadcast_progress(
+        &self,
+        epoch: u64,
+        round: Round,
+        digest: &HashValue,
+        acked: &BitVec,
+    ) -> anyhow::Result<()> {
+        Ok(self.save_dag_broadcast_progress(epoch, round, digest, acked)?)
+    }
+
+    fn get_broadcast_progress(&self) -> anyhow::Result<HashMap<(u64, Round), BroadcastProgress>> {
+        Ok(self.get_dag_broadcast_progress()?)
+    }
+
+    fn delete_broadcast_progress(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_broadcast_progress(keys)?)
+    }
+
+    fn save_equivocator(&self, epoch: u64, author: &Author, round: Round) -> anyhow::Result<()> {
+        Ok(self.save_dag_equivocator(epoch, author, round)?)
+    }
+
+    fn get_equivocators(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(self.get_dag_equivocators()?)
+    }
+
+    fn delete_equivocators(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_equivocators(keys)?)
+    }
+
+    fn save_equivocation_evidence(&self, record: &EvidenceRecord) -> anyhow::Result<()> {
+        Ok(self.save_dag_equivocation_evidence(record)?)
+    }
+
+    fn get_equivocation_evidence(&self) -> anyhow::Result<HashMap<HashValue, EvidenceRecord>> {
+        Ok(self.get_dag_equivocation_evidence()?)
+    }
+
+    fn delete_equivocation_evidence(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_equivocation_evidence(digests)?)
+    }
+
+    fn save_denied_authors(&self, epoch: u64, authors: &[(Author, Round)]) -> anyhow::Result<()> {
+        Ok(self.save_dag_denied_authors(epoch, authors)?)
+    }
+
+    fn get_denied_authors(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(self.get_dag_denied_authors()?)
+    }
+
+    fn delete_denied_authors(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_denied_authors(keys)?)
+    }
+
+    fn save_epoch_start_round(&self, epoch: u64, round: Round) -> anyhow::Result<()> {
+        Ok(self.save_dag_epoch_start_round(epoch, round)?)
+    }
+
+    fn get_epoch_start_round(&self) -> anyhow::Result<Option<(u64, Round)>> {
+        Ok(self.get_dag_epoch_start_round()?)
+    }
+
+    fn save_last_committed_anchor(&self, anchor: &NodeMetadata) -> anyhow::Result<()> {
+        Ok(self.save_dag_last_committed_anchor(anchor)?)
+    }
+
+    fn get_last_committed_anchor(&self) -> anyhow::Result<Option<NodeMetadata>> {
+        Ok(self.get_dag_last_committed_anchor()?)
+    }
+
+    fn delete_last_committed_anchor(&self) -> anyhow::Result<()> {
+        Ok(self.delete_dag_last_committed_anchor()?)
+    }
+
+    fn save_epoch_summary(&self, summary: &DagEpochSummary) -> anyhow::Result<()> {
+        Ok(self.save_dag_epoch_summary(summary)?)
+    }
+
+    fn save_epoch_remnant(&self, remnant: &EpochRemnant) -> anyhow::Result<()> {
+        Ok(self.save_dag_epoch_remnant(remnant)?)
+    }
+
+    fn get_epoch_remnants(&self) -> anyhow::Result<Vec<EpochRemnant>> {
+        Ok(self.get_dag_epoch_remnants()?)
+    }
+
+    fn delete_epoch_remnant(&self, epoch: u64) -> anyhow::Result<()> {
+        Ok(self.delete_dag_epoch_remnant(epoch)?)
+    }
 }
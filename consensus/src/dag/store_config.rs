@@ -0,0 +1,143 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{
+        EvidenceRetention, DEFAULT_MAX_CLOCK_DRIFT, DEFAULT_PARK_GAP, DEFAULT_SKEW_THRESHOLD,
+        DEFAULT_TIMESTAMP_SKEW_TOLERANCE, DEFAULT_WINDOW_SIZE,
+    },
+    pruned_filter::DEFAULT_PRUNED_FILTER_ROUNDS,
+    pruning_policy::{DagPruningPolicy, RetainCommittedPolicy, WindowPolicy},
+    replay::ReplayLogConfig,
+    types::{NodeCertificate, NodeMetadata},
+    write_retry::{
+        WriteRetryQueue, DEFAULT_MAX_WRITE_ATTEMPTS, DEFAULT_WRITE_RETRY_BACKOFF,
+        DEFAULT_WRITE_RETRY_CAPACITY,
+    },
+};
+use aptos_consensus_types::common::Round;
+use serde::{Deserialize, Serialize};
+use std::{mem::size_of, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+
+/// Settings of the DAG store, read from the node config. `Dag::try_new` validates them against
+/// the validator set of the epoch, the setters of `Dag` change them one at a time afterwards.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DagStoreConfig {
+    /// Rounds below the highest round kept when rounds are committed
+    pub window: Round,
+    /// Keeps that many rounds below the committed round instead of the window, for peers lagging
+    /// behind
+    pub committed_retention_rounds: Option<Round>,
+    /// Rounds above the highest round within which `insert_node` parks nodes
+    pub park_gap: Round,
+    /// Skew beyond which `check_author_skew` flags an author
+    pub skew_threshold: Round,
+    /// How much earlier than its latest parent a node can be, for the clock skew between
+    /// validators
+    pub timestamp_skew_tolerance: Duration,
+    /// How far ahead of local time the timestamp of a node can be
+    pub max_clock_drift: Duration,
+    /// Soft limit of the bytes of the nodes, exceeding it turns on backpressure
+    pub memory_budget: usize,
+    /// Pruned rounds whose fingerprints are kept to drop redeliveries
+    pub pruned_filter_rounds: usize,
+    pub evidence_retention: EvidenceRetention,
+    /// Failed node writes queued for a retry
+    pub write_retry_capacity: usize,
+    pub max_write_attempts: u32,
+    pub write_retry_backoff: Duration,
+    /// Records the nodes received to replay them with `dag::replay::run`, off by default
+    pub replay_log: Option<ReplayLogConfig>,
+}
+
+impl Default for DagStoreConfig {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_WINDOW_SIZE,
+            committed_retention_rounds: None,
+            park_gap: DEFAULT_PARK_GAP,
+            skew_threshold: DEFAULT_SKEW_THRESHOLD,
+            timestamp_skew_tolerance: DEFAULT_TIMESTAMP_SKEW_TOLERANCE,
+            max_clock_drift: DEFAULT_MAX_CLOCK_DRIFT,
+            memory_budget: usize::MAX,
+            pruned_filter_rounds: DEFAULT_PRUNED_FILTER_ROUNDS,
+            evidence_retention: EvidenceRetention::default(),
+            write_retry_capacity: DEFAULT_WRITE_RETRY_CAPACITY,
+            max_write_attempts: DEFAULT_MAX_WRITE_ATTEMPTS,
+            write_retry_backoff: DEFAULT_WRITE_RETRY_BACKOFF,
+            replay_log: None,
+        }
+    }
+}
+
+/// A setting of `DagStoreConfig` out of its bounds or inconsistent with another one.
+#[derive(Debug, PartialEq, Eq, ThisError)]
+pub enum DagStoreConfigError {
+    #[error("window of 0 rounds")]
+    ZeroWindow,
+    #[error("park gap of {park_gap} rounds, between 1 and the window of {window} rounds allowed")]
+    ParkGapOutsideWindow { park_gap: Round, window: Round },
+    #[error("memory budget of {memory_budget} bytes, a full window takes at least {window_bytes}")]
+    MemoryBudgetBelowWindow {
+        memory_budget: usize,
+        window_bytes: usize,
+    },
+    #[error("writes retried with 0 attempts")]
+    ZeroWriteAttempts,
+}
+
+impl DagStoreConfig {
+    /// Checks the settings for a validator set of `num_validators`.
+    pub fn validate(&self, num_validators: usize) -> Result<(), DagStoreConfigError> {
+        if self.window == 0 {
+            return Err(DagStoreConfigError::ZeroWindow);
+        }
+        if self.park_gap == 0 || self.park_gap > self.window {
+            return Err(DagStoreConfigError::ParkGapOutsideWindow {
+                park_gap: self.park_gap,
+                window: self.window,
+            });
+        }
+        let window_bytes = self.min_window_bytes(num_validators);
+        if self.memory_budget < window_bytes {
+            return Err(DagStoreConfigError::MemoryBudgetBelowWindow {
+                memory_budget: self.memory_budget,
+                window_bytes,
+            });
+        }
+        if self.max_write_attempts == 0 {
+            return Err(DagStoreConfigError::ZeroWriteAttempts);
+        }
+        Ok(())
+    }
+
+    /// The estimated bytes of a window full of nodes without payload, each linking every node of
+    /// the previous round. It's a lower bound rather than the worst case, the payloads come on
+    /// top: a budget below it turns on backpressure before the window fills up even with empty
+    /// nodes.
+    pub fn min_window_bytes(&self, num_validators: usize) -> usize {
+        let node_bytes = size_of::<NodeMetadata>() + num_validators * size_of::<NodeCertificate>();
+        (self.window as usize)
+            .saturating_mul(num_validators)
+            .saturating_mul(node_bytes)
+    }
+
+    pub fn pruning_policy(&self) -> Arc<dyn DagPruningPolicy> {
+        match self.committed_retention_rounds {
+            Some(extra_rounds) => Arc::new(RetainCommittedPolicy { extra_rounds }),
+            None => Arc::new(WindowPolicy {
+                window: self.window,
+            }),
+        }
+    }
+
+    pub fn write_retry_queue(&self) -> WriteRetryQueue {
+        WriteRetryQueue::new(
+            self.write_retry_capacity,
+            self.max_write_attempts,
+            self.write_retry_backoff,
+        )
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{DagStoreError, DEFAULT_EPOCH_START_ROUND},
+    node_builder::CertifiedNodeBuilder,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, new_epoch_certified_node, TestDag},
+    },
+    types::TDAGMessage,
+};
+use aptos_consensus_types::common::Author;
+use aptos_types::{
+    chain_id::ChainId, epoch_state::EpochState, validator_verifier::random_validator_verifier,
+};
+use std::sync::Arc;
+
+#[test]
+fn test_node_builder_recomputes_digest() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let parents = signers[1..4]
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]).certificate())
+        .collect();
+    let template = new_certified_node(2, signers[0].author(), parents);
+    let sign = |builder: CertifiedNodeBuilder| {
+        let node = builder.build(&signers, &validator_verifier);
+        assert!(node.verify(&validator_verifier).is_ok());
+        node
+    };
+    let node = sign(CertifiedNodeBuilder::new(&template));
+    assert_eq!(node.digest(), template.digest());
+
+    let node = sign(CertifiedNodeBuilder::new(&template).timestamp(1));
+    assert_ne!(node.digest(), template.digest());
+    assert!(node.has_valid_digest());
+    assert_eq!(node.metadata().timestamp(), 1);
+    // a minority of the signers doesn't certify it
+    let node = CertifiedNodeBuilder::new(&template).build(&signers[0..2], &validator_verifier);
+    assert!(node.verify(&validator_verifier).is_err());
+}
+
+#[test]
+fn test_add_node_adversarial_matrix() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &round_one {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let author = signers[0].author();
+    let certificates: Vec<_> = round_one.iter().map(|node| node.certificate()).collect();
+    // the valid node the malformed ones start from
+    let template = new_certified_node(2, author, certificates[0..3].to_vec());
+    let builder = || CertifiedNodeBuilder::new(&template);
+    let sign = |builder: CertifiedNodeBuilder| {
+        let node = builder.build(&signers, &epoch_state.verifier);
+        assert!(node.verify(&epoch_state.verifier).is_ok());
+        node
+    };
+    let stranger = Author::random();
+    let own_round_two = new_certified_node(2, author, vec![]).certificate();
+    let other_round_two = new_certified_node(2, signers[1].author(), vec![]).certificate();
+    let other_epoch = new_epoch_certified_node(2, 1, signers[3].author(), vec![]).certificate();
+    let unknown_parent = sign(CertifiedNodeBuilder::new(&round_one[3]).timestamp(1)).certificate();
+    // another node in the slot of a parent of the template
+    let other_parent = sign(CertifiedNodeBuilder::new(&round_one[1]).timestamp(1)).certificate();
+    let cases = vec![
+        (
+            "wrong epoch",
+            builder().epoch(2),
+            DagStoreError::EpochMismatch {
+                epoch: 2,
+                expected: 1,
+            },
+        ),
+        (
+            "wrong chain",
+            builder().chain_id(ChainId::new(10)),
+            DagStoreError::ChainIdMismatch {
+                chain_id: ChainId::new(10),
+                expected: ChainId::test(),
+            },
+        ),
+        (
+            "unknown author",
+            builder().author(stranger),
+            DagStoreError::UnknownAuthor(stranger),
+        ),
+        (
+            "self parent",
+            builder().add_parent(own_round_two),
+            DagStoreError::SelfParent,
+        ),
+        (
+            "parent of the same round",
+            builder().add_parent(other_round_two),
+            DagStoreError::InvalidParentRound {
+                round: 2,
+                parent_round: 2,
+            },
+        ),
+        (
+            "parent of another epoch",
+            builder().add_parent(other_epoch),
+            DagStoreError::ParentEpochMismatch {
+                epoch: 1,
+                parent_epoch: 2,
+            },
+        ),
+        (
+            "duplicate parents",
+            builder().add_parent(certificates[1].clone()),
+            DagStoreError::DuplicateParent(*certificates[1].metadata().digest()),
+        ),
+        (
+            "two parents in a slot",
+            builder().add_parent(other_parent),
+            DagStoreError::DuplicateParentSlot {
+                round: 1,
+                author: signers[1].author(),
+            },
+        ),
+        (
+            "no parents",
+            builder().parents(vec![]),
+            DagStoreError::EmptyParentsNotAllowed {
+                round: 2,
+                start_round: DEFAULT_EPOCH_START_ROUND,
+            },
+        ),
+        (
+            "round too high",
+            builder().round(4),
+            DagStoreError::RoundTooHigh {
+                round: 4,
+                highest_round: 1,
+                gap: 3,
+            },
+        ),
+        (
+            "missing parent",
+            builder().parents(vec![
+                certificates[0].clone(),
+                certificates[1].clone(),
+                unknown_parent.clone(),
+            ]),
+            DagStoreError::MissingParent(*unknown_parent.metadata().digest()),
+        ),
+        (
+            "equivocation",
+            CertifiedNodeBuilder::new(&round_one[1]).timestamp(1),
+            DagStoreError::EquivocateNode,
+        ),
+    ];
+
+    for (name, builder, expected) in cases {
+        let node = sign(builder);
+        match dag.add_node(node.clone()) {
+            Err(e) => assert_eq!(e.to_string(), expected.to_string(), "{}", name),
+            Ok(()) => panic!("{}: malformed node was added", name),
+        }
+        assert!(!dag.exists(&node.digest()), "{}", name);
+    }
+    assert!(dag.equivocators().contains_key(&signers[1].author()));
+    assert!(dag.add_node(template.clone()).is_ok());
+}
@@ -0,0 +1,177 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{DagStoreError, NodeStatusKind},
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, NodeCertificate, NodeMetadata},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::{ops::ControlFlow, sync::Arc};
+
+/// Round 1 is full, in round 2 only the first validator links to the node of the first validator
+/// in round 1, and round 3 links every node of round 2.
+struct ContinuityDag {
+    epoch_state: Arc<EpochState>,
+    storage: Arc<MockStorage>,
+    dag: TestDag,
+    /// The node of the first validator in round 1
+    first: NodeMetadata,
+    /// The node of the second validator in round 2, not reaching `first`
+    disconnected: NodeMetadata,
+    /// The node of the first validator in round 3, reaching `first`
+    connected: NodeMetadata,
+}
+
+impl ContinuityDag {
+    fn new() -> Self {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let epoch_state = Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier,
+        });
+        let authors = epoch_state.verifier.get_ordered_account_addresses();
+        let storage = Arc::new(MockStorage::new());
+        let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+        let mut add = |nodes: Vec<CertifiedNode>| {
+            for node in &nodes {
+                assert!(dag.add_node(node.clone()).is_ok());
+            }
+            nodes
+        };
+        let round_1 = add(nodes(1, &authors, |_| vec![]));
+        let round_2 = add(nodes(2, &authors, |index| {
+            let skipped = usize::from(index != 0);
+            round_1[skipped..]
+                .iter()
+                .map(|node| node.certificate())
+                .collect()
+        }));
+        let round_3 = add(nodes(3, &authors, |_| {
+            round_2.iter().map(|node| node.certificate()).collect()
+        }));
+        Self {
+            epoch_state,
+            storage,
+            dag,
+            first: round_1[0].metadata().clone(),
+            disconnected: round_2[1].metadata().clone(),
+            connected: round_3[0].metadata().clone(),
+        }
+    }
+
+    fn assert_refused(&mut self, dag_name: &str, previous: Option<&NodeMetadata>) {
+        let budget = self.dag.traversal_budget();
+        match self.dag.order_anchor(&self.disconnected, previous, budget) {
+            Err(DagStoreError::AnchorNotConnected { anchor, previous }) => {
+                assert_eq!(&anchor, self.disconnected.digest());
+                assert_eq!(&previous, self.first.digest());
+            },
+            result => panic!(
+                "{}: expected a refusal, got {:?}",
+                dag_name,
+                result.map(|_| ())
+            ),
+        }
+    }
+}
+
+fn nodes(
+    round: Round,
+    authors: &[Author],
+    parents: impl Fn(usize) -> Vec<NodeCertificate>,
+) -> Vec<CertifiedNode> {
+    authors
+        .iter()
+        .enumerate()
+        .map(|(index, author)| new_certified_node(round, *author, parents(index)))
+        .collect()
+}
+
+#[test]
+fn test_disconnected_anchor_refused() {
+    let mut setup = ContinuityDag::new();
+    assert!(setup.dag.has_path(&setup.connected, &setup.first));
+    assert!(!setup.dag.has_path(&setup.disconnected, &setup.first));
+    assert!(setup.dag.has_path(&setup.first, &setup.first));
+
+    let first = setup.first.clone();
+    let budget = setup.dag.traversal_budget();
+    assert!(setup.dag.order_anchor(&first, None, budget).is_ok());
+    assert_eq!(setup.dag.last_committed_anchor(), Some(&first));
+
+    // against the recorded anchor or the one given, nothing is ordered
+    setup.assert_refused("live", None);
+    setup.assert_refused("live", Some(&first));
+    let disconnected = setup.disconnected.clone();
+    assert!(matches!(
+        setup
+            .dag
+            .order_anchor_streamed(&disconnected, None, 1, |_| ControlFlow::Continue(())),
+        Err(DagStoreError::AnchorNotConnected { .. })
+    ));
+    assert_eq!(setup.dag.statuses(&[disconnected]), vec![Some(
+        NodeStatusKind::Unordered
+    )]);
+    assert_eq!(setup.dag.last_committed_anchor(), Some(&first));
+
+    // a restart enforces it too
+    let mut setup = ContinuityDag {
+        dag: TestDag::new(setup.epoch_state.clone(), setup.storage.clone()),
+        ..setup
+    };
+    assert_eq!(setup.dag.last_committed_anchor(), Some(&first));
+    setup.assert_refused("recovered", None);
+
+    // the record of a previous epoch doesn't bind the next one
+    let next_epoch = Arc::new(EpochState {
+        epoch: 2,
+        verifier: setup.epoch_state.verifier.clone(),
+    });
+    let dag = TestDag::new(next_epoch, setup.storage.clone());
+    assert_eq!(dag.last_committed_anchor(), None);
+}
+
+#[test]
+fn test_connected_anchor_ordered() {
+    let mut setup = ContinuityDag::new();
+    let (first, connected) = (setup.first.clone(), setup.connected.clone());
+    let budget = setup.dag.traversal_budget();
+    assert!(setup.dag.order_anchor(&first, None, budget).is_ok());
+    // the refused anchor is left to the skip path, the next one commits its history
+    setup.assert_refused("live", None);
+    let batch = setup
+        .dag
+        .order_anchor(&connected, Some(&first), budget)
+        .unwrap();
+    assert_eq!(batch.nodes().len(), 3 + 4 + 1);
+    assert!(batch
+        .nodes()
+        .iter()
+        .any(|node| node.metadata() == &setup.disconnected));
+    assert_eq!(setup.dag.last_committed_anchor(), Some(&connected));
+
+    let recovered = TestDag::new(setup.epoch_state.clone(), setup.storage.clone());
+    assert_eq!(recovered.last_committed_anchor(), Some(&connected));
+}
+
+#[test]
+fn test_pruned_previous_anchor_accepted() {
+    let mut setup = ContinuityDag::new();
+    let (first, disconnected) = (setup.first.clone(), setup.disconnected.clone());
+    let budget = setup.dag.traversal_budget();
+    assert!(setup.dag.order_anchor(&first, None, budget).is_ok());
+    setup.assert_refused("live", None);
+
+    // once pruned, the previous anchor is committed for good and can't be reached anymore
+    assert!(setup.dag.prune_below(2).is_ok());
+    assert_eq!(setup.dag.last_committed_anchor(), Some(&first));
+    let mut recovered = TestDag::new(setup.epoch_state.clone(), setup.storage.clone());
+    assert_eq!(recovered.last_committed_anchor(), Some(&first));
+    assert!(recovered.order_anchor(&disconnected, None, budget).is_ok());
+    assert_eq!(recovered.last_committed_anchor(), Some(&disconnected));
+}
@@ -0,0 +1,234 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    batch_verify::{
+        verify_certificates_batched, verify_certificates_with, verify_nodes_batched,
+        CertificateVerifier,
+    },
+    node_builder::CertifiedNodeBuilder,
+    tests::helpers::new_certified_node,
+    types::{CertifiedNode, Node, NodeCertificate},
+};
+use aptos_consensus_types::common::{Payload, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier, VerifyError},
+};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Verifies with the signatures and counts the calls of each path.
+struct InstrumentedVerifier {
+    inner: ValidatorVerifier,
+    num_batches: AtomicUsize,
+    num_certificates: AtomicUsize,
+}
+
+impl InstrumentedVerifier {
+    fn new(inner: ValidatorVerifier) -> Self {
+        Self {
+            inner,
+            num_batches: AtomicUsize::new(0),
+            num_certificates: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl CertificateVerifier for InstrumentedVerifier {
+    fn verify_batch(&self, certs: &[(HashValue, &NodeCertificate)]) -> Result<(), VerifyError> {
+        self.num_batches.fetch_add(1, Ordering::SeqCst);
+        self.inner.verify_batch(certs)
+    }
+
+    fn verify_certificate(
+        &self,
+        digest: HashValue,
+        cert: &NodeCertificate,
+    ) -> Result<(), VerifyError> {
+        self.num_certificates.fetch_add(1, Ordering::SeqCst);
+        self.inner.verify_certificate(digest, cert)
+    }
+}
+
+/// A node of every signer in each round, certified by all of them.
+fn signed_nodes(
+    signers: &[ValidatorSigner],
+    verifier: &ValidatorVerifier,
+    num_rounds: Round,
+) -> Vec<CertifiedNode> {
+    (1..=num_rounds)
+        .flat_map(|round| {
+            signers.iter().map(move |signer| {
+                CertifiedNodeBuilder::new(&new_certified_node(round, signer.author(), vec![]))
+                    .build(signers, verifier)
+            })
+        })
+        .collect()
+}
+
+fn certificates(nodes: &[CertifiedNode]) -> Vec<NodeCertificate> {
+    nodes.iter().map(|node| node.certificate()).collect()
+}
+
+fn as_batch(certificates: &[NodeCertificate]) -> Vec<(HashValue, &NodeCertificate)> {
+    certificates
+        .iter()
+        .map(|certificate| (*certificate.metadata().digest(), certificate))
+        .collect()
+}
+
+#[test]
+fn test_valid_certificates_verified_at_once() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let nodes = signed_nodes(&signers, &validator_verifier, 25);
+    let certificates = certificates(&nodes);
+    let batch = as_batch(&certificates);
+    assert_eq!(batch.len(), 100);
+
+    let verifier = InstrumentedVerifier::new(validator_verifier.clone());
+    verify_certificates_with(&batch, &verifier).unwrap();
+    assert_eq!(verifier.num_batches.load(Ordering::SeqCst), 1);
+    assert_eq!(verifier.num_certificates.load(Ordering::SeqCst), 0);
+    verify_certificates_batched(&batch, &validator_verifier).unwrap();
+
+    // a single certificate isn't worth a batch
+    verify_certificates_with(&batch[..1], &verifier).unwrap();
+    assert_eq!(verifier.num_batches.load(Ordering::SeqCst), 1);
+    assert_eq!(verifier.num_certificates.load(Ordering::SeqCst), 1);
+    verify_certificates_with(&[], &verifier).unwrap();
+}
+
+#[test]
+fn test_invalid_certificate_pinpointed() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let nodes = signed_nodes(&signers, &validator_verifier, 25);
+    let mut certificates = certificates(&nodes);
+    // the signatures of another node
+    certificates[37] = NodeCertificate::new(
+        certificates[37].metadata().clone(),
+        certificates[38].signatures().clone(),
+    );
+    let batch = as_batch(&certificates);
+
+    let verifier = InstrumentedVerifier::new(validator_verifier.clone());
+    let error = verify_certificates_with(&batch, &verifier).unwrap_err();
+    assert_eq!(verifier.num_batches.load(Ordering::SeqCst), 1);
+    assert_eq!(verifier.num_certificates.load(Ordering::SeqCst), 100);
+    assert_eq!(error.invalid().len(), 1);
+    assert_eq!(error.invalid()[0].index, 37);
+    assert_eq!(error.invalid()[0].digest, nodes[37].digest());
+    assert_eq!(error.invalid()[0].error, VerifyError::InvalidMultiSignature);
+
+    let error = verify_certificates_batched(&batch, &validator_verifier).unwrap_err();
+    assert_eq!(
+        error
+            .invalid()
+            .iter()
+            .map(|invalid| invalid.index)
+            .collect::<Vec<_>>(),
+        vec![37]
+    );
+}
+
+#[test]
+fn test_swapped_signatures_fail_the_batch() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let nodes = signed_nodes(&signers, &validator_verifier, 3);
+    let mut certificates = certificates(&nodes);
+    // the aggregate of the signatures is unchanged
+    let (first, second) = (certificates[4].clone(), certificates[5].clone());
+    certificates[4] = NodeCertificate::new(first.metadata().clone(), second.signatures().clone());
+    certificates[5] = NodeCertificate::new(second.metadata().clone(), first.signatures().clone());
+    let batch = as_batch(&certificates);
+
+    let verifier = InstrumentedVerifier::new(validator_verifier);
+    let error = verify_certificates_with(&batch, &verifier).unwrap_err();
+    assert_eq!(verifier.num_certificates.load(Ordering::SeqCst), 12);
+    assert_eq!(
+        error
+            .invalid()
+            .iter()
+            .map(|invalid| invalid.index)
+            .collect::<Vec<_>>(),
+        vec![4, 5]
+    );
+}
+
+#[test]
+fn test_verify_nodes_batched() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let mut nodes = signed_nodes(&signers, &validator_verifier, 3);
+    // a digest that isn't the digest of the node
+    nodes[2] = CertifiedNode::new(
+        Node::new_for_test(
+            nodes[2].metadata().clone(),
+            Payload::empty(false),
+            nodes[..2].iter().map(|node| node.certificate()).collect(),
+        ),
+        nodes[2].signatures().clone(),
+    );
+    // signed by a single validator
+    let partially_signed =
+        CertifiedNodeBuilder::new(&nodes[9]).build(&signers[..1], &validator_verifier);
+    nodes[9] = partially_signed;
+
+    let results = verify_nodes_batched(&nodes.iter().collect::<Vec<_>>(), &validator_verifier);
+    assert_eq!(results.len(), nodes.len());
+    for (position, result) in results.iter().enumerate() {
+        match position {
+            2 => assert_eq!(result.as_ref().unwrap_err().to_string(), "invalid digest"),
+            9 => assert!(result
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .starts_with("unable to verify")),
+            _ => assert!(result.is_ok(), "node {} rejected: {:?}", position, result),
+        }
+    }
+}
+
+fn time_verification(
+    certificates: &[NodeCertificate],
+    verifier: &ValidatorVerifier,
+    num_iterations: usize,
+    batched: bool,
+) -> Duration {
+    let batch = as_batch(certificates);
+    let started = Instant::now();
+    for _ in 0..num_iterations {
+        if batched {
+            verify_certificates_batched(&batch, verifier).unwrap();
+        } else {
+            for (digest, certificate) in &batch {
+                verifier.verify_certificate(*digest, certificate).unwrap();
+            }
+        }
+    }
+    started.elapsed()
+}
+
+/// Micro-benchmark of the verification of 100 certificates of 4 validators, run it with
+/// `cargo test -p aptos-consensus --release bench_batched_verification -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_batched_verification() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let certificates = certificates(&signed_nodes(&signers, &validator_verifier, 25));
+    let num_iterations = 20;
+    let num_verified = (num_iterations * certificates.len()) as f64;
+    // warm up
+    time_verification(&certificates, &validator_verifier, 2, false);
+    let one_by_one = time_verification(&certificates, &validator_verifier, num_iterations, false);
+    let batched = time_verification(&certificates, &validator_verifier, num_iterations, true);
+    println!(
+        "{} certificates: one by one {:.1} us, batched {:.1} us per certificate, {:.2}x",
+        certificates.len(),
+        one_by_one.as_micros() as f64 / num_verified,
+        batched.as_micros() as f64 / num_verified,
+        one_by_one.as_secs_f64() / batched.as_secs_f64()
+    );
+}
@@ -0,0 +1,134 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        broadcast_progress::BroadcastProgressTracker,
+        dag_network::DAGNetworkSender,
+        dag_store::Dag,
+        reliable_broadcast::{BroadcastStatus, ReliableBroadcast},
+        storage::DAGStorage,
+        tests::{dag_test::MockStorage, helpers::new_certified_node},
+        types::{CertifiedAck, CertifiedNode, DAGMessage, Node},
+    },
+    network::TConsensusMsg,
+    network_interface::ConsensusMsg,
+};
+use aptos_consensus_types::common::{Author, Payload};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use async_trait::async_trait;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+/// Acks every certified node and records who it was sent to.
+struct AckingSender {
+    receivers: Mutex<Vec<Author>>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for AckingSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        self.receivers.lock().push(receiver);
+        Ok(DAGMessage::from(CertifiedAck::new(1)).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<(Author, ConsensusMsg)> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_broadcast_resumes_after_restart() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage.clone())));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    assert!(dag.write().add_node(node.clone()).is_ok());
+
+    // the third ack is not written yet when the validator crashes
+    let mut progress = BroadcastProgressTracker::new(dag.clone(), &node, 2);
+    assert_eq!(progress.remaining(), authors);
+    for author in &authors[..3] {
+        assert_eq!(progress.add(*author, CertifiedAck::new(1)).unwrap(), None);
+    }
+    assert_eq!(progress.remaining(), authors[3..].to_vec());
+    assert_eq!(storage.get_broadcast_progress().unwrap().len(), 1);
+
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    let progress = BroadcastProgressTracker::new(dag.clone(), &node, 2);
+    assert_eq!(progress.remaining(), authors[2..].to_vec());
+
+    let sender = Arc::new(AckingSender {
+        receivers: Mutex::new(vec![]),
+    });
+    let rb = ReliableBroadcast::new(authors.clone(), sender.clone());
+    rb.broadcast_to(node.clone(), progress, authors[2..].to_vec())
+        .await;
+    assert_eq!(
+        sender.receivers.lock().iter().collect::<HashSet<_>>(),
+        authors[2..].iter().collect::<HashSet<_>>()
+    );
+    // completing the broadcast is written right away
+    let progress = BroadcastProgressTracker::new(dag.clone(), &node, 2);
+    assert!(progress.is_complete());
+    assert!(progress.remaining().is_empty());
+
+    // pruned with the round
+    assert!(dag.write().prune_below(2).is_ok());
+    assert!(storage.get_broadcast_progress().unwrap().is_empty());
+}
+
+#[test]
+fn test_broadcast_progress_of_another_node_is_dropped() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage.clone())));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    assert!(dag.write().add_node(node.clone()).is_ok());
+    let mut progress = BroadcastProgressTracker::new(dag, &node, 1);
+    for author in &authors[..2] {
+        assert_eq!(progress.add(*author, CertifiedAck::new(1)).unwrap(), None);
+    }
+
+    // built again differently after the restart, the acks were for the other node
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    let other = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            signers[0].author(),
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    let progress = BroadcastProgressTracker::new(dag.clone(), &other, 1);
+    assert_eq!(progress.remaining(), authors);
+    assert!(storage.get_broadcast_progress().unwrap().is_empty());
+    assert_eq!(dag.write().broadcast_progress(1, &node.digest()), None);
+}
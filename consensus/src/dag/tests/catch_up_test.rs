@@ -0,0 +1,398 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        catch_up::{CatchUpOutcome, DagCatchUp, DagCatchUpConfig},
+        dag_fetcher::{AuthorFetchHandler, CertifiedNodeVerifier},
+        dag_network::{DAGNetworkSender, RpcHandler},
+        dag_store::{CatchUpProgress, Dag},
+        fetch_budget::FetchBudget,
+        peer_tracker::DagPeerTracker,
+        reliable_broadcast::CatchUpRequest,
+        tests::{
+            dag_test::MockStorage,
+            helpers::{generate_dag_nodes, new_certified_node, TestDag},
+        },
+        types::{CertifiedNode, DAGMessage, FetchResponse},
+    },
+    network::TConsensusMsg,
+    network_interface::ConsensusMsg,
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
+};
+use anyhow::bail;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::{Mutex, RwLock};
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Accepts the nodes of the genuine DAG only, like the signatures would.
+struct GenuineNodes(HashSet<HashValue>);
+
+impl CertifiedNodeVerifier for GenuineNodes {
+    fn verify_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        if !node.has_valid_digest() || !self.0.contains(&node.digest()) {
+            bail!("invalid signatures");
+        }
+        Ok(())
+    }
+}
+
+enum Behavior {
+    /// Never answers within the timeout
+    Slow,
+    /// Answers with nodes of the requested slots it made up
+    Lying,
+    Good,
+}
+
+/// Serves the author requests from the remote DAG as each peer behaves, recording every request
+/// with the progress the local DAG reported when it was sent.
+struct MockPeers {
+    remote: Arc<RwLock<Dag>>,
+    local: Arc<RwLock<Dag>>,
+    behaviors: HashMap<Author, Behavior>,
+    requests: Mutex<Vec<(Author, Option<CatchUpProgress>)>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl MockPeers {
+    async fn respond(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<FetchResponse> {
+        let request = match TConsensusMsg::from_network_message(message)? {
+            DAGMessage::AuthorFetchRequest(request) => request,
+            message => bail!("unexpected message {}", message.name()),
+        };
+        let response = AuthorFetchHandler::new(self.remote.clone(), 1).process(request)?;
+        match self.behaviors[&receiver] {
+            Behavior::Slow => {
+                tokio::time::sleep(timeout).await;
+                bail!("timed out after {:?}", timeout)
+            },
+            Behavior::Lying => Ok(FetchResponse::new(
+                1,
+                response
+                    .certified_nodes()
+                    .into_iter()
+                    .map(|nodes| {
+                        nodes
+                            .iter()
+                            .map(|node| {
+                                let parents = node.parents()[1..].to_vec();
+                                new_certified_node(node.metadata().round(), *node.author(), parents)
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            )),
+            Behavior::Good => Ok(response),
+        }
+    }
+
+    fn num_requests(&self, peer: &Author) -> usize {
+        self.requests
+            .lock()
+            .iter()
+            .filter(|(receiver, _)| receiver == peer)
+            .count()
+    }
+}
+
+#[async_trait]
+impl DAGNetworkSender for MockPeers {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let progress = self.local.read().catch_up_progress();
+        self.requests.lock().push((receiver, progress));
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let response = self.respond(receiver, message, timeout).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok(DAGMessage::from(response?).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<(Author, ConsensusMsg)> {
+        unimplemented!();
+    }
+}
+
+/// Fails every request, and ends the epoch of the DAG with the request `end_after`.
+struct UnreachablePeers {
+    dag: Arc<RwLock<Dag>>,
+    num_requests: AtomicUsize,
+    end_after: usize,
+}
+
+#[async_trait]
+impl DAGNetworkSender for UnreachablePeers {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        if self.num_requests.fetch_add(1, Ordering::SeqCst) + 1 == self.end_after {
+            self.dag.write().finalize();
+        }
+        bail!("unreachable")
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<(Author, ConsensusMsg)> {
+        unimplemented!();
+    }
+}
+
+/// A DAG whose validators link every node of the previous round in each round, the local DAG
+/// holding its first `local_rounds` rounds and the remote one its first `remote_rounds` rounds.
+struct CatchUpSetup {
+    authors: Vec<Author>,
+    nodes: Vec<Vec<CertifiedNode>>,
+    local: Arc<RwLock<Dag>>,
+    remote: Arc<RwLock<Dag>>,
+}
+
+impl CatchUpSetup {
+    fn new(local_rounds: Round, remote_rounds: Round, num_rounds: Round) -> Self {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let authors = validator_verifier.get_ordered_account_addresses();
+        let epoch_state = Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier,
+        });
+        let full_round = vec![Some((0..authors.len()).collect()); authors.len()];
+        let nodes: Vec<Vec<CertifiedNode>> =
+            generate_dag_nodes(&vec![full_round; num_rounds as usize], &authors)
+                .into_iter()
+                .map(|round_nodes| round_nodes.into_iter().flatten().collect())
+                .collect();
+        let new_dag = |num_rounds: Round| {
+            let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+            for node in nodes[..num_rounds as usize].iter().flatten() {
+                assert!(dag.add_node(node.clone()).is_ok());
+            }
+            Arc::new(RwLock::new(dag.into_inner()))
+        };
+        Self {
+            local: new_dag(local_rounds),
+            remote: new_dag(remote_rounds),
+            authors,
+            nodes,
+        }
+    }
+
+    fn node(&self, round: Round, index: usize) -> &CertifiedNode {
+        &self.nodes[round as usize - 1][index]
+    }
+
+    /// A tracker where every peer claims the whole remote DAG.
+    fn peer_tracker(
+        &self,
+        time_service: Arc<dyn TimeService>,
+        peers: &[Author],
+    ) -> Arc<Mutex<DagPeerTracker>> {
+        let validator_index = self.local.read().validator_index().clone();
+        let mut peer_tracker =
+            DagPeerTracker::new(validator_index, time_service, Duration::from_secs(60));
+        for peer in peers {
+            peer_tracker.update(*peer, 1, self.remote.read().bitmask());
+        }
+        Arc::new(Mutex::new(peer_tracker))
+    }
+
+    fn verifier(&self) -> Arc<dyn CertifiedNodeVerifier> {
+        Arc::new(GenuineNodes(
+            self.nodes
+                .iter()
+                .flatten()
+                .map(|node| node.digest())
+                .collect(),
+        ))
+    }
+
+    fn request(&self, target: &CertifiedNode) -> CatchUpRequest {
+        CatchUpRequest {
+            target: target.clone(),
+            plan: self.local.read().fetch_plan_for(target),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_catch_up_closes_gap_with_mixed_peers() {
+    let setup = CatchUpSetup::new(5, 35, 36);
+    // the peers are ranked by address when they claim as much, the good one comes last
+    let mut peers = setup.authors[1..].to_vec();
+    peers.sort();
+    let (slow, lying, good) = (peers[0], peers[1], peers[2]);
+    let network = Arc::new(MockPeers {
+        remote: setup.remote.clone(),
+        local: setup.local.clone(),
+        behaviors: HashMap::from([
+            (slow, Behavior::Slow),
+            (lying, Behavior::Lying),
+            (good, Behavior::Good),
+        ]),
+        requests: Mutex::new(vec![]),
+        in_flight: AtomicUsize::new(0),
+        max_in_flight: AtomicUsize::new(0),
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let (mut catch_up, request_tx) = DagCatchUp::new(
+        setup.local.clone(),
+        network.clone(),
+        setup.verifier(),
+        setup.peer_tracker(time_service.clone(), &peers),
+        FetchBudget::new(4, 40),
+        time_service,
+        DagCatchUpConfig {
+            request_timeout: Duration::from_millis(10),
+            max_rounds_per_request: 5,
+            ..DagCatchUpConfig::default()
+        },
+    );
+    // the node of round 36 is rejected for being 31 rounds above the highest round
+    let target = setup.node(36, 0).clone();
+    assert!(request_tx.try_send(setup.request(&target)).is_ok());
+
+    assert_eq!(catch_up.catch_up().await, CatchUpOutcome::CaughtUp {
+        num_inserted: 30 * 4
+    });
+    let local = setup.local.read();
+    assert!(local.exists(&target.digest()));
+    for round in 1..=35 {
+        for (index, author) in setup.authors.iter().enumerate() {
+            assert_eq!(
+                local
+                    .get_node_by_round_author(round, author)
+                    .unwrap()
+                    .digest(),
+                setup.node(round, index).digest()
+            );
+        }
+    }
+    assert_eq!(local.catch_up_progress(), None);
+
+    // the first wave tries the slow and the lying peers for each of its 4 requests, then only the
+    // good one is asked, a wave of 5 rounds at a time
+    assert_eq!(network.max_in_flight.load(Ordering::SeqCst), 4);
+    assert_eq!(network.num_requests(&slow), 4);
+    assert_eq!(network.num_requests(&lying), 4);
+    assert_eq!(network.num_requests(&good), 24);
+    let mut remaining: Vec<_> = network
+        .requests
+        .lock()
+        .iter()
+        .filter(|(peer, _)| *peer == good)
+        .map(|(_, progress)| progress.unwrap())
+        .collect();
+    assert!(remaining[0].eta.is_none());
+    assert!(remaining[23].eta.is_some());
+    remaining.dedup_by_key(|progress| progress.slots_remaining);
+    assert_eq!(
+        remaining
+            .iter()
+            .map(|progress| progress.slots_remaining)
+            .collect::<Vec<_>>(),
+        vec![120, 100, 80, 60, 40, 20]
+    );
+}
+
+#[tokio::test]
+async fn test_catch_up_backs_off_until_epoch_ends() {
+    let setup = CatchUpSetup::new(5, 9, 10);
+    let network = Arc::new(UnreachablePeers {
+        dag: setup.local.clone(),
+        num_requests: AtomicUsize::new(0),
+        end_after: 4,
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let (mut catch_up, request_tx) = DagCatchUp::new(
+        setup.local.clone(),
+        network.clone(),
+        setup.verifier(),
+        setup.peer_tracker(time_service.clone(), &setup.authors[1..2]),
+        FetchBudget::new(1, 100),
+        time_service.clone(),
+        DagCatchUpConfig {
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..DagCatchUpConfig::default()
+        },
+    );
+    assert!(request_tx
+        .try_send(setup.request(setup.node(10, 0)))
+        .is_ok());
+
+    // one request per wave, each failed wave doubles the backoff up to the cap
+    assert_eq!(catch_up.catch_up().await, CatchUpOutcome::EpochEnded);
+    assert_eq!(network.num_requests.load(Ordering::SeqCst), 4);
+    assert_eq!(
+        time_service.get_current_timestamp(),
+        Duration::from_millis(100 + 200 + 400 + 500)
+    );
+    assert_eq!(setup.local.read().catch_up_progress(), None);
+    assert_eq!(setup.local.read().highest_round(), 5);
+}
+
+#[tokio::test]
+async fn test_catch_up_task_stops() {
+    let setup = CatchUpSetup::new(5, 9, 10);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let new_catch_up = || {
+        DagCatchUp::new(
+            setup.local.clone(),
+            Arc::new(UnreachablePeers {
+                dag: setup.local.clone(),
+                num_requests: AtomicUsize::new(0),
+                end_after: 0,
+            }),
+            setup.verifier(),
+            setup.peer_tracker(time_service.clone(), &setup.authors[1..]),
+            FetchBudget::new(1, 100),
+            time_service.clone(),
+            DagCatchUpConfig::default(),
+        )
+    };
+
+    // the handler sending the requests is gone
+    let (catch_up, request_tx) = new_catch_up();
+    drop(request_tx);
+    catch_up.run().await;
+
+    // the epoch ended
+    let (catch_up, request_tx) = new_catch_up();
+    setup.local.write().finalize();
+    assert!(request_tx
+        .try_send(setup.request(setup.node(10, 0)))
+        .is_ok());
+    catch_up.run().await;
+    assert_eq!(setup.local.read().highest_round(), 5);
+}
@@ -0,0 +1,341 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::{
+            AckToken, CatchUpProgress, CommitConfirmation, Dag, DagStoreError, DagStoreMode,
+            DeferredAckHandler, EvidenceRetention, InsertOutcome, ObserverMode, OrderedBatch,
+            OrderedChunk, ResetReport, TraversalBudget,
+        },
+        pruned_filter::PrunedDigestFilter,
+        pruning_policy::DagPruningPolicy,
+        replay::ReplayLogStats,
+        slot_id::SlotId,
+        storage::DAGStorage,
+        store_config::DagStoreConfig,
+        types::{CertifiedNode, EpochRemnant, NodeMetadata, SkipCertificate},
+    },
+    util::time_service::TimeService,
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    chain_id::ChainId,
+    epoch_state::EpochState,
+    ledger_info::{LedgerInfo, LedgerInfoWithSignatures},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    ops::{ControlFlow, Deref},
+    sync::Arc,
+};
+
+/// A `Dag` that runs `Dag::verify_consistency` after every mutating call and panics with the
+/// violations, instead of the tests only checking the result at the end. The reads are forwarded
+/// to the `Dag` through `Deref`, the mutations are only reachable through the checked calls.
+pub(crate) struct CheckedDag {
+    dag: Dag,
+}
+
+impl CheckedDag {
+    pub fn new(epoch_state: Arc<EpochState>, storage: Arc<dyn DAGStorage>) -> Self {
+        Self::wrap(Dag::new(epoch_state, storage))
+    }
+
+    pub fn new_with_time_service(
+        epoch_state: Arc<EpochState>,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+    ) -> Self {
+        Self::wrap(Dag::new_with_time_service(
+            epoch_state,
+            storage,
+            time_service,
+        ))
+    }
+
+    pub fn new_with_mode(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+    ) -> Result<Self, DagStoreError> {
+        Dag::new_with_mode(epoch_state, chain_id, storage, time_service, mode).map(Self::wrap)
+    }
+
+    pub fn new_observer(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        observer: ObserverMode,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        Dag::new_observer(
+            epoch_state,
+            chain_id,
+            storage,
+            time_service,
+            mode,
+            observer,
+            config,
+        )
+        .map(Self::wrap)
+    }
+
+    pub fn try_new(
+        epoch_state: Arc<EpochState>,
+        chain_id: ChainId,
+        storage: Arc<dyn DAGStorage>,
+        time_service: Arc<dyn TimeService>,
+        mode: DagStoreMode,
+        config: DagStoreConfig,
+    ) -> Result<Self, DagStoreError> {
+        Dag::try_new(epoch_state, chain_id, storage, time_service, mode, config).map(Self::wrap)
+    }
+
+    /// Checks the recovered DAG right away.
+    pub fn wrap(dag: Dag) -> Self {
+        let checked = Self { dag };
+        checked.check("recovery");
+        checked
+    }
+
+    pub fn into_inner(self) -> Dag {
+        self.dag
+    }
+
+    fn check(&self, call: &str) {
+        if let Err(violations) = self.dag.verify_consistency() {
+            panic!(
+                "DAG inconsistent after {}:\n{}",
+                call,
+                violations.join("\n")
+            );
+        }
+    }
+
+    fn checked<T>(&mut self, call: &str, f: impl FnOnce(&mut Dag) -> T) -> T {
+        let result = f(&mut self.dag);
+        self.check(call);
+        result
+    }
+
+    pub fn add_node(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        self.checked("add_node", |dag| dag.add_node(node))
+    }
+
+    pub fn add_nodes(&mut self, nodes: Vec<CertifiedNode>) -> Vec<Result<(), DagStoreError>> {
+        self.checked("add_nodes", |dag| dag.add_nodes(nodes))
+    }
+
+    pub fn add_validated_node(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        self.checked("add_validated_node", |dag| dag.add_validated_node(node))
+    }
+
+    pub fn add_node_or_buffer(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        self.checked("add_node_or_buffer", |dag| dag.add_node_or_buffer(node))
+    }
+
+    pub fn insert_node(&mut self, node: CertifiedNode) -> InsertOutcome {
+        self.checked("insert_node", |dag| dag.insert_node(node))
+    }
+
+    pub fn insert_node_with_ack(&mut self, node: CertifiedNode, token: AckToken) -> InsertOutcome {
+        self.checked("insert_node_with_ack", |dag| {
+            dag.insert_node_with_ack(node, token)
+        })
+    }
+
+    pub fn link_inserted(&mut self) -> HashMap<HashValue, DagStoreError> {
+        self.checked("link_inserted", |dag| dag.link_inserted())
+    }
+
+    pub fn mark_round_skipped(
+        &mut self,
+        round: Round,
+        certificate: SkipCertificate,
+    ) -> Result<(), DagStoreError> {
+        self.checked("mark_round_skipped", |dag| {
+            dag.mark_round_skipped(round, certificate)
+        })
+    }
+
+    pub fn prune_below(&mut self, round: Round) -> Result<usize, DagStoreError> {
+        self.checked("prune_below", |dag| dag.prune_below(round))
+    }
+
+    pub fn commit_callback(&mut self, committed_round: Round) -> Result<usize, DagStoreError> {
+        self.checked("commit_callback", |dag| {
+            dag.commit_callback(committed_round)
+        })
+    }
+
+    pub fn force_reset(
+        &mut self,
+        to_committed_round: Round,
+        latest_ledger_info: &LedgerInfo,
+    ) -> anyhow::Result<ResetReport> {
+        self.checked("force_reset", |dag| {
+            dag.force_reset(to_committed_round, latest_ledger_info)
+        })
+    }
+
+    pub fn order_anchor(
+        &mut self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+        budget: TraversalBudget,
+    ) -> Result<OrderedBatch, DagStoreError> {
+        self.checked("order_anchor", |dag| {
+            dag.order_anchor(anchor, previous, budget)
+        })
+    }
+
+    pub fn order_anchor_streamed(
+        &mut self,
+        anchor: &NodeMetadata,
+        previous: Option<&NodeMetadata>,
+        chunk_size: usize,
+        sink: impl FnMut(OrderedChunk) -> ControlFlow<()>,
+    ) -> Result<ControlFlow<()>, DagStoreError> {
+        self.checked("order_anchor_streamed", |dag| {
+            dag.order_anchor_streamed(anchor, previous, chunk_size, sink)
+        })
+    }
+
+    pub fn exclude_equivocator(
+        &mut self,
+        author: &Author,
+        round: Round,
+    ) -> Result<bool, DagStoreError> {
+        self.checked("exclude_equivocator", |dag| {
+            dag.exclude_equivocator(author, round)
+        })
+    }
+
+    pub fn clear_equivocator(&mut self, author: &Author) -> Result<bool, DagStoreError> {
+        self.checked("clear_equivocator", |dag| dag.clear_equivocator(author))
+    }
+
+    pub fn set_denied_authors(
+        &mut self,
+        authors: HashSet<Author>,
+        denied_after: Round,
+    ) -> Result<(), DagStoreError> {
+        self.checked("set_denied_authors", |dag| {
+            dag.set_denied_authors(authors, denied_after)
+        })
+    }
+
+    pub fn finalize_epoch(&mut self) -> Result<EpochRemnant, DagStoreError> {
+        self.checked("finalize_epoch", |dag| dag.finalize_epoch())
+    }
+
+    pub fn notify_commit_confirmed(
+        &mut self,
+        anchor_round: Round,
+        ledger_info: &LedgerInfoWithSignatures,
+    ) -> Result<CommitConfirmation, DagStoreError> {
+        self.checked("notify_commit_confirmed", |dag| {
+            dag.notify_commit_confirmed(anchor_round, ledger_info)
+        })
+    }
+
+    pub fn replace_epoch_state(
+        &mut self,
+        epoch_state: Arc<EpochState>,
+    ) -> Result<(), DagStoreError> {
+        self.checked("replace_epoch_state", |dag| {
+            dag.replace_epoch_state(epoch_state)
+        })
+    }
+
+    pub fn set_epoch_start_round(&mut self, round: Round) -> Result<(), DagStoreError> {
+        self.checked("set_epoch_start_round", |dag| {
+            dag.set_epoch_start_round(round)
+        })
+    }
+
+    pub fn set_evidence_pending_report(
+        &mut self,
+        digest: &HashValue,
+        pending_report: bool,
+    ) -> Result<bool, DagStoreError> {
+        self.checked("set_evidence_pending_report", |dag| {
+            dag.set_evidence_pending_report(digest, pending_report)
+        })
+    }
+
+    pub fn set_catch_up_progress(&mut self, progress: Option<CatchUpProgress>) {
+        self.checked("set_catch_up_progress", |dag| {
+            dag.set_catch_up_progress(progress)
+        })
+    }
+
+    pub fn set_memory_budget(&mut self, memory_budget: usize) {
+        self.checked("set_memory_budget", |dag| {
+            dag.set_memory_budget(memory_budget)
+        })
+    }
+
+    pub fn set_park_gap(&mut self, park_gap: Round) {
+        self.checked("set_park_gap", |dag| dag.set_park_gap(park_gap))
+    }
+
+    pub fn set_skew_threshold(&mut self, skew_threshold: Round) {
+        self.checked("set_skew_threshold", |dag| {
+            dag.set_skew_threshold(skew_threshold)
+        })
+    }
+
+    pub fn set_pruning_policy(&mut self, pruning_policy: Arc<dyn DagPruningPolicy>) {
+        self.checked("set_pruning_policy", |dag| {
+            dag.set_pruning_policy(pruning_policy)
+        })
+    }
+
+    pub fn set_evidence_retention(&mut self, evidence_retention: EvidenceRetention) {
+        self.checked("set_evidence_retention", |dag| {
+            dag.set_evidence_retention(evidence_retention)
+        })
+    }
+
+    pub fn set_pruned_digest_filter(&mut self, pruned_digests: PrunedDigestFilter) {
+        self.checked("set_pruned_digest_filter", |dag| {
+            dag.set_pruned_digest_filter(pruned_digests)
+        })
+    }
+
+    pub fn set_deferred_ack_handler(&mut self, handler: Arc<dyn DeferredAckHandler>) {
+        self.checked("set_deferred_ack_handler", |dag| {
+            dag.set_deferred_ack_handler(handler)
+        })
+    }
+
+    pub fn close_replay_log(&mut self) -> Option<io::Result<ReplayLogStats>> {
+        self.checked("close_replay_log", |dag| dag.close_replay_log())
+    }
+
+    /// Not checked, it's the corruption the checks have to catch on the next call.
+    pub fn insert_stale_reference(&mut self, metadata: &NodeMetadata) {
+        self.dag.insert_stale_reference(metadata)
+    }
+
+    /// Not checked either, see `insert_stale_reference`.
+    pub fn insert_stale_child(&mut self, digest: HashValue, slot: SlotId) {
+        self.dag.insert_stale_child(digest, slot)
+    }
+}
+
+impl Deref for CheckedDag {
+    type Target = Dag;
+
+    fn deref(&self) -> &Dag {
+        &self.dag
+    }
+}
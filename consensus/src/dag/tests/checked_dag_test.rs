@@ -0,0 +1,108 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, Node},
+};
+use aptos_consensus_types::common::{Author, Payload};
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use std::{collections::HashSet, sync::Arc};
+
+fn new_dag() -> (TestDag, Vec<Author>) {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    (
+        TestDag::new(epoch_state, Arc::new(MockStorage::new())),
+        authors,
+    )
+}
+
+#[test]
+#[should_panic(expected = "DAG inconsistent after add_node:\nstale reference to")]
+fn test_checked_dag_catches_stale_reference() {
+    let (mut dag, authors) = new_dag();
+    let round_1: Vec<_> = authors[0..3]
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    for node in &round_1 {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    // what a pending node dropped without unreferencing its parents would leave
+    let never_linked = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            authors[3],
+            0,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    dag.insert_stale_reference(never_linked.metadata());
+
+    let parents = round_1.iter().map(|node| node.certificate()).collect();
+    let _ = dag.add_node(new_certified_node(2, authors[0], parents));
+}
+
+#[test]
+#[should_panic(expected = "DAG inconsistent after add_node:\nstale children")]
+fn test_checked_dag_catches_stale_child() {
+    let (mut dag, authors) = new_dag();
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    for node in &round_1[0..3] {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    // what removing a node without unlinking it from its parents would leave
+    let removed_child = dag.slot_of(2, &authors[1]).unwrap();
+    dag.insert_stale_child(round_1[0].digest(), removed_child);
+
+    let _ = dag.add_node(round_1[3].clone());
+}
+
+#[test]
+fn test_checked_dag_pending_node_dropped_at_promotion() {
+    let (mut dag, authors) = new_dag();
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    for node in &round_1[0..3] {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parents: Vec<_> = round_1.iter().map(|node| node.certificate()).collect();
+    let pending = new_certified_node(2, authors[3], parents);
+    assert!(dag.add_node_or_buffer(pending).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 1);
+    assert_eq!(
+        dag.expected_digest(round_1[3].metadata()),
+        Some(round_1[3].digest())
+    );
+
+    // denied in the meantime, its node is dropped once its parents are in and its references
+    // with it
+    assert!(dag
+        .set_denied_authors(HashSet::from([authors[3]]), 1)
+        .is_ok());
+    assert!(dag.add_node_or_buffer(round_1[3].clone()).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 0);
+    for node in &round_1 {
+        assert_eq!(dag.expected_digest(node.metadata()), None);
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        anchor_election::{AnchorElection, RoundRobinAnchorElection},
+        dag_store::{
+            AnchorAttempt, AnchorCommitLatency, CommitLatencyAverage, DagHealth, DagHealthConfig,
+            DagHealthIssue, COMMIT_LATENCY_WINDOW,
+        },
+        skip_round_tracker::SkipRoundTracker,
+        tests::{
+            dag_test::MockStorage,
+            helpers::{new_certified_node, TestDag},
+        },
+        types::{SkipRound, SkipVote},
+    },
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
+};
+use aptos_consensus_types::common::Round;
+use aptos_types::{
+    epoch_state::EpochState, validator_signer::ValidatorSigner,
+    validator_verifier::random_validator_verifier,
+};
+use std::sync::Arc;
+
+fn new_epoch_state() -> (Vec<ValidatorSigner>, Arc<EpochState>) {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    (signers, epoch_state)
+}
+
+/// Orders the anchor of `anchor_round` once the DAG has every node up to `highest_round`, each
+/// linking to the whole previous round, and returns the latency it recorded.
+fn order(
+    dag: &mut TestDag,
+    anchor_election: &dyn AnchorElection,
+    anchor_round: Round,
+    highest_round: Round,
+) -> AnchorCommitLatency {
+    let authors = dag.epoch_state().verifier.get_ordered_account_addresses();
+    for round in dag.highest_round() + 1..=highest_round {
+        let parents = dag.get_certificates_for_round(round - 1);
+        for author in &authors {
+            assert!(dag
+                .add_node(new_certified_node(round, *author, parents.clone()))
+                .is_ok());
+        }
+    }
+    let anchor = dag
+        .get_node_by_round_author(anchor_round, &anchor_election.get_anchor(anchor_round))
+        .unwrap()
+        .metadata()
+        .clone();
+    let budget = dag.traversal_budget();
+    assert!(dag.order_anchor(&anchor, None, budget).is_ok());
+    *dag.commit_latencies().last().unwrap()
+}
+
+#[test]
+fn test_commit_latency_sequence() {
+    let (signers, epoch_state) = new_epoch_state();
+    let storage = Arc::new(MockStorage::new());
+    let anchor_election = Arc::new(RoundRobinAnchorElection::new(
+        epoch_state.verifier.get_ordered_account_addresses(),
+    ));
+    let mut tracker = SkipRoundTracker::new(
+        epoch_state.clone(),
+        anchor_election.clone(),
+        storage.clone(),
+    )
+    .unwrap();
+    let mut dag = TestDag::new(epoch_state, storage);
+    assert_eq!(
+        dag.summary().commit_latency,
+        CommitLatencyAverage::default()
+    );
+    assert_eq!(dag.commit_latency().average(), None);
+
+    // committed by the links of the next round
+    let latency = order(&mut dag, anchor_election.as_ref(), 1, 2);
+    assert_eq!(latency, AnchorCommitLatency {
+        anchor_round: 1,
+        committing_round: 2,
+        attempt: AnchorAttempt::First,
+    });
+    assert_eq!(latency.rounds(), 1);
+
+    // the anchor of round 3 lacks links and is committed in the history of the next anchor, one
+    // anchor round later
+    assert_eq!(order(&mut dag, anchor_election.as_ref(), 3, 6).rounds(), 3);
+    assert_eq!(order(&mut dag, anchor_election.as_ref(), 5, 6).rounds(), 1);
+
+    // the anchor of round 7 is skipped, the next one is a fallback, the one after it isn't
+    let skip = SkipRound::new(1, 7, anchor_election.get_anchor(7));
+    let certificate = signers[0..3]
+        .iter()
+        .filter_map(|signer| {
+            tracker
+                .add_vote(SkipVote::new(skip.clone(), signer).unwrap())
+                .unwrap()
+        })
+        .next()
+        .unwrap();
+    assert!(dag.mark_round_skipped(7, certificate).is_ok());
+    let latency = order(&mut dag, anchor_election.as_ref(), 9, 10);
+    assert_eq!(latency.attempt, AnchorAttempt::Fallback);
+    assert_eq!(latency.rounds(), 1);
+    let latency = order(&mut dag, anchor_election.as_ref(), 11, 12);
+    assert_eq!(latency.attempt, AnchorAttempt::First);
+
+    let expected = CommitLatencyAverage {
+        total_rounds: 7,
+        num_anchors: 5,
+    };
+    assert_eq!(dag.summary().commit_latency, expected);
+    assert_eq!(expected.average(), Some(1.4));
+    assert_eq!(
+        dag.commit_latencies()
+            .iter()
+            .map(|latency| latency.anchor_round)
+            .collect::<Vec<_>>(),
+        vec![1, 3, 5, 9, 11]
+    );
+}
+
+#[test]
+fn test_sustained_commit_latency_degrades_health() {
+    let (_, epoch_state) = new_epoch_state();
+    let anchor_election =
+        RoundRobinAnchorElection::new(epoch_state.verifier.get_ordered_account_addresses());
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    let now = time_service.get_current_timestamp();
+    let config = DagHealthConfig {
+        max_commit_latency_rounds: 2,
+        ..DagHealthConfig::default()
+    };
+
+    // three rounds per anchor, but not for a full window yet
+    for index in 0..COMMIT_LATENCY_WINDOW as Round - 1 {
+        order(&mut dag, &anchor_election, 2 * index + 1, 2 * index + 4);
+    }
+    assert_eq!(dag.health(now, &config), DagHealth::Healthy);
+
+    let round = 2 * COMMIT_LATENCY_WINDOW as Round - 1;
+    order(&mut dag, &anchor_election, round, round + 3);
+    let latency = CommitLatencyAverage {
+        total_rounds: 3 * COMMIT_LATENCY_WINDOW as u64,
+        num_anchors: COMMIT_LATENCY_WINDOW,
+    };
+    assert_eq!(
+        dag.health(now, &config),
+        DagHealth::Degraded(vec![DagHealthIssue::CommitLatencyHigh { latency }])
+    );
+
+    // the slow anchors leave the window as fast ones are committed
+    for index in 0..COMMIT_LATENCY_WINDOW as Round / 2 {
+        let round = 2 * (COMMIT_LATENCY_WINDOW as Round + index) + 1;
+        order(&mut dag, &anchor_election, round, round + 1);
+    }
+    assert_eq!(dag.summary().commit_latency, CommitLatencyAverage {
+        total_rounds: 2 * COMMIT_LATENCY_WINDOW as u64,
+        num_anchors: COMMIT_LATENCY_WINDOW,
+    });
+    assert_eq!(dag.health(now, &config), DagHealth::Healthy);
+}
@@ -0,0 +1,113 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        anchor_election::RoundRobinAnchorElection,
+        dag_driver::DagDriver,
+        dag_network::DAGNetworkSender,
+        dag_store::Dag,
+        reliable_broadcast::ReliableBroadcast,
+        tests::{dag_test::MockStorage, helpers::new_certified_node},
+        types::{DAGMessage, Node},
+    },
+    network_interface::ConsensusMsg,
+    test_utils::MockPayloadManager,
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::Author;
+use aptos_infallible::RwLock;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+/// Forwards the broadcast nodes and never answers, the broadcasts stay in flight.
+struct NodeRecorder {
+    nodes_tx: mpsc::UnboundedSender<Node>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for NodeRecorder {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        if let DAGMessage::NodeMsg(node) = DAGMessage::try_from(message)? {
+            let _ = self.nodes_tx.send(node);
+        }
+        futures::future::pending().await
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<(Author, ConsensusMsg)> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_driver_broadcasts_reserved_node_after_restart() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let (nodes_tx, mut nodes_rx) = mpsc::unbounded_channel();
+    let reliable_broadcast = Arc::new(ReliableBroadcast::new(
+        authors.clone(),
+        Arc::new(NodeRecorder { nodes_tx }),
+    ));
+    let start_driver = |dag: Arc<RwLock<Dag>>| {
+        DagDriver::new(
+            authors[0],
+            epoch_state.clone(),
+            dag,
+            Arc::new(MockPayloadManager::new(None)),
+            reliable_broadcast.clone(),
+            Arc::new(RoundRobinAnchorElection::new(authors.clone())),
+            1,
+            time_service.clone(),
+        )
+    };
+
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage.clone())));
+    for author in &authors {
+        dag.write()
+            .add_node(new_certified_node(1, *author, vec![]))
+            .unwrap();
+    }
+    let mut driver = start_driver(dag.clone());
+    let frontier = dag.read().frontier().unwrap();
+    driver.enter_new_round(frontier.clone());
+    let broadcast = nodes_rx.recv().await.unwrap();
+    assert_eq!(broadcast.metadata().round(), 2);
+    // the whole round fits, it's linked beyond the quorum
+    assert_eq!(broadcast.parents(), frontier.strong_links.as_slice());
+    assert_eq!(
+        dag.read().self_reservation(2).map(Node::digest),
+        Some(broadcast.digest())
+    );
+
+    // the validator crashes before any copy of the node is persisted, after the restart a node
+    // built again would have a later timestamp
+    drop(driver);
+    drop(dag);
+    while nodes_rx.try_recv().is_ok() {}
+    time_service.advance(Duration::from_secs(1));
+
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage)));
+    assert!(dag.read().get_node(&broadcast.digest()).is_none());
+    let mut driver = start_driver(dag.clone());
+    driver.enter_new_round(frontier);
+    let rebroadcast = nodes_rx.recv().await.unwrap();
+    assert_eq!(rebroadcast, broadcast);
+}
@@ -0,0 +1,186 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_fetcher::{
+        check_fetched_nodes, held_digests, CertifiedNodeVerifier, FetchedNodeError,
+        LocalFetchRequest,
+    },
+    dag_store::Dag,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, Node},
+};
+use aptos_consensus_types::common::{Author, Payload};
+use aptos_types::{
+    aggregate_signature::AggregateSignature, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::oneshot;
+
+/// Accepts every node and counts the calls.
+#[derive(Default)]
+struct CountingVerifier {
+    num_verified: AtomicUsize,
+}
+
+impl CertifiedNodeVerifier for CountingVerifier {
+    fn verify_certified_node(&self, _node: &CertifiedNode) -> anyhow::Result<()> {
+        self.num_verified.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_check_fetched_nodes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors: Vec<Author> = signers.iter().map(|signer| signer.author()).collect();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    for node in &round_1 {
+        dag.add_node(node.clone()).unwrap();
+    }
+    let parents: Vec<_> = round_1.iter().map(|node| node.certificate()).collect();
+    let round_2: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(2, *author, parents.clone()))
+        .collect();
+    // the child certifies the round 2 nodes of the first three authors
+    let child = new_certified_node(
+        3,
+        authors[0],
+        round_2[0..3]
+            .iter()
+            .map(|node| node.certificate())
+            .collect(),
+    );
+    dag.add_node_or_buffer(child.clone()).unwrap();
+    assert_eq!(dag.pending_nodes_count(), 1);
+    for node in &round_2[0..3] {
+        assert_eq!(dag.expected_digest(node.metadata()), Some(node.digest()));
+    }
+    assert_eq!(dag.expected_digest(round_2[3].metadata()), None);
+
+    // claims the certified digest with other parents
+    let tampered = CertifiedNode::new(
+        Node::new_for_test(
+            round_2[1].metadata().clone(),
+            Payload::empty(false),
+            parents[0..3].to_vec(),
+        ),
+        AggregateSignature::empty(),
+    );
+    let held: HashMap<_, _> = round_2
+        .iter()
+        .filter_map(|node| {
+            dag.expected_digest(node.metadata())
+                .map(|digest| ((2, *node.author()), digest))
+        })
+        .collect();
+    let peer = authors[3];
+    let verifier = CountingVerifier::default();
+    let (accepted, rejected) = check_fetched_nodes(
+        held,
+        peer,
+        vec![
+            round_2[0].clone(),
+            tampered,
+            round_2[2].clone(),
+            round_2[3].clone(),
+        ],
+        &verifier,
+    );
+
+    // only the node no certificate vouches for is verified
+    assert_eq!(verifier.num_verified.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        accepted
+            .iter()
+            .map(|node| node.digest())
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            round_2[0].digest(),
+            round_2[2].digest(),
+            round_2[3].digest()
+        ])
+    );
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].peer(), &peer);
+    assert!(matches!(
+        &rejected[0],
+        FetchedNodeError::DigestMismatch { round: 2, author, expected, .. }
+            if *author == authors[1] && *expected == round_2[1].digest()
+    ));
+
+    for node in accepted {
+        dag.add_validated_node(node).unwrap();
+    }
+    assert!(!dag.exists(&child.digest()));
+    dag.add_validated_node(round_2[1].clone()).unwrap();
+    assert!(dag.exists(&child.digest()));
+    assert_eq!(dag.pending_nodes_count(), 0);
+
+    dag.prune_below(3).unwrap();
+    for node in &round_2 {
+        assert_eq!(dag.expected_digest(node.metadata()), None);
+    }
+}
+
+#[test]
+fn test_held_digests_only_from_verified_certificates() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors: Vec<Author> = signers.iter().map(|signer| signer.author()).collect();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let dag = Dag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    let parents: Vec<_> = round_1.iter().map(|node| node.certificate()).collect();
+    let child = new_certified_node(2, authors[0], parents);
+
+    // the parents of an uncertified node are only claimed by its author
+    let (sender, _receiver) = oneshot::channel();
+    let request = LocalFetchRequest::Node((*child).clone(), sender);
+    assert!(held_digests(&dag, &request, &round_1).is_empty());
+
+    let (sender, mut receiver) = oneshot::channel();
+    let request = LocalFetchRequest::CertifiedNode(child, sender);
+    let held = held_digests(&dag, &request, &round_1);
+    assert_eq!(held.len(), round_1.len());
+    for node in &round_1 {
+        assert_eq!(held.get(&(1, *node.author())), Some(&node.digest()));
+    }
+
+    // the requester learns which peer sent the rejected nodes
+    let peer = authors[3];
+    request.reject(vec![FetchedNodeError::Unverified {
+        peer,
+        digest: round_1[1].digest(),
+        error: "invalid signature".to_string(),
+    }]);
+    let rejected = receiver.try_recv().unwrap().unwrap_err();
+    assert_eq!(rejected.rejected().len(), 1);
+    assert_eq!(rejected.rejected()[0].peer(), &peer);
+}
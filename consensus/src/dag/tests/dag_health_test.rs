@@ -0,0 +1,347 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_health::{DagHealthMonitor, DagHealthTransition},
+        dag_store::{
+            CatchUpProgress, DagHealth, DagHealthConfig, DagHealthIssue, DagStoreMode, ObserverMode,
+        },
+        epoch_dag_manager::EpochDagManager,
+        store_config::DagStoreConfig,
+        tests::{
+            dag_test::MockStorage,
+            helpers::{generate_dag_nodes, new_certified_node, new_epoch_state, TestDag},
+        },
+        types::CertifiedNode,
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::Author;
+use aptos_types::chain_id::ChainId;
+use std::{sync::Arc, time::Duration};
+
+const NUM_VALIDATORS: usize = 4;
+
+fn config() -> DagHealthConfig {
+    DagHealthConfig {
+        quorum_slow_after: Duration::from_secs(5),
+        quorum_stalled_after: Duration::from_secs(30),
+        max_pending_nodes: 2,
+        absent_rounds: 3,
+        max_absent_authors: 0,
+        max_commit_latency_rounds: 4,
+    }
+}
+
+/// `num_rounds` rounds where the last validator only has a node in round 1.
+fn rounds(authors: &[Author], num_rounds: usize) -> impl Iterator<Item = CertifiedNode> {
+    let links: Vec<_> = (0..num_rounds)
+        .map(|round| {
+            let parents: Vec<_> = match round {
+                0 | 1 => (0..NUM_VALIDATORS).collect(),
+                _ => (0..NUM_VALIDATORS - 1).collect(),
+            };
+            (0..NUM_VALIDATORS)
+                .map(|index| (round == 0 || index + 1 < NUM_VALIDATORS).then(|| parents.clone()))
+                .collect()
+        })
+        .collect();
+    generate_dag_nodes(&links, authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+}
+
+fn add_rounds(dag: &mut TestDag, authors: &[Author], num_rounds: usize) {
+    for node in rounds(authors, num_rounds) {
+        assert!(dag.add_node(node).is_ok());
+    }
+}
+
+#[test]
+fn test_dag_health_quorum_round_progress() {
+    let (epoch_state, authors) = new_epoch_state(NUM_VALIDATORS);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    let config = config();
+    assert_eq!(
+        dag.health(Duration::from_secs(4), &config),
+        DagHealth::Healthy
+    );
+    // measured from the creation before the first quorum round
+    assert_eq!(
+        dag.health(Duration::from_secs(5), &config),
+        DagHealth::Degraded(vec![DagHealthIssue::QuorumRoundStuck {
+            round: 0,
+            since: Duration::ZERO
+        }])
+    );
+
+    time_service.advance(Duration::from_secs(10));
+    add_rounds(&mut dag, &authors, 1);
+    assert_eq!(
+        dag.health(Duration::from_secs(14), &config),
+        DagHealth::Healthy
+    );
+    assert_eq!(
+        dag.health(Duration::from_secs(20), &config),
+        DagHealth::Degraded(vec![DagHealthIssue::QuorumRoundStuck {
+            round: 1,
+            since: Duration::from_secs(10)
+        }])
+    );
+    assert_eq!(
+        dag.health(Duration::from_secs(40), &config),
+        DagHealth::Stalled(DagHealthIssue::QuorumRoundStuck {
+            round: 1,
+            since: Duration::from_secs(10)
+        })
+    );
+}
+
+#[test]
+fn test_dag_health_issues() {
+    let (epoch_state, authors) = new_epoch_state(NUM_VALIDATORS);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state.clone(),
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    let config = config();
+    let now = Duration::ZERO;
+
+    // the last validator is absent once the quorum round is more than 3 rounds above its node
+    add_rounds(&mut dag, &authors, 4);
+    assert_eq!(dag.highest_quorum_round(), 4);
+    assert_eq!(dag.health(now, &config), DagHealth::Healthy);
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state.clone(),
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    add_rounds(&mut dag, &authors, 5);
+    let absent = DagHealthIssue::AbsentAuthors {
+        authors: vec![authors[3]],
+    };
+    assert_eq!(
+        dag.health(now, &config),
+        DagHealth::Degraded(vec![absent.clone()])
+    );
+    assert_eq!(
+        dag.health(now, &DagHealthConfig {
+            max_absent_authors: 1,
+            ..config
+        }),
+        DagHealth::Healthy
+    );
+
+    // the nodes of round 7 wait for their parent
+    let parents = dag.strong_links_for_round(5).unwrap();
+    let missing = new_certified_node(6, authors[0], parents);
+    for author in &authors[0..2] {
+        let node = new_certified_node(7, *author, vec![missing.certificate()]);
+        assert!(dag.add_node_or_buffer(node).is_ok());
+    }
+    assert_eq!(dag.pending_nodes_count(), 2);
+    let pending = DagHealthIssue::PendingBufferFull { num_pending: 2 };
+    assert_eq!(
+        dag.health(now, &config),
+        DagHealth::Degraded(vec![pending.clone(), absent.clone()])
+    );
+
+    dag.set_memory_budget(0);
+    assert!(dag.backpressure());
+    assert_eq!(
+        dag.health(now, &config),
+        DagHealth::Degraded(vec![
+            DagHealthIssue::Backpressure,
+            pending.clone(),
+            absent.clone()
+        ])
+    );
+    assert_eq!(
+        dag.health(Duration::from_secs(5), &config),
+        DagHealth::Degraded(vec![
+            DagHealthIssue::QuorumRoundStuck {
+                round: 5,
+                since: Duration::ZERO
+            },
+            DagHealthIssue::Backpressure,
+            pending,
+            absent
+        ])
+    );
+    // a stall hides the other issues
+    assert_eq!(
+        dag.health(Duration::from_secs(30), &config),
+        DagHealth::Stalled(DagHealthIssue::QuorumRoundStuck {
+            round: 5,
+            since: Duration::ZERO
+        })
+    );
+
+    // observers go over the budget without backpressure
+    let mut observer = TestDag::new_observer(
+        epoch_state,
+        ChainId::test(),
+        Arc::new(MockStorage::new()),
+        time_service,
+        DagStoreMode::Strict,
+        ObserverMode::default(),
+        DagStoreConfig::default(),
+    )
+    .unwrap();
+    add_rounds(&mut observer, &authors, 1);
+    observer.set_memory_budget(0);
+    assert!(!observer.backpressure());
+    assert_eq!(
+        observer.health(now, &config),
+        DagHealth::Degraded(vec![DagHealthIssue::OverMemoryBudget {
+            total_bytes: observer.memory_usage().total_bytes(),
+            memory_budget: 0,
+        }])
+    );
+}
+
+#[test]
+fn test_dag_health_catching_up() {
+    let (epoch_state, authors) = new_epoch_state(NUM_VALIDATORS);
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        Arc::new(SimulatedTimeService::new()),
+    );
+    add_rounds(&mut dag, &authors, 1);
+    let config = config();
+    let now = Duration::ZERO;
+    let catching_up = DagHealth::Degraded(vec![DagHealthIssue::CatchingUp]);
+
+    // the verdict stays the same as the waves fill the slots
+    for (slots_remaining, eta) in [(8, None), (4, Some(Duration::from_secs(2)))] {
+        dag.set_catch_up_progress(Some(CatchUpProgress {
+            slots_remaining,
+            eta,
+        }));
+        assert_eq!(dag.health(now, &config), catching_up);
+    }
+    dag.set_catch_up_progress(None);
+    assert_eq!(dag.health(now, &config), DagHealth::Healthy);
+}
+
+#[test]
+fn test_dag_health_monitor_transitions() {
+    let (epoch_state, authors) = new_epoch_state(NUM_VALIDATORS);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let manager = Arc::new(
+        EpochDagManager::new(
+            epoch_state,
+            ChainId::test(),
+            Arc::new(MockStorage::new()),
+            time_service.clone(),
+            DagStoreMode::Strict,
+            DagStoreConfig::default(),
+        )
+        .unwrap(),
+    );
+    let monitor = DagHealthMonitor::new(manager.clone(), time_service.clone(), config());
+    let mut receiver = monitor.subscribe();
+    assert_eq!(monitor.check(), None);
+    assert_eq!(monitor.verdict(), DagHealth::Healthy);
+    assert!(!receiver.has_changed().unwrap());
+
+    time_service.advance(Duration::from_secs(5));
+    let stuck = DagHealthIssue::QuorumRoundStuck {
+        round: 0,
+        since: Duration::ZERO,
+    };
+    assert_eq!(
+        monitor.check(),
+        Some(DagHealthTransition {
+            epoch: 1,
+            from: DagHealth::Healthy,
+            to: DagHealth::Degraded(vec![stuck.clone()]),
+            at: Duration::from_secs(5),
+        })
+    );
+    assert_eq!(
+        *receiver.borrow_and_update(),
+        DagHealth::Degraded(vec![stuck.clone()])
+    );
+
+    // unchanged as long as the issue is
+    time_service.advance(Duration::from_secs(10));
+    assert_eq!(monitor.check(), None);
+    assert!(!receiver.has_changed().unwrap());
+    time_service.advance(Duration::from_secs(15));
+    assert_eq!(
+        monitor.check(),
+        Some(DagHealthTransition {
+            epoch: 1,
+            from: DagHealth::Degraded(vec![stuck.clone()]),
+            to: DagHealth::Stalled(stuck.clone()),
+            at: Duration::from_secs(30),
+        })
+    );
+    for node in rounds(&authors, 1) {
+        assert!(manager.current().write().add_node(node).is_ok());
+    }
+    assert_eq!(
+        monitor.check(),
+        Some(DagHealthTransition {
+            epoch: 1,
+            from: DagHealth::Stalled(stuck),
+            to: DagHealth::Healthy,
+            at: Duration::from_secs(30),
+        })
+    );
+    assert_eq!(monitor.check(), None);
+    assert!(receiver.borrow_and_update().is_healthy());
+}
+
+#[test]
+fn test_dag_health_monitor_ignores_issue_details() {
+    let (epoch_state, authors) = new_epoch_state(NUM_VALIDATORS);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let manager = Arc::new(
+        EpochDagManager::new(
+            epoch_state,
+            ChainId::test(),
+            Arc::new(MockStorage::new()),
+            time_service.clone(),
+            DagStoreMode::Strict,
+            DagStoreConfig::default(),
+        )
+        .unwrap(),
+    );
+    let monitor = DagHealthMonitor::new(manager.clone(), time_service, config());
+    let mut receiver = monitor.subscribe();
+    for node in rounds(&authors, 1) {
+        assert!(manager.current().write().add_node(node).is_ok());
+    }
+    let parents = manager.current().read().strong_links_for_round(1).unwrap();
+    let missing = new_certified_node(2, authors[0], parents);
+    let park = |author: Author| {
+        let node = new_certified_node(3, author, vec![missing.certificate()]);
+        assert!(manager.current().write().add_node_or_buffer(node).is_ok());
+    };
+    park(authors[0]);
+    park(authors[1]);
+    let to = DagHealth::Degraded(vec![DagHealthIssue::PendingBufferFull { num_pending: 2 }]);
+    assert_eq!(monitor.check().map(|transition| transition.to), Some(to));
+    receiver.borrow_and_update();
+
+    // one more pending node is the same issue, the verdict is refreshed without a transition
+    park(authors[2]);
+    assert_eq!(monitor.check(), None);
+    assert!(!receiver.has_changed().unwrap());
+    assert_eq!(
+        monitor.verdict(),
+        DagHealth::Degraded(vec![DagHealthIssue::PendingBufferFull { num_pending: 3 }])
+    );
+}
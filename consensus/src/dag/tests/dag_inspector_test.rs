@@ -0,0 +1,290 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_inspector::DagInspector,
+    storage::DAGStorage,
+    tests::{dag_test::MockStorage, helpers::new_certified_node},
+    types::{BatchSourceInfo, CertifiedNode, Node, NodeMetadata, OrderedAnchor},
+};
+use aptos_consensus_types::common::{Author, Payload};
+use aptos_crypto::HashValue;
+use aptos_types::{aggregate_signature::AggregateSignature, chain_id::ChainId};
+use serde_json::json;
+use std::collections::HashMap;
+
+struct SeededDag {
+    /// Sorted
+    authors: Vec<Author>,
+    round_1: Vec<CertifiedNode>,
+    /// The nodes of the first three authors, the second one is the ordered anchor
+    round_2: Vec<CertifiedNode>,
+    /// In round 4, its parent is missing
+    pending: CertifiedNode,
+}
+
+fn seed(storage: &MockStorage) -> SeededDag {
+    let mut authors: Vec<_> = (0..4).map(|_| Author::random()).collect();
+    authors.sort();
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    let parents: Vec<_> = round_1.iter().map(|node| node.certificate()).collect();
+    let round_2: Vec<_> = authors[0..3]
+        .iter()
+        .map(|author| new_certified_node(2, *author, parents.clone()))
+        .collect();
+    for node in round_1.iter().chain(&round_2) {
+        storage.save_certified_node(node).unwrap();
+    }
+    let anchor = &round_2[1];
+    let sources = round_1
+        .iter()
+        .chain(std::iter::once(anchor))
+        .enumerate()
+        .map(|(position, node)| BatchSourceInfo::new(node, position as u64))
+        .collect();
+    storage
+        .save_ordered_anchor(&OrderedAnchor::new(anchor.metadata().clone(), sources))
+        .unwrap();
+    let missing = new_certified_node(3, authors[0], vec![round_2[0].certificate()]);
+    let pending = new_certified_node(4, authors[0], vec![missing.certificate()]);
+    storage.save_pending_node(&pending).unwrap();
+    SeededDag {
+        authors,
+        round_1,
+        round_2,
+        pending,
+    }
+}
+
+#[test]
+fn test_inspect_summary() {
+    let storage = MockStorage::new();
+    let dag = seed(&storage);
+    let num_node_writes = storage.num_node_writes();
+
+    let inspector = DagInspector::load(&storage).unwrap();
+    assert_eq!(storage.num_node_writes(), num_node_writes);
+    assert_eq!(inspector.latest_epoch(), Some(1));
+    assert_eq!(
+        inspector.summary().to_string(),
+        "epoch 1: rounds 1 to 2, 7 nodes\n\
+         pending nodes: 1\n\
+         ordered anchors: 1\n\
+         queued deletions: 0\n\
+         epoch start round: none\n"
+    );
+
+    storage.save_epoch_start_round(1, 1).unwrap();
+    storage
+        .save_pending_deletions(&HashMap::from([(dag.round_1[3].digest(), 1)]))
+        .unwrap();
+    let summary = DagInspector::load(&storage).unwrap().summary();
+    assert_eq!(
+        summary.to_string(),
+        "epoch 1: rounds 1 to 2, 7 nodes\n\
+         pending nodes: 1\n\
+         ordered anchors: 1\n\
+         queued deletions: 1\n\
+         epoch start round: 1 in epoch 1\n"
+    );
+    assert_eq!(
+        serde_json::to_value(&summary).unwrap(),
+        json!({
+            "epochs": [{"epoch": 1, "lowest_round": 1, "highest_round": 2, "num_nodes": 7}],
+            "num_pending_nodes": 1,
+            "num_ordered_anchors": 1,
+            "num_queued_deletions": 1,
+            "epoch_start_round": {"epoch": 1, "round": 1},
+        })
+    );
+
+    assert_eq!(
+        DagInspector::load(&MockStorage::new())
+            .unwrap()
+            .summary()
+            .to_string(),
+        "no certified nodes\n\
+         pending nodes: 0\n\
+         ordered anchors: 0\n\
+         queued deletions: 0\n\
+         epoch start round: none\n"
+    );
+}
+
+#[test]
+fn test_inspect_round() {
+    let storage = MockStorage::new();
+    let dag = seed(&storage);
+    storage
+        .save_pending_deletions(&HashMap::from([(dag.round_1[3].digest(), 1)]))
+        .unwrap();
+    let inspector = DagInspector::load(&storage).unwrap();
+
+    let authors = &dag.authors;
+    assert_eq!(
+        inspector.round(1, 1).to_string(),
+        format!(
+            "epoch 1, round 1: 4 nodes\n\
+             {} {:x} ordered\n\
+             {} {:x} ordered\n\
+             {} {:x} ordered\n\
+             {} {:x} queued for deletion\n",
+            authors[0],
+            dag.round_1[0].digest(),
+            authors[1],
+            dag.round_1[1].digest(),
+            authors[2],
+            dag.round_1[2].digest(),
+            authors[3],
+            dag.round_1[3].digest(),
+        )
+    );
+    assert_eq!(
+        inspector.round(1, 2).to_string(),
+        format!(
+            "epoch 1, round 2: 3 nodes\n\
+             {} {:x} unordered\n\
+             {} {:x} ordered\n\
+             {} {:x} unordered\n",
+            authors[0],
+            dag.round_2[0].digest(),
+            authors[1],
+            dag.round_2[1].digest(),
+            authors[2],
+            dag.round_2[2].digest(),
+        )
+    );
+    assert_eq!(
+        inspector.round(1, 4).to_string(),
+        format!(
+            "epoch 1, round 4: 1 nodes\n{} {:x} pending\n",
+            authors[0],
+            dag.pending.digest()
+        )
+    );
+    assert_eq!(
+        inspector.round(2, 1).to_string(),
+        "epoch 2, round 1: 0 nodes\n"
+    );
+}
+
+#[test]
+fn test_inspect_node() {
+    let storage = MockStorage::new();
+    let dag = seed(&storage);
+    let inspector = DagInspector::load(&storage).unwrap();
+
+    let anchor = &dag.round_2[1];
+    let mut expected = format!(
+        "node {:x}\n\
+         chain id: {}\n\
+         epoch: 1\n\
+         round: 2\n\
+         author: {}\n\
+         timestamp: 0\n\
+         status: ordered\n\
+         payload: 0 transactions, 0 bytes\n\
+         signers: 0\n\
+         parents: 4\n",
+        anchor.digest(),
+        ChainId::test(),
+        dag.authors[1],
+    );
+    for parent in &dag.round_1 {
+        expected += &format!("  round 1 {} {:x}\n", parent.author(), parent.digest());
+    }
+    assert_eq!(
+        inspector.node(&anchor.digest()).unwrap().to_string(),
+        expected
+    );
+
+    let missing = dag.pending.parents()[0].metadata();
+    let report = inspector.node(&dag.pending.digest()).unwrap();
+    assert!(report.to_string().ends_with(&format!(
+        "status: pending\n\
+         payload: 0 transactions, 0 bytes\n\
+         signers: 0\n\
+         parents: 1\n  \
+         round 3 {} {:x} (not in the store)\n",
+        missing.author(),
+        missing.digest()
+    )));
+
+    assert!(inspector.node(&HashValue::random()).is_none());
+}
+
+#[test]
+fn test_inspect_check() {
+    let storage = MockStorage::new();
+    let dag = seed(&storage);
+    assert_eq!(
+        DagInspector::load(&storage).unwrap().check().to_string(),
+        "checked 8 nodes, no problems\n"
+    );
+
+    let authors = &dag.authors;
+    let parents: Vec<_> = dag.round_1.iter().map(|node| node.certificate()).collect();
+    let invalid_digest = CertifiedNode::new(
+        Node::new_for_test(
+            NodeMetadata::new_for_test(1, 2, authors[3], 0, HashValue::random()),
+            Payload::empty(false),
+            parents.clone(),
+        ),
+        AggregateSignature::empty(),
+    );
+    let equivocating = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            2,
+            authors[0],
+            1,
+            Payload::empty(false),
+            parents.clone(),
+        ),
+        AggregateSignature::empty(),
+    );
+    let missing = new_certified_node(2, authors[3], parents);
+    let mut round_3_parents: Vec<_> = dag.round_2.iter().map(|node| node.certificate()).collect();
+    round_3_parents.push(missing.certificate());
+    let missing_parent = new_certified_node(3, authors[1], round_3_parents);
+    for node in [&invalid_digest, &equivocating, &missing_parent] {
+        storage.save_certified_node(node).unwrap();
+    }
+    let orphan = new_certified_node(3, authors[2], vec![]);
+    storage
+        .save_ordered_anchor(&OrderedAnchor::new(orphan.metadata().clone(), vec![]))
+        .unwrap();
+    let (kept, dropped) = if dag.round_2[0].digest() < equivocating.digest() {
+        (&dag.round_2[0], &equivocating)
+    } else {
+        (&equivocating, &dag.round_2[0])
+    };
+
+    let report = DagInspector::load(&storage).unwrap().check();
+    assert!(!report.is_consistent());
+    assert_eq!(
+        report.to_string(),
+        format!(
+            "checked 11 nodes, 4 problems\n\
+             epoch 1 round 2 {} {:x}: equivocates with {}\n\
+             epoch 1 round 2 {} {:x}: invalid digest\n\
+             epoch 1 round 3 {} {:x}: parent {} not exist\n\
+             epoch 1 round 3 {} {:x}: anchor {} not exist\n",
+            authors[0],
+            dropped.digest(),
+            kept.digest(),
+            authors[3],
+            invalid_digest.digest(),
+            authors[1],
+            missing_parent.digest(),
+            missing.digest(),
+            authors[2],
+            orphan.digest(),
+            orphan.digest(),
+        )
+    );
+}
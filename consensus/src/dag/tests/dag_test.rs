@@ -1,20 +1,79 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::dag::{
-    dag_store::Dag,
-    storage::DAGStorage,
-    tests::helpers::new_certified_node,
-    types::{CertifiedNode, Node},
+use crate::{
+    dag::{
+        dag_admin::DagAdmin,
+        dag_driver::MAX_STRONG_LINKS_BYTES,
+        dag_fetcher::{AuthorFetchHandler, RemoteFetchHandler},
+        dag_network::RpcHandler,
+        dag_store::{
+            AckDecision, AckToken, AnchorBlockReport, AuditReport, CatchUpProgress,
+            CommitConfirmation, Dag, DagDiff, DagEpochSummary, DagStoreError, DagStoreMode,
+            DeferredAckHandler, EvidenceRetention, ExportStep, FetchPlan, FilteredStats,
+            InsertOutcome, NodeStatusKind, ObserverMode, ResetReport, StrongLinksError,
+            DEFAULT_EPOCH_START_ROUND, DEFAULT_PARK_GAP, PRUNED_GENERATION,
+        },
+        pruned_filter::PrunedDigestFilter,
+        pruning_policy::{DagPruningPolicy, NeverPrune, RetainCommittedPolicy, WindowPolicy},
+        storage::DAGStorage,
+        store_config::DagStoreConfig,
+        tests::helpers::{
+            assert_dag_equivalent, equivocate, generate_dag_nodes, insert_and_link,
+            new_certified_node, new_epoch_certified_node, new_node, TestDag,
+        },
+        types::{
+            AuthorFetchRequest, BroadcastProgress, CertifiedNode, EpochRemnant, EvidenceRecord,
+            Node, NodeCertificate, NodeMetadata, OrderedAnchor, RemoteFetchRequest, SkipVote,
+        },
+        write_retry::WriteRetryQueue,
+    },
+    util::mock_time_service::SimulatedTimeService,
 };
+use aptos_bitvec::BitVec;
+use aptos_consensus_types::common::{Author, Payload, Round};
 use aptos_crypto::HashValue;
-use aptos_infallible::Mutex;
-use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
-use std::{collections::HashMap, sync::Arc};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_types::{
+    aggregate_signature::AggregateSignature,
+    block_info::BlockInfo,
+    chain_id::ChainId,
+    epoch_state::EpochState,
+    ledger_info::{LedgerInfo, LedgerInfoWithSignatures},
+    validator_verifier::{random_validator_verifier, ValidatorConsensusInfo, ValidatorVerifier},
+};
+use proptest::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::{ControlFlow, RangeInclusive},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Barrier,
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{span, Event, Metadata, Subscriber};
 
 pub struct MockStorage {
     node_data: Mutex<HashMap<HashValue, Node>>,
     certified_node_data: Mutex<HashMap<HashValue, CertifiedNode>>,
+    pending_node_data: Mutex<HashMap<HashValue, CertifiedNode>>,
+    pending_deletion_data: Mutex<HashMap<HashValue, u32>>,
+    ordered_anchor_data: Mutex<HashMap<HashValue, OrderedAnchor>>,
+    epoch_start_round: Mutex<Option<(u64, Round)>>,
+    last_committed_anchor: Mutex<Option<NodeMetadata>>,
+    skip_vote_data: Mutex<HashMap<(Round, Author), SkipVote>>,
+    epoch_summary_data: Mutex<BTreeMap<u64, DagEpochSummary>>,
+    self_reservation_data: Mutex<HashMap<(u64, Round), Node>>,
+    broadcast_progress_data: Mutex<HashMap<(u64, Round), BroadcastProgress>>,
+    equivocator_data: Mutex<HashMap<(u64, Author), Round>>,
+    pub(super) evidence_data: Mutex<HashMap<HashValue, EvidenceRecord>>,
+    denied_author_data: Mutex<HashMap<(u64, Author), Round>>,
+    epoch_remnant_data: Mutex<BTreeMap<u64, EpochRemnant>>,
+    /// Writes of certified and pending nodes
+    num_node_writes: AtomicU64,
 }
 
 impl MockStorage {
@@ -22,8 +81,30 @@ impl MockStorage {
         Self {
             node_data: Mutex::new(HashMap::new()),
             certified_node_data: Mutex::new(HashMap::new()),
+            pending_node_data: Mutex::new(HashMap::new()),
+            pending_deletion_data: Mutex::new(HashMap::new()),
+            ordered_anchor_data: Mutex::new(HashMap::new()),
+            epoch_start_round: Mutex::new(None),
+            last_committed_anchor: Mutex::new(None),
+            skip_vote_data: Mutex::new(HashMap::new()),
+            epoch_summary_data: Mutex::new(BTreeMap::new()),
+            self_reservation_data: Mutex::new(HashMap::new()),
+            broadcast_progress_data: Mutex::new(HashMap::new()),
+            equivocator_data: Mutex::new(HashMap::new()),
+            evidence_data: Mutex::new(HashMap::new()),
+            denied_author_data: Mutex::new(HashMap::new()),
+            epoch_remnant_data: Mutex::new(BTreeMap::new()),
+            num_node_writes: AtomicU64::new(0),
         }
     }
+
+    pub fn num_node_writes(&self) -> u64 {
+        self.num_node_writes.load(Ordering::Relaxed)
+    }
+
+    pub fn epoch_summaries(&self) -> BTreeMap<u64, DagEpochSummary> {
+        self.epoch_summary_data.lock().clone()
+    }
 }
 
 impl DAGStorage for MockStorage {
@@ -33,6 +114,7 @@ impl DAGStorage for MockStorage {
     }
 
     fn save_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        self.num_node_writes.fetch_add(1, Ordering::Relaxed);
         self.certified_node_data
             .lock()
             .insert(node.digest(), node.clone());
@@ -49,6 +131,459 @@ impl DAGStorage for MockStorage {
         }
         Ok(())
     }
+
+    fn save_pending_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        self.num_node_writes.fetch_add(1, Ordering::Relaxed);
+        self.pending_node_data
+            .lock()
+            .insert(node.digest(), node.clone());
+        Ok(())
+    }
+
+    fn delete_pending_node(&self, digest: &HashValue) -> anyhow::Result<()> {
+        self.pending_node_data.lock().remove(digest);
+        Ok(())
+    }
+
+    fn get_pending_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Ok(self.pending_node_data.lock().clone())
+    }
+
+    fn save_ordered_anchor(&self, ordered_anchor: &OrderedAnchor) -> anyhow::Result<()> {
+        self.ordered_anchor_data
+            .lock()
+            .insert(*ordered_anchor.anchor().digest(), ordered_anchor.clone());
+        Ok(())
+    }
+
+    fn get_ordered_anchors(&self) -> anyhow::Result<HashMap<HashValue, OrderedAnchor>> {
+        Ok(self.ordered_anchor_data.lock().clone())
+    }
+
+    fn delete_ordered_anchors(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        for digest in digests {
+            self.ordered_anchor_data.lock().remove(&digest);
+        }
+        Ok(())
+    }
+
+    fn save_pending_deletions(&self, deletions: &HashMap<HashValue, u32>) -> anyhow::Result<()> {
+        self.pending_deletion_data.lock().extend(deletions);
+        Ok(())
+    }
+
+    fn delete_pending_deletions(&self, digests: &[HashValue]) -> anyhow::Result<()> {
+        for digest in digests {
+            self.pending_deletion_data.lock().remove(digest);
+        }
+        Ok(())
+    }
+
+    fn get_pending_deletions(&self) -> anyhow::Result<HashMap<HashValue, u32>> {
+        Ok(self.pending_deletion_data.lock().clone())
+    }
+
+    fn save_skip_vote(&self, vote: &SkipVote) -> anyhow::Result<()> {
+        self.skip_vote_data
+            .lock()
+            .insert((vote.skip().round(), *vote.author()), vote.clone());
+        Ok(())
+    }
+
+    fn get_skip_votes(&self) -> anyhow::Result<HashMap<(Round, Author), SkipVote>> {
+        Ok(self.skip_vote_data.lock().clone())
+    }
+
+    fn delete_skip_votes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        for key in keys {
+            self.skip_vote_data.lock().remove(&key);
+        }
+        Ok(())
+    }
+
+    fn save_self_reservation(&self, epoch: u64, round: Round, node: &Node) -> anyhow::Result<()> {
+        self.self_reservation_data
+            .lock()
+            .insert((epoch, round), node.clone());
+        Ok(())
+    }
+
+    fn get_self_reservations(&self) -> anyhow::Result<HashMap<(u64, Round), Node>> {
+        Ok(self.self_reservation_data.lock().clone())
+    }
+
+    fn delete_self_reservations(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        for key in keys {
+            self.self_reservation_data.lock().remove(&key);
+        }
+        Ok(())
+    }
+
+    fn save_broadcast_progress(
+        &self,
+        epoch: u64,
+        round: Round,
+        digest: &HashValue,
+        acked: &BitVec,
+    ) -> anyhow::Result<()> {
+        self.broadcast_progress_data.lock().insert(
+            (epoch, round),
+            BroadcastProgress::new(*digest, acked.clone()),
+        );
+        Ok(())
+    }
+
+    fn get_broadcast_progress(&self) -> anyhow::Result<HashMap<(u64, Round), BroadcastProgress>> {
+        Ok(self.broadcast_progress_data.lock().clone())
+    }
+
+    fn delete_broadcast_progress(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        for key in keys {
+            self.broadcast_progress_data.lock().remove(&key);
+        }
+        Ok(())
+    }
+
+    fn save_equivocator(&self, epoch: u64, author: &Author, round: Round) -> anyhow::Result<()> {
+        self.equivocator_data.lock().insert((epoch, *author), round);
+        Ok(())
+    }
+
+    fn get_equivocators(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(self.equivocator_data.lock().clone())
+    }
+
+    fn delete_equivocators(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        for key in keys {
+            self.equivocator_data.lock().remove(&key);
+        }
+        Ok(())
+    }
+
+    fn save_equivocation_evidence(&self, record: &EvidenceRecord) -> anyhow::Result<()> {
+        self.evidence_data
+            .lock()
+            .insert(*record.conflicting().digest(), record.clone());
+        Ok(())
+    }
+
+    fn get_equivocation_evidence(&self) -> anyhow::Result<HashMap<HashValue, EvidenceRecord>> {
+        Ok(self.evidence_data.lock().clone())
+    }
+
+    fn delete_equivocation_evidence(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        for digest in digests {
+            self.evidence_data.lock().remove(&digest);
+        }
+        Ok(())
+    }
+
+    fn save_denied_authors(&self, epoch: u64, authors: &[(Author, Round)]) -> anyhow::Result<()> {
+        let mut data = self.denied_author_data.lock();
+        for (author, round) in authors {
+            data.insert((epoch, *author), *round);
+        }
+        Ok(())
+    }
+
+    fn get_denied_authors(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Ok(self.denied_author_data.lock().clone())
+    }
+
+    fn delete_denied_authors(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        for key in keys {
+            self.denied_author_data.lock().remove(&key);
+        }
+        Ok(())
+    }
+
+    fn save_epoch_start_round(&self, epoch: u64, round: Round) -> anyhow::Result<()> {
+        *self.epoch_start_round.lock() = Some((epoch, round));
+        Ok(())
+    }
+
+    fn get_epoch_start_round(&self) -> anyhow::Result<Option<(u64, Round)>> {
+        Ok(*self.epoch_start_round.lock())
+    }
+
+    fn save_last_committed_anchor(&self, anchor: &NodeMetadata) -> anyhow::Result<()> {
+        *self.last_committed_anchor.lock() = Some(anchor.clone());
+        Ok(())
+    }
+
+    fn get_last_committed_anchor(&self) -> anyhow::Result<Option<NodeMetadata>> {
+        Ok(self.last_committed_anchor.lock().clone())
+    }
+
+    fn delete_last_committed_anchor(&self) -> anyhow::Result<()> {
+        *self.last_committed_anchor.lock() = None;
+        Ok(())
+    }
+
+    fn save_epoch_summary(&self, summary: &DagEpochSummary) -> anyhow::Result<()> {
+        self.epoch_summary_data
+            .lock()
+            .insert(summary.epoch, summary.clone());
+        Ok(())
+    }
+
+    fn save_epoch_remnant(&self, remnant: &EpochRemnant) -> anyhow::Result<()> {
+        self.epoch_remnant_data
+            .lock()
+            .insert(remnant.epoch(), remnant.clone());
+        Ok(())
+    }
+
+    fn get_epoch_remnants(&self) -> anyhow::Result<Vec<EpochRemnant>> {
+        Ok(self.epoch_remnant_data.lock().values().cloned().collect())
+    }
+
+    fn delete_epoch_remnant(&self, epoch: u64) -> anyhow::Result<()> {
+        self.epoch_remnant_data.lock().remove(&epoch);
+        Ok(())
+    }
+}
+
+/// Wraps `MockStorage` to inject failures.
+struct FailingStorage {
+    inner: MockStorage,
+    fail_reads: AtomicBool,
+    fail_deletes: AtomicBool,
+    fail_pending_writes: AtomicBool,
+    fail_tombstones: AtomicBool,
+    fail_node_writes: AtomicBool,
+    /// The writes of the records kept along with the nodes
+    fail_writes: AtomicBool,
+    fail_committed_anchor_writes: AtomicBool,
+    /// The next certified node write waits on it once when it starts and once before writing
+    write_gate: Mutex<Option<Arc<Barrier>>>,
+}
+
+impl FailingStorage {
+    fn new() -> Self {
+        Self {
+            inner: MockStorage::new(),
+            fail_reads: AtomicBool::new(false),
+            fail_deletes: AtomicBool::new(false),
+            fail_pending_writes: AtomicBool::new(false),
+            fail_tombstones: AtomicBool::new(false),
+            fail_node_writes: AtomicBool::new(false),
+            fail_writes: AtomicBool::new(false),
+            fail_committed_anchor_writes: AtomicBool::new(false),
+            write_gate: Mutex::new(None),
+        }
+    }
+
+    fn check(flag: &AtomicBool) -> anyhow::Result<()> {
+        if flag.load(Ordering::Relaxed) {
+            anyhow::bail!("injected failure");
+        }
+        Ok(())
+    }
+}
+
+impl DAGStorage for FailingStorage {
+    fn save_node(&self, node: &Node) -> anyhow::Result<()> {
+        self.inner.save_node(node)
+    }
+
+    fn save_certified_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        let gate = self.write_gate.lock().take();
+        if let Some(gate) = gate {
+            gate.wait();
+            gate.wait();
+        }
+        Self::check(&self.fail_node_writes)?;
+        self.inner.save_certified_node(node)
+    }
+
+    fn get_certified_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_certified_nodes()
+    }
+
+    fn delete_certified_nodes(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_certified_nodes(digests)
+    }
+
+    fn save_pending_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        Self::check(&self.fail_pending_writes)?;
+        self.inner.save_pending_node(node)
+    }
+
+    fn delete_pending_node(&self, digest: &HashValue) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_pending_node(digest)
+    }
+
+    fn get_pending_nodes(&self) -> anyhow::Result<HashMap<HashValue, CertifiedNode>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_pending_nodes()
+    }
+
+    fn save_ordered_anchor(&self, ordered_anchor: &OrderedAnchor) -> anyhow::Result<()> {
+        Self::check(&self.fail_writes)?;
+        self.inner.save_ordered_anchor(ordered_anchor)
+    }
+
+    fn get_ordered_anchors(&self) -> anyhow::Result<HashMap<HashValue, OrderedAnchor>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_ordered_anchors()
+    }
+
+    fn delete_ordered_anchors(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_ordered_anchors(digests)
+    }
+
+    fn save_pending_deletions(&self, deletions: &HashMap<HashValue, u32>) -> anyhow::Result<()> {
+        Self::check(&self.fail_tombstones)?;
+        self.inner.save_pending_deletions(deletions)
+    }
+
+    fn delete_pending_deletions(&self, digests: &[HashValue]) -> anyhow::Result<()> {
+        Self::check(&self.fail_tombstones)?;
+        self.inner.delete_pending_deletions(digests)
+    }
+
+    fn get_pending_deletions(&self) -> anyhow::Result<HashMap<HashValue, u32>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_pending_deletions()
+    }
+
+    fn save_skip_vote(&self, vote: &SkipVote) -> anyhow::Result<()> {
+        self.inner.save_skip_vote(vote)
+    }
+
+    fn get_skip_votes(&self) -> anyhow::Result<HashMap<(Round, Author), SkipVote>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_skip_votes()
+    }
+
+    fn delete_skip_votes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_skip_votes(keys)
+    }
+
+    fn save_self_reservation(&self, epoch: u64, round: Round, node: &Node) -> anyhow::Result<()> {
+        Self::check(&self.fail_node_writes)?;
+        self.inner.save_self_reservation(epoch, round, node)
+    }
+
+    fn get_self_reservations(&self) -> anyhow::Result<HashMap<(u64, Round), Node>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_self_reservations()
+    }
+
+    fn delete_self_reservations(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_self_reservations(keys)
+    }
+
+    fn save_broadcast_progress(
+        &self,
+        epoch: u64,
+        round: Round,
+        digest: &HashValue,
+        acked: &BitVec,
+    ) -> anyhow::Result<()> {
+        Self::check(&self.fail_node_writes)?;
+        self.inner
+            .save_broadcast_progress(epoch, round, digest, acked)
+    }
+
+    fn get_broadcast_progress(&self) -> anyhow::Result<HashMap<(u64, Round), BroadcastProgress>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_broadcast_progress()
+    }
+
+    fn delete_broadcast_progress(&self, keys: Vec<(u64, Round)>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_broadcast_progress(keys)
+    }
+
+    fn save_equivocator(&self, epoch: u64, author: &Author, round: Round) -> anyhow::Result<()> {
+        self.inner.save_equivocator(epoch, author, round)
+    }
+
+    fn get_equivocators(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_equivocators()
+    }
+
+    fn delete_equivocators(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_equivocators(keys)
+    }
+
+    fn save_equivocation_evidence(&self, record: &EvidenceRecord) -> anyhow::Result<()> {
+        Self::check(&self.fail_writes)?;
+        self.inner.save_equivocation_evidence(record)
+    }
+
+    fn get_equivocation_evidence(&self) -> anyhow::Result<HashMap<HashValue, EvidenceRecord>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_equivocation_evidence()
+    }
+
+    fn delete_equivocation_evidence(&self, digests: Vec<HashValue>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_equivocation_evidence(digests)
+    }
+
+    fn save_denied_authors(&self, epoch: u64, authors: &[(Author, Round)]) -> anyhow::Result<()> {
+        self.inner.save_denied_authors(epoch, authors)
+    }
+
+    fn get_denied_authors(&self) -> anyhow::Result<HashMap<(u64, Author), Round>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_denied_authors()
+    }
+
+    fn delete_denied_authors(&self, keys: Vec<(u64, Author)>) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_denied_authors(keys)
+    }
+
+    fn save_epoch_start_round(&self, epoch: u64, round: Round) -> anyhow::Result<()> {
+        self.inner.save_epoch_start_round(epoch, round)
+    }
+
+    fn get_epoch_start_round(&self) -> anyhow::Result<Option<(u64, Round)>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_epoch_start_round()
+    }
+
+    fn save_last_committed_anchor(&self, anchor: &NodeMetadata) -> anyhow::Result<()> {
+        Self::check(&self.fail_committed_anchor_writes)?;
+        self.inner.save_last_committed_anchor(anchor)
+    }
+
+    fn get_last_committed_anchor(&self) -> anyhow::Result<Option<NodeMetadata>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_last_committed_anchor()
+    }
+
+    fn delete_last_committed_anchor(&self) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_last_committed_anchor()
+    }
+
+    fn save_epoch_remnant(&self, remnant: &EpochRemnant) -> anyhow::Result<()> {
+        Self::check(&self.fail_writes)?;
+        self.inner.save_epoch_remnant(remnant)
+    }
+
+    fn get_epoch_remnants(&self) -> anyhow::Result<Vec<EpochRemnant>> {
+        Self::check(&self.fail_reads)?;
+        self.inner.get_epoch_remnants()
+    }
+
+    fn delete_epoch_remnant(&self, epoch: u64) -> anyhow::Result<()> {
+        Self::check(&self.fail_deletes)?;
+        self.inner.delete_epoch_remnant(epoch)
+    }
 }
 
 #[test]
@@ -56,19 +591,17 @@ fn test_dag_insertion_succeed() {
     let (signers, validator_verifier) = random_validator_verifier(4, None, false);
     let epoch_state = Arc::new(EpochState {
         epoch: 1,
-        verifier: validator_verifier.clone(),
+        verifier: validator_verifier,
     });
     let storage = Arc::new(MockStorage::new());
-    let mut dag = Dag::new(epoch_state, storage);
+    let mut dag = TestDag::new(epoch_state, storage);
 
     // Round 1 - nodes 0, 1, 2 links to vec![]
     for signer in &signers[0..3] {
         let node = new_certified_node(1, signer.author(), vec![]);
         assert!(dag.add_node(node).is_ok());
     }
-    let parents = dag
-        .get_strong_links_for_round(1, &validator_verifier)
-        .unwrap();
+    let parents = dag.strong_links_for_round(1).unwrap();
 
     // Round 2 nodes 0, 1, 2 links to 0, 1, 2
     for signer in &signers[0..3] {
@@ -77,9 +610,7 @@ fn test_dag_insertion_succeed() {
     }
 
     // Round 3 nodes 1, 2 links to 0, 1, 2
-    let parents = dag
-        .get_strong_links_for_round(2, &validator_verifier)
-        .unwrap();
+    let parents = dag.strong_links_for_round(2).unwrap();
 
     for signer in &signers[1..3] {
         let node = new_certified_node(3, signer.author(), parents.clone());
@@ -87,9 +618,7 @@ fn test_dag_insertion_succeed() {
     }
 
     // not enough strong links
-    assert!(dag
-        .get_strong_links_for_round(3, &validator_verifier)
-        .is_none());
+    assert!(dag.strong_links_for_round(3).is_none());
 }
 
 #[test]
@@ -97,10 +626,10 @@ fn test_dag_insertion_failure() {
     let (signers, validator_verifier) = random_validator_verifier(4, None, false);
     let epoch_state = Arc::new(EpochState {
         epoch: 1,
-        verifier: validator_verifier.clone(),
+        verifier: validator_verifier,
     });
     let storage = Arc::new(MockStorage::new());
-    let mut dag = Dag::new(epoch_state, storage);
+    let mut dag = TestDag::new(epoch_state, storage);
 
     // Round 1 - nodes 0, 1, 2 links to vec![]
     for signer in &signers[0..3] {
@@ -111,9 +640,7 @@ fn test_dag_insertion_failure() {
     }
 
     let missing_node = new_certified_node(1, signers[3].author(), vec![]);
-    let mut parents = dag
-        .get_strong_links_for_round(1, &validator_verifier)
-        .unwrap();
+    let mut parents = dag.strong_links_for_round(1).unwrap();
     parents.push(missing_node.certificate());
 
     let node = new_certified_node(2, signers[0].author(), parents.clone());
@@ -126,44 +653,4426 @@ fn test_dag_insertion_failure() {
 
     let node = new_certified_node(2, signers[0].author(), parents[0..3].to_vec());
     assert!(dag.add_node(node).is_ok());
-    let node = new_certified_node(2, signers[0].author(), vec![]);
+    let node = Node::new(
+        ChainId::test(),
+        1,
+        2,
+        signers[0].author(),
+        1,
+        Payload::empty(false),
+        parents[0..3].to_vec(),
+    );
     // equivocation node
-    assert!(dag.add_node(node).is_err());
+    assert!(matches!(
+        dag.add_node(CertifiedNode::new(node, AggregateSignature::empty())),
+        Err(DagStoreError::EquivocateNode)
+    ));
 }
 
 #[test]
-fn test_dag_recover_from_storage() {
+fn test_dag_epoch_root() {
     let (signers, validator_verifier) = random_validator_verifier(4, None, false);
     let epoch_state = Arc::new(EpochState {
         epoch: 1,
         verifier: validator_verifier.clone(),
     });
     let storage = Arc::new(MockStorage::new());
-    let mut dag = Dag::new(epoch_state.clone(), storage.clone());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    assert_eq!(dag.epoch_start_round(), DEFAULT_EPOCH_START_ROUND);
+    assert!(dag.set_epoch_start_round(5).is_ok());
 
-    let mut digests = vec![];
-
-    for round in 1..10 {
-        let parents = dag
-            .get_strong_links_for_round(round, &validator_verifier)
-            .unwrap_or_default();
-        for signer in &signers[0..3] {
-            let node = new_certified_node(round, signer.author(), parents.clone());
-            digests.push(node.digest());
-            assert!(dag.add_node(node).is_ok());
-        }
+    let assert_parentless_rejected = |dag: &mut TestDag, round: Round| {
+        let node = new_certified_node(round, signers[0].author(), vec![]);
+        assert!(!dag.is_epoch_root(node.metadata()));
+        assert!(matches!(
+            dag.add_node(node),
+            Err(DagStoreError::EmptyParentsNotAllowed {
+                round: r,
+                start_round: 5,
+            }) if r == round
+        ));
+    };
+    // below the start round before any node is in, so the round isn't simply too low
+    assert_parentless_rejected(&mut dag, 1);
+    for signer in &signers[0..3] {
+        let node = new_certified_node(5, signer.author(), vec![]);
+        assert!(dag.is_epoch_root(node.metadata()));
+        assert!(dag.add_node(node).is_ok());
     }
-    let new_dag = Dag::new(epoch_state, storage.clone());
+    assert_parentless_rejected(&mut dag, 6);
+    let node = new_certified_node(
+        6,
+        signers[0].author(),
+        dag.strong_links_for_round(5).unwrap(),
+    );
+    assert!(!dag.is_epoch_root(node.metadata()));
+    assert!(dag.add_node(node).is_ok());
 
-    for digest in &digests {
-        assert!(new_dag.exists(digest));
-    }
+    // the start round survives a restart
+    let mut recovered = TestDag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.epoch_start_round(), 5);
+    assert!(matches!(
+        recovered.add_node(new_certified_node(6, signers[1].author(), vec![])),
+        Err(DagStoreError::EmptyParentsNotAllowed { .. })
+    ));
+    assert!(recovered
+        .add_node(new_certified_node(5, signers[3].author(), vec![]))
+        .is_ok());
 
-    let new_epoch_state = Arc::new(EpochState {
+    // and doesn't carry over to the next epoch
+    let next_epoch_state = Arc::new(EpochState {
         epoch: 2,
         verifier: validator_verifier,
     });
+    let mut next_epoch = TestDag::new(next_epoch_state, storage);
+    assert_eq!(next_epoch.epoch_start_round(), DEFAULT_EPOCH_START_ROUND);
+    let root = new_epoch_certified_node(2, 1, signers[0].author(), vec![]);
+    assert!(next_epoch.is_epoch_root(root.metadata()));
+    assert!(next_epoch.add_node(root).is_ok());
+}
 
-    let _new_epoch_dag = Dag::new(new_epoch_state, storage.clone());
-    assert!(storage.certified_node_data.lock().is_empty());
+#[test]
+fn test_dag_chain_id() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    assert_eq!(dag.chain_id(), ChainId::test());
+
+    let other_chain = ChainId::new(10);
+    let foreign = |author: Author| {
+        let node = Node::new(other_chain, 1, 1, author, 0, Payload::empty(false), vec![]);
+        CertifiedNode::new(node, AggregateSignature::empty())
+    };
+    // the chain id is part of the digest
+    assert_ne!(
+        foreign(signers[0].author()).digest(),
+        new_certified_node(1, signers[0].author(), vec![]).digest()
+    );
+    assert!(matches!(
+        dag.add_node(foreign(signers[0].author())),
+        Err(DagStoreError::ChainIdMismatch { chain_id, expected })
+            if chain_id == other_chain && expected == ChainId::test()
+    ));
+    assert!(dag
+        .add_node(new_certified_node(1, signers[0].author(), vec![]))
+        .is_ok());
+
+    // a node of another chain found in storage is dropped at recovery
+    let stray = foreign(signers[1].author());
+    storage.save_certified_node(&stray).unwrap();
+    let recovered = Dag::new(epoch_state, storage.clone());
+    assert!(!recovered.exists(&stray.digest()));
+    assert_eq!(storage.certified_node_data.lock().len(), 1);
+}
+
+#[test]
+fn test_dag_structural_validation() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let num_saved_nodes = storage.certified_node_data.lock().len();
+
+    // a parent with the same round and author as the node
+    let mut self_parents = parents.clone();
+    self_parents.push(new_certified_node(2, signers[0].author(), vec![]).certificate());
+    let node = new_certified_node(2, signers[0].author(), self_parents);
+    assert!(matches!(dag.add_node(node), Err(DagStoreError::SelfParent)));
+
+    // a parent from the same round
+    let mut same_round_parents = parents.clone();
+    same_round_parents.push(new_certified_node(2, signers[1].author(), vec![]).certificate());
+    let node = new_certified_node(2, signers[0].author(), same_round_parents);
+    assert!(matches!(
+        dag.add_node(node),
+        Err(DagStoreError::InvalidParentRound {
+            round: 2,
+            parent_round: 2
+        })
+    ));
+
+    // a parent from another epoch
+    let mut other_epoch_parents = parents;
+    let other_epoch_node = new_node(1, 0, signers[3].author(), vec![]);
+    other_epoch_parents
+        .push(CertifiedNode::new(other_epoch_node, AggregateSignature::empty()).certificate());
+    let node = new_certified_node(2, signers[0].author(), other_epoch_parents);
+    assert!(matches!(
+        dag.add_node(node),
+        Err(DagStoreError::ParentEpochMismatch {
+            epoch: 1,
+            parent_epoch: 0
+        })
+    ));
+
+    // nothing is persisted for rejected nodes
+    assert_eq!(storage.certified_node_data.lock().len(), num_saved_nodes);
+    assert_eq!(dag.highest_round(), 1);
+}
+
+#[test]
+fn test_dag_validation_phases() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let summary = dag.summary();
+    let num_saved_nodes = storage.certified_node_data.lock().len();
+
+    // rejected by pre-validation
+    let (outsiders, _) = random_validator_verifier(1, None, false);
+    let node = new_certified_node(2, outsiders[0].author(), parents.clone());
+    assert!(matches!(
+        dag.pre_validate(&node),
+        Err(DagStoreError::UnknownAuthor(_))
+    ));
+    let other_epoch_node = Node::new(
+        ChainId::test(),
+        2,
+        2,
+        signers[0].author(),
+        0,
+        Payload::empty(false),
+        vec![],
+    );
+    let node = CertifiedNode::new(other_epoch_node, AggregateSignature::empty());
+    assert!(matches!(
+        dag.pre_validate(&node),
+        Err(DagStoreError::EpochMismatch {
+            epoch: 2,
+            expected: 1
+        })
+    ));
+    let node = new_certified_node(0, signers[0].author(), vec![]);
+    assert!(matches!(
+        dag.pre_validate(&node),
+        Err(DagStoreError::RoundTooLow { .. })
+    ));
+    let node = new_certified_node(3, signers[0].author(), parents.clone());
+    assert!(matches!(
+        dag.pre_validate(&node),
+        Err(DagStoreError::RoundTooHigh { .. })
+    ));
+    let mut same_round_parents = parents.clone();
+    same_round_parents.push(new_certified_node(2, signers[1].author(), vec![]).certificate());
+    let node = new_certified_node(2, signers[0].author(), same_round_parents);
+    assert!(matches!(
+        dag.pre_validate(&node),
+        Err(DagStoreError::InvalidParentRound { .. })
+    ));
+
+    // only rejected when added
+    let mut missing_parents = parents;
+    missing_parents.push(new_certified_node(1, signers[3].author(), vec![]).certificate());
+    let missing_parent_node = new_certified_node(2, signers[0].author(), missing_parents);
+    let duplicate_node = new_certified_node(1, signers[0].author(), vec![]);
+    let equivocate_node = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            signers[0].author(),
+            5,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    for node in [&missing_parent_node, &duplicate_node, &equivocate_node] {
+        assert!(dag.pre_validate(node).is_ok());
+    }
+
+    // pre-validation has no side effect
+    assert_eq!(dag.summary(), summary);
+    assert_eq!(storage.certified_node_data.lock().len(), num_saved_nodes);
+
+    assert!(matches!(
+        dag.add_node(missing_parent_node),
+        Err(DagStoreError::MissingParent(_))
+    ));
+    assert!(matches!(
+        dag.add_node(duplicate_node),
+        Err(DagStoreError::DuplicateNode)
+    ));
+    assert!(matches!(
+        dag.add_node(equivocate_node),
+        Err(DagStoreError::EquivocateNode)
+    ));
+}
+
+#[test]
+fn test_dag_batch_insertion() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    let mut parents: Vec<_> = round_one[0..3]
+        .iter()
+        .map(|node| node.certificate())
+        .collect();
+    let good = new_certified_node(2, signers[1].author(), parents.clone());
+    parents.push(new_certified_node(2, signers[0].author(), vec![]).certificate());
+    let bad = new_certified_node(2, signers[0].author(), parents);
+
+    // children come before their parents and the bad node before the good ones
+    let batch = vec![
+        bad,
+        good.clone(),
+        round_one[0].clone(),
+        round_one[1].clone(),
+        round_one[2].clone(),
+    ];
+    let results = dag.add_nodes(batch);
+    assert!(matches!(results[0], Err(DagStoreError::SelfParent)));
+    assert!(results[1..].iter().all(Result::is_ok));
+    assert!(dag.exists(&good.digest()));
+    assert!(dag
+        .get_node_by_round_author(2, &signers[0].author())
+        .is_none());
+}
+
+#[test]
+fn test_dag_frontier() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert!(dag.frontier().is_none());
+
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &round_one {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parents: Vec<_> = round_one[0..3]
+        .iter()
+        .map(|node| node.certificate())
+        .collect();
+    for signer in &signers[0..3] {
+        let node = new_certified_node(2, signer.author(), parents.clone());
+        assert!(dag.add_node(node).is_ok());
+    }
+    // not enough voting power for round 3
+    let node = new_certified_node(
+        3,
+        signers[0].author(),
+        dag.strong_links_for_round(2).unwrap(),
+    );
+    assert!(dag.add_node(node).is_ok());
+
+    let frontier = dag.frontier().unwrap();
+    assert_eq!(frontier.round, 2);
+    assert_eq!(frontier.strong_links.len(), 3);
+    // the round 1 node of the lagging author is not linked by round 2
+    assert_eq!(frontier.weak_links, vec![round_one[3].certificate()]);
+    assert!(!frontier.backpressure);
+}
+
+#[test]
+fn test_dag_frontier_with_concurrent_inserts() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..20)
+        .map(|round| {
+            (0..4)
+                .map(|index| {
+                    // the last validator only produces nodes in even rounds
+                    (index != 3 || round % 2 == 0).then(|| {
+                        if round == 0 {
+                            vec![]
+                        } else {
+                            vec![0, 1, 2]
+                        }
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    let nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    let dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+    )));
+
+    let writer_dag = dag.clone();
+    let writer = thread::spawn(move || {
+        for node in nodes {
+            assert!(writer_dag.write().add_node(node).is_ok());
+        }
+    });
+    let mut last_round = 0;
+    while last_round < 20 {
+        let Some(frontier) = dag.read().frontier() else {
+            continue;
+        };
+        assert!(frontier.round >= last_round);
+        assert!(frontier
+            .strong_links
+            .iter()
+            .all(|certificate| certificate.metadata().round() == frontier.round));
+        assert!(validator_verifier
+            .check_voting_power(
+                frontier
+                    .strong_links
+                    .iter()
+                    .map(|certificate| certificate.metadata().author())
+            )
+            .is_ok());
+        for weak_link in &frontier.weak_links {
+            assert!(weak_link.metadata().round() < frontier.round);
+            assert!(frontier
+                .strong_links
+                .iter()
+                .all(
+                    |certificate| certificate.metadata().author() != weak_link.metadata().author()
+                ));
+        }
+        last_round = frontier.round;
+    }
+    writer.join().unwrap();
+}
+
+/// Parks the node of the first author in round 2, the third node of round 1 is missing.
+fn park_without_parent() -> (TestDag, Arc<MockStorage>, Vec<CertifiedNode>, CertifiedNode) {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in [&round_one[0], &round_one[1], &round_one[3]] {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parked = new_certified_node(
+        2,
+        signers[0].author(),
+        round_one.iter().map(|node| node.certificate()).collect(),
+    );
+    assert!(dag.add_node_or_buffer(parked.clone()).is_ok());
+    // delivered again before its parents, it's still parked once
+    assert!(dag.add_node_or_buffer(parked.clone()).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 1);
+    assert_eq!(storage.pending_node_data.lock().len(), 1);
+    (dag, storage, round_one, parked)
+}
+
+#[test]
+fn test_dag_pending_copy_inserted_by_batch() {
+    let (mut dag, storage, round_one, parked) = park_without_parent();
+    // a fetch response brings the node along with its missing parent
+    let results = dag.add_nodes(vec![parked.clone(), round_one[2].clone()]);
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(dag.pending_nodes_count(), 1);
+
+    // the next promotion drops the parked copy
+    let next = new_certified_node(2, *round_one[1].metadata().author(), vec![
+        round_one[0].certificate(),
+        round_one[1].certificate(),
+        round_one[2].certificate(),
+    ]);
+    assert!(dag.add_node_or_buffer(next).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 0);
+    assert!(storage.pending_node_data.lock().is_empty());
+    assert_eq!(
+        dag.get_node_by_round_author(2, parked.metadata().author())
+            .unwrap()
+            .digest(),
+        parked.digest()
+    );
+    assert!(dag
+        .equivocation_evidence(parked.metadata().author())
+        .is_empty());
+    assert!(dag.equivocators().is_empty());
+    let summary = dag.epoch_summary();
+    assert_eq!(summary.num_nodes, 6);
+    assert_eq!(summary.num_equivocations, 0);
+}
+
+#[test]
+fn test_dag_pending_copy_equivocates_with_batch() {
+    let (mut dag, storage, round_one, parked) = park_without_parent();
+    let author = *parked.metadata().author();
+    // the batch brings another node of the author for the same round
+    let other = new_certified_node(2, author, vec![
+        round_one[0].certificate(),
+        round_one[1].certificate(),
+        round_one[2].certificate(),
+    ]);
+    let results = dag.add_nodes(vec![other.clone(), round_one[2].clone()]);
+    assert!(results.iter().all(Result::is_ok));
+
+    let next = new_certified_node(2, *round_one[1].metadata().author(), vec![
+        round_one[0].certificate(),
+        round_one[1].certificate(),
+        round_one[3].certificate(),
+    ]);
+    assert!(dag.add_node_or_buffer(next).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 0);
+    assert!(storage.pending_node_data.lock().is_empty());
+    assert!(!dag.exists(&parked.digest()));
+    assert_eq!(
+        dag.get_node_by_round_author(2, &author).unwrap().digest(),
+        other.digest()
+    );
+    let evidence = dag.equivocation_evidence(&author);
+    assert_eq!(evidence.len(), 1);
+    assert_eq!(evidence[0].round(), 2);
+    assert_eq!(evidence[0].kept().digest(), &other.digest());
+    assert_eq!(evidence[0].conflicting().digest(), &parked.digest());
+    assert_eq!(dag.equivocators().get(&author), Some(&2));
+    let summary = dag.epoch_summary();
+    assert_eq!(summary.num_nodes, 6);
+    assert_eq!(summary.num_equivocations, 1);
+}
+
+#[test]
+fn test_insert_node_parks_small_round_gap() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let links: Vec<_> = (0..4)
+        .map(|round| {
+            (0..4)
+                .map(|_| Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }))
+                .collect()
+        })
+        .collect();
+    let nodes: Vec<Vec<_>> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .map(|round_nodes| round_nodes.into_iter().flatten().collect())
+        .collect();
+    for node in &nodes[0] {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    assert_eq!(dag.park_gap(), DEFAULT_PARK_GAP);
+
+    let parked = &nodes[2][0];
+    assert!(matches!(
+        dag.pre_validate(parked),
+        Err(DagStoreError::RoundTooHigh {
+            round: 3,
+            highest_round: 1,
+            gap: 2,
+        })
+    ));
+    assert!(dag.pre_validate_parkable(parked).is_ok());
+    assert!(matches!(
+        dag.insert_node(parked.clone()),
+        InsertOutcome::ParkedPendingParents
+    ));
+    assert!(matches!(
+        dag.insert_node(nodes[3][0].clone()),
+        InsertOutcome::Rejected(DagStoreError::RoundTooHigh {
+            round: 4,
+            highest_round: 1,
+            gap: 3,
+        })
+    ));
+    assert_eq!(dag.pending_nodes_count(), 1);
+
+    for node in &nodes[1] {
+        assert!(matches!(
+            dag.insert_node(node.clone()),
+            InsertOutcome::Inserted
+        ));
+    }
+    assert!(dag.exists(&parked.digest()));
+    assert_eq!(dag.pending_nodes_count(), 0);
+
+    // only the next round once the gap is lowered to 1
+    dag.set_park_gap(0);
+    assert_eq!(dag.park_gap(), 1);
+    let far = new_certified_node(5, authors[0], vec![nodes[3][0].certificate()]);
+    assert!(matches!(
+        dag.insert_node(far),
+        InsertOutcome::Rejected(DagStoreError::RoundTooHigh { gap: 2, .. })
+    ));
+    assert!(matches!(
+        dag.insert_node(nodes[3][0].clone()),
+        InsertOutcome::ParkedPendingParents
+    ));
+}
+
+#[test]
+fn test_dag_equivocator_exclusion() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage.clone())));
+    for author in &authors[0..3] {
+        assert!(dag
+            .write()
+            .add_node(new_certified_node(1, *author, vec![]))
+            .is_ok());
+    }
+    assert_eq!(dag.read().highest_quorum_round(), 1);
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            authors[0],
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        insert_and_link(&dag, equivocation),
+        Err(DagStoreError::EquivocateNode)
+    ));
+
+    // the remaining nodes of the round lack the voting power right away
+    {
+        let dag = dag.read();
+        assert_eq!(dag.equivocators(), &BTreeMap::from([(authors[0], 1)]));
+        assert!(dag.exists(&new_certified_node(1, authors[0], vec![]).digest()));
+        assert_eq!(dag.highest_quorum_round(), 0);
+        assert!(matches!(
+            dag.try_strong_links_for_round(1),
+            Err(StrongLinksError::InsufficientPower { .. })
+        ));
+        assert_eq!(dag.bitmask(), vec![vec![false, true, true, false]]);
+        assert!(dag.frontier().is_none());
+    }
+    assert_eq!(
+        storage.equivocator_data.lock().clone(),
+        HashMap::from([((1, authors[0]), 1)])
+    );
+
+    // a restart keeps excluding it
+    let recovered = Dag::new(epoch_state.clone(), storage.clone());
+    assert!(recovered.is_equivocator(&authors[0]));
+    assert_eq!(recovered.highest_quorum_round(), 0);
+    assert!(recovered.strong_links_for_round(1).is_none());
+    assert_eq!(recovered.bitmask(), dag.read().bitmask());
+
+    assert!(dag
+        .write()
+        .add_node(new_certified_node(1, authors[3], vec![]))
+        .is_ok());
+    let strong_links: Vec<_> = dag
+        .read()
+        .strong_links_for_round(1)
+        .unwrap()
+        .iter()
+        .map(|certificate| *certificate.metadata().author())
+        .collect();
+    assert_eq!(strong_links, authors[1..].to_vec());
+    assert_eq!(dag.read().highest_quorum_round(), 1);
+
+    // clearing it restores its node
+    let admin = DagAdmin::new(dag.clone(), HashValue::sha3_256_of(b"passcode"));
+    assert!(admin.clear_equivocator("wrong", &authors[0]).is_err());
+    assert!(dag.read().is_equivocator(&authors[0]));
+    assert!(admin.clear_equivocator("passcode", &authors[0]).unwrap());
+    assert!(!admin.clear_equivocator("passcode", &authors[0]).unwrap());
+    {
+        let dag = dag.read();
+        assert!(dag.equivocators().is_empty());
+        assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 4);
+        assert_eq!(dag.bitmask(), vec![vec![true; 4]]);
+        assert_eq!(dag.frontier().unwrap().strong_links.len(), 4);
+    }
+    assert!(storage.equivocator_data.lock().is_empty());
+    assert!(!Dag::new(epoch_state, storage).is_equivocator(&authors[0]));
+}
+
+#[test]
+fn test_dag_denied_authors() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    // left over from the previous epoch
+    assert!(storage.save_denied_authors(0, &[(authors[0], 5)]).is_ok());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    assert!(storage.denied_author_data.lock().is_empty());
+    for author in &authors[1..] {
+        assert!(dag.add_node(new_certified_node(1, *author, vec![])).is_ok());
+    }
+    assert_eq!(dag.highest_quorum_round(), 1);
+
+    // the round loses its quorum without the denied author, whose node stays
+    assert!(dag
+        .set_denied_authors(HashSet::from([authors[3]]), 1)
+        .is_ok());
+    assert_eq!(dag.denied_authors(), &BTreeMap::from([(authors[3], 1)]));
+    assert!(dag.exists(&new_certified_node(1, authors[3], vec![]).digest()));
+    assert_eq!(dag.highest_quorum_round(), 0);
+    assert_eq!(
+        dag.try_strong_links_for_round(1),
+        Err(StrongLinksError::InsufficientPower {
+            present: 2,
+            required: 3
+        })
+    );
+    assert!(dag
+        .add_node(new_certified_node(1, authors[0], vec![]))
+        .is_ok());
+    assert_eq!(dag.highest_quorum_round(), 1);
+    let strong_links = dag.strong_links_for_round(1).unwrap();
+    let linked: Vec<_> = strong_links
+        .iter()
+        .map(|certificate| *certificate.metadata().author())
+        .collect();
+    assert_eq!(linked, authors[0..3].to_vec());
+    let frontier = dag.frontier().unwrap();
+    assert_eq!(frontier.strong_links.len(), 3);
+    assert!(frontier.weak_links.is_empty());
+
+    // its new nodes are rejected
+    assert!(matches!(
+        dag.add_node(new_certified_node(2, authors[3], strong_links.clone())),
+        Err(DagStoreError::AuthorDenied {
+            author,
+            round: 2,
+            denied_after: 1,
+        }) if author == authors[3]
+    ));
+    assert!(dag
+        .add_node(new_certified_node(2, authors[0], strong_links.clone()))
+        .is_ok());
+    assert_eq!(
+        storage.denied_author_data.lock().clone(),
+        HashMap::from([((1, authors[3]), 1)])
+    );
+
+    // a restart keeps enforcing it
+    let mut recovered = TestDag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.denied_authors(), dag.denied_authors());
+    assert_eq!(recovered.strong_links_for_round(1).unwrap().len(), 3);
+    assert!(matches!(
+        recovered.add_node(new_certified_node(2, authors[3], strong_links.clone())),
+        Err(DagStoreError::AuthorDenied { .. })
+    ));
+
+    // lifted, it's accepted and linked again
+    assert!(dag.set_denied_authors(HashSet::new(), 2).is_ok());
+    assert!(storage.denied_author_data.lock().is_empty());
+    assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 4);
+    assert!(dag
+        .add_node(new_certified_node(2, authors[3], strong_links))
+        .is_ok());
+}
+
+#[test]
+fn test_dag_denied_authors_boundary_from_event() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for author in &authors {
+        assert!(dag.add_node(new_certified_node(1, *author, vec![])).is_ok());
+    }
+    let strong_links = dag.strong_links_for_round(1).unwrap();
+
+    // a validator behind the event round still accepts the nodes up to it
+    assert!(dag
+        .set_denied_authors(HashSet::from([authors[3]]), 2)
+        .is_ok());
+    assert_eq!(dag.highest_round(), 1);
+    assert_eq!(dag.denied_authors(), &BTreeMap::from([(authors[3], 2)]));
+    for author in &authors {
+        assert!(dag
+            .add_node(new_certified_node(2, *author, strong_links.clone()))
+            .is_ok());
+    }
+    // its power still doesn't count, the three others make the quorum
+    assert_eq!(dag.strong_links_for_round(2).unwrap().len(), 3);
+    let strong_links = dag.strong_links_for_round(2).unwrap();
+    assert!(matches!(
+        dag.add_node(new_certified_node(3, authors[3], strong_links)),
+        Err(DagStoreError::AuthorDenied {
+            author,
+            round: 3,
+            denied_after: 2,
+        }) if author == authors[3]
+    ));
+}
+
+#[test]
+fn test_dag_denied_authors_without_quorum() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for author in &authors {
+        assert!(dag.add_node(new_certified_node(1, *author, vec![])).is_ok());
+    }
+
+    // the two others can't reach the quorum, the denied authors are still linked
+    assert!(dag
+        .set_denied_authors(HashSet::from([authors[2], authors[3]]), 1)
+        .is_ok());
+    assert_eq!(dag.highest_quorum_round(), 1);
+    let strong_links = dag.strong_links_for_round(1).unwrap();
+    assert_eq!(strong_links.len(), 4);
+    assert!(matches!(
+        dag.add_node(new_certified_node(2, authors[2], strong_links.clone())),
+        Err(DagStoreError::AuthorDenied { .. })
+    ));
+    for author in &authors[0..2] {
+        assert!(dag
+            .add_node(new_certified_node(2, *author, strong_links.clone()))
+            .is_ok());
+    }
+
+    // with one denied author less the quorum is reachable, the other keeps its round
+    assert!(dag
+        .set_denied_authors(HashSet::from([authors[3]]), 2)
+        .is_ok());
+    assert_eq!(dag.denied_authors(), &BTreeMap::from([(authors[3], 1)]));
+    assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 3);
+    assert!(dag
+        .add_node(new_certified_node(2, authors[2], strong_links.clone()))
+        .is_ok());
+    assert_eq!(dag.highest_quorum_round(), 2);
+
+    // an equivocator makes it unreachable again
+    assert!(dag.exclude_equivocator(&authors[0], 2).unwrap());
+    assert_eq!(dag.highest_quorum_round(), 1);
+    let linked: Vec<_> = dag
+        .strong_links_for_round(1)
+        .unwrap()
+        .iter()
+        .map(|certificate| *certificate.metadata().author())
+        .collect();
+    assert_eq!(linked, authors[1..].to_vec());
+    assert!(matches!(
+        dag.add_node(new_certified_node(2, authors[3], strong_links)),
+        Err(DagStoreError::AuthorDenied { .. })
+    ));
+}
+
+#[test]
+fn test_dag_with_rounds_overlapping_readers() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..10)
+        .map(|round| {
+            (0..4)
+                .map(|_| Some(if round == 0 { vec![] } else { vec![0, 1, 2] }))
+                .collect()
+        })
+        .collect();
+    let nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    let dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+    )));
+
+    let writer = {
+        let dag = dag.clone();
+        thread::spawn(move || {
+            for node in nodes {
+                assert!(insert_and_link(&dag, node).is_ok());
+            }
+        })
+    };
+    let readers: Vec<_> = [vec![3, 1, 2, 5], vec![5, 2, 3, 3, 4]]
+        .into_iter()
+        .map(|rounds| {
+            let dag = dag.clone();
+            let authors = authors.clone();
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    dag.read().with_rounds(&rounds, |view| {
+                        assert!(view.rounds().windows(2).all(|pair| pair[0] < pair[1]));
+                        for round in view.rounds() {
+                            for author in &authors {
+                                let Some(node) = view.get_node(*round, author) else {
+                                    continue;
+                                };
+                                // the parents of a visible node are visible as well
+                                for parent in node.parents() {
+                                    let metadata = parent.metadata();
+                                    if view.contains(metadata.round()) {
+                                        assert!(view
+                                            .get_node(metadata.round(), metadata.author())
+                                            .is_some());
+                                    }
+                                }
+                            }
+                        }
+                    });
+                }
+            })
+        })
+        .collect();
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    let dag = dag.read();
+    dag.with_rounds(&[2, 1, 11], |view| {
+        assert_eq!(view.rounds(), &[1, 2, 11]);
+        assert_eq!(view.bitmask(1), Some(vec![true; 4]));
+        assert_eq!(view.bitmask(11), Some(vec![false; 4]));
+        assert_eq!(view.bitmask(3), None);
+        assert_eq!(view.strong_links(2).map(|links| links.len()), Some(4));
+        assert!(view.strong_links(3).is_none());
+        assert!(view.get_node(3, &authors[0]).is_none());
+        assert!(view.slots(11).is_none());
+    });
+}
+
+#[test]
+fn test_dag_with_rounds_is_consistent() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    for signer in &signers[..3] {
+        assert!(dag
+            .write()
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    let node = new_certified_node(1, signers[3].author(), vec![]);
+    let gate = Arc::new(Barrier::new(2));
+    *storage.write_gate.lock() = Some(gate.clone());
+
+    let dag_reader = dag.read();
+    let writer = dag_reader.with_rounds(&[1], |view| {
+        let writer = {
+            let dag = dag.clone();
+            let node = node.clone();
+            thread::spawn(move || insert_and_link(&dag, node))
+        };
+        // the write of the node goes through, the insert is then left waiting for the view
+        gate.wait();
+        gate.wait();
+        thread::sleep(Duration::from_millis(50));
+        assert!(view.get_node(1, node.author()).is_none());
+        assert_eq!(view.bitmask(1), Some(vec![true, true, true, false]));
+        writer
+    });
+    drop(dag_reader);
+    assert_eq!(writer.join().unwrap().unwrap().digest(), node.digest());
+    dag.read().with_rounds(&[1], |view| {
+        assert_eq!(
+            view.get_node(1, node.author()).map(|node| node.digest()),
+            Some(node.digest())
+        );
+        assert_eq!(view.bitmask(1), Some(vec![true; 4]));
+    });
+}
+
+#[test]
+fn test_dag_recover_from_storage() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+
+    let mut digests = vec![];
+
+    for round in 1..10 {
+        let parents = dag.strong_links_for_round(round - 1).unwrap_or_default();
+        for signer in &signers[0..3] {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            digests.push(node.digest());
+            assert!(dag.add_node(node).is_ok());
+        }
+    }
+    let new_dag = Dag::new(epoch_state, storage.clone());
+
+    for digest in &digests {
+        assert!(new_dag.exists(digest));
+    }
+    assert_eq!(new_dag.content_digest(None), dag.content_digest(None));
+    assert_eq!(new_dag.diff(&dag.slot_digests()), DagDiff::Identical);
+
+    let new_epoch_state = Arc::new(EpochState {
+        epoch: 2,
+        verifier: validator_verifier,
+    });
+
+    let _new_epoch_dag = Dag::new(new_epoch_state, storage.clone());
+    assert!(storage.certified_node_data.lock().is_empty());
+}
+
+#[test]
+fn test_dag_recover_pending_nodes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let missing_parent = new_certified_node(1, signers[3].author(), vec![]);
+    let mut parents = dag.strong_links_for_round(1).unwrap();
+    parents.push(missing_parent.certificate());
+    let pending = new_certified_node(2, signers[0].author(), parents);
+    assert!(dag.add_node_or_buffer(pending.clone()).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 1);
+    assert_eq!(storage.pending_node_data.lock().len(), 1);
+
+    // the pending node survives a restart and is promoted once its parent arrives
+    let mut recovered = TestDag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.pending_nodes_count(), 1);
+    assert!(!recovered.exists(&pending.digest()));
+    assert!(recovered.add_node_or_buffer(missing_parent).is_ok());
+    assert_eq!(recovered.pending_nodes_count(), 0);
+    assert!(recovered.exists(&pending.digest()));
+    assert!(storage.pending_node_data.lock().is_empty());
+
+    // pending nodes from a previous epoch are purged at recovery
+    let stale = new_certified_node(3, signers[1].author(), vec![]);
+    storage.save_pending_node(&stale).unwrap();
+    let new_epoch_state = Arc::new(EpochState {
+        epoch: 2,
+        verifier: validator_verifier,
+    });
+    let new_epoch_dag = Dag::new(new_epoch_state, storage.clone());
+    assert_eq!(new_epoch_dag.pending_nodes_count(), 0);
+    assert!(storage.pending_node_data.lock().is_empty());
+}
+
+#[test]
+fn test_dag_recover_drops_pending_below_floor() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    let mut round_one = vec![];
+    for round in 1..=4 {
+        let parents = dag.strong_links_for_round(round - 1).unwrap_or_default();
+        for signer in &signers[0..3] {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            if round == 1 {
+                round_one.push(node.certificate());
+            }
+            assert!(dag.add_node(node).is_ok());
+        }
+    }
+    dag.prune_below(3).unwrap();
+    assert_eq!(dag.lowest_round(), 3);
+
+    // persisted while its round was still in the DAG, its parents are gone with it
+    let below_floor = new_certified_node(2, signers[3].author(), round_one);
+    storage.save_pending_node(&below_floor).unwrap();
+
+    let recovered = Dag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.lowest_round(), 3);
+    assert_eq!(recovered.pending_nodes_count(), 0);
+    assert!(!recovered.exists(&below_floor.digest()));
+    assert!(storage.pending_node_data.lock().is_empty());
+}
+
+#[test]
+fn test_dag_audit_against_storage() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    let mut nodes = vec![];
+    for round in 1..4 {
+        let parents = dag.strong_links_for_round(round - 1).unwrap_or_default();
+        let round_nodes: Vec<_> = signers[0..3]
+            .iter()
+            .map(|signer| new_certified_node(round, signer.author(), parents.clone()))
+            .collect();
+        for node in &round_nodes {
+            assert!(dag.add_node(node.clone()).is_ok());
+        }
+        nodes.push(round_nodes);
+    }
+    let round_2_links: Vec<_> = nodes[1].iter().map(|node| node.certificate()).collect();
+    let round_3_links: Vec<_> = nodes[2].iter().map(|node| node.certificate()).collect();
+
+    // nodes in memory but not in storage
+    storage
+        .delete_certified_nodes(vec![nodes[0][0].digest(), nodes[2][1].digest()])
+        .unwrap();
+    // nodes in storage but not in memory
+    let missing_round_3 = new_certified_node(3, signers[3].author(), round_2_links.clone());
+    let missing_round_4 = new_certified_node(4, signers[0].author(), round_3_links);
+    storage.save_certified_node(&missing_round_3).unwrap();
+    storage.save_certified_node(&missing_round_4).unwrap();
+    // a node conflicting with the in-memory one and a node stored under the wrong digest
+    let conflicting = new_certified_node(3, signers[0].author(), round_2_links[0..2].to_vec());
+    storage.save_certified_node(&conflicting).unwrap();
+    let wrong_digest = HashValue::random();
+    storage
+        .certified_node_data
+        .lock()
+        .insert(wrong_digest, nodes[1][0].clone());
+
+    let dag = RwLock::new(dag.into_inner());
+    let mut report = Dag::audit_against_storage(&dag).unwrap();
+    report.unrecoverable.sort();
+    let mut unrecoverable = vec![conflicting.digest(), wrong_digest];
+    unrecoverable.sort();
+    assert_eq!(report, AuditReport {
+        num_resaved: 2,
+        num_loaded: 2,
+        unrecoverable,
+    });
+    assert!(dag.read().exists(&missing_round_3.digest()));
+    assert!(dag.read().exists(&missing_round_4.digest()));
+    assert!(!dag.read().exists(&conflicting.digest()));
+    let persisted = storage.certified_node_data.lock().clone();
+    for node in nodes.iter().flatten() {
+        assert!(persisted.contains_key(&node.digest()));
+    }
+
+    // a second audit has nothing left to repair
+    let report = Dag::audit_against_storage(&dag).unwrap();
+    assert_eq!(report.num_resaved + report.num_loaded, 0);
+}
+
+#[test]
+fn test_dag_author_fetch() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let num_rounds = 5;
+
+    // validator 3 links to everyone but nobody links to validator 3
+    let mut links = vec![vec![Some(vec![]); 4]];
+    for _ in 1..num_rounds {
+        let mut round_links = vec![Some(vec![0, 1, 2]); 3];
+        round_links.push(Some(vec![0, 1, 2, 3]));
+        links.push(round_links);
+    }
+    let nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    let serving_dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state.clone(),
+        Arc::new(MockStorage::new()),
+    )));
+    let mut requesting_dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in nodes {
+        if node.author() != &authors[3] {
+            assert!(requesting_dag.add_node(node.clone()).is_ok());
+        }
+        assert!(serving_dag.write().add_node(node).is_ok());
+    }
+
+    // one column of holes
+    let missing_rounds = requesting_dag.missing_slots_for_author(&authors[3]);
+    assert_eq!(missing_rounds, (1..=num_rounds).collect::<Vec<_>>());
+    for author in &authors[0..3] {
+        assert!(requesting_dag.missing_slots_for_author(author).is_empty());
+    }
+
+    // the served nodes are bounded by the window and in ascending round order
+    let served = serving_dag
+        .read()
+        .get_author_nodes_in_range(&authors[3], 0..=num_rounds + 10);
+    assert_eq!(
+        served
+            .iter()
+            .map(|node| node.metadata().round())
+            .collect::<Vec<_>>(),
+        missing_rounds
+    );
+
+    let request = AuthorFetchRequest::new(
+        1,
+        authors[3],
+        missing_rounds[0],
+        *missing_rounds.last().unwrap(),
+    );
+    let response = AuthorFetchHandler::new(serving_dag, 1)
+        .process(request)
+        .unwrap();
+    let fetched: Vec<_> = response.certified_nodes().into_iter().flatten().collect();
+    assert_eq!(fetched.len(), num_rounds as usize);
+    for result in requesting_dag.add_nodes(fetched) {
+        assert!(result.is_ok());
+    }
+    assert!(requesting_dag
+        .missing_slots_for_author(&authors[3])
+        .is_empty());
+}
+
+#[test]
+fn test_dag_remote_fetch_skips_pending() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    // only validator 0 links to validator 3 in round 3
+    let mut links = vec![vec![Some(vec![]); 4], vec![Some(vec![0, 1, 2, 3]); 4]];
+    let mut round_links = vec![Some(vec![0, 1, 2, 3])];
+    round_links.extend(vec![Some(vec![0, 1, 2]); 3]);
+    links.push(round_links);
+    links.push(vec![Some(vec![0, 1, 2, 3]); 4]);
+    let nodes = generate_dag_nodes(&links, &authors);
+    let node = |round: usize, index: usize| nodes[round - 1][index].clone().unwrap();
+
+    let serving_dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state.clone(),
+        Arc::new(MockStorage::new()),
+    )));
+    for node in nodes.iter().flatten().flatten() {
+        assert!(serving_dag.write().add_node(node.clone()).is_ok());
+    }
+    let mut requesting_dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for index in 0..4 {
+        assert!(requesting_dag.add_node(node(1, index)).is_ok());
+    }
+    for index in 0..3 {
+        assert!(requesting_dag.add_node(node(2, index)).is_ok());
+    }
+    assert!(requesting_dag.add_node_or_buffer(node(3, 0)).is_ok());
+    let exists_mask = requesting_dag.exists_mask();
+    let pending_mask = requesting_dag.pending_mask();
+    assert_eq!(exists_mask.start_round(), pending_mask.start_round());
+    assert_eq!(pending_mask.bitmask(), vec![
+        vec![false; 4],
+        vec![false; 4],
+        vec![true, false, false, false]
+    ]);
+
+    let target = node(4, 1);
+    let request = RemoteFetchRequest::new(target.metadata().clone(), exists_mask, pending_mask);
+    let response = RemoteFetchHandler::new(serving_dag.clone(), 1)
+        .process(request.clone())
+        .unwrap();
+    // the same nodes, with the parent certificates the requester has left out
+    let compact = RemoteFetchHandler::new(serving_dag, 1)
+        .process(request.with_omit_known_parent_certs(true))
+        .unwrap();
+    assert!(compact.is_compact());
+    let expanded = compact
+        .expand(|digest| {
+            requesting_dag
+                .get_node(digest)
+                .map(|node| node.certificate())
+        })
+        .unwrap();
+    assert_eq!(
+        expanded.certified_nodes(),
+        response.clone().certified_nodes()
+    );
+    let fetched = response.certified_nodes();
+    let digests: Vec<Vec<_>> = fetched
+        .iter()
+        .map(|round_nodes| round_nodes.iter().map(|node| node.digest()).collect())
+        .collect();
+    // the pending node is not resent, its missing parent is
+    assert_eq!(digests, vec![vec![node(2, 3).digest()], vec![
+        node(3, 1).digest(),
+        node(3, 2).digest(),
+        node(3, 3).digest()
+    ]]);
+    for node in fetched.into_iter().flatten() {
+        assert!(requesting_dag.add_node_or_buffer(node).is_ok());
+    }
+    assert_eq!(requesting_dag.pending_nodes_count(), 0);
+    assert!(requesting_dag.add_node(target).is_ok());
+}
+
+#[test]
+fn test_dag_replace_epoch_state() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for signer in &signers[0..2] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert!(dag.strong_links_for_round(1).is_none());
+    assert!(dag.frontier().is_none());
+    let mut signal = dag.round_advance_signal();
+    let reweight = |powers: [u64; 4]| {
+        Arc::new(EpochState {
+            epoch: 1,
+            verifier: ValidatorVerifier::new(
+                signers
+                    .iter()
+                    .zip(powers)
+                    .map(|(signer, power)| {
+                        ValidatorConsensusInfo::new(signer.author(), signer.public_key(), power)
+                    })
+                    .collect(),
+            ),
+        })
+    };
+
+    // the first two validators now hold the quorum, the cached power of the round follows
+    assert!(dag.replace_epoch_state(reweight([3, 3, 1, 1])).is_ok());
+    assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 2);
+    assert_eq!(dag.frontier().unwrap().round, 1);
+    assert_eq!(dag.read().round_power(1), 6);
+    assert_eq!(dag.highest_quorum_round(), 1);
+    assert_eq!(signal.try_next().map(|advance| advance.round), Some(1));
+
+    // and the round loses it once they don't
+    assert!(dag.replace_epoch_state(reweight([1, 1, 3, 3])).is_ok());
+    assert_eq!(dag.read().round_power(1), 2);
+    assert_eq!(dag.highest_quorum_round(), 0);
+    assert!(dag.strong_links_for_round(1).is_none());
+    assert_eq!(signal.try_next(), None);
+    assert!(dag.replace_epoch_state(reweight([3, 3, 1, 1])).is_ok());
+
+    // membership changes are rejected
+    let (_, other_verifier) = random_validator_verifier(4, None, false);
+    assert!(matches!(
+        dag.replace_epoch_state(Arc::new(EpochState {
+            epoch: 1,
+            verifier: other_verifier,
+        })),
+        Err(DagStoreError::ValidatorSetChanged)
+    ));
+    assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 2);
+}
+
+/// Records the names of the created spans.
+struct SpanRecorder {
+    names: Arc<Mutex<Vec<&'static str>>>,
+    next_id: AtomicU64,
+}
+
+impl SpanRecorder {
+    fn new() -> Self {
+        Self {
+            names: Arc::new(Mutex::new(vec![])),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        self.names.lock().push(span.metadata().name());
+        span::Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn test_dag_trace_spans() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let recorder = SpanRecorder::new();
+    let names = recorder.names.clone();
+
+    tracing::subscriber::with_default(recorder, || {
+        let node = new_certified_node(1, signers[0].author(), vec![]);
+        let anchor = node.metadata().clone();
+        assert!(dag.add_node(node).is_ok());
+        assert!(dag
+            .order_anchor(&anchor, None, dag.traversal_budget())
+            .is_ok());
+        assert_eq!(dag.prune_below(2).unwrap(), 1);
+    });
+    assert_eq!(*names.lock(), vec![
+        "dag::add_node",
+        "dag::validate",
+        "dag::save_certified_node",
+        "dag::order_anchor",
+        "dag::prune",
+    ]);
+}
+
+#[test]
+fn test_dag_store_mode() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let new_dag = |storage: &Arc<FailingStorage>, mode| {
+        Dag::new_with_mode(
+            epoch_state.clone(),
+            ChainId::test(),
+            storage.clone(),
+            Arc::new(SimulatedTimeService::new()),
+            mode,
+        )
+    };
+
+    for mode in [DagStoreMode::Strict, DagStoreMode::BestEffort] {
+        let strict = mode == DagStoreMode::Strict;
+        let storage = Arc::new(FailingStorage::new());
+        let mut dag = new_dag(&storage, mode).unwrap();
+        for signer in &signers[0..3] {
+            let node = new_certified_node(1, signer.author(), vec![]);
+            assert!(dag.add_node(node).is_ok());
+        }
+
+        // failed persistence of a pending node
+        storage.fail_pending_writes.store(true, Ordering::Relaxed);
+        let missing_parent = new_certified_node(1, signers[3].author(), vec![]);
+        let pending =
+            new_certified_node(2, signers[0].author(), vec![missing_parent.certificate()]);
+        let result = dag.add_node_or_buffer(pending);
+        assert_eq!(matches!(result, Err(DagStoreError::Storage(_))), strict);
+        storage.fail_pending_writes.store(false, Ordering::Relaxed);
+
+        // failed recovery read
+        storage.fail_reads.store(true, Ordering::Relaxed);
+        match new_dag(&storage, mode) {
+            Err(DagStoreError::Storage(_)) => assert!(strict),
+            Ok(recovered) => {
+                assert!(!strict);
+                assert_eq!(recovered.memory_usage().num_nodes, 0);
+            },
+            Err(e) => panic!("unexpected error {}", e),
+        }
+        storage.fail_reads.store(false, Ordering::Relaxed);
+
+        // persisted node under the wrong digest
+        let corrupted_digest = HashValue::random();
+        let corrupted_node = new_certified_node(1, signers[3].author(), vec![]);
+        storage
+            .inner
+            .certified_node_data
+            .lock()
+            .insert(corrupted_digest, corrupted_node.clone());
+        match new_dag(&storage, mode) {
+            Err(DagStoreError::DigestMismatch { key, digest }) => {
+                assert!(strict);
+                assert_eq!(key, corrupted_digest);
+                assert_eq!(digest, corrupted_node.digest());
+            },
+            Ok(recovered) => {
+                assert!(!strict);
+                assert_eq!(recovered.memory_usage().num_nodes, 3);
+                assert!(!recovered.exists(&corrupted_node.digest()));
+            },
+            Err(e) => panic!("unexpected error {}", e),
+        }
+        storage
+            .inner
+            .certified_node_data
+            .lock()
+            .remove(&corrupted_digest);
+
+        // failed delete of the pruned nodes that can't be queued either
+        storage.fail_deletes.store(true, Ordering::Relaxed);
+        storage.fail_tombstones.store(true, Ordering::Relaxed);
+        let result = dag.prune_below(2);
+        if strict {
+            assert!(matches!(result, Err(DagStoreError::Storage(_))));
+        } else {
+            assert_eq!(result.unwrap(), 3);
+        }
+    }
+}
+
+#[test]
+fn test_dag_failed_ordered_anchor_save() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new_with_mode(
+        epoch_state,
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+    )
+    .unwrap();
+    for signer in &signers[0..3] {
+        assert!(dag
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let anchor = new_certified_node(2, signers[0].author(), parents);
+    let metadata = anchor.metadata().clone();
+    assert!(dag.add_node(anchor).is_ok());
+
+    // the anchor stays unordered when its record can't be saved
+    storage.fail_writes.store(true, Ordering::Relaxed);
+    let result = dag.order_anchor(&metadata, None, dag.traversal_budget());
+    assert!(matches!(result, Err(DagStoreError::Storage(_))));
+    assert!(storage.inner.get_ordered_anchors().unwrap().is_empty());
+
+    storage.fail_writes.store(false, Ordering::Relaxed);
+    let batch = dag
+        .order_anchor(&metadata, None, dag.traversal_budget())
+        .unwrap();
+    assert_eq!(batch.into_nodes().len(), 4);
+    assert_eq!(storage.inner.get_ordered_anchors().unwrap().len(), 1);
+}
+
+#[test]
+fn test_dag_failed_committed_anchor_save() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new_with_mode(
+        epoch_state,
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+    )
+    .unwrap();
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &round_one {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let round_two: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(2, signer.author(), parents.clone()))
+        .collect();
+    for node in &round_two {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parents = dag.strong_links_for_round(2).unwrap();
+    let next_anchor = new_certified_node(3, signers[1].author(), parents);
+    assert!(dag.add_node(next_anchor.clone()).is_ok());
+
+    // the anchor stays unordered, a retry delivers the whole batch
+    storage
+        .fail_committed_anchor_writes
+        .store(true, Ordering::Relaxed);
+    let metadata = round_two[0].metadata().clone();
+    let result = dag.order_anchor(&metadata, None, dag.traversal_budget());
+    assert!(matches!(result, Err(DagStoreError::Storage(_))));
+    assert_eq!(dag.last_committed_anchor(), None);
+    assert_eq!(dag.epoch_summary().num_ordered_anchors, 0);
+    storage
+        .fail_committed_anchor_writes
+        .store(false, Ordering::Relaxed);
+    let batch = dag
+        .order_anchor(&metadata, None, dag.traversal_budget())
+        .unwrap();
+    assert_eq!(batch.into_nodes().len(), 5);
+    assert_eq!(dag.last_committed_anchor(), Some(&metadata));
+    assert_eq!(dag.epoch_summary().num_ordered_anchors, 1);
+
+    // the chunks before the last one are emitted, the retry resumes with the last one
+    storage
+        .fail_committed_anchor_writes
+        .store(true, Ordering::Relaxed);
+    let next_metadata = next_anchor.metadata().clone();
+    let mut starts = vec![];
+    let mut stream = |dag: &mut TestDag| {
+        dag.order_anchor_streamed(&next_metadata, None, 1, |chunk| {
+            starts.push((chunk.start(), chunk.is_last()));
+            ControlFlow::Continue(())
+        })
+    };
+    assert!(matches!(stream(&mut dag), Err(DagStoreError::Storage(_))));
+    assert_eq!(dag.last_committed_anchor(), Some(&metadata));
+    storage
+        .fail_committed_anchor_writes
+        .store(false, Ordering::Relaxed);
+    assert_eq!(stream(&mut dag).unwrap(), ControlFlow::Continue(()));
+    assert_eq!(starts, vec![(0, false), (1, false), (2, false), (3, true)]);
+    assert_eq!(dag.last_committed_anchor(), Some(&next_metadata));
+    assert_eq!(dag.epoch_summary().num_ordered_anchors, 2);
+}
+
+#[test]
+fn test_dag_failed_epoch_remnant_save() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new_with_mode(
+        epoch_state,
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+    )
+    .unwrap();
+    for signer in &signers[0..3] {
+        assert!(dag
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let anchor = new_certified_node(2, signers[0].author(), parents);
+    let metadata = anchor.metadata().clone();
+    assert!(dag.add_node(anchor).is_ok());
+    assert!(dag
+        .order_anchor(&metadata, None, dag.traversal_budget())
+        .is_ok());
+
+    // the remnant isn't handed out unless it's persisted, the next epoch keeps waiting for it
+    storage.fail_writes.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        dag.finalize_epoch(),
+        Err(DagStoreError::Storage(_))
+    ));
+    assert!(storage.inner.get_epoch_remnants().unwrap().is_empty());
+    assert!(matches!(
+        dag.check_remnant_taken(),
+        Err(DagStoreError::RemnantNotTaken {
+            epoch: 1,
+            num_anchors: 1
+        })
+    ));
+
+    storage.fail_writes.store(false, Ordering::Relaxed);
+    let remnant = dag.finalize_epoch().unwrap();
+    assert_eq!(remnant.anchors().len(), 1);
+    assert_eq!(storage.inner.get_epoch_remnants().unwrap(), vec![remnant]);
+    assert!(dag.check_remnant_taken().is_ok());
+}
+
+#[test]
+fn test_dag_failed_equivocation_evidence_save() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new_with_mode(
+        epoch_state,
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+    )
+    .unwrap();
+    let (_, conflicting) = equivocate(&mut dag, &signers, 0, 1);
+    assert!(dag
+        .set_evidence_pending_report(&conflicting[0], true)
+        .unwrap());
+
+    // the node is rejected and its author excluded all the same, the evidence isn't retained
+    storage.fail_writes.store(true, Ordering::Relaxed);
+    let (_, unrecorded) = equivocate(&mut dag, &signers, 1, 1);
+    assert!(dag.equivocators().contains_key(&signers[1].author()));
+    assert!(dag.equivocation_evidence(&signers[1].author()).is_empty());
+    assert!(!storage
+        .inner
+        .evidence_data
+        .lock()
+        .contains_key(&unrecorded[0]));
+
+    // the flag is left as persisted
+    assert!(matches!(
+        dag.set_evidence_pending_report(&conflicting[0], false),
+        Err(DagStoreError::Storage(_))
+    ));
+    assert!(dag.equivocation_evidence(&signers[0].author())[0].pending_report());
+    assert!(storage.inner.evidence_data.lock()[&conflicting[0]].pending_report());
+}
+
+#[test]
+fn test_dag_observer_mode() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..6)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let nodes = generate_dag_nodes(&links, &authors);
+    // a burst delivering the highest rounds first
+    let stream: Vec<_> = nodes.iter().rev().flatten().flatten().cloned().collect();
+
+    let mut validator = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    let storage = Arc::new(MockStorage::new());
+    let new_observer = |storage: &Arc<MockStorage>| {
+        Dag::new_observer(
+            epoch_state.clone(),
+            ChainId::test(),
+            storage.clone(),
+            Arc::new(SimulatedTimeService::new()),
+            DagStoreMode::Strict,
+            ObserverMode { max_round_span: 8 },
+            DagStoreConfig::default(),
+        )
+        .unwrap()
+    };
+    let mut observer = new_observer(&storage);
+    assert!(observer.is_observer() && !validator.is_observer());
+    for node in stream {
+        let round = node.metadata().round();
+        let result = validator.add_node(node.clone());
+        match round {
+            1 => assert!(result.is_ok()),
+            _ => assert!(matches!(result, Err(DagStoreError::RoundTooHigh { .. }))),
+        }
+        assert!(observer.add_node_or_buffer(node).is_ok());
+    }
+    assert_eq!(validator.highest_round(), 1);
+    assert_eq!(observer.highest_round(), 6);
+    assert_eq!(observer.pending_nodes_count(), 0);
+    for node in nodes.iter().flatten().flatten() {
+        assert!(observer.exists(&node.digest()));
+    }
+
+    // still bounded and checked for equivocation
+    let far = new_certified_node(
+        10,
+        authors[0],
+        nodes[5][0].as_ref().unwrap().parents().to_vec(),
+    );
+    assert!(matches!(
+        observer.add_node_or_buffer(far),
+        Err(DagStoreError::RoundBeyondSpan {
+            round: 10,
+            lowest_round: 1,
+            max_round_span: 8,
+        })
+    ));
+    let parents = nodes[0]
+        .iter()
+        .flatten()
+        .map(|node| node.certificate())
+        .collect();
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            2,
+            authors[1],
+            1,
+            Payload::empty(false),
+            parents,
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        observer.add_node_or_buffer(equivocation),
+        Err(DagStoreError::EquivocateNode)
+    ));
+
+    // no backpressure nor ordering
+    observer.set_memory_budget(0);
+    validator.set_memory_budget(0);
+    assert!(observer.over_memory_budget() && !observer.backpressure());
+    assert!(validator.backpressure());
+    let anchor = nodes[5][0].as_ref().unwrap().metadata().clone();
+    let budget = observer.traversal_budget();
+    assert!(matches!(
+        observer.order_anchor(&anchor, None, budget),
+        Err(DagStoreError::OrderingDisabled)
+    ));
+    let recovered = new_observer(&storage);
+    assert!(recovered.is_observer());
+    assert_eq!(recovered.highest_round(), 6);
+
+    // and serves the lagging validators
+    let response = AuthorFetchHandler::new(Arc::new(RwLock::new(observer)), 1)
+        .process(AuthorFetchRequest::new(1, signers[2].author(), 2, 6))
+        .unwrap();
+    let fetched: Vec<_> = response.certified_nodes().into_iter().flatten().collect();
+    assert_eq!(
+        fetched
+            .iter()
+            .map(|node| node.metadata().round())
+            .collect::<Vec<_>>(),
+        (2..=6).collect::<Vec<_>>()
+    );
+    assert!(validator.add_node(fetched[0].clone()).is_ok());
+}
+
+#[test]
+fn test_dag_force_reset() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..6)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let nodes = generate_dag_nodes(&links, &authors);
+    let storage = Arc::new(MockStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state.clone(), storage.clone())));
+    for node in nodes[0..4].iter().flatten().flatten() {
+        assert!(dag.write().add_node(node.clone()).is_ok());
+    }
+    assert!(dag
+        .write()
+        .add_node_or_buffer(nodes[5][0].clone().unwrap())
+        .is_ok());
+    let anchor = nodes[1][0].as_ref().unwrap().metadata().clone();
+    let budget = dag.read().traversal_budget();
+    assert!(dag.write().order_anchor(&anchor, None, budget).is_ok());
+
+    let ledger_info = |round: Round| {
+        LedgerInfo::new(
+            BlockInfo::new(1, round, HashValue::zero(), HashValue::zero(), 0, 0, None),
+            HashValue::zero(),
+        )
+    };
+    let admin = DagAdmin::new(dag.clone(), HashValue::sha3_256_of(b"passcode"));
+    // refused without the admin passcode
+    assert!(admin.force_reset("wrong", 4, &ledger_info(3)).is_err());
+    // refused below the committed round
+    assert!(admin.force_reset("passcode", 2, &ledger_info(3)).is_err());
+    assert_eq!(dag.read().highest_round(), 4);
+    assert_eq!(storage.certified_node_data.lock().len(), 16);
+
+    let report = admin.force_reset("passcode", 4, &ledger_info(3)).unwrap();
+    assert_eq!(report, ResetReport {
+        epoch: 1,
+        lowest_round: 1,
+        highest_round: 4,
+        num_nodes: 16,
+        num_pending_nodes: 1,
+        num_deleted_nodes: 16,
+        num_deleted_pending_nodes: 1,
+        num_deleted_ordered_anchors: 1,
+        num_deleted_evidence_records: 0,
+        start_round: 5,
+        confirmed_round: 0,
+        pruned_below: 0,
+    });
+    assert!(dag.read().bitmask().is_empty());
+    assert_eq!(dag.read().pending_nodes_count(), 0);
+    assert_eq!(dag.read().epoch_start_round(), 5);
+    assert!(storage.certified_node_data.lock().is_empty());
+    assert!(storage.pending_node_data.lock().is_empty());
+    assert!(storage.ordered_anchor_data.lock().is_empty());
+
+    // the DAG restarts from the round after the committed round, also after a restart
+    assert!(dag
+        .write()
+        .add_node(new_certified_node(5, signers[0].author(), vec![]))
+        .is_ok());
+    let recovered = Dag::new(epoch_state, storage);
+    assert_eq!(recovered.epoch_start_round(), 5);
+    assert_eq!(recovered.highest_round(), 5);
+}
+
+#[test]
+fn test_dag_force_reset_restarts_commit_state() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    dag.set_pruning_policy(Arc::new(RetainCommittedPolicy { extra_rounds: 1 }));
+    dag.set_evidence_retention(EvidenceRetention {
+        window: 2,
+        ..EvidenceRetention::default()
+    });
+    let ledger_info = |round: Round| {
+        LedgerInfo::new(
+            BlockInfo::new(1, round, HashValue::zero(), HashValue::zero(), 0, 0, None),
+            HashValue::zero(),
+        )
+    };
+    let add_rounds = |dag: &mut TestDag, rounds: RangeInclusive<Round>| {
+        for round in rounds {
+            let parents = dag.strong_links_for_round(round - 1).unwrap_or_default();
+            for signer in &signers[0..3] {
+                assert!(dag
+                    .add_node(new_certified_node(round, signer.author(), parents.clone()))
+                    .is_ok());
+            }
+        }
+    };
+
+    // an equivocation in round 1, the anchor of round 2 confirmed and round 1 pruned
+    equivocate(&mut dag, &signers, 3, 1);
+    add_rounds(&mut dag, 1..=4);
+    let anchor = dag
+        .get_node_by_round_author(2, &authors[0])
+        .unwrap()
+        .clone();
+    assert!(dag
+        .order_anchor(anchor.metadata(), None, dag.traversal_budget())
+        .is_ok());
+    let confirmation = dag.notify_commit_confirmed(
+        2,
+        &LedgerInfoWithSignatures::new(ledger_info(2), AggregateSignature::empty()),
+    );
+    assert!(confirmation.is_ok());
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+    assert_eq!(dag.equivocation_evidence(&authors[3]).len(), 1);
+
+    let report = dag.force_reset(6, &ledger_info(3)).unwrap();
+    assert_eq!(report.start_round, 7);
+    assert_eq!(report.confirmed_round, 2);
+    assert_eq!(report.pruned_below, 2);
+    // the evidence is pruned as by a prune to the start round
+    assert_eq!(report.num_deleted_evidence_records, 1);
+    assert!(dag.equivocation_evidence(&authors[3]).is_empty());
+    assert!(storage.evidence_data.lock().is_empty());
+    // the anchors of the dropped DAG count as confirmed
+    assert_eq!(dag.confirmed_round(), 6);
+    assert_eq!(
+        dag.notify_commit_confirmed(
+            4,
+            &LedgerInfoWithSignatures::new(ledger_info(4), AggregateSignature::empty())
+        )
+        .unwrap(),
+        CommitConfirmation::default()
+    );
+    assert_eq!(dag.round_generation(6), PRUNED_GENERATION);
+
+    // the restarted DAG grows and prunes from the start round
+    add_rounds(&mut dag, 7..=9);
+    assert!(dag.round_generation(7) < PRUNED_GENERATION);
+    assert_eq!(dag.prune_below(8).unwrap(), 3);
+    assert_eq!(dag.lowest_round(), 8);
+    assert_eq!(dag.round_generation(7), PRUNED_GENERATION);
+}
+
+#[test]
+fn test_dag_force_reset_clears_write_state() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    for signer in &signers[0..3] {
+        assert!(dag
+            .write()
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    // a write queued for retry, a pruned node and a catch-up in progress
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    let queued = new_certified_node(1, signers[3].author(), vec![]);
+    assert!(matches!(
+        insert_and_link(&dag, queued.clone()),
+        Err(DagStoreError::WriteRetrying(_))
+    ));
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    let mut pruned_digests = PrunedDigestFilter::new(10);
+    pruned_digests.record(1, [queued.metadata()]);
+    dag.write().set_pruned_digest_filter(pruned_digests);
+    dag.write().set_catch_up_progress(Some(CatchUpProgress {
+        slots_remaining: 4,
+        eta: None,
+    }));
+
+    let ledger_info = LedgerInfo::new(
+        BlockInfo::new(1, 1, HashValue::zero(), HashValue::zero(), 0, 0, None),
+        HashValue::zero(),
+    );
+    let admin = DagAdmin::new(dag.clone(), HashValue::sha3_256_of(b"passcode"));
+    assert!(admin.force_reset("passcode", 2, &ledger_info).is_ok());
+    assert_eq!(dag.read().num_queued_writes(), 0);
+    assert!(dag.read().pruned_digests().is_empty());
+    assert!(dag.read().catch_up_progress().is_none());
+
+    // the slot of the queued write is free again in the restarted DAG
+    assert!(Dag::retry_writes(&dag, true).finalized.is_empty());
+    assert!(insert_and_link(&dag, new_certified_node(3, signers[3].author(), vec![])).is_ok());
+}
+
+#[test]
+fn test_dag_author_skew() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    assert_eq!(
+        dag.author_skew(),
+        authors
+            .iter()
+            .map(|author| (*author, 0))
+            .collect::<Vec<_>>()
+    );
+
+    // validator 3 is never heard from, validator 0 runs ahead on its own
+    let mut heads = vec![];
+    for author in &authors[0..3] {
+        let node = new_certified_node(1, *author, vec![]);
+        assert!(dag.add_node(node.clone()).is_ok());
+        heads.push(node);
+    }
+    for round in 2..14 {
+        let node = new_certified_node(round, authors[0], vec![heads[0].certificate()]);
+        assert!(dag.add_node(node.clone()).is_ok());
+        heads[0] = node;
+    }
+    for round in 2..4 {
+        let node = new_certified_node(round, authors[1], vec![heads[1].certificate()]);
+        assert!(dag.add_node(node.clone()).is_ok());
+        heads[1] = node;
+    }
+    // highest rounds 13, 3, 1 and 0, the median is 1
+    let skew = vec![
+        (authors[0], 12),
+        (authors[1], 2),
+        (authors[2], 0),
+        (authors[3], -1),
+    ];
+    assert_eq!(dag.author_skew(), skew);
+    assert_eq!(dag.check_author_skew(), vec![(authors[0], 12)]);
+    dag.set_skew_threshold(0);
+    assert_eq!(dag.check_author_skew(), vec![
+        (authors[0], 12),
+        (authors[1], 2),
+        (authors[3], -1)
+    ]);
+
+    // pruning doesn't lower the highest rounds, recovery rebuilds them
+    assert!(dag.prune_below(2).is_ok());
+    assert_eq!(dag.author_skew(), skew);
+    assert_eq!(Dag::new(epoch_state, storage).author_skew(), vec![
+        (authors[0], 13),
+        (authors[1], 3),
+        (authors[2], 0),
+        (authors[3], 0),
+    ]);
+}
+
+#[test]
+fn test_dag_round_advance_signal() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    let mut signal = dag.round_advance_signal();
+    for signer in &signers[0..2] {
+        assert!(dag
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    assert_eq!(dag.highest_quorum_round(), 0);
+    assert_eq!(signal.try_next(), None);
+
+    time_service.advance(Duration::from_secs(1));
+    assert!(dag
+        .add_node(new_certified_node(1, signers[2].author(), vec![]))
+        .is_ok());
+    assert_eq!(dag.highest_quorum_round(), 1);
+    let advance = signal.try_next().unwrap();
+    assert_eq!(advance.round, 1);
+    assert_eq!(advance.reached_at, Duration::from_secs(1));
+    assert_eq!(signal.try_next(), None);
+
+    // a node in a round that already has quorum doesn't signal
+    assert!(dag
+        .add_node(new_certified_node(1, signers[3].author(), vec![]))
+        .is_ok());
+    assert_eq!(signal.try_next(), None);
+    // nor do the nodes of a round short of quorum
+    let parents = dag.strong_links_for_round(1).unwrap();
+    for signer in &signers[0..2] {
+        assert!(dag
+            .add_node(new_certified_node(2, signer.author(), parents.clone()))
+            .is_ok());
+    }
+    assert_eq!(dag.highest_quorum_round(), 1);
+    assert_eq!(signal.try_next(), None);
+
+    // removing the nodes of the only round with quorum lowers it without signaling
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+    assert_eq!(dag.highest_quorum_round(), 0);
+    assert_eq!(signal.try_next(), None);
+    assert_eq!(signal.latest(), Some(advance));
+}
+
+#[test]
+fn test_dag_recover_equivocating_nodes() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    // persisted by a build without the equivocation check
+    let mut conflicting: Vec<_> = (0..3)
+        .map(|timestamp| {
+            let node = Node::new(
+                ChainId::test(),
+                1,
+                1,
+                authors[0],
+                timestamp,
+                Payload::empty(false),
+                vec![],
+            );
+            CertifiedNode::new(node, AggregateSignature::empty())
+        })
+        .collect();
+    for node in &conflicting {
+        assert!(storage.save_certified_node(node).is_ok());
+    }
+    let other = new_certified_node(1, authors[1], vec![]);
+    assert!(storage.save_certified_node(&other).is_ok());
+    conflicting.sort_by_key(|node| node.digest());
+
+    let dag = Dag::new(epoch_state.clone(), storage.clone());
+    let survivor = conflicting[0].digest();
+    assert_eq!(
+        dag.get_node_by_round_author(1, &authors[0])
+            .unwrap()
+            .digest(),
+        survivor
+    );
+    assert!(dag.exists(&other.digest()));
+    assert!(!dag.exists(&conflicting[1].digest()));
+    assert!(!dag.exists(&conflicting[2].digest()));
+    // the dropped nodes don't count towards the voting power of the round
+    assert_eq!(dag.highest_quorum_round(), 0);
+    let mut evidence = dag.equivocation_evidence(&authors[0]);
+    assert_eq!(evidence.len(), 2);
+    assert!(evidence.iter().all(|evidence| evidence.round() == 1
+        && evidence.is_full()
+        && conflicting[0..2]
+            .iter()
+            .any(|node| &node.digest() == evidence.kept().digest())));
+    let mut dropped: Vec<_> = evidence
+        .iter()
+        .map(|evidence| *evidence.conflicting().digest())
+        .collect();
+    dropped.sort();
+    assert_eq!(dropped, vec![
+        conflicting[1].digest(),
+        conflicting[2].digest()
+    ]);
+    assert_eq!(evidence.last().unwrap().kept().digest(), &survivor);
+    // the losers are deleted, the next recovery finds nothing to resolve and loads the evidence
+    let persisted = storage.certified_node_data.lock().clone();
+    assert_eq!(persisted.len(), 2);
+    assert!(persisted.contains_key(&survivor));
+
+    let recovered = Dag::new(epoch_state, storage);
+    let mut recovered_evidence = recovered.equivocation_evidence(&authors[0]);
+    recovered_evidence.sort_by_key(|evidence| *evidence.conflicting().digest());
+    evidence.sort_by_key(|evidence| *evidence.conflicting().digest());
+    assert_eq!(recovered_evidence, evidence);
+    assert_eq!(
+        recovered
+            .get_node_by_round_author(1, &authors[0])
+            .unwrap()
+            .digest(),
+        survivor
+    );
+}
+
+#[test]
+fn test_dag_equivocation_survives_restart() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let mut nodes: Vec<_> = (0..2)
+        .map(|timestamp| {
+            let node = Node::new(
+                ChainId::test(),
+                1,
+                1,
+                authors[0],
+                timestamp,
+                Payload::empty(false),
+                vec![],
+            );
+            CertifiedNode::new(node, AggregateSignature::empty())
+        })
+        .collect();
+    // the first to arrive has the larger digest, so the smaller digest rule would drop it
+    nodes.sort_by_key(|node| std::cmp::Reverse(node.digest()));
+    let (first, second) = (nodes[0].clone(), nodes[1].clone());
+
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    assert!(dag.add_node(first.clone()).is_ok());
+    assert!(matches!(
+        dag.add_node(second.clone()),
+        Err(DagStoreError::EquivocateNode)
+    ));
+    assert_eq!(
+        dag.equivocation_evidence(&authors[0])[0].kept().digest(),
+        &first.digest()
+    );
+    // the rejected node is never persisted by the live path, but a write that landed anyway
+    // mustn't change which node the slot keeps
+    assert!(storage.save_certified_node(&second).is_ok());
+    drop(dag);
+
+    let recovered = Dag::new(epoch_state, storage.clone());
+    assert_eq!(
+        recovered
+            .get_node_by_round_author(1, &authors[0])
+            .unwrap()
+            .digest(),
+        first.digest()
+    );
+    assert!(!storage
+        .certified_node_data
+        .lock()
+        .contains_key(&second.digest()));
+}
+
+#[test]
+fn test_dag_anchor_blockers() {
+    let (signers, _) = random_validator_verifier(4, None, false);
+    // total power 10, quorum 7 and 4 to commit
+    let verifier = ValidatorVerifier::new(
+        signers
+            .iter()
+            .zip([4, 3, 2, 1])
+            .map(|(signer, power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), power)
+            })
+            .collect(),
+    );
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let epoch_state = Arc::new(EpochState { epoch: 1, verifier });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let round_one: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    for node in &round_one {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let anchor = round_one[3].metadata().clone();
+    let parents = |indices: &[usize]| -> Vec<_> {
+        indices
+            .iter()
+            .map(|index| round_one[*index].certificate())
+            .collect()
+    };
+
+    let report = dag.anchor_blockers(&anchor);
+    assert!(report.not_linking.is_empty());
+    assert_eq!(report.missing, authors);
+    assert_eq!(report.linking_power, 0);
+    assert_eq!(report.shortfall, 4);
+
+    assert!(dag
+        .add_node(new_certified_node(2, authors[0], parents(&[0, 1, 2])))
+        .is_ok());
+    assert!(dag
+        .add_node(new_certified_node(2, authors[1], parents(&[0, 1, 3])))
+        .is_ok());
+    assert_eq!(dag.anchor_blockers(&anchor), AnchorBlockReport {
+        not_linking: vec![authors[0]],
+        missing: vec![authors[2], authors[3]],
+        linking_power: 3,
+        shortfall: 1,
+    });
+
+    assert!(dag
+        .add_node(new_certified_node(2, authors[2], parents(&[0, 2, 3])))
+        .is_ok());
+    assert_eq!(dag.anchor_blockers(&anchor), AnchorBlockReport {
+        not_linking: vec![authors[0]],
+        missing: vec![authors[3]],
+        linking_power: 5,
+        shortfall: 0,
+    });
+    assert_eq!(dag.children_of(anchor.digest()).collect::<Vec<_>>(), vec![
+        dag.slot_of(2, &authors[1]).unwrap(),
+        dag.slot_of(2, &authors[2]).unwrap(),
+    ]);
+
+    // the link of an equivocator counts for nothing, as in the voting power of its round
+    assert!(dag.exclude_equivocator(&authors[1], 2).unwrap());
+    assert_eq!(dag.anchor_blockers(&anchor), AnchorBlockReport {
+        not_linking: vec![authors[0], authors[1]],
+        missing: vec![authors[3]],
+        linking_power: 2,
+        shortfall: 2,
+    });
+}
+
+#[test]
+fn test_dag_try_strong_links() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let parents = dag.try_strong_links_for_round(1).unwrap();
+    assert_eq!(parents.len(), 3);
+    for signer in &signers[0..2] {
+        let node = new_certified_node(2, signer.author(), parents.clone());
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert_eq!(
+        dag.try_strong_links_for_round(2),
+        Err(StrongLinksError::InsufficientPower {
+            present: 2,
+            required: 3
+        })
+    );
+    assert_eq!(
+        dag.try_strong_links_for_round(5),
+        Err(StrongLinksError::RoundUnknown {
+            round: 5,
+            highest_round: 2
+        })
+    );
+    assert!(dag.prune_below(2).is_ok());
+    assert_eq!(
+        dag.try_strong_links_for_round(1),
+        Err(StrongLinksError::RoundPruned {
+            round: 1,
+            lowest_round: 2
+        })
+    );
+}
+
+#[test]
+fn test_dag_strong_links_bounded() {
+    let (signers, _) = random_validator_verifier(4, None, false);
+    // total power 10, quorum 7
+    let verifier = ValidatorVerifier::new(
+        signers
+            .iter()
+            .zip([1, 2, 3, 4])
+            .map(|(signer, power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), power)
+            })
+            .collect(),
+    );
+    let epoch_state = Arc::new(EpochState { epoch: 1, verifier });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let nodes: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &nodes {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let certificate_size = nodes[0].certificate_size();
+    assert_eq!(
+        certificate_size,
+        bcs::to_bytes(&nodes[0].certificate()).unwrap().len()
+    );
+
+    let certificates = |indices: &[usize]| -> Vec<_> {
+        indices
+            .iter()
+            .map(|index| nodes[*index].certificate())
+            .collect()
+    };
+    let anchor = signers[3].author();
+
+    // the whole round if it fits
+    assert_eq!(
+        dag.get_strong_links_bounded(1, MAX_STRONG_LINKS_BYTES, &anchor),
+        Ok(certificates(&[0, 1, 2, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 4 * certificate_size, &anchor),
+        Ok(certificates(&[0, 1, 2, 3]))
+    );
+    // otherwise the largest stakes first, until the quorum is reached
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 4 * certificate_size - 1, &anchor),
+        Ok(certificates(&[2, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 2 * certificate_size - 1, &anchor),
+        Err(StrongLinksError::OverSizeBudget {
+            fitting: 4,
+            required: 7,
+            max_bytes: 2 * certificate_size - 1,
+        })
+    );
+    // the anchor is kept with the smallest stake
+    let anchor = signers[0].author();
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 3 * certificate_size, &anchor),
+        Ok(certificates(&[0, 2, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 3 * certificate_size - 1, &anchor),
+        Err(StrongLinksError::OverSizeBudget {
+            fitting: 5,
+            required: 7,
+            max_bytes: 3 * certificate_size - 1,
+        })
+    );
+    assert!(matches!(
+        dag.get_strong_links_bounded(2, MAX_STRONG_LINKS_BYTES, &anchor),
+        Err(StrongLinksError::RoundUnknown { .. })
+    ));
+}
+
+#[test]
+fn test_dag_strong_links_bounded_by_arrival() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    let nodes: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for index in [3, 1, 0, 2] {
+        assert!(dag.add_node(nodes[index].clone()).is_ok());
+        time_service.advance(Duration::from_millis(10));
+    }
+    let certificate_size = nodes[0].certificate_size();
+
+    let certificates = |indices: &[usize]| -> Vec<_> {
+        indices
+            .iter()
+            .map(|index| nodes[*index].certificate())
+            .collect()
+    };
+
+    // equal stakes, the first three to arrive in validator index order, or the anchor and the
+    // first two
+    assert_eq!(
+        dag.get_strong_links_bounded(1, MAX_STRONG_LINKS_BYTES, &signers[3].author()),
+        Ok(certificates(&[0, 1, 2, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 3 * certificate_size, &signers[3].author()),
+        Ok(certificates(&[0, 1, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 3 * certificate_size, &signers[2].author()),
+        Ok(certificates(&[1, 2, 3]))
+    );
+    assert_eq!(
+        dag.get_strong_links_bounded(1, 3 * certificate_size - 1, &signers[3].author()),
+        Err(StrongLinksError::OverSizeBudget {
+            fitting: 2,
+            required: 3,
+            max_bytes: 3 * certificate_size - 1,
+        })
+    );
+    assert_eq!(dag.strong_links_for_round(1).unwrap().len(), 4);
+}
+
+#[test]
+fn test_dag_weak_links_for_bounded_strong_links() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let nodes = generate_dag_nodes(
+        &[vec![Some(vec![]); 4], vec![Some(vec![0, 1, 2]); 4]],
+        &authors,
+    );
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in nodes.iter().flatten().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let frontier = dag.frontier().unwrap();
+    assert_eq!(frontier.strong_links.len(), 4);
+    assert!(frontier.weak_links.is_empty());
+
+    // without the last validator's node of round 2, its node of round 1 is linked weakly
+    let strong_links: Vec<_> = nodes[1][0..3]
+        .iter()
+        .flatten()
+        .map(|node| node.certificate())
+        .collect();
+    assert_eq!(dag.weak_links_for(2, &strong_links), vec![nodes[0][3]
+        .as_ref()
+        .unwrap()
+        .certificate()]);
+    assert!(dag.weak_links_for(2, &frontier.strong_links).is_empty());
+}
+
+#[test]
+fn test_dag_statuses() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let rounds = generate_dag_nodes(
+        &[
+            vec![Some(vec![]); 4],
+            vec![Some(vec![0, 1, 2, 3]); 4],
+            vec![
+                Some(vec![0, 1, 2, 3]),
+                Some(vec![0, 1, 2, 3]),
+                Some(vec![0, 1, 2, 3]),
+                None,
+            ],
+        ],
+        &authors,
+    );
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in rounds.iter().flatten().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let node = |round: usize, index: usize| rounds[round - 1][index].clone().unwrap();
+    let anchor = node(2, 1);
+    assert!(dag
+        .order_anchor(anchor.metadata(), None, dag.traversal_budget())
+        .is_ok());
+    assert!(dag.prune_below(2).is_ok());
+
+    let missing = new_certified_node(3, authors[3], anchor.parents().to_vec());
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            3,
+            authors[0],
+            1,
+            Payload::empty(false),
+            node(3, 0).parents().to_vec(),
+        ),
+        AggregateSignature::empty(),
+    );
+    let metadatas = vec![
+        node(1, 0).metadata().clone(),
+        anchor.metadata().clone(),
+        node(3, 2).metadata().clone(),
+        missing.metadata().clone(),
+        equivocation.metadata().clone(),
+    ];
+    assert_eq!(dag.statuses(&metadatas), vec![
+        None,
+        Some(NodeStatusKind::Ordered),
+        Some(NodeStatusKind::Unordered),
+        None,
+        None,
+    ]);
+
+    assert!(dag.all_committed(&[]));
+    assert!(dag.all_committed(&metadatas[1..2]));
+    assert!(!dag.all_committed(&metadatas[0..2]));
+    assert!(!dag.all_committed(&metadatas[1..3]));
+}
+
+#[test]
+fn test_dag_certificates_for_round() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert!(dag.get_certificates_for_round(1).is_empty());
+    assert!(dag.round_authors(1).is_empty());
+
+    // partial round, out of validator order
+    for signer in [&signers[2], &signers[0]] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert_eq!(dag.round_authors(1), vec![
+        signers[0].author(),
+        signers[2].author()
+    ]);
+    let certificates = dag.get_certificates_for_round(1);
+    assert_eq!(certificates.len(), 2);
+    assert_eq!(certificates[1].metadata().author(), &signers[2].author());
+    assert!(dag.strong_links_for_round(1).is_none());
+
+    // full round
+    for signer in [&signers[3], &signers[1]] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    assert_eq!(dag.round_authors(1), authors);
+    let certificates = dag.get_certificates_for_round(1);
+    assert_eq!(
+        certificates
+            .iter()
+            .map(|certificate| *certificate.metadata().author())
+            .collect::<Vec<_>>(),
+        authors
+    );
+    assert_eq!(dag.strong_links_for_round(1).unwrap(), certificates);
+}
+
+#[test]
+fn test_dag_content_digest() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    let mut other = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert_eq!(dag.content_digest(None), other.content_digest(None));
+
+    let mut nodes = vec![];
+    for signer in &signers[0..3] {
+        nodes.push(new_certified_node(1, signer.author(), vec![]));
+    }
+    for node in &nodes {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    for node in nodes.iter().rev() {
+        assert!(other.add_node(node.clone()).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let second_round = new_certified_node(2, signers[0].author(), parents);
+    assert!(dag.add_node(second_round.clone()).is_ok());
+    assert_eq!(dag.content_digest(Some(1)), other.content_digest(None));
+    assert_ne!(dag.content_digest(None), other.content_digest(None));
+    assert_eq!(other.diff(&dag.slot_digests()), DagDiff::Divergent {
+        round: 2,
+        author_index: 0,
+        local: None,
+        other: Some(second_round.digest()),
+    });
+
+    // the same node in a different status
+    assert!(other.add_node(second_round.clone()).is_ok());
+    assert_eq!(other.diff(&dag.slot_digests()), DagDiff::Identical);
+    assert!(dag
+        .order_anchor(second_round.metadata(), None, dag.traversal_budget())
+        .is_ok());
+    assert_ne!(dag.content_digest(None), other.content_digest(None));
+
+    // a different set of authors in round 1
+    let mut diverging = TestDag::new(dag.epoch_state().clone(), Arc::new(MockStorage::new()));
+    for node in &nodes[0..2] {
+        assert!(diverging.add_node(node.clone()).is_ok());
+    }
+    let late = new_certified_node(1, signers[3].author(), vec![]);
+    assert!(diverging.add_node(late).is_ok());
+    assert_eq!(dag.diff(&diverging.slot_digests()), DagDiff::Divergent {
+        round: 1,
+        author_index: 2,
+        local: Some(nodes[2].digest()),
+        other: None,
+    });
+}
+
+#[test]
+fn test_dag_round_digests() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let nodes = generate_dag_nodes(
+        &[
+            vec![Some(vec![]); 4],
+            vec![Some(vec![0, 1, 2]); 4],
+            vec![Some(vec![0, 2, 3]); 4],
+        ],
+        &authors,
+    );
+    let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    let mut other = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert_eq!(dag.round_digest(1), None);
+
+    // the other DAG holds an equivocating node of validator 1 in round 2
+    let parents = nodes[1][1].as_ref().unwrap().parents().to_vec();
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            2,
+            authors[1],
+            1,
+            Payload::empty(false),
+            parents,
+        ),
+        AggregateSignature::empty(),
+    );
+    for node in nodes.iter().flatten().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+        let node = match (node.metadata().round(), node.author()) {
+            (2, author) if *author == authors[1] => equivocation.clone(),
+            _ => node.clone(),
+        };
+        assert!(other.add_node(node).is_ok());
+    }
+    let digests = dag.round_digests(1);
+    let other_digests = other.round_digests(1);
+    assert_eq!(digests.len(), 3);
+    for ((round, digest), (other_round, other_digest)) in digests.iter().zip(&other_digests) {
+        assert_eq!(round, other_round);
+        assert_eq!(digest == other_digest, *round != 2);
+    }
+    assert_eq!(dag.round_digests(3), digests[2..].to_vec());
+    assert_eq!(dag.diff(&other.slot_digests()), DagDiff::Divergent {
+        round: 2,
+        author_index: 1,
+        local: Some(nodes[1][1].as_ref().unwrap().digest()),
+        other: Some(equivocation.digest()),
+    });
+
+    // the cached digest follows inserts and pruning
+    let parents = dag.strong_links_for_round(3).unwrap();
+    assert!(dag
+        .add_node(new_certified_node(4, authors[0], parents.clone()))
+        .is_ok());
+    let before = dag.round_digest(4).unwrap();
+    assert!(dag
+        .add_node(new_certified_node(4, authors[1], parents))
+        .is_ok());
+    assert_ne!(dag.round_digest(4).unwrap(), before);
+    assert!(dag.prune_below(3).is_ok());
+    assert_eq!(dag.round_digest(2), None);
+    assert_eq!(dag.round_digests(0).len(), 2);
+}
+
+#[test]
+fn test_dag_round_generations() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..4)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let nodes = generate_dag_nodes(&links, &authors);
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let generations =
+        |dag: &Dag| -> Vec<u64> { (1..=5).map(|r| dag.round_generation(r)).collect() };
+    assert_eq!(generations(&dag), vec![0; 5]);
+    assert_eq!(dag.generation(), 0);
+
+    // an insert moves its round only
+    let first = nodes[0][0].clone().unwrap();
+    assert!(dag.add_node(first.clone()).is_ok());
+    assert_eq!(dag.round_generation(1), 1);
+    assert_eq!(dag.generation(), 1);
+    assert_eq!(generations(&dag)[1..], [0; 4]);
+
+    // duplicate and rejected inserts don't
+    let before = generations(&dag);
+    assert!(matches!(
+        dag.add_node(first.clone()),
+        Err(DagStoreError::DuplicateNode)
+    ));
+    assert_eq!(dag.insert(first.clone()).unwrap().digest(), first.digest());
+    assert!(dag
+        .add_node(new_certified_node(1, Author::random(), vec![]))
+        .is_err());
+    let missing_parent = nodes[0][1].as_ref().unwrap().certificate();
+    assert!(dag
+        .add_node(new_certified_node(2, authors[1], vec![missing_parent]))
+        .is_err());
+    assert_eq!(generations(&dag), before);
+    assert_eq!(dag.generation(), 1);
+
+    for node in nodes.iter().flatten().flatten().skip(1) {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    assert_eq!(dag.generation(), 16);
+    let before = generations(&dag);
+    let digest = dag.round_digest(4).unwrap();
+
+    // ordering moves the rounds of the nodes ordered, ordering again doesn't
+    let anchor = nodes[1][0].as_ref().unwrap().metadata().clone();
+    let budget = dag.traversal_budget();
+    assert!(dag.order_anchor(&anchor, None, budget).is_ok());
+    let after = generations(&dag);
+    assert!(after[0] > before[0] && after[1] > before[1]);
+    assert_eq!(after[2..], before[2..]);
+    assert!(dag.order_anchor(&anchor, None, budget).is_err());
+    assert_eq!(generations(&dag), after);
+
+    // excluding an equivocator changes the power counted in the rounds of its nodes
+    let before = after;
+    assert!(dag.exclude_equivocator(&authors[3], 1).unwrap());
+    let after = generations(&dag);
+    assert!((0..4).all(|r| after[r] > before[r]));
+    assert_eq!(after[4], 0);
+    assert!(!dag.exclude_equivocator(&authors[3], 1).unwrap());
+    assert_eq!(generations(&dag), after);
+    // the slots didn't change, the digest recomputed for the new generation is the same
+    assert_eq!(dag.round_digest(4).unwrap(), digest);
+
+    // pruning moves the pruned rounds to the pruned generation, pruning again doesn't
+    let generation = dag.generation();
+    assert!(dag.prune_below(3).is_ok());
+    assert_eq!(dag.generation(), generation + 1);
+    assert_eq!(generations(&dag), vec![
+        PRUNED_GENERATION,
+        PRUNED_GENERATION,
+        after[2],
+        after[3],
+        0
+    ]);
+    assert!(dag.prune_below(3).is_ok());
+    assert!(dag.prune_below(1).is_ok());
+    assert_eq!(dag.generation(), generation + 1);
+
+    // a reset empties the rounds held, the ones below the start round count as pruned
+    let generation = dag.generation();
+    let ledger_info = LedgerInfo::new(
+        BlockInfo::new(1, 3, HashValue::zero(), HashValue::zero(), 0, 0, None),
+        HashValue::zero(),
+    );
+    assert!(dag.force_reset(4, &ledger_info).is_ok());
+    let mut after = vec![PRUNED_GENERATION; 4];
+    after.push(0);
+    assert_eq!(generations(&dag), after);
+    assert!(dag.generation() > generation);
+}
+
+#[test]
+fn test_dag_pruning_policies() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..10)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+
+    let policies: Vec<(Arc<dyn DagPruningPolicy>, [u64; 2])> = vec![
+        (Arc::new(WindowPolicy { window: 3 }), [4, 7]),
+        (Arc::new(RetainCommittedPolicy { extra_rounds: 5 }), [1, 3]),
+        (Arc::new(NeverPrune), [1, 1]),
+    ];
+    for (policy, floors) in policies {
+        let storage = Arc::new(MockStorage::new());
+        let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+        dag.set_pruning_policy(policy);
+        for node in &nodes {
+            assert!(dag.add_node(node.clone()).is_ok());
+        }
+        for (committed_round, floor) in [4, 8].into_iter().zip(floors) {
+            let before = dag.lowest_round();
+            assert_eq!(
+                dag.commit_callback(committed_round).unwrap(),
+                (floor - before) as usize * 4
+            );
+            assert_eq!(dag.lowest_round(), floor);
+            assert_eq!(
+                storage.certified_node_data.lock().len(),
+                (11 - floor) as usize * 4
+            );
+        }
+    }
+}
+
+#[test]
+fn test_dag_commit_confirmation() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..6)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let rounds = generate_dag_nodes(&links, &authors);
+    let node = |round: usize, index: usize| rounds[round - 1][index].clone().unwrap();
+    let (first, second) = (node(2, 0), node(4, 1));
+    let ledger_info = |epoch: u64, round: Round| {
+        LedgerInfoWithSignatures::new(
+            LedgerInfo::new(
+                BlockInfo::new(
+                    epoch,
+                    round,
+                    HashValue::zero(),
+                    HashValue::zero(),
+                    0,
+                    0,
+                    None,
+                ),
+                HashValue::zero(),
+            ),
+            AggregateSignature::empty(),
+        )
+    };
+    let ordered_dag = || {
+        let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+        dag.set_pruning_policy(Arc::new(RetainCommittedPolicy { extra_rounds: 1 }));
+        for node in rounds.iter().flatten().flatten() {
+            assert!(dag.add_node(node.clone()).is_ok());
+        }
+        for anchor in [&first, &second] {
+            assert!(dag
+                .order_anchor(anchor.metadata(), None, dag.traversal_budget())
+                .is_ok());
+        }
+        dag
+    };
+
+    let mut dag = ordered_dag();
+    let ordered = ordered_dag();
+    assert_eq!(dag.content_digest(None), ordered.content_digest(None));
+    assert_eq!(
+        dag.notify_commit_confirmed(2, &ledger_info(1, 2)).unwrap(),
+        CommitConfirmation {
+            num_anchors: 1,
+            num_committed: 5,
+            num_pruned: 0,
+        }
+    );
+    assert_eq!(dag.confirmed_round(), 2);
+    // the committed nodes hash apart from the same nodes only ordered
+    assert_ne!(dag.content_digest(None), ordered.content_digest(None));
+    assert_eq!(dag.statuses(&[first.metadata().clone()]), vec![Some(
+        NodeStatusKind::Committed
+    )]);
+    assert_eq!(dag.statuses(&[node(3, 0).metadata().clone()]), vec![Some(
+        NodeStatusKind::Ordered
+    )]);
+    // ordered and committed nodes alike
+    assert!(dag.all_committed(&[first.metadata().clone(), node(3, 0).metadata().clone()]));
+    assert_eq!(
+        dag.notify_commit_confirmed(4, &ledger_info(1, 4)).unwrap(),
+        CommitConfirmation {
+            num_anchors: 1,
+            num_committed: 8,
+            num_pruned: 8,
+        }
+    );
+    assert_eq!(dag.lowest_round(), 3);
+    assert_eq!(dag.statuses(&[node(3, 0).metadata().clone()]), vec![Some(
+        NodeStatusKind::Committed
+    )]);
+    // confirmed again, the first anchor is pruned by now
+    assert_eq!(
+        dag.notify_commit_confirmed(2, &ledger_info(1, 2)).unwrap(),
+        CommitConfirmation::default()
+    );
+    assert_eq!(
+        dag.notify_commit_confirmed(4, &ledger_info(1, 4)).unwrap(),
+        CommitConfirmation::default()
+    );
+    assert!(matches!(
+        dag.notify_commit_confirmed(5, &ledger_info(1, 5)),
+        Err(DagStoreError::AnchorNotOrdered(5))
+    ));
+    assert!(matches!(
+        dag.notify_commit_confirmed(5, &ledger_info(1, 6)),
+        Err(DagStoreError::CommitConfirmationMismatch { round: 6, .. })
+    ));
+    assert!(matches!(
+        dag.notify_commit_confirmed(5, &ledger_info(2, 5)),
+        Err(DagStoreError::CommitConfirmationMismatch { epoch: 2, .. })
+    ));
+    assert_eq!(dag.confirmed_round(), 4);
+
+    // the second anchor confirmed first confirms the first one with it
+    let mut dag = ordered_dag();
+    assert_eq!(
+        dag.notify_commit_confirmed(4, &ledger_info(1, 4)).unwrap(),
+        CommitConfirmation {
+            num_anchors: 2,
+            num_committed: 13,
+            num_pruned: 8,
+        }
+    );
+    assert_eq!(
+        dag.notify_commit_confirmed(2, &ledger_info(1, 2)).unwrap(),
+        CommitConfirmation::default()
+    );
+    assert_eq!(dag.confirmed_round(), 4);
+    assert_eq!(dag.lowest_round(), 3);
+    let round_3: Vec<_> = (0..4)
+        .map(|index| node(3, index).metadata().clone())
+        .collect();
+    assert_eq!(dag.statuses(&round_3), vec![
+        Some(NodeStatusKind::Committed);
+        4
+    ]);
+    assert_eq!(dag.statuses(&[node(4, 0).metadata().clone()]), vec![Some(
+        NodeStatusKind::Unordered
+    )]);
+}
+
+#[test]
+fn test_dag_filter_relevant() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..6)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let rounds = generate_dag_nodes(&links, &authors);
+    let round_nodes = |round: usize| -> Vec<CertifiedNode> {
+        rounds[round - 1].iter().flatten().cloned().collect()
+    };
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for round in 1..=4 {
+        for node in round_nodes(round) {
+            assert!(dag.add_node(node).is_ok());
+        }
+    }
+    assert!(dag.prune_below(3).is_ok());
+
+    // entirely stale
+    let (relevant, stats) = dag.filter_relevant(round_nodes(1));
+    assert!(relevant.is_empty());
+    assert_eq!(stats, FilteredStats {
+        below_floor: 4,
+        ..Default::default()
+    });
+
+    // partially useful
+    let mut response = round_nodes(2);
+    response.extend(round_nodes(5));
+    response.push(new_epoch_certified_node(2, 5, authors[0], vec![]));
+    let (relevant, stats) = dag.filter_relevant(response);
+    assert_eq!(relevant, round_nodes(5));
+    assert_eq!(stats, FilteredStats {
+        wrong_epoch: 1,
+        below_floor: 4,
+        duplicate: 0,
+    });
+
+    // duplicates mixed with new nodes
+    let mut response = round_nodes(4);
+    response.extend(round_nodes(5));
+    response.shuffle(&mut StdRng::seed_from_u64(0));
+    let (relevant, stats) = dag.filter_relevant(response);
+    assert_eq!(relevant.len(), 4);
+    assert!(relevant.iter().all(|node| node.metadata().round() == 5));
+    assert_eq!(stats, FilteredStats {
+        duplicate: 4,
+        ..Default::default()
+    });
+    assert_eq!(stats.num_dropped(), 4);
+}
+
+#[test]
+fn test_dag_concurrent_insert() {
+    const NUM_INSERTERS: u64 = 8;
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..250)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let rounds = generate_dag_nodes(&links, &authors);
+    let nodes: Vec<CertifiedNode> = rounds.iter().flatten().flatten().cloned().collect();
+    assert_eq!(nodes.len(), 1000);
+
+    let mut serial = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    for node in &nodes {
+        assert!(serial.add_node(node.clone()).is_ok());
+    }
+
+    // every inserter inserts every node, in its own order within each round, through a shared
+    // reference to the DAG
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    thread::scope(|scope| {
+        for seed in 0..NUM_INSERTERS {
+            let dag = &dag;
+            let rounds = &rounds;
+            scope.spawn(move || {
+                let mut rng = StdRng::seed_from_u64(seed);
+                for round_nodes in rounds {
+                    let mut round_nodes: Vec<_> = round_nodes.iter().flatten().collect();
+                    round_nodes.shuffle(&mut rng);
+                    for node in round_nodes {
+                        loop {
+                            match dag.insert(node.clone()) {
+                                Ok(inserted) => {
+                                    assert_eq!(inserted.digest(), node.digest());
+                                    break;
+                                },
+                                // the parents, or the node itself, are being written by another
+                                // inserter
+                                Err(
+                                    DagStoreError::MissingParent(_)
+                                    | DagStoreError::RoundTooHigh { .. }
+                                    | DagStoreError::WriteRetrying(_),
+                                ) => thread::yield_now(),
+                                Err(e) => panic!("unexpected error {}", e),
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    // one inserter wrote each node, the others got it back
+    assert_eq!(storage.inner.num_node_writes(), nodes.len() as u64);
+    assert!(dag.link_inserted().is_empty());
+
+    assert_eq!(dag.content_digest(None), serial.content_digest(None));
+    assert_eq!(dag.memory_usage(), serial.memory_usage());
+    assert_eq!(storage.inner.certified_node_data.lock().len(), nodes.len());
+}
+
+#[test]
+fn test_dag_insert_links_written_nodes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    let parents: Vec<_> = signers[..3]
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &parents {
+        assert_eq!(dag.insert(node.clone()).unwrap().digest(), node.digest());
+    }
+    // the written nodes are parents already and inserted again without a write, the readers
+    // don't see them yet
+    assert_eq!(
+        dag.insert(parents[0].clone()).unwrap().digest(),
+        parents[0].digest()
+    );
+    let child = new_certified_node(
+        2,
+        signers[0].author(),
+        parents.iter().map(CertifiedNode::certificate).collect(),
+    );
+    assert!(dag.insert(child.clone()).is_ok());
+    let equivocation = Node::new(
+        ChainId::test(),
+        1,
+        1,
+        signers[0].author(),
+        1,
+        Payload::empty(false),
+        vec![],
+    );
+    assert!(matches!(
+        dag.insert(CertifiedNode::new(
+            equivocation,
+            AggregateSignature::empty()
+        )),
+        Err(DagStoreError::EquivocateNode)
+    ));
+    assert_eq!(storage.inner.num_node_writes(), 4);
+    assert!(!dag.exists(&parents[0].digest()));
+    assert!(dag.equivocators().is_empty());
+
+    assert!(dag.link_inserted().is_empty());
+    assert!(parents.iter().all(|node| dag.exists(&node.digest())));
+    assert!(dag.exists(&child.digest()));
+    assert_eq!(
+        dag.equivocators(),
+        &BTreeMap::from([(signers[0].author(), 1)])
+    );
+    assert_eq!(dag.insert(child.clone()).unwrap().digest(), child.digest());
+    assert_eq!(storage.inner.num_node_writes(), 4);
+}
+
+#[test]
+fn test_dag_insert_releases_lock_during_write() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    let gate = Arc::new(Barrier::new(2));
+    *storage.write_gate.lock() = Some(gate.clone());
+
+    let writer = {
+        let dag = dag.clone();
+        let node = node.clone();
+        thread::spawn(move || insert_and_link(&dag, node))
+    };
+    // the write is in progress, the lock is free and the slot is reserved, a second insert
+    // isn't acked before the node is persisted
+    gate.wait();
+    assert!(!dag.read().exists(&node.digest()));
+    assert_eq!(dag.read().memory_usage().num_nodes, 0);
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::WriteRetrying(digest)) if digest == node.digest()
+    ));
+    let equivocation = Node::new(
+        ChainId::test(),
+        1,
+        1,
+        signers[0].author(),
+        1,
+        Payload::empty(false),
+        vec![],
+    );
+    assert!(matches!(
+        dag.write().add_node(CertifiedNode::new(
+            equivocation,
+            AggregateSignature::empty()
+        )),
+        Err(DagStoreError::EquivocateNode)
+    ));
+    assert!(dag
+        .write()
+        .add_node(new_certified_node(1, signers[1].author(), vec![]))
+        .is_ok());
+
+    gate.wait();
+    assert_eq!(writer.join().unwrap().unwrap().digest(), node.digest());
+    assert!(dag.read().exists(&node.digest()));
+    assert_eq!(storage.inner.num_node_writes(), 2);
+    assert_eq!(
+        insert_and_link(&dag, node.clone()).unwrap().digest(),
+        node.digest()
+    );
+}
+
+#[test]
+fn test_dag_insert_deletes_node_rejected_after_write() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    let gate = Arc::new(Barrier::new(2));
+    *storage.write_gate.lock() = Some(gate.clone());
+
+    let writer = {
+        let dag = dag.clone();
+        let node = node.clone();
+        thread::spawn(move || insert_and_link(&dag, node))
+    };
+    // the round is pruned while the node is written
+    gate.wait();
+    assert!(dag.write().prune_below(2).is_ok());
+    gate.wait();
+    assert!(writer.join().unwrap().is_err());
+    assert!(!dag.read().exists(&node.digest()));
+    assert!(!storage
+        .inner
+        .get_certified_nodes()
+        .unwrap()
+        .contains_key(&node.digest()));
+}
+
+#[test]
+fn test_dag_insert_write_failure_releases_slot() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let dag = RwLock::new(Dag::new(epoch_state, storage.clone()));
+    // without write retries
+    dag.write()
+        .set_write_retry_queue(WriteRetryQueue::new(0, 1, Duration::ZERO));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::Storage(_))
+    ));
+    assert!(!dag.read().exists(&node.digest()));
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node.clone()),
+        InsertOutcome::Rejected(DagStoreError::Storage(_))
+    ));
+
+    // nothing is left reserved, the slot can be filled once the storage recovers
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node.clone()),
+        InsertOutcome::Inserted
+    ));
+    assert!(dag.read().exists(&node.digest()));
+    assert!(storage
+        .inner
+        .certified_node_data
+        .lock()
+        .contains_key(&node.digest()));
+}
+
+#[test]
+fn test_dag_insert_write_retry_finalizes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let dag = RwLock::new(Dag::new_with_time_service(
+        epoch_state,
+        storage.clone(),
+        time_service.clone(),
+    ));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::WriteRetrying(digest)) if digest == node.digest()
+    ));
+    assert!(!dag.read().exists(&node.digest()));
+    assert_eq!(dag.read().num_queued_writes(), 1);
+    // the slot stays reserved and a redelivery isn't acked before the write
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node.clone()),
+        InsertOutcome::WriteRetrying
+    ));
+    assert_eq!(
+        InsertOutcome::WriteRetrying.ack_decision(),
+        AckDecision::AckLater
+    );
+    let equivocation = Node::new(
+        ChainId::test(),
+        1,
+        1,
+        signers[0].author(),
+        1,
+        Payload::empty(false),
+        vec![],
+    );
+    assert!(matches!(
+        insert_and_link(
+            &dag,
+            CertifiedNode::new(equivocation, AggregateSignature::empty())
+        ),
+        Err(DagStoreError::EquivocateNode)
+    ));
+    // not proposing or voting while the write fails
+    assert!(Dag::flush_writes(&dag).is_err());
+    assert_eq!(dag.read().num_queued_writes(), 1);
+
+    // the storage recovers, the retry waits for its backoff
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    assert_eq!(Dag::retry_writes(&dag, false).num_queued, 1);
+    time_service.advance(Duration::from_millis(100));
+    let report = Dag::retry_writes(&dag, false);
+    assert_eq!(report.finalized, vec![node.digest()]);
+    assert!(report.rejected.is_empty());
+    assert_eq!(report.num_queued, 0);
+    assert!(dag.read().exists(&node.digest()));
+    assert!(storage
+        .inner
+        .certified_node_data
+        .lock()
+        .contains_key(&node.digest()));
+    assert!(Dag::flush_writes(&dag).is_ok());
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node),
+        InsertOutcome::AlreadyPresent
+    ));
+}
+
+#[test]
+fn test_dag_write_retry_in_progress_not_acked() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let dag = Arc::new(RwLock::new(Dag::new_with_time_service(
+        epoch_state,
+        storage.clone(),
+        time_service.clone(),
+    )));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::WriteRetrying(_))
+    ));
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    time_service.advance(Duration::from_millis(50));
+    let gate = Arc::new(Barrier::new(2));
+    *storage.write_gate.lock() = Some(gate.clone());
+
+    let retrier = {
+        let dag = dag.clone();
+        thread::spawn(move || Dag::retry_writes(&dag, false))
+    };
+    // the retry took the write out of the queue, a redelivery still waits for it to land
+    gate.wait();
+    assert_eq!(dag.read().num_queued_writes(), 0);
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node.clone()),
+        InsertOutcome::WriteRetrying
+    ));
+    assert!(matches!(
+        dag.write().insert_node(node.clone()),
+        InsertOutcome::WriteRetrying
+    ));
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::WriteRetrying(_))
+    ));
+    gate.wait();
+    assert_eq!(retrier.join().unwrap().finalized, vec![node.digest()]);
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node),
+        InsertOutcome::AlreadyPresent
+    ));
+}
+
+#[test]
+fn test_dag_insert_write_retry_exhausted() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let dag = RwLock::new(Dag::new_with_time_service(
+        epoch_state,
+        storage.clone(),
+        time_service.clone(),
+    ));
+    dag.write()
+        .set_write_retry_queue(WriteRetryQueue::new(1, 3, Duration::from_millis(50)));
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        insert_and_link(&dag, node.clone()),
+        Err(DagStoreError::WriteRetrying(_))
+    ));
+    // the second attempt, the next one waits twice as long
+    assert!(Dag::flush_writes(&dag).is_err());
+    time_service.advance(Duration::from_millis(50));
+    assert_eq!(Dag::retry_writes(&dag, false).num_queued, 1);
+    time_service.advance(Duration::from_millis(50));
+    let report = Dag::retry_writes(&dag, false);
+    assert_eq!(report.rejected, vec![node.digest()]);
+    assert_eq!(report.num_queued, 0);
+    assert!(!dag.read().exists(&node.digest()));
+
+    // the slot is free, the redelivered node inserts cleanly
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, node.clone()),
+        InsertOutcome::Inserted
+    ));
+    assert!(dag.read().exists(&node.digest()));
+
+    // the oldest write is evicted from a full queue
+    storage.fail_node_writes.store(true, Ordering::Relaxed);
+    let evicted = new_certified_node(1, signers[1].author(), vec![]);
+    let kept = new_certified_node(1, signers[2].author(), vec![]);
+    for node in [&evicted, &kept] {
+        assert!(matches!(
+            insert_and_link(&dag, node.clone()),
+            Err(DagStoreError::WriteRetrying(_))
+        ));
+    }
+    assert_eq!(dag.read().num_queued_writes(), 1);
+    storage.fail_node_writes.store(false, Ordering::Relaxed);
+    assert!(matches!(
+        Dag::insert_node_shared(&dag, evicted),
+        InsertOutcome::Inserted
+    ));
+    assert_eq!(Dag::flush_writes(&dag).unwrap().finalized, vec![
+        kept.digest()
+    ]);
+}
+
+#[test]
+fn test_dag_deletion_retry() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    let mut pruned = vec![];
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        pruned.push(node.digest());
+        assert!(dag.add_node(node).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    for signer in &signers[0..3] {
+        let node = new_certified_node(2, signer.author(), parents.clone());
+        assert!(dag.add_node(node).is_ok());
+    }
+
+    // the first delete fails and the digests are queued
+    storage.fail_deletes.store(true, Ordering::Relaxed);
+    assert_eq!(dag.prune_below(2).unwrap(), 3);
+    let queued = storage.inner.get_pending_deletions().unwrap();
+    assert_eq!(queued.len(), 3);
+    assert!(pruned.iter().all(|digest| queued.get(digest) == Some(&0)));
+
+    // a failed retry keeps them queued with one more attempt
+    assert_eq!(dag.retry_pending_deletions(2).unwrap(), 3);
+    let queued = storage.inner.get_pending_deletions().unwrap();
+    assert!(pruned.iter().all(|digest| queued.get(digest) == Some(&1)));
+
+    // the next startup drains the queue
+    storage.fail_deletes.store(false, Ordering::Relaxed);
+    let recovered = Dag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.memory_usage().num_nodes, 3);
+    let persisted = storage.inner.get_certified_nodes().unwrap();
+    assert_eq!(persisted.len(), 3);
+    assert!(pruned.iter().all(|digest| !persisted.contains_key(digest)));
+    assert!(storage.inner.get_pending_deletions().unwrap().is_empty());
+}
+
+#[test]
+fn test_dag_storage_error_keeps_memory_consistent() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(FailingStorage::new());
+    let mut dag = TestDag::new_with_mode(
+        epoch_state,
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+    )
+    .unwrap();
+    let mut round_one = vec![];
+    for signer in &signers[0..3] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        round_one.push(node.digest());
+        assert!(dag.add_node(node).is_ok());
+    }
+
+    // the node is promoted once its parent arrives, failing to delete its parked copy
+    let missing_parent = new_certified_node(1, signers[3].author(), vec![]);
+    let pending = new_certified_node(2, signers[0].author(), vec![missing_parent.certificate()]);
+    assert!(dag.add_node_or_buffer(pending.clone()).is_ok());
+    storage.fail_deletes.store(true, Ordering::Relaxed);
+    let result = dag.add_node_or_buffer(missing_parent);
+    assert!(matches!(result, Err(DagStoreError::Storage(_))));
+    assert!(dag.exists(&pending.digest()));
+    assert_eq!(dag.pending_nodes_count(), 0);
+
+    // a parked node and the pruned rounds are removed from memory even though storage fails
+    let unknown_parent = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            signers[2].author(),
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    let parked = new_certified_node(2, signers[1].author(), vec![unknown_parent.certificate()]);
+    storage.fail_deletes.store(false, Ordering::Relaxed);
+    assert!(dag.add_node_or_buffer(parked).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 1);
+    assert!(dag
+        .add_node(new_certified_node(3, signers[0].author(), vec![
+            pending.certificate()
+        ]))
+        .is_ok());
+    storage.fail_deletes.store(true, Ordering::Relaxed);
+    storage.fail_tombstones.store(true, Ordering::Relaxed);
+    let result = dag.prune_below(3);
+    assert!(matches!(result, Err(DagStoreError::Storage(_))));
+    assert_eq!(dag.lowest_round(), 3);
+    assert!(!dag.exists(&pending.digest()));
+    assert!(round_one.iter().all(|digest| !dag.exists(digest)));
+    assert_eq!(dag.pending_nodes_count(), 0);
+    assert_eq!(dag.memory_usage().num_nodes, 1);
+}
+
+#[test]
+fn test_dag_fetch_plan() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    // holes at (1, 2), (2, 3) and (3, 1)
+    let links = vec![
+        vec![Some(vec![]), Some(vec![]), None, Some(vec![])],
+        vec![
+            Some(vec![0, 1, 3]),
+            Some(vec![0, 1, 3]),
+            Some(vec![0, 1, 3]),
+            None,
+        ],
+        vec![
+            Some(vec![0, 1, 2]),
+            None,
+            Some(vec![0, 1, 2]),
+            Some(vec![0, 1, 2]),
+        ],
+    ];
+    for node in generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert_eq!(dag.prune_below(2).unwrap(), 3);
+
+    // target two rounds ahead, with a weak link below our lowest round
+    let mut parents: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(4, *author, vec![]).certificate())
+        .collect();
+    parents.push(new_certified_node(1, authors[2], vec![]).certificate());
+    let target = new_certified_node(5, authors[0], parents);
+    let summary = dag.summary();
+
+    let plan = dag.fetch_plan_for(&target);
+    assert_eq!(
+        plan.missing_slots,
+        BTreeMap::from([
+            (2, vec![authors[3]]),
+            (3, vec![authors[1]]),
+            (4, authors.clone()),
+        ])
+    );
+    assert_eq!(plan.estimated_nodes, 6);
+    assert_eq!(dag.summary(), summary);
+
+    let mut requests: Vec<_> = plan
+        .author_requests(1, 2)
+        .iter()
+        .map(|request| (*request.author(), request.rounds()))
+        .collect();
+    requests.sort_by_key(|(author, rounds)| (*author, *rounds.start()));
+    let mut expected = vec![
+        (authors[0], 4..=4),
+        (authors[1], 3..=4),
+        (authors[2], 4..=4),
+        (authors[3], 2..=2),
+        (authors[3], 4..=4),
+    ];
+    expected.sort_by_key(|(author, rounds)| (*author, *rounds.start()));
+    assert_eq!(requests, expected);
+}
+
+#[test]
+fn test_dag_memory_budget() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+
+    for signer in &signers[0..2] {
+        let node = new_certified_node(1, signer.author(), vec![]);
+        assert!(dag.add_node(node).is_ok());
+    }
+    let usage = dag.memory_usage();
+    assert_eq!(usage.num_nodes, 2);
+    assert!(!dag.over_memory_budget());
+
+    dag.set_memory_budget(usage.total_bytes());
+    assert!(!dag.over_memory_budget());
+    let node = new_certified_node(1, signers[2].author(), vec![]);
+    assert!(dag.add_node(node).is_ok());
+    assert!(dag.over_memory_budget());
+    assert!(dag.backpressure());
+    assert!(dag.summary().over_memory_budget);
+    assert_eq!(dag.summary().memory_usage, dag.memory_usage());
+
+    // recovery accounts the persisted nodes
+    let recovered = Dag::new(epoch_state, storage);
+    assert_eq!(recovered.memory_usage(), dag.memory_usage());
+
+    assert_eq!(dag.prune_below(2).unwrap(), 3);
+    assert_eq!(dag.memory_usage(), Default::default());
+    assert!(!dag.over_memory_budget());
+}
+
+#[test]
+fn test_dag_reception_time() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let time_service = SimulatedTimeService::new();
+    let mut dag = TestDag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        Arc::new(time_service.clone()),
+    );
+
+    let first = new_certified_node(1, signers[0].author(), vec![]);
+    assert!(dag.add_node(first.clone()).is_ok());
+    time_service.advance(Duration::from_secs(5));
+    let second = new_certified_node(1, signers[1].author(), vec![]);
+    assert!(dag.add_node(second.clone()).is_ok());
+
+    assert_eq!(dag.reception_time(&first.digest()), Some(Duration::ZERO));
+    assert_eq!(
+        dag.reception_time(&second.digest()),
+        Some(Duration::from_secs(5))
+    );
+
+    assert!(dag.prune_below(2).is_ok());
+    assert_eq!(dag.reception_time(&first.digest()), None);
+}
+
+#[test]
+fn test_self_slot_reservation_survives_restart() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    for signer in &signers {
+        dag.add_node(new_certified_node(1, signer.author(), vec![]))
+            .unwrap();
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    let author = signers[0].author();
+
+    // the validator crashes after broadcasting its round 2 node, which is only persisted with
+    // its reservation
+    let broadcast = new_node(2, 100, author, parents.clone());
+    let dag = RwLock::new(dag.into_inner());
+    Dag::reserve_self_slot(&dag, &broadcast).unwrap();
+    Dag::reserve_self_slot(&dag, &broadcast).unwrap();
+    drop(dag);
+
+    let recovered = RwLock::new(Dag::new(epoch_state, storage.clone()));
+    assert_eq!(recovered.read().self_reservation(2), Some(&broadcast));
+    assert_eq!(recovered.read().self_reservation(3), None);
+    // a node built again after the restart has another timestamp
+    let rebuilt = new_node(2, 200, author, parents);
+    assert_ne!(rebuilt.digest(), broadcast.digest());
+    assert!(matches!(
+        Dag::reserve_self_slot(&recovered, &rebuilt),
+        Err(DagStoreError::SelfSlotReserved { round: 2, reserved })
+            if reserved == broadcast.digest()
+    ));
+    assert!(Dag::reserve_self_slot(&recovered, &broadcast).is_ok());
+    Dag::reserve_self_slot(&recovered, &new_node(3, 300, author, vec![])).unwrap();
+
+    let mut recovered = recovered.into_inner();
+    recovered.prune_below(3).unwrap();
+    assert_eq!(recovered.self_reservation(2), None);
+    assert_eq!(
+        storage
+            .get_self_reservations()
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>(),
+        vec![(1, 3)]
+    );
+
+    // the reservations don't carry over to the next epoch
+    let next_epoch_state = Arc::new(EpochState {
+        epoch: 2,
+        verifier: validator_verifier,
+    });
+    let next_epoch = Dag::new(next_epoch_state, storage.clone());
+    assert_eq!(next_epoch.self_reservation(3), None);
+    assert!(storage.get_self_reservations().unwrap().is_empty());
+}
+
+#[test]
+fn test_dag_node_latency_samples() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let time_service = SimulatedTimeService::new();
+    let dag = Arc::new(RwLock::new(Dag::new_with_time_service(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+        Arc::new(time_service.clone()),
+    )));
+    let mut dag_writer = dag.write();
+
+    let round_1: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &round_1 {
+        dag_writer.add_node(node.clone()).unwrap();
+    }
+    time_service.advance(Duration::from_secs(2));
+    let parents: Vec<_> = round_1.iter().map(|node| node.certificate()).collect();
+    let anchor = new_certified_node(2, signers[0].author(), parents.clone());
+    dag_writer.add_node(anchor.clone()).unwrap();
+    time_service.advance(Duration::from_secs(3));
+    let budget = dag_writer.traversal_budget();
+    let batch = dag_writer
+        .order_anchor(anchor.metadata(), None, budget)
+        .unwrap();
+    assert_eq!(batch.nodes().len(), 5);
+    // ordered but not committed
+    assert!(dag_writer.latency_samples().is_empty());
+
+    // a node inserted after the ordering isn't committed with the anchor
+    let late = new_certified_node(2, signers[1].author(), parents);
+    dag_writer.add_node(late.clone()).unwrap();
+    time_service.advance(Duration::from_secs(4));
+    dag_writer.commit_callback(2).unwrap();
+    let samples = dag_writer.latency_samples();
+    assert_eq!(samples.len(), 5);
+    for sample in &samples {
+        let (inserted_at, insert_to_order) = if sample.digest == anchor.digest() {
+            (Duration::from_secs(2), Duration::from_secs(3))
+        } else {
+            (Duration::ZERO, Duration::from_secs(5))
+        };
+        assert_eq!(sample.inserted_at, inserted_at);
+        assert_eq!(sample.ordered_at, Some(Duration::from_secs(5)));
+        assert_eq!(sample.committed_at, Duration::from_secs(9));
+        assert_eq!(sample.insert_to_order(), Some(insert_to_order));
+        assert_eq!(sample.order_to_commit(), Some(Duration::from_secs(4)));
+    }
+    assert!(samples.iter().all(|sample| sample.digest != late.digest()));
+    // committed only once
+    dag_writer.commit_callback(2).unwrap();
+    assert_eq!(dag_writer.latency_samples().len(), 5);
+    drop(dag_writer);
+
+    let admin = DagAdmin::new(dag.clone(), HashValue::sha3_256_of(b"passcode"));
+    assert!(admin.latency_samples("wrong").is_err());
+    assert_eq!(admin.latency_samples("passcode").unwrap(), samples);
+
+    // the timings of the pruned nodes are dropped
+    dag.write().prune_below(3).unwrap();
+    assert_eq!(dag.read().reception_time(&anchor.digest()), None);
+    assert_eq!(dag.read().latency_samples(), samples);
+}
+
+#[test]
+fn test_export_cursor_with_concurrent_inserts() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    // rounds 1 to 3 without the last author
+    let mut parents = vec![];
+    for round in 1..=3 {
+        let nodes: Vec<_> = authors[0..3]
+            .iter()
+            .map(|author| new_certified_node(round, *author, parents.clone()))
+            .collect();
+        for node in &nodes {
+            dag.add_node(node.clone()).unwrap();
+        }
+        parents = nodes.iter().map(|node| node.certificate()).collect();
+    }
+    let late_parent = new_certified_node(1, authors[3], vec![]);
+    let mut round_1_parents = dag.strong_links_for_round(1).unwrap();
+    round_1_parents.push(late_parent.certificate());
+    let late_child = new_certified_node(2, authors[3], round_1_parents);
+
+    let mut cursor = dag.export_cursor();
+    assert_eq!(cursor.floor(), 1);
+    let mut exported: BTreeMap<Round, Vec<Arc<CertifiedNode>>> = BTreeMap::new();
+    let mut retries = vec![];
+    let mut late_nodes = Some((late_parent, late_child));
+    let mut pruned = false;
+    loop {
+        match cursor.next_round(&dag) {
+            ExportStep::Nodes(round, nodes) => {
+                exported.insert(round, nodes);
+                // round 2 gets a node whose parent is only added to round 1 once it was read
+                if let Some((parent, child)) = late_nodes.take() {
+                    dag.add_node(parent).unwrap();
+                    dag.add_node(child).unwrap();
+                }
+                if round == 2 && !pruned {
+                    pruned = true;
+                    dag.prune_below(2).unwrap();
+                }
+            },
+            ExportStep::Retry(round) => {
+                retries.push(round);
+                exported.split_off(&round);
+                exported = exported.split_off(&cursor.floor());
+            },
+            ExportStep::Done => break,
+        }
+    }
+
+    assert_eq!(retries, vec![1, 2]);
+    assert_eq!(cursor.floor(), 2);
+    assert_eq!(
+        exported
+            .iter()
+            .map(|(round, nodes)| (*round, nodes.len()))
+            .collect::<Vec<_>>(),
+        vec![(2, 4), (3, 3)]
+    );
+    let digests: HashSet<_> = exported
+        .values()
+        .flatten()
+        .map(|node| node.digest())
+        .collect();
+    for node in exported.values().flatten() {
+        assert!(dag.exists(&node.digest()));
+        for parent in node.parents() {
+            assert!(
+                parent.metadata().round() < cursor.floor()
+                    || digests.contains(parent.metadata().digest())
+            );
+        }
+    }
+
+    // without mutations the rounds are read at once
+    let mut cursor = dag.export_cursor();
+    let mut rounds = vec![];
+    while let ExportStep::Nodes(round, _) = cursor.next_round(&dag) {
+        rounds.push(round);
+    }
+    assert_eq!(rounds, vec![2, 3]);
+}
+
+struct RecordingAckHandler {
+    promoted: Mutex<Vec<(AckToken, HashValue)>>,
+    evicted: Mutex<Vec<AckToken>>,
+}
+
+impl RecordingAckHandler {
+    fn new() -> Self {
+        Self {
+            promoted: Mutex::new(vec![]),
+            evicted: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl DeferredAckHandler for RecordingAckHandler {
+    fn on_promoted(&self, token: AckToken, metadata: &NodeMetadata) {
+        self.promoted.lock().push((token, *metadata.digest()));
+    }
+
+    fn on_evicted(&self, token: AckToken) {
+        self.evicted.lock().push(token);
+    }
+}
+
+#[test]
+fn test_insert_outcome_ack_decision() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    assert_eq!(
+        dag.insert_node(node.clone()).ack_decision(),
+        AckDecision::AckNow
+    );
+    assert_eq!(
+        dag.insert_node(node.clone()).ack_decision(),
+        AckDecision::AckNow
+    );
+    let missing = new_certified_node(1, signers[1].author(), vec![]);
+    let child = new_certified_node(2, signers[0].author(), vec![
+        node.certificate(),
+        missing.certificate(),
+    ]);
+    assert_eq!(dag.insert_node(child).ack_decision(), AckDecision::AckLater);
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            signers[0].author(),
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert_eq!(
+        dag.insert_node(equivocation).ack_decision(),
+        AckDecision::NeverAck
+    );
+    let wrong_epoch = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            2,
+            1,
+            signers[2].author(),
+            0,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert_eq!(
+        dag.insert_node(wrong_epoch).ack_decision(),
+        AckDecision::NeverAck
+    );
+
+    assert_eq!(
+        DagStoreError::DuplicateNode.ack_decision(),
+        AckDecision::AckNow
+    );
+    for error in [
+        DagStoreError::MissingParent(HashValue::random()),
+        DagStoreError::RoundTooHigh {
+            round: 5,
+            highest_round: 1,
+            gap: 4,
+        },
+        DagStoreError::Storage(anyhow::anyhow!("disk full")),
+    ] {
+        assert_eq!(error.ack_decision(), AckDecision::AckLater);
+    }
+    for error in [
+        DagStoreError::SelfParent,
+        DagStoreError::EpochEnded(1),
+        DagStoreError::RoundTooLow {
+            round: 1,
+            lowest_round: 2,
+        },
+    ] {
+        assert_eq!(error.ack_decision(), AckDecision::NeverAck);
+    }
+}
+
+#[test]
+fn test_deferred_ack_fires_on_promotion() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let handler = Arc::new(RecordingAckHandler::new());
+    dag.set_deferred_ack_handler(handler.clone());
+
+    let parents: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    let child = new_certified_node(
+        2,
+        signers[0].author(),
+        parents.iter().map(|node| node.certificate()).collect(),
+    );
+    for parent in &parents[1..] {
+        assert!(matches!(
+            dag.insert_node_with_ack(parent.clone(), AckToken(0)),
+            InsertOutcome::Inserted
+        ));
+    }
+    // a redelivery while parked defers a second ack
+    for token in [AckToken(1), AckToken(2)] {
+        assert!(matches!(
+            dag.insert_node_with_ack(child.clone(), token),
+            InsertOutcome::ParkedPendingParents
+        ));
+    }
+    assert!(handler.promoted.lock().is_empty());
+
+    assert!(matches!(
+        dag.insert_node(parents[0].clone()),
+        InsertOutcome::Inserted
+    ));
+    assert_eq!(*handler.promoted.lock(), vec![
+        (AckToken(1), child.digest()),
+        (AckToken(2), child.digest())
+    ]);
+    assert!(handler.evicted.lock().is_empty());
+
+    // only parked nodes defer their ack
+    assert!(matches!(
+        dag.insert_node_with_ack(child, AckToken(3)),
+        InsertOutcome::AlreadyPresent
+    ));
+    assert_eq!(handler.promoted.lock().len(), 2);
+}
+
+#[test]
+fn test_deferred_ack_not_fired_on_eviction() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let missing = new_certified_node(1, signers[1].author(), vec![]);
+    let new_dag = || {
+        let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+        let handler = Arc::new(RecordingAckHandler::new());
+        dag.set_deferred_ack_handler(handler.clone());
+        assert!(matches!(
+            dag.insert_node(new_certified_node(1, signers[0].author(), vec![])),
+            InsertOutcome::Inserted
+        ));
+        let child = new_certified_node(2, signers[0].author(), vec![missing.certificate()]);
+        assert!(matches!(
+            dag.insert_node_with_ack(child, AckToken(1)),
+            InsertOutcome::ParkedPendingParents
+        ));
+        (dag, handler)
+    };
+
+    let (mut dag, handler) = new_dag();
+    assert!(dag.prune_below(3).is_ok());
+    assert_eq!(dag.pending_nodes_count(), 0);
+    assert_eq!(*handler.evicted.lock(), vec![AckToken(1)]);
+
+    let (mut dag, handler) = new_dag();
+    let ledger_info = LedgerInfo::new(
+        BlockInfo::new(1, 0, HashValue::zero(), HashValue::zero(), 0, 0, None),
+        HashValue::zero(),
+    );
+    assert!(dag.force_reset(0, &ledger_info).is_ok());
+    assert_eq!(*handler.evicted.lock(), vec![AckToken(1)]);
+
+    // the parent arriving after the eviction doesn't bring the ack back
+    assert!(matches!(dag.insert_node(missing), InsertOutcome::Inserted));
+    assert!(handler.promoted.lock().is_empty());
+}
+
+#[test]
+fn test_parent_certificate_metadata_mismatch() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links = vec![vec![Some(vec![]); 4], vec![
+        Some(vec![0, 1, 2, 3]),
+        Some(vec![0, 1, 2, 3]),
+        Some(vec![0, 1, 2, 3]),
+        None,
+    ]];
+    let nodes = generate_dag_nodes(&links, &authors);
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in nodes.iter().flatten().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+
+    // the digest of a round 1 node passed off as the missing round 2 node
+    let real = nodes[0][3].as_ref().unwrap();
+    let forged = NodeCertificate::new(
+        NodeMetadata::new_for_test(1, 2, authors[3], 0, real.digest()),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        dag.resolve_parent(&real.certificate()),
+        Ok(Some(node)) if node.digest() == real.digest()
+    ));
+    let mut parents: Vec<_> = nodes[1][0..3]
+        .iter()
+        .map(|node| node.as_ref().unwrap().certificate())
+        .collect();
+    parents.push(forged);
+    let child = new_certified_node(3, authors[0], parents);
+
+    assert!(!dag.all_exists(child.parents()));
+    assert_eq!(dag.fetch_plan_for(&child), FetchPlan::default());
+    let outcome = dag.insert_node(child.clone());
+    assert_eq!(outcome.ack_decision(), AckDecision::NeverAck);
+    match outcome {
+        InsertOutcome::Rejected(DagStoreError::ParentMetadataMismatch {
+            digest,
+            claimed_round,
+            claimed_author,
+            round,
+            author,
+        }) => {
+            assert_eq!(digest, real.digest());
+            assert_eq!((claimed_round, claimed_author), (2, authors[3]));
+            assert_eq!((round, author), (1, authors[3]));
+        },
+        outcome => panic!("unexpected outcome {:?}", outcome),
+    }
+    assert!(!dag.exists(&child.digest()));
+    assert_eq!(dag.pending_nodes_count(), 0);
+
+    // a wrong author is caught the same way
+    let forged = NodeCertificate::new(
+        NodeMetadata::new_for_test(1, 1, authors[2], 0, real.digest()),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        dag.resolve_parent(&forged),
+        Err(DagStoreError::ParentMetadataMismatch { .. })
+    ));
+}
+
+proptest! {
+    #[test]
+    fn test_dag_memory_usage_matches_recomputation(
+        seed in any::<u64>(),
+        prunes in prop::collection::vec(prop::option::weighted(0.2, 1..10u64), 36),
+    ) {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let epoch_state = Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier,
+        });
+        let authors = epoch_state.verifier.get_ordered_account_addresses();
+        let links: Vec<_> = (0..9)
+            .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+            .collect();
+        let mut nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        nodes.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+        for (node, prune) in nodes.into_iter().zip(prunes) {
+            let _ = dag.add_node_or_buffer(node);
+            prop_assert_eq!(dag.memory_usage(), dag.recompute_memory_usage());
+            if let Some(round) = prune {
+                prop_assert!(dag.prune_below(round).is_ok());
+                prop_assert_eq!(dag.memory_usage(), dag.recompute_memory_usage());
+            }
+        }
+    }
+
+    #[test]
+    fn test_dag_recovery_matches_live_dag(
+        seed in any::<u64>(),
+        presence in prop::collection::vec(prop::collection::vec(prop::bool::weighted(0.85), 4), 8),
+        actions in prop::collection::vec(
+            prop::option::weighted(0.3, (any::<bool>(), 1..10u64)),
+            48,
+        ),
+    ) {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let epoch_state = Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier,
+        });
+        let authors = epoch_state.verifier.get_ordered_account_addresses();
+        let links: Vec<Vec<_>> = presence
+            .iter()
+            .enumerate()
+            .map(|(round, present)| {
+                present
+                    .iter()
+                    .map(|present| {
+                        present.then(|| match round {
+                            0 => vec![],
+                            _ => (0..4).filter(|index| presence[round - 1][*index]).collect(),
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut nodes = vec![];
+        for node in generate_dag_nodes(&links, &authors).into_iter().flatten().flatten() {
+            // whichever of the two gets in, the other one must not reach storage
+            if rng.gen_bool(0.1) {
+                nodes.push(CertifiedNode::new(
+                    Node::new(
+                        ChainId::test(),
+                        1,
+                        node.metadata().round(),
+                        *node.metadata().author(),
+                        1,
+                        Payload::empty(false),
+                        node.parents().to_vec(),
+                    ),
+                    AggregateSignature::empty(),
+                ));
+            }
+            nodes.push(node);
+        }
+        nodes.shuffle(&mut rng);
+
+        let storage = Arc::new(MockStorage::new());
+        let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+        for (node, action) in nodes.into_iter().zip(actions) {
+            let _ = dag.insert_node(node);
+            match action {
+                Some((true, round)) => {
+                    let anchor = dag
+                        .get_node_by_round_author(round, &authors[round as usize % 4])
+                        .map(|anchor| anchor.metadata().clone());
+                    if let Some(anchor) = anchor {
+                        let budget = dag.traversal_budget();
+                        let _ = dag.order_anchor(&anchor, None, budget);
+                    }
+                },
+                Some((false, round)) => prop_assert!(dag.prune_below(round).is_ok()),
+                None => {},
+            }
+        }
+        assert_dag_equivalent(&dag, &Dag::new(epoch_state, storage));
+    }
 }
@@ -0,0 +1,175 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{Dag, ExportStep},
+    storage::DAGStorage,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, Node},
+};
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_infallible::RwLock;
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use std::sync::Arc;
+
+const NUM_ROUNDS: Round = 5;
+
+fn epoch_state() -> (Arc<EpochState>, Vec<Author>) {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    (epoch_state, authors)
+}
+
+/// The nodes of every round by validator index, the last validator misses round 3.
+fn rounds(authors: &[Author]) -> Vec<Vec<CertifiedNode>> {
+    let mut rounds: Vec<Vec<CertifiedNode>> = vec![];
+    for round in 1..=NUM_ROUNDS {
+        let parents: Vec<_> = rounds
+            .last()
+            .into_iter()
+            .flatten()
+            .map(|node| node.certificate())
+            .collect();
+        let nodes = authors
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| round != 3 || *index != authors.len() - 1)
+            .map(|(_, author)| new_certified_node(round, *author, parents.clone()))
+            .collect();
+        rounds.push(nodes);
+    }
+    rounds
+}
+
+/// Everything the DAG serializes about its content: the exported rounds, the round digests, the
+/// content digest and the summary of the epoch.
+fn serialize(dag: &Dag) -> Vec<u8> {
+    let mut cursor = dag.export_cursor();
+    let mut exported = vec![];
+    loop {
+        match cursor.next_round(dag) {
+            ExportStep::Nodes(round, nodes) => {
+                let nodes: Vec<_> = nodes.iter().map(|node| node.as_ref().clone()).collect();
+                exported.push((round, nodes));
+            },
+            ExportStep::Retry(round) => panic!("round {} changed during the export", round),
+            ExportStep::Done => break,
+        }
+    }
+    bcs::to_bytes(&(
+        exported,
+        dag.round_digests(0),
+        dag.content_digest(None),
+        dag.epoch_summary(),
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_serialization_is_canonical() {
+    let (epoch_state, authors) = epoch_state();
+    let rounds = rounds(&authors);
+    let mut in_order = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    for node in rounds.iter().flatten() {
+        assert!(in_order.add_node(node.clone()).is_ok());
+    }
+    // the same nodes, from the last validator to the first in every round
+    let storage = Arc::new(MockStorage::new());
+    let mut reversed = TestDag::new(epoch_state.clone(), storage.clone());
+    for nodes in &rounds {
+        for node in nodes.iter().rev() {
+            assert!(reversed.add_node(node.clone()).is_ok());
+        }
+    }
+
+    let bytes = serialize(&in_order);
+    assert_eq!(serialize(&in_order), bytes);
+    assert_eq!(serialize(&reversed), bytes);
+    let rebuilt = Dag::new(epoch_state, storage);
+    assert_eq!(serialize(&rebuilt), bytes);
+}
+
+#[test]
+fn test_audit_reconciles_equivocations_canonically() {
+    let (epoch_state, authors) = epoch_state();
+    let mut conflicting: Vec<_> = (0..4)
+        .map(|timestamp| {
+            let node = Node::new(
+                ChainId::test(),
+                1,
+                1,
+                authors[0],
+                timestamp,
+                Payload::empty(false),
+                vec![],
+            );
+            CertifiedNode::new(node, AggregateSignature::empty())
+        })
+        .collect();
+    conflicting.sort_by_key(|node| node.digest());
+    let others: Vec<_> = authors[1..]
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+
+    // each storage iterates the nodes in its own order
+    for _ in 0..8 {
+        let storage = Arc::new(MockStorage::new());
+        let dag = RwLock::new(Dag::new(epoch_state.clone(), storage.clone()));
+        for node in &others {
+            assert!(dag.write().add_node(node.clone()).is_ok());
+        }
+        for node in &conflicting {
+            assert!(storage.save_certified_node(node).is_ok());
+        }
+        let report = Dag::audit_against_storage(&dag).unwrap();
+        assert_eq!(report.num_loaded, 1);
+        assert_eq!(
+            report.unrecoverable,
+            conflicting[1..]
+                .iter()
+                .map(|node| node.digest())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            dag.read()
+                .get_node_by_round_author(1, &authors[0])
+                .unwrap()
+                .digest(),
+            conflicting[0].digest()
+        );
+    }
+}
+
+#[test]
+fn test_consistency_violations_by_validator_index() {
+    let (epoch_state, authors) = epoch_state();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert!(dag
+        .add_node(new_certified_node(1, authors[0], vec![]))
+        .is_ok());
+    // references no node links to, left from the last validator to the first
+    for author in authors.iter().rev() {
+        dag.insert_stale_reference(new_certified_node(2, *author, vec![]).metadata());
+    }
+    let violations = dag.verify_consistency().unwrap_err();
+    assert_eq!(violations.len(), authors.len());
+    for (violation, author) in violations.iter().zip(&authors) {
+        assert!(
+            violation.contains(&author.to_string()),
+            "{} should be about {}",
+            violation,
+            author
+        );
+    }
+}
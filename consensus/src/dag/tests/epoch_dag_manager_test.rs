@@ -0,0 +1,499 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        anchor_election::RoundRobinAnchorElection,
+        dag_store::{Dag, DagEpochSummary, DagStoreError, DagStoreMode},
+        epoch_dag_manager::EpochDagManager,
+        skip_round_tracker::SkipRoundTracker,
+        storage::DAGStorage,
+        store_config::DagStoreConfig,
+        tests::{
+            dag_test::MockStorage,
+            helpers::{new_certified_node, new_epoch_certified_node},
+        },
+        types::{CertifiedNode, Node, SkipRound, SkipVote},
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::{Payload, Round};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn test_epoch_transitions() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = |epoch| {
+        Arc::new(EpochState {
+            epoch,
+            verifier: validator_verifier.clone(),
+        })
+    };
+    let storage = Arc::new(MockStorage::new());
+    let manager = Arc::new(
+        EpochDagManager::new(
+            epoch_state(1),
+            ChainId::test(),
+            storage.clone(),
+            Arc::new(SimulatedTimeService::new()),
+            DagStoreMode::Strict,
+            DagStoreConfig::default(),
+        )
+        .unwrap(),
+    );
+    let inserted = Arc::new(Mutex::new(vec![]));
+    let done = Arc::new(AtomicBool::new(false));
+
+    // every validator extends its own chain of nodes in whatever epoch is current
+    let inserters: Vec<_> = signers
+        .iter()
+        .map(|signer| {
+            let author = signer.author();
+            let manager = manager.clone();
+            let inserted = inserted.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut last: Option<CertifiedNode> = None;
+                while !done.load(Ordering::Relaxed) {
+                    let dag = manager.current();
+                    let mut dag = dag.write();
+                    let epoch = dag.epoch_state().epoch;
+                    let parents: Vec<_> = last
+                        .iter()
+                        .filter(|node| node.metadata().epoch() == epoch)
+                        .map(|node| node.certificate())
+                        .collect();
+                    let round = parents
+                        .first()
+                        .map_or(1, |parent| parent.metadata().round() + 1);
+                    let node = new_epoch_certified_node(epoch, round, author, parents);
+                    match dag.add_node(node.clone()) {
+                        Ok(()) => {
+                            inserted.lock().push((epoch, node.digest()));
+                            last = Some(node);
+                        },
+                        Err(DagStoreError::EpochEnded(ended)) => assert_eq!(ended, epoch),
+                        Err(e) => panic!("unexpected error {}", e),
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let wait_for_inserts = |epoch| {
+        while !inserted.lock().iter().any(|(e, _)| *e == epoch) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+    wait_for_inserts(1);
+    let dag = manager.start_new_epoch(epoch_state(2)).unwrap();
+    assert_eq!(dag.read().epoch_state().epoch, 2);
+    wait_for_inserts(2);
+    let dag = manager.start_new_epoch(epoch_state(3)).unwrap();
+    assert_eq!(dag.read().epoch_state().epoch, 3);
+    wait_for_inserts(3);
+    done.store(true, Ordering::Relaxed);
+    for inserter in inserters {
+        inserter.join().unwrap();
+    }
+
+    assert!(matches!(
+        manager.start_new_epoch(epoch_state(3)),
+        Err(DagStoreError::EpochMismatch {
+            epoch: 3,
+            expected: 4
+        })
+    ));
+
+    // every successful insert landed in the DAG of its epoch, none after finalization
+    let inserted = inserted.lock();
+    let current = manager.current();
+    let previous = manager.previous().unwrap();
+    assert!(previous.read().is_ended());
+    assert!(!current.read().is_ended());
+    let count = |epoch| inserted.iter().filter(|(e, _)| *e == epoch).count();
+    assert_eq!(previous.read().memory_usage().num_nodes, count(2));
+    assert_eq!(current.read().memory_usage().num_nodes, count(3));
+    for (epoch, digest) in inserted.iter() {
+        match epoch {
+            2 => assert!(previous.read().exists(digest)),
+            3 => assert!(current.read().exists(digest)),
+            _ => {},
+        }
+    }
+
+    // the previous epochs are deleted from storage
+    let persisted = storage.get_certified_nodes().unwrap();
+    assert_eq!(persisted.len(), count(3));
+    assert!(persisted.values().all(|node| node.metadata().epoch() == 3));
+
+    let late = new_epoch_certified_node(2, 1, signers[0].author(), vec![]);
+    assert!(matches!(
+        previous.write().add_node_or_buffer(late),
+        Err(DagStoreError::EpochEnded(2))
+    ));
+}
+
+#[test]
+fn test_epoch_summary() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = |epoch| {
+        Arc::new(EpochState {
+            epoch,
+            verifier: validator_verifier.clone(),
+        })
+    };
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let manager = EpochDagManager::new(
+        epoch_state(1),
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+        DagStoreConfig::default(),
+    )
+    .unwrap();
+    let dag = manager.current();
+    let mut dag = dag.write();
+    let add_round = |dag: &mut Dag, round: Round, indices: &[usize]| {
+        let parents = match round {
+            1 => vec![],
+            _ => dag.strong_links_for_round(round - 1).unwrap(),
+        };
+        for index in indices {
+            assert!(dag
+                .add_node(new_certified_node(round, authors[*index], parents.clone()))
+                .is_ok());
+        }
+    };
+    let order = |dag: &mut Dag, round: Round, index: usize| {
+        let anchor = dag
+            .get_node_by_round_author(round, &authors[index])
+            .unwrap()
+            .metadata()
+            .clone();
+        let budget = dag.traversal_budget();
+        assert!(dag.order_anchor(&anchor, None, budget).is_ok());
+    };
+
+    // the anchor of round 2, validator 1, misses its round and the round is skipped
+    add_round(&mut dag, 1, &[0, 1, 2, 3]);
+    add_round(&mut dag, 2, &[0, 2, 3]);
+    order(&mut dag, 1, 0);
+    let mut tracker = SkipRoundTracker::new(
+        epoch_state(1),
+        Arc::new(RoundRobinAnchorElection::new(authors.clone())),
+        storage.clone(),
+    )
+    .unwrap();
+    let certificate = signers[0..3]
+        .iter()
+        .filter_map(|signer| {
+            tracker
+                .add_vote(SkipVote::new(SkipRound::new(1, 2, authors[1]), signer).unwrap())
+                .unwrap()
+        })
+        .next()
+        .unwrap();
+    assert!(dag.mark_round_skipped(2, certificate.clone()).is_ok());
+    assert!(dag.mark_round_skipped(2, certificate).is_ok());
+    add_round(&mut dag, 3, &[0, 1, 2, 3]);
+    // the same equivocation twice
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            3,
+            authors[0],
+            1,
+            Payload::empty(false),
+            dag.strong_links_for_round(2).unwrap(),
+        ),
+        AggregateSignature::empty(),
+    );
+    for _ in 0..2 {
+        assert!(matches!(
+            dag.add_node(equivocation.clone()),
+            Err(DagStoreError::EquivocateNode)
+        ));
+    }
+    add_round(&mut dag, 4, &[0, 1, 2, 3]);
+    order(&mut dag, 3, 1);
+    let node_bytes = dag.memory_usage().node_bytes;
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+    let pruned_bytes = (node_bytes - dag.memory_usage().node_bytes) as u64;
+    drop(dag);
+
+    // nothing is committed, the remnant has to be taken first
+    assert!(matches!(
+        manager.start_new_epoch(epoch_state(2)),
+        Err(DagStoreError::RemnantNotTaken { epoch: 1, .. })
+    ));
+    assert!(manager.finalize_epoch().is_ok());
+    assert!(manager.start_new_epoch(epoch_state(2)).is_ok());
+    let summary = manager.previous_summary().unwrap();
+    let by_author =
+        |counts: [u64; 4]| -> BTreeMap<_, _> { authors.iter().copied().zip(counts).collect() };
+    assert_eq!(summary, DagEpochSummary {
+        epoch: 1,
+        num_nodes: 15,
+        num_rounds: 4,
+        nodes_by_author: by_author([4, 3, 4, 4]),
+        missed_rounds_by_author: by_author([0, 1, 0, 0]),
+        num_equivocations: 1,
+        num_ordered_anchors: 2,
+        num_skipped_anchors: 1,
+        // both anchors are ordered one round after theirs
+        total_commit_latency_rounds: 2,
+        pruned_bytes,
+    });
+    assert_eq!(summary.average_commit_latency(), Some(1.0));
+    assert_eq!(storage.epoch_summaries(), BTreeMap::from([(1, summary)]));
+}
+
+#[test]
+fn test_equivocators_reset_with_epoch() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = |epoch| {
+        Arc::new(EpochState {
+            epoch,
+            verifier: validator_verifier.clone(),
+        })
+    };
+    let storage = Arc::new(MockStorage::new());
+    let manager = EpochDagManager::new(
+        epoch_state(1),
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+        DagStoreConfig::default(),
+    )
+    .unwrap();
+    let dag = manager.current();
+    assert!(dag
+        .write()
+        .add_node(new_certified_node(1, authors[0], vec![]))
+        .is_ok());
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            authors[0],
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        dag.write().add_node(equivocation),
+        Err(DagStoreError::EquivocateNode)
+    ));
+    assert!(dag.read().is_equivocator(&authors[0]));
+    assert_eq!(storage.get_equivocators().unwrap().len(), 1);
+
+    let dag = manager.start_new_epoch(epoch_state(2)).unwrap();
+    assert!(dag.read().equivocators().is_empty());
+    assert!(storage.get_equivocators().unwrap().is_empty());
+    assert!(dag
+        .write()
+        .add_node(new_epoch_certified_node(2, 1, authors[0], vec![]))
+        .is_ok());
+    assert_eq!(dag.read().bitmask(), vec![vec![true, false, false, false]]);
+}
+
+#[test]
+fn test_second_epoch_start_resets_epoch_state() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = |epoch| {
+        Arc::new(EpochState {
+            epoch,
+            verifier: validator_verifier.clone(),
+        })
+    };
+    let storage = Arc::new(MockStorage::new());
+    let manager = EpochDagManager::new(
+        epoch_state(1),
+        ChainId::test(),
+        storage.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+        DagStoreConfig::default(),
+    )
+    .unwrap();
+    // every epoch has an equivocator and a reservation of its own
+    let fill = |dag_lock: &RwLock<Dag>, epoch: u64, equivocator: usize| {
+        let mut dag = dag_lock.write();
+        for author in &authors {
+            assert!(dag
+                .add_node(new_epoch_certified_node(epoch, 1, *author, vec![]))
+                .is_ok());
+        }
+        let equivocation = CertifiedNode::new(
+            Node::new(
+                ChainId::test(),
+                epoch,
+                1,
+                authors[equivocator],
+                1,
+                Payload::empty(false),
+                vec![],
+            ),
+            AggregateSignature::empty(),
+        );
+        assert!(matches!(
+            dag.add_node(equivocation),
+            Err(DagStoreError::EquivocateNode)
+        ));
+        let parents = dag.strong_links_for_round(1).unwrap();
+        let next = new_epoch_certified_node(epoch, 2, authors[0], parents);
+        drop(dag);
+        assert!(Dag::reserve_self_slot(dag_lock, &next).is_ok());
+    };
+
+    fill(&manager.current(), 1, 0);
+    let dag = manager.start_new_epoch(epoch_state(2)).unwrap();
+    fill(&dag, 2, 1);
+    let dag = manager.start_new_epoch(epoch_state(3)).unwrap();
+
+    // the second start carries epoch 2 only
+    let previous = manager.previous().unwrap();
+    assert_eq!(previous.read().epoch_state().epoch, 2);
+    assert!(previous.read().is_equivocator(&authors[1]));
+    assert!(!previous.read().is_equivocator(&authors[0]));
+    let summary = manager.previous_summary().unwrap();
+    assert_eq!(summary.epoch, 2);
+    assert_eq!(summary.num_equivocations, 1);
+    assert!(manager.take_recovered_remnants().is_empty());
+
+    // and resets every index of epoch 3, in memory and in storage
+    let dag = dag.read();
+    assert_eq!(dag.memory_usage().num_nodes, 0);
+    assert!(dag.equivocators().is_empty());
+    assert!(authors
+        .iter()
+        .all(|author| dag.equivocation_evidence(author).is_empty()));
+    assert_eq!(dag.self_reservation(2), None);
+    assert!(storage.get_equivocators().unwrap().is_empty());
+    assert!(storage.get_equivocation_evidence().unwrap().is_empty());
+    assert!(storage.get_self_reservations().unwrap().is_empty());
+    assert!(storage.get_certified_nodes().unwrap().is_empty());
+}
+
+#[test]
+fn test_epoch_remnant_carried_over() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = |epoch| {
+        Arc::new(EpochState {
+            epoch,
+            verifier: validator_verifier.clone(),
+        })
+    };
+    let storage = Arc::new(MockStorage::new());
+    let new_manager = |epoch| {
+        EpochDagManager::new(
+            epoch_state(epoch),
+            ChainId::test(),
+            storage.clone(),
+            Arc::new(SimulatedTimeService::new()),
+            DagStoreMode::Strict,
+            DagStoreConfig::default(),
+        )
+        .unwrap()
+    };
+    let manager = new_manager(1);
+    let dag = manager.current();
+    let mut dag = dag.write();
+    for round in 1..=4 {
+        let parents = match round {
+            1 => vec![],
+            _ => dag.strong_links_for_round(round - 1).unwrap(),
+        };
+        for author in &authors {
+            assert!(dag
+                .add_node(new_certified_node(round, *author, parents.clone()))
+                .is_ok());
+        }
+    }
+    let mut order = |round: Round| {
+        let anchor = dag
+            .get_node_by_round_author(round, &authors[round as usize - 1])
+            .unwrap()
+            .metadata()
+            .clone();
+        let budget = dag.traversal_budget();
+        dag.order_anchor(&anchor, None, budget).unwrap()
+    };
+    // the first anchor is committed, the anchors of rounds 2 and 3 are only ordered
+    order(1);
+    let ordered: Vec<_> = [order(2), order(3)]
+        .into_iter()
+        .flat_map(|batch| batch.into_nodes())
+        .map(|node| node.as_ref().clone())
+        .collect();
+    assert_eq!(ordered.len(), 8);
+    assert!(dag.commit_callback(1).is_ok());
+    drop(dag);
+
+    assert!(matches!(
+        manager.start_new_epoch(epoch_state(2)),
+        Err(DagStoreError::RemnantNotTaken {
+            epoch: 1,
+            num_anchors: 2
+        })
+    ));
+    let remnant = manager.finalize_epoch().unwrap();
+    assert_eq!(remnant.epoch(), 1);
+    let anchor_rounds: Vec<_> = remnant
+        .anchors()
+        .iter()
+        .map(|ordered_anchor| ordered_anchor.anchor().round())
+        .collect();
+    assert_eq!(anchor_rounds, vec![2, 3]);
+    assert_eq!(remnant.nodes(), ordered.as_slice());
+    assert_eq!(storage.get_epoch_remnants().unwrap(), vec![remnant.clone()]);
+
+    // the new epoch deletes the nodes of the old one, the remnant stays until it's committed
+    let dag = manager.start_new_epoch(epoch_state(2)).unwrap();
+    assert!(dag
+        .write()
+        .add_node(new_epoch_certified_node(2, 1, authors[0], vec![]))
+        .is_ok());
+    let persisted = storage.get_certified_nodes().unwrap();
+    assert!(persisted.values().all(|node| node.metadata().epoch() == 2));
+    assert_eq!(storage.get_epoch_remnants().unwrap(), vec![remnant.clone()]);
+
+    // a crash before the commit recovers the remnant, which has to be taken again
+    let manager = new_manager(2);
+    assert!(matches!(
+        manager.start_new_epoch(epoch_state(3)),
+        Err(DagStoreError::RemnantNotTaken {
+            epoch: 1,
+            num_anchors: 2
+        })
+    ));
+    assert_eq!(manager.take_recovered_remnants(), vec![remnant]);
+    assert!(manager.remnant_committed(1).is_ok());
+    assert!(storage.get_epoch_remnants().unwrap().is_empty());
+    assert!(new_manager(2).take_recovered_remnants().is_empty());
+    assert!(manager.start_new_epoch(epoch_state(3)).is_ok());
+}
@@ -0,0 +1,144 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::EvidenceRetention,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{equivocate, TestDag},
+    },
+    types::EvidenceNodes,
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    epoch_state::EpochState, validator_signer::ValidatorSigner,
+    validator_verifier::random_validator_verifier,
+};
+use std::sync::Arc;
+
+fn new_dag(
+    evidence_retention: EvidenceRetention,
+) -> (TestDag, Arc<MockStorage>, Vec<ValidatorSigner>) {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state, storage.clone());
+    dag.set_evidence_retention(evidence_retention);
+    (dag, storage, signers)
+}
+
+fn conflicting_digests(dag: &TestDag, author: &Author) -> Vec<HashValue> {
+    dag.equivocation_evidence(author)
+        .iter()
+        .map(|record| *record.conflicting().digest())
+        .collect()
+}
+
+#[test]
+fn test_evidence_capped_per_author() {
+    let (mut dag, storage, signers) = new_dag(EvidenceRetention {
+        max_full_records_per_author: 2,
+        ..EvidenceRetention::default()
+    });
+    let (kept, conflicting) = equivocate(&mut dag, &signers, 0, 6);
+    let evidence = dag.equivocation_evidence(&signers[0].author());
+    assert_eq!(conflicting_digests(&dag, &signers[0].author()), conflicting);
+    assert!(evidence
+        .iter()
+        .all(|record| record.epoch() == 1 && record.round() == 1));
+    assert!(evidence
+        .iter()
+        .all(|record| record.kept().digest() == &kept.digest()));
+    // the first ones keep both nodes, the flood past the cap only the metadata
+    assert_eq!(
+        evidence
+            .iter()
+            .map(|record| record.is_full())
+            .collect::<Vec<_>>(),
+        vec![true, true, false, false, false, false]
+    );
+    match evidence[0].nodes() {
+        EvidenceNodes::Full { kept: node, .. } => assert_eq!(**node, kept),
+        EvidenceNodes::Metadata { .. } => panic!("expected the nodes"),
+    }
+
+    // the cap is per author
+    equivocate(&mut dag, &signers, 1, 1);
+    assert!(dag.equivocation_evidence(&signers[1].author())[0].is_full());
+    assert!(dag.equivocation_evidence(&signers[2].author()).is_empty());
+    let usage = dag.evidence_usage();
+    assert_eq!(usage.num_records, 7);
+    assert_eq!(usage.num_full_records, 3);
+    assert_eq!(usage.num_pending_report, 0);
+    assert!(usage.bytes > 0);
+    assert_eq!(dag.epoch_summary().num_equivocations, 7);
+    assert_eq!(storage.evidence_data.lock().len(), 7);
+}
+
+#[test]
+fn test_evidence_persisted_across_restart() {
+    let (mut dag, storage, signers) = new_dag(EvidenceRetention {
+        max_full_records_per_author: 1,
+        ..EvidenceRetention::default()
+    });
+    let (_, conflicting) = equivocate(&mut dag, &signers, 0, 3);
+    assert!(dag
+        .set_evidence_pending_report(&conflicting[2], true)
+        .unwrap());
+    let mut evidence = dag.equivocation_evidence(&signers[0].author());
+
+    let recovered = TestDag::new(dag.epoch_state().clone(), storage.clone());
+    let mut recovered_evidence = recovered.equivocation_evidence(&signers[0].author());
+    evidence.sort_by_key(|record| *record.conflicting().digest());
+    recovered_evidence.sort_by_key(|record| *record.conflicting().digest());
+    assert_eq!(recovered_evidence, evidence);
+    assert_eq!(recovered.evidence_usage(), dag.evidence_usage());
+    assert_eq!(recovered.equivocators().get(&signers[0].author()), Some(&1));
+
+    // the evidence of a previous epoch is dropped
+    let next_epoch = Arc::new(EpochState {
+        epoch: 2,
+        verifier: dag.epoch_state().verifier.clone(),
+    });
+    let dag = TestDag::new(next_epoch, storage.clone());
+    assert!(dag.equivocation_evidence(&signers[0].author()).is_empty());
+    assert!(storage.evidence_data.lock().is_empty());
+}
+
+#[test]
+fn test_pruning_spares_evidence_pending_report() {
+    let window: Round = 1;
+    let (mut dag, storage, signers) = new_dag(EvidenceRetention {
+        window,
+        ..EvidenceRetention::default()
+    });
+    let (_, reported) = equivocate(&mut dag, &signers, 0, 1);
+    let (_, unreported) = equivocate(&mut dag, &signers, 1, 1);
+    assert!(dag.set_evidence_pending_report(&reported[0], true).unwrap());
+    assert!(!dag
+        .set_evidence_pending_report(&HashValue::random(), true)
+        .unwrap());
+
+    // round 1 is still within the window
+    assert!(dag.prune_below(2).is_ok());
+    assert_eq!(dag.evidence_usage().num_records, 2);
+
+    assert!(dag.prune_below(2 + window).is_ok());
+    assert_eq!(conflicting_digests(&dag, &signers[0].author()), reported);
+    assert!(dag.equivocation_evidence(&signers[1].author()).is_empty());
+    assert!(!storage.evidence_data.lock().contains_key(&unreported[0]));
+    assert_eq!(dag.evidence_usage().num_pending_report, 1);
+
+    // once reported, the next pruning drops it
+    assert!(dag
+        .set_evidence_pending_report(&reported[0], false)
+        .unwrap());
+    assert!(!storage.evidence_data.lock()[&reported[0]].pending_report());
+    assert!(dag.prune_below(3 + window).is_ok());
+    assert_eq!(dag.evidence_usage().num_records, 0);
+    assert!(storage.evidence_data.lock().is_empty());
+}
@@ -0,0 +1,144 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{FetchPlan, InsertOutcome},
+    fetch_budget::{Admission, FetchBudget, FetchBudgetConfig},
+    tests::{
+        dag_test::MockStorage,
+        helpers::{generate_dag_nodes, new_certified_node, TestDag},
+    },
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+fn single_slot_plan(round: Round, author: Author) -> FetchPlan {
+    FetchPlan {
+        missing_slots: BTreeMap::from([(round, vec![author])]),
+        estimated_nodes: 1,
+    }
+}
+
+#[test]
+fn test_fetch_budget_caps_waves() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let links: Vec<_> = (0..126)
+        .map(|round| vec![Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }); 4])
+        .collect();
+    let nodes = generate_dag_nodes(&links, &authors);
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in nodes[0].iter().flatten() {
+        dag.add_node(node.clone()).unwrap();
+    }
+    assert_eq!(dag.fetch_gap(), 0);
+    // a node far ahead leaves rounds 2 to 126 to fetch
+    let tip = new_certified_node(
+        127,
+        authors[0],
+        nodes[125]
+            .iter()
+            .flatten()
+            .map(|node| node.certificate())
+            .collect(),
+    );
+    dag.add_node_or_buffer(tip.clone()).unwrap();
+    assert_eq!(dag.fetch_gap(), 500);
+
+    let mut budget = FetchBudget::from_dag(&dag, FetchBudgetConfig::default());
+    assert_eq!(budget.max_requests(), 8);
+    assert_eq!(budget.max_in_flight_nodes(), 100);
+
+    // one plan per missing slot, as a naive fetcher would send
+    let mut plans: VecDeque<_> = nodes[1..]
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|node| single_slot_plan(node.metadata().round(), *node.metadata().author()))
+        .collect();
+    let mut waves = vec![];
+    while !plans.is_empty() {
+        let mut wave = vec![];
+        while let Some(plan) = plans.front() {
+            match budget.admit(plan) {
+                Admission::Admitted(_) => wave.push(plans.pop_front().unwrap()),
+                Admission::Deferred => break,
+            }
+        }
+        assert_eq!(budget.in_flight_requests(), wave.len());
+        waves.push(wave.len());
+        // the responses arrive and release the budget
+        for plan in wave {
+            for (round, slot_authors) in &plan.missing_slots {
+                for author in slot_authors {
+                    let index = authors.iter().position(|a| a == author).unwrap();
+                    let node = nodes[*round as usize - 1][index].clone().unwrap();
+                    let metadata = node.metadata().clone();
+                    let outcome = dag.insert_node(node);
+                    assert!(matches!(outcome, InsertOutcome::Inserted));
+                    budget.on_insert(&metadata, &outcome);
+                }
+            }
+        }
+        assert_eq!(budget.in_flight_requests(), 0);
+        assert_eq!(budget.in_flight_nodes(), 0);
+    }
+    assert_eq!(waves.len(), 63);
+    assert!(waves[..62].iter().all(|wave| *wave == 8));
+    assert_eq!(waves[62], 4);
+    assert!(dag.exists(&tip.digest()));
+    assert_eq!(dag.fetch_gap(), 0);
+}
+
+#[test]
+fn test_fetch_budget_admission() {
+    let authors: Vec<_> = (0..4).map(|_| Author::random()).collect();
+    let mut budget = FetchBudget::new(2, 3);
+    let plan = FetchPlan {
+        missing_slots: BTreeMap::from([(1, authors[0..2].to_vec())]),
+        estimated_nodes: 2,
+    };
+    let ticket = match budget.admit(&plan) {
+        Admission::Admitted(ticket) => ticket,
+        Admission::Deferred => panic!("plan should be admitted"),
+    };
+    // the slots are already in flight
+    assert_eq!(budget.admit(&plan), Admission::Deferred);
+    // over the node budget
+    let big_plan = FetchPlan {
+        missing_slots: BTreeMap::from([(2, authors.clone())]),
+        estimated_nodes: 4,
+    };
+    assert_eq!(budget.admit(&big_plan), Admission::Deferred);
+    assert!(matches!(
+        budget.admit(&single_slot_plan(2, authors[0])),
+        Admission::Admitted(_)
+    ));
+    // over the request budget
+    assert_eq!(
+        budget.admit(&single_slot_plan(3, authors[0])),
+        Admission::Deferred
+    );
+    assert_eq!(budget.in_flight_nodes(), 3);
+
+    // a failed request gives its budget back
+    budget.release(ticket);
+    assert_eq!(budget.in_flight_requests(), 1);
+    assert_eq!(budget.in_flight_nodes(), 1);
+    let node = new_certified_node(2, authors[0], vec![]);
+    budget.on_insert(node.metadata(), &InsertOutcome::AlreadyPresent);
+    assert_eq!(budget.in_flight_nodes(), 1);
+    budget.on_insert(node.metadata(), &InsertOutcome::Inserted);
+    assert_eq!(budget.in_flight_requests(), 0);
+    // a plan bigger than the node budget goes alone
+    assert!(matches!(budget.admit(&big_plan), Admission::Admitted(_)));
+    assert_eq!(budget.in_flight_nodes(), 4);
+}
@@ -0,0 +1,348 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Golden scenarios guarding the ordering against changes across releases, a validator upgrading
+//! mid-epoch must order the DAG exactly like the ones still on the previous release. Each
+//! scenario is a node set in `golden/<name>.bcs`, generated once from a fixed seed, and the
+//! outcome of every anchor round in `golden/<name>.expected`. Running the tests with
+//! `UPDATE_GOLDENFILES=1` rewrites the expected outcomes from the node sets as they are, and
+//! generates the node sets that are missing. A node set is only regenerated once deleted, so a
+//! change of the generator doesn't silently replace the corpus.
+
+use crate::dag::{
+    anchor_election::{AnchorElection, RoundRobinAnchorElection},
+    round_schedule::RoundSchedule,
+    skip_round_tracker::SkipRoundTracker,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, SkipCertificate, SkipRound, SkipVote},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    epoch_state::EpochState,
+    validator_verifier::{random_validator_verifier, ValidatorConsensusInfo, ValidatorVerifier},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, env, fs, path::PathBuf, sync::Arc};
+
+/// How the node set of a scenario is generated.
+struct ScenarioSpec {
+    name: &'static str,
+    /// Voting power of each validator
+    powers: &'static [u64],
+    num_rounds: Round,
+    seed: u64,
+    /// The slots without a node, by round and validator index
+    absent: &'static [(Round, usize)],
+    /// Anchor rounds abandoned with a skip certificate, their anchor must be absent
+    skipped: &'static [Round],
+}
+
+const SCENARIOS: &[ScenarioSpec] = &[
+    // the validators missing rounds are caught up with weak links
+    ScenarioSpec {
+        name: "weak_links",
+        powers: &[1, 1, 1, 1],
+        num_rounds: 12,
+        seed: 1,
+        absent: &[(3, 2), (4, 2), (6, 0), (9, 3)],
+        skipped: &[],
+    },
+    // the anchor of round 5 never shows up, the anchor of round 7 orders its round
+    ScenarioSpec {
+        name: "skipped_anchor",
+        powers: &[1, 1, 1, 1],
+        num_rounds: 10,
+        seed: 2,
+        absent: &[(5, 2), (8, 1)],
+        skipped: &[5],
+    },
+    // two validators hold most of the stake, a quorum can miss the two others
+    ScenarioSpec {
+        name: "weighted_stake",
+        powers: &[5, 3, 1, 1],
+        num_rounds: 10,
+        seed: 3,
+        absent: &[(2, 3), (4, 2), (4, 3), (7, 3)],
+        skipped: &[],
+    },
+];
+
+/// The content of `golden/<name>.bcs`, the nodes in their order of arrival.
+#[derive(Serialize, Deserialize)]
+struct GoldenScenario {
+    epoch_state: EpochState,
+    nodes: Vec<CertifiedNode>,
+    skips: Vec<SkipCertificate>,
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/dag/tests/golden")
+}
+
+/// Every node links to a random set of the previous round with a quorum of voting power, and to
+/// the nodes two rounds back of the validators its strong links miss and don't reach. The nodes
+/// arrive shuffled two rounds at a time, within the park gap of the DAG.
+fn generate(spec: &ScenarioSpec) -> GoldenScenario {
+    let mut rng = StdRng::seed_from_u64(spec.seed);
+    let (signers, _) = random_validator_verifier(spec.powers.len(), None, false);
+    let verifier = ValidatorVerifier::new(
+        signers
+            .iter()
+            .zip(spec.powers)
+            .map(|(signer, power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), *power)
+            })
+            .collect(),
+    );
+    let epoch_state = EpochState { epoch: 1, verifier };
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+
+    let mut rounds: Vec<Vec<Option<CertifiedNode>>> = vec![];
+    for round in 1..=spec.num_rounds {
+        let round_nodes = (0..authors.len())
+            .map(|index| {
+                if spec.absent.contains(&(round, index)) {
+                    return None;
+                }
+                let parents = match rounds.last() {
+                    Some(previous) => {
+                        let mut candidates: Vec<_> = previous.iter().flatten().collect();
+                        candidates.shuffle(&mut rng);
+                        let mut strong_links: Vec<&CertifiedNode> = vec![];
+                        for candidate in candidates {
+                            let quorum = epoch_state
+                                .verifier
+                                .check_voting_power(strong_links.iter().map(|node| node.author()))
+                                .is_ok();
+                            if !quorum || rng.gen_bool(0.5) {
+                                strong_links.push(candidate);
+                            }
+                        }
+                        assert!(
+                            epoch_state
+                                .verifier
+                                .check_voting_power(strong_links.iter().map(|node| node.author()))
+                                .is_ok(),
+                            "round {} of {} has no quorum",
+                            round - 1,
+                            spec.name
+                        );
+                        let linked: HashSet<_> = strong_links
+                            .iter()
+                            .flat_map(|node| node.parents())
+                            .map(|parent| *parent.metadata().digest())
+                            .collect();
+                        let weak_links: Vec<_> = rounds
+                            .iter()
+                            .rev()
+                            .nth(1)
+                            .into_iter()
+                            .flatten()
+                            .flatten()
+                            .filter(|node| {
+                                strong_links
+                                    .iter()
+                                    .all(|strong| strong.author() != node.author())
+                                    && !linked.contains(&node.digest())
+                            })
+                            .collect();
+                        let mut parents: Vec<_> = strong_links
+                            .into_iter()
+                            .chain(weak_links)
+                            .map(|node| node.certificate())
+                            .collect();
+                        parents.sort_by_key(|parent| {
+                            (
+                                parent.metadata().round(),
+                                authors
+                                    .iter()
+                                    .position(|author| author == parent.metadata().author()),
+                            )
+                        });
+                        parents
+                    },
+                    None => vec![],
+                };
+                Some(new_certified_node(round, authors[index], parents))
+            })
+            .collect();
+        rounds.push(round_nodes);
+    }
+    let mut nodes = vec![];
+    for window in rounds.chunks(2) {
+        let mut arrivals: Vec<_> = window.iter().flatten().flatten().cloned().collect();
+        arrivals.shuffle(&mut rng);
+        nodes.extend(arrivals);
+    }
+
+    let epoch_state = Arc::new(epoch_state);
+    let anchor_election = Arc::new(RoundRobinAnchorElection::new(authors));
+    let mut tracker = SkipRoundTracker::new(
+        epoch_state.clone(),
+        anchor_election.clone(),
+        Arc::new(MockStorage::new()),
+    )
+    .unwrap();
+    let skips = spec
+        .skipped
+        .iter()
+        .map(|round| {
+            let skip_round = SkipRound::new(1, *round, anchor_election.get_anchor(*round));
+            signers
+                .iter()
+                .find_map(|signer| {
+                    tracker
+                        .add_vote(SkipVote::new(skip_round.clone(), signer).unwrap())
+                        .unwrap()
+                })
+                .expect("all the validators skip the round")
+        })
+        .collect();
+    GoldenScenario {
+        epoch_state: epoch_state.as_ref().clone(),
+        nodes,
+        skips,
+    }
+}
+
+/// Inserts the nodes in their order of arrival, then walks the anchor rounds: skips the round
+/// of a skip certificate, and orders the anchor elected for the round if it's in the DAG. One
+/// line per anchor round, then the content digest of the DAG.
+fn replay(scenario: &GoldenScenario) -> Vec<String> {
+    let epoch_state = Arc::new(scenario.epoch_state.clone());
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let index_of = |author: &Author| authors.iter().position(|a| a == author).unwrap();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in &scenario.nodes {
+        let outcome = dag.insert_node(node.clone());
+        assert_ne!(
+            outcome.label(),
+            "rejected",
+            "node {} of round {} rejected: {:?}",
+            node.digest(),
+            node.metadata().round(),
+            outcome
+        );
+    }
+    assert_eq!(dag.pending_nodes_count(), 0, "nodes left pending");
+
+    let anchor_election = RoundRobinAnchorElection::new(authors.clone());
+    let mut lines = vec![];
+    for round in RoundSchedule::default().anchor_rounds_between(1, dag.highest_round()) {
+        let author = anchor_election.get_anchor(round);
+        let prefix = format!("round {} anchor {}", round, index_of(&author));
+        let skip = scenario
+            .skips
+            .iter()
+            .find(|skip| skip.skip().round() == round);
+        if let Some(skip) = skip {
+            dag.mark_round_skipped(round, skip.clone()).unwrap();
+            lines.push(format!("{}: skipped", prefix));
+            continue;
+        }
+        let anchor = match dag.get_node_by_round_author(round, &author) {
+            Some(anchor) => anchor.metadata().clone(),
+            None => {
+                lines.push(format!("{}: missing", prefix));
+                continue;
+            },
+        };
+        match dag.order_anchor(&anchor, None, dag.traversal_budget()) {
+            Ok(batch) => {
+                let slots: Vec<_> = batch
+                    .nodes()
+                    .iter()
+                    .map(|node| format!("{}:{}", node.metadata().round(), index_of(node.author())))
+                    .collect();
+                let digests: Vec<_> = batch
+                    .nodes()
+                    .iter()
+                    .flat_map(|node| node.digest().to_vec())
+                    .collect();
+                lines.push(format!(
+                    "{}: ordered {:x} [{}]",
+                    prefix,
+                    HashValue::sha3_256_of(&digests),
+                    slots.join(" ")
+                ));
+            },
+            Err(e) => lines.push(format!("{}: refused, {}", prefix, e)),
+        }
+    }
+    lines.push(format!("content {:x}", dag.content_digest(None)));
+    lines
+}
+
+/// The first line the output differs from the golden at.
+fn first_difference(expected: &str, output: &[String]) -> Option<String> {
+    let expected: Vec<_> = expected.lines().collect();
+    (0..expected.len().max(output.len())).find_map(|line| {
+        let (expected, got) = (expected.get(line), output.get(line));
+        (expected.copied() != got.map(String::as_str)).then(|| {
+            format!(
+                "line {}: expected `{}`, got `{}`",
+                line + 1,
+                expected.unwrap_or(&"<end>"),
+                got.map_or("<end>", String::as_str)
+            )
+        })
+    })
+}
+
+#[test]
+fn test_golden_scenarios() {
+    let bless = env::var("UPDATE_GOLDENFILES").map_or(false, |value| value == "1");
+    let dir = golden_dir();
+    let mut failures = vec![];
+    for spec in SCENARIOS {
+        let scenario_path = dir.join(format!("{}.bcs", spec.name));
+        let expected_path = dir.join(format!("{}.expected", spec.name));
+        let scenario = match fs::read(&scenario_path) {
+            Ok(bytes) => bcs::from_bytes(&bytes)
+                .unwrap_or_else(|e| panic!("{} is corrupted: {}", scenario_path.display(), e)),
+            Err(_) if bless => {
+                let scenario = generate(spec);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(&scenario_path, bcs::to_bytes(&scenario).unwrap()).unwrap();
+                scenario
+            },
+            Err(_) => {
+                failures.push(format!(
+                    "{}: {} is missing",
+                    spec.name,
+                    scenario_path.display()
+                ));
+                continue;
+            },
+        };
+        let output = replay(&scenario);
+        if bless {
+            fs::write(&expected_path, output.join("\n") + "\n").unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) => {
+                if let Some(difference) = first_difference(&expected, &output) {
+                    failures.push(format!("{}: ordering changed, {}", spec.name, difference));
+                }
+            },
+            Err(_) => failures.push(format!(
+                "{}: {} is missing",
+                spec.name,
+                expected_path.display()
+            )),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nThe ordering of the golden scenarios must not change across releases. If the change \
+         is intended, bless it with UPDATE_GOLDENFILES=1 and review the diff of {}",
+        failures.join("\n"),
+        dir.display()
+    );
+}
@@ -1,15 +1,58 @@
 // Copyright © Aptos Foundation
 
-use crate::dag::types::{CertifiedNode, Node, NodeCertificate};
+use crate::dag::{
+    dag_store::{Dag, DagStoreError},
+    node_builder::CertifiedNodeBuilder,
+    tests::checked_dag::CheckedDag,
+    types::{CertifiedNode, Node, NodeCertificate},
+};
 use aptos_consensus_types::common::{Author, Payload, Round};
-use aptos_types::aggregate_signature::AggregateSignature;
+use aptos_crypto::HashValue;
+use aptos_infallible::RwLock;
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_signer::ValidatorSigner, validator_verifier::random_validator_verifier,
+};
+use std::{collections::BTreeSet, sync::Arc};
+
+/// The DAG of the suites driving it through `&mut self` calls, set to `Dag` to run them without
+/// the consistency checks.
+pub(crate) type TestDag = CheckedDag;
+
+/// Epoch 1 of `num_validators` random validators, with their ordered addresses.
+pub(crate) fn new_epoch_state(num_validators: usize) -> (Arc<EpochState>, Vec<Author>) {
+    let (_, validator_verifier) = random_validator_verifier(num_validators, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    (epoch_state, authors)
+}
 
 pub(crate) fn new_certified_node(
     round: Round,
     author: Author,
     parents: Vec<NodeCertificate>,
 ) -> CertifiedNode {
-    let node = Node::new(1, round, author, 0, Payload::empty(false), parents);
+    new_epoch_certified_node(1, round, author, parents)
+}
+
+pub(crate) fn new_epoch_certified_node(
+    epoch: u64,
+    round: Round,
+    author: Author,
+    parents: Vec<NodeCertificate>,
+) -> CertifiedNode {
+    let node = Node::new(
+        ChainId::test(),
+        epoch,
+        round,
+        author,
+        0,
+        Payload::empty(false),
+        parents,
+    );
     CertifiedNode::new(node, AggregateSignature::empty())
 }
 
@@ -19,5 +62,116 @@ pub(crate) fn new_node(
     author: Author,
     parents: Vec<NodeCertificate>,
 ) -> Node {
-    Node::new(0, round, author, timestamp, Payload::empty(false), parents)
+    Node::new(
+        ChainId::test(),
+        0,
+        round,
+        author,
+        timestamp,
+        Payload::empty(false),
+        parents,
+    )
+}
+
+/// Generates the certified nodes of a DAG starting at round 1, `links[r][i]` holds the validator
+/// indices of the previous round that validator i's node in round r + 1 links to, or `None` if
+/// validator i has no node in that round.
+pub(crate) fn generate_dag_nodes(
+    links: &[Vec<Option<Vec<usize>>>],
+    authors: &[Author],
+) -> Vec<Vec<Option<CertifiedNode>>> {
+    let mut dag: Vec<Vec<Option<CertifiedNode>>> = Vec::with_capacity(links.len());
+    for (round, round_links) in links.iter().enumerate() {
+        let round_nodes = round_links
+            .iter()
+            .enumerate()
+            .map(|(index, parents)| {
+                parents.as_ref().map(|parents| {
+                    let parents = parents
+                        .iter()
+                        .filter_map(|parent| dag.last()?[*parent].as_ref())
+                        .map(|node| node.certificate())
+                        .collect();
+                    new_certified_node(round as Round + 1, authors[index], parents)
+                })
+            })
+            .collect();
+        dag.push(round_nodes);
+    }
+    dag
+}
+
+/// Adds the node of `signers[index]` in round 1, then `count` nodes conflicting with it, signed by
+/// all the signers. Returns the kept node and the digests of the conflicting ones.
+#[track_caller]
+pub(crate) fn equivocate(
+    dag: &mut TestDag,
+    signers: &[ValidatorSigner],
+    index: usize,
+    count: u64,
+) -> (CertifiedNode, Vec<HashValue>) {
+    let kept = new_certified_node(1, signers[index].author(), vec![]);
+    assert!(dag.add_node(kept.clone()).is_ok());
+    let verifier = dag.epoch_state().verifier.clone();
+    let conflicting = (1..=count)
+        .map(|timestamp| {
+            let node = CertifiedNodeBuilder::new(&kept)
+                .timestamp(timestamp)
+                .build(signers, &verifier);
+            assert!(matches!(
+                dag.add_node(node.clone()),
+                Err(DagStoreError::EquivocateNode)
+            ));
+            node.digest()
+        })
+        .collect();
+    (kept, conflicting)
+}
+
+/// Inserts the node the way `DagDriver` does, written without holding the lock and linked in
+/// right after.
+pub(crate) fn insert_and_link(
+    dag: &RwLock<Dag>,
+    node: CertifiedNode,
+) -> Result<Arc<CertifiedNode>, DagStoreError> {
+    let inserted = Dag::insert_shared(dag, node);
+    let mut dropped = dag.write().link_inserted();
+    let inserted = inserted?;
+    match dropped.remove(&inserted.digest()) {
+        Some(e) => Err(e),
+        None => Ok(inserted),
+    }
+}
+
+/// Fails with the first difference between the content of the two DAGs: the floor, the first
+/// slot in round and author order, then the pending nodes and the equivocators.
+#[track_caller]
+pub(crate) fn assert_dag_equivalent(live: &Dag, recovered: &Dag) {
+    let live = live.content_view();
+    let recovered = recovered.content_view();
+    if live == recovered {
+        return;
+    }
+    assert_eq!(live.floor, recovered.floor, "floors differ");
+    let keys: BTreeSet<_> = live.slots.keys().chain(recovered.slots.keys()).collect();
+    for key @ (round, author) in keys {
+        let (live_slot, recovered_slot) = (live.slots.get(key), recovered.slots.get(key));
+        assert!(
+            live_slot == recovered_slot,
+            "round {} author {} differs, live: {:?}, recovered: {:?}",
+            round,
+            author,
+            live_slot,
+            recovered_slot
+        );
+    }
+    assert_eq!(live.pending, recovered.pending, "pending nodes differ");
+    assert_eq!(
+        live.equivocators, recovered.equivocators,
+        "equivocators differ"
+    );
+    assert_eq!(
+        live.last_committed_anchor, recovered.last_committed_anchor,
+        "last committed anchors differ"
+    );
 }
@@ -1,7 +1,38 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-mod dag_test;
+mod adversarial_node_test;
+mod anchor_continuity_test;
+mod batch_verify_test;
+mod broadcast_progress_test;
+mod catch_up_test;
+mod checked_dag;
+mod checked_dag_test;
+mod commit_latency_test;
+mod dag_driver_test;
+mod dag_fetcher_test;
+mod dag_health_test;
+mod dag_inspector_test;
+pub(super) mod dag_test;
+mod determinism_test;
+mod epoch_dag_manager_test;
+mod equivocation_evidence_test;
+mod fetch_budget_test;
+mod golden_test;
 mod helpers;
+mod node_timestamp_test;
+mod order_test;
+mod peer_tracker_test;
+mod pruned_filter_test;
+mod read_guard_test;
+mod recovered_indexes_test;
 mod reliable_broadcast_tests;
+mod replay_test;
+mod round_schedule_test;
+mod simulation_test;
+mod skip_round_tracker_test;
+mod slot_id_test;
+mod store_config_test;
 mod types_test;
+mod validator_index_test;
+mod wire_format_test;
@@ -0,0 +1,210 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::{AckDecision, Dag, DagStoreError, DEFAULT_TIMESTAMP_SKEW_TOLERANCE},
+        tests::{dag_test::MockStorage, helpers::new_epoch_state},
+        types::{CertifiedNode, Node, NodeCertificate, NodeMetadata},
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+};
+use std::{sync::Arc, time::Duration};
+
+fn new_dag(epoch_state: Arc<EpochState>, time_service: Arc<SimulatedTimeService>) -> Dag {
+    Dag::new_with_time_service(epoch_state, Arc::new(MockStorage::new()), time_service)
+}
+
+fn node_at(
+    round: Round,
+    author: Author,
+    timestamp: u64,
+    parents: Vec<NodeCertificate>,
+) -> CertifiedNode {
+    CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            round,
+            author,
+            timestamp,
+            Payload::empty(false),
+            parents,
+        ),
+        AggregateSignature::empty(),
+    )
+}
+
+/// The roots at 1 second, the one of the first author 10 microseconds later.
+fn roots(authors: &[Author]) -> Vec<CertifiedNode> {
+    authors
+        .iter()
+        .enumerate()
+        .map(|(index, author)| {
+            let timestamp = if index == 0 { 1_000_010 } else { 1_000_000 };
+            node_at(1, *author, timestamp, vec![])
+        })
+        .collect()
+}
+
+#[test]
+fn test_timestamp_skew_tolerance() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let mut dag = new_dag(epoch_state, Arc::new(SimulatedTimeService::new()));
+    let roots = roots(&authors);
+    for node in &roots {
+        dag.add_node(node.clone()).unwrap();
+    }
+    let parents: Vec<_> = roots.iter().map(|node| node.certificate()).collect();
+    let tolerance = DEFAULT_TIMESTAMP_SKEW_TOLERANCE.as_micros() as u64;
+    let latest = 1_000_010;
+
+    // earlier than the latest parent by the tolerance
+    let error = dag
+        .add_node(node_at(2, authors[0], latest - tolerance, parents.clone()))
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        DagStoreError::NonMonotonicTimestamp { timestamp, parent_timestamp, tolerance: skew }
+            if timestamp == latest - tolerance
+                && parent_timestamp == latest
+                && skew == DEFAULT_TIMESTAMP_SKEW_TOLERANCE
+    ));
+    // within it
+    dag.add_node(node_at(
+        2,
+        authors[0],
+        latest - tolerance + 1,
+        parents.clone(),
+    ))
+    .unwrap();
+
+    // without tolerance a node must be strictly later than its parents
+    dag.set_timestamp_skew_tolerance(Duration::ZERO);
+    assert!(matches!(
+        dag.add_node(node_at(2, authors[1], latest, parents.clone())),
+        Err(DagStoreError::NonMonotonicTimestamp { .. })
+    ));
+    dag.add_node(node_at(2, authors[1], latest + 1, parents))
+        .unwrap();
+}
+
+#[test]
+fn test_backdated_node_rejected() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = new_dag(epoch_state, time_service.clone());
+
+    // a root more than the drift ahead of local time, accepted once the clock catches up
+    let ahead = node_at(
+        1,
+        authors[0],
+        Duration::from_secs(61).as_micros() as u64,
+        vec![],
+    );
+    let error = dag.add_node(ahead.clone()).unwrap_err();
+    assert!(matches!(error, DagStoreError::TimestampAhead { .. }));
+    assert_eq!(error.ack_decision(), AckDecision::AckLater);
+    time_service.advance(Duration::from_secs(1));
+    dag.add_node(ahead.clone()).unwrap();
+
+    let roots = roots(&authors);
+    for node in &roots[1..] {
+        dag.add_node(node.clone()).unwrap();
+    }
+    let mut parents: Vec<_> = roots[1..].iter().map(|node| node.certificate()).collect();
+    parents.push(ahead.certificate());
+    // the bound holds for every node, not only the roots
+    let far_ahead = Duration::from_secs(62).as_micros() as u64;
+    assert!(matches!(
+        dag.add_node(node_at(2, authors[1], far_ahead, parents.clone())),
+        Err(DagStoreError::TimestampAhead { timestamp, .. }) if timestamp == far_ahead
+    ));
+    // backdated below the root from the future
+    let error = dag
+        .add_node(node_at(2, authors[1], 2_000_000, parents.clone()))
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        DagStoreError::NonMonotonicTimestamp { parent_timestamp, .. }
+            if parent_timestamp == ahead.metadata().timestamp()
+    ));
+    assert_eq!(error.ack_decision(), AckDecision::NeverAck);
+
+    // the timestamp a parent certificate claims doesn't count, the stored one does
+    let metadata = ahead.metadata();
+    parents.pop();
+    parents.push(NodeCertificate::new(
+        NodeMetadata::new_for_test(1, 1, *metadata.author(), 0, *metadata.digest()),
+        ahead.signatures().clone(),
+    ));
+    assert!(matches!(
+        dag.add_node(node_at(2, authors[1], 2_000_000, parents)),
+        Err(DagStoreError::NonMonotonicTimestamp { .. })
+    ));
+    assert!(dag.get_node_by_round_author(2, &authors[1]).is_none());
+}
+
+#[test]
+fn test_batch_timestamps_deterministic() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let mut rounds: Vec<Vec<CertifiedNode>> = vec![];
+    for round in 1..=4 {
+        let parents: Vec<_> = rounds
+            .last()
+            .map(|previous| previous.iter().map(|node| node.certificate()).collect())
+            .unwrap_or_default();
+        rounds.push(
+            authors
+                .iter()
+                .enumerate()
+                .map(|(index, author)| {
+                    let timestamp = round * 1_000_000 + index as u64 * 1_000;
+                    node_at(round, *author, timestamp, parents.clone())
+                })
+                .collect(),
+        );
+    }
+
+    // different local clocks and orders of arrival
+    let time_services = [Duration::ZERO, Duration::from_secs(3)].map(|offset| {
+        let time_service = Arc::new(SimulatedTimeService::new());
+        time_service.advance(offset);
+        time_service
+    });
+    let batch_timestamps: Vec<Vec<u64>> = time_services
+        .into_iter()
+        .enumerate()
+        .map(|(index, time_service)| {
+            let mut dag = new_dag(epoch_state.clone(), time_service);
+            for round_nodes in &rounds {
+                let mut arrivals = round_nodes.clone();
+                if index == 1 {
+                    arrivals.reverse();
+                }
+                for node in arrivals {
+                    dag.add_node(node).unwrap();
+                }
+            }
+            let first = rounds[1][1].metadata().clone();
+            let second = rounds[3][3].metadata().clone();
+            let budget = dag.traversal_budget();
+            [
+                dag.order_anchor(&first, None, budget).unwrap(),
+                dag.order_anchor(&second, None, budget).unwrap(),
+            ]
+            .iter()
+            .map(|batch| {
+                assert_eq!(batch.timestamp(), batch.anchor().timestamp());
+                batch.timestamp()
+            })
+            .collect()
+        })
+        .collect();
+    assert_eq!(batch_timestamps[0], vec![2_001_000, 4_003_000]);
+    assert_eq!(batch_timestamps[0], batch_timestamps[1]);
+}
@@ -0,0 +1,476 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    anchor_election::{AnchorElection, RoundRobinAnchorElection},
+    dag_store::{DagStoreError, OrderedChunk, TraversalBudget, DEFAULT_WINDOW_SIZE},
+    skip_round_tracker::SkipRoundTracker,
+    storage::DAGStorage,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{generate_dag_nodes, new_certified_node, TestDag},
+    },
+    types::{BatchSourceInfo, CertifiedNode, NodeMetadata, SkipCertificate, SkipRound, SkipVote},
+};
+use aptos_consensus_types::common::Round;
+use aptos_crypto::HashValue;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use proptest::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::{ops::ControlFlow, sync::Arc};
+
+const NUM_VALIDATORS: usize = 4;
+const NUM_INSTANCES: usize = 4;
+
+/// Every round has at least 2f + 1 nodes and every node links to at least 2f + 1 nodes of the
+/// previous round. For each round, `missing` is the validator without a node (none if out of
+/// range) and `dropped` decides which parent each node skips when the previous round is full.
+fn dag_links(shape: &[(usize, usize)]) -> Vec<Vec<Option<Vec<usize>>>> {
+    let mut links = vec![];
+    let mut prev_round: Vec<usize> = vec![];
+    for (missing, dropped) in shape {
+        let authors: Vec<usize> = (0..NUM_VALIDATORS).filter(|i| i != missing).collect();
+        let round_links = (0..NUM_VALIDATORS)
+            .map(|index| {
+                authors.contains(&index).then(|| {
+                    let skip = (dropped + index) % (NUM_VALIDATORS + 1);
+                    prev_round
+                        .iter()
+                        .copied()
+                        .filter(|parent| prev_round.len() < NUM_VALIDATORS || *parent != skip)
+                        .collect()
+                })
+            })
+            .collect();
+        links.push(round_links);
+        prev_round = authors;
+    }
+    links
+}
+
+/// Inserts the nodes in the given arrival order and orders every anchor round, returning the
+/// concatenated digests of the ordered nodes and the content digest of the DAG.
+fn ordered_digests(
+    epoch_state: Arc<EpochState>,
+    mut nodes: Vec<CertifiedNode>,
+    seed: u64,
+    num_rounds: Round,
+) -> (Vec<u8>, HashValue) {
+    nodes.shuffle(&mut StdRng::seed_from_u64(seed));
+    let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    for node in nodes {
+        assert!(dag.add_node_or_buffer(node).is_ok());
+    }
+    assert_eq!(dag.pending_nodes_count(), 0);
+    assert_eq!(dag.highest_round(), num_rounds);
+
+    let anchor_election =
+        RoundRobinAnchorElection::new(epoch_state.verifier.get_ordered_account_addresses());
+    let mut digests = vec![];
+    for round in (1..=num_rounds).step_by(2) {
+        let anchor = match dag.get_node_by_round_author(round, &anchor_election.get_anchor(round)) {
+            Some(anchor) => anchor.metadata().clone(),
+            None => continue,
+        };
+        let batch = dag
+            .order_anchor(&anchor, None, dag.traversal_budget())
+            .unwrap();
+        assert_eq!(batch.nodes().last().unwrap().metadata(), &anchor);
+        for node in batch.nodes() {
+            digests.extend(node.digest().to_vec());
+        }
+    }
+    (digests, dag.content_digest(None))
+}
+
+#[test]
+fn test_default_budget_fits_window() {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let full_round = vec![Some((0..NUM_VALIDATORS).collect()); NUM_VALIDATORS];
+    let links = vec![full_round; DEFAULT_WINDOW_SIZE as usize];
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        assert!(dag.add_node(node).is_ok());
+    }
+
+    // the whole window is ordered by a single anchor, of its own round only the anchor itself
+    let anchor = dag
+        .get_node_by_round_author(DEFAULT_WINDOW_SIZE, &authors[0])
+        .unwrap()
+        .metadata()
+        .clone();
+    let batch = dag
+        .order_anchor(&anchor, None, dag.traversal_budget())
+        .unwrap();
+    assert_eq!(
+        batch.nodes().len(),
+        (DEFAULT_WINDOW_SIZE as usize - 1) * NUM_VALIDATORS + 1
+    );
+}
+
+#[test]
+fn test_budget_exceeded() {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+
+    // a chain where every node only links to a node of another validator in the previous round
+    let num_rounds = 3 * DEFAULT_WINDOW_SIZE;
+    let mut parents = vec![];
+    for round in 1..=num_rounds {
+        let node = new_certified_node(round, authors[round as usize % NUM_VALIDATORS], parents);
+        parents = vec![node.certificate()];
+        assert!(dag.add_node(node).is_ok());
+    }
+    let anchor = parents[0].metadata().clone();
+
+    let budget = dag.traversal_budget();
+    match dag.order_anchor(&anchor, None, budget) {
+        Err(DagStoreError::BudgetExceeded {
+            budget: exceeded,
+            visited_nodes,
+            lowest_round,
+        }) => {
+            assert_eq!(exceeded, budget);
+            assert_eq!(visited_nodes, budget.max_rounds as usize + 1);
+            assert_eq!(lowest_round, num_rounds - budget.max_rounds);
+        },
+        _ => panic!("expected budget exceeded"),
+    }
+    let node_budget = TraversalBudget {
+        max_nodes: 5,
+        max_rounds: num_rounds,
+    };
+    assert!(matches!(
+        dag.order_anchor(&anchor, None, node_budget),
+        Err(DagStoreError::BudgetExceeded {
+            visited_nodes: 6,
+            ..
+        })
+    ));
+
+    // nothing was ordered by the failed attempts
+    let budget = TraversalBudget {
+        max_nodes: num_rounds as usize,
+        max_rounds: num_rounds,
+    };
+    let batch = dag.order_anchor(&anchor, None, budget).unwrap();
+    assert_eq!(batch.nodes().len(), num_rounds as usize);
+}
+
+#[test]
+fn test_batch_sources_survive_restart() {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    for node in generate_dag_nodes(&dag_links(&[(4, 0), (1, 2), (2, 1), (4, 3)]), &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        assert!(dag.add_node(node).is_ok());
+    }
+
+    let anchor_election = RoundRobinAnchorElection::new(authors);
+    let mut batches = vec![];
+    for round in [1, 3] {
+        let anchor = dag
+            .get_node_by_round_author(round, &anchor_election.get_anchor(round))
+            .unwrap()
+            .metadata()
+            .clone();
+        let batch = dag
+            .order_anchor(&anchor, None, dag.traversal_budget())
+            .unwrap();
+        assert_eq!(batch.sources().len(), batch.nodes().len());
+        for (position, (source, node)) in batch.sources().iter().zip(batch.nodes()).enumerate() {
+            assert_eq!(source.author(), node.author());
+            assert_eq!(source.round(), node.metadata().round());
+            assert_eq!(source.digest(), &node.digest());
+            assert_eq!(source.position(), position as u64);
+        }
+        let bytes = bcs::to_bytes(batch.sources()).unwrap();
+        assert_eq!(
+            bcs::from_bytes::<Vec<BatchSourceInfo>>(&bytes).unwrap(),
+            batch.sources()
+        );
+        batches.push(batch);
+    }
+
+    let mut recovered = TestDag::new(epoch_state, storage.clone());
+    assert_eq!(recovered.content_digest(None), dag.content_digest(None));
+    for batch in &batches {
+        assert_eq!(
+            recovered.ordered_sources(batch.anchor().digest()),
+            Some(batch.sources())
+        );
+        assert!(matches!(
+            recovered.order_anchor(batch.anchor(), None, recovered.traversal_budget()),
+            Err(DagStoreError::AnchorAlreadyOrdered(_))
+        ));
+    }
+
+    // pruning the anchor drops its record
+    assert!(recovered.prune_below(2).is_ok());
+    assert_eq!(
+        recovered.ordered_sources(batches[0].anchor().digest()),
+        None
+    );
+    assert_eq!(storage.get_ordered_anchors().unwrap().len(), 1);
+}
+
+/// Five full rounds, the anchor of round 1 is ordered and the anchor of round 3 is skipped, so
+/// the anchor of round 5 orders the remaining 16 nodes.
+struct StreamedDag {
+    epoch_state: Arc<EpochState>,
+    nodes: Vec<CertifiedNode>,
+    skip: SkipCertificate,
+    first_anchor: NodeMetadata,
+    anchor: NodeMetadata,
+}
+
+fn streamed_dag() -> StreamedDag {
+    let (signers, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors = epoch_state.verifier.get_ordered_account_addresses();
+    let full_round = vec![Some((0..NUM_VALIDATORS).collect()); NUM_VALIDATORS];
+    let links = vec![full_round; 5];
+    let nodes: Vec<_> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    let anchor_election = Arc::new(RoundRobinAnchorElection::new(authors));
+    let mut tracker = SkipRoundTracker::new(
+        epoch_state.clone(),
+        anchor_election.clone(),
+        Arc::new(MockStorage::new()),
+    )
+    .unwrap();
+    let skip_round = SkipRound::new(1, 3, anchor_election.get_anchor(3));
+    let skip = signers[0..3]
+        .iter()
+        .filter_map(|signer| {
+            tracker
+                .add_vote(SkipVote::new(skip_round.clone(), signer).unwrap())
+                .unwrap()
+        })
+        .next()
+        .unwrap();
+    let anchor_of = |round: Round| {
+        nodes
+            .iter()
+            .find(|node| {
+                node.metadata().round() == round
+                    && *node.author() == anchor_election.get_anchor(round)
+            })
+            .unwrap()
+            .metadata()
+            .clone()
+    };
+    let first_anchor = anchor_of(1);
+    let anchor = anchor_of(5);
+    StreamedDag {
+        epoch_state,
+        nodes,
+        skip,
+        first_anchor,
+        anchor,
+    }
+}
+
+impl StreamedDag {
+    fn new_dag(&self, storage: Arc<MockStorage>) -> TestDag {
+        let mut dag = TestDag::new(self.epoch_state.clone(), storage);
+        for node in &self.nodes {
+            assert!(dag.add_node(node.clone()).is_ok());
+        }
+        assert!(dag
+            .order_anchor(&self.first_anchor, None, dag.traversal_budget())
+            .is_ok());
+        assert!(dag.mark_round_skipped(3, self.skip.clone()).is_ok());
+        dag
+    }
+}
+
+/// Streams the anchor into `chunks`, stopping after `max_chunks`.
+fn stream(
+    dag: &mut TestDag,
+    anchor: &NodeMetadata,
+    chunk_size: usize,
+    max_chunks: usize,
+    chunks: &mut Vec<OrderedChunk>,
+) -> ControlFlow<()> {
+    let mut emitted = 0;
+    dag.order_anchor_streamed(anchor, None, chunk_size, |chunk| {
+        chunks.push(chunk);
+        emitted += 1;
+        if emitted == max_chunks {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_streamed_chunks_concatenate_to_batch() {
+    let streamed = streamed_dag();
+    let mut dag = streamed.new_dag(Arc::new(MockStorage::new()));
+    let batch = dag
+        .order_anchor(&streamed.anchor, None, dag.traversal_budget())
+        .unwrap();
+    assert_eq!(batch.nodes().len(), 16);
+
+    for chunk_size in [0, 1, 4, 16, 100] {
+        let storage = Arc::new(MockStorage::new());
+        let mut dag = streamed.new_dag(storage.clone());
+        let mut chunks = vec![];
+        assert_eq!(
+            stream(
+                &mut dag,
+                &streamed.anchor,
+                chunk_size,
+                usize::MAX,
+                &mut chunks
+            ),
+            ControlFlow::Continue(())
+        );
+        let chunk_size = chunk_size.max(1);
+        assert_eq!(chunks.len(), (16 + chunk_size - 1) / chunk_size);
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.nodes().len() <= chunk_size);
+            assert_eq!(chunk.start(), (index * chunk_size) as u64);
+            assert_eq!(chunk.is_last(), index + 1 == chunks.len());
+        }
+        let completion = chunks.last().unwrap().completion().unwrap();
+        assert_eq!(completion.anchor(), &streamed.anchor);
+        assert_eq!(completion.failed_authors(), &[(
+            3,
+            *streamed.skip.skip().anchor()
+        )]);
+
+        let nodes: Vec<_> = chunks.iter().flat_map(OrderedChunk::nodes).collect();
+        assert_eq!(nodes, batch.nodes().iter().collect::<Vec<_>>());
+        let sources: Vec<_> = chunks
+            .iter()
+            .flat_map(|chunk| chunk.sources().iter().cloned())
+            .collect();
+        assert_eq!(sources, batch.sources());
+        assert_eq!(
+            dag.ordered_sources(streamed.anchor.digest()),
+            Some(batch.sources())
+        );
+        assert!(matches!(
+            dag.order_anchor_streamed(&streamed.anchor, None, chunk_size, |_| {
+                ControlFlow::Continue(())
+            }),
+            Err(DagStoreError::AnchorAlreadyOrdered(_))
+        ));
+    }
+}
+
+#[test]
+fn test_streamed_ordering_resumes_after_restart() {
+    let streamed = streamed_dag();
+    let mut dag = streamed.new_dag(Arc::new(MockStorage::new()));
+    let batch = dag
+        .order_anchor(&streamed.anchor, None, dag.traversal_budget())
+        .unwrap();
+
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = streamed.new_dag(storage.clone());
+    let mut chunks = vec![];
+    // the validator crashes after the second chunk
+    assert_eq!(
+        stream(&mut dag, &streamed.anchor, 4, 2, &mut chunks),
+        ControlFlow::Break(())
+    );
+    assert_eq!(chunks.len(), 2);
+    assert!(!chunks[1].is_last());
+    assert_eq!(
+        dag.ordered_sources(streamed.anchor.digest()),
+        Some(&batch.sources()[..8])
+    );
+
+    // the skip is marked again once the tracker is recovered
+    let mut recovered = TestDag::new(streamed.epoch_state.clone(), storage);
+    assert!(recovered
+        .mark_round_skipped(3, streamed.skip.clone())
+        .is_ok());
+    assert_eq!(
+        recovered.ordered_sources(streamed.anchor.digest()),
+        Some(&batch.sources()[..8])
+    );
+    assert_eq!(
+        stream(&mut recovered, &streamed.anchor, 4, usize::MAX, &mut chunks),
+        ControlFlow::Continue(())
+    );
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[2].start(), 8);
+    let completion = chunks.last().unwrap().completion().unwrap();
+    assert_eq!(completion.failed_authors(), &[(
+        3,
+        *streamed.skip.skip().anchor()
+    )]);
+    let nodes: Vec<_> = chunks.iter().flat_map(OrderedChunk::nodes).collect();
+    assert_eq!(nodes, batch.nodes().iter().collect::<Vec<_>>());
+    assert_eq!(
+        recovered.ordered_sources(streamed.anchor.digest()),
+        Some(batch.sources())
+    );
+    assert!(matches!(
+        recovered.order_anchor(&streamed.anchor, None, recovered.traversal_budget()),
+        Err(DagStoreError::AnchorAlreadyOrdered(_))
+    ));
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_ordering_is_deterministic_across_validators(
+        shape in prop::collection::vec((0..NUM_VALIDATORS + 1, 0..NUM_VALIDATORS + 1), 1..12),
+        seeds in prop::collection::vec(any::<u64>(), NUM_INSTANCES),
+    ) {
+        let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+        let epoch_state = Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier,
+        });
+        let authors = epoch_state.verifier.get_ordered_account_addresses();
+        let nodes: Vec<_> = generate_dag_nodes(&dag_links(&shape), &authors)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+
+        let expected = ordered_digests(epoch_state.clone(), nodes.clone(), seeds[0], shape.len() as Round);
+        for seed in &seeds[1..] {
+            let digests = ordered_digests(epoch_state.clone(), nodes.clone(), *seed, shape.len() as Round);
+            prop_assert_eq!(&digests, &expected);
+        }
+    }
+}
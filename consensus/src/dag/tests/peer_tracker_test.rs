@@ -0,0 +1,107 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::FetchPlan, peer_tracker::DagPeerTracker, tests::helpers::new_certified_node,
+        validator_index::ValidatorIndex,
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+fn plan(missing_slots: BTreeMap<Round, Vec<Author>>) -> FetchPlan {
+    let estimated_nodes = missing_slots.values().map(Vec::len).sum();
+    FetchPlan {
+        missing_slots,
+        estimated_nodes,
+    }
+}
+
+#[test]
+fn test_peer_tracker_ranking() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut tracker = DagPeerTracker::new(
+        Arc::new(ValidatorIndex::new(&epoch_state)),
+        time_service.clone(),
+        Duration::from_secs(10),
+    );
+
+    // the first peer claims everything, the second one only round 2
+    tracker.update(authors[1], 1, vec![vec![true; 4]; 3]);
+    let empty_round = vec![false; 4];
+    tracker.update(authors[2], 1, vec![
+        empty_round.clone(),
+        vec![true; 4],
+        empty_round,
+    ]);
+    assert_eq!(tracker.highest_round(&authors[1]), Some(3));
+    assert_eq!(tracker.highest_round(&authors[2]), Some(2));
+    assert_eq!(tracker.density(&authors[1]), Some(1.0));
+    assert_eq!(tracker.highest_claimed_round(), Some(3));
+    assert!(tracker.is_claimed(3, &authors[0]));
+    assert!(!tracker.is_claimed(4, &authors[0]));
+
+    let plan = plan(BTreeMap::from([
+        (2, vec![authors[0], authors[3]]),
+        (3, vec![authors[0]]),
+    ]));
+    assert_eq!(tracker.rank_for(&plan), vec![authors[1], authors[2]]);
+
+    // claiming everything but delivering nothing drops the first peer below the second
+    tracker.record_response(&authors[1], &plan, &[]);
+    tracker.record_response(&authors[1], &plan, &[]);
+    assert_eq!(tracker.rank_for(&plan), vec![authors[2], authors[1]]);
+
+    // complete responses forgive the failures
+    let delivered = vec![
+        new_certified_node(2, authors[0], vec![]),
+        new_certified_node(2, authors[3], vec![]),
+        new_certified_node(3, authors[0], vec![]),
+    ];
+    tracker.record_response(&authors[1], &plan, &delivered);
+    tracker.record_response(&authors[1], &plan, &delivered);
+    assert_eq!(tracker.rank_for(&plan), vec![authors[1], authors[2]]);
+
+    // stale bitmasks age out
+    time_service.advance(Duration::from_secs(11));
+    assert!(tracker.rank_for(&plan).is_empty());
+    assert!(!tracker.is_claimed(3, &authors[0]));
+    assert_eq!(tracker.highest_claimed_round(), None);
+    tracker.update(authors[3], 1, vec![vec![true; 4]; 500]);
+    assert_eq!(tracker.num_peers(), 1);
+    assert_eq!(tracker.highest_round(&authors[3]), Some(500));
+    assert_eq!(tracker.rank_for(&plan), vec![]);
+}
+
+#[test]
+fn test_peer_tracker_truncates_wide_bitmask() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    let mut tracker = DagPeerTracker::new(
+        Arc::new(ValidatorIndex::new(&epoch_state)),
+        Arc::new(SimulatedTimeService::new()),
+        Duration::from_secs(10),
+    );
+
+    // round 2 only claims slots past the validator set
+    let mut beyond = vec![false; 4];
+    beyond.extend([true; 4]);
+    tracker.update(authors[1], 1, vec![vec![true; 4], beyond]);
+    assert_eq!(tracker.highest_round(&authors[1]), Some(1));
+    assert_eq!(tracker.highest_claimed_round(), Some(1));
+    assert_eq!(tracker.density(&authors[1]), Some(0.5));
+    assert!(!tracker.is_claimed(2, &authors[0]));
+}
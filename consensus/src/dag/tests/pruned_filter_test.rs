@@ -0,0 +1,125 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::DagStoreError,
+    pruned_filter::{fingerprint, PrunedDigestFilter},
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, Node, NodeCertificate, NodeMetadata},
+};
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    aggregate_signature::AggregateSignature, chain_id::ChainId, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use std::sync::Arc;
+
+fn new_dag() -> (TestDag, Vec<Author>) {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    (
+        TestDag::new(epoch_state, Arc::new(MockStorage::new())),
+        authors,
+    )
+}
+
+fn add_round(
+    dag: &mut TestDag,
+    round: Round,
+    authors: &[Author],
+    parents: &[CertifiedNode],
+) -> Vec<CertifiedNode> {
+    let parents: Vec<NodeCertificate> = parents.iter().map(|node| node.certificate()).collect();
+    authors
+        .iter()
+        .map(|author| {
+            let node = new_certified_node(round, *author, parents.clone());
+            assert!(dag.add_node(node.clone()).is_ok());
+            node
+        })
+        .collect()
+}
+
+#[test]
+fn test_pruned_node_redelivery_dropped() {
+    let (mut dag, authors) = new_dag();
+    dag.set_pruned_digest_filter(PrunedDigestFilter::new(1));
+    let mut rounds = vec![add_round(&mut dag, 1, &authors, &[])];
+    for round in 2..=3 {
+        let parents = rounds.last().unwrap().clone();
+        rounds.push(add_round(&mut dag, round, &authors, &parents));
+    }
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+    assert_eq!(dag.pruned_digests().pruned_below(), 2);
+    assert_eq!(dag.pruned_digests().len(), 4);
+    for node in &rounds[0] {
+        assert!(dag.is_pruned_redelivery(node.metadata()));
+    }
+    for node in &rounds[1] {
+        assert!(!dag.is_pruned_redelivery(node.metadata()));
+        assert!(dag.exists(&node.digest()));
+    }
+
+    // another node of a pruned round goes through the full validation
+    let unknown = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            authors[0],
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(!dag.is_pruned_redelivery(unknown.metadata()));
+    assert!(matches!(
+        dag.pre_validate(&unknown),
+        Err(DagStoreError::RoundTooLow { .. })
+    ));
+
+    // only the last pruned round is remembered
+    assert_eq!(dag.prune_below(3).unwrap(), 4);
+    assert_eq!(dag.pruned_digests().len(), 4);
+    assert!(!dag.is_pruned_redelivery(rounds[0][0].metadata()));
+    assert!(dag.is_pruned_redelivery(rounds[1][0].metadata()));
+}
+
+#[test]
+fn test_pruned_filter_false_positive_falls_through() {
+    let (mut dag, authors) = new_dag();
+    let round_1 = add_round(&mut dag, 1, &authors[1..], &[]);
+    let round_2 = add_round(&mut dag, 2, &authors, &round_1);
+    let parents = round_2.iter().map(|node| node.certificate()).collect();
+    let node = new_certified_node(3, authors[0], parents);
+
+    // a pruned node whose digest only differs from the new node in its last byte
+    let mut bytes = node.digest().to_vec();
+    bytes[HashValue::LENGTH - 1] ^= 1;
+    let colliding = HashValue::from_slice(&bytes).unwrap();
+    assert_eq!(fingerprint(&colliding), fingerprint(&node.digest()));
+    let crafted = CertifiedNode::new(
+        Node::new_for_test(
+            NodeMetadata::new_for_test(1, 1, authors[0], 0, colliding),
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(dag.add_node(crafted).is_ok());
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+
+    assert!(dag.pruned_digests().may_contain(&node.digest()));
+    assert!(!dag.is_pruned_redelivery(node.metadata()));
+    assert!(dag.pre_validate(&node).is_ok());
+    assert!(dag.add_node(node).is_ok());
+}
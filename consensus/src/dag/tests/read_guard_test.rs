@@ -0,0 +1,211 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// The references of `DagReadGuard` can't outlive the lock the DAG is read through, which the
+// borrow checker enforces rather than a test. The guard borrows the `Dag` behind the lock guard
+// and every reference it hands out borrows the guard, so each of these is rejected:
+//
+// - keeping a node past the lock guard, `dag_reader` is dropped while still borrowed (E0597):
+//
+//   let node = {
+//       let dag_reader = dag.read();
+//       dag_reader.read().get_node_ref(&metadata)
+//   };
+//
+// - keeping a node past a temporary read guard, the temporary is dropped at the end of the
+//   statement while still borrowed (E0716):
+//
+//   let node = dag.read().read().get_node_ref(&metadata);
+//   node.unwrap().digest();
+//
+// - mutating a `Dag` the caller owns while a reference is alive, the guard borrows it (E0502):
+//
+//   let reader = dag.read();
+//   let node = reader.get_node_ref(&metadata);
+//   dag.prune_below(2);
+//   node.unwrap().digest();
+//
+// A caller holding a node past the lock takes its `Arc` from `Dag::get_node`.
+
+use crate::dag::{
+    dag_store::Dag,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{generate_dag_nodes, new_certified_node, TestDag},
+    },
+    types::{CertifiedNode, NodeMetadata},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::RwLock;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+fn full_dag(num_validators: usize, num_rounds: usize) -> (Dag, Vec<Author>) {
+    let (_, validator_verifier) = random_validator_verifier(num_validators, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let full_round = vec![Some((0..num_validators).collect()); num_validators];
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    for node in generate_dag_nodes(&vec![full_round; num_rounds], &authors)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        assert!(dag.add_node(node).is_ok());
+    }
+    (dag.into_inner(), authors)
+}
+
+#[test]
+fn test_borrowed_reads_match_owned_reads() {
+    let (mut dag, authors) = full_dag(4, 3);
+    // round 4 misses two validators, one of them excluded for equivocating
+    let parents = dag.strong_links_for_round(3).unwrap();
+    for author in &authors[..2] {
+        assert!(dag
+            .add_node(new_certified_node(4, *author, parents.clone()))
+            .is_ok());
+    }
+    assert!(dag.exclude_equivocator(&authors[1], 4).unwrap());
+
+    let reader = dag.read();
+    for round in 1..=4 {
+        let nodes: Vec<_> = reader.round_nodes(round).collect();
+        let certificates = dag.get_certificates_for_round(round);
+        assert_eq!(nodes.len(), certificates.len());
+        for (node, certificate) in nodes.iter().zip(&certificates) {
+            let metadata = certificate.metadata();
+            assert_eq!(node.metadata(), metadata);
+            let owned = dag.get_node(metadata.digest()).unwrap();
+            assert_eq!(reader.get_node_ref(metadata), Some(owned.as_ref()));
+            assert_eq!(
+                reader.get_node_by_digest(metadata.digest()),
+                Some(owned.as_ref())
+            );
+        }
+        assert_eq!(
+            reader.has_quorum(round),
+            dag.strong_links_for_round(round).is_some()
+        );
+        assert_eq!(
+            reader.round_bitmask(round),
+            dag.bitmask()[round as usize - 1]
+        );
+    }
+    // the equivocator doesn't count
+    assert_eq!(reader.round_power(3), 3);
+    assert_eq!(reader.round_power(4), 1);
+    assert_eq!(reader.parents_power(&parents), 3);
+
+    // another node in the slot of the metadata
+    let metadata = NodeMetadata::new_for_test(1, 1, authors[0], 0, HashValue::random());
+    assert!(reader.get_node_ref(&metadata).is_none());
+    let unknown = new_certified_node(5, authors[0], vec![]);
+    assert!(reader.all_parents_exist(&parents));
+    assert!(!reader.all_parents_exist(&[unknown.certificate()]));
+    assert!(reader
+        .resolve_parent(&unknown.certificate())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_borrowed_reads_keep_reference_counts() {
+    let (dag, _) = full_dag(4, 2);
+    let owned: Vec<Arc<CertifiedNode>> = dag
+        .get_certificates_for_round(2)
+        .iter()
+        .map(|certificate| dag.get_node(certificate.metadata().digest()).unwrap())
+        .collect();
+    let counts: Vec<_> = owned.iter().map(Arc::strong_count).collect();
+
+    // the references held from the guard don't add a count, the `Arc`s taken do
+    let reader = dag.read();
+    let borrowed: Vec<&CertifiedNode> = reader.round_nodes(2).collect();
+    assert_eq!(borrowed.len(), owned.len());
+    assert_eq!(
+        owned.iter().map(Arc::strong_count).collect::<Vec<_>>(),
+        counts
+    );
+    let taken: Vec<_> = borrowed
+        .iter()
+        .map(|node| dag.get_node(&node.digest()).unwrap())
+        .collect();
+    assert_eq!(
+        owned.iter().map(Arc::strong_count).collect::<Vec<_>>(),
+        counts.iter().map(|count| count + 1).collect::<Vec<_>>()
+    );
+    drop(taken);
+}
+
+/// Reads every node of the round from `num_threads` threads sharing the lock, with `Arc`s or
+/// with references.
+fn time_reads(
+    dag: &Arc<RwLock<Dag>>,
+    round: Round,
+    num_threads: usize,
+    num_iterations: usize,
+    borrowed: bool,
+) -> Duration {
+    let digests: Vec<_> = dag
+        .read()
+        .get_certificates_for_round(round)
+        .iter()
+        .map(|certificate| *certificate.metadata().digest())
+        .collect();
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| {
+                let dag_reader = dag.read();
+                let mut total: u64 = 0;
+                for _ in 0..num_iterations {
+                    for digest in &digests {
+                        total += if borrowed {
+                            dag_reader
+                                .read()
+                                .get_node_by_digest(digest)
+                                .map_or(0, |node| node.parents().len() as u64)
+                        } else {
+                            dag_reader
+                                .get_node(digest)
+                                .map_or(0, |node| node.parents().len() as u64)
+                        };
+                    }
+                }
+                assert!(total > 0);
+            });
+        }
+    });
+    started.elapsed()
+}
+
+/// Micro-benchmark of the concurrent reads of a round of 200 validators, run it with
+/// `cargo test -p aptos-consensus --release bench_borrowed_reads -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_borrowed_reads() {
+    let (dag, _) = full_dag(200, 3);
+    let dag = Arc::new(RwLock::new(dag));
+    let (num_threads, num_iterations) = (8, 2_000);
+    let num_reads = (num_threads * num_iterations * 200) as f64;
+    // warm up
+    time_reads(&dag, 3, num_threads, num_iterations / 10, false);
+    let owned = time_reads(&dag, 3, num_threads, num_iterations, false);
+    let borrowed = time_reads(&dag, 3, num_threads, num_iterations, true);
+    println!(
+        "{} threads: Arc reads {:.1} ns, borrowed reads {:.1} ns, {:.2}x",
+        num_threads,
+        owned.as_nanos() as f64 / num_reads,
+        borrowed.as_nanos() as f64 / num_reads,
+        owned.as_secs_f64() / borrowed.as_secs_f64()
+    );
+}
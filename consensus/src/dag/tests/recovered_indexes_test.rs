@@ -0,0 +1,87 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::{Dag, NodeStatus, NodesByRound, RecoveredIndexes},
+        tests::{dag_test::MockStorage, helpers::generate_dag_nodes},
+    },
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
+};
+use aptos_consensus_types::common::Round;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::sync::Arc;
+
+const NUM_VALIDATORS: usize = 200;
+const NUM_ROUNDS: usize = 50;
+
+#[test]
+fn test_rebuild_matches_incremental_indexes() {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    // a few parents per node keep the DAG small, every seventh slot is empty
+    let links: Vec<Vec<Option<Vec<usize>>>> = (0..NUM_ROUNDS)
+        .map(|round| {
+            (0..NUM_VALIDATORS)
+                .map(|index| {
+                    ((round * NUM_VALIDATORS + index) % 7 != 0).then(|| match round {
+                        0 => vec![],
+                        _ => (0..4)
+                            .map(|offset| (index + offset * 13) % NUM_VALIDATORS)
+                            .collect(),
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    let nodes = generate_dag_nodes(&links, &authors);
+
+    // a plain `Dag`, checking the consistency after each of the thousands of inserts is quadratic
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let mut dag = Dag::new_with_time_service(
+        Arc::new(EpochState {
+            epoch: 1,
+            verifier: validator_verifier.clone(),
+        }),
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+    );
+    for node in nodes.iter().flatten().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+
+    let nodes_by_round: NodesByRound = nodes
+        .into_iter()
+        .enumerate()
+        .map(|(round, slots)| {
+            let slots = slots
+                .into_iter()
+                .map(|node| node.map(|node| NodeStatus::Unordered(Arc::new(node))))
+                .collect();
+            (round as Round + 1, slots)
+        })
+        .collect();
+
+    // the nodes were all inserted at the time they're recovered at
+    let recovered_at = time_service.get_current_timestamp();
+    let serial = RecoveredIndexes::rebuild(
+        &nodes_by_round,
+        &validator_verifier,
+        NUM_VALIDATORS,
+        recovered_at,
+    );
+    let parallel = RecoveredIndexes::rebuild_parallel(
+        &nodes_by_round,
+        &validator_verifier,
+        NUM_VALIDATORS,
+        recovered_at,
+    );
+    assert_eq!(serial, dag.indexes());
+    assert_eq!(parallel, serial);
+    assert_eq!(
+        serial.num_nodes(),
+        (0..NUM_ROUNDS * NUM_VALIDATORS)
+            .filter(|slot| slot % 7 != 0)
+            .count()
+    );
+}
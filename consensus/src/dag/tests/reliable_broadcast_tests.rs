@@ -3,18 +3,20 @@
 
 use crate::{
     dag::{
+        counters,
         dag_network::DAGNetworkSender,
-        dag_store::Dag,
+        dag_store::{Dag, DagStoreError, InsertOutcome},
         reliable_broadcast::{
-            BroadcastStatus, CertifiedNodeHandleError, CertifiedNodeHandler,
+            BroadcastStatus, CertifiedNodeHandleError, CertifiedNodeHandler, CertifiedNodeResponse,
             NodeBroadcastHandleError, NodeBroadcastHandler, ReliableBroadcast,
         },
         tests::{
             dag_test::MockStorage,
-            helpers::{new_certified_node, new_node},
+            helpers::{generate_dag_nodes, new_certified_node, new_node},
         },
         types::{
-            CertifiedAck, DAGMessage, NodeCertificate, NodeDigestSignature, TestAck, TestMessage,
+            CertifiedAck, CertifiedNode, DAGMessage, Node, NodeCertificate, NodeDigestSignature,
+            TestAck, TestMessage,
         },
         RpcHandler,
     },
@@ -22,10 +24,12 @@ use crate::{
     network_interface::ConsensusMsg,
 };
 use anyhow::bail;
-use aptos_consensus_types::common::Author;
+use aptos_consensus_types::common::{Author, Payload};
 use aptos_infallible::{Mutex, RwLock};
 use aptos_types::{
-    aggregate_signature::PartialSignatures, epoch_state::EpochState,
+    aggregate_signature::{AggregateSignature, PartialSignatures},
+    chain_id::ChainId,
+    epoch_state::EpochState,
     validator_verifier::random_validator_verifier,
 };
 use async_trait::async_trait;
@@ -39,7 +43,7 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 
 struct TestBroadcastStatus {
     threshold: usize,
@@ -106,7 +110,7 @@ impl DAGNetworkSender for TestDAGSender {
         _responders: Vec<Author>,
         _message: ConsensusMsg,
         _timeout: Duration,
-    ) -> anyhow::Result<ConsensusMsg> {
+    ) -> anyhow::Result<(Author, ConsensusMsg)> {
         unimplemented!();
     }
 }
@@ -294,19 +298,221 @@ fn test_certified_node_receiver() {
     let storage = Arc::new(MockStorage::new());
     let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage)));
 
-    let zeroth_round_node = new_certified_node(0, signers[0].author(), vec![]);
+    let first_round_node = new_certified_node(1, signers[0].author(), vec![]);
 
     let mut rb_receiver = CertifiedNodeHandler::new(dag);
 
     // expect an ack for a valid message
-    assert_ok!(rb_receiver.process(zeroth_round_node.clone()));
+    assert_ok!(rb_receiver.process(first_round_node.clone()));
     // expect an ack if the same message is sent again
-    assert_ok_eq!(rb_receiver.process(zeroth_round_node), CertifiedAck::new(1));
+    assert_ok_eq!(rb_receiver.process(first_round_node), CertifiedAck::new(1));
 
-    let parent_node = new_certified_node(0, signers[1].author(), vec![]);
-    let invalid_node = new_certified_node(1, signers[0].author(), vec![parent_node.certificate()]);
+    let parent_node = new_certified_node(1, signers[1].author(), vec![]);
+    let invalid_node = new_certified_node(2, signers[0].author(), vec![parent_node.certificate()]);
     assert_eq!(
         rb_receiver.process(invalid_node).unwrap_err().to_string(),
         CertifiedNodeHandleError::MissingParents.to_string()
     );
 }
+
+#[test]
+fn test_certified_node_deferred_ack() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+    )));
+    let mut rb_receiver = CertifiedNodeHandler::new(dag.clone()).with_deferred_acks();
+    let deferred = |response| match response {
+        CertifiedNodeResponse::Deferred(ack_rx) => ack_rx,
+        CertifiedNodeResponse::Ack(_) => panic!("expected a deferred ack"),
+    };
+
+    let parents: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    let certificates: Vec<_> = parents.iter().map(|node| node.certificate()).collect();
+    for parent in &parents[1..] {
+        assert!(matches!(
+            rb_receiver.process_deferred(parent.clone()).unwrap(),
+            CertifiedNodeResponse::Ack(_)
+        ));
+    }
+    // parked until its last parent arrives, acked once it is promoted
+    let child = new_certified_node(2, signers[0].author(), certificates.clone());
+    let mut ack_rx = deferred(rb_receiver.process_deferred(child.clone()).unwrap());
+    assert!(ack_rx.try_recv().is_err());
+    rb_receiver.process_deferred(parents[0].clone()).unwrap();
+    assert!(dag.read().exists(&child.digest()));
+    assert_eq!(ack_rx.try_recv().unwrap(), CertifiedAck::new(1));
+
+    // a parked node evicted by pruning is never acked
+    let orphan = new_certified_node(3, signers[1].author(), vec![new_certified_node(
+        2,
+        signers[1].author(),
+        certificates,
+    )
+    .certificate()]);
+    let mut ack_rx = deferred(rb_receiver.process_deferred(orphan).unwrap());
+    dag.write().prune_below(4).unwrap();
+    assert!(matches!(
+        ack_rx.try_recv(),
+        Err(oneshot::error::TryRecvError::Closed)
+    ));
+    assert_eq!(rb_receiver.deferred_acks().unwrap().num_waiting(), 0);
+}
+
+#[test]
+fn test_certified_node_redelivery() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let storage = Arc::new(MockStorage::new());
+    let dag = Arc::new(RwLock::new(Dag::new(epoch_state, storage.clone())));
+    let mut rb_receiver = CertifiedNodeHandler::new(dag.clone());
+    let outcome_count = |outcome: &str| {
+        counters::INSERT_OUTCOME_COUNT
+            .with_label_values(&[outcome])
+            .get()
+    };
+
+    let parents: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    let child = new_certified_node(
+        2,
+        signers[0].author(),
+        parents.iter().map(|node| node.certificate()).collect(),
+    );
+    for parent in &parents[1..] {
+        assert_ok!(rb_receiver.process(parent.clone()));
+    }
+    // parked until its last parent arrives, the retry is not persisted again
+    for _ in 0..2 {
+        assert_eq!(
+            rb_receiver.process(child.clone()).unwrap_err().to_string(),
+            CertifiedNodeHandleError::MissingParents.to_string()
+        );
+    }
+    assert_eq!(storage.num_node_writes(), 4);
+    assert_ok!(rb_receiver.process(parents[0].clone()));
+    assert!(dag.read().exists(&child.digest()));
+    let num_writes = storage.num_node_writes();
+
+    let already_present = outcome_count("already_present");
+    let rejected = outcome_count("rejected");
+    for node in parents.iter().chain([&child]) {
+        assert_ok_eq!(rb_receiver.process(node.clone()), CertifiedAck::new(1));
+        assert!(matches!(
+            dag.write().insert_node(node.clone()),
+            InsertOutcome::AlreadyPresent
+        ));
+    }
+    assert_eq!(storage.num_node_writes(), num_writes);
+    // the counters are shared with the tests running in parallel, only the deltas of this one
+    // are known to be there
+    assert!(outcome_count("already_present") >= already_present + 10);
+
+    let equivocation = CertifiedNode::new(
+        Node::new(
+            ChainId::test(),
+            1,
+            1,
+            signers[0].author(),
+            1,
+            Payload::empty(false),
+            vec![],
+        ),
+        AggregateSignature::empty(),
+    );
+    assert!(matches!(
+        dag.write().insert_node(equivocation),
+        InsertOutcome::Rejected(DagStoreError::EquivocateNode)
+    ));
+    assert!(outcome_count("rejected") > rejected);
+}
+
+#[test]
+fn test_certified_node_catch_up() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let dag = Arc::new(RwLock::new(Dag::new(
+        epoch_state,
+        Arc::new(MockStorage::new()),
+    )));
+    let links: Vec<_> = (0..5)
+        .map(|round| {
+            (0..4)
+                .map(|_| Some(if round == 0 { vec![] } else { vec![0, 1, 2, 3] }))
+                .collect()
+        })
+        .collect();
+    let nodes: Vec<Vec<_>> = generate_dag_nodes(&links, &authors)
+        .into_iter()
+        .map(|round_nodes| round_nodes.into_iter().flatten().collect())
+        .collect();
+    for node in &nodes[0] {
+        assert!(dag.write().add_node(node.clone()).is_ok());
+    }
+    let (catch_up_tx, mut catch_up_rx) = mpsc::channel(4);
+    let mut rb_receiver = CertifiedNodeHandler::new(dag.clone()).with_catch_up(catch_up_tx);
+
+    // two rounds ahead, parked until the DAG reaches it
+    assert_ok!(rb_receiver.pre_validate(&nodes[2][0]));
+    assert_eq!(
+        rb_receiver
+            .process(nodes[2][0].clone())
+            .unwrap_err()
+            .to_string(),
+        CertifiedNodeHandleError::MissingParents.to_string()
+    );
+    assert_eq!(dag.read().pending_nodes_count(), 1);
+    assert!(catch_up_rx.try_recv().is_err());
+    assert_eq!(rb_receiver.largest_gap(), 2);
+
+    // further ahead, rejected with a fetch plan against the node
+    let target = &nodes[4][0];
+    assert_ok!(rb_receiver.pre_validate(target));
+    assert!(matches!(
+        rb_receiver
+            .process(target.clone())
+            .unwrap_err()
+            .downcast::<DagStoreError>(),
+        Ok(DagStoreError::RoundTooHigh {
+            round: 5,
+            highest_round: 1,
+            gap: 4,
+        })
+    ));
+    let request = catch_up_rx.try_recv().unwrap();
+    assert_eq!(request.target.digest(), target.digest());
+    assert_eq!(request.plan, dag.read().fetch_plan_for(target));
+    // every slot of rounds 2 and 3, and the parents in round 4
+    assert_eq!(request.plan.estimated_nodes, 12);
+    assert_eq!(rb_receiver.largest_gap(), 4);
+
+    // a smaller gap doesn't lower the largest one
+    assert!(rb_receiver.process(nodes[3][0].clone()).is_err());
+    assert!(catch_up_rx.try_recv().is_ok());
+    assert_eq!(rb_receiver.largest_gap(), 4);
+    assert_eq!(dag.read().pending_nodes_count(), 1);
+
+    // it's reset once the DAG advances
+    for node in &nodes[1] {
+        assert_ok!(rb_receiver.process(node.clone()));
+    }
+    assert!(dag.read().exists(&nodes[2][0].digest()));
+    assert_eq!(rb_receiver.largest_gap(), 0);
+}
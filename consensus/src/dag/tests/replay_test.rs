@@ -0,0 +1,226 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::DagStoreMode,
+        replay::{
+            self, read_log, write_entry, RecordedOutcome, ReplayDivergence, ReplayEntry,
+            ReplayLogConfig,
+        },
+        store_config::DagStoreConfig,
+        tests::{
+            dag_test::MockStorage,
+            helpers::{new_certified_node, new_epoch_certified_node, new_epoch_state, TestDag},
+        },
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::Author;
+use aptos_crypto::HashValue;
+use aptos_temppath::TempPath;
+use aptos_types::{
+    chain_id::ChainId, epoch_state::EpochState, validator_verifier::random_validator_verifier,
+};
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Records a stream with every outcome but a write retry, returns the path of the log.
+fn record(epoch_state: Arc<EpochState>, authors: &[Author], dir: &Path) -> PathBuf {
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let config = DagStoreConfig {
+        replay_log: Some(ReplayLogConfig {
+            dir: dir.to_path_buf(),
+            ..ReplayLogConfig::default()
+        }),
+        ..DagStoreConfig::default()
+    };
+    let mut dag = TestDag::try_new(
+        epoch_state,
+        ChainId::test(),
+        Arc::new(MockStorage::new()),
+        time_service.clone(),
+        DagStoreMode::Strict,
+        config,
+    )
+    .unwrap();
+    let path = dag.replay_log_path().unwrap().to_path_buf();
+
+    let round_1: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    let round_1_parents: Vec<_> = round_1[1..].iter().map(|node| node.certificate()).collect();
+    let missing: Vec<_> = [authors[0], authors[2], authors[3]]
+        .iter()
+        .map(|author| new_certified_node(2, *author, round_1_parents.clone()).certificate())
+        .collect();
+    let stream = vec![
+        round_1[0].clone(),
+        round_1[1].clone(),
+        // a retry of the broadcast
+        round_1[0].clone(),
+        // parked until the round 1 of its parents
+        new_certified_node(2, authors[1], round_1_parents),
+        round_1[2].clone(),
+        round_1[3].clone(),
+        // parked and never promoted
+        new_certified_node(3, authors[2], missing),
+        new_epoch_certified_node(2, 1, authors[0], vec![]),
+        new_certified_node(1, Author::random(), vec![]),
+    ];
+    for node in stream {
+        time_service.advance(Duration::from_millis(10));
+        dag.insert_node(node);
+    }
+    let stats = dag.close_replay_log().unwrap().unwrap();
+    // the header, a checkpoint on each end and the nodes
+    assert_eq!(stats.num_entries, 12);
+    assert_eq!(stats.num_dropped, 0);
+    assert_eq!(stats.num_bytes, fs::metadata(&path).unwrap().len());
+    path
+}
+
+fn rewrite(path: &Path, entries: &[ReplayEntry]) {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    for entry in entries {
+        write_entry(&mut file, entry).unwrap();
+    }
+}
+
+#[test]
+fn test_replay_of_recorded_stream() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let dir = TempPath::new();
+    let path = record(epoch_state.clone(), &authors, dir.path());
+
+    let log = read_log(&path).unwrap();
+    assert!(!log.truncated);
+    assert!(matches!(log.entries[0], ReplayEntry::Header(_)));
+    assert!(matches!(log.entries[1], ReplayEntry::Checkpoint(_)));
+    let outcomes: Vec<_> = log
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            ReplayEntry::Node { outcome, .. } => Some(outcome.label.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(outcomes, vec![
+        "inserted",
+        "inserted",
+        "already_present",
+        "parked_pending_parents",
+        "inserted",
+        "inserted",
+        "parked_pending_parents",
+        "rejected",
+        "rejected",
+    ]);
+
+    let report = replay::run(&path, epoch_state).unwrap();
+    assert!(report.is_faithful(), "{}", report);
+    assert_eq!(report.num_nodes, 9);
+    assert_eq!(report.num_divergences, 0);
+    assert_eq!(report.recorded_digest, Some(report.replayed_digest));
+    assert!(!report.incomplete);
+
+    // the log is of epoch 1
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let next_epoch_state = Arc::new(EpochState {
+        epoch: 2,
+        verifier: validator_verifier,
+    });
+    assert!(replay::run(&path, next_epoch_state).is_err());
+}
+
+#[test]
+fn test_replay_reports_divergences() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let dir = TempPath::new();
+    let path = record(epoch_state.clone(), &authors, dir.path());
+    let entries = read_log(&path).unwrap().entries;
+
+    // the duplicate recorded as inserted
+    let mut tampered = entries.clone();
+    let digest = match &mut tampered[4] {
+        ReplayEntry::Node { node, outcome, .. } => {
+            outcome.label = "inserted".to_string();
+            node.digest()
+        },
+        _ => unreachable!("entry 4 is the duplicate"),
+    };
+    rewrite(&path, &tampered);
+    let report = replay::run(&path, epoch_state.clone()).unwrap();
+    assert!(!report.is_faithful());
+    assert_eq!(report.num_divergences, 1);
+    assert_eq!(
+        report.first_divergence,
+        Some(ReplayDivergence::Outcome {
+            entry: 4,
+            digest,
+            round: 1,
+            author: authors[0],
+            recorded: RecordedOutcome {
+                label: "inserted".to_string(),
+                error: None,
+            },
+            replayed: RecordedOutcome {
+                label: "already_present".to_string(),
+                error: None,
+            },
+        })
+    );
+    // the content still matches
+    assert_eq!(report.recorded_digest, Some(report.replayed_digest));
+
+    // the content of another DAG
+    let mut tampered = entries;
+    let last = tampered.len() - 1;
+    tampered[last] = ReplayEntry::Checkpoint(HashValue::zero());
+    rewrite(&path, &tampered);
+    let report = replay::run(&path, epoch_state).unwrap();
+    assert!(matches!(
+        report.first_divergence,
+        Some(ReplayDivergence::Content { entry, .. }) if entry == last
+    ));
+}
+
+#[test]
+fn test_replay_stops_at_gaps() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let dir = TempPath::new();
+    let path = record(epoch_state.clone(), &authors, dir.path());
+    let entries = read_log(&path).unwrap().entries;
+
+    // the entries after a drop aren't replayed
+    let mut dropped = entries.clone();
+    dropped.insert(5, ReplayEntry::Dropped(3));
+    rewrite(&path, &dropped);
+    let report = replay::run(&path, epoch_state.clone()).unwrap();
+    assert!(report.incomplete);
+    assert!(report.is_faithful());
+    assert_eq!(report.num_nodes, 3);
+
+    // a crash in the middle of the last entry
+    rewrite(&path, &entries);
+    let len = fs::metadata(&path).unwrap().len();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 1)
+        .unwrap();
+    let log = read_log(&path).unwrap();
+    assert!(log.truncated);
+    assert_eq!(log.entries.len(), entries.len() - 1);
+    let report = replay::run(&path, epoch_state).unwrap();
+    assert!(report.incomplete);
+    assert!(report.is_faithful());
+    assert_eq!(report.num_nodes, 9);
+}
@@ -0,0 +1,117 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    anchor_election::{AnchorElection, RoundRobinAnchorElection},
+    round_schedule::{AnchorParity, RoundSchedule},
+};
+use aptos_consensus_types::common::{Author, Round};
+
+#[test]
+fn test_round_schedule_parity() {
+    // the parity is relative to the start round, not absolute
+    let even = RoundSchedule::new(5, AnchorParity::Even);
+    let odd = RoundSchedule::new(5, AnchorParity::Odd);
+    for round in 0..5 {
+        assert!(!even.is_anchor_round(round));
+        assert!(!odd.is_anchor_round(round));
+        assert!(!even.is_vote_round(round));
+    }
+    assert_eq!(
+        (5..12)
+            .filter(|round| even.is_anchor_round(*round))
+            .collect::<Vec<_>>(),
+        vec![5, 7, 9, 11]
+    );
+    assert_eq!(
+        (5..12)
+            .filter(|round| even.is_vote_round(*round))
+            .collect::<Vec<_>>(),
+        vec![6, 8, 10]
+    );
+    assert_eq!(
+        (5..12)
+            .filter(|round| odd.is_anchor_round(*round))
+            .collect::<Vec<_>>(),
+        vec![6, 8, 10]
+    );
+    assert_eq!(
+        (5..12)
+            .filter(|round| odd.is_vote_round(*round))
+            .collect::<Vec<_>>(),
+        vec![7, 9, 11]
+    );
+    assert_eq!(even.start_round(), 5);
+
+    assert_eq!(even.next_anchor_round(0), 5);
+    assert_eq!(even.next_anchor_round(5), 7);
+    assert_eq!(even.next_anchor_round(6), 7);
+    assert_eq!(odd.next_anchor_round(5), 6);
+    assert_eq!(odd.next_anchor_round(6), 8);
+
+    // every anchor round is at its position among the anchor rounds
+    for (position, round) in even.anchor_rounds_between(0, 20).enumerate() {
+        assert_eq!(even.anchor_position(round), position as u64);
+    }
+    assert_eq!(even.anchor_position(6), 1);
+
+    let cadence = RoundSchedule::new(1, AnchorParity::Even).with_cadence(3);
+    assert_eq!(
+        cadence.anchor_rounds_between(1, 10).collect::<Vec<_>>(),
+        vec![1, 4, 7, 10]
+    );
+    assert!(cadence.is_vote_round(5));
+    assert!(!cadence.is_vote_round(6));
+}
+
+#[test]
+fn test_round_schedule_anchor_rounds_between() {
+    let schedule = RoundSchedule::new(3, AnchorParity::Even);
+    let between = |low: Round, high: Round| {
+        schedule
+            .anchor_rounds_between(low, high)
+            .collect::<Vec<_>>()
+    };
+    // across the start round
+    assert_eq!(between(0, 8), vec![3, 5, 7]);
+    assert_eq!(between(0, 2), Vec::<Round>::new());
+    // both bounds included
+    assert_eq!(between(5, 9), vec![5, 7, 9]);
+    assert_eq!(between(6, 8), vec![7]);
+    assert_eq!(between(7, 7), vec![7]);
+    assert_eq!(between(8, 8), Vec::<Round>::new());
+    assert_eq!(between(9, 4), Vec::<Round>::new());
+    // every window of 10 rounds past the start round holds 5 anchor rounds
+    for low in 3..20 {
+        let window = between(low, low + 9);
+        assert_eq!(window.len(), 5);
+        assert!(window.iter().all(|round| schedule.is_anchor_round(*round)));
+    }
+    assert_eq!(
+        schedule
+            .anchor_rounds_between(3, 9)
+            .rev()
+            .collect::<Vec<_>>(),
+        vec![9, 7, 5, 3]
+    );
+}
+
+#[test]
+fn test_round_robin_anchor_election_schedule() {
+    let validators: Vec<_> = (0..4).map(|_| Author::random()).collect();
+    // the default schedule keeps the anchors of the rounds from 1 on
+    let election = RoundRobinAnchorElection::new(validators.clone());
+    for round in 1..20 {
+        assert_eq!(
+            election.get_anchor(round),
+            validators[(round / 2) as usize % validators.len()]
+        );
+    }
+    let election = RoundRobinAnchorElection::with_schedule(
+        validators.clone(),
+        RoundSchedule::new(4, AnchorParity::Odd),
+    );
+    assert_eq!(election.get_anchor(5), validators[0]);
+    assert_eq!(election.get_anchor(7), validators[1]);
+    assert_eq!(election.get_anchor(13), validators[0]);
+}
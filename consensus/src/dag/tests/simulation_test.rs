@@ -0,0 +1,68 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::simulation::{
+    CommitThreshold, DagSimulation, FixedSchedule, SimulationConfig, SimulationReport,
+};
+use aptos_consensus_types::common::Round;
+
+fn committed_rounds(report: &SimulationReport, index: usize) -> Vec<Round> {
+    report.validators[index]
+        .anchors
+        .iter()
+        .map(|anchor| anchor.round)
+        .collect()
+}
+
+#[test]
+fn test_simulation_full_participation() {
+    for commit_threshold in [CommitThreshold::Minority, CommitThreshold::Quorum] {
+        let config = SimulationConfig {
+            commit_threshold,
+            ..SimulationConfig::default()
+        };
+        let report = DagSimulation::new(config, Box::new(FixedSchedule::new(1)))
+            .run()
+            .unwrap();
+
+        // one round per tick, every anchor is committed by the next round
+        assert_eq!(report.num_ticks, 20);
+        let expected: Vec<Round> = (1..20).step_by(2).collect();
+        for (index, validator) in report.validators.iter().enumerate() {
+            assert_eq!(validator.round, 20);
+            assert_eq!(committed_rounds(&report, index), expected);
+            assert!(validator.anchors.iter().all(|anchor| anchor.direct
+                && anchor.latency_rounds == 1
+                && anchor.commit_latency.rounds() == 1));
+            assert_eq!(validator.ordered, report.validators[0].ordered);
+        }
+    }
+}
+
+#[test]
+fn test_simulation_crashed_validator() {
+    let report = DagSimulation::new(
+        SimulationConfig::default(),
+        Box::new(FixedSchedule::new(1).crash(3, 1)),
+    )
+    .run()
+    .unwrap();
+
+    // the rounds anchored by the crashed validator wait for the anchor timeout
+    assert_eq!(report.num_ticks, 30);
+    let expected: Vec<Round> = (1..20)
+        .step_by(2)
+        .filter(|round| ![7, 15].contains(round))
+        .collect();
+    for index in 0..3 {
+        let validator = &report.validators[index];
+        assert_eq!(validator.round, 20);
+        assert_eq!(committed_rounds(&report, index), expected);
+        assert!(validator
+            .anchors
+            .iter()
+            .all(|anchor| anchor.direct && anchor.latency_rounds == 1));
+        assert_eq!(validator.ordered, report.validators[0].ordered);
+    }
+    assert!(!report.validators[0].ordered.is_empty());
+}
@@ -0,0 +1,194 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    anchor_election::{AnchorElection, RoundRobinAnchorElection},
+    dag_store::DagStoreError,
+    skip_round_tracker::{SkipRoundTracker, SkipVoteError},
+    storage::DAGStorage,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{SkipCertificate, SkipRound, SkipVote},
+};
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::sync::Arc;
+
+#[test]
+fn test_skip_round_votes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let anchor_election = Arc::new(RoundRobinAnchorElection::new(authors.clone()));
+    let storage = Arc::new(MockStorage::new());
+    let new_tracker = |epoch_state: &Arc<EpochState>| {
+        SkipRoundTracker::new(
+            epoch_state.clone(),
+            anchor_election.clone(),
+            storage.clone(),
+        )
+        .unwrap()
+    };
+    let mut tracker = new_tracker(&epoch_state);
+    // the anchor of round 2 is validator 1
+    let skip = SkipRound::new(1, 2, authors[1]);
+    let vote = |index: usize| SkipVote::new(skip.clone(), &signers[index]).unwrap();
+
+    assert!(matches!(tracker.add_vote(vote(0)), Ok(None)));
+    // duplicate
+    assert!(matches!(tracker.add_vote(vote(0)), Ok(None)));
+    assert_eq!(tracker.voting_power(2), 1);
+
+    let wrong_anchor = SkipVote::new(SkipRound::new(1, 2, authors[0]), &signers[2]).unwrap();
+    assert!(matches!(
+        tracker.add_vote(wrong_anchor),
+        Err(SkipVoteError::WrongAnchor { round: 2, .. })
+    ));
+    let wrong_epoch = SkipVote::new(SkipRound::new(2, 2, authors[1]), &signers[2]).unwrap();
+    assert!(matches!(
+        tracker.add_vote(wrong_epoch),
+        Err(SkipVoteError::EpochMismatch {
+            epoch: 2,
+            expected: 1
+        })
+    ));
+
+    // not enough voting power yet
+    assert!(matches!(tracker.add_vote(vote(2)), Ok(None)));
+    assert_eq!(tracker.voting_power(2), 2);
+    assert!(tracker.certificate(2).is_none());
+
+    // the votes survive a restart
+    let mut tracker = new_tracker(&epoch_state);
+    assert_eq!(tracker.voting_power(2), 2);
+    let certificate = tracker.add_vote(vote(3)).unwrap().unwrap();
+    assert_eq!(certificate.skip(), &skip);
+    assert!(certificate.verify(&validator_verifier).is_ok());
+    assert_eq!(tracker.certificate(2), Some(&certificate));
+    // produced only once
+    assert!(matches!(tracker.add_vote(vote(1)), Ok(None)));
+    let serialized = bcs::to_bytes(&certificate).unwrap();
+    assert_eq!(
+        bcs::from_bytes::<SkipCertificate>(&serialized).unwrap(),
+        certificate
+    );
+
+    let tracker = new_tracker(&epoch_state);
+    assert!(tracker.certificate(2).is_some());
+    assert_eq!(tracker.voting_power(2), 4);
+
+    // the votes of a previous epoch are deleted at recovery
+    let next_epoch_state = Arc::new(EpochState {
+        epoch: 2,
+        verifier: validator_verifier,
+    });
+    let tracker = new_tracker(&next_epoch_state);
+    assert_eq!(tracker.voting_power(2), 0);
+    assert!(storage.get_skip_votes().unwrap().is_empty());
+}
+
+#[test]
+fn test_dag_skipped_round() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let storage = Arc::new(MockStorage::new());
+    let mut dag = TestDag::new(epoch_state.clone(), storage.clone());
+    for author in &authors {
+        assert!(dag.add_node(new_certified_node(1, *author, vec![])).is_ok());
+    }
+    let parents = dag.strong_links_for_round(1).unwrap();
+    // the anchor of round 2 is missing
+    for index in [0, 2, 3] {
+        assert!(dag
+            .add_node(new_certified_node(2, authors[index], parents.clone()))
+            .is_ok());
+    }
+    assert_eq!(dag.bitmask(), vec![vec![true; 4], vec![
+        true, false, true, true
+    ]]);
+
+    let mut tracker = SkipRoundTracker::new(
+        epoch_state,
+        Arc::new(RoundRobinAnchorElection::new(authors.clone())),
+        storage,
+    )
+    .unwrap();
+    let skip = SkipRound::new(1, 2, authors[1]);
+    let certificate = signers[0..3]
+        .iter()
+        .filter_map(|signer| {
+            tracker
+                .add_vote(SkipVote::new(skip.clone(), signer).unwrap())
+                .unwrap()
+        })
+        .next()
+        .unwrap();
+    assert!(matches!(
+        dag.mark_round_skipped(3, certificate.clone()),
+        Err(DagStoreError::InvalidSkipCertificate(3))
+    ));
+    assert!(dag.mark_round_skipped(2, certificate).is_ok());
+    assert!(dag.is_round_skipped(2));
+    assert_eq!(dag.skipped_anchor(2), Some(&authors[1]));
+    assert_eq!(dag.bitmask(), vec![vec![true; 4]; 2]);
+
+    // the anchor arriving after the skip is kept but never ordered
+    let anchor = new_certified_node(2, authors[1], parents);
+    assert!(dag.add_node(anchor.clone()).is_ok());
+    assert!(dag.exists(&anchor.digest()));
+    let budget = dag.traversal_budget();
+    assert!(matches!(
+        dag.order_anchor(anchor.metadata(), None, budget),
+        Err(DagStoreError::AnchorSkipped(2))
+    ));
+
+    assert!(dag.prune_below(3).is_ok());
+    assert!(!dag.is_round_skipped(2));
+}
+
+#[test]
+fn test_skip_votes_bounded_to_round_window() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let anchor_election = Arc::new(RoundRobinAnchorElection::new(authors));
+    let storage = Arc::new(MockStorage::new());
+    let mut tracker =
+        SkipRoundTracker::new(epoch_state, anchor_election.clone(), storage.clone()).unwrap();
+    let vote = |round, index: usize| {
+        let skip = SkipRound::new(1, round, anchor_election.get_anchor(round));
+        SkipVote::new(skip, &signers[index]).unwrap()
+    };
+
+    assert!(matches!(tracker.add_vote(vote(2, 0)), Ok(None)));
+    assert!(matches!(
+        tracker.add_vote(vote(101, 0)),
+        Err(SkipVoteError::RoundOutsideWindow {
+            round: 101,
+            lowest_round: 0,
+            highest_round: 100,
+        })
+    ));
+
+    // committing moves the window and prunes the votes below it
+    assert!(tracker.set_committed_round(10).is_ok());
+    assert_eq!(tracker.voting_power(2), 0);
+    assert!(storage.get_skip_votes().unwrap().is_empty());
+    assert!(matches!(
+        tracker.add_vote(vote(2, 1)),
+        Err(SkipVoteError::RoundOutsideWindow { round: 2, .. })
+    ));
+    assert!(matches!(tracker.add_vote(vote(101, 0)), Ok(None)));
+    assert!(tracker.voting_power(101) > 0);
+}
@@ -0,0 +1,123 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::{AckDecision, DagStoreError},
+    slot_id::{SlotId, SlotIdError},
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, new_epoch_state, TestDag},
+    },
+    validator_index::ValidatorIndex,
+};
+use aptos_consensus_types::common::Author;
+use std::sync::Arc;
+
+#[test]
+fn test_slot_id_conversions() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let validator_index = ValidatorIndex::new(&epoch_state);
+    for (position, author) in authors.iter().enumerate() {
+        let slot = SlotId::of_author(7, author, &validator_index).unwrap();
+        assert_eq!(slot, SlotId::new(7, position as u32));
+        assert_eq!(slot, SlotId::at(7, position, &validator_index).unwrap());
+        assert_eq!(slot.position(), position);
+        assert_eq!(slot.author(&validator_index), Ok(author));
+        assert_eq!(slot.to_round_author(&validator_index), Ok((7, *author)));
+    }
+
+    let unknown = Author::random();
+    assert_eq!(
+        SlotId::of_author(7, &unknown, &validator_index),
+        Err(SlotIdError::UnknownAuthor(unknown))
+    );
+    let out_of_range = SlotIdError::IndexOutOfRange {
+        index: 4,
+        num_validators: 4,
+    };
+    assert_eq!(
+        SlotId::at(7, 4, &validator_index),
+        Err(out_of_range.clone())
+    );
+    assert_eq!(
+        SlotId::new(7, 4).author(&validator_index),
+        Err(out_of_range.clone())
+    );
+    assert_eq!(
+        SlotId::new(7, 4).to_round_author(&validator_index),
+        Err(out_of_range)
+    );
+    // a position that doesn't fit the index is out of range rather than wrapped around
+    assert!(matches!(
+        SlotId::at(7, u32::MAX as usize + 1, &validator_index),
+        Err(SlotIdError::IndexOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_slot_id_order_and_serde() {
+    let mut slots = vec![
+        SlotId::new(2, 0),
+        SlotId::new(1, 3),
+        SlotId::new(2, 1),
+        SlotId::new(1, 0),
+    ];
+    slots.sort();
+    assert_eq!(slots, vec![
+        SlotId::new(1, 0),
+        SlotId::new(1, 3),
+        SlotId::new(2, 0),
+        SlotId::new(2, 1),
+    ]);
+
+    for slot in [
+        SlotId::new(0, 0),
+        SlotId::new(42, 3),
+        SlotId::new(u64::MAX, u32::MAX),
+    ] {
+        let bytes = bcs::to_bytes(&slot).unwrap();
+        // the round and a 4 bytes index whatever the platform
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(bcs::from_bytes::<SlotId>(&bytes).unwrap(), slot);
+        let json = serde_json::to_string(&slot).unwrap();
+        assert_eq!(serde_json::from_str::<SlotId>(&json).unwrap(), slot);
+    }
+    assert_eq!(
+        serde_json::to_value(SlotId::new(42, 3)).unwrap(),
+        serde_json::json!({"round": 42, "index": 3})
+    );
+}
+
+#[test]
+fn test_dag_slot_accessors() {
+    let (epoch_state, authors) = new_epoch_state(4);
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let node = new_certified_node(1, authors[2], vec![]);
+    assert!(dag.add_node(node.clone()).is_ok());
+
+    let slot = dag.slot_of(1, &authors[2]).unwrap();
+    assert_eq!(slot, SlotId::new(1, 2));
+    let status = dag.slot_status(slot).unwrap().unwrap();
+    assert_eq!(status.as_node().digest(), node.digest());
+    assert!(dag.slot_status(SlotId::new(1, 1)).unwrap().is_none());
+    // a round the DAG doesn't hold
+    assert!(dag.slot_status(SlotId::new(9, 2)).unwrap().is_none());
+
+    let unknown = Author::random();
+    assert_eq!(
+        dag.slot_of(1, &unknown),
+        Err(SlotIdError::UnknownAuthor(unknown))
+    );
+    assert!(matches!(
+        dag.slot_status(SlotId::new(1, 4)),
+        Err(SlotIdError::IndexOutOfRange {
+            index: 4,
+            num_validators: 4,
+        })
+    ));
+    assert!(dag.get_node_status(1, &unknown).is_none());
+
+    let error = DagStoreError::from(SlotIdError::UnknownAuthor(unknown));
+    assert!(matches!(error, DagStoreError::InvalidSlot(_)));
+    assert_eq!(error.ack_decision(), AckDecision::NeverAck);
+}
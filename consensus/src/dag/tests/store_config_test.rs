@@ -0,0 +1,204 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_store::{Dag, DagStoreError, DagStoreMode, EvidenceRetention, ObserverMode},
+        store_config::{DagStoreConfig, DagStoreConfigError},
+        tests::dag_test::MockStorage,
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_types::{
+    chain_id::ChainId, epoch_state::EpochState, validator_verifier::random_validator_verifier,
+};
+use std::{sync::Arc, time::Duration};
+
+const NUM_VALIDATORS: usize = 4;
+
+fn try_new(storage: Arc<MockStorage>, config: DagStoreConfig) -> Result<Dag, DagStoreError> {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    Dag::try_new(
+        epoch_state,
+        ChainId::test(),
+        storage,
+        Arc::new(SimulatedTimeService::new()),
+        DagStoreMode::Strict,
+        config,
+    )
+}
+
+#[test]
+fn test_default_config_validates() {
+    let config = DagStoreConfig::default();
+    assert_eq!(config.validate(NUM_VALIDATORS), Ok(()));
+    assert_eq!(config.validate(65_536), Ok(()));
+}
+
+#[test]
+fn test_config_validation_failures() {
+    let window = DagStoreConfig::default().window;
+    let cases = vec![
+        (
+            DagStoreConfig {
+                window: 0,
+                park_gap: 0,
+                ..DagStoreConfig::default()
+            },
+            DagStoreConfigError::ZeroWindow,
+        ),
+        (
+            DagStoreConfig {
+                park_gap: 0,
+                ..DagStoreConfig::default()
+            },
+            DagStoreConfigError::ParkGapOutsideWindow {
+                park_gap: 0,
+                window,
+            },
+        ),
+        (
+            DagStoreConfig {
+                park_gap: window + 1,
+                ..DagStoreConfig::default()
+            },
+            DagStoreConfigError::ParkGapOutsideWindow {
+                park_gap: window + 1,
+                window,
+            },
+        ),
+        (
+            DagStoreConfig {
+                max_write_attempts: 0,
+                ..DagStoreConfig::default()
+            },
+            DagStoreConfigError::ZeroWriteAttempts,
+        ),
+    ];
+    for (config, expected) in cases {
+        assert_eq!(config.validate(NUM_VALIDATORS), Err(expected));
+    }
+
+    // the budget has to hold a full window, which grows with the validator set
+    let window_bytes = DagStoreConfig::default().min_window_bytes(NUM_VALIDATORS);
+    assert!(window_bytes > 0);
+    let config = DagStoreConfig {
+        memory_budget: window_bytes,
+        ..DagStoreConfig::default()
+    };
+    assert_eq!(config.validate(NUM_VALIDATORS), Ok(()));
+    assert_eq!(
+        config.validate(2 * NUM_VALIDATORS),
+        Err(DagStoreConfigError::MemoryBudgetBelowWindow {
+            memory_budget: window_bytes,
+            window_bytes: config.min_window_bytes(2 * NUM_VALIDATORS),
+        })
+    );
+    let config = DagStoreConfig {
+        memory_budget: window_bytes - 1,
+        ..DagStoreConfig::default()
+    };
+    assert_eq!(
+        config.validate(NUM_VALIDATORS),
+        Err(DagStoreConfigError::MemoryBudgetBelowWindow {
+            memory_budget: window_bytes - 1,
+            window_bytes,
+        })
+    );
+}
+
+#[test]
+fn test_try_new_applies_config() {
+    let storage = Arc::new(MockStorage::new());
+    let invalid = DagStoreConfig {
+        park_gap: 0,
+        ..DagStoreConfig::default()
+    };
+    assert!(matches!(
+        try_new(storage.clone(), invalid),
+        Err(DagStoreError::InvalidConfig(
+            DagStoreConfigError::ParkGapOutsideWindow { .. }
+        ))
+    ));
+
+    let config = DagStoreConfig {
+        park_gap: 5,
+        evidence_retention: EvidenceRetention {
+            max_full_records_per_author: 1,
+            window: 3,
+        },
+        ..DagStoreConfig::default()
+    };
+    let dag = try_new(storage, config.clone()).unwrap();
+    assert_eq!(dag.park_gap(), 5);
+    assert_eq!(dag.evidence_retention(), config.evidence_retention);
+    assert!(!dag.over_memory_budget());
+}
+
+#[test]
+fn test_new_observer_applies_config() {
+    let (_, validator_verifier) = random_validator_verifier(NUM_VALIDATORS, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let new_observer = |config| {
+        Dag::new_observer(
+            epoch_state.clone(),
+            ChainId::test(),
+            Arc::new(MockStorage::new()),
+            Arc::new(SimulatedTimeService::new()),
+            DagStoreMode::Strict,
+            ObserverMode::default(),
+            config,
+        )
+    };
+    assert!(matches!(
+        new_observer(DagStoreConfig {
+            max_write_attempts: 0,
+            ..DagStoreConfig::default()
+        }),
+        Err(DagStoreError::InvalidConfig(
+            DagStoreConfigError::ZeroWriteAttempts
+        ))
+    ));
+    let observer = new_observer(DagStoreConfig {
+        park_gap: 5,
+        ..DagStoreConfig::default()
+    })
+    .unwrap();
+    assert!(observer.is_observer());
+    assert_eq!(observer.park_gap(), 5);
+}
+
+#[test]
+fn test_config_file_round_trip() {
+    let config = DagStoreConfig {
+        window: 20,
+        committed_retention_rounds: Some(5),
+        park_gap: 4,
+        memory_budget: 1 << 30,
+        evidence_retention: EvidenceRetention {
+            max_full_records_per_author: 2,
+            window: 50,
+        },
+        write_retry_backoff: Duration::from_millis(200),
+        ..DagStoreConfig::default()
+    };
+    let serialized = serde_yaml::to_string(&config).unwrap();
+    let deserialized: DagStoreConfig = serde_yaml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, config);
+
+    // the settings left out are the defaults
+    let partial: DagStoreConfig = serde_yaml::from_str("window: 20\npark_gap: 4\n").unwrap();
+    assert_eq!(partial, DagStoreConfig {
+        window: 20,
+        park_gap: 4,
+        ..DagStoreConfig::default()
+    });
+    assert!(serde_yaml::from_str::<DagStoreConfig>("hot_rounds: 3\n").is_err());
+}
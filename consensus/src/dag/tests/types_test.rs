@@ -3,15 +3,22 @@
 use super::helpers::new_node;
 use crate::dag::{
     tests::helpers::new_certified_node,
-    types::{CertifiedNode, Node, NodeCertificate, NodeMetadata, TDAGMessage},
+    types::{
+        CertifiedNode, CompactCertifiedNode, FetchResponse, Node, NodeCertificate, NodeMetadata,
+        SignatureBuilder, SignatureBuilderError, TDAGMessage,
+    },
 };
-use aptos_consensus_types::common::Payload;
+use aptos_consensus_types::common::{Payload, Round};
 use aptos_crypto::HashValue;
 use aptos_types::{
-    aggregate_signature::AggregateSignature, validator_verifier::random_validator_verifier,
+    aggregate_signature::{AggregateSignature, PartialSignatures},
+    chain_id::ChainId,
+    epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier},
 };
-use claims::assert_ok;
-use std::vec;
+use claims::{assert_none, assert_ok, assert_some};
+use std::{collections::HashMap, sync::Arc, vec};
 
 #[test]
 fn test_node_verify() {
@@ -41,9 +48,10 @@ fn test_node_verify() {
         "not enough parents to satisfy voting power",
     );
 
-    // Round 1
+    // Parent in the same round
+    let same_round_node = new_node(3, 10, signers[1].author(), vec![]);
     let parent_cert = NodeCertificate::new(
-        zeroth_round_node.metadata().clone(),
+        same_round_node.metadata().clone(),
         AggregateSignature::empty(),
     );
     let node = new_node(3, 20, signers[0].author(), vec![parent_cert]);
@@ -53,6 +61,54 @@ fn test_node_verify() {
     );
 }
 
+#[test]
+fn test_node_verify_parent_power() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let certificate = |round, index: usize, timestamp| {
+        let node = new_node(round, timestamp, signers[index].author(), vec![]);
+        NodeCertificate::new(node.metadata().clone(), AggregateSignature::empty())
+    };
+    let round_1: Vec<_> = (0..4).map(|index| certificate(1, index, 1)).collect();
+    let round_2: Vec<_> = (0..4).map(|index| certificate(2, index, 2)).collect();
+    let verify = |parents: Vec<NodeCertificate>| {
+        new_node(3, 3, signers[0].author(), parents)
+            .verify(&validator_verifier)
+            .map_err(|e| e.to_string())
+    };
+    let not_enough = Err("not enough parents to satisfy voting power".to_string());
+
+    assert_ok!(verify(round_2[0..3].to_vec()));
+    // weak links to the nodes the strong links miss
+    assert_ok!(verify(vec![
+        round_2[0].clone(),
+        round_2[1].clone(),
+        round_2[2].clone(),
+        round_1[3].clone(),
+    ]));
+
+    // the authors of the strong links again in weak links, 4 parents by a naive count
+    assert_eq!(
+        verify(vec![
+            round_2[0].clone(),
+            round_2[1].clone(),
+            round_1[0].clone(),
+            round_1[1].clone(),
+        ]),
+        not_enough
+    );
+    // weak links alone
+    assert_eq!(verify(round_1), not_enough);
+    // two certificates of the same author in the previous round
+    assert_eq!(
+        verify(vec![
+            round_2[0].clone(),
+            certificate(2, 0, 20),
+            round_2[1].clone(),
+        ]),
+        not_enough
+    );
+}
+
 #[test]
 fn test_certified_node_verify() {
     let (signers, validator_verifier) = random_validator_verifier(4, None, false);
@@ -81,3 +137,220 @@ fn test_certified_node_verify() {
         "unable to verify: Invalid bitvec from the multi-signature"
     );
 }
+
+#[test]
+fn test_signature_builder() {
+    // the broadcast task owns the builder
+    fn assert_send<T: Send>() {}
+    assert_send::<SignatureBuilder>();
+
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let quorum_voting_power = validator_verifier.quorum_voting_power();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let node = new_node(1, 10, signers[0].author(), vec![]);
+    let mut builder = SignatureBuilder::new(node.metadata().clone(), epoch_state);
+    assert_eq!(builder.missing_power(), quorum_voting_power);
+
+    let signature = node.sign(&signers[0]).unwrap();
+    assert_none!(builder
+        .add_signature(signers[0].author(), signature.clone())
+        .unwrap());
+    assert_eq!(builder.missing_power(), quorum_voting_power - 1);
+
+    // duplicate signature is ignored
+    assert_none!(builder
+        .add_signature(signers[0].author(), signature)
+        .unwrap());
+    assert_eq!(builder.missing_power(), quorum_voting_power - 1);
+
+    // signature over another digest
+    let other_node = new_node(1, 20, signers[0].author(), vec![]);
+    assert_eq!(
+        builder
+            .add_signature(signers[1].author(), other_node.sign(&signers[1]).unwrap())
+            .unwrap_err(),
+        SignatureBuilderError::InvalidSignature(signers[1].author())
+    );
+
+    // signature from a non validator
+    let (outsiders, _) = random_validator_verifier(1, None, false);
+    assert_eq!(
+        builder
+            .add_signature(outsiders[0].author(), node.sign(&outsiders[0]).unwrap())
+            .unwrap_err(),
+        SignatureBuilderError::UnknownAuthor(outsiders[0].author())
+    );
+    assert_eq!(builder.missing_power(), quorum_voting_power - 1);
+
+    // the certificate is produced exactly when the quorum is reached and only once
+    let mut certificate = None;
+    for signer in &signers[1..] {
+        let result = builder
+            .add_signature(signer.author(), node.sign(signer).unwrap())
+            .unwrap();
+        if builder.missing_power() == 0 && certificate.is_none() {
+            certificate = assert_some!(result).into();
+        } else {
+            assert_none!(result);
+        }
+    }
+    let certified_node = CertifiedNode::from_certificate(node, certificate.unwrap()).unwrap();
+    assert_ok!(certified_node.verify(&validator_verifier));
+}
+
+/// The certified nodes of `num_rounds` rounds from round 1, every node links to all the nodes of
+/// the round before and is signed by all the validators.
+fn new_signed_dag(
+    signers: &[ValidatorSigner],
+    validator_verifier: &ValidatorVerifier,
+    num_rounds: Round,
+) -> Vec<Vec<CertifiedNode>> {
+    let mut dag: Vec<Vec<CertifiedNode>> = vec![];
+    for round in 1..=num_rounds {
+        let parents: Vec<_> = dag.last().map_or(vec![], |nodes| {
+            nodes.iter().map(|node| node.certificate()).collect()
+        });
+        let round_nodes = signers
+            .iter()
+            .map(|author| {
+                let node = Node::new(
+                    ChainId::test(),
+                    1,
+                    round,
+                    author.author(),
+                    round,
+                    Payload::empty(false),
+                    parents.clone(),
+                );
+                let mut partial_signatures = PartialSignatures::empty();
+                for signer in signers {
+                    partial_signatures.add_signature(signer.author(), node.sign(signer).unwrap());
+                }
+                let signatures = validator_verifier
+                    .aggregate_signatures(&partial_signatures)
+                    .unwrap();
+                CertifiedNode::new(node, signatures)
+            })
+            .collect();
+        dag.push(round_nodes);
+    }
+    dag
+}
+
+#[test]
+fn test_certificate_size_cached() {
+    let (signers, _) = random_validator_verifier(4, None, false);
+    let parents: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]).certificate())
+        .collect();
+    let node = new_certified_node(2, signers[0].author(), parents);
+    let certificate = node.certificate();
+    let size = bcs::to_bytes(&certificate).unwrap().len();
+    assert_eq!(node.certificate_size(), size);
+    assert_eq!(certificate.serialized_size(), size);
+
+    // the cache isn't sent and doesn't take part in the comparison
+    let decoded: NodeCertificate = bcs::from_bytes(&bcs::to_bytes(&certificate).unwrap()).unwrap();
+    assert_eq!(decoded, certificate);
+    assert_eq!(decoded.serialized_size(), size);
+    let decoded: CertifiedNode = bcs::from_bytes(&bcs::to_bytes(&node).unwrap()).unwrap();
+    assert_eq!(decoded, node);
+    assert_eq!(decoded.certificate(), certificate);
+}
+
+#[test]
+fn test_compact_certified_node() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = new_signed_dag(&signers, &validator_verifier, 3);
+    let certificates: HashMap<_, _> = dag[1]
+        .iter()
+        .map(|node| (node.digest(), node.certificate()))
+        .collect();
+    let lookup = |digest: &HashValue| certificates.get(digest).cloned();
+    let node = dag[2][0].clone();
+
+    let compact = CompactCertifiedNode::new(&node, |_| true);
+    assert_eq!(compact.metadata(), node.metadata());
+    let serialized = bcs::to_bytes(&compact).unwrap();
+    assert!(serialized.len() < bcs::to_bytes(&node).unwrap().len());
+    let compact: CompactCertifiedNode = bcs::from_bytes(&serialized).unwrap();
+    assert_eq!(compact.clone().into_certified_node(lookup).unwrap(), node);
+    assert_eq!(
+        CompactCertifiedNode::new(&node, |_| false)
+            .into_certified_node(|_| None)
+            .unwrap(),
+        node
+    );
+    let known = node.parents()[1].metadata().clone();
+    assert_eq!(
+        CompactCertifiedNode::new(&node, |parent| *parent == known)
+            .into_certified_node(lookup)
+            .unwrap(),
+        node
+    );
+
+    // a parent missing from the receiver
+    assert!(compact
+        .clone()
+        .into_certified_node(|_| None)
+        .unwrap_err()
+        .to_string()
+        .starts_with("unknown parent"));
+    // a parent certificate other than the one the node was built with
+    let other_certificate = |digest: &HashValue| {
+        lookup(digest).map(|certificate| {
+            NodeCertificate::new(certificate.metadata().clone(), AggregateSignature::empty())
+        })
+    };
+    assert!(compact
+        .into_certified_node(other_certificate)
+        .unwrap_err()
+        .to_string()
+        .starts_with("reconstructed node doesn't match the digest"));
+}
+
+#[test]
+fn test_compact_fetch_response_size() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = new_signed_dag(&signers, &validator_verifier, 8);
+    // the requester has the first 6 rounds and fetches the last 2
+    let certificates: HashMap<_, _> = dag[0..6]
+        .iter()
+        .flatten()
+        .map(|node| (node.digest(), node.certificate()))
+        .collect();
+    let fetched = dag[6..].to_vec();
+    let full = FetchResponse::new(1, fetched.clone());
+    let compact = FetchResponse::new_compact(
+        1,
+        fetched
+            .iter()
+            .map(|round_nodes| {
+                round_nodes
+                    .iter()
+                    .map(|node| CompactCertifiedNode::new(node, |parent| parent.round() <= 6))
+                    .collect()
+            })
+            .collect(),
+    );
+    let full_size = bcs::to_bytes(&full).unwrap().len();
+    let compact_size = bcs::to_bytes(&compact).unwrap().len();
+    // the parents of the first fetched round are left out, the ones of the second are sent
+    assert!(
+        compact_size * 4 < full_size * 3,
+        "compact {} bytes, full {} bytes",
+        compact_size,
+        full_size
+    );
+
+    assert!(compact.is_compact());
+    let expanded = compact
+        .expand(|digest| certificates.get(digest).cloned())
+        .unwrap();
+    assert!(!expanded.is_compact());
+    assert_eq!(expanded.certified_nodes(), fetched);
+}
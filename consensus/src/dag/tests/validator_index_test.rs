@@ -0,0 +1,61 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        peer_tracker::DagPeerTracker,
+        tests::{dag_test::MockStorage, helpers::TestDag},
+        validator_index::ValidatorIndex,
+    },
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_consensus_types::common::Author;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn test_validator_index_lookups() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    };
+    let index = ValidatorIndex::new(&epoch_state);
+    assert_eq!(index.len(), 4);
+    assert_eq!(index.authors(), authors.as_slice());
+    for (position, author) in authors.iter().enumerate() {
+        assert_eq!(index.index_of(author), Some(position));
+        assert_eq!(index.author_at(position), Some(author));
+    }
+    assert_eq!(index.author_at(3), authors.last());
+    assert_eq!(index.author_at(4), None);
+    assert_eq!(index.index_of(&Author::random()), None);
+    assert!(index.matches(&epoch_state.verifier));
+    let (_, other_verifier) = random_validator_verifier(4, None, false);
+    assert!(!index.matches(&other_verifier));
+}
+
+#[test]
+fn test_validator_index_shared() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state.clone(), Arc::new(MockStorage::new()));
+    let tracker = DagPeerTracker::new(
+        dag.validator_index().clone(),
+        Arc::new(SimulatedTimeService::new()),
+        Duration::from_secs(10),
+    );
+    assert!(Arc::ptr_eq(
+        dag.validator_index(),
+        tracker.validator_index()
+    ));
+
+    // the same validators keep the same handle
+    let handle = dag.validator_index().clone();
+    assert!(dag.replace_epoch_state(epoch_state).is_ok());
+    assert!(Arc::ptr_eq(dag.validator_index(), &handle));
+}
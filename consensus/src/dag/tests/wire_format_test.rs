@@ -0,0 +1,356 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    dag_store::DagStoreError,
+    node_builder::CertifiedNodeBuilder,
+    tests::{
+        dag_test::MockStorage,
+        helpers::{new_certified_node, TestDag},
+    },
+    types::{
+        AuthorFetchRequest, CertifiedNode, CompactCertifiedNode, DAGMessage, DAGNetworkMessage,
+        DagSnapshotBitmask, FetchResponse, NodeCertificate, RemoteFetchRequest, SkipCertificate,
+        TDAGMessage,
+    },
+    wire_limits::{MAX_FETCH_RESPONSE_NODES, MAX_FETCH_ROUNDS, MAX_VALIDATORS},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_types::{
+    epoch_state::EpochState,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier},
+};
+use proptest::prelude::*;
+use std::{collections::HashMap, sync::Arc};
+
+fn uleb128(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn new_rounds(authors: &[Author], num_rounds: Round) -> Vec<Vec<CertifiedNode>> {
+    let mut rounds: Vec<Vec<CertifiedNode>> = vec![];
+    for round in 1..=num_rounds {
+        let parents: Vec<NodeCertificate> = rounds
+            .last()
+            .map(|nodes| nodes.iter().map(|node| node.certificate()).collect())
+            .unwrap_or_default();
+        rounds.push(
+            authors
+                .iter()
+                .map(|author| new_certified_node(round, *author, parents.clone()))
+                .collect(),
+        );
+    }
+    rounds
+}
+
+/// Valid encodings of the messages a peer sends, the seeds of the mutations.
+fn valid_messages(authors: &[Author]) -> Vec<Vec<u8>> {
+    let rounds = new_rounds(authors, 3);
+    let target = rounds[2][0].metadata().clone();
+    let bitmask = DagSnapshotBitmask::new(1, vec![vec![true, true, false, true]]);
+    let compact = rounds[1..]
+        .iter()
+        .map(|nodes| {
+            nodes
+                .iter()
+                .map(|node| CompactCertifiedNode::new(node, |parent| parent.round() == 1))
+                .collect()
+        })
+        .collect();
+    let messages = vec![
+        DAGMessage::CertifiedNodeMsg(rounds[1][0].clone()),
+        DAGMessage::FetchRequest(RemoteFetchRequest::new(
+            target,
+            bitmask,
+            DagSnapshotBitmask::new(1, vec![]),
+        )),
+        DAGMessage::FetchResponse(FetchResponse::new(1, rounds.clone())),
+        DAGMessage::FetchResponse(FetchResponse::new_compact(1, compact)),
+        DAGMessage::AuthorFetchRequest(AuthorFetchRequest::new(1, authors[0], 1, 3)),
+    ];
+    messages
+        .iter()
+        .map(|message| bcs::to_bytes(message).unwrap())
+        .collect()
+}
+
+/// Decodes the bytes as every type received from a peer and runs the checks it goes through
+/// before reaching the store. Either may fail, neither may panic.
+fn decode_and_verify(bytes: &[u8], verifier: &ValidatorVerifier, authors: &[Author]) {
+    let lookup = |_: &HashValue| None;
+    let author_request = AuthorFetchRequest::new(1, authors[0], 1, 3);
+    if let Ok(bitmask) = bcs::from_bytes::<DagSnapshotBitmask>(bytes) {
+        let _ = bitmask.verify(verifier.len());
+    }
+    if let Ok(certificate) = bcs::from_bytes::<SkipCertificate>(bytes) {
+        let _ = certificate.verify(verifier);
+    }
+    if let Ok(node) = bcs::from_bytes::<CompactCertifiedNode>(bytes) {
+        if let Ok(node) = node.into_certified_node(lookup) {
+            let _ = node.verify(verifier);
+        }
+    }
+    let network_message = DAGNetworkMessage {
+        epoch: 1,
+        data: bytes.to_vec(),
+    };
+    match DAGMessage::try_from(network_message) {
+        Ok(DAGMessage::NodeMsg(node)) => {
+            let _ = node.verify(verifier);
+        },
+        Ok(DAGMessage::CertifiedNodeMsg(node)) => {
+            let _ = node.verify(verifier);
+        },
+        Ok(DAGMessage::FetchRequest(request)) => {
+            let _ = request.verify(verifier);
+        },
+        Ok(DAGMessage::AuthorFetchRequest(request)) => {
+            let _ = request.verify(verifier);
+        },
+        Ok(DAGMessage::FetchResponse(response)) => {
+            if let Ok(response) = response.expand(lookup) {
+                let _ = response.verify_author_nodes(&author_request, verifier);
+            }
+        },
+        _ => (),
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn test_random_bytes_rejected_without_panic(bytes in prop::collection::vec(any::<u8>(), 0..1024)) {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let authors = validator_verifier.get_ordered_account_addresses();
+        decode_and_verify(&bytes, &validator_verifier, &authors);
+    }
+
+    #[test]
+    fn test_mutated_messages_rejected_without_panic(
+        message in any::<prop::sample::Index>(),
+        mutations in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+        truncate in any::<Option<prop::sample::Index>>(),
+    ) {
+        let (_, validator_verifier) = random_validator_verifier(4, None, false);
+        let authors = validator_verifier.get_ordered_account_addresses();
+        let messages = valid_messages(&authors);
+        let mut bytes = message.get(&messages).clone();
+        for (position, value) in mutations {
+            let position = position.index(bytes.len());
+            bytes[position] ^= value;
+        }
+        if let Some(len) = truncate {
+            bytes.truncate(len.index(bytes.len()));
+        }
+        decode_and_verify(&bytes, &validator_verifier, &authors);
+    }
+}
+
+#[test]
+fn test_zero_length_bitmask() {
+    let empty = DagSnapshotBitmask::new(5, vec![]);
+    let decoded: DagSnapshotBitmask = bcs::from_bytes(&bcs::to_bytes(&empty).unwrap()).unwrap();
+    assert_eq!(decoded, empty);
+    assert!(decoded.verify(4).is_ok());
+    assert!(!decoded.has(5, 0));
+
+    let zero_width = DagSnapshotBitmask::new(5, vec![vec![]]);
+    let decoded: DagSnapshotBitmask =
+        bcs::from_bytes(&bcs::to_bytes(&zero_width).unwrap()).unwrap();
+    assert_eq!(
+        decoded.verify(4).unwrap_err().to_string(),
+        "bitmask doesn't match the validator set"
+    );
+}
+
+#[test]
+fn test_bitmask_width_mismatch() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let target = new_certified_node(3, authors[0], vec![]).metadata().clone();
+    for row in [vec![true; 3], vec![true; 5]] {
+        let request = RemoteFetchRequest::new(
+            target.clone(),
+            DagSnapshotBitmask::new(1, vec![vec![true; 4], row]),
+            DagSnapshotBitmask::new(1, vec![]),
+        );
+        assert_eq!(
+            request.verify(&validator_verifier).unwrap_err().to_string(),
+            "bitmask doesn't match the validator set"
+        );
+    }
+}
+
+#[test]
+fn test_bitmask_beyond_highest_round() {
+    let bitmask = DagSnapshotBitmask::new(Round::MAX, vec![vec![false; 4]]);
+    let decoded: DagSnapshotBitmask = bcs::from_bytes(&bcs::to_bytes(&bitmask).unwrap()).unwrap();
+    assert_eq!(
+        decoded.verify(4).unwrap_err().to_string(),
+        "bitmask ends beyond the highest round"
+    );
+    assert!(
+        DagSnapshotBitmask::new(Round::MAX - 1, vec![vec![false; 4]])
+            .verify(4)
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_oversized_bitmask_rejected_at_decoding() {
+    // the rows are all there, only their number is beyond the limit
+    let mut bytes = 1u64.to_le_bytes().to_vec();
+    bytes.extend(uleb128(MAX_FETCH_ROUNDS));
+    bytes.extend(vec![0; MAX_FETCH_ROUNDS]);
+    let decoded: DagSnapshotBitmask = bcs::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.bitmask().len(), MAX_FETCH_ROUNDS);
+
+    let mut bytes = 1u64.to_le_bytes().to_vec();
+    bytes.extend(uleb128(MAX_FETCH_ROUNDS + 1));
+    bytes.extend(vec![0; MAX_FETCH_ROUNDS + 1]);
+    assert!(bcs::from_bytes::<DagSnapshotBitmask>(&bytes).is_err());
+
+    // a single row wider than the largest validator set
+    let mut bytes = 1u64.to_le_bytes().to_vec();
+    bytes.extend(uleb128(1));
+    bytes.extend(uleb128(MAX_VALIDATORS + 1));
+    bytes.extend(vec![0; MAX_VALIDATORS + 1]);
+    assert!(bcs::from_bytes::<DagSnapshotBitmask>(&bytes).is_err());
+
+    // a length prefix far beyond the bytes, nothing is reserved for it
+    let mut bytes = 1u64.to_le_bytes().to_vec();
+    bytes.extend(uleb128(u32::MAX as usize >> 1));
+    assert!(bcs::from_bytes::<DagSnapshotBitmask>(&bytes).is_err());
+}
+
+#[test]
+fn test_fetch_response_truncated_to_limits() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let node = new_certified_node(1, authors[0], vec![]);
+
+    let response = FetchResponse::new(1, vec![vec![]; MAX_FETCH_ROUNDS + 10]);
+    assert_eq!(response.certified_nodes().len(), MAX_FETCH_ROUNDS);
+
+    // 166 rounds of 600 nodes fit, the 167th goes beyond the nodes limit
+    let rounds = vec![vec![node; 600]; 200];
+    let response = FetchResponse::new(1, rounds);
+    let bytes = bcs::to_bytes(&response).unwrap();
+    let response = bcs::from_bytes::<FetchResponse>(&bytes)
+        .unwrap()
+        .certified_nodes();
+    assert_eq!(response.len(), MAX_FETCH_RESPONSE_NODES / 600);
+    assert!(response.iter().flatten().count() <= MAX_FETCH_RESPONSE_NODES);
+}
+
+#[test]
+fn test_tampered_compact_node() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let parents = new_rounds(&authors, 1).remove(0);
+    let timestamp: u64 = 0x0102_0304_0506_0708;
+    let node = CertifiedNodeBuilder::new(&new_certified_node(
+        2,
+        authors[0],
+        parents.iter().map(|parent| parent.certificate()).collect(),
+    ))
+    .timestamp(timestamp)
+    .build(&signers, &validator_verifier);
+    assert!(node.verify(&validator_verifier).is_ok());
+    let certificates: HashMap<_, _> = parents
+        .iter()
+        .map(|parent| (parent.digest(), parent.certificate()))
+        .collect();
+    let lookup = |digest: &HashValue| certificates.get(digest).cloned();
+
+    let mut bytes = bcs::to_bytes(&CompactCertifiedNode::new(&node, |_| true)).unwrap();
+    let compact: CompactCertifiedNode = bcs::from_bytes(&bytes).unwrap();
+    assert_eq!(compact.into_certified_node(lookup).unwrap(), node);
+
+    let position = bytes
+        .windows(8)
+        .position(|window| window == timestamp.to_le_bytes())
+        .unwrap();
+    bytes[position] ^= 1;
+    let compact: CompactCertifiedNode = bcs::from_bytes(&bytes).unwrap();
+    assert!(compact
+        .into_certified_node(lookup)
+        .unwrap_err()
+        .to_string()
+        .starts_with("reconstructed node doesn't match the digest"));
+}
+
+#[test]
+fn test_signed_node_with_too_many_parents() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let parent = new_certified_node(1, authors[1], vec![]).certificate();
+    let builder = CertifiedNodeBuilder::new(&new_certified_node(2, authors[0], vec![]));
+
+    // duplicates within the limit decode and carry valid signatures, the store rejects them
+    let node = builder
+        .clone()
+        .parents(vec![parent.clone(); 3])
+        .build(&signers, &validator_verifier);
+    let decoded: CertifiedNode = bcs::from_bytes(&bcs::to_bytes(&node).unwrap()).unwrap();
+    assert!(decoded.verify(&validator_verifier).is_ok());
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    assert!(dag
+        .add_node(new_certified_node(1, authors[1], vec![]))
+        .is_ok());
+    assert!(matches!(
+        dag.add_node(decoded),
+        Err(DagStoreError::DuplicateParent(digest)) if digest == *parent.metadata().digest()
+    ));
+
+    // over the limit, the signed node doesn't decode
+    let node = builder
+        .parents(vec![parent; MAX_VALIDATORS + 1])
+        .build(&signers, &validator_verifier);
+    let bytes = bcs::to_bytes(&node).unwrap();
+    assert!(bcs::from_bytes::<CertifiedNode>(&bytes).is_err());
+}
+
+#[test]
+fn test_cursors_below_lowest_round() {
+    let (_, validator_verifier) = random_validator_verifier(4, None, false);
+    let authors = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let mut dag = TestDag::new(epoch_state, Arc::new(MockStorage::new()));
+    let rounds = new_rounds(&authors, 3);
+    for node in rounds.iter().flatten() {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    assert_eq!(dag.prune_below(2).unwrap(), 4);
+
+    let nodes = dag.get_author_nodes_in_range(&authors[0], 0..=3);
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(nodes[0].metadata().round(), 2);
+    assert!(dag.get_author_nodes_in_range(&authors[0], 0..=1).is_empty());
+
+    let request = RemoteFetchRequest::new(
+        rounds[2][0].metadata().clone(),
+        DagSnapshotBitmask::new(0, vec![]),
+        DagSnapshotBitmask::new(0, vec![]),
+    );
+    assert_eq!(dag.get_missing_nodes(&request), vec![rounds[1].clone()]);
+}
@@ -2,10 +2,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    dag::reliable_broadcast::BroadcastStatus, network::TConsensusMsg,
+    dag::{
+        batch_verify::verify_nodes_batched,
+        reliable_broadcast::BroadcastStatus,
+        wire_limits::{
+            deserialize_bitmask, deserialize_parents, deserialize_response_nodes,
+            truncate_response_nodes,
+        },
+    },
+    network::TConsensusMsg,
     network_interface::ConsensusMsg,
 };
 use anyhow::{bail, ensure};
+use aptos_bitvec::BitVec;
 use aptos_consensus_types::common::{Author, Payload, Round};
 use aptos_crypto::{
     bls12381,
@@ -13,16 +22,23 @@ use aptos_crypto::{
     hash::{CryptoHash, CryptoHasher},
     CryptoMaterialError, HashValue,
 };
-use aptos_crypto_derive::CryptoHasher;
+use aptos_crypto_derive::{BCSCryptoHash, CryptoHasher};
 use aptos_enum_conversion_derive::EnumConversion;
 use aptos_types::{
     aggregate_signature::{AggregateSignature, PartialSignatures},
+    chain_id::ChainId,
     epoch_state::EpochState,
     validator_signer::ValidatorSigner,
-    validator_verifier::ValidatorVerifier,
+    validator_verifier::{ValidatorVerifier, VerifyError},
 };
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, ops::Deref, sync::Arc};
+use std::{
+    collections::HashSet,
+    ops::{Deref, RangeInclusive},
+    sync::Arc,
+};
+use thiserror::Error as ThisError;
 
 pub trait TDAGMessage: Into<DAGMessage> + TryFrom<DAGMessage> {
     fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()>;
@@ -42,6 +58,7 @@ impl TDAGMessage for CertifiedAck {
 
 #[derive(Serialize)]
 struct NodeWithoutDigest<'a> {
+    chain_id: ChainId,
     epoch: u64,
     round: Round,
     author: Author,
@@ -64,6 +81,7 @@ impl<'a> CryptoHash for NodeWithoutDigest<'a> {
 impl<'a> From<&'a Node> for NodeWithoutDigest<'a> {
     fn from(node: &'a Node) -> Self {
         Self {
+            chain_id: node.metadata.chain_id,
             epoch: node.metadata.epoch,
             round: node.metadata.round,
             author: node.metadata.author,
@@ -77,6 +95,8 @@ impl<'a> From<&'a Node> for NodeWithoutDigest<'a> {
 /// Represents the metadata about the node, without payload and parents from Node
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct NodeMetadata {
+    /// Binds the node to its network, the same validator keys can be used on several chains
+    chain_id: ChainId,
     epoch: u64,
     round: Round,
     author: Author,
@@ -94,6 +114,7 @@ impl NodeMetadata {
         digest: HashValue,
     ) -> Self {
         Self {
+            chain_id: ChainId::test(),
             epoch,
             round,
             author,
@@ -102,6 +123,10 @@ impl NodeMetadata {
         }
     }
 
+    pub fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
+
     pub fn digest(&self) -> &HashValue {
         &self.digest
     }
@@ -117,6 +142,10 @@ impl NodeMetadata {
     pub fn epoch(&self) -> u64 {
         self.epoch
     }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
 }
 
 #[derive(Serialize)]
@@ -143,11 +172,13 @@ impl CryptoHash for NodeDigest {
 pub struct Node {
     metadata: NodeMetadata,
     payload: Payload,
+    #[serde(deserialize_with = "deserialize_parents")]
     parents: Vec<NodeCertificate>,
 }
 
 impl Node {
     pub fn new(
+        chain_id: ChainId,
         epoch: u64,
         round: Round,
         author: Author,
@@ -155,11 +186,13 @@ impl Node {
         payload: Payload,
         parents: Vec<NodeCertificate>,
     ) -> Self {
-        let digest =
-            Self::calculate_digest_internal(epoch, round, author, timestamp, &payload, &parents);
+        let digest = Self::calculate_digest_internal(
+            chain_id, epoch, round, author, timestamp, &payload, &parents,
+        );
 
         Self {
             metadata: NodeMetadata {
+                chain_id,
                 epoch,
                 round,
                 author,
@@ -186,6 +219,7 @@ impl Node {
 
     /// Calculate the node digest based on all fields in the node
     fn calculate_digest_internal(
+        chain_id: ChainId,
         epoch: u64,
         round: Round,
         author: Author,
@@ -194,6 +228,7 @@ impl Node {
         parents: &Vec<NodeCertificate>,
     ) -> HashValue {
         let node_with_out_digest = NodeWithoutDigest {
+            chain_id,
             epoch,
             round,
             author,
@@ -206,6 +241,7 @@ impl Node {
 
     fn calculate_digest(&self) -> HashValue {
         Self::calculate_digest_internal(
+            self.metadata.chain_id,
             self.metadata.epoch,
             self.metadata.round,
             self.metadata.author,
@@ -219,6 +255,11 @@ impl Node {
         self.metadata.digest
     }
 
+    /// Whether the digest is the hash of the contents of the node.
+    pub fn has_valid_digest(&self) -> bool {
+        self.digest() == self.calculate_digest()
+    }
+
     pub fn metadata(&self) -> &NodeMetadata {
         &self.metadata
     }
@@ -227,6 +268,26 @@ impl Node {
         &self.parents
     }
 
+    /// The parents in the round before the node.
+    pub fn strong_links(&self) -> impl Iterator<Item = &NodeCertificate> {
+        let round = self.metadata.round;
+        self.parents
+            .iter()
+            .filter(move |parent| parent.metadata().round() + 1 == round)
+    }
+
+    /// The parents in older rounds, references to nodes the strong links don't reach.
+    pub fn weak_links(&self) -> impl Iterator<Item = &NodeCertificate> {
+        let round = self.metadata.round;
+        self.parents
+            .iter()
+            .filter(move |parent| parent.metadata().round() + 1 < round)
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
     pub fn author(&self) -> &Author {
         self.metadata.author()
     }
@@ -240,7 +301,7 @@ impl Node {
 impl TDAGMessage for Node {
     fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
         // TODO: move this check to rpc process logic to delay it as much as possible for performance
-        ensure!(self.digest() == self.calculate_digest(), "invalid digest");
+        ensure!(self.has_valid_digest(), "invalid digest");
 
         let current_round = self.metadata().round();
 
@@ -249,22 +310,22 @@ impl TDAGMessage for Node {
             return Ok(());
         }
 
-        let prev_round = current_round - 1;
-        // check if the parents' round is the node's round - 1
         ensure!(
             self.parents()
                 .iter()
-                .all(|parent| parent.metadata().round() == prev_round),
+                .all(|parent| parent.metadata().round() < current_round),
             "invalid parent round"
         );
 
+        // only the strong links count towards the quorum, once per author, a weak link or a
+        // second certificate of the same author carries no voting power
+        let strong_link_authors: HashSet<_> = self
+            .strong_links()
+            .map(|parent| parent.metadata().author())
+            .collect();
         ensure!(
             verifier
-                .check_voting_power(
-                    self.parents()
-                        .iter()
-                        .map(|parent| parent.metadata().author())
-                )
+                .check_voting_power(strong_link_authors.into_iter())
                 .is_ok(),
             "not enough parents to satisfy voting power"
         );
@@ -275,11 +336,283 @@ impl TDAGMessage for Node {
     }
 }
 
-/// Quorum signatures over the node digest
+/// Where a node of an ordered batch comes from, `position` is its index in the causal order of
+/// the anchor.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BatchSourceInfo {
+    author: Author,
+    round: Round,
+    digest: HashValue,
+    position: u64,
+}
+
+impl BatchSourceInfo {
+    pub fn new(node: &CertifiedNode, position: u64) -> Self {
+        Self {
+            author: *node.author(),
+            round: node.metadata().round(),
+            digest: node.digest(),
+            position,
+        }
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn digest(&self) -> &HashValue {
+        &self.digest
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+/// An ordered anchor with the sources of its batch, persisted so the order survives a restart.
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OrderedAnchor {
+    anchor: NodeMetadata,
+    sources: Vec<BatchSourceInfo>,
+}
+
+impl OrderedAnchor {
+    pub fn new(anchor: NodeMetadata, sources: Vec<BatchSourceInfo>) -> Self {
+        Self { anchor, sources }
+    }
+
+    pub fn anchor(&self) -> &NodeMetadata {
+        &self.anchor
+    }
+
+    pub fn sources(&self) -> &[BatchSourceInfo] {
+        &self.sources
+    }
+}
+
+/// The anchors ordered and not committed when an epoch ended, with the nodes they ordered in
+/// commit order. Persisted until the history is committed, the DAG of the next epoch deletes the
+/// nodes of the epoch.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct EpochRemnant {
+    epoch: u64,
+    anchors: Vec<OrderedAnchor>,
+    nodes: Vec<CertifiedNode>,
+}
+
+impl EpochRemnant {
+    pub fn new(epoch: u64, anchors: Vec<OrderedAnchor>, nodes: Vec<CertifiedNode>) -> Self {
+        Self {
+            epoch,
+            anchors,
+            nodes,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// By round
+    pub fn anchors(&self) -> &[OrderedAnchor] {
+        &self.anchors
+    }
+
+    /// The nodes of each anchor in the order of its sources, anchor after anchor
+    pub fn nodes(&self) -> &[CertifiedNode] {
+        &self.nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+}
+
+/// The two nodes of an equivocation, or only their metadata once the author used up the full
+/// records retained for it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum EvidenceNodes {
+    Full {
+        kept: Box<CertifiedNode>,
+        conflicting: Box<CertifiedNode>,
+    },
+    Metadata {
+        kept: NodeMetadata,
+        conflicting: NodeMetadata,
+    },
+}
+
+/// A node conflicting with the node the DAG kept for the same author and round, persisted until
+/// pruning drops it, which spares the records pending report.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct EvidenceRecord {
+    epoch: u64,
+    nodes: EvidenceNodes,
+    pending_report: bool,
+}
+
+impl EvidenceRecord {
+    pub fn new(epoch: u64, nodes: EvidenceNodes) -> Self {
+        Self {
+            epoch,
+            nodes,
+            pending_report: false,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn author(&self) -> &Author {
+        self.conflicting().author()
+    }
+
+    pub fn round(&self) -> Round {
+        self.conflicting().round()
+    }
+
+    pub fn kept(&self) -> &NodeMetadata {
+        match &self.nodes {
+            EvidenceNodes::Full { kept, .. } => kept.metadata(),
+            EvidenceNodes::Metadata { kept, .. } => kept,
+        }
+    }
+
+    pub fn conflicting(&self) -> &NodeMetadata {
+        match &self.nodes {
+            EvidenceNodes::Full { conflicting, .. } => conflicting.metadata(),
+            EvidenceNodes::Metadata { conflicting, .. } => conflicting,
+        }
+    }
+
+    pub fn nodes(&self) -> &EvidenceNodes {
+        &self.nodes
+    }
+
+    pub fn is_full(&self) -> bool {
+        matches!(self.nodes, EvidenceNodes::Full { .. })
+    }
+
+    pub fn pending_report(&self) -> bool {
+        self.pending_report
+    }
+
+    pub fn set_pending_report(&mut self, pending_report: bool) {
+        self.pending_report = pending_report;
+    }
+}
+
+/// What validators sign to give up on the anchor of `round` when it doesn't show up in time.
+#[derive(Clone, Serialize, Deserialize, CryptoHasher, BCSCryptoHash, Debug, PartialEq, Eq)]
+pub struct SkipRound {
+    epoch: u64,
+    round: Round,
+    anchor: Author,
+}
+
+impl SkipRound {
+    pub fn new(epoch: u64, round: Round, anchor: Author) -> Self {
+        Self {
+            epoch,
+            round,
+            anchor,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn anchor(&self) -> &Author {
+        &self.anchor
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SkipVote {
+    skip: SkipRound,
+    author: Author,
+    signature: Signature,
+}
+
+impl SkipVote {
+    pub fn new(skip: SkipRound, signer: &ValidatorSigner) -> Result<Self, CryptoMaterialError> {
+        let signature = signer.sign(&skip)?;
+        Ok(Self {
+            skip,
+            author: signer.author(),
+            signature,
+        })
+    }
+
+    pub fn skip(&self) -> &SkipRound {
+        &self.skip
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+}
+
+/// Quorum signatures over a `SkipRound`, the round is abandoned once it's known.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SkipCertificate {
+    skip: SkipRound,
+    signatures: AggregateSignature,
+}
+
+impl SkipCertificate {
+    pub fn new(skip: SkipRound, signatures: AggregateSignature) -> Self {
+        Self { skip, signatures }
+    }
+
+    pub fn skip(&self) -> &SkipRound {
+        &self.skip
+    }
+
+    pub fn signatures(&self) -> &AggregateSignature {
+        &self.signatures
+    }
+
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        verifier
+            .verify_multi_signatures(&self.skip, &self.signatures)
+            .map_err(|e| anyhow::anyhow!("unable to verify: {}", e))
+    }
+}
+
+/// Quorum signatures over the node digest
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct NodeCertificate {
     metadata: NodeMetadata,
     signatures: AggregateSignature,
+    /// BCS size of the certificate, computed once
+    #[serde(skip)]
+    serialized_size: OnceCell<usize>,
+}
+
+fn certificate_size(metadata: &NodeMetadata, signatures: &AggregateSignature) -> usize {
+    bcs::serialized_size(metadata).expect("Unable to serialize metadata")
+        + bcs::serialized_size(signatures).expect("Unable to serialize signatures")
+}
+
+impl PartialEq for NodeCertificate {
+    fn eq(&self, other: &Self) -> bool {
+        self.metadata == other.metadata && self.signatures == other.signatures
+    }
 }
 
 impl NodeCertificate {
@@ -287,9 +620,17 @@ impl NodeCertificate {
         Self {
             metadata,
             signatures,
+            serialized_size: OnceCell::new(),
         }
     }
 
+    /// The bytes the certificate adds to a node linking it.
+    pub fn serialized_size(&self) -> usize {
+        *self
+            .serialized_size
+            .get_or_init(|| certificate_size(&self.metadata, &self.signatures))
+    }
+
     pub fn metadata(&self) -> &NodeMetadata {
         &self.metadata
     }
@@ -303,15 +644,29 @@ impl NodeCertificate {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CertifiedNode {
     node: Node,
     signatures: AggregateSignature,
+    /// `NodeCertificate::serialized_size` of the certificate, handed to every certificate taken
+    /// from the node
+    #[serde(skip)]
+    certificate_size: OnceCell<usize>,
+}
+
+impl PartialEq for CertifiedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node && self.signatures == other.signatures
+    }
 }
 
 impl CertifiedNode {
     pub fn new(node: Node, signatures: AggregateSignature) -> Self {
-        Self { node, signatures }
+        Self {
+            node,
+            signatures,
+            certificate_size: OnceCell::new(),
+        }
     }
 
     pub fn signatures(&self) -> &AggregateSignature {
@@ -319,7 +674,25 @@ impl CertifiedNode {
     }
 
     pub fn certificate(&self) -> NodeCertificate {
-        NodeCertificate::new(self.node.metadata.clone(), self.signatures.clone())
+        NodeCertificate {
+            metadata: self.node.metadata.clone(),
+            signatures: self.signatures.clone(),
+            serialized_size: OnceCell::with_value(self.certificate_size()),
+        }
+    }
+
+    pub fn certificate_size(&self) -> usize {
+        *self
+            .certificate_size
+            .get_or_init(|| certificate_size(&self.node.metadata, &self.signatures))
+    }
+
+    pub fn from_certificate(node: Node, certificate: NodeCertificate) -> anyhow::Result<Self> {
+        ensure!(
+            node.metadata() == certificate.metadata(),
+            "certificate doesn't match the node"
+        );
+        Ok(Self::new(node, certificate.signatures))
     }
 }
 
@@ -333,7 +706,7 @@ impl Deref for CertifiedNode {
 
 impl TDAGMessage for CertifiedNode {
     fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
-        ensure!(self.digest() == self.calculate_digest(), "invalid digest");
+        ensure!(self.has_valid_digest(), "invalid digest");
 
         let node_digest = NodeDigest::new(self.digest());
 
@@ -343,6 +716,86 @@ impl TDAGMessage for CertifiedNode {
     }
 }
 
+/// A parent of a `CompactCertifiedNode`, only its digest when the receiver already has it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum CompactParent {
+    Certificate(Box<NodeCertificate>),
+    Known(HashValue),
+}
+
+/// Wire representation of a `CertifiedNode` whose parents known to the receiver are sent as
+/// digests, the receiver puts back their certificates from its own DAG.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CompactCertifiedNode {
+    metadata: NodeMetadata,
+    payload: Payload,
+    #[serde(deserialize_with = "deserialize_parents")]
+    parents: Vec<CompactParent>,
+    signatures: AggregateSignature,
+}
+
+impl CompactCertifiedNode {
+    /// Sends the parents for which `is_known` holds as digests.
+    pub fn new(node: &CertifiedNode, is_known: impl Fn(&NodeMetadata) -> bool) -> Self {
+        let parents = node
+            .parents()
+            .iter()
+            .map(|parent| {
+                if is_known(parent.metadata()) {
+                    CompactParent::Known(*parent.metadata().digest())
+                } else {
+                    CompactParent::Certificate(Box::new(parent.clone()))
+                }
+            })
+            .collect();
+        Self {
+            metadata: node.metadata().clone(),
+            payload: node.payload().clone(),
+            parents,
+            signatures: node.signatures().clone(),
+        }
+    }
+
+    pub fn metadata(&self) -> &NodeMetadata {
+        &self.metadata
+    }
+
+    /// Rebuilds the certified node with the certificates `lookup` returns for the known parents.
+    /// Fails if a known parent can't be found, or if the rebuilt node doesn't hash to the original
+    /// digest because the receiver holds a different certificate for one of the parents.
+    pub fn into_certified_node(
+        self,
+        lookup: impl Fn(&HashValue) -> Option<NodeCertificate>,
+    ) -> anyhow::Result<CertifiedNode> {
+        let mut parents = Vec::with_capacity(self.parents.len());
+        for parent in self.parents {
+            parents.push(match parent {
+                CompactParent::Certificate(certificate) => *certificate,
+                CompactParent::Known(digest) => match lookup(&digest) {
+                    Some(certificate) => certificate,
+                    None => bail!("unknown parent {}", digest),
+                },
+            });
+        }
+        let metadata = self.metadata;
+        let node = Node::new(
+            metadata.chain_id,
+            metadata.epoch,
+            metadata.round,
+            metadata.author,
+            metadata.timestamp,
+            self.payload,
+            parents,
+        );
+        ensure!(
+            node.digest() == metadata.digest,
+            "reconstructed node doesn't match the digest {}",
+            metadata.digest
+        );
+        Ok(CertifiedNode::new(node, self.signatures))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct NodeDigestSignature {
     epoch: u64,
@@ -364,10 +817,22 @@ impl NodeDigestSignature {
     }
 }
 
+#[derive(Debug, ThisError, PartialEq, Eq)]
+pub enum SignatureBuilderError {
+    #[error("signature from unknown author {0}")]
+    UnknownAuthor(Author),
+    #[error("invalid signature from {0}")]
+    InvalidSignature(Author),
+}
+
+/// Accumulates the signatures of the validators on our node until they reach quorum voting power
+/// and aggregates them into the node certificate, which is produced only once.
 pub struct SignatureBuilder {
     metadata: NodeMetadata,
     partial_signatures: PartialSignatures,
     epoch_state: Arc<EpochState>,
+    voting_power: u128,
+    certified: bool,
 }
 
 impl SignatureBuilder {
@@ -376,8 +841,54 @@ impl SignatureBuilder {
             metadata,
             partial_signatures: PartialSignatures::empty(),
             epoch_state,
+            voting_power: 0,
+            certified: false,
         }
     }
+
+    /// Verifies and adds the signature of `author`, duplicates are ignored. Returns the
+    /// certificate when this signature brings the voting power to quorum.
+    pub fn add_signature(
+        &mut self,
+        author: Author,
+        signature: Signature,
+    ) -> Result<Option<NodeCertificate>, SignatureBuilderError> {
+        let verifier = &self.epoch_state.verifier;
+        let voting_power = verifier
+            .get_voting_power(&author)
+            .ok_or(SignatureBuilderError::UnknownAuthor(author))?;
+        if self.partial_signatures.signatures().contains_key(&author) {
+            return Ok(None);
+        }
+        verifier
+            .verify(author, &NodeDigest::new(self.metadata.digest), &signature)
+            .map_err(|e| match e {
+                VerifyError::UnknownAuthor => SignatureBuilderError::UnknownAuthor(author),
+                _ => SignatureBuilderError::InvalidSignature(author),
+            })?;
+        self.partial_signatures.add_signature(author, signature);
+        self.voting_power += voting_power as u128;
+
+        if self.certified || self.missing_power() > 0 {
+            return Ok(None);
+        }
+        self.certified = true;
+        let aggregated_signature = verifier
+            .aggregate_signatures(&self.partial_signatures)
+            .expect("Signature aggregation should succeed");
+        Ok(Some(NodeCertificate::new(
+            self.metadata.clone(),
+            aggregated_signature,
+        )))
+    }
+
+    /// Voting power still needed to reach quorum.
+    pub fn missing_power(&self) -> u128 {
+        self.epoch_state
+            .verifier
+            .quorum_voting_power()
+            .saturating_sub(self.voting_power)
+    }
 }
 
 impl BroadcastStatus for SignatureBuilder {
@@ -387,20 +898,7 @@ impl BroadcastStatus for SignatureBuilder {
 
     fn add(&mut self, peer: Author, ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
         ensure!(self.metadata.digest == ack.digest, "Digest mismatch");
-        self.partial_signatures.add_signature(peer, ack.signature);
-        Ok(self
-            .epoch_state
-            .verifier
-            .check_voting_power(self.partial_signatures.signatures().keys())
-            .ok()
-            .map(|_| {
-                let aggregated_signature = self
-                    .epoch_state
-                    .verifier
-                    .aggregate_signatures(&self.partial_signatures)
-                    .expect("Signature aggregation should succeed");
-                NodeCertificate::new(self.metadata.clone(), aggregated_signature)
-            }))
+        Ok(self.add_signature(peer, ack.signature)?)
     }
 }
 
@@ -429,6 +927,29 @@ impl CertifiedAck {
     }
 }
 
+/// The validators that acked the certified node broadcast by the local validator in a round, by
+/// validator index. Persisted so a restart only sends the node to the other validators.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BroadcastProgress {
+    digest: HashValue,
+    acked: BitVec,
+}
+
+impl BroadcastProgress {
+    pub fn new(digest: HashValue, acked: BitVec) -> Self {
+        Self { digest, acked }
+    }
+
+    /// The certified node the acks are for
+    pub fn digest(&self) -> &HashValue {
+        &self.digest
+    }
+
+    pub fn acked(&self) -> &BitVec {
+        &self.acked
+    }
+}
+
 impl BroadcastStatus for CertificateAckState {
     type Ack = CertifiedAck;
     type Aggregated = ();
@@ -444,35 +965,221 @@ impl BroadcastStatus for CertificateAckState {
     }
 }
 
-/// Represents a request to fetch missing dependencies for `target`, `start_round` represents
-/// the first round we care about in the DAG, `exists_bitmask` is a two dimensional bitmask represents
-/// if a node exist at [start_round + index][validator_index].
+/// A two dimensional bitmask of the DAG slots, `bitmask[round - start_round][validator_index]`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DagSnapshotBitmask {
+    start_round: Round,
+    #[serde(deserialize_with = "deserialize_bitmask")]
+    bitmask: Vec<Vec<bool>>,
+}
+
+impl DagSnapshotBitmask {
+    pub fn new(start_round: Round, bitmask: Vec<Vec<bool>>) -> Self {
+        Self {
+            start_round,
+            bitmask,
+        }
+    }
+
+    pub fn start_round(&self) -> Round {
+        self.start_round
+    }
+
+    pub fn bitmask(&self) -> &[Vec<bool>] {
+        &self.bitmask
+    }
+
+    /// Whether the slot is set, the slots outside of the bitmask are not.
+    pub fn has(&self, round: Round, index: usize) -> bool {
+        round
+            .checked_sub(self.start_round)
+            .and_then(|offset| self.bitmask.get(offset as usize))
+            .and_then(|slots| slots.get(index))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Checks a bitmask received from a peer, every round has a slot per validator and the last
+    /// round is a valid round.
+    pub fn verify(&self, num_validators: usize) -> anyhow::Result<()> {
+        ensure!(
+            self.bitmask
+                .iter()
+                .all(|slots| slots.len() == num_validators),
+            "bitmask doesn't match the validator set"
+        );
+        ensure!(
+            self.start_round
+                .checked_add(self.bitmask.len() as u64)
+                .is_some(),
+            "bitmask ends beyond the highest round"
+        );
+        Ok(())
+    }
+}
+
+/// Represents a request to fetch missing dependencies for `target`. `exists_bitmask` tells the
+/// nodes the requester has, `pending_bitmask` the nodes it holds until their parents arrive, which
+/// are not sent back but whose missing parents are. Both start at the lowest round of the
+/// requester. With `omit_known_parent_certs` the nodes are sent as `CompactCertifiedNode`s, the
+/// parents in `exists_bitmask` as digests.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RemoteFetchRequest {
     target: NodeMetadata,
-    start_round: Round,
-    exists_bitmask: Vec<Vec<bool>>,
+    exists_bitmask: DagSnapshotBitmask,
+    pending_bitmask: DagSnapshotBitmask,
+    omit_known_parent_certs: bool,
 }
 
 impl RemoteFetchRequest {
-    pub fn new(target: NodeMetadata, start_round: Round, exists_bitmask: Vec<Vec<bool>>) -> Self {
+    pub fn new(
+        target: NodeMetadata,
+        exists_bitmask: DagSnapshotBitmask,
+        pending_bitmask: DagSnapshotBitmask,
+    ) -> Self {
         Self {
             target,
-            start_round,
             exists_bitmask,
+            pending_bitmask,
+            omit_known_parent_certs: false,
+        }
+    }
+
+    pub fn with_omit_known_parent_certs(mut self, omit_known_parent_certs: bool) -> Self {
+        self.omit_known_parent_certs = omit_known_parent_certs;
+        self
+    }
+
+    pub fn omit_known_parent_certs(&self) -> bool {
+        self.omit_known_parent_certs
+    }
+
+    pub fn target(&self) -> &NodeMetadata {
+        &self.target
+    }
+
+    pub fn start_round(&self) -> Round {
+        self.exists_bitmask.start_round()
+    }
+
+    pub fn exists_bitmask(&self) -> &DagSnapshotBitmask {
+        &self.exists_bitmask
+    }
+
+    pub fn pending_bitmask(&self) -> &DagSnapshotBitmask {
+        &self.pending_bitmask
+    }
+}
+
+impl TDAGMessage for RemoteFetchRequest {
+    fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        ensure!(
+            self.exists_bitmask.start_round() == self.pending_bitmask.start_round(),
+            "bitmasks start at different rounds"
+        );
+        self.exists_bitmask.verify(verifier.len())?;
+        self.pending_bitmask.verify(verifier.len())
+    }
+}
+
+/// Represents a request to fetch the nodes of `author` from `start_round` to `end_round`, it's
+/// cheaper than the bitmask when the missing nodes all come from a single validator.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorFetchRequest {
+    epoch: u64,
+    author: Author,
+    start_round: Round,
+    end_round: Round,
+}
+
+impl AuthorFetchRequest {
+    pub fn new(epoch: u64, author: Author, start_round: Round, end_round: Round) -> Self {
+        Self {
+            epoch,
+            author,
+            start_round,
+            end_round,
         }
     }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn rounds(&self) -> RangeInclusive<Round> {
+        self.start_round..=self.end_round
+    }
+}
+
+impl TDAGMessage for AuthorFetchRequest {
+    fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        ensure!(self.start_round <= self.end_round, "invalid round range");
+        ensure!(
+            verifier.get_voting_power(&self.author).is_some(),
+            "unknown author"
+        );
+        Ok(())
+    }
 }
 
 /// Represents a response to FetchRequest, `certified_nodes` are indexed by [round][validator_index]
 /// It should fill in gaps from the `exists_bitmask` according to the parents from the `target_digest` node.
+/// A request omitting the known parent certificates is answered with `compact_nodes` instead, to
+/// be expanded by the requester before anything else.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FetchResponse {
     epoch: u64,
+    #[serde(deserialize_with = "deserialize_response_nodes")]
     certifies_nodes: Vec<Vec<CertifiedNode>>,
+    #[serde(deserialize_with = "deserialize_response_nodes")]
+    compact_nodes: Vec<Vec<CompactCertifiedNode>>,
 }
 
 impl FetchResponse {
+    /// The highest rounds beyond the limits of a response are left out, see
+    /// `truncate_response_nodes`.
+    pub fn new(epoch: u64, mut certifies_nodes: Vec<Vec<CertifiedNode>>) -> Self {
+        truncate_response_nodes(&mut certifies_nodes);
+        Self {
+            epoch,
+            certifies_nodes,
+            compact_nodes: vec![],
+        }
+    }
+
+    pub fn new_compact(epoch: u64, mut compact_nodes: Vec<Vec<CompactCertifiedNode>>) -> Self {
+        truncate_response_nodes(&mut compact_nodes);
+        Self {
+            epoch,
+            certifies_nodes: vec![],
+            compact_nodes,
+        }
+    }
+
+    pub fn is_compact(&self) -> bool {
+        !self.compact_nodes.is_empty()
+    }
+
+    /// Turns the compact nodes into certified nodes, `lookup` returns the certificates of the
+    /// requester's nodes. Fails if any of them can't be rebuilt exactly.
+    pub fn expand(
+        mut self,
+        lookup: impl Fn(&HashValue) -> Option<NodeCertificate>,
+    ) -> anyhow::Result<Self> {
+        for round_nodes in std::mem::take(&mut self.compact_nodes) {
+            let round_nodes = round_nodes
+                .into_iter()
+                .map(|node| node.into_certified_node(&lookup))
+                .collect::<anyhow::Result<_>>()?;
+            self.certifies_nodes.push(round_nodes);
+        }
+        Ok(self)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
     pub fn certified_nodes(self) -> Vec<Vec<CertifiedNode>> {
         self.certifies_nodes
     }
@@ -484,6 +1191,28 @@ impl FetchResponse {
     ) -> anyhow::Result<Self> {
         todo!("verification");
     }
+
+    /// Checks that every node of the response is a valid certified node of the requested author
+    /// within the requested rounds.
+    pub fn verify_author_nodes(
+        self,
+        request: &AuthorFetchRequest,
+        validator_verifier: &ValidatorVerifier,
+    ) -> anyhow::Result<Self> {
+        ensure!(self.epoch == request.epoch, "epoch mismatch");
+        for node in self.certifies_nodes.iter().flatten() {
+            ensure!(node.author() == request.author(), "unexpected author");
+            ensure!(
+                request.rounds().contains(&node.metadata().round()),
+                "unexpected round"
+            );
+        }
+        let nodes: Vec<_> = self.certifies_nodes.iter().flatten().collect();
+        for result in verify_nodes_batched(&nodes, validator_verifier) {
+            result?;
+        }
+        Ok(self)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -501,6 +1230,7 @@ pub enum DAGMessage {
     CertifiedAckMsg(CertifiedAck),
     FetchRequest(RemoteFetchRequest),
     FetchResponse(FetchResponse),
+    AuthorFetchRequest(AuthorFetchRequest),
 
     #[cfg(test)]
     TestMessage(TestMessage),
@@ -517,6 +1247,7 @@ impl DAGMessage {
             DAGMessage::CertifiedAckMsg(_) => "CertifiedAckMsg",
             DAGMessage::FetchRequest(_) => "FetchRequest",
             DAGMessage::FetchResponse(_) => "FetchResponse",
+            DAGMessage::AuthorFetchRequest(_) => "AuthorFetchRequest",
             #[cfg(test)]
             DAGMessage::TestMessage(_) => "TestMessage",
             #[cfg(test)]
@@ -542,6 +1273,7 @@ impl TConsensusMsg for DAGMessage {
             DAGMessage::CertifiedAckMsg(ack) => ack.epoch,
             DAGMessage::FetchRequest(req) => req.target.epoch,
             DAGMessage::FetchResponse(res) => res.epoch,
+            DAGMessage::AuthorFetchRequest(req) => req.epoch,
             #[cfg(test)]
             DAGMessage::TestMessage(_) => 1,
             #[cfg(test)]
@@ -0,0 +1,61 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_consensus_types::common::Author;
+use aptos_types::{epoch_state::EpochState, validator_verifier::ValidatorVerifier};
+use std::collections::HashMap;
+
+/// The position of each validator of an epoch in the order of the validator verifier, which is
+/// the order of the slots of a round. Built once per epoch and shared by handle between the DAG
+/// components instead of each keeping a copy.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidatorIndex {
+    author_to_index: HashMap<Author, usize>,
+    index_to_author: Vec<Author>,
+}
+
+impl ValidatorIndex {
+    pub fn new(epoch_state: &EpochState) -> Self {
+        let author_to_index = epoch_state.verifier.address_to_validator_index().clone();
+        let mut index_to_author = vec![Author::ZERO; author_to_index.len()];
+        for (author, index) in &author_to_index {
+            index_to_author[*index] = *author;
+        }
+        Self {
+            author_to_index,
+            index_to_author,
+        }
+    }
+
+    pub fn index_of(&self, author: &Author) -> Option<usize> {
+        self.author_to_index.get(author).copied()
+    }
+
+    pub fn author_at(&self, index: usize) -> Option<&Author> {
+        self.index_to_author.get(index)
+    }
+
+    /// The validators in index order.
+    pub fn authors(&self) -> &[Author] {
+        &self.index_to_author
+    }
+
+    /// Orders authors by validator index, the ones outside the validator set last by address, so
+    /// what's collected by author comes out the same on every run.
+    pub fn order_key(&self, author: &Author) -> (usize, Author) {
+        (self.index_of(author).unwrap_or(usize::MAX), *author)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index_to_author.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_to_author.is_empty()
+    }
+
+    /// Whether the validators of `verifier` have the same positions.
+    pub fn matches(&self, verifier: &ValidatorVerifier) -> bool {
+        verifier.address_to_validator_index() == &self.author_to_index
+    }
+}
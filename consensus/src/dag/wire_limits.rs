@@ -0,0 +1,170 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Size limits of the DAG types received from peers, enforced while they're deserialized so a
+//! length prefix claiming more than the limit fails before anything is allocated for it.
+
+use serde::{
+    de::{DeserializeSeed, Error, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{fmt, marker::PhantomData};
+
+/// Rounds in the bitmasks of a fetch request and in a fetch response.
+pub const MAX_FETCH_ROUNDS: usize = 1_000;
+
+/// Slots of a round and parents of a node, the size of the largest validator set.
+pub const MAX_VALIDATORS: usize = 65_536;
+
+/// Nodes in a fetch response over all its rounds.
+pub const MAX_FETCH_RESPONSE_NODES: usize = 100_000;
+
+/// Elements reserved up front, a longer sequence grows as its elements are actually read.
+const MAX_PREALLOCATED: usize = 4_096;
+
+fn check_len<E: Error>(len: Option<usize>, max: usize) -> Result<usize, E> {
+    match len {
+        Some(len) if len > max => Err(E::custom(format!(
+            "{} elements, at most {} allowed",
+            len, max
+        ))),
+        len => Ok(len.unwrap_or(0).min(MAX_PREALLOCATED)),
+    }
+}
+
+/// A sequence of at most `max_len` elements.
+struct BoundedSeq<T> {
+    max_len: usize,
+    element: PhantomData<T>,
+}
+
+impl<T> BoundedSeq<T> {
+    fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            element: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for BoundedSeq<T> {
+    type Value = Vec<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for BoundedSeq<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of at most {} elements", self.max_len)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut elements = Vec::with_capacity(check_len(seq.size_hint(), self.max_len)?);
+        while let Some(element) = seq.next_element()? {
+            if elements.len() == self.max_len {
+                return Err(A::Error::custom(format!(
+                    "more than {} elements",
+                    self.max_len
+                )));
+            }
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+}
+
+/// At most `max_rows` rows of at most `max_row_len` elements, and `max_elements` in total.
+struct BoundedRows<T> {
+    max_rows: usize,
+    max_row_len: usize,
+    max_elements: usize,
+    element: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for BoundedRows<T> {
+    type Value = Vec<Vec<T>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of at most {} rows", self.max_rows)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut rows = Vec::with_capacity(check_len(seq.size_hint(), self.max_rows)?);
+        let mut remaining = self.max_elements;
+        while let Some(row) =
+            seq.next_element_seed(BoundedSeq::<T>::new(self.max_row_len.min(remaining)))?
+        {
+            if rows.len() == self.max_rows {
+                return Err(A::Error::custom(format!(
+                    "more than {} rows",
+                    self.max_rows
+                )));
+            }
+            remaining -= row.len();
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+fn deserialize_rows<'de, D, T>(
+    deserializer: D,
+    max_rows: usize,
+    max_elements: usize,
+) -> Result<Vec<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    deserializer.deserialize_seq(BoundedRows {
+        max_rows,
+        max_row_len: MAX_VALIDATORS,
+        max_elements,
+        element: PhantomData,
+    })
+}
+
+/// The rows of a `DagSnapshotBitmask`.
+pub fn deserialize_bitmask<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Vec<bool>>, D::Error> {
+    deserialize_rows(deserializer, MAX_FETCH_ROUNDS, usize::MAX)
+}
+
+/// The nodes of a `FetchResponse` by round.
+pub fn deserialize_response_nodes<'de, D, T>(deserializer: D) -> Result<Vec<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    deserialize_rows(deserializer, MAX_FETCH_ROUNDS, MAX_FETCH_RESPONSE_NODES)
+}
+
+/// The parents of a node.
+pub fn deserialize_parents<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    BoundedSeq::new(MAX_VALIDATORS).deserialize(deserializer)
+}
+
+/// Drops the highest rounds of a response beyond the limits, so the requester can decode it and
+/// fetches them again. The rounds are ascending.
+pub fn truncate_response_nodes<T>(nodes: &mut Vec<Vec<T>>) {
+    let mut remaining = MAX_FETCH_RESPONSE_NODES;
+    let kept = nodes
+        .iter()
+        .take(MAX_FETCH_ROUNDS)
+        .take_while(|round_nodes| {
+            let fits = round_nodes.len() <= remaining.min(MAX_VALIDATORS);
+            remaining = remaining.saturating_sub(round_nodes.len());
+            fits
+        })
+        .count();
+    nodes.truncate(kept);
+}
@@ -0,0 +1,133 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{slot_id::SlotId, types::CertifiedNode};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+/// Nodes kept by default, at most one per slot of a few rounds.
+pub const DEFAULT_WRITE_RETRY_CAPACITY: usize = 64;
+
+/// Attempts a write gets by default, the first one in `Dag::insert` included.
+pub const DEFAULT_MAX_WRITE_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled after each failed retry.
+pub const DEFAULT_WRITE_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A validated node whose storage write failed, its slot is still reserved.
+#[derive(Clone, Debug)]
+pub struct QueuedWrite {
+    node: Arc<CertifiedNode>,
+    slot: SlotId,
+    attempts: u32,
+    retry_at: Duration,
+    /// Order of the first attempt
+    seq: u64,
+}
+
+impl QueuedWrite {
+    pub fn node(&self) -> &Arc<CertifiedNode> {
+        &self.node
+    }
+
+    pub fn slot(&self) -> SlotId {
+        self.slot
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+/// The nodes `Dag::insert` failed to persist, retried with exponential backoff instead of being
+/// rejected and redelivered by the sender. Bounded, the oldest write is evicted when it's full. A
+/// capacity of 0 disables the retries.
+pub struct WriteRetryQueue {
+    capacity: usize,
+    max_attempts: u32,
+    backoff: Duration,
+    /// Oldest first attempt first
+    writes: VecDeque<QueuedWrite>,
+    next_seq: u64,
+}
+
+impl WriteRetryQueue {
+    pub fn new(capacity: usize, max_attempts: u32, backoff: Duration) -> Self {
+        Self {
+            capacity,
+            max_attempts: max_attempts.max(1),
+            backoff,
+            writes: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Queues the node after its first failed attempt. Returns the write evicted to make room,
+    /// or the node itself if the retries are disabled or it already used its attempts.
+    pub fn push(
+        &mut self,
+        node: Arc<CertifiedNode>,
+        slot: SlotId,
+        now: Duration,
+    ) -> Option<QueuedWrite> {
+        let write = QueuedWrite {
+            node,
+            slot,
+            attempts: 1,
+            retry_at: now + self.backoff,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        if self.capacity == 0 || self.max_attempts == 1 {
+            return Some(write);
+        }
+        self.writes.push_back(write);
+        if self.writes.len() > self.capacity {
+            self.writes.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Takes the writes due at `now` out of the queue, or all of them with `all`.
+    pub fn take_due(&mut self, now: Duration, all: bool) -> Vec<QueuedWrite> {
+        let (due, waiting) = std::mem::take(&mut self.writes)
+            .into_iter()
+            .partition(|write| all || write.retry_at <= now);
+        self.writes = waiting;
+        due.into()
+    }
+
+    /// Queues a write that failed again, returns it if it's out of attempts.
+    pub fn retry_later(&mut self, mut write: QueuedWrite, now: Duration) -> Option<QueuedWrite> {
+        write.attempts += 1;
+        if write.attempts >= self.max_attempts {
+            return Some(write);
+        }
+        write.retry_at = now + self.backoff * 2u32.saturating_pow(write.attempts - 1);
+        let position = self.writes.partition_point(|queued| queued.seq < write.seq);
+        self.writes.insert(position, write);
+        None
+    }
+}
+
+impl Default for WriteRetryQueue {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_WRITE_RETRY_CAPACITY,
+            DEFAULT_MAX_WRITE_ATTEMPTS,
+            DEFAULT_WRITE_RETRY_BACKOFF,
+        )
+    }
+}
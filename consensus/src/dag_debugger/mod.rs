@@ -0,0 +1,121 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Offline inspection of the DAG records of a consensus DB, and replay of the logs recorded by
+//! `DagStoreConfig::replay_log`, for debugging.
+
+use crate::{
+    consensusdb::ConsensusDB,
+    dag::{replay, DagInspector},
+};
+use anyhow::{bail, Context, Result};
+use aptos_consensus_types::common::Round;
+use aptos_crypto::HashValue;
+use aptos_types::epoch_state::EpochState;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{fmt::Display, fs, path::PathBuf, sync::Arc};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Inspect the DAG of a consensus DB, the DB is opened read-only.
+#[derive(Parser)]
+pub struct Cmd {
+    /// The directory holding the consensus DB, required by all the commands but `replay`
+    #[clap(long, value_parser)]
+    db_dir: Option<PathBuf>,
+
+    #[clap(long, value_enum, ignore_case = true, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the epochs, round spans and number of nodes in the DB.
+    Summary,
+    /// Print the nodes of a round with their status.
+    Round {
+        round: Round,
+        /// Defaults to the latest epoch in the DB
+        #[clap(long)]
+        epoch: Option<u64>,
+    },
+    /// Print the metadata and parents of a node.
+    Node { digest: HashValue },
+    /// Validate the digests and links of the records, fails if any problem is found.
+    Check,
+    /// Replay a log against a fresh DAG of this build, fails if it diverges from the log.
+    Replay {
+        #[clap(value_parser)]
+        log: PathBuf,
+        /// The BCS encoded `EpochState` of the epoch of the log
+        #[clap(long, value_parser)]
+        epoch_state: PathBuf,
+    },
+}
+
+impl Cmd {
+    /// Runs the command and prints its report.
+    pub fn run(self) -> Result<()> {
+        if let Command::Replay { log, epoch_state } = &self.command {
+            let bytes = fs::read(epoch_state)
+                .with_context(|| format!("failed to read {}", epoch_state.display()))?;
+            let epoch_state: EpochState = bcs::from_bytes(&bytes)
+                .with_context(|| format!("{} isn't an epoch state", epoch_state.display()))?;
+            let report = replay::run(log, Arc::new(epoch_state))?;
+            print_report(&report, self.format)?;
+            if !report.is_faithful() {
+                bail!("found {} divergences", report.num_divergences);
+            }
+            return Ok(());
+        }
+        let Some(db_dir) = &self.db_dir else {
+            bail!("--db-dir is required");
+        };
+        let db = ConsensusDB::open_readonly(db_dir)?;
+        let inspector = DagInspector::load(&db)?;
+        match self.command {
+            Command::Summary => print_report(&inspector.summary(), self.format),
+            Command::Round { round, epoch } => {
+                let epoch = epoch
+                    .or_else(|| inspector.latest_epoch())
+                    .unwrap_or_default();
+                print_report(&inspector.round(epoch, round), self.format)
+            },
+            Command::Node { digest } => match inspector.node(&digest) {
+                Some(report) => print_report(&report, self.format),
+                None => bail!("node {:x} not in the DB", digest),
+            },
+            Command::Check => {
+                let report = inspector.check();
+                print_report(&report, self.format)?;
+                if !report.is_consistent() {
+                    bail!("found {} problems", report.problems.len());
+                }
+                Ok(())
+            },
+            Command::Replay { .. } => unreachable!("replayed before opening the DB"),
+        }
+    }
+}
+
+fn print_report<T: Display + Serialize>(report: &T, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => print!("{}", report),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_tool() {
+    use clap::CommandFactory;
+    Cmd::command().debug_assert()
+}
@@ -18,6 +18,8 @@ extern crate core;
 mod block_storage;
 mod consensusdb;
 mod dag;
+#[cfg(feature = "dag-debugger")]
+pub mod dag_debugger;
 mod epoch_manager;
 mod error;
 mod experimental;
@@ -99,6 +99,15 @@ impl SimulatedTimeService {
         }
     }
 
+    /// Moves the current time forward by the given duration, without running the pending tasks
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock();
+        inner.now += duration;
+        if inner.now > inner.max {
+            inner.now = inner.max;
+        }
+    }
+
     /// Update time_limit of this SimulatedTimeService instance and run pending tasks that has
     /// deadline lower then new time_limit
     #[allow(dead_code)]
@@ -12,7 +12,8 @@
 //!  3. aggregate signature on different messages from many signers
 //!
 //! The signature verification APIs in `Signature::verify`, `Signature::verify_arbitrary_msg`,
-//! `Signature::verify_aggregate` and `Signature::verify_aggregate_arbitrary_msg` do NOT
+//! `Signature::verify_aggregate`, `Signature::verify_aggregate_arbitrary_msg`,
+//! `Signature::verify_multiple` and `Signature::verify_multiple_arbitrary_msg` do NOT
 //! assume the signature to be a valid group element and will implicitly "subgroup-check" it. This
 //! makes the caller's job easier and, more importantly, makes the library safer to use.
 
@@ -28,6 +29,7 @@ use crate::{
 use anyhow::{anyhow, Result};
 use aptos_crypto_derive::{DeserializeKey, SerializeKey};
 use blst::BLST_ERROR;
+use rand::Rng;
 use serde::Serialize;
 use std::{convert::TryFrom, fmt};
 
@@ -118,6 +120,90 @@ impl Signature {
         self.verify_aggregate_arbitrary_msg(&msgs_refs, pks)
     }
 
+    /// Verifies that each `sigs[i]` is a signature on `msgs[i]` under `pks[i]`, where each
+    /// `pks[i]` can be an aggregated key (i.e., `sigs[i]` a multisignature). All signatures are
+    /// checked in a single multi-pairing, after multiplying each of them by a random 64-bit scalar.
+    /// Unlike verifying the aggregate of `sigs` via `Signature::verify_aggregate_arbitrary_msg`,
+    /// this does not pass when signature material is moved between the signatures (e.g., when two
+    /// of them are swapped), except with probability 2^{-64}.
+    ///
+    /// WARNING: This function assumes that the public keys have been subgroup-checked by the caller
+    /// implicitly when verifying their proof-of-possession (PoP) in `ProofOfPossession::verify`.
+    pub fn verify_multiple_arbitrary_msg(
+        msgs: &[&[u8]],
+        pks: &[&PublicKey],
+        sigs: &[&Signature],
+    ) -> Result<()> {
+        if msgs.len() != pks.len() || msgs.len() != sigs.len() {
+            return Err(anyhow!(
+                "Expected as many messages as public keys and signatures, got {}, {} and {}",
+                msgs.len(),
+                pks.len(),
+                sigs.len()
+            ));
+        }
+
+        if msgs.is_empty() {
+            return Err(anyhow!("Expected at least one signature"));
+        }
+
+        let mut rng = rand::thread_rng();
+        let rands = sigs
+            .iter()
+            .map(|_| {
+                // A zero scalar would leave its signature out of the check.
+                let scalar: u64 = rng.gen_range(1, u64::MAX);
+                let mut b = [0u8; 32];
+                b[..8].copy_from_slice(&scalar.to_le_bytes());
+                blst::blst_scalar { b }
+            })
+            .collect::<Vec<blst::blst_scalar>>();
+        let pks = pks
+            .iter()
+            .map(|&pk| &pk.pubkey)
+            .collect::<Vec<&blst::min_pk::PublicKey>>();
+        let sigs = sigs
+            .iter()
+            .map(|&sig| &sig.sig)
+            .collect::<Vec<&blst::min_pk::Signature>>();
+
+        let result = blst::min_pk::Signature::verify_multiple_aggregate_signatures(
+            msgs,
+            DST_BLS_SIG_IN_G2_WITH_POP,
+            &pks,
+            false,
+            &sigs,
+            true,
+            &rands,
+            64,
+        );
+
+        if result == BLST_ERROR::BLST_SUCCESS {
+            Ok(())
+        } else {
+            Err(anyhow!("{:?}", result))
+        }
+    }
+
+    /// Serializes the messages of type `T` to bytes and calls `Signature::verify_multiple_arbitrary_msg`.
+    pub fn verify_multiple<T: CryptoHash + Serialize>(
+        msgs: &[&T],
+        pks: &[&PublicKey],
+        sigs: &[&Signature],
+    ) -> Result<()> {
+        let mut messages: Vec<Vec<u8>> = vec![];
+        for message in msgs {
+            messages.push(signing_message(*message)?);
+        }
+
+        let msgs_refs = messages
+            .iter()
+            .map(|m| m.as_slice())
+            .collect::<Vec<&[u8]>>();
+
+        Self::verify_multiple_arbitrary_msg(&msgs_refs, pks, sigs)
+    }
+
     /// Return a dummy signature for testing.
     #[cfg(any(test, feature = "fuzzing"))]
     pub fn dummy_signature() -> Self {
@@ -221,6 +221,55 @@ fn bls12381_aggsig_zero_messages_or_pks_does_not_verify() {
     assert!(aggsig.verify_aggregate(&msgs_refs, &pubkeys).is_err());
 }
 
+/// Tests that signatures (here, multisignatures) batch-verified with random scalars verify correctly,
+/// and that they do NOT verify once two of them are swapped, even though their aggregate still does.
+#[test]
+fn bls12381_multiple_multisigs_should_verify() {
+    let mut rng = OsRng;
+    let num_sigs = 10;
+    let num_signers = 4;
+
+    let messages = random_messages_for_signing(&mut rng, num_sigs);
+    let messages_wrong = random_messages_for_signing(&mut rng, num_sigs);
+
+    let mut multisigs = vec![];
+    let mut aggpks = vec![];
+    for msg in &messages {
+        let key_pairs = bls12381_keygen(num_signers, &mut rng);
+        let sigshares = key_pairs
+            .iter()
+            .map(|kp| kp.private_key.sign(msg).unwrap())
+            .collect::<Vec<bls12381::Signature>>();
+        let pks = key_pairs
+            .iter()
+            .map(|kp| &kp.public_key)
+            .collect::<Vec<&PublicKey>>();
+
+        multisigs.push(bls12381::Signature::aggregate(sigshares).unwrap());
+        aggpks.push(PublicKey::aggregate(pks).unwrap());
+    }
+
+    let msgs_refs = messages.iter().collect::<Vec<&TestAptosCrypto>>();
+    let pks_refs = aggpks.iter().collect::<Vec<&PublicKey>>();
+    let sigs_refs = multisigs.iter().collect::<Vec<&bls12381::Signature>>();
+    assert!(bls12381::Signature::verify_multiple(&msgs_refs, &pks_refs, &sigs_refs).is_ok());
+
+    // the signatures should NOT verify on incorrect messages
+    let msgs_wrong_refs = messages_wrong.iter().collect::<Vec<&TestAptosCrypto>>();
+    assert!(bls12381::Signature::verify_multiple(&msgs_wrong_refs, &pks_refs, &sigs_refs).is_err());
+
+    // the signatures should NOT verify once two of them are swapped, while their aggregate does
+    let mut sigs_swapped = sigs_refs.clone();
+    sigs_swapped.swap(0, 1);
+    assert!(bls12381::Signature::verify_multiple(&msgs_refs, &pks_refs, &sigs_swapped).is_err());
+    let aggsig = bls12381::Signature::aggregate(multisigs.clone()).unwrap();
+    assert!(aggsig.verify_aggregate(&msgs_refs, &pks_refs).is_ok());
+
+    // the signatures should NOT verify on zero or mismatched inputs
+    assert!(bls12381::Signature::verify_multiple::<TestAptosCrypto>(&[], &[], &[]).is_err());
+    assert!(bls12381::Signature::verify_multiple(&msgs_refs, &pks_refs[1..], &sigs_refs).is_err());
+}
+
 /// Tests that a multisignature incorrectly aggregated from signature shares on different messages does
 /// NOT verify.
 #[test]
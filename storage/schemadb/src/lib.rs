@@ -46,6 +46,7 @@ pub type ColumnFamilyName = &'static str;
 enum WriteOp {
     Value { key: Vec<u8>, value: Vec<u8> },
     Deletion { key: Vec<u8> },
+    DeletionRange { begin: Vec<u8>, end: Vec<u8> },
 }
 
 /// `SchemaBatch` holds a collection of updates that can be applied to a DB atomically. The updates
@@ -96,6 +97,19 @@ impl SchemaBatch {
 
         Ok(())
     }
+
+    /// Adds an operation deleting the keys in the range [`begin`, `end`) to the batch.
+    pub fn delete_range<S: Schema>(&self, begin: &S::Key, end: &S::Key) -> Result<()> {
+        let begin = <S::Key as KeyCodec<S>>::encode_key(begin)?;
+        let end = <S::Key as KeyCodec<S>>::encode_key(end)?;
+        self.rows
+            .lock()
+            .entry(S::COLUMN_FAMILY_NAME)
+            .or_insert_with(Vec::new)
+            .push(WriteOp::DeletionRange { begin, end });
+
+        Ok(())
+    }
 }
 
 /// This DB is a schematized RocksDB wrapper where all data passed in and out are typed according to
@@ -236,6 +250,9 @@ impl DB {
                 match write_op {
                     WriteOp::Value { key, value } => db_batch.put_cf(cf_handle, key, value),
                     WriteOp::Deletion { key } => db_batch.delete_cf(cf_handle, key),
+                    WriteOp::DeletionRange { begin, end } => {
+                        db_batch.delete_range_cf(cf_handle, begin, end)
+                    },
                 }
             }
         }
@@ -252,7 +269,7 @@ impl DB {
                             .with_label_values(&[cf_name])
                             .observe((key.len() + value.len()) as f64);
                     },
-                    WriteOp::Deletion { key: _ } => {
+                    WriteOp::Deletion { key: _ } | WriteOp::DeletionRange { .. } => {
                         APTOS_SCHEMADB_DELETES.with_label_values(&[cf_name]).inc();
                     },
                 }
@@ -227,6 +227,37 @@ fn test_single_schema_batch() {
     );
 }
 
+#[test]
+fn test_delete_range() {
+    let db = TestDB::new();
+
+    let db_batch = SchemaBatch::new();
+    for i in 0..5 {
+        db_batch
+            .put::<TestSchema1>(&TestField(i), &TestField(i))
+            .unwrap();
+    }
+    db_batch
+        .put::<TestSchema2>(&TestField(1), &TestField(1))
+        .unwrap();
+    db.write_schemas(db_batch).unwrap();
+
+    let db_batch = SchemaBatch::new();
+    db_batch
+        .delete_range::<TestSchema1>(&TestField(1), &TestField(3))
+        .unwrap();
+    db.write_schemas(db_batch).unwrap();
+
+    assert_eq!(
+        collect_values::<TestSchema1>(&db),
+        gen_expected_values(&[(0, 0), (3, 3), (4, 4)]),
+    );
+    assert_eq!(
+        collect_values::<TestSchema2>(&db),
+        gen_expected_values(&[(1, 1)]),
+    );
+}
+
 #[test]
 fn test_two_schema_batches() {
     let db = TestDB::new();